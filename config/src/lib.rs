@@ -1,15 +1,23 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crypto::{generate_production_keypair, PublicKey, SecretKey};
+use crypto::{
+    generate_production_keypair, BlsProofOfPossession, BlsPublicKey, KeyShare, PublicKey,
+    SecretKey, ThresholdPublicKey,
+};
 use log::info;
 use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, OpenOptions};
 use std::io::BufWriter;
 use std::io::Write as _;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
 use thiserror::Error;
 
+#[cfg(test)]
+#[path = "tests/config_tests.rs"]
+pub mod config_tests;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Node {0} is not in the committee")]
@@ -23,6 +31,127 @@ pub enum ConfigError {
 
     #[error("Failed to write config file '{file}': {message}")]
     ExportError { file: String, message: String },
+
+    #[error("Invalid parameter '{name}': {message}")]
+    InvalidParameter { name: String, message: String },
+
+    #[error("The BLS key of {0} lacks a valid proof of possession")]
+    InvalidBlsKey(PublicKey),
+}
+
+/// Parses an address written either as `ip:port` or as `host:port`. We do not resolve hostnames
+/// here (the peer may not resolve yet): the peer is identified by a placeholder address (see
+/// `hostname_address`) and the network resolves its hostname when connecting to it.
+fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    if let Ok(address) = address.parse() {
+        return Ok(address);
+    }
+    hostname_address(address).ok_or_else(|| format!("Invalid address {}", address))
+}
+
+/// Returns the placeholder address identifying the peer with the specified hostname (written as
+/// `host:port`), or `None` if it is not a valid hostname. The placeholder keeps the port and lies in
+/// the IPv6 discard prefix (`100::/64`), its interface identifier being a hash of the hostname: it is
+/// the same for every node and never routed.
+pub fn hostname_address(address: &str) -> Option<SocketAddr> {
+    let (host, port) = address.rsplit_once(':')?;
+    let port = port.parse::<u16>().ok()?;
+    let valid = !host.is_empty()
+        && host.len() <= 253
+        && host
+            .split('.')
+            .all(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    if !valid {
+        return None;
+    }
+
+    // Hash the hostname with FNV-1a, which is stable across platforms and versions.
+    let hash = host
+        .to_ascii_lowercase()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, x| {
+            (hash ^ x as u64).wrapping_mul(0x100000001b3)
+        });
+    let ip = Ipv6Addr::from(((0x100u128) << 112) | hash as u128);
+    Some(SocketAddr::new(IpAddr::V6(ip), port))
+}
+
+/// Returns the hostnames of the addresses of the specified committee file written as `host:port`
+/// (rather than `ip:port`), by the placeholder address identifying their peer.
+pub fn hostnames(path: &str) -> Result<BTreeMap<SocketAddr, String>, ConfigError> {
+    fn collect(
+        value: &serde_json::Value,
+        hostnames: &mut BTreeMap<SocketAddr, String>,
+    ) -> Result<(), String> {
+        match value {
+            serde_json::Value::String(x) if x.parse::<SocketAddr>().is_err() => {
+                if let Some(address) = hostname_address(x) {
+                    if let Some(other) = hostnames.insert(address, x.clone()) {
+                        if !other.eq_ignore_ascii_case(x) {
+                            return Err(format!("{} and {} collide", other, x));
+                        }
+                    }
+                }
+            }
+            serde_json::Value::Array(x) => {
+                for value in x {
+                    collect(value, hostnames)?;
+                }
+            }
+            serde_json::Value::Object(x) => {
+                for value in x.values() {
+                    collect(value, hostnames)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    let error = |message: String| ConfigError::ImportError {
+        file: path.to_string(),
+        message,
+    };
+    let data = fs::read(path).map_err(|e| error(e.to_string()))?;
+    let value = serde_json::from_slice(&data).map_err(|e| error(e.to_string()))?;
+    let mut hostnames = BTreeMap::new();
+    collect(&value, &mut hostnames).map_err(error)?;
+    Ok(hostnames)
+}
+
+/// Deserializes an address, accepting hostnames in human-readable formats (such as the committee
+/// file). Binary formats (such as the admin commands) only carry socket addresses.
+fn deserialize_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SocketAddr, D::Error> {
+    if !deserializer.is_human_readable() {
+        return SocketAddr::deserialize(deserializer);
+    }
+    parse_address(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SocketAddr>, D::Error> {
+    if !deserializer.is_human_readable() {
+        return Option::<SocketAddr>::deserialize(deserializer);
+    }
+    match Option::<String>::deserialize(deserializer)? {
+        Some(address) => parse_address(&address)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// Deserializes the listen addresses of the node, by the committee address (possibly a hostname)
+/// they stand for.
+fn deserialize_listen_addresses<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<SocketAddr, SocketAddr>, D::Error> {
+    BTreeMap::<String, SocketAddr>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(advertised, listen)| Ok((parse_address(&advertised)?, listen)))
+        .collect::<Result<_, String>>()
+        .map_err(serde::de::Error::custom)
 }
 
 pub trait Import: DeserializeOwned {
@@ -31,10 +160,17 @@ pub trait Import: DeserializeOwned {
             let data = fs::read(path)?;
             Ok(serde_json::from_slice(data.as_slice())?)
         };
-        reader().map_err(|e| ConfigError::ImportError {
+        let imported = reader().map_err(|e| ConfigError::ImportError {
             file: path.to_string(),
             message: e.to_string(),
-        })
+        })?;
+        imported.validate()?;
+        Ok(imported)
+    }
+
+    /// Checks the imported configuration.
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
     }
 }
 
@@ -57,15 +193,46 @@ pub trait Export: Serialize {
 
 pub type Stake = u32;
 pub type WorkerId = u32;
+pub type Epoch = u64;
 
 #[derive(Deserialize, Clone)]
 pub struct Parameters {
     /// The preferred header size. The primary creates a new header when it has enough parents and
-    /// enough batches' digests to reach `header_size`. Denominated in bytes.
+    /// enough batches' digests to reach `header_size`. Headers never carry more digests than this
+    /// (the remaining ones go into the next header). Denominated in bytes.
     pub header_size: usize,
     /// The maximum delay that the primary waits between generating two headers, even if the header
     /// did not reach `max_header_size`. Denominated in ms.
     pub max_header_delay: u64,
+    /// The minimum payload for which the primary creates a new header as soon as it has enough
+    /// parents, without waiting for `header_size` or `max_header_delay`. Disabled if not set.
+    /// Denominated in bytes.
+    #[serde(default)]
+    pub min_header_size: Option<usize>,
+    /// The maximum number of batches' digests a header may reference. The remaining digests go
+    /// into the next header, and the headers of other primaries referencing more are rejected.
+    /// Unbounded if not set.
+    #[serde(default)]
+    pub max_header_payload: Option<usize>,
+    /// How the primary picks the batches' digests of its workers when they do not all fit in a header.
+    #[serde(default)]
+    pub payload_balancing: PayloadBalancing,
+    /// The maximum number of rounds the primary may get ahead of the last round committed by the
+    /// consensus. The primary stops creating headers beyond it, bounding the uncommitted dag, but
+    /// never before the dag holds the rounds needed to commit the next leader. It must be at least
+    /// `MIN_COMMIT_LAG`. Unbounded if not set.
+    #[serde(default)]
+    pub max_commit_lag: Option<u64>,
+    /// The delay the primary waits before proposing a header without payload, instead of
+    /// `max_header_delay`, unless it is the leader of the round. This spares idle networks from
+    /// churning empty headers (which the dag still needs to advance). Disabled if not set.
+    /// Denominated in ms.
+    #[serde(default)]
+    pub lazy_header_delay: Option<u64>,
+    /// The accepted skew between the timestamp of the headers we receive and our local time.
+    /// Headers outside these bounds are dropped.
+    #[serde(default)]
+    pub timestamp_bounds: TimestampBounds,
     /// The depth of the garbage collection (Denominated in number of rounds).
     pub gc_depth: u64,
     /// The delay after which the synchronizer retries to send sync requests. Denominated in ms.
@@ -79,6 +246,177 @@ pub struct Parameters {
     /// The delay after which the workers seal a batch of transactions, even if `max_batch_size`
     /// is not reached. Denominated in ms.
     pub max_batch_delay: u64,
+    /// The number of signature verifications the primary is willing to perform per second for each
+    /// class of messages. Messages exceeding the budget of their class are dropped unverified.
+    #[serde(default)]
+    pub verification_budget: VerificationBudget,
+    /// The maximum number of messages (or batches of certificates) whose signatures the primary
+    /// verifies in parallel, off its main task. Defaults to the number of available cores.
+    #[serde(default)]
+    pub verification_parallelism: Option<usize>,
+    /// The number of messages of each class the primary accepts per second from each authority.
+    /// Authorities exceeding one of their quotas are temporarily banned.
+    #[serde(default)]
+    pub peer_quotas: PeerQuotas,
+    /// The maximum number of missing dependencies (parents or batches) each component waits for at
+    /// any time. Further sync requests are refused until some of them are resolved or cleaned up.
+    #[serde(default)]
+    pub sync_limits: SyncLimits,
+    /// Bounds how many times the sync requests of a header are re-issued before giving up.
+    #[serde(default)]
+    pub sync_retries: SyncRetries,
+    /// Limits the certificates we request from each peer when catching up with the dag.
+    #[serde(default)]
+    pub fetch_limits: FetchLimits,
+    /// Limits the certificates we serve to each peer catching up with the dag.
+    #[serde(default)]
+    pub helper_limits: HelperLimits,
+    /// The policy electing the leaders of the consensus.
+    #[serde(default)]
+    pub leader_schedule: LeaderSchedule,
+    /// How the consensus output is buffered when the application layer does not keep up.
+    #[serde(default)]
+    pub output_buffer: OutputBufferParameters,
+    /// Whether our votes carry a BLS signature, allowing the header's author to aggregate the votes
+    /// of its certificate into a single signature. Certificates are only aggregated when every vote
+    /// of the quorum carries a BLS signature and the committee lists the BLS keys of the voters
+    /// (along with their proofs of possession).
+    #[serde(default)]
+    pub aggregate_votes: bool,
+    /// How thoroughly the primary checks that the payload of a header is available before voting.
+    #[serde(default)]
+    pub payload_availability: PayloadAvailability,
+    /// How the primary bundles its votes on the headers of the same author (of successive rounds)
+    /// into a single network message.
+    #[serde(default)]
+    pub vote_batching: VoteBatching,
+    /// How durably the primary logs its votes before sending them.
+    #[serde(default)]
+    pub vote_log: VoteLogParameters,
+    /// How the primary diffuses the certificates to the other primaries.
+    #[serde(default)]
+    pub certificate_diffusion: CertificateDiffusion,
+    /// The number of recently received headers, votes, and certificates the primary remembers to
+    /// drop their replays (if not set, the primary picks a default).
+    #[serde(default)]
+    pub replay_cache_size: Option<usize>,
+    /// Whether the primary only archives the dag: it never proposes nor votes, but it syncs and
+    /// keeps every header, certificate, and batch digest it sees (garbage collection is disabled).
+    /// Archival primaries should have no stake.
+    #[serde(default)]
+    pub archival: bool,
+    /// The delay after which the primary raises an alert if one of its headers did not gather a
+    /// quorum of votes (if set). Denominated in ms.
+    #[serde(default)]
+    pub quorum_alert_delay: Option<u64>,
+    /// How the workers drop the transactions that clients submit more than once.
+    #[serde(default)]
+    pub transaction_dedup: TransactionDedup,
+    /// The number of priority lanes of the workers (if set). The first byte of each transaction is
+    /// its priority (capped to the highest lane); under load, the workers batch the transactions of
+    /// the highest lanes first. Transactions are batched in the order we receive them if not set.
+    #[serde(default)]
+    pub priority_lanes: Option<usize>,
+    /// The maximum size of a client transaction. The workers drop larger transactions. Unbounded if
+    /// not set. Denominated in bytes.
+    #[serde(default)]
+    pub max_transaction_size: Option<usize>,
+    /// The maximum size of a serialized batch. The workers seal their batches before they exceed
+    /// it (and reject the transactions that cannot fit in a batch). Defaults to (and capped at) the
+    /// maximum size of the frames we send (see `frame_limits`), less the few bytes the connections
+    /// add to each frame, and to the `max_batch_size` of the committee. Denominated in bytes.
+    #[serde(default)]
+    pub max_serialized_batch_size: Option<usize>,
+    /// The directory of the Unix domain sockets on which the workers also receive the transactions
+    /// of the clients running on the same host, sparing them the overhead of TCP (if set). Worker
+    /// `i` of the authority of public key `k` listens on `worker-<h>-<i>.sock`, where `h` is the hex
+    /// encoding of the first 8 bytes of `k` (so that the nodes of a host may share a directory).
+    /// Only supported on Unix.
+    #[serde(default)]
+    pub transactions_socket_dir: Option<PathBuf>,
+    /// How the workers wait for the other workers to acknowledge their batches.
+    #[serde(default)]
+    pub quorum_waiter: QuorumWaiterParameters,
+    /// The number of rounds the workers keep the batches committed by the consensus (if set): a
+    /// batch committed at round `r` is deleted once the consensus commits round `r + retention`.
+    /// It must be at least `gc_depth`, since the lagging workers may request batches of that window.
+    /// The workers keep all batches if not set, or if the node is archival.
+    #[serde(default)]
+    pub batch_retention: Option<u64>,
+    /// How the workers serve the batches requested by the other workers.
+    #[serde(default)]
+    pub batch_helper: BatchHelperLimits,
+    /// The maximum number of transactions per second a worker accepts from each client address
+    /// (unbounded if not set). The clients submitting over the Unix domain socket share a single
+    /// limit. The workers reply to the clients exceeding their limit that they are overloaded.
+    #[serde(default)]
+    pub client_rate_limit: Option<u64>,
+    /// How the workers hold the transactions waiting to be batched.
+    #[serde(default)]
+    pub mempool: MempoolParameters,
+    /// The number of threads on which the workers verify client transactions, in parallel chunks,
+    /// before batching them. The workers verify each transaction as they receive it if not set.
+    #[serde(default)]
+    pub validation_threads: Option<usize>,
+    /// Lets the workers adapt the size and delay of their batches to the load (if set), between
+    /// the specified minimums and the `batch_size` and `max_batch_delay` parameters.
+    #[serde(default)]
+    pub adaptive_batching: Option<AdaptiveBatching>,
+    /// How the workers advertise their recent batches to the other workers, so that the workers
+    /// that missed a broadcast pull the batch without waiting for a header to reference it.
+    #[serde(default)]
+    pub anti_entropy: AntiEntropyParameters,
+    /// Whether the workers reject the client transactions routed to another worker of their
+    /// authority (by the routing key the transaction validator tells).
+    #[serde(default)]
+    pub enforce_routing: bool,
+    /// The number of sealed batches waiting for the acknowledgements of the other workers above
+    /// which the workers ask clients to retry later, rather than buffering their transactions
+    /// (unbounded if not set).
+    #[serde(default)]
+    pub max_batch_backlog: Option<usize>,
+    /// How the primaries and workers re-attempt to reach an unreachable peer before giving up on
+    /// the messages they send it.
+    #[serde(default)]
+    pub delivery_retries: DeliveryRetries,
+    /// Bounds the messages the primaries and workers hold for each peer, by class of messages.
+    #[serde(default)]
+    pub send_queues: SendQueues,
+    /// The maximum sizes of the frames the primaries and workers exchange over the network.
+    #[serde(default)]
+    pub frame_limits: FrameLimits,
+    /// How the primaries and workers offer to compress their connections to their peers (and
+    /// whether they accept the offers of the others). Each pair of nodes agrees on it when
+    /// connecting. The workers do not compress the batches of a committee compressing them already
+    /// (see `batch_compression`).
+    #[serde(default)]
+    pub transport_compression: TransportCompression,
+    /// Whether we drop the messages whose claimed origin is not authenticated: the primaries then
+    /// require the sync requests and advertisements of their peers to be signed (and sign theirs),
+    /// and the workers require the batch requests and summaries of their peers to come over a
+    /// connection authenticating the authority they claim (so it requires a `transport_security`
+    /// other than plaintext, or QUIC). The whole committee should enable it at once.
+    #[serde(default)]
+    pub authenticate_messages: bool,
+    /// The internal addresses of the relays (or sentries) hiding our IP address: we open our
+    /// connections to the other authorities through them, and the committee lists their addresses
+    /// as ours. We connect directly if empty.
+    #[serde(default)]
+    pub relays: Vec<SocketAddr>,
+    /// The addresses we listen on, by the address of the committee they stand for (for instance
+    /// behind a NAT or in a container, where we cannot bind the address the committee advertises).
+    /// We listen on the unspecified IP (`0.0.0.0`) with the port of the committee address otherwise.
+    #[serde(default, deserialize_with = "deserialize_listen_addresses")]
+    pub listen_addresses: BTreeMap<SocketAddr, SocketAddr>,
+    /// How the primaries and workers secure their connections to those of the other authorities
+    /// (the connections of the clients stay in plaintext). The whole committee should enable it at
+    /// once.
+    #[serde(default)]
+    pub transport_security: TransportSecurity,
+    /// The protocol over which the primaries and workers reach those of the other authorities (the
+    /// clients keep reaching us over TCP). The whole committee should switch at once.
+    #[serde(default)]
+    pub transport_protocol: TransportProtocol,
 }
 
 impl Default for Parameters {
@@ -86,45 +424,1161 @@ impl Default for Parameters {
         Self {
             header_size: 1_000,
             max_header_delay: 100,
+            min_header_size: None,
+            max_header_payload: None,
+            payload_balancing: PayloadBalancing::default(),
+            max_commit_lag: None,
+            lazy_header_delay: None,
+            timestamp_bounds: TimestampBounds::default(),
             gc_depth: 50,
             sync_retry_delay: 5_000,
             sync_retry_nodes: 3,
             batch_size: 500_000,
             max_batch_delay: 100,
+            verification_budget: VerificationBudget::default(),
+            verification_parallelism: None,
+            peer_quotas: PeerQuotas::default(),
+            sync_limits: SyncLimits::default(),
+            sync_retries: SyncRetries::default(),
+            fetch_limits: FetchLimits::default(),
+            helper_limits: HelperLimits::default(),
+            leader_schedule: LeaderSchedule::default(),
+            output_buffer: OutputBufferParameters::default(),
+            aggregate_votes: false,
+            payload_availability: PayloadAvailability::default(),
+            vote_batching: VoteBatching::default(),
+            vote_log: VoteLogParameters::default(),
+            certificate_diffusion: CertificateDiffusion::default(),
+            replay_cache_size: None,
+            archival: false,
+            quorum_alert_delay: None,
+            transaction_dedup: TransactionDedup::default(),
+            priority_lanes: None,
+            max_transaction_size: None,
+            max_serialized_batch_size: None,
+            transactions_socket_dir: None,
+            quorum_waiter: QuorumWaiterParameters::default(),
+            batch_retention: None,
+            batch_helper: BatchHelperLimits::default(),
+            client_rate_limit: None,
+            mempool: MempoolParameters::default(),
+            validation_threads: None,
+            adaptive_batching: None,
+            anti_entropy: AntiEntropyParameters::default(),
+            enforce_routing: false,
+            max_batch_backlog: None,
+            delivery_retries: DeliveryRetries::default(),
+            send_queues: SendQueues::default(),
+            frame_limits: FrameLimits::default(),
+            transport_compression: TransportCompression::None,
+            authenticate_messages: false,
+            relays: Vec::new(),
+            listen_addresses: BTreeMap::new(),
+            transport_security: TransportSecurity::default(),
+            transport_protocol: TransportProtocol::default(),
         }
     }
 }
 
-impl Import for Parameters {}
+impl Import for Parameters {
+    /// Rejects the parameters that would prevent the node from making progress.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |name: &str, message: String| ConfigError::InvalidParameter {
+            name: name.to_string(),
+            message,
+        };
+        if let Some(max_commit_lag) = self.max_commit_lag {
+            if max_commit_lag < MIN_COMMIT_LAG {
+                return Err(invalid(
+                    "max_commit_lag",
+                    format!("must be at least {} rounds", MIN_COMMIT_LAG),
+                ));
+            }
+        }
+        if let Some(retention) = self.batch_retention {
+            if retention < self.gc_depth {
+                return Err(invalid(
+                    "batch_retention",
+                    format!(
+                        "must be at least gc_depth ({} rounds): lagging workers may still request \
+                         the batches of that window",
+                        self.gc_depth
+                    ),
+                ));
+            }
+        }
+        if self.batch_size == 0 {
+            return Err(invalid(
+                "batch_size",
+                "must be positive (the workers would seal empty batches forever)".to_string(),
+            ));
+        }
+        if self
+            .adaptive_batching
+            .is_some_and(|adaptive| adaptive.min_batch_size == 0)
+        {
+            return Err(invalid(
+                "adaptive_batching.min_batch_size",
+                "must be positive (the workers would seal empty batches forever)".to_string(),
+            ));
+        }
+        if self.peer_quotas.frames == 0 {
+            return Err(invalid(
+                "peer_quotas.frames",
+                "must be positive (a peer would never get to send a frame)".to_string(),
+            ));
+        }
+        if self.authenticate_messages
+            && self.transport_security == TransportSecurity::Plaintext
+            && self.transport_protocol == TransportProtocol::Tcp
+        {
+            return Err(invalid(
+                "authenticate_messages",
+                "the workers authenticate their peers by their connections, which must be secured"
+                    .to_string(),
+            ));
+        }
+        if self.transport_protocol == TransportProtocol::Quic {
+            if !self.relays.is_empty() {
+                return Err(invalid(
+                    "transport_protocol",
+                    "QUIC does not run through relays".to_string(),
+                ));
+            }
+            if self.transport_security != TransportSecurity::Plaintext {
+                return Err(invalid(
+                    "transport_protocol",
+                    "QUIC already runs TLS (leave the transport security to plaintext)".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The smallest `max_commit_lag` accepted: twice the rounds needed to commit a leader (its round, the
+/// round voting for it, the round triggering its commit, and the two rounds separating leaders), so
+/// that the dag may move past a skipped leader.
+pub const MIN_COMMIT_LAG: u64 = 10;
 
 impl Parameters {
     pub fn log(&self) {
         info!("Header size set to {} B", self.header_size);
         info!("Max header delay set to {} ms", self.max_header_delay);
+        if let Some(min_header_size) = self.min_header_size {
+            info!("Min header size set to {} B", min_header_size);
+        }
+        if let Some(max_header_payload) = self.max_header_payload {
+            info!("Max header payload set to {} digests", max_header_payload);
+        }
+        info!(
+            "Payload balancing set to {:?} (max batch age {} ms)",
+            self.payload_balancing.policy, self.payload_balancing.max_batch_age
+        );
+        if let Some(max_commit_lag) = self.max_commit_lag {
+            info!("Max commit lag set to {} rounds", max_commit_lag);
+        }
+        if let Some(lazy_header_delay) = self.lazy_header_delay {
+            info!("Lazy header delay set to {} ms", lazy_header_delay);
+        }
+        if let Some(skew) = self.timestamp_bounds.max_future_skew {
+            info!("Max header timestamp future skew set to {} ms", skew);
+        }
+        if let Some(skew) = self.timestamp_bounds.max_past_skew {
+            info!("Max header timestamp past skew set to {} ms", skew);
+        }
         info!("Garbage collection depth set to {} rounds", self.gc_depth);
         info!("Sync retry delay set to {} ms", self.sync_retry_delay);
         info!("Sync retry nodes set to {} nodes", self.sync_retry_nodes);
         info!("Batch size set to {} B", self.batch_size);
         info!("Max batch delay set to {} ms", self.max_batch_delay);
+        info!(
+            "Verification budget set to {} headers/s, {} votes/s, {} certificates/s, {} sync responses/s",
+            self.verification_budget.headers,
+            self.verification_budget.votes,
+            self.verification_budget.certificates,
+            self.verification_budget.sync_responses
+        );
+        if let Some(parallelism) = self.verification_parallelism {
+            info!("Verification parallelism set to {} jobs", parallelism);
+        }
+        info!(
+            "Peer quotas set to {} headers/s, {} votes/s, {} sync requests/s (ban {} ms), {} frames/s",
+            self.peer_quotas.headers,
+            self.peer_quotas.votes,
+            self.peer_quotas.sync_requests,
+            self.peer_quotas.ban_duration,
+            self.peer_quotas.frames
+        );
+        info!(
+            "Sync limits set to {} (header waiter), {} (certificate waiter), {} (worker synchronizer) digests",
+            self.sync_limits.header_waiter,
+            self.sync_limits.certificate_waiter,
+            self.sync_limits.worker_synchronizer
+        );
+        info!(
+            "Sync retries set to {} retries (at most {} ms apart)",
+            self.sync_retries.max_retries, self.sync_retries.max_delay
+        );
+        info!(
+            "Fetch limits set to {} certificates/s and {} pending certificates per peer",
+            self.fetch_limits.rate_limit, self.fetch_limits.max_in_flight
+        );
+        info!(
+            "Helper limits set to {} certificates/s per peer and {} certificates per page",
+            self.helper_limits.rate_limit, self.helper_limits.page_size
+        );
+        info!("Leader schedule set to {:?}", self.leader_schedule);
+        info!(
+            "Output buffer set to {} certificates ({:?} when full)",
+            self.output_buffer.capacity, self.output_buffer.policy
+        );
+        info!("Vote aggregation set to {}", self.aggregate_votes);
+        info!(
+            "Payload availability check set to {:?} (workers timeout {} ms)",
+            self.payload_availability.check, self.payload_availability.timeout
+        );
+        info!(
+            "Vote batching set to {} votes (max delay {} ms)",
+            self.vote_batching.max_votes, self.vote_batching.max_delay
+        );
+        info!(
+            "Vote log fsync set to {:?} (every {} ms when periodic)",
+            self.vote_log.fsync, self.vote_log.fsync_interval
+        );
+        info!(
+            "Certificate diffusion set to {:?} (fanout {}, pull every {} ms over {} rounds)",
+            self.certificate_diffusion.mode,
+            self.certificate_diffusion.fanout,
+            self.certificate_diffusion.pull_interval,
+            self.certificate_diffusion.pull_depth
+        );
+        if self.certificate_diffusion.mode == DiffusionMode::Mesh {
+            info!(
+                "Mesh relays certificates over {} hops",
+                self.certificate_diffusion.rounds
+            );
+        }
+        if let Some(size) = self.replay_cache_size {
+            info!("Replay cache size set to {} messages", size);
+        }
+        info!("Archival mode set to {}", self.archival);
+        if let Some(delay) = self.quorum_alert_delay {
+            info!("Quorum alert delay set to {} ms", delay);
+        }
+        info!(
+            "Transaction dedup set to {} transactions over {} ms",
+            self.transaction_dedup.capacity, self.transaction_dedup.window
+        );
+        if let Some(lanes) = self.priority_lanes {
+            info!("Priority lanes set to {} lanes", lanes);
+        }
+        if let Some(size) = self.max_transaction_size {
+            info!("Max transaction size set to {} B", size);
+        }
+        if let Some(size) = self.max_serialized_batch_size {
+            info!("Max serialized batch size set to {} B", size);
+        }
+        if let Some(dir) = &self.transactions_socket_dir {
+            info!("Transactions socket directory set to {}", dir.display());
+        }
+        info!(
+            "Quorum waiter threshold set to {:?} (re-broadcast after {} ms)",
+            self.quorum_waiter.threshold, self.quorum_waiter.rebroadcast_timeout
+        );
+        if let Some(retention) = self.batch_retention {
+            info!("Batch retention set to {} rounds", retention);
+        }
+        info!(
+            "Batch helper limits set to {} batches per page, {} B per reply, {} B chunks ({} B cache)",
+            self.batch_helper.page_size,
+            self.batch_helper.max_reply_size,
+            self.batch_helper.chunk_size,
+            self.batch_helper.cache_size
+        );
+        if let Some(rate) = self.client_rate_limit {
+            info!("Client rate limit set to {} tx/s", rate);
+        }
+        info!(
+            "Mempool capacity set to {} B ({:?} evicted first, {} pending transactions per sender)",
+            self.mempool.capacity, self.mempool.eviction, self.mempool.max_pending_per_sender
+        );
+        if let Some(ttl) = self.mempool.ttl {
+            info!("Mempool TTL set to {} ms", ttl);
+        }
+        if let Some(threads) = self.validation_threads {
+            info!("Transaction validation threads set to {}", threads);
+        }
+        info!(
+            "Anti-entropy set to every {} ms over the last {} ms of batches",
+            self.anti_entropy.interval, self.anti_entropy.window
+        );
+        if self.enforce_routing {
+            info!("Transaction routing enforced");
+        }
+        if let Some(backlog) = self.max_batch_backlog {
+            info!("Max batch backlog set to {} batches", backlog);
+        }
+        if let Some(adaptive) = &self.adaptive_batching {
+            info!(
+                "Adaptive batching set to at least {} B and {} ms (target latency {} ms)",
+                adaptive.min_batch_size, adaptive.min_batch_delay, adaptive.target_latency
+            );
+        }
+        info!(
+            "Delivery retries set to {}-{} ms (jitter {}) and {} attempts",
+            self.delivery_retries.initial_delay,
+            self.delivery_retries.max_delay,
+            self.delivery_retries.jitter,
+            self.delivery_retries
+                .max_attempts
+                .map_or_else(|| "unbounded".to_string(), |x| x.to_string())
+        );
+        for (class, queue) in [
+            ("consensus", &self.send_queues.consensus),
+            ("batch", &self.send_queues.batches),
+            ("sync", &self.send_queues.sync),
+            ("request", &self.send_queues.requests),
+            ("internal", &self.send_queues.internal),
+        ] {
+            info!(
+                "Send queues of {} messages set to {} messages ({:?})",
+                class, queue.capacity, queue.policy
+            );
+        }
+        info!(
+            "Frame limits set to {} B inbound and {} B outbound",
+            self.frame_limits.max_inbound, self.frame_limits.max_outbound
+        );
+        info!(
+            "Transport compression set to {:?}",
+            self.transport_compression
+        );
+        if self.authenticate_messages {
+            info!("Message authentication enabled");
+        }
+        if !self.relays.is_empty() {
+            info!(
+                "Connecting to the committee through relays {:?}",
+                self.relays
+            );
+        }
+        for (advertised, listen) in &self.listen_addresses {
+            info!("Listening on {} for {}", listen, advertised);
+        }
+        info!("Transport security set to {:?}", self.transport_security);
+        info!("Transport protocol set to {:?}", self.transport_protocol);
+    }
+}
+
+impl Parameters {
+    /// Returns the address to listen on for the specified committee address.
+    pub fn listen_address(&self, address: SocketAddr) -> SocketAddr {
+        match self.listen_addresses.get(&address) {
+            Some(listen) => *listen,
+            None => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), address.port()),
+        }
+    }
+
+    /// Returns the path of the Unix domain socket of the specified worker (if any).
+    pub fn transactions_socket(&self, name: &PublicKey, id: WorkerId) -> Option<PathBuf> {
+        let authority: String = name.0[..8].iter().map(|x| format!("{:02x}", x)).collect();
+        self.transactions_socket_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("worker-{}-{}.sock", authority, id)))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct VerificationBudget {
+    /// The maximum number of headers verified per second.
+    pub headers: u64,
+    /// The maximum number of votes verified per second. The votes exceeding it are deferred rather
+    /// than dropped.
+    pub votes: u64,
+    /// The maximum number of certificates verified per second.
+    pub certificates: u64,
+    /// The maximum number of certificates verified per second among those we receive in reply to
+    /// our own sync requests. They have their own budget so that a flood of relayed certificates
+    /// cannot prevent us from catching up (and vice versa).
+    pub sync_responses: u64,
+}
+
+impl Default for VerificationBudget {
+    fn default() -> Self {
+        Self {
+            headers: 1_000,
+            votes: 10_000,
+            certificates: 10_000,
+            sync_responses: 10_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct TimestampBounds {
+    /// How far ahead of our local time a header may be timestamped (unbounded if not set).
+    /// Denominated in ms.
+    pub max_future_skew: Option<u64>,
+    /// How far behind our local time a header may be timestamped (unbounded if not set).
+    /// Denominated in ms.
+    pub max_past_skew: Option<u64>,
+}
+
+impl Default for TimestampBounds {
+    fn default() -> Self {
+        Self {
+            max_future_skew: Some(5_000),
+            max_past_skew: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct PeerQuotas {
+    /// The maximum number of headers accepted from each authority per second.
+    pub headers: u64,
+    /// The maximum number of votes accepted from each authority per second.
+    pub votes: u64,
+    /// The maximum number of sync requests (and advertisements) accepted from each authority per
+    /// second.
+    pub sync_requests: u64,
+    /// The time during which we drop all messages of an authority that exceeded one of its
+    /// quotas. Denominated in ms.
+    pub ban_duration: u64,
+    /// The maximum number of frames accepted per second from each authenticated node (or over each
+    /// connection authenticating no node) between primaries (and between workers). Checked by the
+    /// network receivers before reading the frames, so that floods are shed before we pay for
+    /// decoding them. Must be positive.
+    #[serde(default = "PeerQuotas::default_frames")]
+    pub frames: u64,
+}
+
+impl PeerQuotas {
+    fn default_frames() -> u64 {
+        10_000
+    }
+}
+
+impl Default for PeerQuotas {
+    fn default() -> Self {
+        Self {
+            headers: 100,
+            votes: 1_000,
+            sync_requests: 100,
+            ban_duration: 10_000,
+            frames: Self::default_frames(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct SyncLimits {
+    /// The maximum number of missing parents and batches of the headers held by the header waiter.
+    pub header_waiter: usize,
+    /// The maximum number of missing parents of the certificates held by the certificate waiter.
+    pub certificate_waiter: usize,
+    /// The maximum number of missing batches the worker synchronizer waits for.
+    pub worker_synchronizer: usize,
+}
+
+impl Default for SyncLimits {
+    fn default() -> Self {
+        Self {
+            header_waiter: 100_000,
+            certificate_waiter: 100_000,
+            worker_synchronizer: 100_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct SyncRetries {
+    /// The number of times the sync requests of a header are re-issued (each time to another peer,
+    /// doubling the delay starting from `sync_retry_delay`). After that, the header is dropped.
+    pub max_retries: u32,
+    /// The maximum delay between two retries. Denominated in ms.
+    pub max_delay: u64,
+}
+
+impl Default for SyncRetries {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            max_delay: 60_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct FetchLimits {
+    /// The maximum number of certificates requested from each peer per second.
+    pub rate_limit: u64,
+    /// The maximum number of certificates requested from each peer and not yet received. Requests
+    /// that time out (see `sync_retry_delay`) are sent to another peer.
+    pub max_in_flight: usize,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        Self {
+            rate_limit: 1_000,
+            max_in_flight: 500,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadPolicy {
+    /// Include the digests in the order our workers reported them.
+    #[default]
+    Fifo,
+    /// Include the digests older than `max_batch_age` first, then favor the workers with the
+    /// largest backlog.
+    Balanced,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct PayloadBalancing {
+    /// The policy picking the digests of the next header.
+    pub policy: PayloadPolicy,
+    /// The age after which a digest is included before any other (regardless of the backlog of
+    /// its worker). Only used by the `balanced` policy. Denominated in ms.
+    pub max_batch_age: u64,
+}
+
+impl Default for PayloadBalancing {
+    fn default() -> Self {
+        Self {
+            policy: PayloadPolicy::default(),
+            max_batch_age: 1_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct HelperLimits {
+    /// The maximum number of certificates served to each peer per second. The certificates
+    /// requested beyond this quota are not served (the peer asks again later).
+    pub rate_limit: u64,
+    /// The maximum number of certificates served in reply to a single request. Larger requests
+    /// are served page by page, each page being requested with the token of the previous one.
+    pub page_size: usize,
+}
+
+impl Default for HelperLimits {
+    fn default() -> Self {
+        Self {
+            rate_limit: 1_000,
+            page_size: 100,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct BatchHelperLimits {
+    /// The number of batches a worker reads and sends in a row in reply to a single request.
+    /// Larger requests are served page by page, interleaved with the pages of the other requests.
+    pub page_size: usize,
+    /// The maximum number of bytes a worker sends in reply to a single request. The batches beyond
+    /// are not served (the requestor asks again later).
+    pub max_reply_size: usize,
+    /// The size of the chunks in which a worker streams the batches it serves. Larger batches are
+    /// split into several messages. Denominated in bytes.
+    pub chunk_size: usize,
+    /// The number of bytes of recently served batches a worker keeps in memory, so that it reads
+    /// the batches many workers request at once (such as its own, under pull dissemination) from
+    /// the store only once.
+    pub cache_size: usize,
+}
+
+impl Default for BatchHelperLimits {
+    fn default() -> Self {
+        Self {
+            page_size: 10,
+            max_reply_size: 50_000_000,
+            chunk_size: 1_000_000,
+            cache_size: 20_000_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderSchedule {
+    /// Every authority is elected in turn, regardless of its stake.
+    #[default]
+    RoundRobin,
+    /// Authorities are elected in proportion to their stake (weighted round-robin).
+    StakeWeighted,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputPolicy {
+    /// Stop committing until the application layer drains the buffer.
+    #[default]
+    Pause,
+    /// Keep committing and persist the overflow to the store until the application catches up.
+    Spill,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadCheck {
+    /// Vote without checking the payload: it is fetched later, if needed.
+    Trust,
+    /// Vote once our workers reported the batches of the payload (syncing the missing ones first).
+    #[default]
+    Reported,
+    /// Additionally ask our workers to confirm they store the batches before voting.
+    Confirmed,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct PayloadAvailability {
+    /// The strictness of the check.
+    pub check: PayloadCheck,
+    /// How long we wait for our workers to confirm they store the payload (only used by the
+    /// `confirmed` check). We do not vote if they do not reply in time. Denominated in ms.
+    pub timeout: u64,
+}
+
+impl Default for PayloadAvailability {
+    fn default() -> Self {
+        Self {
+            check: PayloadCheck::default(),
+            timeout: 1_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct VoteBatching {
+    /// The maximum number of votes (on the headers of the same author) bundled in a single message.
+    /// Votes are sent one by one if set to 1 (or less).
+    pub max_votes: usize,
+    /// The maximum delay a vote waits for others to be bundled with. Denominated in ms.
+    pub max_delay: u64,
+}
+
+impl Default for VoteBatching {
+    fn default() -> Self {
+        Self {
+            max_votes: 1,
+            max_delay: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportSecurity {
+    /// The connections are neither authenticated nor encrypted.
+    #[default]
+    Plaintext,
+    /// TLS 1.3, each node authenticating with the key the committee lists for its authority.
+    Tls,
+    /// The Noise IK handshake, over the X25519 form of the keys the committee lists.
+    Noise,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportProtocol {
+    /// A TCP connection per logical channel.
+    #[default]
+    Tcp,
+    /// A single QUIC connection with each node (authenticated by TLS 1.3 over the keys the
+    /// committee lists), with a stream per logical channel.
+    Quic,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffusionMode {
+    /// Every primary sends each certificate to all the others.
+    #[default]
+    Broadcast,
+    /// Every primary pushes each new certificate to a few random peers, and periodically
+    /// advertises the digests of its recent certificates so that peers pull those they miss.
+    Gossip,
+    /// Like `gossip`, but over a deterministic partial mesh (for large committees): certificates
+    /// are relayed along the mesh up to a number of hops from their origin, and digests are
+    /// advertised to mesh neighbours so that the farther primaries pull what they miss.
+    Mesh,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct CertificateDiffusion {
+    /// Whether certificates are broadcast or gossiped.
+    pub mode: DiffusionMode,
+    /// The number of random peers to which we push each new certificate (`gossip`), or the
+    /// minimum number of neighbours of each primary in the mesh (`mesh`).
+    pub fanout: usize,
+    /// The delay between two advertisements of our recent certificates. Only used by `gossip`
+    /// and `mesh`. Denominated in ms.
+    pub pull_interval: u64,
+    /// The number of (most recent) rounds whose certificates we advertise. Only used by `gossip`
+    /// and `mesh`.
+    pub pull_depth: u64,
+    /// The number of hops (from their origin) over which certificates are relayed along the mesh.
+    /// Only used by `mesh`.
+    #[serde(default = "CertificateDiffusion::default_rounds")]
+    pub rounds: usize,
+}
+
+impl CertificateDiffusion {
+    fn default_rounds() -> usize {
+        3
+    }
+}
+
+impl Default for CertificateDiffusion {
+    fn default() -> Self {
+        Self {
+            mode: DiffusionMode::default(),
+            fanout: 3,
+            pull_interval: 500,
+            pull_depth: 5,
+            rounds: Self::default_rounds(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct OutputBufferParameters {
+    /// The number of committed certificates buffered in memory for the application layer.
+    pub capacity: usize,
+    /// What to do when the buffer is full.
+    pub policy: OutputPolicy,
+}
+
+impl Default for OutputBufferParameters {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            policy: OutputPolicy::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    /// Sync the log to disk before sending each vote.
+    #[default]
+    EveryVote,
+    /// Sync the log to disk every `fsync_interval`. Votes sent since the last sync may be lost in
+    /// a crash of the machine.
+    Periodic,
+    /// Leave the syncing to the OS. Votes may be lost in a crash of the machine (but not of the
+    /// process).
+    Os,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct VoteLogParameters {
+    /// When to sync the log of our votes to disk.
+    pub fsync: FsyncPolicy,
+    /// The delay between two syncs of the log under the periodic policy. Denominated in ms.
+    pub fsync_interval: u64,
+}
+
+impl Default for VoteLogParameters {
+    fn default() -> Self {
+        Self {
+            fsync: FsyncPolicy::default(),
+            fsync_interval: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct TransactionDedup {
+    /// The maximum number of transactions the worker remembers. When full, the worker forgets the
+    /// oldest ones. Deduplication is disabled if set to 0.
+    pub capacity: usize,
+    /// The time during which the worker drops the resubmissions of a transaction, starting from its
+    /// first submission. Denominated in ms.
+    pub window: u64,
+}
+
+impl Default for TransactionDedup {
+    fn default() -> Self {
+        Self {
+            capacity: 100_000,
+            window: 10_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// The worker evicts the transactions that waited the longest.
+    #[default]
+    Oldest,
+    /// The worker evicts the oldest transaction of the lowest priority lane. It drops the new
+    /// transactions of a lower priority than all the waiting ones.
+    LowestPriority,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct MempoolParameters {
+    /// The maximum size of the transactions waiting to be batched. When full, the worker evicts
+    /// transactions rather than holding back its clients. Denominated in bytes.
+    pub capacity: usize,
+    /// Which transactions the worker evicts when its mempool is full.
+    pub eviction: EvictionPolicy,
+    /// The time after which the worker drops the transactions still waiting to be batched
+    /// (unbounded if not set). Clients may also attach an earlier expiry to their transactions
+    /// (see the transaction validator). Denominated in ms.
+    pub ttl: Option<u64>,
+    /// The maximum number of transactions of each sender waiting to be batched. Only enforced
+    /// when the transaction validator tells the sender and nonce of the transactions.
+    pub max_pending_per_sender: usize,
+}
+
+impl Default for MempoolParameters {
+    fn default() -> Self {
+        Self {
+            capacity: 20_000_000,
+            eviction: EvictionPolicy::default(),
+            ttl: None,
+            max_pending_per_sender: 64,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct AntiEntropyParameters {
+    /// The delay between two advertisements of our recent batches. Denominated in ms.
+    pub interval: u64,
+    /// How long a worker keeps advertising a batch it sealed. Denominated in ms.
+    pub window: u64,
+}
+
+impl Default for AntiEntropyParameters {
+    fn default() -> Self {
+        Self {
+            interval: 5_000,
+            window: 60_000,
+        }
     }
 }
 
+#[derive(Deserialize, Clone, Copy)]
+pub struct AdaptiveBatching {
+    /// The smallest preferred batch size the workers adapt to. Denominated in bytes.
+    pub min_batch_size: usize,
+    /// The smallest delay after which the workers seal a batch. Denominated in ms.
+    pub min_batch_delay: u64,
+    /// The time the other workers should take to acknowledge our batches. The workers grow their
+    /// batches when it takes longer, and shrink them again otherwise. Denominated in ms.
+    pub target_latency: u64,
+}
+
+impl Default for AdaptiveBatching {
+    fn default() -> Self {
+        Self {
+            min_batch_size: 10_000,
+            min_batch_delay: 10,
+            target_latency: 500,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AckThreshold {
+    /// Wait for the acknowledgements of f+1 authorities (counting our own stake): at least one
+    /// honest authority holds the batch.
+    Validity,
+    /// Wait for the acknowledgements of 2f+1 authorities (counting our own stake).
+    #[default]
+    Quorum,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct DeliveryRetries {
+    /// The delay before the first re-attempt to reach a peer, doubled after each failed attempt.
+    /// Denominated in ms.
+    pub initial_delay: u64,
+    /// The maximum delay between two attempts. Denominated in ms.
+    pub max_delay: u64,
+    /// The fraction (between 0 and 1) of each delay randomly shaved off, so that the nodes that
+    /// lost the same peer do not all re-attempt at the same time.
+    pub jitter: f64,
+    /// The number of consecutive failed attempts after which the messages waiting for the peer
+    /// are abandoned (never if not set).
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for DeliveryRetries {
+    fn default() -> Self {
+        Self {
+            initial_delay: 200,
+            max_delay: 60_000,
+            jitter: 0.5,
+            max_attempts: Some(10),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuePolicy {
+    /// Drop the oldest message of a full queue to make room for the new one.
+    DropOldest,
+    /// Drop the new messages while the queue is full.
+    DropNewest,
+    /// Block the sender until the queue has room.
+    Block,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct SendQueue {
+    /// The maximum number of messages waiting to be sent to each peer.
+    pub capacity: usize,
+    /// What happens to a new message when the queue of its peer is full.
+    pub policy: QueuePolicy,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct SendQueues {
+    /// The headers, votes, and certificates the primary sends to the other primaries.
+    pub consensus: SendQueue,
+    /// The batches the workers broadcast to the other workers. The workers re-broadcast the
+    /// dropped batches that still lack acknowledgements.
+    pub batches: SendQueue,
+    /// The replies to the sync requests of the other primaries and workers.
+    pub sync: SendQueue,
+    /// The sync requests (and advertisements) the primaries and workers send to their peers.
+    #[serde(default = "SendQueues::default_requests")]
+    pub requests: SendQueue,
+    /// The messages between a primary and its own workers.
+    #[serde(default = "SendQueues::default_internal")]
+    pub internal: SendQueue,
+}
+
+impl SendQueues {
+    fn default_requests() -> SendQueue {
+        SendQueue {
+            capacity: 1_000,
+            policy: QueuePolicy::DropOldest,
+        }
+    }
+
+    fn default_internal() -> SendQueue {
+        SendQueue {
+            capacity: 10_000,
+            policy: QueuePolicy::DropOldest,
+        }
+    }
+}
+
+impl Default for SendQueues {
+    fn default() -> Self {
+        Self {
+            consensus: SendQueue {
+                capacity: 10_000,
+                policy: QueuePolicy::DropOldest,
+            },
+            batches: SendQueue {
+                capacity: 1_000,
+                policy: QueuePolicy::DropOldest,
+            },
+            sync: SendQueue {
+                capacity: 1_000,
+                policy: QueuePolicy::DropNewest,
+            },
+            requests: Self::default_requests(),
+            internal: Self::default_internal(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct FrameLimits {
+    /// The maximum size of the frames we accept. Peers sending larger frames get a protocol error
+    /// and lose their connection. Denominated in bytes.
+    pub max_inbound: usize,
+    /// The maximum size of the frames we send. Larger messages are dropped (and reported as
+    /// undeliverable). Denominated in bytes.
+    pub max_outbound: usize,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self {
+            max_inbound: 8 * 1024 * 1024,
+            max_outbound: 8 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct QuorumWaiterParameters {
+    /// The stake that must acknowledge our batches before we report them to our primary.
+    pub threshold: AckThreshold,
+    /// The delay after which we re-broadcast a batch to the workers that did not acknowledge it
+    /// yet. Denominated in ms.
+    pub rebroadcast_timeout: u64,
+}
+
+impl Default for QuorumWaiterParameters {
+    fn default() -> Self {
+        Self {
+            threshold: AckThreshold::default(),
+            rebroadcast_timeout: 5_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchCompression {
+    /// Batches are sent and stored as they are.
+    #[default]
+    None,
+    /// Batches are compressed with snappy (fast, moderate ratio).
+    Snappy,
+    /// Batches are compressed with zstd (slower, better ratio).
+    Zstd,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportCompression {
+    /// Connections carry the messages as they are.
+    #[default]
+    None,
+    /// Connections compress their messages with snappy (fast, moderate ratio).
+    Snappy,
+    /// Connections compress their messages with zstd (slower, better ratio).
+    Zstd,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchDissemination {
+    /// Workers send their whole batches to the workers of all other authorities.
+    #[default]
+    Replicated,
+    /// Workers split their batches into Reed-Solomon coded shards and send a single shard to the
+    /// worker of each other authority. Any f+1 shards reconstruct the batch.
+    ErasureCoded,
+    /// Workers only announce the digests of their batches. The other workers pull a batch from its
+    /// origin when their primary needs it (to vote on a header referencing it), which saves the
+    /// bandwidth of the batches nobody asks for in large committees.
+    Pull,
+}
+
+/// The addresses of the committee may be written as `ip:port` or as `host:port`. Hostnames are
+/// resolved when loading the committee, and again by the senders when they (re)connect. Each
+/// address may also be written as `{ "advertise": "host:port", "listen": "ip:port" }` when the
+/// node cannot listen on the address the others reach it at; the node listens on `0.0.0.0` with
+/// the advertised port otherwise.
 #[derive(Clone, Deserialize)]
 pub struct PrimaryAddresses {
     /// Address to receive messages from other primaries (WAN).
+    #[serde(deserialize_with = "deserialize_address")]
     pub primary_to_primary: SocketAddr,
     /// Address to receive messages from our workers (LAN).
+    #[serde(deserialize_with = "deserialize_address")]
     pub worker_to_primary: SocketAddr,
 }
 
-#[derive(Clone, Deserialize, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, Hash, PartialEq)]
 pub struct WorkerAddresses {
     /// Address to receive client transactions (WAN).
+    #[serde(deserialize_with = "deserialize_address")]
     pub transactions: SocketAddr,
     /// Address to receive messages from other workers (WAN).
+    #[serde(deserialize_with = "deserialize_address")]
     pub worker_to_worker: SocketAddr,
     /// Address to receive messages from our primary (LAN).
+    #[serde(deserialize_with = "deserialize_address")]
     pub primary_to_worker: SocketAddr,
+    /// Address to receive client transactions, replying with a receipt once they are sealed into
+    /// a batch (WAN). Disabled if not set.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_address",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub receipts: Option<SocketAddr>,
+    /// Additional addresses to receive client transactions (for instance, a public endpoint and a
+    /// private cluster endpoint), each with its own rate limits.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ingress: Vec<IngressAddress>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, Hash, PartialEq)]
+pub struct IngressAddress {
+    /// Address to receive client transactions.
+    #[serde(deserialize_with = "deserialize_address")]
+    pub address: SocketAddr,
+    /// The maximum number of transactions per second accepted from each client address on this
+    /// address. Falls back to the `client_rate_limit` parameter if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u64>,
+    /// Whether clients receive a receipt for their transactions (as on the `receipts` address).
+    #[serde(default)]
+    pub receipts: bool,
+    /// Serves this address over TLS (if set), for instance to expose it to the public.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsParameters>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, Hash, PartialEq)]
+pub struct TlsParameters {
+    /// The PEM file holding the certificate chain of the endpoint.
+    pub certificate: PathBuf,
+    /// The PEM file holding the private key of the endpoint.
+    pub private_key: PathBuf,
+    /// The PEM file holding the certificates of the authorities clients must present a certificate
+    /// of (if set). Any client may connect otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<PathBuf>,
+}
+
+/// The addresses of a relay (or sentry) node hiding the IP address of a validator.
+#[derive(Clone, Deserialize)]
+pub struct RelayAddresses {
+    /// The public key of the validator, the only node the relay serves.
+    pub name: PublicKey,
+    /// Address to receive the requests of the validator to connect to the committee (LAN).
+    pub internal: SocketAddr,
+    /// The addresses forwarded to the validator.
+    pub forwards: Vec<RelayForward>,
+}
+
+impl Import for RelayAddresses {}
+
+#[derive(Clone, Deserialize)]
+pub struct RelayForward {
+    /// Address to receive messages from the other authorities (WAN), listed as the address of the
+    /// validator in the committee.
+    #[serde(deserialize_with = "deserialize_address")]
+    pub external: SocketAddr,
+    /// The address on which the relay listens for `external` (for instance behind a NAT). The
+    /// unspecified IP (`0.0.0.0`) with the port of `external` if absent.
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+    /// The address of the validator to which the messages are forwarded (LAN).
+    #[serde(deserialize_with = "deserialize_address")]
+    pub validator: SocketAddr,
+}
+
+impl RelayForward {
+    /// Returns the address on which the relay listens for its external address.
+    pub fn listen_address(&self) -> SocketAddr {
+        self.listen
+            .unwrap_or_else(|| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), self.external.port()))
+    }
 }
 
 #[derive(Clone, Deserialize)]
@@ -135,16 +1589,73 @@ pub struct Authority {
     pub primary: PrimaryAddresses,
     /// Map of workers' id and their network addresses.
     pub workers: HashMap<WorkerId, WorkerAddresses>,
+    /// The BLS public key of the authority, needed to verify its aggregated votes.
+    #[serde(default)]
+    pub bls_key: Option<BlsPublicKey>,
+    /// The proof of possession of `bls_key`. The committee rejects the BLS keys without one: an
+    /// authority could otherwise pick its key as a function of the others and forge their
+    /// aggregated votes.
+    #[serde(default)]
+    pub bls_proof: Option<BlsProofOfPossession>,
 }
 
 #[derive(Clone, Deserialize)]
 pub struct Committee {
+    /// The epoch of the committee. Headers and certificates are only valid within the epoch of
+    /// the committee that created them.
+    #[serde(default)]
+    pub epoch: Epoch,
     pub authorities: BTreeMap<PublicKey, Authority>,
+    /// How the workers compress their batches before broadcasting them. All workers must be able to
+    /// decompress the batches of the others, so the whole committee agrees on it.
+    #[serde(default)]
+    pub batch_compression: BatchCompression,
+    /// How the workers disseminate their batches. The workers must understand the shards of the
+    /// others, so the whole committee agrees on it.
+    #[serde(default)]
+    pub batch_dissemination: BatchDissemination,
+    /// The maximum size of a serialized batch the workers accept from each other (unbounded if not
+    /// set). A worker dropping a batch that the others certify could never sync it, so the whole
+    /// committee agrees on it. Denominated in bytes.
+    #[serde(default)]
+    pub max_batch_size: Option<usize>,
+    /// The public key to which clients may encrypt their transactions, so that their content stays
+    /// hidden until a threshold of the committee decrypts them. Plaintext only if absent.
+    #[serde(default)]
+    pub encryption_key: Option<ThresholdPublicKey>,
 }
 
-impl Import for Committee {}
+impl Import for Committee {
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (name, authority) in &self.authorities {
+            if let Some(bls_key) = &authority.bls_key {
+                authority
+                    .bls_proof
+                    .as_ref()
+                    .and_then(|x| bls_key.verify_possession(x).ok())
+                    .ok_or(ConfigError::InvalidBlsKey(*name))?;
+            }
+        }
+        Ok(())
+    }
+}
 
 impl Committee {
+    /// Returns the key of the authority listening on each address of its primary and workers (except
+    /// the addresses serving clients), by which the nodes authenticate each other.
+    pub fn network_keys(&self) -> BTreeMap<SocketAddr, PublicKey> {
+        let mut keys = BTreeMap::new();
+        for (name, authority) in &self.authorities {
+            keys.insert(authority.primary.primary_to_primary, *name);
+            keys.insert(authority.primary.worker_to_primary, *name);
+            for addresses in authority.workers.values() {
+                keys.insert(addresses.worker_to_worker, *name);
+                keys.insert(addresses.primary_to_worker, *name);
+            }
+        }
+        keys
+    }
+
     /// Returns the number of authorities.
     pub fn size(&self) -> usize {
         self.authorities.len()
@@ -155,6 +1666,11 @@ impl Committee {
         self.authorities.get(&name).map_or_else(|| 0, |x| x.stake)
     }
 
+    /// Returns the BLS public key of a specific authority (if it registered one).
+    pub fn bls_key(&self, name: &PublicKey) -> Option<BlsPublicKey> {
+        self.authorities.get(name).and_then(|x| x.bls_key)
+    }
+
     /// Returns the stake of all authorities except `myself`.
     pub fn others_stake(&self, myself: &PublicKey) -> Vec<(PublicKey, Stake)> {
         self.authorities
@@ -172,6 +1688,61 @@ impl Committee {
         2 * total_votes / 3 + 1
     }
 
+    /// Returns one cycle of the leader schedule: the leader of the `k`-th even round is the `k`-th entry
+    /// of the cycle (modulo its length), see `scheduled_leader`. The stake-weighted schedule follows the
+    /// smooth weighted round-robin algorithm, which spreads the slots of every authority evenly over the
+    /// cycle. Its cycle gives each authority a number of slots proportional to its stake, and holds at
+    /// most `MAX_SCHEDULE_LENGTH` slots.
+    pub fn leader_schedule(&self, schedule: LeaderSchedule) -> Vec<PublicKey> {
+        let mut keys: Vec<_> = self.authorities.keys().cloned().collect();
+        keys.sort();
+
+        match schedule {
+            LeaderSchedule::RoundRobin => keys,
+            LeaderSchedule::StakeWeighted => {
+                // Only the proportions of the stakes matter: divide them by their gcd, and scale them
+                // down (rounding down) if they still add up to more than the maximum cycle length.
+                let stakes: Vec<u64> = keys.iter().map(|x| self.stake(x) as u64).collect();
+                let divisor = stakes.iter().fold(0, |a, b| gcd(a, *b)).max(1);
+                let total: u64 = stakes.iter().map(|x| x / divisor).sum();
+                let weights: Vec<i64> = stakes
+                    .iter()
+                    .map(|x| match total > MAX_SCHEDULE_LENGTH {
+                        true => (x / divisor * MAX_SCHEDULE_LENGTH / total) as i64,
+                        false => (x / divisor) as i64,
+                    })
+                    .collect();
+
+                let total: i64 = weights.iter().sum();
+                let mut current = vec![0i64; keys.len()];
+                (0..total)
+                    .map(|_| {
+                        for (i, weight) in weights.iter().enumerate() {
+                            current[i] += weight;
+                        }
+                        // Ties go to the smallest key.
+                        let (selected, _) =
+                            current
+                                .iter()
+                                .enumerate()
+                                .fold(
+                                    (0, i64::MIN),
+                                    |max, (i, x)| {
+                                        if *x > max.1 {
+                                            (i, *x)
+                                        } else {
+                                            max
+                                        }
+                                    },
+                                );
+                        current[selected] -= total;
+                        keys[selected]
+                    })
+                    .collect()
+            }
+        }
+    }
+
     /// Returns the stake required to reach availability (f+1).
     pub fn validity_threshold(&self) -> Stake {
         // If N = 3f + 1 + k (0 <= k < 3)
@@ -188,6 +1759,19 @@ impl Committee {
             .ok_or_else(|| ConfigError::NotInCommittee(*to))
     }
 
+    /// Returns the addresses the target primary listens on (rather than those it advertises),
+    /// according to its parameters.
+    pub fn listen_primary(
+        &self,
+        to: &PublicKey,
+        parameters: &Parameters,
+    ) -> Result<PrimaryAddresses, ConfigError> {
+        self.primary(to).map(|x| PrimaryAddresses {
+            primary_to_primary: parameters.listen_address(x.primary_to_primary),
+            worker_to_primary: parameters.listen_address(x.worker_to_primary),
+        })
+    }
+
     /// Returns the addresses of all primaries except `myself`.
     pub fn others_primaries(&self, myself: &PublicKey) -> Vec<(PublicKey, PrimaryAddresses)> {
         self.authorities
@@ -211,6 +1795,31 @@ impl Committee {
             .ok_or_else(|| ConfigError::NotInCommittee(*to))
     }
 
+    /// Returns the addresses a specific worker (`id`) of a specific authority (`to`) listens on
+    /// (rather than those it advertises), according to its parameters.
+    pub fn listen_worker(
+        &self,
+        to: &PublicKey,
+        id: &WorkerId,
+        parameters: &Parameters,
+    ) -> Result<WorkerAddresses, ConfigError> {
+        let listen = |x| parameters.listen_address(x);
+        self.worker(to, id).map(|x| WorkerAddresses {
+            transactions: listen(x.transactions),
+            worker_to_worker: listen(x.worker_to_worker),
+            primary_to_worker: listen(x.primary_to_worker),
+            receipts: x.receipts.map(listen),
+            ingress: x
+                .ingress
+                .into_iter()
+                .map(|ingress| IngressAddress {
+                    address: listen(ingress.address),
+                    ..ingress
+                })
+                .collect(),
+        })
+    }
+
     /// Returns the addresses of all our workers.
     pub fn our_workers(&self, myself: &PublicKey) -> Result<Vec<WorkerAddresses>, ConfigError> {
         self.authorities
@@ -246,6 +1855,24 @@ impl Committee {
     }
 }
 
+/// The maximum number of slots of one cycle of the stake-weighted leader schedule. Building the
+/// cycle costs O(length x committee size), so it must not grow with the magnitude of the stakes.
+pub const MAX_SCHEDULE_LENGTH: u64 = 10_000;
+
+/// Returns the leader of the specified round in one cycle of a leader schedule. The consensus only
+/// elects leaders for even rounds, so each slot of the cycle goes to the next even round.
+pub fn scheduled_leader(schedule: &[PublicKey], round: u64) -> PublicKey {
+    schedule[(round / 2) as usize % schedule.len()]
+}
+
+/// Returns the greatest common divisor of two numbers (the other number if one of them is 0).
+fn gcd(a: u64, b: u64) -> u64 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct KeyPair {
     /// The node's public key (and identifier).
@@ -255,8 +1882,22 @@ pub struct KeyPair {
 }
 
 impl Import for KeyPair {}
+
+/// The share of the decryption key of the committee (see `Committee::encryption_key`) held by the
+/// node.
+impl Import for KeyShare {}
+
 impl Export for KeyPair {}
 
+/// The public key of the node, for nodes whose secret key is held by an external signer.
+#[derive(Deserialize)]
+pub struct PublicIdentity {
+    /// The node's public key (and identifier).
+    pub name: PublicKey,
+}
+
+impl Import for PublicIdentity {}
+
 impl KeyPair {
     pub fn new() -> Self {
         let (name, secret) = generate_production_keypair();
@@ -0,0 +1,359 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crypto::BlsSecretKey;
+
+#[test]
+fn validate_commit_lag() {
+    let parameters = Parameters {
+        max_commit_lag: Some(MIN_COMMIT_LAG),
+        ..Parameters::default()
+    };
+    assert!(parameters.validate().is_ok());
+
+    let parameters = Parameters {
+        max_commit_lag: Some(1),
+        ..Parameters::default()
+    };
+    match parameters.validate() {
+        Err(ConfigError::InvalidParameter { name, .. }) => assert_eq!(name, "max_commit_lag"),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn parse_hostname_without_resolving() {
+    // Hostnames that do not resolve (yet) get a placeholder address keeping their port.
+    let json = r#"{
+        "primary_to_primary": "primary.unknown.invalid:3000",
+        "worker_to_primary": "127.0.0.1:3001"
+    }"#;
+    let addresses: PrimaryAddresses = serde_json::from_str(json).unwrap();
+    let placeholder = addresses.primary_to_primary;
+    assert_eq!(
+        Some(placeholder),
+        hostname_address("primary.unknown.invalid:3000")
+    );
+    assert_eq!(placeholder.port(), 3000);
+    match placeholder.ip() {
+        IpAddr::V6(ip) => assert_eq!(ip.segments()[..4], [0x100, 0, 0, 0]),
+        IpAddr::V4(_) => panic!("Unexpected placeholder"),
+    }
+    assert_eq!(
+        addresses.worker_to_primary,
+        "127.0.0.1:3001".parse().unwrap()
+    );
+
+    // Distinct hostnames (or ports) get distinct placeholders.
+    assert_ne!(
+        Some(placeholder),
+        hostname_address("other.unknown.invalid:3000")
+    );
+    assert_ne!(
+        Some(placeholder),
+        hostname_address("primary.unknown.invalid:3001")
+    );
+    assert_eq!(hostname_address("not a hostname:3000"), None);
+}
+
+#[test]
+fn collect_hostnames() {
+    let path = std::env::temp_dir().join(format!("narwhal_hostnames_{}.json", std::process::id()));
+    let json = r#"{
+        "primary": { "primary_to_primary": "primary.unknown.invalid:3000" },
+        "workers": [ "worker.unknown.invalid:3100", "127.0.0.1:3101" ]
+    }"#;
+    fs::write(&path, json).unwrap();
+    let hostnames = hostnames(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let expected: BTreeMap<_, _> = [
+        "primary.unknown.invalid:3000",
+        "worker.unknown.invalid:3100",
+    ]
+    .iter()
+    .map(|x| (hostname_address(x).unwrap(), x.to_string()))
+    .collect();
+    assert_eq!(hostnames, expected);
+}
+
+#[test]
+fn parse_listen_addresses() {
+    // We listen on the unspecified IP by default.
+    let parameters: Parameters = serde_json::from_str(
+        r#"{
+        "header_size": 1000,
+        "max_header_delay": 100,
+        "gc_depth": 50,
+        "sync_retry_delay": 5000,
+        "sync_retry_nodes": 3,
+        "batch_size": 500000,
+        "max_batch_delay": 100
+    }"#,
+    )
+    .unwrap();
+    let advertised = "1.2.3.4:3000".parse().unwrap();
+    assert!(parameters.listen_addresses.is_empty());
+    assert_eq!(
+        parameters.listen_address(advertised),
+        "0.0.0.0:3000".parse().unwrap()
+    );
+
+    // The listen addresses stand for committee addresses, which may be hostnames.
+    let parameters: Parameters = serde_json::from_str(
+        r#"{
+        "header_size": 1000,
+        "max_header_delay": 100,
+        "gc_depth": 50,
+        "sync_retry_delay": 5000,
+        "sync_retry_nodes": 3,
+        "batch_size": 500000,
+        "max_batch_delay": 100,
+        "listen_addresses": {
+            "1.2.3.4:3000": "10.0.0.1:3000",
+            "primary.unknown.invalid:3001": "10.0.0.1:4001"
+        }
+    }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        parameters.listen_address(advertised),
+        "10.0.0.1:3000".parse().unwrap()
+    );
+    let hostname = hostname_address("primary.unknown.invalid:3001").unwrap();
+    assert_eq!(
+        parameters.listen_address(hostname),
+        "10.0.0.1:4001".parse().unwrap()
+    );
+
+    // Listen addresses must be socket addresses.
+    let result = serde_json::from_str::<Parameters>(
+        r#"{
+        "header_size": 1000,
+        "max_header_delay": 100,
+        "gc_depth": 50,
+        "sync_retry_delay": 5000,
+        "sync_retry_nodes": 3,
+        "batch_size": 500000,
+        "max_batch_delay": 100,
+        "listen_addresses": { "1.2.3.4:3000": "localhost:3000" }
+    }"#,
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn parse_relay_forwards() {
+    let forward: RelayForward = serde_json::from_str(
+        r#"{
+        "external": "1.2.3.4:3000",
+        "validator": "10.0.0.2:3000"
+    }"#,
+    )
+    .unwrap();
+    assert_eq!(forward.listen_address(), "0.0.0.0:3000".parse().unwrap());
+
+    let forward: RelayForward = serde_json::from_str(
+        r#"{
+        "external": "1.2.3.4:3000",
+        "listen": "10.0.0.1:4000",
+        "validator": "10.0.0.2:3000"
+    }"#,
+    )
+    .unwrap();
+    assert_eq!(forward.listen_address(), "10.0.0.1:4000".parse().unwrap());
+}
+
+#[test]
+fn network_keys_skip_client_addresses() {
+    let name = PublicKey([1; 32]);
+    let address = |port| format!("127.0.0.1:{}", port).parse::<SocketAddr>().unwrap();
+    let worker = WorkerAddresses {
+        transactions: address(3100),
+        worker_to_worker: address(3101),
+        primary_to_worker: address(3102),
+        receipts: Some(address(3103)),
+        ingress: Vec::new(),
+    };
+    let authority = Authority {
+        stake: 1,
+        primary: PrimaryAddresses {
+            primary_to_primary: address(3000),
+            worker_to_primary: address(3001),
+        },
+        workers: std::iter::once((0, worker)).collect(),
+        bls_key: None,
+        bls_proof: None,
+    };
+    let committee = Committee {
+        epoch: 0,
+        authorities: std::iter::once((name, authority)).collect(),
+        batch_compression: BatchCompression::default(),
+        batch_dissemination: BatchDissemination::default(),
+        max_batch_size: None,
+        encryption_key: None,
+    };
+
+    // The nodes authenticate on the addresses between nodes, not on those of the clients.
+    let expected: BTreeMap<_, _> = [3000, 3001, 3101, 3102]
+        .iter()
+        .map(|x| (address(*x), name))
+        .collect();
+    assert_eq!(committee.network_keys(), expected);
+}
+
+#[test]
+fn validate_quic() {
+    let parameters = Parameters {
+        transport_protocol: TransportProtocol::Quic,
+        ..Parameters::default()
+    };
+    assert!(parameters.validate().is_ok());
+
+    // QUIC runs neither through relays nor under another layer of security.
+    let parameters = Parameters {
+        transport_protocol: TransportProtocol::Quic,
+        transport_security: TransportSecurity::Noise,
+        ..Parameters::default()
+    };
+    match parameters.validate() {
+        Err(ConfigError::InvalidParameter { name, .. }) => assert_eq!(name, "transport_protocol"),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn validate_authenticate_messages() {
+    // The workers authenticate their peers by their secured connections.
+    let parameters = Parameters {
+        authenticate_messages: true,
+        ..Parameters::default()
+    };
+    match parameters.validate() {
+        Err(ConfigError::InvalidParameter { name, .. }) => {
+            assert_eq!(name, "authenticate_messages")
+        }
+        _ => panic!("Unexpected result"),
+    }
+
+    let parameters = Parameters {
+        authenticate_messages: true,
+        transport_security: TransportSecurity::Tls,
+        ..Parameters::default()
+    };
+    assert!(parameters.validate().is_ok());
+}
+
+#[test]
+fn validate_frame_quota() {
+    let parameters = Parameters {
+        peer_quotas: PeerQuotas {
+            frames: 0,
+            ..PeerQuotas::default()
+        },
+        ..Parameters::default()
+    };
+    match parameters.validate() {
+        Err(ConfigError::InvalidParameter { name, .. }) => assert_eq!(name, "peer_quotas.frames"),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn transactions_socket_per_authority() {
+    let parameters = Parameters {
+        transactions_socket_dir: Some(PathBuf::from("/tmp")),
+        ..Parameters::default()
+    };
+    let first = parameters.transactions_socket(&PublicKey([1; 32]), 0);
+    let second = parameters.transactions_socket(&PublicKey([2; 32]), 0);
+    assert_eq!(
+        first,
+        Some(PathBuf::from("/tmp/worker-0101010101010101-0.sock"))
+    );
+    assert_ne!(first, second);
+    assert!(Parameters::default()
+        .transactions_socket(&PublicKey([1; 32]), 0)
+        .is_none());
+}
+
+#[test]
+fn validate_batch_retention() {
+    let parameters = Parameters {
+        batch_retention: Some(Parameters::default().gc_depth),
+        ..Parameters::default()
+    };
+    assert!(parameters.validate().is_ok());
+
+    let parameters = Parameters {
+        batch_retention: Some(Parameters::default().gc_depth - 1),
+        ..Parameters::default()
+    };
+    match parameters.validate() {
+        Err(ConfigError::InvalidParameter { name, .. }) => assert_eq!(name, "batch_retention"),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn validate_batch_size() {
+    let parameters = Parameters {
+        batch_size: 0,
+        ..Parameters::default()
+    };
+    match parameters.validate() {
+        Err(ConfigError::InvalidParameter { name, .. }) => assert_eq!(name, "batch_size"),
+        _ => panic!("Unexpected result"),
+    }
+
+    let parameters = Parameters {
+        adaptive_batching: Some(AdaptiveBatching {
+            min_batch_size: 0,
+            ..AdaptiveBatching::default()
+        }),
+        ..Parameters::default()
+    };
+    match parameters.validate() {
+        Err(ConfigError::InvalidParameter { name, .. }) => {
+            assert_eq!(name, "adaptive_batching.min_batch_size")
+        }
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn validate_bls_proofs() {
+    let keypair = KeyPair::new();
+    let secret = BlsSecretKey::derive(&keypair.secret);
+    let other = BlsSecretKey::derive(&KeyPair::new().secret);
+    let committee = |bls_proof| {
+        let authority = Authority {
+            stake: 1,
+            primary: PrimaryAddresses {
+                primary_to_primary: "127.0.0.1:3000".parse().unwrap(),
+                worker_to_primary: "127.0.0.1:3001".parse().unwrap(),
+            },
+            workers: HashMap::new(),
+            bls_key: Some(secret.public()),
+            bls_proof,
+        };
+        Committee {
+            epoch: 0,
+            authorities: std::iter::once((keypair.name, authority)).collect(),
+            batch_compression: BatchCompression::default(),
+            batch_dissemination: BatchDissemination::default(),
+            max_batch_size: None,
+            encryption_key: None,
+        }
+    };
+    assert!(committee(Some(secret.prove_possession()))
+        .validate()
+        .is_ok());
+
+    // Ensure we reject the BLS keys without a proof, or with the proof of another key.
+    for proof in [None, Some(other.prove_possession())] {
+        match committee(proof).validate() {
+            Err(ConfigError::InvalidBlsKey(name)) => assert_eq!(name, keypair.name),
+            _ => panic!("Unexpected result"),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::State;
+use crypto::{Digest, PublicKey};
+use primary::Round;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+
+#[cfg(test)]
+#[path = "tests/debug_tests.rs"]
+pub mod debug_tests;
+
+/// The formats in which the DAG can be exported.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum DagFormat {
+    /// A GraphViz graph (committed certificates are filled).
+    Dot,
+    /// A JSON document listing the vertices of the DAG.
+    Json,
+}
+
+/// A request to export the DAG held by the consensus, along with the channel where to send it.
+pub type DagRequest = (DagFormat, tokio::sync::oneshot::Sender<String>);
+
+/// A certificate of the DAG.
+#[derive(Serialize)]
+struct Vertex {
+    round: Round,
+    author: PublicKey,
+    digest: String,
+    parents: Vec<String>,
+    committed: bool,
+}
+
+/// The DAG held by the consensus.
+#[derive(Serialize)]
+struct DagExport {
+    last_committed_round: Round,
+    vertices: Vec<Vertex>,
+}
+
+impl DagExport {
+    fn new(state: &State) -> Self {
+        let mut vertices: Vec<_> = state
+            .dag
+            .values()
+            .flat_map(|authorities| authorities.values())
+            .map(|(digest, certificate)| Vertex {
+                round: certificate.round(),
+                author: certificate.origin(),
+                digest: encode(digest),
+                parents: certificate.parents().iter().map(encode).collect(),
+                committed: matches!(
+                    state.last_committed.get(&certificate.origin()),
+                    Some(r) if certificate.round() <= *r
+                ),
+            })
+            .collect();
+        vertices.sort_by_key(|x| (x.round, x.author));
+
+        Self {
+            last_committed_round: state.last_committed_round,
+            vertices,
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dag {\n");
+        for vertex in &self.vertices {
+            let style = match vertex.committed {
+                true => ", style=filled",
+                false => "",
+            };
+            let _ = writeln!(
+                dot,
+                "  \"{}\" [label=\"R{} {}\"{}];",
+                vertex.digest, vertex.round, vertex.author, style
+            );
+            for parent in &vertex.parents {
+                let _ = writeln!(dot, "  \"{}\" -> \"{}\";", vertex.digest, parent);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Encodes a digest in full (its `Display` implementation truncates it).
+fn encode(digest: &Digest) -> String {
+    format!("{:?}", digest)
+}
+
+/// Serializes the DAG held by the consensus state in the specified format.
+pub(crate) fn export(state: &State, format: DagFormat) -> String {
+    let export = DagExport::new(state);
+    match format {
+        DagFormat::Dot => export.to_dot(),
+        DagFormat::Json => {
+            serde_json::to_string_pretty(&export).expect("Failed to serialize the dag")
+        }
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::ConsensusOutput;
+use config::WorkerId;
+use crypto::{Digest, PublicKey};
+use log::{debug, warn};
+use primary::SystemMessage;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/dispatcher_tests.rs"]
+pub mod dispatcher_tests;
+
+/// An application-provided predicate over the batches of a committed certificate. It receives the
+/// author of the certificate, the batch's digest and the id of the worker holding the batch.
+pub type BatchTag = Arc<dyn Fn(&PublicKey, &Digest, &WorkerId) -> bool + Send + Sync>;
+
+/// Selects the committed payloads an output consumer is interested in.
+#[derive(Clone)]
+pub enum CommitFilter {
+    /// Receive all committed certificates.
+    All,
+    /// Only receive the batches proposed by a specific authority.
+    Author(PublicKey),
+    /// Only receive the batches held by a specific worker id.
+    Worker(WorkerId),
+    /// Only receive the batches selected by the application.
+    Tag(BatchTag),
+    /// Only receive the system entries (without batches).
+    System,
+}
+
+impl CommitFilter {
+    /// Returns a view of the output restricted to the batches selected by the filter, or `None` if the
+    /// filter selects none of them. The system entries are only kept by the `All` and `System` filters.
+    /// The certificate itself is left untouched so that consumers can still check its digest and votes.
+    pub fn apply(&self, output: &ConsensusOutput) -> Option<FilteredOutput> {
+        let author = output.certificate.origin();
+        let selected = |digest: &Digest, worker_id: &WorkerId| match self {
+            Self::All => true,
+            Self::System => false,
+            Self::Author(name) => name == &author,
+            Self::Worker(id) => id == worker_id,
+            Self::Tag(tag) => tag(&author, digest, worker_id),
+        };
+
+        let payload: BTreeMap<_, _> = output
+            .certificate
+            .header
+            .payload
+            .iter()
+            .filter(|(digest, _)| !output.duplicates.contains(digest))
+            .filter(|(digest, worker_id)| selected(digest, worker_id))
+            .map(|(digest, worker_id)| (digest.clone(), *worker_id))
+            .collect();
+        let system = match self {
+            Self::All | Self::System => output.certificate.system().to_vec(),
+            _ => Vec::new(),
+        };
+        match payload.is_empty() && system.is_empty() && !matches!(self, Self::All) {
+            true => None,
+            false => Some(FilteredOutput {
+                output: output.clone(),
+                payload,
+                system,
+            }),
+        }
+    }
+}
+
+/// The part of a committed certificate selected by a subscriber's filter.
+#[derive(Clone, Debug)]
+pub struct FilteredOutput {
+    /// The unmodified consensus output.
+    pub output: ConsensusOutput,
+    /// The batches of the certificate selected by the filter (skipping those already committed).
+    pub payload: BTreeMap<Digest, WorkerId>,
+    /// The system entries of the certificate selected by the filter.
+    pub system: Vec<SystemMessage>,
+}
+
+/// A request to receive the committed certificates matching a filter.
+pub struct Subscription {
+    /// The filter to apply to the output sequence.
+    pub filter: CommitFilter,
+    /// The channel where to deliver the filtered outputs.
+    pub tx_output: Sender<FilteredOutput>,
+}
+
+/// Fans out the output sequence of the consensus to all subscribers. Filters are applied here, before
+/// the consumers resolve the batches referenced by the certificates, so that they only fetch the
+/// batches they are interested in. Each subscriber is served by its own task so that a slow consumer
+/// cannot hold back the others; a subscriber falling more than `capacity` outputs behind is dropped.
+pub struct Dispatcher {
+    /// Receives the sequence of ordered certificates from the consensus.
+    rx_output: Receiver<ConsensusOutput>,
+    /// Receives new subscriptions from the application layer.
+    rx_subscription: Receiver<Subscription>,
+    /// Fans out the output sequence to the subscriber tasks.
+    tx_subscribers: broadcast::Sender<ConsensusOutput>,
+}
+
+impl Dispatcher {
+    /// The number of outputs a subscriber may lag behind before being dropped.
+    const CAPACITY: usize = 10_000;
+
+    pub fn spawn(rx_output: Receiver<ConsensusOutput>, rx_subscription: Receiver<Subscription>) {
+        tokio::spawn(async move {
+            let (tx_subscribers, _) = broadcast::channel(Self::CAPACITY);
+            Self {
+                rx_output,
+                rx_subscription,
+                tx_subscribers,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                // Register new subscribers before dispatching further certificates.
+                biased;
+
+                Some(subscription) = self.rx_subscription.recv() => {
+                    let rx_output = self.tx_subscribers.subscribe();
+                    tokio::spawn(Self::serve(subscription, rx_output));
+                },
+                Some(output) = self.rx_output.recv() => {
+                    // There may be no subscriber at the moment.
+                    let _ = self.tx_subscribers.send(output);
+                },
+                else => break,
+            }
+        }
+    }
+
+    /// Delivers the outputs selected by the filter of a single subscriber.
+    async fn serve(
+        subscription: Subscription,
+        mut rx_output: broadcast::Receiver<ConsensusOutput>,
+    ) {
+        loop {
+            let output = match rx_output.recv().await {
+                Ok(output) => output,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(
+                        "Dropping output subscriber lagging {} outputs behind",
+                        missed
+                    );
+                    return;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            if let Some(filtered) = subscription.filter.apply(&output) {
+                if subscription.tx_output.send(filtered).await.is_err() {
+                    debug!("Removing closed output subscriber");
+                    return;
+                }
+            }
+        }
+    }
+}
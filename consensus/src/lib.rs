@@ -1,17 +1,59 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use config::{Committee, Stake};
+use crate::debug::{DagFormat, DagRequest};
+use config::{scheduled_leader, Committee, LeaderSchedule, Stake};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
 use log::{debug, info, log_enabled, warn};
-use primary::{Certificate, Round};
+use primary::{Certificate, GcDepthCommand, GcDepthError, GcDepthUpdate, Round, SystemMessage};
+use serde::{Deserialize, Serialize};
 use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use tokio::sync::mpsc::{Receiver, Sender};
 
+pub mod debug;
+mod dispatcher;
+mod output;
+mod snapshot;
+
+pub use crate::dispatcher::{BatchTag, CommitFilter, Dispatcher, FilteredOutput, Subscription};
+pub use crate::output::OutputBuffer;
+pub use crate::snapshot::{replay, SnapshotDiff, SnapshotEntry, SnapshotError};
+
 #[cfg(test)]
 #[path = "tests/consensus_tests.rs"]
 pub mod consensus_tests;
 
+/// The position of a certificate in the commit sequence.
+pub type SequenceNumber = u64;
+
+/// A committed certificate, annotated with the information execution layers need to build their
+/// dependency graphs without re-walking the DAG.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConsensusOutput {
+    /// The committed certificate.
+    pub certificate: Certificate,
+    /// The position of the certificate in the commit sequence (starting from 0).
+    pub index: SequenceNumber,
+    /// The digest of the leader (anchor) under which the certificate was ordered.
+    pub anchor: Digest,
+    /// The batches of the certificate already committed by an earlier certificate of the sequence
+    /// (within the gc window). Execution layers must skip them.
+    pub duplicates: BTreeSet<Digest>,
+}
+
+impl ConsensusOutput {
+    /// The digests of the parents of the committed certificate.
+    pub fn parents(&self) -> &BTreeSet<Digest> {
+        self.certificate.parents()
+    }
+
+    /// The system entries of the committed certificate (injected by its author rather than by its
+    /// workers).
+    pub fn system(&self) -> &[SystemMessage] {
+        self.certificate.system()
+    }
+}
+
 /// The representation of the DAG in memory.
 type Dag = HashMap<Round, HashMap<PublicKey, (Digest, Certificate)>>;
 
@@ -60,140 +102,171 @@ impl State {
     }
 }
 
-pub struct Consensus {
+/// The commit logic of the consensus, free of any IO. The caller feeds it certificates (one by
+/// one) and receives the resulting commit sequence; this allows to drive it deterministically.
+pub struct ConsensusCore {
     /// The committee information.
     committee: Committee,
     /// The depth of the garbage collector.
     gc_depth: Round,
-
-    /// Receives new certificates from the primary. The primary should send us new certificates only
-    /// if it already sent us its whole history.
-    rx_primary: Receiver<Certificate>,
-    /// Outputs the sequence of ordered certificates to the primary (for cleanup and feedback).
-    tx_primary: Sender<Certificate>,
-    /// Outputs the sequence of ordered certificates to the application layer.
-    tx_output: Sender<Certificate>,
-
-    /// The genesis certificates.
-    genesis: Vec<Certificate>,
+    /// One cycle of the leader schedule.
+    leaders: Vec<PublicKey>,
+    /// The consensus state.
+    state: State,
+    /// A change of the depth of the garbage collector waiting for its activation round.
+    pending_update: Option<GcDepthUpdate>,
+    /// The position of the next committed certificate.
+    next_index: SequenceNumber,
+    /// The batches referenced by the recently committed certificates (along with their round).
+    committed_batches: HashMap<Digest, Round>,
 }
 
-impl Consensus {
-    pub fn spawn(
-        committee: Committee,
-        gc_depth: Round,
-        rx_primary: Receiver<Certificate>,
-        tx_primary: Sender<Certificate>,
-        tx_output: Sender<Certificate>,
-    ) {
-        tokio::spawn(async move {
-            Self {
-                committee: committee.clone(),
-                gc_depth,
-                rx_primary,
-                tx_primary,
-                tx_output,
-                genesis: Certificate::genesis(&committee),
-            }
-            .run()
-            .await;
-        });
+impl ConsensusCore {
+    pub fn new(committee: Committee, gc_depth: Round, schedule: LeaderSchedule) -> Self {
+        let genesis = Certificate::genesis(&committee);
+        Self {
+            leaders: committee.leader_schedule(schedule),
+            committee,
+            gc_depth,
+            state: State::new(genesis),
+            pending_update: None,
+            next_index: 0,
+            committed_batches: HashMap::new(),
+        }
     }
 
-    async fn run(&mut self) {
-        // The consensus state (everything else is immutable).
-        let mut state = State::new(self.genesis.clone());
+    /// Serializes the DAG currently held in memory (for debugging).
+    pub fn export_dag(&self, format: DagFormat) -> String {
+        debug::export(&self.state, format)
+    }
 
-        // Listen to incoming certificates.
-        while let Some(certificate) = self.rx_primary.recv().await {
-            debug!("Processing {:?}", certificate);
-            let round = certificate.round();
-
-            // Add the new certificate to the local storage.
-            state
-                .dag
-                .entry(round)
-                .or_insert_with(HashMap::new)
-                .insert(certificate.origin(), (certificate.digest(), certificate));
-
-            // Try to order the dag to commit. Start from the highest round for which we have at least
-            // 2f+1 certificates. This is because we need them to reveal the common coin.
-            let r = round - 1;
-
-            // We only elect leaders for even round numbers.
-            if r % 2 != 0 || r < 4 {
-                continue;
-            }
+    /// The last committed round.
+    pub fn last_committed_round(&self) -> Round {
+        self.state.last_committed_round
+    }
 
-            // Get the certificate's digest of the leader of round r-2. If we already ordered this leader,
-            // there is nothing to do.
-            let leader_round = r - 2;
-            if leader_round <= state.last_committed_round {
-                continue;
-            }
-            let (leader_digest, leader) = match self.leader(leader_round, &state.dag) {
-                Some(x) => x,
-                None => continue,
-            };
+    /// Schedules a change of the depth of the garbage collector. The new depth is applied once the
+    /// activation round is committed.
+    pub fn update_gc_depth(&mut self, update: GcDepthUpdate) -> Result<(), GcDepthError> {
+        update.validate(self.state.last_committed_round)?;
+        self.pending_update = Some(update);
+        Ok(())
+    }
 
-            // Check if the leader has f+1 support from its children (ie. round r-1).
-            let stake: Stake = state
-                .dag
-                .get(&(r - 1))
-                .expect("We should have the whole history by now")
-                .values()
-                .filter(|(_, x)| x.header.parents.contains(&leader_digest))
-                .map(|(_, x)| self.committee.stake(&x.origin()))
-                .sum();
-
-            // If it is the case, we can commit the leader. But first, we need to recursively go back to
-            // the last committed leader, and commit all preceding leaders in the right order. Committing
-            // a leader block means committing all its dependencies.
-            if stake < self.committee.validity_threshold() {
-                debug!("Leader {:?} does not have enough support", leader);
-                continue;
-            }
+    /// Adds a certificate to the dag and returns the (possibly empty) sequence of certificates it
+    /// allows to commit. The caller should only provide certificates whose history it already provided.
+    /// The sequence lists the causal history of each leader by round, before the leader itself.
+    pub fn process_certificate(&mut self, certificate: Certificate) -> Vec<ConsensusOutput> {
+        debug!("Processing {:?}", certificate);
+        let round = certificate.round();
+
+        // Add the new certificate to the local storage.
+        self.state
+            .dag
+            .entry(round)
+            .or_insert_with(HashMap::new)
+            .insert(certificate.origin(), (certificate.digest(), certificate));
+
+        // Try to order the dag to commit. Start from the highest round for which we have at least
+        // 2f+1 certificates. This is because we need them to reveal the common coin.
+        let r = round - 1;
+
+        // We only elect leaders for even round numbers.
+        if r % 2 != 0 || r < 4 {
+            return Vec::new();
+        }
 
-            // Get an ordered list of past leaders that are linked to the current leader.
-            debug!("Leader {:?} has enough support", leader);
-            let mut sequence = Vec::new();
-            for leader in self.order_leaders(leader, &state).iter().rev() {
-                // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
-                for x in self.order_dag(leader, &state) {
-                    // Update and clean up internal state.
-                    state.update(&x, self.gc_depth);
-
-                    // Add the certificate to the sequence.
-                    sequence.push(x);
-                }
-            }
+        // Get the certificate's digest of the leader of round r-2. If we already ordered this leader,
+        // there is nothing to do.
+        let leader_round = r - 2;
+        if leader_round <= self.state.last_committed_round {
+            return Vec::new();
+        }
+        let (leader_digest, leader) = match self.leader(leader_round, &self.state.dag) {
+            Some(x) => x,
+            None => return Vec::new(),
+        };
+
+        // Check if the leader has f+1 support from its children (ie. round r-1).
+        let stake: Stake = self
+            .state
+            .dag
+            .get(&(r - 1))
+            .expect("We should have the whole history by now")
+            .values()
+            .filter(|(_, x)| x.header.parents.contains(leader_digest))
+            .map(|(_, x)| self.committee.stake(&x.origin()))
+            .sum();
+
+        // If it is the case, we can commit the leader. But first, we need to recursively go back to
+        // the last committed leader, and commit all preceding leaders in the right order. Committing
+        // a leader block means committing all its dependencies.
+        if stake < self.committee.validity_threshold() {
+            debug!("Leader {:?} does not have enough support", leader);
+            return Vec::new();
+        }
 
-            // Log the latest committed round of every authority (for debug).
-            if log_enabled!(log::Level::Debug) {
-                for (name, round) in &state.last_committed {
-                    debug!("Latest commit of {}: Round {}", name, round);
+        // Get an ordered list of past leaders that are linked to the current leader.
+        debug!("Leader {:?} has enough support", leader);
+        let mut sequence = Vec::new();
+        for leader in self.order_leaders(leader, &self.state).iter().rev() {
+            // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
+            let anchor = leader.digest();
+            for x in self.order_dag(leader, &self.state) {
+                // Update and clean up internal state.
+                self.state.update(&x, self.gc_depth);
+
+                // Flag the batches already committed: every authority commits the same sequence,
+                // so they all skip the same batches.
+                let mut duplicates = BTreeSet::new();
+                for digest in x.header.payload.keys() {
+                    if self
+                        .committed_batches
+                        .insert(digest.clone(), x.round())
+                        .is_some()
+                    {
+                        duplicates.insert(digest.clone());
+                    }
                 }
-            }
 
-            // Output the sequence in the right order.
-            for certificate in sequence {
-                #[cfg(not(feature = "benchmark"))]
-                info!("Committed {}", certificate.header);
+                // Add the certificate to the sequence.
+                sequence.push(ConsensusOutput {
+                    certificate: x,
+                    index: self.next_index,
+                    anchor: anchor.clone(),
+                    duplicates,
+                });
+                self.next_index += 1;
+            }
 
-                #[cfg(feature = "benchmark")]
-                for digest in certificate.header.payload.keys() {
-                    // NOTE: This log entry is used to compute performance.
-                    info!("Committed {} -> {:?}", certificate.header, digest);
-                }
+            // Apply the pending change of depth (if any) right after committing the leader of its
+            // activation round, and forget the batches committed before the gc window. Authorities
+            // may commit several leaders at once (or not), so we do it after every leader: they
+            // all switch depth at the same point of the commit sequence.
+            self.apply_pending_update();
+            let last_committed_round = self.state.last_committed_round;
+            let gc_depth = self.gc_depth;
+            self.committed_batches
+                .retain(|_, r| *r + gc_depth >= last_committed_round);
+        }
 
-                self.tx_primary
-                    .send(certificate.clone())
-                    .await
-                    .expect("Failed to send certificate to primary");
+        // Log the latest committed round of every authority (for debug).
+        if log_enabled!(log::Level::Debug) {
+            for (name, round) in &self.state.last_committed {
+                debug!("Latest commit of {}: Round {}", name, round);
+            }
+        }
+        sequence
+    }
 
-                if let Err(e) = self.tx_output.send(certificate).await {
-                    warn!("Failed to output certificate: {}", e);
-                }
+    /// Applies the pending change of depth (if any) once we committed its activation round.
+    fn apply_pending_update(&mut self) {
+        if let Some(update) = self.pending_update.take() {
+            if self.state.last_committed_round >= update.activation_round {
+                info!("Garbage collection depth set to {} rounds", update.depth);
+                self.gc_depth = update.depth;
+            } else {
+                self.pending_update = Some(update);
             }
         }
     }
@@ -210,9 +283,7 @@ impl Consensus {
         let coin = round;
 
         // Elect the leader.
-        let mut keys: Vec<_> = self.committee.authorities.keys().cloned().collect();
-        keys.sort();
-        let leader = keys[coin as usize % self.committee.size()];
+        let leader = scheduled_leader(&self.leaders, coin);
 
         // Return its certificate and the certificate's digest.
         dag.get(&round).map(|x| x.get(&leader)).flatten()
@@ -300,3 +371,86 @@ impl Consensus {
         ordered
     }
 }
+
+pub struct Consensus {
+    /// The commit logic.
+    core: ConsensusCore,
+
+    /// Receives new certificates from the primary. The primary should send us new certificates only
+    /// if it already sent us its whole history.
+    rx_primary: Receiver<Certificate>,
+    /// Receives requests to change the depth of the garbage collector.
+    rx_gc_depth: Receiver<GcDepthCommand>,
+    /// Receives requests to export the DAG (for debugging).
+    rx_debug: Receiver<DagRequest>,
+    /// Outputs the sequence of ordered certificates to the primary (for cleanup and feedback).
+    tx_primary: Sender<Certificate>,
+    /// Outputs the sequence of ordered certificates to the application layer.
+    tx_output: Sender<ConsensusOutput>,
+}
+
+impl Consensus {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        committee: Committee,
+        gc_depth: Round,
+        leader_schedule: LeaderSchedule,
+        rx_primary: Receiver<Certificate>,
+        rx_gc_depth: Receiver<GcDepthCommand>,
+        rx_debug: Receiver<DagRequest>,
+        tx_primary: Sender<Certificate>,
+        tx_output: Sender<ConsensusOutput>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                core: ConsensusCore::new(committee, gc_depth, leader_schedule),
+                rx_primary,
+                rx_gc_depth,
+                rx_debug,
+                tx_primary,
+                tx_output,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        // Listen to incoming certificates.
+        loop {
+            let certificate = tokio::select! {
+                Some(certificate) = self.rx_primary.recv() => certificate,
+                Some((update, reply)) = self.rx_gc_depth.recv() => {
+                    let _ = reply.send(self.core.update_gc_depth(update));
+                    continue;
+                },
+                Some((format, reply)) = self.rx_debug.recv() => {
+                    let _ = reply.send(self.core.export_dag(format));
+                    continue;
+                },
+                else => break,
+            };
+
+            // Output the sequence in the right order.
+            for output in self.core.process_certificate(certificate) {
+                #[cfg(not(feature = "benchmark"))]
+                info!("Committed {}", output.certificate.header);
+
+                #[cfg(feature = "benchmark")]
+                for digest in output.certificate.header.payload.keys() {
+                    // NOTE: This log entry is used to compute performance.
+                    info!("Committed {} -> {:?}", output.certificate.header, digest);
+                }
+
+                self.tx_primary
+                    .send(output.certificate.clone())
+                    .await
+                    .expect("Failed to send certificate to primary");
+
+                if let Err(e) = self.tx_output.send(output).await {
+                    warn!("Failed to output certificate: {}", e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{ConsensusOutput, SequenceNumber};
+use config::{OutputBufferParameters, OutputPolicy};
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::Store;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/output_tests.rs"]
+pub mod output_tests;
+
+/// The prefix of the store keys holding spilled outputs.
+const SPILL_PREFIX: &[u8] = b"consensus_output";
+
+/// Buffers the consensus output for the application layer. When the buffer is full, it either stops
+/// reading from the consensus (which pauses commits) or spills the overflow to the store, so that a
+/// slow application never loses committed certificates.
+pub struct OutputBuffer {
+    /// The persistent storage (used to spill outputs).
+    store: Store,
+    /// The capacity and policy of the buffer.
+    parameters: OutputBufferParameters,
+    /// The number of committed certificates not yet delivered to the application layer.
+    lag: Arc<AtomicU64>,
+    /// Receives the sequence of committed certificates from the consensus.
+    rx_consensus: Receiver<ConsensusOutput>,
+    /// Delivers the sequence of committed certificates to the application layer.
+    tx_output: Sender<ConsensusOutput>,
+    /// The outputs buffered in memory.
+    buffer: VecDeque<ConsensusOutput>,
+    /// The (contiguous) range of outputs spilled to the store.
+    spilled: std::ops::Range<SequenceNumber>,
+}
+
+impl OutputBuffer {
+    pub fn spawn(
+        store: Store,
+        parameters: OutputBufferParameters,
+        lag: Arc<AtomicU64>,
+        rx_consensus: Receiver<ConsensusOutput>,
+        tx_output: Sender<ConsensusOutput>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                store,
+                parameters,
+                lag,
+                rx_consensus,
+                tx_output,
+                buffer: VecDeque::new(),
+                spilled: 0..0,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    fn spill_key(index: SequenceNumber) -> Vec<u8> {
+        [SPILL_PREFIX, &index.to_be_bytes()].concat()
+    }
+
+    /// Buffers a new output, spilling it to the store if the buffer is full (or if older outputs
+    /// are already spilled, to preserve the order).
+    async fn push(&mut self, output: ConsensusOutput) {
+        if self.spilled.is_empty() && self.buffer.len() < self.parameters.capacity {
+            self.buffer.push_back(output);
+            return;
+        }
+
+        if self.spilled.is_empty() {
+            warn!("Application layer lagging behind: spilling the consensus output to the store");
+            self.spilled = output.index..output.index;
+        }
+        let bytes = bincode::serialize(&output).expect("Failed to serialize consensus output");
+        self.store.write(Self::spill_key(output.index), bytes).await;
+        self.spilled.end = output.index + 1;
+    }
+
+    /// Refills the buffer with the oldest spilled outputs, removing them from the store.
+    async fn refill(&mut self) {
+        while self.buffer.len() < self.parameters.capacity && !self.spilled.is_empty() {
+            let index = self.spilled.start;
+            let bytes = match self.store.read(Self::spill_key(index)).await {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => panic!("Spilled output {} missing from the store", index),
+                Err(e) => panic!("Storage failure: {}", e),
+            };
+            let output =
+                bincode::deserialize(&bytes).expect("Failed to deserialize spilled output");
+            self.buffer.push_back(output);
+            if let Err(e) = self.store.delete(Self::spill_key(index)).await {
+                warn!("Failed to delete spilled output {}: {}", index, e);
+            }
+            self.spilled.start += 1;
+        }
+    }
+
+    async fn run(&mut self) {
+        // The number of outputs received from the consensus and delivered to the application.
+        let mut committed = 0;
+        let mut delivered = 0;
+
+        loop {
+            let accepting = match self.parameters.policy {
+                OutputPolicy::Pause => self.buffer.len() < self.parameters.capacity,
+                OutputPolicy::Spill => true,
+            };
+
+            let received = tokio::select! {
+                Some(output) = self.rx_consensus.recv(), if accepting => Some(output),
+                Ok(permit) = self.tx_output.reserve(), if !self.buffer.is_empty() => {
+                    let output = self.buffer.pop_front().unwrap();
+                    delivered = output.index + 1;
+                    permit.send(output);
+                    None
+                },
+                else => break,
+            };
+            match received {
+                Some(output) => {
+                    committed = output.index + 1;
+                    self.push(output).await;
+                }
+                None => self.refill().await,
+            }
+
+            let lag = committed - delivered;
+            debug!("Application layer lagging {} certificates behind", lag);
+            self.lag.store(lag, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::ConsensusCore;
+use config::{Committee, LeaderSchedule};
+use crypto::Digest;
+use crypto::Hash as _;
+use primary::{Certificate, Round};
+use std::collections::BTreeSet;
+use std::fmt;
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/snapshot_tests.rs"]
+pub mod snapshot_tests;
+
+/// Identifies a certificate by its round and digest.
+pub type SnapshotEntry = (Round, Digest);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SnapshotError {
+    #[error("The snapshot holds no certificate of round {0} but holds later ones")]
+    MissingRound(Round),
+}
+
+/// Reconstructs the commit sequence of a snapshot by feeding its certificates (in round order)
+/// to a fresh consensus core. The consensus needs the whole history, so the snapshot must hold
+/// certificates of every round up to its last one.
+pub fn replay(
+    committee: Committee,
+    gc_depth: Round,
+    leader_schedule: LeaderSchedule,
+    mut certificates: Vec<Certificate>,
+) -> Result<Vec<Certificate>, SnapshotError> {
+    // The genesis is already known by the consensus.
+    certificates.retain(|x| x.round() > 0);
+    certificates.sort_by_key(|x| (x.round(), x.origin()));
+
+    let rounds: BTreeSet<_> = certificates.iter().map(|x| x.round()).collect();
+    let last = rounds.iter().next_back().cloned().unwrap_or_default();
+    if let Some(round) = (1..=last).find(|x| !rounds.contains(x)) {
+        return Err(SnapshotError::MissingRound(round));
+    }
+
+    let mut core = ConsensusCore::new(committee, gc_depth, leader_schedule);
+    Ok(certificates
+        .into_iter()
+        .flat_map(|x| core.process_certificate(x))
+        .map(|x| x.certificate)
+        .collect())
+}
+
+/// A structured comparison of the store snapshots of two authorities.
+#[derive(Debug, Default, PartialEq)]
+pub struct SnapshotDiff {
+    /// The certificates only present in the first snapshot.
+    pub only_in_first: Vec<SnapshotEntry>,
+    /// The certificates only present in the second snapshot.
+    pub only_in_second: Vec<SnapshotEntry>,
+    /// The length of the commit sequence of each snapshot.
+    pub commit_lengths: (usize, usize),
+    /// The commits (index, first, second) on which the two sequences disagree. A sequence that is
+    /// only shorter than the other one (ie. lagging behind) does not disagree.
+    pub divergent_commits: Vec<(usize, SnapshotEntry, SnapshotEntry)>,
+}
+
+impl SnapshotDiff {
+    /// Compares the certificates and the commit sequences of two snapshots.
+    pub fn new(
+        first: &[Certificate],
+        second: &[Certificate],
+        first_sequence: &[Certificate],
+        second_sequence: &[Certificate],
+    ) -> Self {
+        let entries = |certificates: &[Certificate]| {
+            certificates
+                .iter()
+                .map(|x| (x.round(), x.digest()))
+                .collect::<BTreeSet<_>>()
+        };
+        let first_entries = entries(first);
+        let second_entries = entries(second);
+
+        let divergent_commits = first_sequence
+            .iter()
+            .zip(second_sequence.iter())
+            .enumerate()
+            .map(|(i, (x, y))| (i, (x.round(), x.digest()), (y.round(), y.digest())))
+            .filter(|(_, x, y)| x != y)
+            .collect();
+
+        Self {
+            only_in_first: first_entries.difference(&second_entries).cloned().collect(),
+            only_in_second: second_entries.difference(&first_entries).cloned().collect(),
+            commit_lengths: (first_sequence.len(), second_sequence.len()),
+            divergent_commits,
+        }
+    }
+
+    /// Replays both snapshots through the consensus and compares them.
+    pub fn compute(
+        committee: Committee,
+        gc_depth: Round,
+        leader_schedule: LeaderSchedule,
+        first: Vec<Certificate>,
+        second: Vec<Certificate>,
+    ) -> Result<Self, SnapshotError> {
+        let first_sequence = replay(committee.clone(), gc_depth, leader_schedule, first.clone())?;
+        let second_sequence = replay(committee, gc_depth, leader_schedule, second.clone())?;
+        Ok(Self::new(
+            &first,
+            &second,
+            &first_sequence,
+            &second_sequence,
+        ))
+    }
+
+    /// The index of the first commit on which the two sequences disagree (if any).
+    pub fn first_divergence(&self) -> Option<usize> {
+        self.divergent_commits.first().map(|(i, _, _)| *i)
+    }
+
+    /// Whether the two snapshots are consistent with each other.
+    pub fn is_consistent(&self) -> bool {
+        self.divergent_commits.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Certificates only in first: {}",
+            self.only_in_first.len()
+        )?;
+        for (round, digest) in &self.only_in_first {
+            writeln!(f, "  R{} {}", round, digest)?;
+        }
+        writeln!(
+            f,
+            "Certificates only in second: {}",
+            self.only_in_second.len()
+        )?;
+        for (round, digest) in &self.only_in_second {
+            writeln!(f, "  R{} {}", round, digest)?;
+        }
+        writeln!(
+            f,
+            "Commit sequence lengths: {} / {}",
+            self.commit_lengths.0, self.commit_lengths.1
+        )?;
+        match self.first_divergence() {
+            Some(index) => writeln!(f, "First divergent commit: #{}", index)?,
+            None => writeln!(f, "First divergent commit: none")?,
+        }
+        for (index, (r1, d1), (r2, d2)) in &self.divergent_commits {
+            writeln!(f, "  #{}: R{} {} / R{} {}", index, r1, d1, r2, d2)?;
+        }
+        Ok(())
+    }
+}
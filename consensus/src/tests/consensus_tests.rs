@@ -1,15 +1,18 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use config::{Authority, PrimaryAddresses};
+use config::{
+    Authority, BatchCompression, BatchDissemination, PrimaryAddresses, MAX_SCHEDULE_LENGTH,
+};
 use crypto::{generate_keypair, SecretKey};
-use primary::Header;
+use primary::{GcDepthError, Header};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use std::collections::{BTreeSet, VecDeque};
 use tokio::sync::mpsc::channel;
+use tokio::sync::oneshot;
 
 // Fixture
-fn keys() -> Vec<(PublicKey, SecretKey)> {
+pub fn keys() -> Vec<(PublicKey, SecretKey)> {
     let mut rng = StdRng::from_seed([0; 32]);
     (0..4).map(|_| generate_keypair(&mut rng)).collect()
 }
@@ -17,6 +20,7 @@ fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn mock_committee() -> Committee {
     Committee {
+        epoch: 0,
         authorities: keys()
             .iter()
             .map(|(id, _)| {
@@ -29,15 +33,21 @@ pub fn mock_committee() -> Committee {
                             worker_to_primary: "0.0.0.0:0".parse().unwrap(),
                         },
                         workers: HashMap::default(),
+                        bls_key: None,
+                        bls_proof: None,
                     },
                 )
             })
             .collect(),
+        batch_compression: BatchCompression::default(),
+        batch_dissemination: BatchDissemination::default(),
+        max_batch_size: None,
+        encryption_key: None,
     }
 }
 
 // Fixture
-fn mock_certificate(
+pub fn mock_certificate(
     origin: PublicKey,
     round: Round,
     parents: BTreeSet<Digest>,
@@ -57,7 +67,7 @@ fn mock_certificate(
 // Creates one certificate per authority starting and finishing at the specified rounds (inclusive).
 // Outputs a VecDeque of certificates (the certificate with higher round is on the front) and a set
 // of digests to be used as parents for the certificates of the next round.
-fn make_certificates(
+pub fn make_certificates(
     start: Round,
     stop: Round,
     initial_parents: &BTreeSet<Digest>,
@@ -99,10 +109,15 @@ async fn commit_one() {
     let (tx_waiter, rx_waiter) = channel(1);
     let (tx_primary, mut rx_primary) = channel(1);
     let (tx_output, mut rx_output) = channel(1);
+    let (_tx_gc_depth, rx_gc_depth) = channel(1);
+    let (_tx_debug, rx_debug) = channel(1);
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
         rx_waiter,
+        rx_gc_depth,
+        rx_debug,
         tx_primary,
         tx_output,
     );
@@ -117,10 +132,10 @@ async fn commit_one() {
     // Ensure the first 4 ordered certificates are from round 1 (they are the parents of the committed
     // leader); then the leader's certificate should be committed.
     for _ in 1..=4 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         assert_eq!(certificate.round(), 1);
     }
-    let certificate = rx_output.recv().await.unwrap();
+    let certificate = rx_output.recv().await.unwrap().certificate;
     assert_eq!(certificate.round(), 2);
 }
 
@@ -144,10 +159,15 @@ async fn dead_node() {
     let (tx_waiter, rx_waiter) = channel(1);
     let (tx_primary, mut rx_primary) = channel(1);
     let (tx_output, mut rx_output) = channel(1);
+    let (_tx_gc_depth, rx_gc_depth) = channel(1);
+    let (_tx_debug, rx_debug) = channel(1);
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
         rx_waiter,
+        rx_gc_depth,
+        rx_debug,
         tx_primary,
         tx_output,
     );
@@ -162,18 +182,18 @@ async fn dead_node() {
 
     // We should commit 3 leaders (rounds 2, 4, and 6).
     for i in 1..=15 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         let expected = ((i - 1) / keys.len() as u64) + 1;
         assert_eq!(certificate.round(), expected);
     }
-    let certificate = rx_output.recv().await.unwrap();
+    let certificate = rx_output.recv().await.unwrap().certificate;
     assert_eq!(certificate.round(), 6);
 }
 
-// Run for 6 dag rounds. The leaders of round 2 does not have enough support, but the leader of
-// round 4 does. The leader of rounds 2 and 4 should thus be committed upon entering round 6.
-#[tokio::test]
-async fn not_enough_support() {
+// Creates the certificates of 7 dag rounds. The leader of round 2 does not have enough support, but
+// the leader of round 4 does. The leaders of rounds 2 and 4 should thus be committed together, upon
+// the (single) certificate of round 7.
+fn weak_leader_certificates() -> VecDeque<Certificate> {
     let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
     keys.sort();
 
@@ -227,15 +247,28 @@ async fn not_enough_support() {
     // Round 7: Send a single certificate to trigger the commits.
     let (_, certificate) = mock_certificate(keys[0], 7, parents);
     certificates.push_back(certificate);
+    certificates
+}
+
+// Run for 6 dag rounds. The leaders of round 2 does not have enough support, but the leader of
+// round 4 does. The leader of rounds 2 and 4 should thus be committed upon entering round 6.
+#[tokio::test]
+async fn not_enough_support() {
+    let mut certificates = weak_leader_certificates();
 
     // Spawn the consensus engine and sink the primary channel.
     let (tx_waiter, rx_waiter) = channel(1);
     let (tx_primary, mut rx_primary) = channel(1);
     let (tx_output, mut rx_output) = channel(1);
+    let (_tx_gc_depth, rx_gc_depth) = channel(1);
+    let (_tx_debug, rx_debug) = channel(1);
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
         rx_waiter,
+        rx_gc_depth,
+        rx_debug,
         tx_primary,
         tx_output,
     );
@@ -249,18 +282,18 @@ async fn not_enough_support() {
 
     // We should commit 2 leaders (rounds 2 and 4).
     for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         assert_eq!(certificate.round(), 1);
     }
     for _ in 1..=4 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         assert_eq!(certificate.round(), 2);
     }
     for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         assert_eq!(certificate.round(), 3);
     }
-    let certificate = rx_output.recv().await.unwrap();
+    let certificate = rx_output.recv().await.unwrap().certificate;
     assert_eq!(certificate.round(), 4);
 }
 
@@ -295,10 +328,15 @@ async fn missing_leader() {
     let (tx_waiter, rx_waiter) = channel(1);
     let (tx_primary, mut rx_primary) = channel(1);
     let (tx_output, mut rx_output) = channel(1);
+    let (_tx_gc_depth, rx_gc_depth) = channel(1);
+    let (_tx_debug, rx_debug) = channel(1);
     Consensus::spawn(
         mock_committee(),
         /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
         rx_waiter,
+        rx_gc_depth,
+        rx_debug,
         tx_primary,
         tx_output,
     );
@@ -312,17 +350,233 @@ async fn missing_leader() {
 
     // Ensure the commit sequence is as expected.
     for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         assert_eq!(certificate.round(), 1);
     }
     for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         assert_eq!(certificate.round(), 2);
     }
     for _ in 1..=4 {
-        let certificate = rx_output.recv().await.unwrap();
+        let certificate = rx_output.recv().await.unwrap().certificate;
         assert_eq!(certificate.round(), 3);
     }
-    let certificate = rx_output.recv().await.unwrap();
+    let certificate = rx_output.recv().await.unwrap().certificate;
     assert_eq!(certificate.round(), 4);
 }
+
+// Request changes of the depth of the garbage collector and ensure invalid ones are rejected.
+#[tokio::test]
+async fn update_gc_depth() {
+    let (_tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, _rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let (tx_gc_depth, rx_gc_depth) = channel(1);
+    let (_tx_debug, rx_debug) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
+        rx_waiter,
+        rx_gc_depth,
+        rx_debug,
+        tx_primary,
+        tx_output,
+    );
+
+    // The new depth must be positive.
+    let update = GcDepthUpdate {
+        depth: 0,
+        activation_round: 10,
+    };
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_gc_depth.send((update, tx_reply)).await.unwrap();
+    assert!(matches!(
+        rx_reply.await.unwrap(),
+        Err(GcDepthError::InvalidDepth)
+    ));
+
+    // The activation round must be after the last committed round.
+    let update = GcDepthUpdate {
+        depth: 100,
+        activation_round: 0,
+    };
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_gc_depth.send((update, tx_reply)).await.unwrap();
+    assert!(matches!(
+        rx_reply.await.unwrap(),
+        Err(GcDepthError::StaleActivation(0, 0))
+    ));
+
+    // Valid updates are accepted.
+    let update = GcDepthUpdate {
+        depth: 100,
+        activation_round: 10,
+    };
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_gc_depth.send((update, tx_reply)).await.unwrap();
+    assert!(rx_reply.await.unwrap().is_ok());
+}
+
+// Drive the consensus core directly (without channels) for 4 dag rounds in ideal conditions. Only
+// the certificate of round 5 should commit the leader of round 2.
+#[test]
+fn core_commit_one() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+
+    let mut core = ConsensusCore::new(
+        mock_committee(),
+        /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
+    );
+    for certificate in certificates {
+        assert!(core.process_certificate(certificate).is_empty());
+    }
+
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+    let sequence = core.process_certificate(certificate);
+    assert_eq!(sequence.len(), 5);
+    let leader = &sequence.last().unwrap().certificate;
+    assert_eq!(leader.round(), 2);
+
+    // Every certificate is annotated with its position and the leader it was ordered under.
+    for (i, output) in sequence.iter().enumerate() {
+        assert_eq!(output.index, i as SequenceNumber);
+        assert_eq!(output.anchor, leader.digest());
+    }
+    assert_eq!(core.last_committed_round(), 2);
+}
+
+// Two certificates of round 1 reference the same batch: only the first one committed delivers it.
+#[test]
+fn flag_duplicate_batches() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    let digest = Digest([1; 32]);
+    for certificate in certificates.iter_mut().take(2) {
+        certificate.header.payload = [(digest.clone(), 0)].iter().cloned().collect();
+    }
+
+    let mut core = ConsensusCore::new(
+        mock_committee(),
+        /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
+    );
+    for certificate in certificates {
+        assert!(core.process_certificate(certificate).is_empty());
+    }
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+    let sequence = core.process_certificate(certificate);
+
+    // Ensure the batch is flagged as a duplicate in the second certificate referencing it.
+    let referencing: Vec<_> = sequence
+        .iter()
+        .filter(|x| x.certificate.header.payload.contains_key(&digest))
+        .collect();
+    assert_eq!(referencing.len(), 2);
+    assert!(referencing[0].duplicates.is_empty());
+    assert_eq!(
+        referencing[1].duplicates,
+        [digest].iter().cloned().collect::<BTreeSet<_>>()
+    );
+}
+
+// Commit the leaders of rounds 2 and 4 at once, with a change of depth activated at round 2. The
+// new depth must apply from the leader of round 4, as for the authorities committing the two leaders
+// separately.
+#[test]
+fn update_gc_depth_between_leaders() {
+    let mut core = ConsensusCore::new(
+        mock_committee(),
+        /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
+    );
+    let update = GcDepthUpdate {
+        depth: 1,
+        activation_round: 2,
+    };
+    core.update_gc_depth(update).unwrap();
+
+    let mut certificates = weak_leader_certificates();
+    let last = certificates.pop_back().unwrap();
+    for certificate in certificates {
+        assert!(core.process_certificate(certificate).is_empty());
+    }
+    let sequence = core.process_certificate(last);
+    let anchors: BTreeSet<_> = sequence.iter().map(|x| x.anchor.clone()).collect();
+    assert_eq!(anchors.len(), 2);
+
+    // Ensure the dag was already pruned with the new depth after the leader of round 4.
+    assert_eq!(core.gc_depth, 1);
+    assert_eq!(core.last_committed_round(), 4);
+    assert!(core.state.dag.keys().all(|r| *r >= 3));
+}
+
+#[test]
+fn stake_weighted_schedule() {
+    let mut committee = mock_committee();
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort();
+    committee.authorities.get_mut(&keys[3]).unwrap().stake = 3;
+
+    // The round-robin schedule ignores the stake.
+    assert_eq!(committee.leader_schedule(LeaderSchedule::RoundRobin), keys);
+
+    // The stake-weighted schedule elects authorities in proportion to their stake, and spreads the
+    // slots of the heavy authority over the cycle.
+    let schedule = committee.leader_schedule(LeaderSchedule::StakeWeighted);
+    assert_eq!(schedule.len(), 6);
+    assert_eq!(schedule.iter().filter(|x| *x == &keys[3]).count(), 3);
+    assert!(schedule.windows(2).all(|x| x[0] != x[1]));
+
+    // Walking the even rounds of two cycles elects every authority, each in proportion to its stake.
+    let mut elected = HashMap::new();
+    for round in (0..4 * schedule.len() as u64).step_by(2) {
+        *elected
+            .entry(scheduled_leader(&schedule, round))
+            .or_insert(0) += 1;
+    }
+    for key in &keys[..3] {
+        assert_eq!(elected[key], 2);
+    }
+    assert_eq!(elected[&keys[3]], 6);
+
+    // The schedule only depends on the proportions of the stakes.
+    for (i, key) in keys.iter().enumerate() {
+        let stake = if i == 3 { 3_000_000_000 } else { 1_000_000_000 };
+        committee.authorities.get_mut(key).unwrap().stake = stake;
+    }
+    assert_eq!(
+        committee.leader_schedule(LeaderSchedule::StakeWeighted),
+        schedule
+    );
+}
+
+// Stakes without common divisor would make a cycle as long as their sum: the cycle is bounded and
+// its slots remain proportional to the stakes (up to rounding).
+#[test]
+fn bounded_stake_weighted_schedule() {
+    let mut committee = mock_committee();
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort();
+    for (i, key) in keys.iter().enumerate() {
+        let stake = if i == 3 { 900_000_001 } else { 300_000_000 };
+        committee.authorities.get_mut(key).unwrap().stake = stake;
+    }
+
+    let schedule = committee.leader_schedule(LeaderSchedule::StakeWeighted);
+    assert!(schedule.len() as u64 <= MAX_SCHEDULE_LENGTH);
+    let slots = |key| schedule.iter().filter(|x| *x == key).count();
+    for key in &keys[..3] {
+        assert!(slots(&keys[3]).abs_diff(3 * slots(key)) <= 3);
+    }
+}
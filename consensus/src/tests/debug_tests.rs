@@ -0,0 +1,51 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::consensus_tests::{keys, make_certificates, mock_certificate, mock_committee};
+use crate::ConsensusCore;
+use config::LeaderSchedule;
+use crypto::Hash as _;
+use primary::Certificate;
+use std::collections::BTreeSet;
+
+// Fixture: a consensus core that committed the leader of round 2.
+fn core() -> ConsensusCore {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+
+    let mut core = ConsensusCore::new(
+        mock_committee(),
+        /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
+    );
+    for certificate in certificates.into_iter().chain(std::iter::once(certificate)) {
+        core.process_certificate(certificate);
+    }
+    core
+}
+
+#[test]
+fn export_json() {
+    let json = core().export_dag(DagFormat::Json);
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["last_committed_round"], 2);
+
+    // We keep the latest committed certificate of each authority: the leader of round 2 and the
+    // certificates of round 1 of the other authorities.
+    let vertices = value["vertices"].as_array().unwrap();
+    assert_eq!(vertices.len(), 16);
+    assert!(vertices[..3].iter().all(|x| x["round"] == 1));
+    let committed = vertices.iter().filter(|x| x["committed"] == true).count();
+    assert_eq!(committed, 4);
+}
+
+#[test]
+fn export_dot() {
+    let dot = core().export_dag(DagFormat::Dot);
+    assert!(dot.starts_with("digraph dag {"));
+    assert_eq!(dot.matches("style=filled").count(), 4);
+}
@@ -0,0 +1,129 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{ConsensusOutput, SequenceNumber};
+use primary::{Certificate, Header, SystemMessage};
+use std::collections::BTreeSet;
+use tokio::sync::mpsc::channel;
+
+// Fixture
+fn certificate(author: PublicKey) -> Certificate {
+    let payload = vec![(Digest([0; 32]), 0), (Digest([1; 32]), 1)];
+    Certificate {
+        header: Header {
+            author,
+            round: 1,
+            payload: payload.into_iter().collect(),
+            ..Header::default()
+        },
+        ..Certificate::default()
+    }
+}
+
+// Fixture
+fn output(certificate: Certificate) -> ConsensusOutput {
+    ConsensusOutput {
+        certificate,
+        index: 0,
+        anchor: Digest::default(),
+        duplicates: BTreeSet::new(),
+    }
+}
+
+#[test]
+fn skip_duplicates() {
+    let mut output = output(certificate(PublicKey::default()));
+    output.duplicates.insert(Digest([0; 32]));
+
+    let filtered = CommitFilter::All.apply(&output).unwrap();
+    assert_eq!(filtered.payload.len(), 1);
+    assert!(filtered.payload.contains_key(&Digest([1; 32])));
+}
+
+#[test]
+fn filter_worker() {
+    let output = output(certificate(PublicKey::default()));
+
+    let filtered = CommitFilter::Worker(1).apply(&output).unwrap();
+    assert_eq!(filtered.payload.len(), 1);
+    assert_eq!(filtered.payload.get(&Digest([1; 32])), Some(&1));
+
+    // The certificate itself is not modified by the filter.
+    assert_eq!(filtered.output.certificate.header.payload.len(), 2);
+
+    assert!(CommitFilter::Worker(2).apply(&output).is_none());
+}
+
+#[test]
+fn filter_tag() {
+    let output = output(certificate(PublicKey::default()));
+    let tag: BatchTag = Arc::new(|_, digest, _| digest == &Digest([0; 32]));
+
+    let filtered = CommitFilter::Tag(tag).apply(&output).unwrap();
+    assert_eq!(filtered.payload.len(), 1);
+    assert!(filtered.payload.contains_key(&Digest([0; 32])));
+}
+
+#[test]
+fn filter_system() {
+    let mut certificate = certificate(PublicKey::default());
+    assert!(CommitFilter::System
+        .apply(&output(certificate.clone()))
+        .is_none());
+
+    // Ensure the system entries are only delivered to the `All` and `System` filters.
+    certificate.header.system = vec![SystemMessage::Heartbeat];
+    let output = output(certificate);
+    let filtered = CommitFilter::System.apply(&output).unwrap();
+    assert!(filtered.payload.is_empty());
+    assert_eq!(filtered.system, vec![SystemMessage::Heartbeat]);
+
+    let filtered = CommitFilter::Worker(1).apply(&output).unwrap();
+    assert!(filtered.system.is_empty());
+    let filtered = CommitFilter::All.apply(&output).unwrap();
+    assert_eq!(filtered.system, vec![SystemMessage::Heartbeat]);
+}
+
+#[tokio::test]
+async fn dispatch() {
+    let author = PublicKey([1; 32]);
+
+    let (tx_output, rx_output) = channel(1);
+    let (tx_subscription, rx_subscription) = channel(2);
+    Dispatcher::spawn(rx_output, rx_subscription);
+
+    // Register two subscribers.
+    let (tx_all, mut rx_all) = channel(2);
+    let subscription = Subscription {
+        filter: CommitFilter::All,
+        tx_output: tx_all,
+    };
+    tx_subscription.send(subscription).await.unwrap();
+
+    let (tx_author, mut rx_author) = channel(2);
+    let subscription = Subscription {
+        filter: CommitFilter::Author(author),
+        tx_output: tx_author,
+    };
+    tx_subscription.send(subscription).await.unwrap();
+
+    // Output two certificates from different authors.
+    for (index, author) in [PublicKey::default(), author].iter().enumerate() {
+        let output = ConsensusOutput {
+            certificate: certificate(*author),
+            index: index as SequenceNumber,
+            anchor: Digest::default(),
+            duplicates: BTreeSet::new(),
+        };
+        tx_output.send(output).await.unwrap();
+    }
+
+    // Ensure each subscriber only receives what it asked for.
+    let filtered = rx_all.recv().await.unwrap();
+    assert_eq!(filtered.output.certificate.origin(), PublicKey::default());
+    assert_eq!(filtered.output.index, 0);
+    let filtered = rx_all.recv().await.unwrap();
+    assert_eq!(filtered.output.certificate.origin(), author);
+    let filtered = rx_author.recv().await.unwrap();
+    assert_eq!(filtered.output.certificate.origin(), author);
+    assert_eq!(filtered.output.index, 1);
+}
@@ -0,0 +1,101 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crypto::Digest;
+use primary::Certificate;
+use std::collections::BTreeSet;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout, Duration};
+
+// Fixture
+fn output(index: SequenceNumber) -> ConsensusOutput {
+    ConsensusOutput {
+        certificate: Certificate::default(),
+        index,
+        anchor: Digest::default(),
+        duplicates: BTreeSet::new(),
+    }
+}
+
+#[tokio::test]
+async fn spill_to_store() {
+    let (tx_consensus, rx_consensus) = channel(10);
+    let (tx_output, mut rx_output) = channel(1);
+    let lag = Arc::new(AtomicU64::new(0));
+
+    // Create a new test store.
+    let path = ".db_test_spill_to_store";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Spawn an output buffer holding a single output in memory.
+    let parameters = OutputBufferParameters {
+        capacity: 1,
+        policy: OutputPolicy::Spill,
+    };
+    OutputBuffer::spawn(
+        store.clone(),
+        parameters,
+        lag.clone(),
+        rx_consensus,
+        tx_output,
+    );
+
+    // Commit more outputs than the buffer and the output channel can hold. The consensus is never
+    // blocked and the application layer lags behind.
+    for i in 0..5 {
+        tx_consensus.send(output(i)).await.unwrap();
+    }
+    while lag.load(Ordering::Relaxed) != 4 {
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    // Ensure the application receives all outputs, in order.
+    for i in 0..5 {
+        assert_eq!(rx_output.recv().await.unwrap().index, i);
+    }
+
+    // Ensure the spilled outputs are removed from the store once delivered.
+    for i in 0..5 {
+        let key = OutputBuffer::spill_key(i);
+        assert!(store.read(key).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn pause_consensus() {
+    let (tx_consensus, rx_consensus) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    let lag = Arc::new(AtomicU64::new(0));
+
+    // Create a new test store.
+    let path = ".db_test_pause_consensus";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn an output buffer holding a single output in memory.
+    let parameters = OutputBufferParameters {
+        capacity: 1,
+        policy: OutputPolicy::Pause,
+    };
+    OutputBuffer::spawn(store, parameters, lag.clone(), rx_consensus, tx_output);
+
+    // Fill the output channel, the buffer and the consensus channel.
+    for i in 0..3 {
+        tx_consensus.send(output(i)).await.unwrap();
+    }
+    while lag.load(Ordering::Relaxed) != 1 {
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    // Ensure the consensus is paused rather than spilling to the store.
+    let paused = timeout(Duration::from_millis(100), tx_consensus.send(output(3))).await;
+    assert!(paused.is_err());
+
+    // Ensure the consensus resumes once the application catches up, without losing any output.
+    assert_eq!(rx_output.recv().await.unwrap().index, 0);
+    tx_consensus.send(output(3)).await.unwrap();
+    for i in 1..4 {
+        assert_eq!(rx_output.recv().await.unwrap().index, i);
+    }
+}
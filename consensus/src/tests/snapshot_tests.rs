@@ -0,0 +1,88 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::consensus_tests::{keys, make_certificates, mock_certificate, mock_committee};
+
+// Fixture: the certificates of rounds 1 to 4 and one certificate of round 5 to trigger the
+// commit of the leader of round 2.
+fn snapshot() -> Vec<Certificate> {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+    certificates
+        .into_iter()
+        .chain(std::iter::once(certificate))
+        .collect()
+}
+
+#[test]
+fn replay_snapshot() {
+    let sequence = replay(
+        mock_committee(),
+        /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
+        snapshot(),
+    )
+    .unwrap();
+
+    // We should commit the 4 certificates of round 1 and then the leader of round 2.
+    assert_eq!(sequence.len(), 5);
+    assert!(sequence[..4].iter().all(|x| x.round() == 1));
+    assert_eq!(sequence[4].round(), 2);
+}
+
+#[test]
+fn replay_snapshot_missing_round() {
+    let mut snapshot = snapshot();
+    snapshot.retain(|x| x.round() != 2);
+    let result = replay(
+        mock_committee(),
+        /* gc_depth */ 50,
+        LeaderSchedule::RoundRobin,
+        snapshot,
+    );
+    assert_eq!(result, Err(SnapshotError::MissingRound(2)));
+}
+
+#[test]
+fn identical_snapshots() {
+    let diff = SnapshotDiff::compute(
+        mock_committee(),
+        50,
+        LeaderSchedule::RoundRobin,
+        snapshot(),
+        snapshot(),
+    )
+    .unwrap();
+    assert!(diff.is_consistent());
+    assert!(diff.only_in_first.is_empty());
+    assert!(diff.only_in_second.is_empty());
+    assert_eq!(diff.commit_lengths, (5, 5));
+}
+
+#[test]
+fn divergent_snapshots() {
+    // Replace a certificate of round 1 in the second snapshot; the certificates of round 2 do not
+    // reference it, so it is not committed.
+    let first = snapshot();
+    let mut second = snapshot();
+    let replaced = first[3].clone();
+    second[3].header.id = Digest([1; 32]);
+
+    let diff = SnapshotDiff::compute(
+        mock_committee(),
+        50,
+        LeaderSchedule::RoundRobin,
+        first,
+        second.clone(),
+    )
+    .unwrap();
+    assert!(!diff.is_consistent());
+    assert!(diff.first_divergence().is_some());
+    assert_eq!(diff.only_in_first, vec![(1, replaced.digest())]);
+    assert_eq!(diff.only_in_second, vec![(1, second[3].digest())]);
+    assert_eq!(diff.commit_lengths, (5, 4));
+}
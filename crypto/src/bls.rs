@@ -0,0 +1,241 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{CryptoError, Digest, SecretKey};
+use blst::min_pk as blst_core;
+use blst::BLST_ERROR;
+use serde::{de, ser, Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+
+/// The domain separation tag of the BLS signatures (hash-to-curve, proof of possession scheme).
+const DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// The domain separation tag of the proofs of possession of the BLS keys. It differs from `DST`, so
+/// that a signature over a digest can never pass as a proof of possession (or the other way round).
+const POP_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// Domain separation of the derivation of the BLS key from the ed25519 secret.
+const KEY_INFO: &[u8] = b"narwhal-bls-key";
+
+fn check(result: BLST_ERROR) -> Result<(), CryptoError> {
+    match result {
+        BLST_ERROR::BLST_SUCCESS => Ok(()),
+        _ => Err(CryptoError::new()),
+    }
+}
+
+/// Represents a BLS public key (compressed G1 point, in bytes).
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct BlsPublicKey(pub [u8; 48]);
+
+impl BlsPublicKey {
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0[..])
+    }
+
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes
+            .get(..48)
+            .and_then(|x| x.try_into().ok())
+            .ok_or(base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
+
+    /// Decompresses the key and ensures it is a valid point of the prime-order subgroup.
+    fn load(&self) -> Result<blst_core::PublicKey, CryptoError> {
+        blst_core::PublicKey::key_validate(&self.0).map_err(|_| CryptoError::new())
+    }
+
+    /// Verifies that the holder of the key knows its secret. The aggregated signatures are only
+    /// safe against rogue keys (chosen as a function of the others) if every key passed this check.
+    pub fn verify_possession(&self, proof: &BlsProofOfPossession) -> Result<(), CryptoError> {
+        let key = self.load()?;
+        let signature =
+            blst_core::Signature::sig_validate(&proof.0, true).map_err(|_| CryptoError::new())?;
+        check(signature.verify(false, &self.0, POP_DST, &[], &key, false))
+    }
+}
+
+impl fmt::Debug for BlsPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode_base64())
+    }
+}
+
+impl fmt::Display for BlsPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode_base64().get(0..16).unwrap())
+    }
+}
+
+impl Serialize for BlsPublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.encode_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsPublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = Self::decode_base64(&s).map_err(|e| de::Error::custom(e.to_string()))?;
+        Ok(value)
+    }
+}
+
+/// Represents a BLS secret key. It is derived from the node's ed25519 secret key, so nodes do not
+/// need to store a second secret.
+pub struct BlsSecretKey(blst_core::SecretKey);
+
+impl BlsSecretKey {
+    pub fn derive(secret: &SecretKey) -> Self {
+        let key = blst_core::SecretKey::key_gen(&secret.0, KEY_INFO)
+            .expect("Failed to derive BLS secret key");
+        Self(key)
+    }
+
+    pub fn public(&self) -> BlsPublicKey {
+        BlsPublicKey(self.0.sk_to_pk().compress())
+    }
+
+    /// Proves the possession of the secret key by signing its public key.
+    pub fn prove_possession(&self) -> BlsProofOfPossession {
+        let public = self.public();
+        BlsProofOfPossession(self.0.sign(&public.0, POP_DST, &[]).compress())
+    }
+}
+
+/// Represents the proof of possession of a BLS secret key: its signature over its own public key
+/// (compressed G2 point, in bytes).
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct BlsProofOfPossession(pub [u8; 96]);
+
+impl BlsProofOfPossession {
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0[..])
+    }
+
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes
+            .get(..96)
+            .and_then(|x| x.try_into().ok())
+            .ok_or(base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
+}
+
+impl fmt::Debug for BlsProofOfPossession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode_base64())
+    }
+}
+
+impl Serialize for BlsProofOfPossession {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.encode_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsProofOfPossession {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = Self::decode_base64(&s).map_err(|e| de::Error::custom(e.to_string()))?;
+        Ok(value)
+    }
+}
+
+/// Represents a BLS signature (compressed G2 point), possibly aggregating the signatures of
+/// several authorities over the same digest.
+#[derive(Clone)]
+pub struct BlsSignature(pub [u8; 96]);
+
+impl BlsSignature {
+    pub fn new(digest: &Digest, secret: &BlsSecretKey) -> Self {
+        Self(secret.0.sign(&digest.0, DST, &[]).compress())
+    }
+
+    fn load(&self) -> Result<blst_core::Signature, CryptoError> {
+        blst_core::Signature::sig_validate(&self.0, true).map_err(|_| CryptoError::new())
+    }
+
+    pub fn verify(&self, digest: &Digest, public_key: &BlsPublicKey) -> Result<(), CryptoError> {
+        let key = public_key.load()?;
+        check(self.load()?.verify(false, &digest.0, DST, &[], &key, false))
+    }
+
+    /// Aggregates signatures (over the same digest) into a single signature.
+    pub fn aggregate<'a, I>(signatures: I) -> Result<Self, CryptoError>
+    where
+        I: IntoIterator<Item = &'a BlsSignature>,
+    {
+        let signatures = signatures
+            .into_iter()
+            .map(|x| x.load())
+            .collect::<Result<Vec<_>, _>>()?;
+        let signatures: Vec<_> = signatures.iter().collect();
+        let aggregate = blst_core::AggregateSignature::aggregate(&signatures, false)
+            .map_err(|_| CryptoError::new())?;
+        Ok(Self(aggregate.to_signature().compress()))
+    }
+
+    /// Verifies an aggregated signature of the holders of `public_keys` over the same digest. This
+    /// check does not protect against rogue keys: every key must have passed `verify_possession`.
+    pub fn verify_aggregate<'a, I>(
+        &self,
+        digest: &Digest,
+        public_keys: I,
+    ) -> Result<(), CryptoError>
+    where
+        I: IntoIterator<Item = &'a BlsPublicKey>,
+    {
+        let keys = public_keys
+            .into_iter()
+            .map(|x| x.load())
+            .collect::<Result<Vec<_>, _>>()?;
+        let keys: Vec<_> = keys.iter().collect();
+        check(
+            self.load()?
+                .fast_aggregate_verify(false, &digest.0, DST, &keys),
+        )
+    }
+}
+
+impl fmt::Debug for BlsSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", base64::encode(&self.0[..]))
+    }
+}
+
+impl Serialize for BlsSignature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsSignature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let array = bytes
+            .try_into()
+            .map_err(|_| de::Error::custom("Unexpected BLS signature length"))?;
+        Ok(Self(array))
+    }
+}
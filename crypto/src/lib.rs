@@ -1,7 +1,9 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
+use curve25519_dalek::edwards::CompressedEdwardsY;
 use ed25519_dalek as dalek;
 use ed25519_dalek::ed25519;
-use ed25519_dalek::Signer as _;
+use ed25519_dalek::{Digest as _, Signer as _};
 use rand::rngs::OsRng;
 use rand::{CryptoRng, RngCore};
 use serde::{de, ser, Deserialize, Serialize};
@@ -15,6 +17,14 @@ use tokio::sync::oneshot;
 #[path = "tests/crypto_tests.rs"]
 pub mod crypto_tests;
 
+mod bls;
+mod remote;
+mod threshold;
+
+pub use crate::bls::{BlsProofOfPossession, BlsPublicKey, BlsSecretKey, BlsSignature};
+pub use crate::remote::RemoteSigner;
+pub use crate::threshold::{deal, Ciphertext, DecryptionShare, KeyShare, ThresholdPublicKey};
+
 pub type CryptoError = ed25519::Error;
 
 /// Represents a hash digest (32 bytes).
@@ -77,6 +87,14 @@ impl PublicKey {
             .map_err(|_| base64::DecodeError::InvalidLength)?;
         Ok(Self(array))
     }
+
+    /// Returns the X25519 form of the key (for Diffie-Hellman key exchanges), or `None` if the key
+    /// is not a valid point.
+    pub fn to_x25519(&self) -> Option<[u8; 32]> {
+        CompressedEdwardsY(self.0)
+            .decompress()
+            .map(|x| x.to_montgomery().to_bytes())
+    }
 }
 
 impl fmt::Debug for PublicKey {
@@ -121,6 +139,29 @@ impl AsRef<[u8]> for PublicKey {
 pub struct SecretKey([u8; 64]);
 
 impl SecretKey {
+    /// Returns a copy of the key, for the components holding their own (such as the network layer
+    /// authenticating the node to its peers).
+    pub fn duplicate(&self) -> Self {
+        Self(self.0)
+    }
+
+    /// Returns the key encoded as a PKCS#8 document (the format the TLS libraries load keys from).
+    pub fn to_pkcs8(&self) -> Vec<u8> {
+        // The PKCS#8 (v1) header of an ed25519 key, followed by its 32-byte seed.
+        let mut document = vec![
+            0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22,
+            0x04, 0x20,
+        ];
+        document.extend_from_slice(&self.0[..32]);
+        document
+    }
+
+    /// Returns the X25519 form of the key, matching `PublicKey::to_x25519`.
+    pub fn to_x25519(&self) -> [u8; 32] {
+        let hash = dalek::Sha512::digest(&self.0[..32]);
+        hash[..32].try_into().expect("Unexpected hash length")
+    }
+
     pub fn encode_base64(&self) -> String {
         base64::encode(&self.0[..])
     }
@@ -168,9 +209,11 @@ pub fn generate_keypair<R>(csprng: &mut R) -> (PublicKey, SecretKey)
 where
     R: CryptoRng + RngCore,
 {
-    let keypair = dalek::Keypair::generate(csprng);
-    let public = PublicKey(keypair.public.to_bytes());
-    let secret = SecretKey(keypair.to_bytes());
+    let mut seed = [0u8; 32];
+    csprng.fill_bytes(&mut seed);
+    let keypair = dalek::SigningKey::from_bytes(&seed);
+    let public = PublicKey(keypair.verifying_key().to_bytes());
+    let secret = SecretKey(keypair.to_keypair_bytes());
     (public, secret)
 }
 
@@ -183,13 +226,20 @@ pub struct Signature {
 
 impl Signature {
     pub fn new(digest: &Digest, secret: &SecretKey) -> Self {
-        let keypair = dalek::Keypair::from_bytes(&secret.0).expect("Unable to load secret key");
+        let keypair =
+            dalek::SigningKey::from_keypair_bytes(&secret.0).expect("Unable to load secret key");
         let sig = keypair.sign(&digest.0).to_bytes();
         let part1 = sig[..32].try_into().expect("Unexpected signature length");
         let part2 = sig[32..64].try_into().expect("Unexpected signature length");
         Signature { part1, part2 }
     }
 
+    pub fn from_bytes(bytes: &[u8; 64]) -> Self {
+        let part1 = bytes[..32].try_into().expect("Unexpected signature length");
+        let part2 = bytes[32..].try_into().expect("Unexpected signature length");
+        Signature { part1, part2 }
+    }
+
     fn flatten(&self) -> [u8; 64] {
         [self.part1, self.part2]
             .concat()
@@ -198,8 +248,8 @@ impl Signature {
     }
 
     pub fn verify(&self, digest: &Digest, public_key: &PublicKey) -> Result<(), CryptoError> {
-        let signature = ed25519::signature::Signature::from_bytes(&self.flatten())?;
-        let key = dalek::PublicKey::from_bytes(&public_key.0)?;
+        let signature = dalek::Signature::from_bytes(&self.flatten());
+        let key = dalek::VerifyingKey::from_bytes(&public_key.0)?;
         key.verify_strict(&digest.0, &signature)
     }
 
@@ -209,30 +259,99 @@ impl Signature {
     {
         let mut messages: Vec<&[u8]> = Vec::new();
         let mut signatures: Vec<dalek::Signature> = Vec::new();
-        let mut keys: Vec<dalek::PublicKey> = Vec::new();
+        let mut keys: Vec<dalek::VerifyingKey> = Vec::new();
         for (key, sig) in votes.into_iter() {
             messages.push(&digest.0[..]);
-            signatures.push(ed25519::signature::Signature::from_bytes(&sig.flatten())?);
-            keys.push(dalek::PublicKey::from_bytes(&key.0)?);
+            signatures.push(dalek::Signature::from_bytes(&sig.flatten()));
+            keys.push(dalek::VerifyingKey::from_bytes(&key.0)?);
         }
         dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
     }
+
+    /// Verifies signatures over different digests in a single batch.
+    pub fn verify_many<'a, I>(items: I) -> Result<(), CryptoError>
+    where
+        I: IntoIterator<Item = (&'a Digest, &'a PublicKey, &'a Signature)>,
+    {
+        let mut messages: Vec<&[u8]> = Vec::new();
+        let mut signatures: Vec<dalek::Signature> = Vec::new();
+        let mut keys: Vec<dalek::VerifyingKey> = Vec::new();
+        for (digest, key, sig) in items.into_iter() {
+            messages.push(&digest.0[..]);
+            signatures.push(dalek::Signature::from_bytes(&sig.flatten()));
+            keys.push(dalek::VerifyingKey::from_bytes(&key.0)?);
+        }
+        dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
+    }
+}
+
+/// Requests handled by the `SignatureService`.
+enum SignatureRequest {
+    Ed25519(Digest, oneshot::Sender<Signature>),
+    Bls(Digest, oneshot::Sender<BlsSignature>),
+}
+
+/// Produces the signatures of the node. Implementations may hold the keys in memory or forward
+/// the digests to an external signer (so that the keys never enter the process).
+#[async_trait]
+pub trait Signer: Send + 'static {
+    /// Returns an ed25519 signature over the digest.
+    async fn sign(&mut self, digest: &Digest) -> Signature;
+
+    /// Returns a BLS signature over the digest.
+    async fn sign_bls(&mut self, digest: &Digest) -> BlsSignature;
+}
+
+/// Signs with keys held in memory.
+pub struct LocalSigner {
+    secret: SecretKey,
+    bls_secret: BlsSecretKey,
+}
+
+impl LocalSigner {
+    pub fn new(secret: SecretKey) -> Self {
+        let bls_secret = BlsSecretKey::derive(&secret);
+        Self { secret, bls_secret }
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    async fn sign(&mut self, digest: &Digest) -> Signature {
+        Signature::new(digest, &self.secret)
+    }
+
+    async fn sign_bls(&mut self, digest: &Digest) -> BlsSignature {
+        BlsSignature::new(digest, &self.bls_secret)
+    }
 }
 
-/// This service holds the node's private key. It takes digests as input and returns a signature
+/// This service drives the node's signer. It takes digests as input and returns a signature
 /// over the digest (through a oneshot channel).
 #[derive(Clone)]
 pub struct SignatureService {
-    channel: Sender<(Digest, oneshot::Sender<Signature>)>,
+    channel: Sender<SignatureRequest>,
 }
 
 impl SignatureService {
+    /// Signs with the node's secret key (held in memory).
     pub fn new(secret: SecretKey) -> Self {
-        let (tx, mut rx): (Sender<(_, oneshot::Sender<_>)>, _) = channel(100);
+        Self::with_signer(LocalSigner::new(secret))
+    }
+
+    /// Signs with the specified signer.
+    pub fn with_signer<S: Signer>(mut signer: S) -> Self {
+        let (tx, mut rx): (Sender<SignatureRequest>, _) = channel(100);
         tokio::spawn(async move {
-            while let Some((digest, sender)) = rx.recv().await {
-                let signature = Signature::new(&digest, &secret);
-                let _ = sender.send(signature);
+            while let Some(request) = rx.recv().await {
+                match request {
+                    SignatureRequest::Ed25519(digest, sender) => {
+                        let _ = sender.send(signer.sign(&digest).await);
+                    }
+                    SignatureRequest::Bls(digest, sender) => {
+                        let _ = sender.send(signer.sign_bls(&digest).await);
+                    }
+                }
             }
         });
         Self { channel: tx }
@@ -240,7 +359,26 @@ impl SignatureService {
 
     pub async fn request_signature(&mut self, digest: Digest) -> Signature {
         let (sender, receiver): (oneshot::Sender<_>, oneshot::Receiver<_>) = oneshot::channel();
-        if let Err(e) = self.channel.send((digest, sender)).await {
+        if let Err(e) = self
+            .channel
+            .send(SignatureRequest::Ed25519(digest, sender))
+            .await
+        {
+            panic!("Failed to send message Signature Service: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive signature from Signature Service")
+    }
+
+    /// Returns a BLS signature over the digest, using the key derived from the node's secret.
+    pub async fn request_bls_signature(&mut self, digest: Digest) -> BlsSignature {
+        let (sender, receiver): (oneshot::Sender<_>, oneshot::Receiver<_>) = oneshot::channel();
+        if let Err(e) = self
+            .channel
+            .send(SignatureRequest::Bls(digest, sender))
+            .await
+        {
             panic!("Failed to send message Signature Service: {}", e);
         }
         receiver
@@ -0,0 +1,112 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{BlsPublicKey, BlsSignature, Digest, PublicKey, Signature, Signer};
+use async_trait::async_trait;
+use log::warn;
+use std::convert::TryInto as _;
+use std::io;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::UnixStream;
+use tokio::time::{sleep, timeout, Duration};
+
+/// The maximum time (ms) we wait for the external signer to answer a request.
+const TIMEOUT: u64 = 5_000;
+
+/// The delay (ms) before the first retry of a failed request. It doubles after each failure, up
+/// to `MAX_RETRY_DELAY`.
+const RETRY_DELAY: u64 = 100;
+
+/// The maximum delay (ms) between two retries of a failed request.
+const MAX_RETRY_DELAY: u64 = 10_000;
+
+/// Forwards the digests to an external signer listening on a Unix socket, so that the keys of the
+/// node never enter the process. The protocol is a sequence of fixed-size requests and replies:
+/// each request is a one-byte tag (0 for ed25519, 1 for BLS) followed by the 32-byte digest; the
+/// signer replies with the 64-byte ed25519 signature or the 96-byte (compressed) BLS signature.
+pub struct RemoteSigner {
+    /// The path of the Unix socket of the external signer.
+    path: PathBuf,
+    /// The public key of the node, against which we check the ed25519 signatures of the signer.
+    name: PublicKey,
+    /// The BLS public key of the node (if any), against which we check the BLS signatures. We
+    /// cannot check the BLS signatures without it, so BLS signing is then refused.
+    bls_key: Option<BlsPublicKey>,
+    /// The connection to the external signer (if any).
+    stream: Option<UnixStream>,
+}
+
+impl RemoteSigner {
+    pub fn new(path: PathBuf, name: PublicKey, bls_key: Option<BlsPublicKey>) -> Self {
+        Self {
+            path,
+            name,
+            bls_key,
+            stream: None,
+        }
+    }
+
+    /// Sends a request to the external signer and fills `reply` with its answer.
+    async fn try_request(&mut self, tag: u8, digest: &Digest, reply: &mut [u8]) -> io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self.stream.insert(UnixStream::connect(&self.path).await?),
+        };
+        let request = [&[tag][..], &digest.0[..]].concat();
+        stream.write_all(&request).await?;
+        stream.read_exact(reply).await?;
+        Ok(())
+    }
+
+    /// Sends a request to the external signer until it replies with a valid signature,
+    /// reconnecting if the connection drops or stalls. We cannot make progress without
+    /// signatures: we keep retrying (with backoff) until the signer is back.
+    async fn request<F>(&mut self, tag: u8, digest: &Digest, reply: &mut [u8], valid: F)
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut delay = RETRY_DELAY;
+        loop {
+            let duration = Duration::from_millis(TIMEOUT);
+            let error = match timeout(duration, self.try_request(tag, digest, reply)).await {
+                Ok(Ok(())) if valid(reply) => return,
+                Ok(Ok(())) => "Invalid signature".to_string(),
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => "Request timed out".to_string(),
+            };
+            warn!(
+                "Failed to get a signature from the external signer at {:?}: {}",
+                self.path, error
+            );
+            self.stream = None;
+            sleep(Duration::from_millis(delay)).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    async fn sign(&mut self, digest: &Digest) -> Signature {
+        let name = self.name;
+        let valid = |x: &[u8]| {
+            let bytes = x.try_into().expect("Unexpected signature length");
+            Signature::from_bytes(bytes).verify(digest, &name).is_ok()
+        };
+        let mut reply = [0u8; 64];
+        self.request(0, digest, &mut reply, valid).await;
+        Signature::from_bytes(&reply)
+    }
+
+    async fn sign_bls(&mut self, digest: &Digest) -> BlsSignature {
+        let bls_key = self
+            .bls_key
+            .expect("Cannot check the BLS signatures of the external signer without a BLS key");
+        let valid = |x: &[u8]| {
+            let bytes = x.try_into().expect("Unexpected signature length");
+            BlsSignature(bytes).verify(digest, &bls_key).is_ok()
+        };
+        let mut reply = [0u8; 96];
+        self.request(1, digest, &mut reply, valid).await;
+        BlsSignature(reply)
+    }
+}
@@ -1,13 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use ed25519_dalek::Digest as _;
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::montgomery::MontgomeryPoint;
 use ed25519_dalek::Sha512;
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 
 impl Hash for &[u8] {
     fn digest(&self) -> Digest {
-        Digest(Sha512::digest(self).as_slice()[..32].try_into().unwrap())
+        Digest(Sha512::digest(self)[..32].try_into().unwrap())
     }
 }
 
@@ -37,6 +38,20 @@ fn import_export_public_key() {
     assert_eq!(import.unwrap(), public_key);
 }
 
+#[test]
+fn agree_on_x25519_keys() {
+    // The X25519 forms of two key pairs agree on the same Diffie-Hellman secret.
+    let keys = keys();
+    let (a, b) = (&keys[0], &keys[1]);
+    let shared_a = MontgomeryPoint(b.0.to_x25519().unwrap()).mul_clamped(a.1.to_x25519());
+    let shared_b = MontgomeryPoint(a.0.to_x25519().unwrap()).mul_clamped(b.1.to_x25519());
+    assert_eq!(shared_a, shared_b);
+    assert_eq!(
+        X25519_BASEPOINT.mul_clamped(a.1.to_x25519()).to_bytes(),
+        a.0.to_x25519().unwrap()
+    );
+}
+
 #[test]
 fn import_export_secret_key() {
     let (_, secret_key) = keys().pop().unwrap();
@@ -114,6 +129,82 @@ fn verify_invalid_batch() {
     assert!(Signature::verify_batch(&digest, &signatures).is_err());
 }
 
+#[test]
+fn verify_many() {
+    // Make signatures over different digests.
+    let messages: Vec<&[u8]> = vec![b"Hello", b"world", b"!"];
+    let digests: Vec<_> = messages.iter().map(|x| x.digest()).collect();
+    let keys = keys();
+    let mut items: Vec<_> = digests
+        .iter()
+        .zip(keys.iter())
+        .map(|(digest, (public_key, secret_key))| {
+            (digest, public_key, Signature::new(digest, secret_key))
+        })
+        .collect();
+
+    // Verify the batch.
+    let batch = items.iter().map(|(x, y, z)| (*x, *y, z));
+    assert!(Signature::verify_many(batch).is_ok());
+
+    // Swap two signatures and ensure the batch is rejected.
+    let signature = items[0].2.clone();
+    items[0].2 = items[1].2.clone();
+    items[1].2 = signature;
+    let batch = items.iter().map(|(x, y, z)| (*x, *y, z));
+    assert!(Signature::verify_many(batch).is_err());
+}
+
+#[test]
+fn verify_bls_aggregate() {
+    // Make BLS signatures over the same digest.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let secrets: Vec<_> = keys()
+        .iter()
+        .map(|(_, secret)| BlsSecretKey::derive(secret))
+        .collect();
+    let public_keys: Vec<_> = secrets.iter().map(|x| x.public()).collect();
+    let signatures: Vec<_> = secrets
+        .iter()
+        .map(|x| BlsSignature::new(&digest, x))
+        .collect();
+    assert!(signatures[0].verify(&digest, &public_keys[0]).is_ok());
+
+    // Aggregate the first three signatures and verify them at once.
+    let aggregate = BlsSignature::aggregate(&signatures[..3]).unwrap();
+    assert!(aggregate
+        .verify_aggregate(&digest, &public_keys[..3])
+        .is_ok());
+
+    // The aggregate does not verify against a different set of signers or digest.
+    assert!(aggregate
+        .verify_aggregate(&digest, &public_keys[1..])
+        .is_err());
+    let other: &[u8] = b"Bye, world!";
+    let result = aggregate.verify_aggregate(&other.digest(), &public_keys[..3]);
+    assert!(result.is_err());
+}
+
+#[test]
+fn verify_bls_possession() {
+    let secrets: Vec<_> = keys()
+        .iter()
+        .map(|(_, secret)| BlsSecretKey::derive(secret))
+        .collect();
+    let proof = secrets[0].prove_possession();
+    assert!(secrets[0].public().verify_possession(&proof).is_ok());
+
+    // The proof does not verify for another key.
+    assert!(secrets[1].public().verify_possession(&proof).is_err());
+
+    // A signature over the bytes of the key does not pass as a proof of possession.
+    let public = secrets[0].public();
+    let signature = BlsSignature::new(&Digest(public.0[..32].try_into().unwrap()), &secrets[0]);
+    let forged = BlsProofOfPossession(signature.0);
+    assert!(public.verify_possession(&forged).is_err());
+}
+
 #[tokio::test]
 async fn signature_service() {
     // Get a keypair.
@@ -129,4 +220,133 @@ async fn signature_service() {
 
     // Verify the signature we received.
     assert!(signature.verify(&digest, &public_key).is_ok());
+
+    // The BLS signature uses the key derived from the same secret.
+    let bls_signature = service.request_bls_signature(digest.clone()).await;
+    let bls_key = BlsSecretKey::derive(&keys().pop().unwrap().1).public();
+    assert!(bls_signature.verify(&digest, &bls_key).is_ok());
+}
+
+// Spawns an external signer holding the keys on a Unix socket. It replies with garbage (and drops
+// the connection) to the first `faulty` connections.
+fn spawn_external_signer(path: &std::path::Path, faulty: usize) {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio::net::UnixListener;
+
+    let listener = UnixListener::bind(path).unwrap();
+    tokio::spawn(async move {
+        let mut signer = LocalSigner::new(keys().pop().unwrap().1);
+        for i in 0.. {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 33];
+            while stream.read_exact(&mut request).await.is_ok() {
+                let digest = Digest(request[1..].try_into().unwrap());
+                let reply = match request[0] {
+                    0 => signer.sign(&digest).await.flatten().to_vec(),
+                    _ => signer.sign_bls(&digest).await.0.to_vec(),
+                };
+                if i < faulty {
+                    let _ = stream.write_all(&vec![0u8; reply.len()]).await;
+                    break;
+                }
+                stream.write_all(&reply).await.unwrap();
+            }
+        }
+    });
+}
+
+#[tokio::test]
+async fn remote_signature_service() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("signer.sock");
+    spawn_external_signer(&path, 0);
+
+    // Spawn the signature service forwarding to the external signer.
+    let (public_key, secret_key) = keys().pop().unwrap();
+    let bls_key = BlsSecretKey::derive(&secret_key).public();
+    let signer = RemoteSigner::new(path, public_key, Some(bls_key));
+    let mut service = SignatureService::with_signer(signer);
+
+    // Ensure the signatures we receive verify against the keys of the signer.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let signature = service.request_signature(digest.clone()).await;
+    assert!(signature.verify(&digest, &public_key).is_ok());
+
+    let bls_signature = service.request_bls_signature(digest.clone()).await;
+    assert!(bls_signature.verify(&digest, &bls_key).is_ok());
+}
+
+#[tokio::test]
+async fn remote_signer_retries_invalid_signatures() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("signer.sock");
+    spawn_external_signer(&path, 4);
+
+    // Spawn the signature service forwarding to the external signer.
+    let (public_key, secret_key) = keys().pop().unwrap();
+    let bls_key = BlsSecretKey::derive(&secret_key).public();
+    let signer = RemoteSigner::new(path, public_key, Some(bls_key));
+    let mut service = SignatureService::with_signer(signer);
+
+    // Ensure we discard the invalid signatures and keep asking until we get valid ones.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let signature = service.request_signature(digest.clone()).await;
+    assert!(signature.verify(&digest, &public_key).is_ok());
+
+    let bls_signature = service.request_bls_signature(digest.clone()).await;
+    assert!(bls_signature.verify(&digest, &bls_key).is_ok());
+}
+
+#[test]
+fn threshold_decrypt() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (public, shares) = deal(3, 4, &mut rng);
+    let message = b"Hello, world!".repeat(10);
+
+    // Encrypt a message to the committee.
+    let ciphertext = Ciphertext::new(&public, &message, &mut rng).unwrap();
+    assert!(ciphertext.verify().is_ok());
+
+    // Decrypt the message from any 3 shares.
+    let decryptions: Vec<_> = shares
+        .iter()
+        .skip(1)
+        .map(|x| x.decrypt(&ciphertext, &mut rng).unwrap())
+        .collect();
+    for x in &decryptions {
+        assert!(public.verify_share(&ciphertext, x).is_ok());
+    }
+    assert_eq!(public.combine(&ciphertext, &decryptions).unwrap(), message);
+}
+
+#[test]
+fn threshold_decrypt_insufficient_shares() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (public, shares) = deal(3, 4, &mut rng);
+    let ciphertext = Ciphertext::new(&public, b"Hello, world!", &mut rng).unwrap();
+
+    // Two valid shares, a duplicate, and a share claiming the wrong index.
+    let mut decryptions: Vec<_> = shares
+        .iter()
+        .take(2)
+        .map(|x| x.decrypt(&ciphertext, &mut rng).unwrap())
+        .collect();
+    decryptions.push(decryptions[0].clone());
+    let mut forged = shares[2].decrypt(&ciphertext, &mut rng).unwrap();
+    forged.index = 4;
+    assert!(public.verify_share(&ciphertext, &forged).is_err());
+    decryptions.push(forged);
+
+    assert!(public.combine(&ciphertext, &decryptions).is_err());
+}
+
+#[test]
+fn verify_mauled_ciphertext() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (public, _) = deal(3, 4, &mut rng);
+    let mut ciphertext = Ciphertext::new(&public, b"Hello, world!", &mut rng).unwrap();
+    ciphertext.payload[0] ^= 1;
+    assert!(ciphertext.verify().is_err());
 }
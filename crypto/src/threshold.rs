@@ -0,0 +1,290 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::CryptoError;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT as G;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::{Digest as _, Sha512};
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryInto as _;
+
+/// Domain separation of the hashes of the scheme.
+const KEY_DOMAIN: &[u8] = b"narwhal-threshold-key";
+const TAG_DOMAIN: &[u8] = b"narwhal-threshold-tag";
+const POK_DOMAIN: &[u8] = b"narwhal-threshold-pok";
+const DLEQ_DOMAIN: &[u8] = b"narwhal-threshold-dleq";
+
+fn hash_to_scalar(domain: &[u8], parts: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new();
+    hasher.update(domain);
+    parts.iter().for_each(|x| hasher.update(x));
+    Scalar::from_hash(hasher)
+}
+
+/// Samples a uniformly random scalar.
+fn random_scalar<R: RngCore + CryptoRng>(rng: &mut R) -> Scalar {
+    let mut bytes = [0u8; 64];
+    rng.fill_bytes(&mut bytes);
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+fn hash(domain: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha512::new();
+    hasher.update(domain);
+    parts.iter().for_each(|x| hasher.update(x));
+    hasher.finalize()[..32].try_into().unwrap()
+}
+
+fn load(point: &[u8; 32]) -> Result<RistrettoPoint, CryptoError> {
+    CompressedRistretto(*point)
+        .decompress()
+        .ok_or_else(CryptoError::new)
+}
+
+fn load_scalar(scalar: &[u8; 32]) -> Result<Scalar, CryptoError> {
+    Option::from(Scalar::from_canonical_bytes(*scalar)).ok_or_else(CryptoError::new)
+}
+
+/// XORs the data with a keystream derived from the key (SHA-512 in counter mode).
+fn apply_keystream(key: &[u8; 32], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let block = Sha512::new()
+            .chain_update(key)
+            .chain_update((i as u64).to_le_bytes())
+            .finalize();
+        chunk.iter_mut().zip(block).for_each(|(x, y)| *x ^= y);
+    }
+}
+
+/// The public key of a committee holding a secret key split into shares, any `threshold` of which
+/// decrypt the messages encrypted to the committee (threshold ElGamal over Ristretto).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThresholdPublicKey {
+    /// The number of decryption shares needed to decrypt a message.
+    pub threshold: usize,
+    /// The public key of the committee (compressed point).
+    pub key: [u8; 32],
+    /// The public key of each share, by index (share `i` has index `i + 1`).
+    pub shares: Vec<[u8; 32]>,
+}
+
+/// A share of the secret key of the committee.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyShare {
+    /// The index of the share (starting from 1).
+    pub index: u32,
+    /// The secret share (canonical scalar).
+    secret: [u8; 32],
+}
+
+/// A message encrypted to the committee. Anyone can check that its sender knows the randomness of
+/// the encryption (so that a ciphertext cannot be re-encrypted or mauled into another one).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ciphertext {
+    /// The ephemeral public key of the encryption.
+    pub ephemeral: [u8; 32],
+    /// The encrypted message.
+    pub payload: Vec<u8>,
+    /// Authenticates the message under the decryption key.
+    pub tag: [u8; 32],
+    /// A Schnorr proof of knowledge of the randomness of the ephemeral key (challenge, response).
+    pub proof: ([u8; 32], [u8; 32]),
+}
+
+/// The share of the decryption of a ciphertext by the holder of a key share, with a proof that it
+/// used its share (a Chaum-Pedersen proof of discrete logarithm equality).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecryptionShare {
+    /// The index of the key share.
+    pub index: u32,
+    /// The ephemeral key of the ciphertext multiplied by the key share.
+    pub share: [u8; 32],
+    /// The proof of correct decryption (challenge, response).
+    pub proof: ([u8; 32], [u8; 32]),
+}
+
+/// Splits a new secret key into `holders` shares, any `threshold` of which decrypt. The dealer
+/// learns the secret key: it should be trusted (or the shares generated by a distributed protocol).
+pub fn deal<R>(threshold: usize, holders: usize, rng: &mut R) -> (ThresholdPublicKey, Vec<KeyShare>)
+where
+    R: RngCore + CryptoRng,
+{
+    assert!(threshold > 0 && threshold <= holders);
+    let coefficients: Vec<_> = (0..threshold).map(|_| random_scalar(rng)).collect();
+    let evaluate = |x: u32| {
+        let x = Scalar::from(x as u64);
+        coefficients
+            .iter()
+            .rev()
+            .fold(Scalar::ZERO, |y, a| y * x + a)
+    };
+
+    let shares: Vec<_> = (1..=holders as u32)
+        .map(|index| KeyShare {
+            index,
+            secret: evaluate(index).to_bytes(),
+        })
+        .collect();
+    let public = ThresholdPublicKey {
+        threshold,
+        key: (coefficients[0] * G).compress().to_bytes(),
+        shares: shares
+            .iter()
+            .map(|x| {
+                (Scalar::from_bytes_mod_order(x.secret) * G)
+                    .compress()
+                    .to_bytes()
+            })
+            .collect(),
+    };
+    (public, shares)
+}
+
+impl Ciphertext {
+    /// Encrypts a message to the committee.
+    pub fn new<R>(
+        key: &ThresholdPublicKey,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Result<Self, CryptoError>
+    where
+        R: RngCore + CryptoRng,
+    {
+        let r = random_scalar(rng);
+        let ephemeral = (r * G).compress().to_bytes();
+        let secret = (r * load(&key.key)?).compress().to_bytes();
+
+        let mut payload = message.to_vec();
+        apply_keystream(&hash(KEY_DOMAIN, &[&secret]), &mut payload);
+        let tag = hash(TAG_DOMAIN, &[&secret, &payload]);
+
+        let w = random_scalar(rng);
+        let commitment = (w * G).compress().to_bytes();
+        let c = hash_to_scalar(POK_DOMAIN, &[&ephemeral, &commitment, &payload, &tag]);
+        let z = w + c * r;
+        Ok(Self {
+            ephemeral,
+            payload,
+            tag,
+            proof: (c.to_bytes(), z.to_bytes()),
+        })
+    }
+
+    /// Checks that the sender of the ciphertext knows the randomness of its encryption.
+    pub fn verify(&self) -> Result<(), CryptoError> {
+        let u = load(&self.ephemeral)?;
+        let c = load_scalar(&self.proof.0)?;
+        let z = load_scalar(&self.proof.1)?;
+        let commitment = (z * G - c * u).compress().to_bytes();
+        let expected = hash_to_scalar(
+            POK_DOMAIN,
+            &[&self.ephemeral, &commitment, &self.payload, &self.tag],
+        );
+        match expected == c {
+            true => Ok(()),
+            false => Err(CryptoError::new()),
+        }
+    }
+}
+
+impl KeyShare {
+    /// Computes our share of the decryption of a (verified) ciphertext.
+    pub fn decrypt<R>(
+        &self,
+        ciphertext: &Ciphertext,
+        rng: &mut R,
+    ) -> Result<DecryptionShare, CryptoError>
+    where
+        R: RngCore + CryptoRng,
+    {
+        let x = load_scalar(&self.secret)?;
+        let u = load(&ciphertext.ephemeral)?;
+        let share = (x * u).compress().to_bytes();
+        let public = (x * G).compress().to_bytes();
+
+        let w = random_scalar(rng);
+        let a = (w * G).compress().to_bytes();
+        let b = (w * u).compress().to_bytes();
+        let c = hash_to_scalar(
+            DLEQ_DOMAIN,
+            &[&ciphertext.ephemeral, &public, &share, &a, &b],
+        );
+        let z = w + c * x;
+        Ok(DecryptionShare {
+            index: self.index,
+            share,
+            proof: (c.to_bytes(), z.to_bytes()),
+        })
+    }
+}
+
+impl ThresholdPublicKey {
+    /// Checks that a decryption share of the ciphertext was computed with the key share of its index.
+    pub fn verify_share(
+        &self,
+        ciphertext: &Ciphertext,
+        share: &DecryptionShare,
+    ) -> Result<(), CryptoError> {
+        let public = share
+            .index
+            .checked_sub(1)
+            .and_then(|i| self.shares.get(i as usize))
+            .ok_or_else(CryptoError::new)?;
+        let u = load(&ciphertext.ephemeral)?;
+        let d = load(&share.share)?;
+        let c = load_scalar(&share.proof.0)?;
+        let z = load_scalar(&share.proof.1)?;
+        let a = (z * G - c * load(public)?).compress().to_bytes();
+        let b = (z * u - c * d).compress().to_bytes();
+        let expected = hash_to_scalar(
+            DLEQ_DOMAIN,
+            &[&ciphertext.ephemeral, public, &share.share, &a, &b],
+        );
+        match expected == c {
+            true => Ok(()),
+            false => Err(CryptoError::new()),
+        }
+    }
+
+    /// Decrypts a ciphertext from the decryption shares of at least `threshold` distinct key shares.
+    /// Invalid shares are ignored.
+    pub fn combine(
+        &self,
+        ciphertext: &Ciphertext,
+        shares: &[DecryptionShare],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let valid: Vec<_> = shares
+            .iter()
+            .filter(|x| self.verify_share(ciphertext, x).is_ok())
+            .map(|x| (x.index, x))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .take(self.threshold)
+            .collect();
+        if valid.len() < self.threshold {
+            return Err(CryptoError::new());
+        }
+
+        // Interpolate the shares at 0 (Lagrange).
+        let mut secret = RistrettoPoint::default();
+        for (i, share) in &valid {
+            let xi = Scalar::from(*i as u64);
+            let lambda = valid
+                .iter()
+                .map(|(j, _)| j)
+                .filter(|j| *j != i)
+                .map(|j| Scalar::from(*j as u64))
+                .fold(Scalar::ONE, |acc, xj| acc * xj * (xj - xi).invert());
+            secret += lambda * load(&share.share)?;
+        }
+        let secret = secret.compress().to_bytes();
+
+        if hash(TAG_DOMAIN, &[&secret, &ciphertext.payload]) != ciphertext.tag {
+            return Err(CryptoError::new());
+        }
+        let mut message = ciphertext.payload.clone();
+        apply_keystream(&hash(KEY_DOMAIN, &[&secret]), &mut message);
+        Ok(message)
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::Bytes;
+use std::io;
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/compression_tests.rs"]
+pub mod compression_tests;
+
+/// The messages smaller than this size are not worth compressing (in bytes).
+const MIN_COMPRESSED_SIZE: usize = 256;
+
+/// The zstd compression level (0 selects the default level of the library).
+const ZSTD_LEVEL: i32 = 0;
+
+/// How a connection compresses the messages it carries, as agreed by both ends at handshake.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Messages are sent as they are.
+    #[default]
+    None,
+    /// Messages are compressed with snappy (fast, moderate ratio).
+    Snappy,
+    /// Messages are compressed with zstd (slower, better ratio).
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the byte identifying the compression on the wire.
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Snappy => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    /// Returns the compression identified by the byte, if any.
+    pub(crate) fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::None),
+            1 => Some(Self::Snappy),
+            2 => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum DecodeError {
+    #[error("Message decompresses to {0} B, more than the maximum frame size ({1} B)")]
+    TooLarge(usize, usize),
+
+    #[error("Invalid compressed message: {0}")]
+    Invalid(io::Error),
+}
+
+/// Encodes a message for a connection compressing its messages as specified. Each message of a
+/// compressing connection starts with the tag of its compression: small messages, and those that
+/// do not shrink, are sent as they are.
+pub(crate) fn encode(compression: Compression, data: &Bytes) -> Bytes {
+    if compression == Compression::None {
+        return data.clone();
+    }
+    let compressed = match compression {
+        _ if data.len() < MIN_COMPRESSED_SIZE => None,
+        Compression::None => None,
+        Compression::Snappy => snap::raw::Encoder::new().compress_vec(data).ok(),
+        Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok(),
+    };
+    match compressed {
+        Some(x) if x.len() < data.len() => Bytes::from([&[compression.tag()], &x[..]].concat()),
+        _ => Bytes::from([&[Compression::None.tag()], &data[..]].concat()),
+    }
+}
+
+/// Decodes a message received over a compressing connection, rejecting the messages that
+/// decompress to more than the specified size. The messages sent as they are share the buffer of
+/// their frame.
+pub(crate) fn decode(frame: Bytes, max_size: usize) -> Result<Bytes, DecodeError> {
+    let invalid = |e: Box<dyn std::error::Error + Send + Sync>| {
+        DecodeError::Invalid(io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+    let (tag, payload) = frame
+        .split_first()
+        .ok_or_else(|| invalid("empty frame".into()))?;
+    match Compression::from_tag(*tag) {
+        Some(Compression::None) => Ok(frame.slice(1..)),
+        Some(Compression::Snappy) => {
+            let size = snap::raw::decompress_len(payload).map_err(|e| invalid(e.into()))?;
+            if size > max_size {
+                return Err(DecodeError::TooLarge(size, max_size));
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(payload)
+                .map(Bytes::from)
+                .map_err(|e| invalid(e.into()))
+        }
+        Some(Compression::Zstd) => {
+            let size = match zstd::zstd_safe::get_frame_content_size(payload) {
+                Ok(Some(size)) => size as usize,
+                _ => return Err(invalid("unknown decompressed size".into())),
+            };
+            if size > max_size {
+                return Err(DecodeError::TooLarge(size, max_size));
+            }
+            zstd::bulk::decompress(payload, size)
+                .map(Bytes::from)
+                .map_err(DecodeError::Invalid)
+        }
+        None => Err(invalid(format!("unknown compression {}", tag).into())),
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receiver::Connection;
+use crate::relay;
+use crate::resolver::Resolver;
+use crate::security::{SecureChannels, Security};
+use crate::transport::{TcpTransport, Transport};
+use crate::version::Features;
+use crypto::{PublicKey, SecretKey};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// The environment the senders and receivers of a node share: the transport over which they reach
+/// their peers, the hostnames of the peers, the relays through which they reach them, the identity
+/// and features of the node, and how it secures its connections. Each node gets its own context
+/// (the default one runs over plaintext TCP, without hostnames, relays nor features), so that
+/// several nodes may run in the same process. Cloning the context shares it.
+#[derive(Clone)]
+pub struct NetworkContext {
+    /// The transport over which we reach our peers.
+    transport: Arc<dyn Transport>,
+    /// The transport over which the clients reach us (the same, unless the nodes talk over a
+    /// transport of their own).
+    client_transport: Arc<dyn Transport>,
+    /// Resolves the hostnames of the peers known by hostname.
+    resolver: Resolver,
+    /// The relays through which we connect to the peers identified by each address.
+    relays: Arc<BTreeMap<SocketAddr, Vec<SocketAddr>>>,
+    /// The public and secret keys of the node (if set), authenticating it to its relays.
+    identity: Option<Arc<(PublicKey, SecretKey)>>,
+    /// The features the node enables, announced over all its connections.
+    features: Features,
+    /// Secures the connections between the nodes (if set).
+    secure: Option<SecureChannels>,
+}
+
+impl Default for NetworkContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkContext {
+    pub fn new() -> Self {
+        let transport: Arc<dyn Transport> = Arc::new(TcpTransport);
+        Self {
+            transport: transport.clone(),
+            client_transport: transport,
+            resolver: Resolver::default(),
+            relays: Arc::default(),
+            identity: None,
+            features: Features::NONE,
+            secure: None,
+        }
+    }
+
+    /// Returns a context reaching the peers over the specified transport.
+    pub fn with_transport(self, transport: impl Transport) -> Self {
+        let transport: Arc<dyn Transport> = Arc::new(transport);
+        Self {
+            transport: transport.clone(),
+            client_transport: transport,
+            ..self
+        }
+    }
+
+    /// Returns a context reaching the other nodes over the specified transport (for instance
+    /// `QuicTransport`), while the clients keep reaching us over the current one.
+    pub fn with_peer_transport(self, transport: impl Transport) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            ..self
+        }
+    }
+
+    /// Returns a context reaching the peers identified by each address through the specified
+    /// hostname (written as `host:port`), which we re-resolve when (re)connecting to the peer.
+    pub fn with_hostnames(self, hostnames: BTreeMap<SocketAddr, String>) -> Self {
+        Self {
+            resolver: Resolver::new(hostnames),
+            ..self
+        }
+    }
+
+    /// Returns a context routing the connections to the peer identified by each address through
+    /// the specified relays (trying them in random order), so that the peer does not learn our IP
+    /// address. Our receivers also learn the address of the peers whose connections the relays
+    /// forward to us.
+    pub fn with_relays(self, relays: BTreeMap<SocketAddr, Vec<SocketAddr>>) -> Self {
+        Self {
+            relays: Arc::new(relays),
+            ..self
+        }
+    }
+
+    /// Returns a context authenticating the node with the specified keys (for instance to the
+    /// relays, which only serve their validator).
+    pub fn with_identity(self, name: PublicKey, secret: SecretKey) -> Self {
+        Self {
+            identity: Some(Arc::new((name, secret))),
+            ..self
+        }
+    }
+
+    /// Returns a context announcing the specified features to the peers.
+    pub fn with_features(self, features: Features) -> Self {
+        Self { features, ..self }
+    }
+
+    /// Returns a context securing the connections between the nodes as specified, authenticating
+    /// each node by the key the committee lists for it: we only connect to the node listed at each
+    /// of the specified addresses (`peers`), and only accept the connections of the listed nodes.
+    /// Requires the identity of the node (see `with_identity`).
+    pub fn with_security(
+        self,
+        security: Security,
+        peers: BTreeMap<SocketAddr, PublicKey>,
+    ) -> io::Result<Self> {
+        let secure = match self.identity() {
+            Some((name, secret)) => SecureChannels::new(security, name, secret, peers)?,
+            None if security == Security::Plaintext => None,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Securing the connections requires the identity of the node",
+                ))
+            }
+        };
+        Ok(Self { secure, ..self })
+    }
+
+    /// Returns the context of the receivers serving clients, which connect without authenticating
+    /// (over the transport of the clients).
+    pub fn for_clients(&self) -> Self {
+        Self {
+            transport: self.client_transport.clone(),
+            secure: None,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the transport over which we reach our peers.
+    pub(crate) fn transport(&self) -> &dyn Transport {
+        self.transport.as_ref()
+    }
+
+    /// Opens a connection to the receiver bound to the specified address.
+    pub(crate) async fn connect(&self, address: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        self.transport.connect(address).await
+    }
+
+    /// Opens a connection to the peer identified by the specified address (which resolved as
+    /// specified), through its relays (if any), and secures it (if we secure our connections).
+    pub(crate) async fn open(
+        &self,
+        address: SocketAddr,
+        resolved: io::Result<SocketAddr>,
+    ) -> io::Result<Box<dyn Connection>> {
+        let connection = relay::connect(self, address, resolved).await?;
+        match &self.secure {
+            Some(secure) => secure.connect(connection, address).await,
+            None => Ok(connection),
+        }
+    }
+
+    /// Secures a connection we accepted (if we secure our connections), returning it along with
+    /// the node it comes from.
+    pub(crate) async fn accept(
+        &self,
+        connection: Box<dyn Connection>,
+    ) -> io::Result<(Box<dyn Connection>, Option<PublicKey>)> {
+        match &self.secure {
+            Some(secure) => {
+                let (connection, name) = secure.accept(connection).await?;
+                Ok((connection, Some(name)))
+            }
+            None => Ok((connection, None)),
+        }
+    }
+
+    /// Returns the resolver of the hostnames of our peers.
+    pub(crate) fn resolver(&self) -> &Resolver {
+        &self.resolver
+    }
+
+    /// Returns the relays through which we connect to the peer identified by the specified
+    /// address (if any).
+    pub(crate) fn relays(&self, address: &SocketAddr) -> Option<&Vec<SocketAddr>> {
+        self.relays.get(address)
+    }
+
+    /// Whether the peer (as described by the `Listener`) is one of our relays, which forward us
+    /// the connections of the other nodes.
+    pub(crate) fn is_relay(&self, peer: &str) -> bool {
+        let ip = match peer.parse::<SocketAddr>() {
+            Ok(x) => x.ip(),
+            Err(_) => return false,
+        };
+        self.relays
+            .values()
+            .flatten()
+            .any(|x| x.ip() == ip && !ip.is_unspecified())
+    }
+
+    /// Returns the features the node announces to its peers.
+    pub(crate) fn features(&self) -> Features {
+        self.features
+    }
+
+    /// Returns the public and secret keys of the node (if set).
+    pub(crate) fn identity(&self) -> Option<&(PublicKey, SecretKey)> {
+        self.identity.as_deref()
+    }
+}
@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::version::Features;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use thiserror::Error;
@@ -6,7 +7,7 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum NetworkError {
     #[error("Failed to connect to {0} (retry {1}): {2}")]
-    FailedToConnect(SocketAddr, u16, std::io::Error),
+    FailedToConnect(SocketAddr, u32, std::io::Error),
 
     #[error("Failed to accept connection: {0}")]
     FailedToListen(std::io::Error),
@@ -20,6 +21,30 @@ pub enum NetworkError {
     #[error("Failed to receive ACK from {0}")]
     FailedToReceiveAck(SocketAddr),
 
+    #[error("Peer {0} stopped answering our keep-alive probes")]
+    KeepAliveTimeout(SocketAddr),
+
+    #[error("Message to {0} is {1} B, larger than the maximum frame size ({2} B)")]
+    FrameTooLarge(SocketAddr, usize, usize),
+
+    #[error("Peer {0} rejected our message as larger than the frames it accepts")]
+    FrameRejected(SocketAddr),
+
+    #[error("Closing the idle connection to {0}")]
+    IdleConnection(SocketAddr),
+
+    #[error("Peer {0} moved to {1}")]
+    AddressChanged(SocketAddr, SocketAddr),
+
     #[error("Receive unexpected ACK from {0}")]
     UnexpectedAck(SocketAddr),
+
+    #[error("Failed to agree on a protocol version with {0}")]
+    FailedHandshake(SocketAddr),
+
+    #[error("Peer {0} speaks protocol version {1}, which we no longer support")]
+    IncompatibleVersion(SocketAddr, u16),
+
+    #[error("Peer {0} (node version {1}) requires the features {2}, which we do not enable")]
+    MissingFeatures(SocketAddr, String, Features),
 }
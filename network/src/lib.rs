@@ -1,13 +1,47 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod compression;
+mod context;
 mod error;
+mod limiter;
+mod memory;
+mod metrics;
+mod noise;
+mod queue;
+mod quic;
 mod receiver;
+mod relay;
 mod reliable_sender;
+mod resolver;
+mod rpc;
+mod security;
+mod sequence;
 mod simple_sender;
+mod transport;
+mod version;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 pub mod common;
 
-pub use crate::receiver::{MessageHandler, Receiver, Writer};
-pub use crate::reliable_sender::{CancelHandler, ReliableSender};
+pub use crate::compression::Compression;
+pub use crate::context::NetworkContext;
+pub use crate::limiter::{FrameQuota, InboundLimiter, TokenBucket};
+pub use crate::memory::{LinkConditions, MemoryNetwork};
+pub use crate::metrics::NetworkMetrics;
+pub use crate::queue::{Priority, QueueLimits, QueuePolicy};
+pub use crate::quic::QuicTransport;
+pub use crate::receiver::{Acceptor, Connection, Listener, MessageHandler, Peer, Receiver, Writer};
+pub use crate::relay::Relay;
+pub use crate::reliable_sender::{CancelHandler, PeerHealth, ReliableSender, RetryPolicy};
+pub use crate::rpc::{PendingResponse, RpcClient, RpcError, RpcHandler, RpcServer};
+pub use crate::security::Security;
 pub use crate::simple_sender::SimpleSender;
+pub use crate::transport::{TcpTransport, Transport};
+pub use crate::version::{
+    accept_handshake, codec, compression_frame, features_frame, handshake, hello, negotiate,
+    negotiate_compression, parse_compression_frame, parse_features_frame, parse_hello,
+    parse_sequence_frame, sequence_frame, Features, PeerFeatures, ProtocolVersion, COMPRESS,
+    COMPRESSION_VERSION, FEATURES, FEATURES_VERSION, FRAME_OVERHEAD, FRAME_TOO_LARGE,
+    IDLE_TIMEOUT_VERSION, KEEP_ALIVE_VERSION, MAX_FRAME_SIZE, MIN_PROTOCOL_VERSION, NODE_VERSION,
+    PING, PROTOCOL_VERSION, REPLAY_PROTECTION_VERSION, SEQUENCE,
+};
@@ -0,0 +1,177 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::PublicKey;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+#[cfg(test)]
+#[path = "tests/limiter_tests.rs"]
+pub mod limiter_tests;
+
+/// The number of nodes above which we start forgetting the nodes that are not currently limited.
+const MAX_TRACKED_PEERS: usize = 10_000;
+
+/// A token bucket refilled at a constant rate. It holds at most one second worth of tokens.
+pub struct TokenBucket {
+    /// The number of tokens added every second.
+    rate: u64,
+    /// The number of tokens the bucket holds when full (in thousandths of token).
+    capacity: u64,
+    /// The number of tokens currently available (in thousandths of token).
+    millis: u64,
+    /// The last time the bucket was refilled.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        let capacity = rate.saturating_mul(1_000);
+        Self {
+            rate,
+            capacity,
+            millis: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_millis() as u64;
+        if elapsed > 0 {
+            let refill = elapsed.saturating_mul(self.rate);
+            self.millis = min(self.millis.saturating_add(refill), self.capacity);
+            self.last_refill = now;
+        }
+    }
+
+    /// Returns `true` if the bucket is full, that is, if it is no different from a new bucket.
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.millis == self.capacity
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        self.refill();
+        if self.millis < 1_000 {
+            return false;
+        }
+        self.millis -= 1_000;
+        true
+    }
+
+    /// Returns the time until the bucket holds a token again.
+    pub fn delay(&mut self) -> Duration {
+        self.refill();
+        let missing = 1_000u64.saturating_sub(self.millis);
+        Duration::from_millis(missing.div_ceil(self.rate.max(1)))
+    }
+}
+
+/// Limits the rate at which each peer sends us frames. We identify the peers by the node their
+/// connections authenticated (whatever the number of their connections), and limit each of the
+/// other connections on its own (their IP address may be that of a relay or a NAT shared by many
+/// peers). The receivers check it before reading each frame: a peer exceeding its rate has its
+/// connections paused, so that its flood stays in the socket buffers rather than costing us
+/// allocations and decoding. Cloning the limiter shares it.
+#[derive(Clone)]
+pub struct InboundLimiter {
+    /// The maximum number of frames per second accepted from each peer.
+    rate: u64,
+    /// The quota of each authenticated node we recently heard from.
+    buckets: Arc<Mutex<NodeBuckets>>,
+}
+
+/// The quotas of the authenticated nodes.
+struct NodeBuckets {
+    buckets: HashMap<PublicKey, TokenBucket>,
+    /// The number of nodes above which we forget those that are not currently limited. It doubles
+    /// whenever they all are, so that we scan the buckets once in a while only.
+    threshold: usize,
+}
+
+impl InboundLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            buckets: Arc::new(Mutex::new(NodeBuckets {
+                buckets: HashMap::new(),
+                threshold: MAX_TRACKED_PEERS,
+            })),
+        }
+    }
+
+    /// Returns the quota of a connection, shared with the other connections of its node (if it
+    /// authenticated one).
+    pub fn quota(&self, name: Option<PublicKey>) -> FrameQuota {
+        FrameQuota {
+            limiter: self.clone(),
+            source: match name {
+                Some(name) => Source::Node(name),
+                None => Source::Connection(TokenBucket::new(self.rate)),
+            },
+        }
+    }
+
+    /// Returns the time to wait before we accept one more frame from the node (or `None` if we
+    /// accept it right away, taking it out of its quota).
+    fn try_acquire(&self, name: &PublicKey) -> Option<Duration> {
+        let mut guard = self.buckets.lock().unwrap();
+        let nodes = &mut *guard;
+        if nodes.buckets.len() >= nodes.threshold && !nodes.buckets.contains_key(name) {
+            // A full bucket is no different from a new one: forgetting it loses nothing.
+            nodes.buckets.retain(|_, bucket| !bucket.is_full());
+            nodes.threshold = MAX_TRACKED_PEERS.max(2 * nodes.buckets.len());
+        }
+        let bucket = nodes
+            .buckets
+            .entry(*name)
+            .or_insert_with(|| TokenBucket::new(self.rate));
+        take(bucket)
+    }
+}
+
+/// Takes a token out of a bucket, or returns the time until it holds one.
+fn take(bucket: &mut TokenBucket) -> Option<Duration> {
+    match bucket.try_acquire() {
+        true => None,
+        false => Some(bucket.delay()),
+    }
+}
+
+/// Where the frames of a connection are charged.
+enum Source {
+    /// The quota of the node the connection authenticated.
+    Node(PublicKey),
+    /// The quota of the connection itself.
+    Connection(TokenBucket),
+}
+
+/// The quota of the frames of a connection (see `InboundLimiter`).
+pub struct FrameQuota {
+    limiter: InboundLimiter,
+    source: Source,
+}
+
+impl FrameQuota {
+    /// Returns the time to wait before we accept one more frame (or `None` if we accept it right
+    /// away, taking it out of the quota).
+    fn try_acquire(&mut self) -> Option<Duration> {
+        match &mut self.source {
+            Source::Node(name) => self.limiter.try_acquire(name),
+            Source::Connection(bucket) => take(bucket),
+        }
+    }
+
+    /// Waits until we accept one more frame. Returns `true` if the peer exceeded its rate (and we
+    /// had to wait).
+    pub async fn acquire(&mut self) -> bool {
+        let mut throttled = false;
+        while let Some(delay) = self.try_acquire() {
+            throttled = true;
+            sleep(delay).await;
+        }
+        throttled
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receiver::{Connection, Listener, Peer};
+use crate::transport::Transport;
+use crate::version::codec;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::join;
+use futures::sink::SinkExt as _;
+use futures::stream::{SplitSink, SplitStream, StreamExt as _};
+use rand::rngs::SmallRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{duplex, DuplexStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep_until, Duration, Instant};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/memory_tests.rs"]
+pub mod memory_tests;
+
+/// The capacity of each in-memory pipe (in bytes).
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// The conditions of the links of an in-memory network.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkConditions {
+    /// The delay of each frame.
+    pub latency: Duration,
+    /// The probability (between 0 and 1) that a frame is lost. As over TCP, losing a frame breaks
+    /// its connection (the senders then reconnect and re-transmit).
+    pub loss: f64,
+}
+
+struct State {
+    /// The channels of the listeners bound to the addresses of the network.
+    listeners: BTreeMap<SocketAddr, mpsc::Sender<(DuplexStream, String)>>,
+    /// The conditions of the links.
+    conditions: LinkConditions,
+    /// The seed of the randomness of the links.
+    seed: u64,
+    /// The number of connections established so far.
+    connections: u64,
+}
+
+/// A network of in-process channels, so that tests can run nodes without sockets: the transport of
+/// the nodes given a context running over it (see `NetworkContext::with_transport`). Their
+/// receivers bind the addresses of the network and their senders connect to them. The receivers
+/// binding the unspecified IP (`0.0.0.0`) accept the connections to any IP of their port. Cloning
+/// the network shares it.
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<State>>,
+    /// The addresses cut from the rest of the network.
+    partitioned: Arc<watch::Sender<BTreeSet<SocketAddr>>>,
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        let (partitioned, _) = watch::channel(BTreeSet::new());
+        Self {
+            state: Arc::new(Mutex::new(State {
+                listeners: BTreeMap::new(),
+                conditions: LinkConditions::default(),
+                seed: 0,
+                connections: 0,
+            })),
+            partitioned: Arc::new(partitioned),
+        }
+    }
+
+    /// Returns a network whose lossy links drop frames as drawn from the specified seed.
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.state.lock().unwrap().seed = seed;
+        self
+    }
+
+    /// Sets the conditions of the links (applying to the frames sent from now on).
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.state.lock().unwrap().conditions = conditions;
+    }
+
+    /// Cuts the specified addresses from the rest of the network: their connections break and
+    /// nobody can connect to them until the partition heals.
+    pub fn partition(&self, addresses: impl IntoIterator<Item = SocketAddr>) {
+        self.partitioned.send_modify(|x| x.extend(addresses));
+    }
+
+    /// Heals all partitions.
+    pub fn heal(&self) {
+        self.partitioned.send_modify(|x| x.clear());
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryNetwork {
+    async fn connect(&self, address: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        let refused = || io::Error::from(io::ErrorKind::ConnectionRefused);
+        if self.partitioned.borrow().contains(&address) {
+            return Err(refused());
+        }
+        let (listener, id, rng) = {
+            let mut state = self.state.lock().unwrap();
+            let unspecified = SocketAddr::new("0.0.0.0".parse().unwrap(), address.port());
+            let listener = state
+                .listeners
+                .get(&address)
+                .or_else(|| state.listeners.get(&unspecified))
+                .cloned()
+                .ok_or_else(refused)?;
+            state.connections += 1;
+            let rng = SmallRng::seed_from_u64(state.seed.wrapping_add(state.connections));
+            (listener, state.connections, rng)
+        };
+
+        // Relay the frames between both ends of the connection.
+        let (client, client_relay) = duplex(PIPE_CAPACITY);
+        let (server_relay, server) = duplex(PIPE_CAPACITY);
+        listener
+            .send((server, format!("memory:{}", id)))
+            .await
+            .map_err(|_| refused())?;
+        let link = Link {
+            address,
+            state: self.state.clone(),
+            partitioned: self.partitioned.subscribe(),
+            rng,
+        };
+        tokio::spawn(link.relay(client_relay, server_relay));
+        Ok(Box::new(client))
+    }
+
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        let mut state = self.state.lock().unwrap();
+        if state.listeners.contains_key(&address) {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+        let (tx, rx) = mpsc::channel(1_000);
+        state.listeners.insert(address, tx);
+        Ok(Box::new(MemoryListener {
+            receiver: tokio::sync::Mutex::new(rx),
+        }))
+    }
+}
+
+/// Relays the frames of a single connection, applying the conditions of the network.
+struct Link {
+    /// The address the connection goes to.
+    address: SocketAddr,
+    /// The state of the network.
+    state: Arc<Mutex<State>>,
+    /// The addresses cut from the rest of the network.
+    partitioned: watch::Receiver<BTreeSet<SocketAddr>>,
+    /// The randomness of the frames we lose.
+    rng: SmallRng,
+}
+
+impl Link {
+    /// Relays the frames in both directions until either end closes the connection, a frame is
+    /// lost, or the partition of the address cuts the connection.
+    async fn relay(self, client: DuplexStream, server: DuplexStream) {
+        let Self {
+            address,
+            state,
+            mut partitioned,
+            rng,
+        } = self;
+        let (client_writer, client_reader) = Framed::new(client, codec(usize::MAX)).split();
+        let (server_writer, server_reader) = Framed::new(server, codec(usize::MAX)).split();
+        let forward = Self::pipe(client_reader, server_writer, state.clone(), rng.clone());
+        let backward = Self::pipe(server_reader, client_writer, state, rng);
+        let cut = async move {
+            while partitioned.changed().await.is_ok() {
+                if partitioned.borrow().contains(&address) {
+                    return;
+                }
+            }
+            futures::future::pending::<()>().await
+        };
+        tokio::select! {
+            () = forward => (),
+            () = backward => (),
+            () = cut => (),
+        }
+    }
+
+    /// Delays the frames of one direction of the connection (keeping their order).
+    async fn pipe(
+        mut reader: SplitStream<Framed<DuplexStream, LengthDelimitedCodec>>,
+        mut writer: SplitSink<Framed<DuplexStream, LengthDelimitedCodec>, Bytes>,
+        state: Arc<Mutex<State>>,
+        mut rng: SmallRng,
+    ) {
+        let (tx, mut rx) = mpsc::channel::<(Instant, Bytes)>(1_000);
+        let read = async move {
+            while let Some(Ok(frame)) = reader.next().await {
+                let conditions = state.lock().unwrap().conditions;
+                if rng.gen_bool(conditions.loss.clamp(0.0, 1.0)) {
+                    return;
+                }
+                let deadline = Instant::now() + conditions.latency;
+                if tx.send((deadline, frame.freeze())).await.is_err() {
+                    return;
+                }
+            }
+        };
+        let deliver = async move {
+            while let Some((deadline, frame)) = rx.recv().await {
+                sleep_until(deadline).await;
+                if writer.send(frame).await.is_err() {
+                    return;
+                }
+            }
+        };
+        join(read, deliver).await;
+    }
+}
+
+/// A listener accepting the connections of an in-memory network.
+struct MemoryListener {
+    receiver: tokio::sync::Mutex<mpsc::Receiver<(DuplexStream, String)>>,
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&self) -> io::Result<(Box<dyn Connection>, Peer)> {
+        match self.receiver.lock().await.recv().await {
+            Some((connection, peer)) => Ok((Box::new(connection), Peer::new(peer))),
+            None => Err(io::Error::from(io::ErrorKind::NotConnected)),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_with_registry, Histogram, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, Registry,
+};
+use std::net::SocketAddr;
+
+/// The Prometheus metrics of the connection pools of the senders (the simple senders only report
+/// the messages they drop, the bytes they exchange and the connections they close) and of the
+/// receivers reporting to them. The per-peer metrics are labelled by the
+/// address we connect to. Cloning the metrics shares them.
+#[derive(Clone)]
+pub struct NetworkMetrics {
+    /// The number of established connections to our peers.
+    pub connected_peers: IntGauge,
+    /// The number of messages waiting to be sent or acknowledged.
+    pub pending_messages: IntGauge,
+    /// The number of times we re-attempted to connect to a peer (after failing to connect or
+    /// losing the connection).
+    pub reconnects: IntCounter,
+    /// The number of connections we dropped because the peer stopped answering our keep-alives.
+    pub keep_alive_timeouts: IntCounter,
+    /// The number of idle connections we closed (senders close the connections over which they
+    /// sent nothing for a while, receivers those over which their peer sent nothing at all).
+    pub idle_evictions: IntCounter,
+    /// The number of frames our peers replayed into their connections (receivers close these
+    /// connections).
+    pub replayed_frames: IntCounter,
+    /// The number of frames we waited for because their peer exceeded its inbound rate (receivers
+    /// pause these connections).
+    pub throttled_frames: IntCounter,
+    /// The number of messages we gave up delivering because their peer exhausted its budget of
+    /// connection attempts.
+    pub abandoned_messages: IntCounter,
+    /// The number of messages we dropped because the queue of their peer was full.
+    pub dropped_messages: IntCounter,
+    /// The number of peers that exhausted their budget of connection attempts.
+    pub down_peers: IntGauge,
+    /// The number of messages broadcasts skipped because their peer was down.
+    pub skipped_messages: IntCounter,
+    /// The number of bytes we sent to each peer.
+    pub peer_bytes_sent: IntCounterVec,
+    /// The number of bytes each peer sent us: the replies to our messages (labelled by the address
+    /// of the peer) and the frames our receivers read (labelled by the node the peer authenticated
+    /// as, or by its IP address).
+    pub peer_bytes_received: IntCounterVec,
+    /// The time each peer takes to acknowledge our messages.
+    pub peer_round_trip: HistogramVec,
+    /// The number of times we re-attempted to connect to each peer.
+    pub peer_retries: IntCounterVec,
+}
+
+/// The metrics of our connection to a single peer.
+#[derive(Clone)]
+pub(crate) struct PeerMetrics {
+    pub bytes_sent: IntCounter,
+    pub bytes_received: IntCounter,
+    pub round_trip: Histogram,
+    pub retries: IntCounter,
+}
+
+impl NetworkMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            connected_peers: register_int_gauge_with_registry!(
+                "network_connected_peers",
+                "Number of established connections to our peers",
+                registry
+            )
+            .expect("Failed to register metric"),
+            pending_messages: register_int_gauge_with_registry!(
+                "network_pending_messages",
+                "Number of messages waiting to be sent or acknowledged",
+                registry
+            )
+            .expect("Failed to register metric"),
+            reconnects: register_int_counter_with_registry!(
+                "network_reconnects",
+                "Number of times we re-attempted to connect to a peer",
+                registry
+            )
+            .expect("Failed to register metric"),
+            keep_alive_timeouts: register_int_counter_with_registry!(
+                "network_keep_alive_timeouts",
+                "Number of connections dropped because the peer stopped answering keep-alives",
+                registry
+            )
+            .expect("Failed to register metric"),
+            idle_evictions: register_int_counter_with_registry!(
+                "network_idle_evictions",
+                "Number of idle connections we closed",
+                registry
+            )
+            .expect("Failed to register metric"),
+            replayed_frames: register_int_counter_with_registry!(
+                "network_replayed_frames",
+                "Number of frames replayed into their connections",
+                registry
+            )
+            .expect("Failed to register metric"),
+            throttled_frames: register_int_counter_with_registry!(
+                "network_throttled_frames",
+                "Number of frames delayed because their peer exceeded its inbound rate",
+                registry
+            )
+            .expect("Failed to register metric"),
+            abandoned_messages: register_int_counter_with_registry!(
+                "network_abandoned_messages",
+                "Number of messages abandoned because their peer stayed unreachable",
+                registry
+            )
+            .expect("Failed to register metric"),
+            dropped_messages: register_int_counter_with_registry!(
+                "network_dropped_messages",
+                "Number of messages dropped because the queue of their peer was full",
+                registry
+            )
+            .expect("Failed to register metric"),
+            down_peers: register_int_gauge_with_registry!(
+                "network_down_peers",
+                "Number of peers that exhausted their budget of connection attempts",
+                registry
+            )
+            .expect("Failed to register metric"),
+            skipped_messages: register_int_counter_with_registry!(
+                "network_skipped_messages",
+                "Number of messages broadcasts skipped because their peer was down",
+                registry
+            )
+            .expect("Failed to register metric"),
+            peer_bytes_sent: register_int_counter_vec_with_registry!(
+                "network_peer_bytes_sent",
+                "Number of bytes sent to each peer",
+                &["peer"],
+                registry
+            )
+            .expect("Failed to register metric"),
+            peer_bytes_received: register_int_counter_vec_with_registry!(
+                "network_peer_bytes_received",
+                "Number of bytes each peer sent us",
+                &["peer"],
+                registry
+            )
+            .expect("Failed to register metric"),
+            peer_round_trip: register_histogram_vec_with_registry!(
+                "network_peer_round_trip_seconds",
+                "Time between sending a message to each peer and receiving its acknowledgement",
+                &["peer"],
+                registry
+            )
+            .expect("Failed to register metric"),
+            peer_retries: register_int_counter_vec_with_registry!(
+                "network_peer_retries",
+                "Number of times we re-attempted to connect to each peer",
+                &["peer"],
+                registry
+            )
+            .expect("Failed to register metric"),
+        }
+    }
+
+    /// Returns the metrics of the connection to the specified peer.
+    pub(crate) fn peer(&self, address: &SocketAddr) -> PeerMetrics {
+        let peer = address.to_string();
+        let label = [peer.as_str()];
+        PeerMetrics {
+            bytes_sent: self.peer_bytes_sent.with_label_values(&label),
+            bytes_received: self.peer_bytes_received.with_label_values(&label),
+            round_trip: self.peer_round_trip.with_label_values(&label),
+            retries: self.peer_retries.with_label_values(&label),
+        }
+    }
+}
+
+impl Default for NetworkMetrics {
+    /// Metrics registered in a registry of their own (they are not exported).
+    fn default() -> Self {
+        Self::new(&Registry::new())
+    }
+}
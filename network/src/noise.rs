@@ -0,0 +1,220 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receiver::Connection;
+use bytes::{Buf as _, BytesMut};
+use snow::{Builder, HandshakeState, TransportState};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _, ReadBuf};
+
+/// The Noise protocol the nodes run: the initiator knows the static key of the responder (that of
+/// the node the committee lists at the address it connects to) and sends its own, encrypted.
+const NOISE_PARAMS: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
+/// The maximum size of a Noise message (including its authentication tag).
+const MAX_MESSAGE: usize = 65535;
+
+/// The size of the authentication tag of each Noise message.
+const TAG_SIZE: usize = 16;
+
+/// The maximum number of bytes we encrypt into a single Noise message.
+const MAX_PAYLOAD: usize = MAX_MESSAGE - TAG_SIZE;
+
+fn invalid(e: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Runs the handshake of the initiator with the responder of the specified static key, returning
+/// the encrypted connection.
+pub(crate) async fn initiate(
+    mut connection: Box<dyn Connection>,
+    secret: &[u8; 32],
+    remote: &[u8; 32],
+) -> io::Result<NoiseStream> {
+    let mut handshake = Builder::new(NOISE_PARAMS.parse().expect("Invalid Noise parameters"))
+        .local_private_key(secret)
+        .remote_public_key(remote)
+        .build_initiator()
+        .map_err(invalid)?;
+    let mut buffer = vec![0u8; MAX_MESSAGE];
+    let length = handshake.write_message(&[], &mut buffer).map_err(invalid)?;
+    write_message(&mut connection, &buffer[..length]).await?;
+    let message = read_message(&mut connection).await?;
+    handshake
+        .read_message(&message, &mut buffer)
+        .map_err(invalid)?;
+    NoiseStream::new(connection, handshake)
+}
+
+/// Runs the handshake of the responder, returning the encrypted connection along with the static
+/// key of the initiator, provided that `accept` accepts it.
+pub(crate) async fn respond(
+    mut connection: Box<dyn Connection>,
+    secret: &[u8; 32],
+    accept: impl FnOnce(&[u8]) -> bool,
+) -> io::Result<(NoiseStream, Vec<u8>)> {
+    let mut handshake = Builder::new(NOISE_PARAMS.parse().expect("Invalid Noise parameters"))
+        .local_private_key(secret)
+        .build_responder()
+        .map_err(invalid)?;
+    let mut buffer = vec![0u8; MAX_MESSAGE];
+    let message = read_message(&mut connection).await?;
+    handshake
+        .read_message(&message, &mut buffer)
+        .map_err(invalid)?;
+    let remote = handshake.get_remote_static().unwrap_or_default().to_vec();
+    if !accept(&remote) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Unknown static key",
+        ));
+    }
+    let length = handshake.write_message(&[], &mut buffer).map_err(invalid)?;
+    write_message(&mut connection, &buffer[..length]).await?;
+    Ok((NoiseStream::new(connection, handshake)?, remote))
+}
+
+/// Writes a Noise message, prefixed by its length.
+async fn write_message(connection: &mut Box<dyn Connection>, message: &[u8]) -> io::Result<()> {
+    connection.write_u16(message.len() as u16).await?;
+    connection.write_all(message).await?;
+    connection.flush().await
+}
+
+/// Reads a Noise message, prefixed by its length.
+async fn read_message(connection: &mut Box<dyn Connection>) -> io::Result<Vec<u8>> {
+    let length = connection.read_u16().await? as usize;
+    let mut message = vec![0u8; length];
+    connection.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+/// A connection encrypted by a Noise session: each write is encrypted into messages of at most
+/// `MAX_MESSAGE` bytes, prefixed by their length.
+pub(crate) struct NoiseStream {
+    inner: Box<dyn Connection>,
+    state: TransportState,
+    /// The encrypted bytes read from the connection, not yet decrypted.
+    encrypted: BytesMut,
+    /// The decrypted bytes not yet consumed by the reader.
+    decrypted: BytesMut,
+    /// The encrypted bytes not yet written to the connection.
+    pending: BytesMut,
+}
+
+impl NoiseStream {
+    fn new(inner: Box<dyn Connection>, handshake: HandshakeState) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            state: handshake.into_transport_mode().map_err(invalid)?,
+            encrypted: BytesMut::new(),
+            decrypted: BytesMut::new(),
+            pending: BytesMut::new(),
+        })
+    }
+
+    /// Writes the pending encrypted bytes to the connection.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.pending) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => self.pending.advance(n),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Decrypts the next message read from the connection (if we read it whole).
+    fn decrypt(&mut self) -> io::Result<bool> {
+        if self.encrypted.len() < 2 {
+            return Ok(false);
+        }
+        let length = u16::from_be_bytes([self.encrypted[0], self.encrypted[1]]) as usize;
+        if self.encrypted.len() < 2 + length {
+            return Ok(false);
+        }
+        let message = self.encrypted.split_to(2 + length);
+        let mut payload = vec![0u8; length];
+        let size = self
+            .state
+            .read_message(&message[2..], &mut payload)
+            .map_err(invalid)?;
+        self.decrypted.extend_from_slice(&payload[..size]);
+        Ok(true)
+    }
+}
+
+impl AsyncRead for NoiseStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.decrypted.is_empty() {
+            if this.decrypt()? {
+                continue;
+            }
+            let mut chunk = [0u8; 8 * 1024];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(())) if chunk.filled().is_empty() => {
+                    return match this.encrypted.is_empty() {
+                        true => Poll::Ready(Ok(())),
+                        false => Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                    };
+                }
+                Poll::Ready(Ok(())) => this.encrypted.extend_from_slice(chunk.filled()),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let size = this.decrypted.len().min(buf.remaining());
+        buf.put_slice(&this.decrypted.split_to(size));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for NoiseStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Hold back the writer while the connection does not take the previous messages.
+        match this.poll_pending(cx) {
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending if this.pending.len() >= MAX_MESSAGE => return Poll::Pending,
+            _ => (),
+        }
+        let size = buf.len().min(MAX_PAYLOAD);
+        let mut message = vec![0u8; size + TAG_SIZE];
+        let length = this
+            .state
+            .write_message(&buf[..size], &mut message)
+            .map_err(invalid)?;
+        this.pending
+            .extend_from_slice(&(length as u16).to_be_bytes());
+        this.pending.extend_from_slice(&message[..length]);
+        Poll::Ready(Ok(size))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::collections::VecDeque;
+
+#[cfg(test)]
+#[path = "tests/queue_tests.rs"]
+pub mod queue_tests;
+
+/// What a connection does with a new message when its queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Drop the oldest message of the queue (of the lowest priority) to make room for the new one.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+    /// Stop accepting messages until the queue has room, blocking the sender.
+    Block,
+}
+
+/// Bounds the messages a connection holds for its peer (while it is unreachable or slow).
+#[derive(Clone, Copy, Debug)]
+pub struct QueueLimits {
+    /// The maximum number of messages waiting to be sent to the peer.
+    pub capacity: usize,
+    /// What we do with a new message when the queue is full.
+    pub policy: QueuePolicy,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            policy: QueuePolicy::DropOldest,
+        }
+    }
+}
+
+/// The priority of a message. A connection sends its queued messages of higher priority first, so
+/// that small control messages do not wait behind bulk data sent to the same peer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Bulk data, such as batches.
+    #[default]
+    Bulk,
+    /// The replies to sync requests, such as the batches requested by other workers.
+    Sync,
+    /// Proposals, such as headers.
+    Proposal,
+    /// Control messages, such as votes and certificates.
+    Control,
+}
+
+impl Priority {
+    /// The number of priorities.
+    const COUNT: usize = 4;
+}
+
+/// The messages a connection holds for its peer, by priority.
+pub(crate) struct PriorityQueue<T> {
+    /// Bounds the total number of messages of the queue.
+    limits: QueueLimits,
+    /// The messages of each priority, from the oldest.
+    queues: [VecDeque<T>; Priority::COUNT],
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn new(limits: QueueLimits) -> Self {
+        Self {
+            limits,
+            queues: Default::default(),
+        }
+    }
+
+    /// Returns the number of queued messages.
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(|x| x.len()).sum()
+    }
+
+    /// Whether the queue accepts more messages. Only blocking queues ever refuse them.
+    pub fn accepts(&self) -> bool {
+        self.limits.policy != QueuePolicy::Block || self.len() < self.limits.capacity.max(1)
+    }
+
+    /// Queues a message, returning the message we dropped to make room (if any). We never drop a
+    /// message to make room for a message of lower priority.
+    pub fn push(&mut self, priority: Priority, item: T) -> Option<T> {
+        if self.len() >= self.limits.capacity.max(1) {
+            match self.limits.policy {
+                QueuePolicy::DropNewest => return Some(item),
+                QueuePolicy::DropOldest | QueuePolicy::Block => {
+                    let lowest = self.queues[..=priority as usize]
+                        .iter_mut()
+                        .find(|x| !x.is_empty());
+                    let dropped = match lowest {
+                        Some(queue) => queue.pop_front(),
+                        None => return Some(item),
+                    };
+                    self.queues[priority as usize].push_back(item);
+                    return dropped;
+                }
+            }
+        }
+        self.queues[priority as usize].push_back(item);
+        None
+    }
+
+    /// Puts back a message at the front of the queue of its priority (regardless of the limits),
+    /// for instance to re-transmit it.
+    pub fn push_front(&mut self, priority: Priority, item: T) {
+        self.queues[priority as usize].push_front(item);
+    }
+
+    /// Removes the oldest message of the highest priority.
+    pub fn pop(&mut self) -> Option<(Priority, T)> {
+        let priorities = [
+            Priority::Control,
+            Priority::Proposal,
+            Priority::Sync,
+            Priority::Bulk,
+        ];
+        priorities
+            .iter()
+            .find_map(|&x| self.queues[x as usize].pop_front().map(|item| (x, item)))
+    }
+
+    /// Only keeps the messages satisfying the predicate.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        for queue in &mut self.queues {
+            queue.retain(&mut f);
+        }
+    }
+
+    /// Drops all messages.
+    pub fn clear(&mut self) {
+        for queue in &mut self.queues {
+            queue.clear();
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receiver::{Connection, Listener, Peer};
+use crate::security::{tls_configs, tls_peer, HANDSHAKE_TIMEOUT};
+use crate::transport::Transport;
+use async_trait::async_trait;
+use crypto::{PublicKey, SecretKey};
+use log::{debug, warn};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{ClientConfig, Endpoint, ServerConfig};
+use rustls::pki_types::CertificateDer;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom as _;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+#[cfg(test)]
+#[path = "tests/quic_tests.rs"]
+pub mod quic_tests;
+
+/// The name under which we reach the other nodes (they authenticate by their key instead).
+const SERVER_NAME: &str = "narwhal";
+
+/// The capacity of the channel of the streams accepted by a listener.
+const STREAMS_CAPACITY: usize = 1_000;
+
+/// Reaches the other nodes over QUIC: we keep a single QUIC connection with each node and run each
+/// connection of the senders (each logical channel) over its own stream, so that a frame lost on
+/// one channel does not hold back the others. QUIC always runs TLS 1.3, with which the nodes
+/// authenticate each other by the ed25519 key the committee lists for them (as in
+/// `Security::Tls`). Cloning the transport shares it.
+#[derive(Clone)]
+pub struct QuicTransport {
+    client: ClientConfig,
+    server: ServerConfig,
+    /// The key of the node listening on each address of the committee.
+    peers: Arc<BTreeMap<SocketAddr, PublicKey>>,
+    /// Our connections to the other nodes, by the address they resolved to and their key.
+    connections: Arc<Mutex<HashMap<(SocketAddr, PublicKey), quinn::Connection>>>,
+}
+
+impl QuicTransport {
+    /// Makes the transport of the node with the specified keys, reaching the specified peers (the
+    /// key of the node listening on each address of the committee).
+    pub fn new(
+        name: &PublicKey,
+        secret: &SecretKey,
+        peers: BTreeMap<SocketAddr, PublicKey>,
+    ) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let (client, server) = tls_configs(name, secret, peers.values().copied().collect())?;
+        let client = QuicClientConfig::try_from(Arc::new(client)).map_err(invalid)?;
+        let server = QuicServerConfig::try_from(Arc::new(server)).map_err(invalid)?;
+        Ok(Self {
+            client: ClientConfig::new(Arc::new(client)),
+            server: ServerConfig::with_crypto(Arc::new(server)),
+            peers: Arc::new(peers),
+            connections: Arc::default(),
+        })
+    }
+
+    /// Opens a stream to the node with the specified key, listening on the specified address.
+    async fn open(
+        &self,
+        resolved: SocketAddr,
+        expected: PublicKey,
+    ) -> io::Result<Box<dyn Connection>> {
+        // Open a stream over our connection with the node (if it is still up).
+        let cached = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(&(resolved, expected))
+            .cloned();
+        if let Some(connection) = cached {
+            if connection.close_reason().is_none() {
+                if let Ok((send, receive)) = connection.open_bi().await {
+                    return Ok(Box::new(tokio::io::join(receive, send)));
+                }
+            }
+        }
+
+        // Connect to the node otherwise.
+        let connection = self.handshake(resolved, expected).await?;
+        let (send, receive) = connection.open_bi().await?;
+        self.connections
+            .lock()
+            .unwrap()
+            .insert((resolved, expected), connection);
+        Ok(Box::new(tokio::io::join(receive, send)))
+    }
+
+    /// Opens a QUIC connection to the node with the specified key at the specified address (on an
+    /// endpoint of its own, which lives as long as the connection).
+    async fn handshake(
+        &self,
+        address: SocketAddr,
+        expected: PublicKey,
+    ) -> io::Result<quinn::Connection> {
+        let local = match address {
+            SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        };
+        let endpoint = Endpoint::client(local)?;
+        let connecting = endpoint
+            .connect_with(self.client.clone(), address, SERVER_NAME)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = timeout(HANDSHAKE_TIMEOUT, connecting)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out"))??;
+        let name = peer_name(&connection)?;
+        if name != expected {
+            let message = format!(
+                "{} authenticated as {} instead of {}",
+                address, name, expected
+            );
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+        }
+        Ok(connection)
+    }
+}
+
+/// Returns the key the node at the other end of a QUIC connection authenticated with.
+fn peer_name(connection: &quinn::Connection) -> io::Result<PublicKey> {
+    let certificates = connection
+        .peer_identity()
+        .and_then(|x| x.downcast::<Vec<CertificateDer<'static>>>().ok());
+    tls_peer(certificates.as_deref().map(|x| &x[..]))
+}
+
+#[async_trait]
+impl Transport for QuicTransport {
+    async fn connect(&self, address: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        self.connect_node(address, address).await
+    }
+
+    async fn connect_node(
+        &self,
+        address: SocketAddr,
+        resolved: SocketAddr,
+    ) -> io::Result<Box<dyn Connection>> {
+        let expected = self.peers.get(&address).copied().ok_or_else(|| {
+            let message = format!("{} is not the address of a node of the committee", address);
+            io::Error::new(io::ErrorKind::PermissionDenied, message)
+        })?;
+        self.open(resolved, expected).await
+    }
+
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        let endpoint = Endpoint::server(self.server.clone(), address)?;
+        let (tx, rx) = mpsc::channel(STREAMS_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                tokio::spawn(serve(incoming, tx.clone()));
+            }
+        });
+        Ok(Box::new(QuicListener {
+            receiver: tokio::sync::Mutex::new(rx),
+        }))
+    }
+}
+
+/// Accepts the streams of an incoming QUIC connection, attributing them to its node.
+async fn serve(incoming: quinn::Incoming, tx: mpsc::Sender<(Box<dyn Connection>, Peer)>) {
+    let connection = match timeout(HANDSHAKE_TIMEOUT, incoming).await {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => {
+            warn!("Failed to accept QUIC connection: {}", e);
+            return;
+        }
+        Err(_) => {
+            warn!("Failed to accept QUIC connection: handshake timed out");
+            return;
+        }
+    };
+    let peer = Peer {
+        address: connection.remote_address().to_string(),
+        name: peer_name(&connection).ok(),
+    };
+    while let Ok((send, receive)) = connection.accept_bi().await {
+        let stream: Box<dyn Connection> = Box::new(tokio::io::join(receive, send));
+        if tx.send((stream, peer.clone())).await.is_err() {
+            return;
+        }
+    }
+    debug!("QUIC connection with {} closed", peer.address);
+}
+
+/// A listener accepting the streams of the QUIC connections of the other nodes.
+struct QuicListener {
+    receiver: tokio::sync::Mutex<mpsc::Receiver<(Box<dyn Connection>, Peer)>>,
+}
+
+#[async_trait]
+impl Listener for QuicListener {
+    async fn accept(&self) -> io::Result<(Box<dyn Connection>, Peer)> {
+        match self.receiver.lock().await.recv().await {
+            Some(stream) => Ok(stream),
+            None => Err(io::Error::from(io::ErrorKind::NotConnected)),
+        }
+    }
+}
@@ -1,21 +1,112 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::compression::{decode, Compression, DecodeError};
+use crate::context::NetworkContext;
 use crate::error::NetworkError;
+use crate::limiter::InboundLimiter;
+use crate::metrics::NetworkMetrics;
+use crate::relay::{read_forwarded, Forwarded};
+use crate::sequence::{nonce, ReplayWindow};
+use crate::version::{
+    codec, compression_frame, features_frame, hello, negotiate, parse_compression_frame,
+    parse_features_frame, parse_hello, sequence_frame, Features, FEATURES_VERSION, FRAME_TOO_LARGE,
+    IDLE_TIMEOUT_VERSION, MAX_FRAME_SIZE, PING, REPLAY_PROTECTION_VERSION,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
+use crypto::PublicKey;
+use futures::sink::SinkExt as _;
 use futures::stream::SplitSink;
 use futures::stream::StreamExt as _;
 use log::{debug, info, warn};
 use std::error::Error;
+#[cfg(unix)]
+use std::fs;
+use std::io;
 use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt as _;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite};
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::net::{TcpListener, TcpStream};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec, LengthDelimitedCodecError};
 
 #[cfg(test)]
 #[path = "tests/receiver_tests.rs"]
 pub mod receiver_tests;
 
-/// Convenient alias for the writer end of the TCP channel.
-pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+/// The default delay after which we close a connection over which the peer sent nothing at all,
+/// not even a keep-alive probe (peers probe their quiet connections every few seconds).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A connection accepted by a receiver (a TCP stream or a Unix domain socket).
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection for T {}
+
+/// Convenient alias for the writer end of the channel.
+pub type Writer = SplitSink<Framed<Box<dyn Connection>, LengthDelimitedCodec>, Bytes>;
+
+/// A socket on which a receiver accepts connections.
+#[async_trait]
+pub trait Listener: Send + Sync + 'static {
+    /// Accepts a new connection, returning it along with its peer (and the node it authenticated
+    /// as, if the listener authenticates its peers).
+    async fn accept(&self) -> io::Result<(Box<dyn Connection>, Peer)>;
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    async fn accept(&self) -> io::Result<(Box<dyn Connection>, Peer)> {
+        let (socket, peer) = TcpListener::accept(self).await?;
+        Ok((Box::new(socket), Peer::new(peer.to_string())))
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl Listener for UnixListener {
+    async fn accept(&self) -> io::Result<(Box<dyn Connection>, Peer)> {
+        let (socket, _) = UnixListener::accept(self).await?;
+        // The peers of a Unix domain socket are usually unnamed.
+        let peer = match self.local_addr()?.as_pathname() {
+            Some(path) => format!("unix:{}", path.display()),
+            None => "unix".to_string(),
+        };
+        Ok((Box::new(socket), Peer::new(peer)))
+    }
+}
+
+/// The peer of a connection: its address (as described by the `Listener`) and, if the context or
+/// the transport secures the connections between the nodes, the node of the committee it
+/// authenticated as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peer {
+    pub address: String,
+    pub name: Option<PublicKey>,
+}
+
+impl Peer {
+    /// Makes an unauthenticated peer with the specified address.
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            name: None,
+        }
+    }
+}
+
+/// Upgrades the TCP connections a receiver accepts before exchanging messages over them (for
+/// instance, with a TLS handshake optionally authenticating the client by its certificate).
+#[async_trait]
+pub trait Acceptor: Send + Sync + 'static {
+    /// Upgrades a new connection with the specified peer, failing if the peer may not connect.
+    async fn accept(&self, socket: TcpStream, peer: SocketAddr) -> io::Result<Box<dyn Connection>>;
+}
 
 #[async_trait]
 pub trait MessageHandler: Clone + Send + Sync + 'static {
@@ -24,32 +115,132 @@ pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
     /// responses or acknowledgements to the sender machine (see unit tests for examples).
     async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
+
+    /// Returns the handler of a new connection with the specified peer. Handlers keeping per-peer
+    /// state (such as rate limits) override it.
+    fn for_peer(&self, _peer: &Peer) -> Self {
+        self.clone()
+    }
+
+    /// Returns the maximum size of the frames we accept. Peers sending larger frames get a
+    /// `FRAME_TOO_LARGE` reply and lose their connection.
+    fn max_frame_size(&self) -> usize {
+        MAX_FRAME_SIZE
+    }
+
+    /// Whether we accept the offers of our peers to compress the messages they send us (trading
+    /// CPU for bandwidth).
+    fn accepts_compression(&self) -> bool {
+        false
+    }
+
+    /// Returns the delay after which we close a connection over which the peer sent nothing at all.
+    /// Only applies to the peers probing their quiet connections (those speaking protocol version
+    /// `IDLE_TIMEOUT_VERSION` or later), so that clients may stay quiet.
+    fn idle_timeout(&self) -> Duration {
+        IDLE_TIMEOUT
+    }
+
+    /// Returns the metrics to which we report the connections we close (if any).
+    fn metrics(&self) -> Option<NetworkMetrics> {
+        None
+    }
+
+    /// Returns the limiter of the rate at which each peer sends us frames (if any).
+    fn inbound_limiter(&self) -> Option<InboundLimiter> {
+        None
+    }
+
+    /// Returns the features we require from our peers (those speaking protocol version
+    /// `FEATURES_VERSION` or later). We close the connections of the peers lacking them.
+    fn required_features(&self) -> Features {
+        Features::NONE
+    }
 }
 
 /// For each incoming request, we spawn a new runner responsible to receive messages and forward them
 /// through the provided deliver channel.
 pub struct Receiver<Handler: MessageHandler> {
-    /// Address to listen to.
-    address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// The environment of the receiver (telling it which peers are our relays).
+    context: NetworkContext,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
-    /// Spawn a new network receiver handling connections from any incoming peer.
+    /// Spawn a new network receiver handling connections from any incoming peer (over TCP).
     pub fn spawn(address: SocketAddr, handler: Handler) {
+        Self::spawn_with_context(address, handler, NetworkContext::default());
+    }
+
+    /// Spawn a new network receiver handling connections from any incoming peer, over the
+    /// transport of the specified context.
+    pub fn spawn_with_context(address: SocketAddr, handler: Handler, context: NetworkContext) {
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
+            let listener = context
+                .transport()
+                .bind(address)
+                .await
+                .expect("Failed to bind address");
+            debug!("Listening on {}", address);
+            Self { handler, context }.run(listener).await;
         });
     }
 
-    /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
-    async fn run(&self) {
-        let listener = TcpListener::bind(&self.address)
-            .await
-            .expect("Failed to bind TCP port");
+    /// Spawn a new network receiver handling connections from any incoming peer, upgraded by the
+    /// specified acceptor. Each connection is upgraded by its own task, so that a slow peer does not
+    /// hold back the others.
+    pub fn spawn_with_acceptor(address: SocketAddr, handler: Handler, acceptor: Arc<dyn Acceptor>) {
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(&address)
+                .await
+                .expect("Failed to bind TCP port");
+            debug!("Listening on {}", address);
+            loop {
+                let (socket, peer) = match listener.accept().await {
+                    Ok(value) => value,
+                    Err(e) => {
+                        warn!("{}", NetworkError::FailedToListen(e));
+                        continue;
+                    }
+                };
+                let handler = handler.clone();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    match acceptor.accept(socket, peer).await {
+                        Ok(connection) => {
+                            info!("Incoming connection established with {}", peer);
+                            let context = NetworkContext::default();
+                            let peer = Peer::new(peer.to_string());
+                            Self::spawn_runner(connection, peer, handler, context).await;
+                        }
+                        Err(e) => warn!("Failed to accept connection from {}: {}", peer, e),
+                    }
+                });
+            }
+        });
+    }
+
+    /// Spawn a new network receiver handling connections from the processes of the same host,
+    /// over a Unix domain socket.
+    #[cfg(unix)]
+    pub fn spawn_unix(path: PathBuf, handler: Handler) {
+        tokio::spawn(async move {
+            // Remove the socket left behind by a previous run (if any).
+            if let Ok(metadata) = fs::symlink_metadata(&path) {
+                if metadata.file_type().is_socket() {
+                    fs::remove_file(&path).expect("Failed to remove stale Unix socket");
+                }
+            }
+            let listener = UnixListener::bind(&path).expect("Failed to bind Unix socket");
+            debug!("Listening on {}", path.display());
+            let context = NetworkContext::default();
+            Self { handler, context }.run(Box::new(listener)).await;
+        });
+    }
 
-        debug!("Listening on {}", self.address);
+    /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
+    async fn run(&self, listener: Box<dyn Listener>) {
         loop {
             let (socket, peer) = match listener.accept().await {
                 Ok(value) => value,
@@ -58,32 +249,295 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                     continue;
                 }
             };
-            info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            info!("Incoming connection established with {}", peer.address);
+            let (handler, context) = (self.handler.clone(), self.context.clone());
+            Self::spawn_runner(socket, peer, handler, context).await;
         }
     }
 
-    /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
-    /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    /// Spawn a new runner to handle a specific connection. It receives messages and process them
+    /// using the provided handler. Other nodes open the connection by announcing the version of
+    /// their protocol, to which we reply with ours; clients (which do not) speak the first version.
+    /// The connections accepted from our relays may start with the address of the peer on whose
+    /// behalf the relay forwards them. We then secure the connection (if the context secures them),
+    /// end to end with the peer.
+    async fn spawn_runner(
+        mut socket: Box<dyn Connection>,
+        peer: Peer,
+        handler: Handler,
+        context: NetworkContext,
+    ) {
         tokio::spawn(async move {
-            let transport = Framed::new(socket, LengthDelimitedCodec::new());
+            let Peer {
+                address: mut peer,
+                name,
+            } = peer;
+            let mut prefix = None;
+            if context.is_relay(&peer) {
+                match read_forwarded(&mut socket).await {
+                    Ok(Forwarded::Peer(address)) => {
+                        debug!("Relay {} forwards the connection of {}", peer, address);
+                        peer = address;
+                    }
+                    Ok(Forwarded::Direct(bytes)) => prefix = Some(bytes),
+                    Err(e) => {
+                        warn!("Failed to receive message from {}: {}", peer, e);
+                        return;
+                    }
+                }
+            }
+            if let Some(bytes) = prefix {
+                let (reader, writer) = tokio::io::split(socket);
+                socket = Box::new(tokio::io::join(
+                    io::Cursor::new(bytes).chain(reader),
+                    writer,
+                ));
+            }
+            let (socket, name) = match context.accept(socket).await {
+                Ok((socket, authenticated)) => (socket, authenticated.or(name)),
+                Err(e) => {
+                    warn!("Failed to secure the connection with {}: {}", peer, e);
+                    return;
+                }
+            };
+            let handler = handler.for_peer(&Peer {
+                address: peer.clone(),
+                name,
+            });
+            let transport = Framed::new(socket, codec(handler.max_frame_size()));
             let (mut writer, mut reader) = transport.split();
-            while let Some(frame) = reader.next().await {
-                match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
-                    Ok(message) => {
-                        if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
-                            warn!("{}", e);
+            let mut first = true;
+            let mut compression = Compression::None;
+            let mut idle_timeout = None;
+            let mut window = None;
+            let mut awaiting_features = false;
+            let mut quota = handler.inbound_limiter().map(|x| x.quota(name));
+            let bytes_received = handler.metrics().map(|x| {
+                let label = peer_label(&peer, name);
+                x.peer_bytes_received.with_label_values(&[&label])
+            });
+            loop {
+                // Pause the connection while the peer exceeds its rate, before reading its frame.
+                if let Some(quota) = &mut quota {
+                    if quota.acquire().await {
+                        debug!("Throttling peer {}", peer);
+                        if let Some(metrics) = handler.metrics() {
+                            metrics.throttled_frames.inc();
+                        }
+                    }
+                }
+
+                let frame = match idle_timeout {
+                    Some(delay) => match timeout(delay, reader.next()).await {
+                        Ok(frame) => frame,
+                        Err(_) => {
+                            // The peer stopped probing the connection: a middlebox may have
+                            // dropped it silently.
+                            debug!("Closing idle connection with {}", peer);
+                            if let Some(metrics) = handler.metrics() {
+                                metrics.idle_evictions.inc();
+                            }
                             return;
                         }
+                    },
+                    None => reader.next().await,
+                };
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => break,
+                };
+                let message = match frame {
+                    Ok(message) => {
+                        if let Some(counter) = &bytes_received {
+                            counter.inc_by(message.len() as u64);
+                        }
+                        message
+                    }
+                    Err(e) if is_frame_too_large(&e) => {
+                        // Tell the peer why we drop the connection.
+                        warn!(
+                            "Peer {} sent a frame larger than {} B",
+                            peer,
+                            handler.max_frame_size()
+                        );
+                        let _ = writer.send(Bytes::from_static(FRAME_TOO_LARGE)).await;
+                        return;
                     }
                     Err(e) => {
-                        warn!("{}", e);
+                        warn!("Failed to receive message from {}: {}", peer, e);
                         return;
                     }
+                };
+
+                // Agree on the version of the protocol (if the peer announces its own).
+                if std::mem::replace(&mut first, false) {
+                    let version = parse_hello(&message);
+
+                    // The peers too old to announce their features cannot have those we require.
+                    let required = handler.required_features();
+                    let announces = version.and_then(negotiate) >= Some(FEATURES_VERSION);
+                    if required != Features::NONE && !announces {
+                        warn!(
+                            "Peer {} speaks protocol version {}, too old to have the features {}, \
+                            which we require",
+                            peer,
+                            version.unwrap_or(1),
+                            required
+                        );
+                        return;
+                    }
+
+                    if let Some(version) = version {
+                        let negotiated = match negotiate(version) {
+                            Some(x) => {
+                                debug!("Speaking protocol version {} with {}", x, peer);
+                                if x >= IDLE_TIMEOUT_VERSION {
+                                    idle_timeout = Some(handler.idle_timeout());
+                                }
+                                x
+                            }
+                            None => {
+                                warn!(
+                                    "Peer {} speaks protocol version {}, which we no longer support",
+                                    peer, version
+                                );
+                                return;
+                            }
+                        };
+                        if let Err(e) = writer.send(hello()).await {
+                            warn!("Failed to send message to {}: {}", peer, e);
+                            return;
+                        }
+
+                        // Tell the peer which features we require, so that it can fail clearly.
+                        if negotiated >= FEATURES_VERSION {
+                            let frame =
+                                features_frame(context.features(), handler.required_features());
+                            if let Err(e) = writer.send(frame).await {
+                                warn!("Failed to send message to {}: {}", peer, e);
+                                return;
+                            }
+                            awaiting_features = true;
+                        }
+
+                        // Number the frames of the peer from a fresh nonce, so that the frames
+                        // captured from another connection cannot be replayed into this one. We
+                        // only number them over the connections authenticating the peer, which
+                        // authenticate the sequence numbers along with the frames: anyone on the
+                        // path of a plaintext connection could renumber the frames it replays.
+                        if negotiated >= REPLAY_PROTECTION_VERSION {
+                            let nonce = name.map(|_| nonce());
+                            if let Err(e) = writer.send(sequence_frame(nonce)).await {
+                                warn!("Failed to send message to {}: {}", peer, e);
+                                return;
+                            }
+                            window = nonce.map(ReplayWindow::new);
+                        }
+                        continue;
+                    }
+                }
+
+                // Check the features of the peer, which it announces right after its hello.
+                if std::mem::replace(&mut awaiting_features, false) {
+                    let features = match parse_features_frame(&message) {
+                        Some(x) => x,
+                        None => {
+                            warn!("Peer {} did not announce its features", peer);
+                            return;
+                        }
+                    };
+                    debug!(
+                        "Peer {} runs node version {} with features {}",
+                        peer, features.node_version, features.features
+                    );
+                    let missing = features.features.missing(handler.required_features());
+                    if missing != Features::NONE {
+                        warn!(
+                            "Peer {} (node version {}) lacks the features {}, which we require",
+                            peer, features.node_version, missing
+                        );
+                        return;
+                    }
+                    continue;
+                }
+
+                // Agree on the compression of the messages of the peer (if it offers one).
+                if let Some(offered) = parse_compression_frame(&message) {
+                    compression = match handler.accepts_compression() {
+                        true => offered,
+                        false => Compression::None,
+                    };
+                    debug!("Decompressing messages of {} with {:?}", peer, compression);
+                    if let Err(e) = writer.send(compression_frame(compression)).await {
+                        warn!("Failed to send message to {}: {}", peer, e);
+                        return;
+                    }
+                    continue;
+                }
+
+                // Answer the keep-alive probes of the peer.
+                if message[..] == PING[..] {
+                    if let Err(e) = writer.send(Bytes::from_static(PING)).await {
+                        warn!("Failed to send message to {}: {}", peer, e);
+                        return;
+                    }
+                    continue;
+                }
+
+                // Reject the frames replayed into the connection (if we number them).
+                let message = match &mut window {
+                    Some(window) => match window.check(message) {
+                        Some(message) => message,
+                        None => {
+                            warn!("Peer {} replayed a frame", peer);
+                            if let Some(metrics) = handler.metrics() {
+                                metrics.replayed_frames.inc();
+                            }
+                            return;
+                        }
+                    },
+                    None => message,
+                };
+
+                let message = match compression {
+                    Compression::None => message.freeze(),
+                    _ => match decode(message.freeze(), handler.max_frame_size()) {
+                        Ok(message) => message,
+                        Err(e @ DecodeError::TooLarge(..)) => {
+                            warn!("Peer {} sent a frame too large: {}", peer, e);
+                            let _ = writer.send(Bytes::from_static(FRAME_TOO_LARGE)).await;
+                            return;
+                        }
+                        Err(e) => {
+                            warn!("Failed to receive message from {}: {}", peer, e);
+                            return;
+                        }
+                    },
+                };
+
+                if let Err(e) = handler.dispatch(&mut writer, message).await {
+                    warn!("{}", e);
+                    return;
                 }
             }
             warn!("Connection closed by peer {}", peer);
         });
     }
 }
+
+/// Whether the error is the codec rejecting a frame larger than it accepts.
+fn is_frame_too_large(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|x| x.is::<LengthDelimitedCodecError>())
+}
+
+/// Returns the label under which we account for the bytes a peer sends us: the node it
+/// authenticated as or, failing that, its IP address (its ports change with each connection).
+fn peer_label(peer: &str, name: Option<PublicKey>) -> String {
+    match (name, peer.parse::<SocketAddr>()) {
+        (Some(name), _) => name.to_string(),
+        (None, Ok(address)) => address.ip().to_string(),
+        (None, Err(_)) => peer.to_string(),
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::context::NetworkContext;
+use crate::receiver::Connection;
+use crypto::{Digest, PublicKey, Signature};
+use ed25519_dalek::{Digest as _, Sha512};
+use log::{debug, info, warn};
+use rand::seq::SliceRandom as _;
+use rand::RngCore as _;
+use std::collections::BTreeSet;
+use std::convert::TryInto as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{copy_bidirectional, AsyncReadExt as _, AsyncWriteExt as _};
+
+#[cfg(test)]
+#[path = "tests/relay_tests.rs"]
+pub mod relay_tests;
+
+/// The prefix of the request a node sends to its relay to open a connection to another node
+/// (followed by the length and the text of the address of the node, and the public key of the
+/// node sending the request).
+const RELAY_MAGIC: &[u8; 8] = b"NARWHAL>";
+
+/// The prefix of the header a relay sends the validator before the frames of a connection it
+/// forwards (followed by the length and the text of the address of the peer). Its first four bytes
+/// are too large to be the length of a frame, so the header cannot be mistaken for one.
+const FORWARD_MAGIC: &[u8; 8] = b"NARWHAL<";
+
+/// The maximum length of the address of a relay request.
+const MAX_ADDRESS_LENGTH: usize = 64;
+
+/// Connects to the peer identified by the specified address, through one of its relays (if it has
+/// any, which resolve its address themselves) or directly to the address it resolved to.
+pub(crate) async fn connect(
+    context: &NetworkContext,
+    address: SocketAddr,
+    resolved: io::Result<SocketAddr>,
+) -> io::Result<Box<dyn Connection>> {
+    let mut relays = match context.relays(&address) {
+        Some(relays) => relays.clone(),
+        None => return context.transport().connect_node(address, resolved?).await,
+    };
+    relays.shuffle(&mut rand::thread_rng());
+    let mut error = io::Error::from(io::ErrorKind::NotFound);
+    for relay in relays {
+        match connect_through(context, relay, address).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                debug!("Failed to reach {} through relay {}: {}", address, relay, e);
+                error = e;
+            }
+        }
+    }
+    Err(error)
+}
+
+/// Asks a relay to open a connection to the specified address, returning the connection once the
+/// relay established it. We prove to the relay that we are its validator by signing its challenge.
+async fn connect_through(
+    context: &NetworkContext,
+    relay: SocketAddr,
+    address: SocketAddr,
+) -> io::Result<Box<dyn Connection>> {
+    let (name, secret) = context.identity().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "The relays only serve nodes with an identity",
+        )
+    })?;
+    let mut stream = context.connect(relay).await?;
+    let address = address.to_string();
+    let mut request = RELAY_MAGIC.to_vec();
+    request.push(address.len() as u8);
+    request.extend_from_slice(address.as_bytes());
+    request.extend_from_slice(&name.0);
+    stream.write_all(&request).await?;
+
+    let mut challenge = [0u8; 32];
+    stream.read_exact(&mut challenge).await?;
+    let signature = Signature::new(&challenge_digest(&challenge, &address), secret);
+    let signature = bincode::serialize(&signature).expect("Failed to serialize signature");
+    stream.write_all(&signature).await?;
+    match stream.read_u8().await? {
+        1 => Ok(stream),
+        _ => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+    }
+}
+
+/// Returns the digest the validator signs to prove its identity to its relay.
+fn challenge_digest(challenge: &[u8; 32], address: &str) -> Digest {
+    let mut hasher = Sha512::new();
+    hasher.update(RELAY_MAGIC);
+    hasher.update(challenge);
+    hasher.update(address.as_bytes());
+    Digest(hasher.finalize()[..32].try_into().unwrap())
+}
+
+/// The first bytes of a connection accepted from one of our relays.
+pub(crate) enum Forwarded {
+    /// The relay forwards us the connection of the peer with the specified address.
+    Peer(String),
+    /// The relay itself opened the connection, which starts with these bytes (the length of its
+    /// first frame).
+    Direct([u8; 4]),
+}
+
+/// Reads the header of a connection accepted from one of our relays (if it has any).
+pub(crate) async fn read_forwarded(connection: &mut Box<dyn Connection>) -> io::Result<Forwarded> {
+    let mut prefix = [0u8; 4];
+    connection.read_exact(&mut prefix).await?;
+    if prefix != FORWARD_MAGIC[..4] {
+        return Ok(Forwarded::Direct(prefix));
+    }
+    let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+    let mut magic = [0u8; 4];
+    connection.read_exact(&mut magic).await?;
+    if magic != FORWARD_MAGIC[4..] {
+        return Err(invalid());
+    }
+    let length = connection.read_u8().await? as usize;
+    if length > MAX_ADDRESS_LENGTH {
+        return Err(invalid());
+    }
+    let mut peer = vec![0u8; length];
+    connection.read_exact(&mut peer).await?;
+    String::from_utf8(peer)
+        .map(Forwarded::Peer)
+        .map_err(|_| invalid())
+}
+
+/// A relay (or sentry) node hiding the IP address of a validator: the other authorities connect to
+/// the relay (whose addresses the committee lists as those of the validator) and the relay forwards
+/// their connections to the validator, telling it the address of each peer. Conversely, the
+/// validator opens its connections to the other authorities through the relay, which only serves
+/// the validator (authenticated by its key) and only connects to the committee.
+pub struct Relay;
+
+impl Relay {
+    /// Spawns a relay accepting on its `internal` address the requests of the specified validator
+    /// to connect to the specified destinations, and forwarding the connections it accepts on each
+    /// external address to the matching address of the validator.
+    pub fn spawn(
+        internal: SocketAddr,
+        validator: PublicKey,
+        destinations: BTreeSet<SocketAddr>,
+        forwards: Vec<(SocketAddr, SocketAddr)>,
+        context: NetworkContext,
+    ) {
+        let destinations = Arc::new(destinations);
+        let cloned = context.clone();
+        tokio::spawn(async move {
+            let context = cloned;
+            let listener = context
+                .transport()
+                .bind(internal)
+                .await
+                .expect("Failed to bind address");
+            info!(
+                "Relaying the connections of the validator from {}",
+                internal
+            );
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        tokio::spawn(Self::open(
+                            stream,
+                            peer.address,
+                            validator,
+                            destinations.clone(),
+                            context.clone(),
+                        ));
+                    }
+                    Err(e) => warn!("Failed to accept connection: {}", e),
+                }
+            }
+        });
+
+        for (external, validator) in forwards {
+            let context = context.clone();
+            tokio::spawn(async move {
+                let listener = context
+                    .transport()
+                    .bind(external)
+                    .await
+                    .expect("Failed to bind address");
+                info!(
+                    "Forwarding the connections to {} to {}",
+                    external, validator
+                );
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, peer)) => {
+                            let peer = peer.address;
+                            tokio::spawn(Self::forward(stream, peer, validator, context.clone()));
+                        }
+                        Err(e) => warn!("Failed to accept connection: {}", e),
+                    }
+                }
+            });
+        }
+    }
+
+    /// Serves a request of the validator to open a connection to one of the destinations.
+    async fn open(
+        mut stream: Box<dyn Connection>,
+        peer: String,
+        validator: PublicKey,
+        destinations: Arc<BTreeSet<SocketAddr>>,
+        context: NetworkContext,
+    ) {
+        let (address, name) = match Self::read_request(&mut stream).await {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Invalid relay request from {}: {}", peer, e);
+                return;
+            }
+        };
+        if name != validator {
+            warn!("Refusing to relay {} claiming to be {}", peer, name);
+            let _ = stream.write_u8(0).await;
+            return;
+        }
+
+        // Ensure the peer holds the key of the validator.
+        let mut challenge = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        if stream.write_all(&challenge).await.is_err() {
+            return;
+        }
+        let mut signature = [0u8; 64];
+        if stream.read_exact(&mut signature).await.is_err() {
+            return;
+        }
+        let verified = bincode::deserialize::<Signature>(&signature)
+            .ok()
+            .filter(|x| {
+                let digest = challenge_digest(&challenge, &address.to_string());
+                x.verify(&digest, &validator).is_ok()
+            })
+            .is_some();
+        if !verified {
+            warn!("Refusing to relay {}: invalid signature", peer);
+            let _ = stream.write_u8(0).await;
+            return;
+        }
+
+        if !destinations.contains(&address) {
+            warn!("Refusing to relay {} to {}", peer, address);
+            let _ = stream.write_u8(0).await;
+            return;
+        }
+        let outgoing = match context.resolver().resolve(address).await {
+            Ok(resolved) => context.connect(resolved).await,
+            Err(e) => Err(e),
+        };
+        let mut outgoing = match outgoing {
+            Ok(x) => x,
+            Err(e) => {
+                debug!("Failed to relay {} to {}: {}", peer, address, e);
+                let _ = stream.write_u8(0).await;
+                return;
+            }
+        };
+        if stream.write_u8(1).await.is_err() {
+            return;
+        }
+        debug!("Relaying {} to {}", peer, address);
+        let _ = copy_bidirectional(&mut stream, &mut outgoing).await;
+    }
+
+    /// Reads the address and the public key of the sender of a relay request.
+    async fn read_request(stream: &mut Box<dyn Connection>) -> io::Result<(SocketAddr, PublicKey)> {
+        let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+        let mut magic = [0u8; 8];
+        stream.read_exact(&mut magic).await?;
+        if &magic != RELAY_MAGIC {
+            return Err(invalid());
+        }
+        let length = stream.read_u8().await? as usize;
+        if length > MAX_ADDRESS_LENGTH {
+            return Err(invalid());
+        }
+        let mut address = vec![0u8; length];
+        stream.read_exact(&mut address).await?;
+        let address = String::from_utf8(address)
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(invalid)?;
+        let mut name = [0u8; 32];
+        stream.read_exact(&mut name).await?;
+        Ok((address, PublicKey(name)))
+    }
+
+    /// Forwards a connection of another node to the validator, starting with the address of the
+    /// node (so that the validator may tell its peers apart).
+    async fn forward(
+        mut stream: Box<dyn Connection>,
+        peer: String,
+        validator: SocketAddr,
+        context: NetworkContext,
+    ) {
+        let outgoing = match context.resolver().resolve(validator).await {
+            Ok(resolved) => context.connect(resolved).await,
+            Err(e) => Err(e),
+        };
+        let mut outgoing = match outgoing {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to forward {} to {}: {}", peer, validator, e);
+                return;
+            }
+        };
+        let mut header = FORWARD_MAGIC.to_vec();
+        header.push(peer.len() as u8);
+        header.extend_from_slice(peer.as_bytes());
+        if let Err(e) = outgoing.write_all(&header).await {
+            warn!("Failed to forward {} to {}: {}", peer, validator, e);
+            return;
+        }
+        debug!("Forwarding {} to {}", peer, validator);
+        let _ = copy_bidirectional(&mut stream, &mut outgoing).await;
+    }
+}
@@ -1,21 +1,30 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::compression::{encode, Compression};
+use crate::context::NetworkContext;
 use crate::error::NetworkError;
+use crate::metrics::{NetworkMetrics, PeerMetrics};
+use crate::queue::{Priority, PriorityQueue, QueueLimits};
+use crate::sequence::Sequencer;
+use crate::version::{
+    codec, handshake, negotiate_compression, FRAME_OVERHEAD, FRAME_TOO_LARGE, KEEP_ALIVE_VERSION,
+    MAX_FRAME_SIZE, PING,
+};
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use log::{info, warn};
+use log::{debug, info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
-use rand::SeedableRng as _;
-use std::cmp::min;
+use rand::{Rng as _, SeedableRng as _};
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
-use tokio::time::{sleep, Duration};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/reliable_sender_tests.rs"]
@@ -24,15 +33,119 @@ pub mod reliable_sender_tests;
 /// Convenient alias for cancel handlers returned to the caller task.
 pub type CancelHandler = oneshot::Receiver<Bytes>;
 
+/// The default delay after which we probe an idle connection (or a connection whose peer does not
+/// reply) to check that it is still alive.
+pub(crate) const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The default delay after which we close a connection over which we sent nothing (we re-connect
+/// on the next message). Until then, keep-alive probes prevent middleboxes from dropping it.
+pub(crate) const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The delay after which we re-resolve the hostname of a connected peer (if it has one), to move
+/// to its new address if it changed.
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How a connection re-attempts to reach its peer.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// The delay before the first re-attempt, doubled after each failed attempt (in ms).
+    pub initial_delay: u64,
+    /// The maximum delay between two attempts (in ms).
+    pub max_delay: u64,
+    /// The fraction (between 0 and 1) of each delay we randomly shave off, so that the nodes that
+    /// lost the same peer do not all re-attempt at the same time.
+    pub jitter: f64,
+    /// The number of consecutive failed attempts after which we abandon the messages waiting for
+    /// the peer (if set). Their cancel handlers then resolve to an error, and the peer is down
+    /// until we reach it again (we keep re-attempting in the background).
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: 200,
+            max_delay: 60_000,
+            jitter: 0.5,
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait after the specified number of consecutive failed attempts.
+    fn delay(&self, failures: u32, rng: &mut SmallRng) -> Duration {
+        let exponent = failures.saturating_sub(1).min(32);
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jitter = rng.gen_range(0.0, self.jitter.clamp(0.0, 1.0) + f64::EPSILON);
+        Duration::from_millis(delay).mul_f64(1.0 - jitter.min(1.0))
+    }
+}
+
+/// The health of a peer, as tracked by its connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerHealth {
+    /// We are connected to the peer (or did not fail to reach it yet).
+    Healthy,
+    /// We recently failed to reach the peer (or lost the connection to it), but did not give up.
+    Degraded,
+    /// The peer exhausted its budget of connection attempts. Broadcasts skip it until we reach it
+    /// again.
+    Down,
+}
+
+/// The health of a peer, shared between the sender and the connection to the peer.
+#[derive(Clone, Default)]
+struct SharedHealth(Arc<AtomicU8>);
+
+impl SharedHealth {
+    fn get(&self) -> PeerHealth {
+        match self.0.load(Ordering::Relaxed) {
+            0 => PeerHealth::Healthy,
+            1 => PeerHealth::Degraded,
+            _ => PeerHealth::Down,
+        }
+    }
+
+    fn set(&self, health: PeerHealth) -> PeerHealth {
+        let previous = self.get();
+        self.0.store(health as u8, Ordering::Relaxed);
+        previous
+    }
+}
+
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 /// This sender is 'reliable' in the sense that it keeps trying to re-transmit messages for which it didn't
-/// receive an ACK back (until they succeed or are canceled).
+/// receive an ACK back (until they succeed, are canceled, or the peer stays unreachable for longer than the
+/// `RetryPolicy` allows). All messages to a peer are pipelined over its connection, which we probe when it
+/// goes quiet to detect (and replace) half-open connections. Broadcasts skip the peers that are down.
 pub struct ReliableSender {
     /// A map holding the channels to our connections.
     connections: HashMap<SocketAddr, Sender<InnerMessage>>,
+    /// The health of the peers we have a connection to.
+    health: HashMap<SocketAddr, SharedHealth>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// The delay after which we probe a quiet connection.
+    keep_alive: Duration,
+    /// The delay after which we close a connection over which we sent nothing.
+    idle_timeout: Duration,
+    /// How our connections re-attempt to reach their peer.
+    retry_policy: RetryPolicy,
+    /// Bounds the messages each connection holds for its peer.
+    queue: QueueLimits,
+    /// The metrics of our connections.
+    metrics: NetworkMetrics,
+    /// The maximum size of the messages we send.
+    max_frame_size: usize,
+    /// The compression we offer to our peers.
+    compression: Compression,
+    /// How we reach our peers.
+    context: NetworkContext,
 }
 
 impl std::default::Default for ReliableSender {
@@ -45,26 +158,135 @@ impl ReliableSender {
     pub fn new() -> Self {
         Self {
             connections: HashMap::new(),
+            health: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            keep_alive: KEEP_ALIVE_INTERVAL,
+            idle_timeout: IDLE_TIMEOUT,
+            retry_policy: RetryPolicy::default(),
+            queue: QueueLimits::default(),
+            metrics: NetworkMetrics::default(),
+            max_frame_size: MAX_FRAME_SIZE,
+            compression: Compression::None,
+            context: NetworkContext::default(),
+        }
+    }
+
+    /// Returns a sender reaching its peers as specified by the context.
+    pub fn with_context(self, context: NetworkContext) -> Self {
+        Self { context, ..self }
+    }
+
+    /// Returns a sender reporting the state of its connections to the specified metrics.
+    pub fn with_metrics(self, metrics: NetworkMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Returns a sender probing its quiet connections after the specified delay. A connection is
+    /// dropped (and re-established) if the peer does not reply within twice that delay.
+    pub fn with_keep_alive(self, keep_alive: Duration) -> Self {
+        Self { keep_alive, ..self }
+    }
+
+    /// Returns a sender closing the connections over which it sent nothing for the specified delay.
+    /// The connection is re-established with the next message.
+    pub fn with_idle_timeout(self, idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout,
+            ..self
+        }
+    }
+
+    /// Returns a sender re-attempting to reach its peers as specified.
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+
+    /// Returns a sender bounding the messages each connection holds for its peer as specified.
+    /// Dropped messages notify their caller through their cancel handler (as if abandoned).
+    pub fn with_queue(self, queue: QueueLimits) -> Self {
+        Self { queue, ..self }
+    }
+
+    /// Returns a sender dropping the messages larger than the specified size (their cancel
+    /// handlers resolve to an error right away).
+    pub fn with_max_frame_size(self, max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            ..self
+        }
+    }
+
+    /// Returns a sender offering to compress its connections as specified. Each peer accepts or
+    /// declines the offer when we connect (older peers always decline).
+    pub fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            compression,
+            ..self
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    fn spawn_connection(&self, address: SocketAddr, health: SharedHealth) -> Sender<InnerMessage> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(
+            address,
+            rx,
+            health,
+            self.keep_alive,
+            self.idle_timeout,
+            self.retry_policy,
+            self.queue,
+            self.max_frame_size,
+            self.compression,
+            self.metrics.clone(),
+            self.context.clone(),
+        );
         tx
     }
 
-    /// Reliably send a message to a specific address.
+    /// Returns the health of the specified peer.
+    pub fn health(&self, address: &SocketAddr) -> PeerHealth {
+        self.health
+            .get(address)
+            .map_or(PeerHealth::Healthy, |x| x.get())
+    }
+
+    /// Reliably send a message to a specific address. The returned cancel handler resolves to the
+    /// reply of the peer, or to an error if we abandon the message because the peer is unreachable.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
+        self.send_with_priority(address, data, Priority::default())
+            .await
+    }
+
+    /// Reliably send a message of the specified priority to a specific address. The connection to
+    /// the peer sends its queued messages of higher priority first.
+    pub async fn send_with_priority(
+        &mut self,
+        address: SocketAddr,
+        data: Bytes,
+        priority: Priority,
+    ) -> CancelHandler {
         let (sender, receiver) = oneshot::channel();
-        self.connections
-            .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
+        if data.len() + FRAME_OVERHEAD > self.max_frame_size {
+            let max_size = self.max_frame_size.saturating_sub(FRAME_OVERHEAD);
+            let error = NetworkError::FrameTooLarge(address, data.len(), max_size);
+            warn!("{}", error);
+            return receiver;
+        }
+        if !self.connections.contains_key(&address) {
+            let health = SharedHealth::default();
+            let connection = self.spawn_connection(address, health.clone());
+            self.connections.insert(address, connection);
+            self.health.insert(address, health);
+        }
+        self.connections[&address]
             .send(InnerMessage {
                 data,
                 cancel_handler: sender,
+                priority,
             })
             .await
             .expect("Failed to send internal message");
@@ -77,17 +299,40 @@ impl ReliableSender {
         &mut self,
         addresses: Vec<SocketAddr>,
         data: Bytes,
+    ) -> Vec<CancelHandler> {
+        self.broadcast_with_priority(addresses, data, Priority::default())
+            .await
+    }
+
+    /// Broadcast the message of the specified priority to all specified addresses in a reliable
+    /// manner. It returns a vector of cancel handlers ordered as the input `addresses` vector. The
+    /// handlers of the peers that are down resolve to an error right away.
+    pub async fn broadcast_with_priority(
+        &mut self,
+        addresses: Vec<SocketAddr>,
+        data: Bytes,
+        priority: Priority,
     ) -> Vec<CancelHandler> {
         let mut handlers = Vec::new();
         for address in addresses {
-            let handler = self.send(address, data.clone()).await;
+            if self.health(&address) == PeerHealth::Down {
+                // Their connection keeps re-attempting to reach them in the background.
+                debug!("Skipping {}: the peer is down", address);
+                self.metrics.skipped_messages.inc();
+                let (_, handler) = oneshot::channel();
+                handlers.push(handler);
+                continue;
+            }
+            let handler = self
+                .send_with_priority(address, data.clone(), priority)
+                .await;
             handlers.push(handler);
         }
         handlers
     }
 
-    /// Pick a few addresses at random (specified by `nodes`) and send the message only to them.
-    /// It returns a vector of cancel handlers with no specific order.
+    /// Pick a few addresses at random (specified by `nodes`), preferring the peers that are not down,
+    /// and send the message only to them. It returns a vector of cancel handlers with no specific order.
     pub async fn lucky_broadcast(
         &mut self,
         mut addresses: Vec<SocketAddr>,
@@ -95,6 +340,7 @@ impl ReliableSender {
         nodes: usize,
     ) -> Vec<CancelHandler> {
         addresses.shuffle(&mut self.rng);
+        addresses.sort_by_key(|x| self.health(x) == PeerHealth::Down);
         addresses.truncate(nodes);
         self.broadcast(addresses, data).await
     }
@@ -108,6 +354,8 @@ struct InnerMessage {
     /// The cancel handler allowing the caller task to cancel the transmission of this message
     /// and to be notified of its successfully transmission.
     cancel_handler: oneshot::Sender<Bytes>,
+    /// The priority of the message.
+    priority: Priority,
 }
 
 /// A connection is responsible to reliably establish (and keep alive) a connection with a single peer.
@@ -116,133 +364,381 @@ struct Connection {
     address: SocketAddr,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<InnerMessage>,
-    /// The initial delay to wait before re-attempting a connection (in ms).
-    retry_delay: u64,
-    /// Buffer keeping all messages that need to be re-transmitted.
-    buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    /// The health of the peer.
+    health: SharedHealth,
+    /// How we re-attempt to reach the peer.
+    retry_policy: RetryPolicy,
+    /// The number of consecutive failed attempts to reach the peer.
+    failures: u32,
+    /// Small RNG just used to jitter the delays between attempts (not crypto related).
+    rng: SmallRng,
+    /// Buffer keeping all messages that need to be re-transmitted (bounded and by priority).
+    buffer: PriorityQueue<(Bytes, oneshot::Sender<Bytes>)>,
+    /// The delay after which we probe the connection when it goes quiet.
+    keep_alive: Duration,
+    /// The delay after which we close the connection if we sent nothing over it.
+    idle_timeout: Duration,
+    /// The maximum size of the messages we send.
+    max_frame_size: usize,
+    /// The compression we offer to the peer.
+    compression: Compression,
+    /// The metrics of the connections.
+    metrics: NetworkMetrics,
+    /// The metrics of this connection.
+    peer_metrics: PeerMetrics,
+    /// The number of pending messages we last reported to the metrics.
+    reported: i64,
+    /// How we reach the peer.
+    context: NetworkContext,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    #[allow(clippy::too_many_arguments)]
+    fn spawn(
+        address: SocketAddr,
+        receiver: Receiver<InnerMessage>,
+        health: SharedHealth,
+        keep_alive: Duration,
+        idle_timeout: Duration,
+        retry_policy: RetryPolicy,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        compression: Compression,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
+    ) {
         tokio::spawn(async move {
             Self {
                 address,
                 receiver,
-                retry_delay: 200,
-                buffer: VecDeque::new(),
+                health,
+                retry_policy,
+                failures: 0,
+                rng: SmallRng::from_entropy(),
+                buffer: PriorityQueue::new(queue),
+                keep_alive,
+                idle_timeout,
+                max_frame_size,
+                compression,
+                peer_metrics: metrics.peer(&address),
+                metrics,
+                reported: 0,
+                context,
             }
             .run()
             .await;
         });
     }
 
+    /// Updates the health of the peer.
+    fn set_health(&mut self, health: PeerHealth) {
+        let previous = self.health.set(health);
+        if previous != health && health == PeerHealth::Down {
+            warn!("Peer {} is down", self.address);
+            self.metrics.down_peers.inc();
+        } else if previous == PeerHealth::Down && health != PeerHealth::Down {
+            info!("Peer {} is back up", self.address);
+            self.metrics.down_peers.dec();
+        }
+    }
+
+    /// Reports the number of messages waiting to be sent (or acknowledged) to the metrics.
+    fn report_pending(&mut self, in_flight: usize) {
+        let pending = (self.buffer.len() + in_flight) as i64;
+        self.metrics.pending_messages.add(pending - self.reported);
+        self.reported = pending;
+    }
+
+    /// Adds a message to the buffer, dropping a message (which notifies its caller through its
+    /// cancel handler) if the buffer is full.
+    fn enqueue(&mut self, message: InnerMessage) {
+        let InnerMessage {
+            data,
+            cancel_handler,
+            priority,
+        } = message;
+        self.buffer.retain(|(_, handler)| !handler.is_closed());
+        if self.buffer.push(priority, (data, cancel_handler)).is_some() {
+            debug!("Queue of {} is full: dropping a message", self.address);
+            self.metrics.dropped_messages.inc();
+        }
+    }
+
+    /// Moves the messages waiting in the channel to the buffer (as long as the buffer accepts them),
+    /// so that we send the messages of higher priority first.
+    fn drain_channel(&mut self) {
+        while self.buffer.accepts() {
+            match self.receiver.try_recv() {
+                Ok(message) => self.enqueue(message),
+                Err(_) => break,
+            }
+        }
+    }
+
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
-        let mut delay = self.retry_delay;
-        let mut retry = 0;
+        let mut first = true;
         loop {
-            match TcpStream::connect(self.address).await {
+            if !std::mem::replace(&mut first, false) {
+                self.metrics.reconnects.inc();
+                self.peer_metrics.retries.inc();
+            }
+            let resolved = self.context.resolver().resolve(self.address).await;
+            let peer = resolved.as_ref().ok().copied();
+            let error = match self.context.open(self.address, resolved).await {
                 Ok(stream) => {
                     info!("Outgoing connection established with {}", self.address);
 
-                    // Reset the delay.
-                    delay = self.retry_delay;
-                    retry = 0;
-
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
                     // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
-                    warn!("{}", error);
+                    self.keep_alive(stream, peer).await
                 }
-                Err(e) => {
-                    warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
-                    let timer = sleep(Duration::from_millis(delay));
-                    tokio::pin!(timer);
-
-                    'waiter: loop {
-                        tokio::select! {
-                            // Wait an increasing delay before attempting to reconnect.
-                            () = &mut timer => {
-                                delay = min(2*delay, 60_000);
-                                retry +=1;
-                                break 'waiter;
-                            },
-
-                            // Drain the channel into the buffer to not saturate the channel and block the caller task.
-                            // The caller is responsible to cleanup the buffer through the cancel handlers.
-                            Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
-                                self.buffer.retain(|(_, handler)| !handler.is_closed());
-                            }
-                        }
+                Err(e) => NetworkError::FailedToConnect(self.address, self.failures, e),
+            };
+            // Closing an idle connection is not a failure either: we re-connect once we have a
+            // message to send.
+            if let NetworkError::IdleConnection(_) = error {
+                debug!("{}", error);
+                self.metrics.idle_evictions.inc();
+                match self.receiver.recv().await {
+                    Some(message) => self.enqueue(message),
+                    None => return,
+                }
+                self.report_pending(0);
+                first = true;
+                continue;
+            }
+            // Moving to the new address of the peer is not a failure to reach it.
+            if let NetworkError::AddressChanged(..) = error {
+                info!("{}", error);
+                continue;
+            }
+            if let NetworkError::KeepAliveTimeout(_) = error {
+                self.metrics.keep_alive_timeouts.inc();
+            }
+            warn!("{}", error);
+            self.failures += 1;
+
+            // Give up on the messages waiting for the peer once it exhausted its budget of attempts
+            // (we keep trying to reach it in the background, but broadcasts skip it meanwhile).
+            if self
+                .retry_policy
+                .max_attempts
+                .is_some_and(|max| self.failures >= max)
+            {
+                self.set_health(PeerHealth::Down);
+                self.abandon();
+            } else {
+                self.set_health(PeerHealth::Degraded);
+            }
+
+            // Wait an increasing delay before attempting to reconnect.
+            let timer = sleep(self.retry_policy.delay(self.failures, &mut self.rng));
+            tokio::pin!(timer);
+            'waiter: loop {
+                tokio::select! {
+                    () = &mut timer => break 'waiter,
+
+                    // Drain the channel into the buffer to not saturate the channel and block the caller task
+                    // (unless the queue is full and blocks the caller). The caller is responsible to cleanup
+                    // the buffer through the cancel handlers.
+                    Some(message) = self.receiver.recv(), if self.buffer.accepts() => {
+                        self.enqueue(message);
+                        self.report_pending(0);
                     }
                 }
             }
         }
     }
 
+    /// Drops the messages waiting for the peer, notifying their callers through their cancel handlers.
+    fn abandon(&mut self) {
+        self.buffer.retain(|(_, handler)| !handler.is_closed());
+        if self.buffer.len() == 0 {
+            return;
+        }
+        warn!(
+            "Abandoning {} messages to {} after {} failed attempts",
+            self.buffer.len(),
+            self.address,
+            self.failures
+        );
+        self.metrics
+            .abandoned_messages
+            .inc_by(self.buffer.len() as u64);
+        self.buffer.clear();
+        self.report_pending(0);
+    }
+
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    async fn keep_alive(
+        &mut self,
+        stream: Box<dyn crate::receiver::Connection>,
+        peer: Option<SocketAddr>,
+    ) -> NetworkError {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
 
-        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        // Agree on the version of the protocol (accepting replies as large as our peers accept by
+        // default).
+        let mut transport = Framed::new(stream, codec(self.max_frame_size.max(MAX_FRAME_SIZE)));
+        let features = self.context.features();
+        let (version, nonce) = match handshake(&mut transport, self.address, features).await {
+            Ok(x) => x,
+            Err(e) => return e,
+        };
+        let mut sequencer = Sequencer::new(nonce);
+        debug!(
+            "Speaking protocol version {} with {}",
+            version, self.address
+        );
+        let compression =
+            match negotiate_compression(&mut transport, self.address, version, self.compression)
+                .await
+            {
+                Ok(compression) => compression,
+                Err(e) => return e,
+            };
+        debug!(
+            "Compressing messages to {} with {:?}",
+            self.address, compression
+        );
+        self.failures = 0;
+        self.set_health(PeerHealth::Healthy);
+
+        // Older peers do not answer keep-alive probes.
+        let probe = version >= KEEP_ALIVE_VERSION;
+        let timer = sleep(self.keep_alive);
+        tokio::pin!(timer);
+        let mut probing = false;
+
+        // Re-resolve the hostname of the peer (if it has one) from time to time.
+        let resolving = self.context.resolver().has_hostname(&self.address);
+        let resolution = sleep(RESOLVE_INTERVAL);
+        tokio::pin!(resolution);
+
+        // Close the connection once we have nothing to send over it for a while.
+        let idle = sleep(self.idle_timeout);
+        tokio::pin!(idle);
+
+        self.metrics.connected_peers.inc();
+        let (mut writer, mut reader) = transport.split();
         let error = 'connection: loop {
-            // Try to send all messages of the buffer.
-            while let Some((data, handler)) = self.buffer.pop_front() {
+            // Try to send all messages of the buffer, highest priority first (picking up the messages
+            // of higher priority we get meanwhile).
+            self.drain_channel();
+            while let Some((priority, (data, handler))) = self.buffer.pop() {
                 // Skip messages that have been cancelled.
                 if handler.is_closed() {
                     continue;
                 }
 
                 // Try to send the message.
-                match writer.send(data.clone()).await {
+                let frame = sequencer.tag(encode(compression, &data));
+                let size = frame.len() as u64;
+                match writer.send(frame).await {
                     Ok(()) => {
                         // The message has been sent, we remove it from the buffer and add it to
                         // `pending_replies` while we wait for an ACK.
-                        pending_replies.push_back((data, handler));
+                        self.peer_metrics.bytes_sent.inc_by(size);
+                        pending_replies.push_back((priority, data, handler, Instant::now()));
+                        idle.as_mut().reset(Instant::now() + self.idle_timeout);
                     }
                     Err(e) => {
                         // We failed to send the message, we put it back into the buffer.
-                        self.buffer.push_front((data, handler));
+                        self.buffer.push_front(priority, (data, handler));
                         break 'connection NetworkError::FailedToSendMessage(self.address, e);
                     }
                 }
+                self.drain_channel();
             }
 
+            self.report_pending(pending_replies.len());
+
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
-                Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
+                Some(message) = self.receiver.recv() => {
                     // Add the message to the buffer of messages to send.
-                    self.buffer.push_back((data, cancel_handler));
+                    self.enqueue(message);
                 },
                 response = reader.next() => {
-                    let (data, handler) = match pending_replies.pop_front() {
+                    let (priority, data, handler, sent) = match pending_replies.pop_front() {
                         Some(message) => message,
                         None => break 'connection NetworkError::UnexpectedAck(self.address)
                     };
                     match response {
+                        Some(Ok(bytes)) if bytes[..] == FRAME_TOO_LARGE[..] => {
+                            // Drop the message (which notifies its caller): it would be rejected
+                            // again. The peer closes the connection.
+                            break 'connection NetworkError::FrameRejected(self.address);
+                        },
                         Some(Ok(bytes)) => {
+                            self.peer_metrics.bytes_received.inc_by(bytes.len() as u64);
+                            self.peer_metrics.round_trip.observe(sent.elapsed().as_secs_f64());
+
                             // Notify the handler that the message has been successfully sent.
                             let _ = handler.send(bytes.freeze());
+
+                            // The peer is alive.
+                            probing = false;
+                            timer.as_mut().reset(Instant::now() + self.keep_alive);
                         },
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
                             // Put the message back in the buffer, we will try to send it again.
-                            pending_replies.push_front((data, handler));
+                            pending_replies.push_front((priority, data, handler, sent));
                             break 'connection NetworkError::FailedToReceiveAck(self.address);
                         }
                     }
                 },
+                () = &mut timer, if probe => {
+                    // The peer did not reply to our previous probe (nor to anything else).
+                    if probing {
+                        break 'connection NetworkError::KeepAliveTimeout(self.address);
+                    }
+
+                    // Probe the connection. Nobody waits for the reply, so the probe is dropped
+                    // (rather than re-transmitted) if we lose the connection.
+                    let ping = Bytes::from_static(PING);
+                    if let Err(e) = writer.send(ping.clone()).await {
+                        break 'connection NetworkError::FailedToSendMessage(self.address, e);
+                    }
+                    self.peer_metrics.bytes_sent.inc_by(ping.len() as u64);
+                    let (handler, _) = oneshot::channel();
+                    pending_replies.push_back((Priority::Control, ping, handler, Instant::now()));
+                    probing = true;
+                    timer.as_mut().reset(Instant::now() + self.keep_alive);
+                },
+                () = &mut idle => {
+                    // Only close the connection once the peer acknowledged all our messages.
+                    let acknowledged = pending_replies.iter().all(|(_, data, ..)| data[..] == PING[..]);
+                    if acknowledged && self.buffer.len() == 0 {
+                        break 'connection NetworkError::IdleConnection(self.address);
+                    }
+                    idle.as_mut().reset(Instant::now() + self.idle_timeout);
+                },
+                () = &mut resolution, if resolving => {
+                    let resolved = self.context.resolver().resolve(self.address).await;
+                    match resolved {
+                        Ok(resolved) if Some(resolved) != peer => {
+                            break 'connection NetworkError::AddressChanged(self.address, resolved);
+                        }
+                        _ => (),
+                    }
+                    resolution.as_mut().reset(Instant::now() + RESOLVE_INTERVAL);
+                },
             }
         };
+        self.metrics.connected_peers.dec();
 
         // If we reach this code, it means something went wrong. Put the messages for which we didn't receive an ACK
         // back into the sending buffer, we will try to send them again once we manage to establish a new connection.
-        while let Some(message) = pending_replies.pop_back() {
-            self.buffer.push_front(message);
+        while let Some((priority, data, handler, _)) = pending_replies.pop_back() {
+            self.buffer.push_front(priority, (data, handler));
         }
+        self.buffer.retain(|(_, handler)| !handler.is_closed());
+        self.report_pending(0);
         error
     }
 }
@@ -0,0 +1,91 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::lookup_host;
+
+#[cfg(test)]
+#[path = "tests/resolver_tests.rs"]
+pub mod resolver_tests;
+
+/// The hostname of a peer and the address it last resolved to (if it ever did).
+struct Hostname {
+    hostname: String,
+    resolved: Option<SocketAddr>,
+}
+
+/// Resolves the hostnames of the peers known by hostname, identified by a placeholder address (see
+/// `config::hostname_address`). We only resolve a hostname when connecting to its peer, so that
+/// peers whose hostname does not resolve yet do not hold back the others. Cloning the resolver
+/// shares it.
+#[derive(Clone, Default)]
+pub(crate) struct Resolver {
+    hostnames: Arc<Mutex<BTreeMap<SocketAddr, Hostname>>>,
+}
+
+impl Resolver {
+    /// Makes a resolver of the specified hostnames, by the address identifying their peer.
+    pub fn new(hostnames: BTreeMap<SocketAddr, String>) -> Self {
+        let hostnames = hostnames
+            .into_iter()
+            .map(|(address, hostname)| {
+                let entry = Hostname {
+                    hostname,
+                    resolved: None,
+                };
+                (address, entry)
+            })
+            .collect();
+        Self {
+            hostnames: Arc::new(Mutex::new(hostnames)),
+        }
+    }
+
+    /// Whether the peer identified by the specified address is known by hostname.
+    pub fn has_hostname(&self, address: &SocketAddr) -> bool {
+        self.hostnames.lock().unwrap().contains_key(address)
+    }
+
+    /// Returns the address to connect to the peer identified by the specified address, resolving
+    /// its hostname (if any). Falls back to the address it last resolved to if the resolution fails,
+    /// and fails if it never resolved.
+    pub async fn resolve(&self, address: SocketAddr) -> io::Result<SocketAddr> {
+        let (hostname, last) = match self.hostnames.lock().unwrap().get(&address) {
+            Some(entry) => (entry.hostname.clone(), entry.resolved),
+            None => return Ok(address),
+        };
+
+        // Prefer the IPv4 addresses of the hostname (if it has any).
+        let resolved = match lookup_host(&hostname).await {
+            Ok(addresses) => {
+                let addresses: Vec<_> = addresses.collect();
+                addresses
+                    .iter()
+                    .find(|x| x.is_ipv4())
+                    .or_else(|| addresses.first())
+                    .copied()
+            }
+            Err(e) => {
+                warn!("Failed to resolve {}: {}", hostname, e);
+                None
+            }
+        };
+        let resolved = match (resolved, last) {
+            (Some(x), _) => x,
+            (None, Some(last)) => return Ok(last),
+            (None, None) => {
+                let message = format!("{} does not resolve", hostname);
+                return Err(io::Error::new(io::ErrorKind::NotFound, message));
+            }
+        };
+        if Some(resolved) != last {
+            debug!("{} now resolves to {}", hostname, resolved);
+            if let Some(entry) = self.hostnames.lock().unwrap().get_mut(&address) {
+                entry.resolved = Some(resolved);
+            }
+        }
+        Ok(resolved)
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receiver::{MessageHandler, Writer};
+use crate::reliable_sender::{CancelHandler, ReliableSender};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use thiserror::Error;
+use tokio::time::{timeout_at, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/rpc_tests.rs"]
+pub mod rpc_tests;
+
+/// The default delay after which we give up waiting for the response to a request.
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request or a response, tagged with the id correlating the response to its request.
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    id: u64,
+    payload: T,
+}
+
+#[derive(Error, Debug)]
+pub enum RpcError {
+    #[error("Request to {0} timed out")]
+    Timeout(SocketAddr),
+
+    #[error("Gave up reaching {0}")]
+    Unreachable(SocketAddr),
+
+    #[error("Failed to decode the response of {0}: {1}")]
+    InvalidResponse(SocketAddr, bincode::Error),
+
+    #[error("Response of {0} answers request {2} instead of {1}")]
+    MismatchedResponse(SocketAddr, u64, u64),
+
+    #[error("Peer {0} failed to serve the request: {1}")]
+    Remote(SocketAddr, String),
+}
+
+/// Serves the typed requests of the peers (see `RpcServer`).
+#[async_trait]
+pub trait RpcHandler: Clone + Send + Sync + 'static {
+    type Request: DeserializeOwned + Send;
+    type Response: Serialize + Send;
+
+    /// Returns the response to a request, or the reason why we cannot serve it (sent to the peer).
+    async fn handle(&self, request: Self::Request) -> Result<Self::Response, Box<dyn Error>>;
+}
+
+/// A message handler answering each request with the response of the `RpcHandler`, tagged with
+/// the id of the request. Requests failing to decode drop the connection. The handlers of other
+/// messages may also dispatch the requests embedded in their own messages (see
+/// `RpcClient::with_wrapper`) to a server.
+#[derive(Clone)]
+pub struct RpcServer<Handler: RpcHandler> {
+    handler: Handler,
+}
+
+impl<Handler: RpcHandler> RpcServer<Handler> {
+    pub fn new(handler: Handler) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl<Handler: RpcHandler> MessageHandler for RpcServer<Handler> {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let request: Envelope<Handler::Request> = bincode::deserialize(&message)?;
+        let payload = self
+            .handler
+            .handle(request.payload)
+            .await
+            .map_err(|e| e.to_string());
+        let response = Envelope {
+            id: request.id,
+            payload,
+        };
+        let bytes = bincode::serialize(&response).expect("Failed to serialize our own response");
+        writer.send(Bytes::from(bytes)).await?;
+        Ok(())
+    }
+}
+
+/// Sends typed requests to the peers serving them with a `RpcServer`, over a reliable sender.
+pub struct RpcClient<Request, Response> {
+    /// The sender carrying the requests.
+    network: ReliableSender,
+    /// The id of our next request.
+    next_id: u64,
+    /// The delay after which we give up waiting for a response.
+    timeout: Duration,
+    /// Embeds each serialized request into a message of the protocol of the peers (if any).
+    wrapper: Option<fn(Vec<u8>) -> Vec<u8>>,
+    _types: PhantomData<fn(Request) -> Response>,
+}
+
+impl<Request: Serialize, Response: DeserializeOwned> RpcClient<Request, Response> {
+    /// Returns a client sending its requests over the specified sender.
+    pub fn new(network: ReliableSender) -> Self {
+        Self {
+            network,
+            next_id: 0,
+            timeout: RPC_TIMEOUT,
+            wrapper: None,
+            _types: PhantomData,
+        }
+    }
+
+    /// Returns a client giving up on its requests after the specified delay.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Returns a client embedding each serialized request into a message of the protocol of the
+    /// peers, so that they may serve the requests on the port of their other messages.
+    pub fn with_wrapper(self, wrapper: fn(Vec<u8>) -> Vec<u8>) -> Self {
+        Self {
+            wrapper: Some(wrapper),
+            ..self
+        }
+    }
+
+    /// Sends a request, returning the handle of its response (so that several requests may be
+    /// in flight at once). The timeout of the request starts now.
+    pub async fn send(
+        &mut self,
+        address: SocketAddr,
+        request: Request,
+    ) -> PendingResponse<Response> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let envelope = Envelope {
+            id,
+            payload: request,
+        };
+        let mut bytes = bincode::serialize(&envelope).expect("Failed to serialize our own request");
+        if let Some(wrapper) = self.wrapper {
+            bytes = wrapper(bytes);
+        }
+        let deadline = Instant::now() + self.timeout;
+        let handler = self.network.send(address, Bytes::from(bytes)).await;
+        PendingResponse {
+            address,
+            id,
+            deadline,
+            handler,
+            _type: PhantomData,
+        }
+    }
+
+    /// Sends a request and waits for its response.
+    pub async fn call(
+        &mut self,
+        address: SocketAddr,
+        request: Request,
+    ) -> Result<Response, RpcError> {
+        self.send(address, request).await.response().await
+    }
+}
+
+/// The response to a request in flight. Dropping it cancels the request (if not sent yet).
+pub struct PendingResponse<Response> {
+    address: SocketAddr,
+    id: u64,
+    deadline: Instant,
+    handler: CancelHandler,
+    _type: PhantomData<fn() -> Response>,
+}
+
+impl<Response: DeserializeOwned> PendingResponse<Response> {
+    /// Waits for the response (until the timeout of the request).
+    pub async fn response(self) -> Result<Response, RpcError> {
+        let address = self.address;
+        let bytes = match timeout_at(self.deadline, self.handler).await {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(_)) => return Err(RpcError::Unreachable(address)),
+            Err(_) => return Err(RpcError::Timeout(address)),
+        };
+        let response: Envelope<Result<Response, String>> =
+            bincode::deserialize(&bytes).map_err(|e| RpcError::InvalidResponse(address, e))?;
+        if response.id != self.id {
+            return Err(RpcError::MismatchedResponse(address, self.id, response.id));
+        }
+        response.payload.map_err(|e| RpcError::Remote(address, e))
+    }
+}
@@ -0,0 +1,371 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::noise;
+use crate::receiver::Connection;
+use crypto::{PublicKey, SecretKey};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::AlwaysResolvesClientRawPublicKeys;
+use rustls::crypto::{ring, verify_tls13_signature_with_raw_key, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, SubjectPublicKeyInfoDer,
+    UnixTime,
+};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::AlwaysResolvesServerRawPublicKeys;
+use rustls::sign::CertifiedKey;
+use rustls::version::TLS13;
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, DistinguishedName, ServerConfig,
+    SignatureScheme,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::{TryFrom as _, TryInto as _};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::{timeout, Duration};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+#[cfg(test)]
+#[path = "tests/security_tests.rs"]
+pub mod security_tests;
+
+/// The delay after which we give up on the handshake securing a connection.
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The header of the SubjectPublicKeyInfo of an ed25519 key (followed by the 32-byte key), the
+/// raw public key by which the nodes authenticate over TLS (RFC 7250).
+const SPKI_HEADER: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// How the nodes secure the connections between them (the connections of the clients are never
+/// secured by the nodes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Security {
+    /// The connections are neither authenticated nor encrypted.
+    Plaintext,
+    /// The nodes run TLS 1.3 over their connections, authenticating each other by the ed25519 key
+    /// the committee lists for them (as raw public keys, without certificate authority).
+    Tls,
+    /// The nodes run the Noise IK handshake over their connections, with the X25519 form of the
+    /// ed25519 key the committee lists for them, then encrypt their messages.
+    Noise,
+}
+
+/// How we run the handshake securing a connection.
+#[derive(Clone)]
+enum Handshake {
+    Tls {
+        connector: TlsConnector,
+        acceptor: TlsAcceptor,
+    },
+    Noise {
+        /// The X25519 form of our secret key.
+        secret: Arc<[u8; 32]>,
+        /// The nodes of the committee, by the X25519 form of their key.
+        keys: Arc<BTreeMap<[u8; 32], PublicKey>>,
+    },
+}
+
+/// Secures the connections between the nodes of the committee: we only connect to the node the
+/// committee lists at each address, and only accept the connections of the nodes of the committee.
+/// Cloning it shares it.
+#[derive(Clone)]
+pub(crate) struct SecureChannels {
+    /// The key of the node listening on each address of the committee.
+    peers: Arc<BTreeMap<SocketAddr, PublicKey>>,
+    /// The handshake we run over the connections.
+    handshake: Handshake,
+}
+
+impl SecureChannels {
+    /// Secures the connections of the node with the specified keys to the specified peers (the key
+    /// of the node listening on each address of the committee).
+    pub fn new(
+        security: Security,
+        name: &PublicKey,
+        secret: &SecretKey,
+        peers: BTreeMap<SocketAddr, PublicKey>,
+    ) -> io::Result<Option<Self>> {
+        let handshake = match security {
+            Security::Plaintext => return Ok(None),
+            Security::Tls => tls_handshake(name, secret, peers.values().copied().collect())?,
+            Security::Noise => Handshake::Noise {
+                secret: Arc::new(secret.to_x25519()),
+                keys: Arc::new(
+                    peers
+                        .values()
+                        .filter_map(|x| x.to_x25519().map(|key| (key, *x)))
+                        .collect(),
+                ),
+            },
+        };
+        Ok(Some(Self {
+            peers: Arc::new(peers),
+            handshake,
+        }))
+    }
+
+    /// Secures a connection we opened to the node listening on the specified address, failing if
+    /// the node does not authenticate with the key the committee lists for this address.
+    pub async fn connect(
+        &self,
+        connection: Box<dyn Connection>,
+        address: SocketAddr,
+    ) -> io::Result<Box<dyn Connection>> {
+        let expected = self.peers.get(&address).ok_or_else(|| {
+            let message = format!("{} is not the address of a node of the committee", address);
+            io::Error::new(io::ErrorKind::PermissionDenied, message)
+        })?;
+        let (connection, name) = with_timeout(async {
+            match &self.handshake {
+                Handshake::Tls { connector, .. } => {
+                    // We authenticate the peer by its key rather than by its name.
+                    let server_name = ServerName::try_from("narwhal").expect("Invalid server name");
+                    let stream = connector.connect(server_name, connection).await?;
+                    let name = tls_peer(stream.get_ref().1.peer_certificates())?;
+                    Ok((Box::new(stream) as Box<dyn Connection>, name))
+                }
+                Handshake::Noise { secret, .. } => {
+                    // Only the node holding the expected key completes the handshake.
+                    let remote = expected.to_x25519().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "Invalid key")
+                    })?;
+                    let stream = noise::initiate(connection, secret, &remote).await?;
+                    Ok((Box::new(stream) as Box<dyn Connection>, *expected))
+                }
+            }
+        })
+        .await?;
+        if &name != expected {
+            let message = format!(
+                "{} authenticated as {} instead of {}",
+                address, name, expected
+            );
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+        }
+        Ok(connection)
+    }
+
+    /// Secures a connection we accepted, returning it along with the node of the committee it
+    /// comes from.
+    pub async fn accept(
+        &self,
+        connection: Box<dyn Connection>,
+    ) -> io::Result<(Box<dyn Connection>, PublicKey)> {
+        with_timeout(async {
+            match &self.handshake {
+                Handshake::Tls { acceptor, .. } => {
+                    let stream = acceptor.accept(connection).await?;
+                    let name = tls_peer(stream.get_ref().1.peer_certificates())?;
+                    Ok((Box::new(stream) as Box<dyn Connection>, name))
+                }
+                Handshake::Noise { secret, keys } => {
+                    let known =
+                        |x: &[u8]| <[u8; 32]>::try_from(x).is_ok_and(|x| keys.contains_key(&x));
+                    let (stream, remote) = noise::respond(connection, secret, known).await?;
+                    let remote: [u8; 32] = remote.try_into().expect("Unexpected key length");
+                    Ok((Box::new(stream) as Box<dyn Connection>, keys[&remote]))
+                }
+            }
+        })
+        .await
+    }
+}
+
+/// Runs a handshake, giving up after `HANDSHAKE_TIMEOUT`.
+async fn with_timeout<T>(
+    handshake: impl std::future::Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out"))?
+}
+
+/// Returns the raw public key (RFC 7250) of the specified ed25519 key.
+fn spki(name: &PublicKey) -> Vec<u8> {
+    let mut spki = SPKI_HEADER.to_vec();
+    spki.extend_from_slice(&name.0);
+    spki
+}
+
+/// Parses the ed25519 key of a raw public key (RFC 7250).
+fn parse_spki(spki: &[u8]) -> Option<PublicKey> {
+    match spki.strip_prefix(&SPKI_HEADER[..]) {
+        Some(key) => key.try_into().ok().map(PublicKey),
+        None => None,
+    }
+}
+
+/// Returns the key the peer of a TLS connection authenticated with.
+pub(crate) fn tls_peer(certificates: Option<&[CertificateDer<'_>]>) -> io::Result<PublicKey> {
+    certificates
+        .and_then(|x| x.first())
+        .and_then(|x| parse_spki(x))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "Peer did not authenticate"))
+}
+
+/// Makes the TLS handshake of the node with the specified keys, accepting the specified keys.
+fn tls_handshake(
+    name: &PublicKey,
+    secret: &SecretKey,
+    keys: BTreeSet<PublicKey>,
+) -> io::Result<Handshake> {
+    let (client, server) = tls_configs(name, secret, keys)?;
+    Ok(Handshake::Tls {
+        connector: TlsConnector::from(Arc::new(client)),
+        acceptor: TlsAcceptor::from(Arc::new(server)),
+    })
+}
+
+/// Makes the TLS 1.3 configurations (as client and as server) of the node with the specified keys,
+/// accepting the specified keys.
+pub(crate) fn tls_configs(
+    name: &PublicKey,
+    secret: &SecretKey,
+    keys: BTreeSet<PublicKey>,
+) -> io::Result<(ClientConfig, ServerConfig)> {
+    let invalid = |e: rustls::Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let provider = Arc::new(ring::default_provider());
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(secret.to_pkcs8()));
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(invalid)?;
+    let certified = Arc::new(CertifiedKey::new(
+        vec![CertificateDer::from(spki(name))],
+        key,
+    ));
+    let verifier = Arc::new(CommitteeVerifier {
+        keys,
+        algorithms: provider.signature_verification_algorithms,
+    });
+
+    let client = ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&TLS13])
+        .map_err(invalid)?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_client_cert_resolver(Arc::new(AlwaysResolvesClientRawPublicKeys::new(
+            certified.clone(),
+        )));
+    let server = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&TLS13])
+        .map_err(invalid)?
+        .with_client_cert_verifier(verifier)
+        .with_cert_resolver(Arc::new(AlwaysResolvesServerRawPublicKeys::new(certified)));
+    Ok((client, server))
+}
+
+/// Accepts the raw public keys of the nodes of the committee (as server and as client).
+#[derive(Debug)]
+struct CommitteeVerifier {
+    keys: BTreeSet<PublicKey>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl CommitteeVerifier {
+    fn verify(&self, spki: &CertificateDer<'_>) -> Result<(), rustls::Error> {
+        match parse_spki(spki) {
+            Some(key) if self.keys.contains(&key) => Ok(()),
+            Some(_) => Err(CertificateError::ApplicationVerificationFailure.into()),
+            None => Err(CertificateError::BadEncoding.into()),
+        }
+    }
+
+    fn verify_signature(
+        &self,
+        message: &[u8],
+        spki: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let spki = SubjectPublicKeyInfoDer::from(spki.as_ref());
+        verify_tls13_signature_with_raw_key(message, &spki, dss, &self.algorithms)
+    }
+}
+
+impl ServerCertVerifier for CommitteeVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verify(end_entity)
+            .map(|()| ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::General(
+            "TLS 1.2 is not supported".to_string(),
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+impl ClientCertVerifier for CommitteeVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.verify(end_entity)
+            .map(|()| ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::General(
+            "TLS 1.2 is not supported".to_string(),
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::{BufMut as _, Bytes, BytesMut};
+use std::convert::TryInto as _;
+
+#[cfg(test)]
+#[path = "tests/sequence_tests.rs"]
+pub mod sequence_tests;
+
+/// The number of sequence numbers (below the highest we received) we keep track of. Older frames
+/// are rejected.
+pub(crate) const REPLAY_WINDOW: u64 = 64;
+
+/// The size of the sequence number prefixing each frame.
+const SEQUENCE_SIZE: usize = 8;
+
+/// Returns a fresh nonce, the sequence number preceding the first frame of a connection. Nonces
+/// leave room for the sequence numbers of the connection before they overflow.
+pub(crate) fn nonce() -> u64 {
+    rand::random::<u64>() >> 1
+}
+
+/// Numbers the frames we send over a connection, from the nonce the receiver gave us (if any).
+pub(crate) struct Sequencer {
+    /// The sequence number of the last frame we sent, if the receiver numbers our frames.
+    last: Option<u64>,
+}
+
+impl Sequencer {
+    pub fn new(nonce: Option<u64>) -> Self {
+        Self { last: nonce }
+    }
+
+    /// Prefixes a frame with its sequence number (if the receiver numbers our frames).
+    pub fn tag(&mut self, frame: Bytes) -> Bytes {
+        let last = match &mut self.last {
+            Some(x) => x,
+            None => return frame,
+        };
+        *last += 1;
+        let mut tagged = BytesMut::with_capacity(SEQUENCE_SIZE + frame.len());
+        tagged.put_u64_le(*last);
+        tagged.put(frame);
+        tagged.freeze()
+    }
+}
+
+/// Rejects the frames of a connection whose sequence number we already received, or which are
+/// too old to tell. The window is a bitmap of the sequence numbers below the highest we received.
+pub(crate) struct ReplayWindow {
+    /// The highest sequence number we received (starting with the nonce of the connection).
+    highest: u64,
+    /// The sequence numbers we received, the lowest bit standing for `highest`.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    pub fn new(nonce: u64) -> Self {
+        Self {
+            highest: nonce,
+            seen: 1,
+        }
+    }
+
+    /// Strips the sequence number of a frame, returning the frame if it is not a replay (nor too
+    /// short to hold a sequence number).
+    pub fn check(&mut self, mut frame: BytesMut) -> Option<BytesMut> {
+        if frame.len() < SEQUENCE_SIZE {
+            return None;
+        }
+        let sequence = frame.split_to(SEQUENCE_SIZE);
+        let sequence = u64::from_le_bytes(sequence[..].try_into().unwrap());
+        if sequence > self.highest {
+            let shift = sequence - self.highest;
+            self.seen = match shift < REPLAY_WINDOW {
+                true => self.seen << shift | 1,
+                false => 1,
+            };
+            self.highest = sequence;
+        } else {
+            let offset = self.highest - sequence;
+            if offset >= REPLAY_WINDOW || self.seen & (1 << offset) != 0 {
+                return None;
+            }
+            self.seen |= 1 << offset;
+        }
+        Some(frame)
+    }
+}
@@ -1,17 +1,27 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::compression::{encode, Compression};
+use crate::context::NetworkContext;
 use crate::error::NetworkError;
+use crate::metrics::NetworkMetrics;
+use crate::queue::{Priority, PriorityQueue, QueueLimits};
+use crate::reliable_sender::{IDLE_TIMEOUT, KEEP_ALIVE_INTERVAL};
+use crate::sequence::Sequencer;
+use crate::version::{
+    codec, handshake, negotiate_compression, FRAME_OVERHEAD, FRAME_TOO_LARGE, KEEP_ALIVE_VERSION,
+    MAX_FRAME_SIZE, PING,
+};
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use log::{info, warn};
+use log::{debug, info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::net::TcpStream;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::codec::Framed;
 
 #[cfg(test)]
 #[path = "tests/simple_sender_tests.rs"]
@@ -21,9 +31,23 @@ pub mod simple_sender_tests;
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 pub struct SimpleSender {
     /// A map holding the channels to our connections.
-    connections: HashMap<SocketAddr, Sender<Bytes>>,
+    connections: HashMap<SocketAddr, Sender<(Bytes, Priority)>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// Bounds the messages each connection holds for its peer.
+    queue: QueueLimits,
+    /// The metrics of our connections.
+    metrics: NetworkMetrics,
+    /// The maximum size of the messages we send.
+    max_frame_size: usize,
+    /// The compression we offer to our peers.
+    compression: Compression,
+    /// The delay after which we probe a quiet connection.
+    keep_alive: Duration,
+    /// The delay after which we close a connection over which we sent nothing.
+    idle_timeout: Duration,
+    /// How we reach our peers.
+    context: NetworkContext,
 }
 
 impl std::default::Default for SimpleSender {
@@ -37,29 +61,112 @@ impl SimpleSender {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            queue: QueueLimits::default(),
+            metrics: NetworkMetrics::default(),
+            max_frame_size: MAX_FRAME_SIZE,
+            compression: Compression::None,
+            keep_alive: KEEP_ALIVE_INTERVAL,
+            idle_timeout: IDLE_TIMEOUT,
+            context: NetworkContext::default(),
+        }
+    }
+
+    /// Returns a sender reaching its peers as specified by the context.
+    pub fn with_context(self, context: NetworkContext) -> Self {
+        Self { context, ..self }
+    }
+
+    /// Returns a sender bounding the messages each connection holds for its peer as specified.
+    pub fn with_queue(self, queue: QueueLimits) -> Self {
+        Self { queue, ..self }
+    }
+
+    /// Returns a sender reporting the messages it drops to the specified metrics.
+    pub fn with_metrics(self, metrics: NetworkMetrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Returns a sender dropping the messages larger than the specified size.
+    pub fn with_max_frame_size(self, max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size,
+            ..self
+        }
+    }
+
+    /// Returns a sender offering to compress its connections as specified. Each peer accepts or
+    /// declines the offer when we connect (older peers always decline).
+    pub fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    /// Returns a sender probing its quiet connections after the specified delay. A connection is
+    /// dropped if the peer does not reply within twice that delay.
+    pub fn with_keep_alive(self, keep_alive: Duration) -> Self {
+        Self { keep_alive, ..self }
+    }
+
+    /// Returns a sender closing the connections over which it sent nothing for the specified delay.
+    /// The connection is re-established with the next message.
+    pub fn with_idle_timeout(self, idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout,
+            ..self
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
+    fn spawn_connection(&self, address: SocketAddr) -> Sender<(Bytes, Priority)> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(
+            address,
+            rx,
+            self.queue,
+            self.max_frame_size,
+            self.compression,
+            self.keep_alive,
+            self.idle_timeout,
+            self.metrics.clone(),
+            self.context.clone(),
+        );
         tx
     }
 
     /// Try (best-effort) to send a message to a specific address.
     /// This is useful to answer sync requests.
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) {
+        self.send_with_priority(address, data, Priority::default())
+            .await
+    }
+
+    /// Try (best-effort) to send a message of the specified priority to a specific address. The
+    /// connection to the peer sends its queued messages of higher priority first.
+    pub async fn send_with_priority(
+        &mut self,
+        address: SocketAddr,
+        data: Bytes,
+        priority: Priority,
+    ) {
+        if data.len() + FRAME_OVERHEAD > self.max_frame_size {
+            let max_size = self.max_frame_size.saturating_sub(FRAME_OVERHEAD);
+            let error = NetworkError::FrameTooLarge(address, data.len(), max_size);
+            warn!("{}", error);
+            return;
+        }
+
         // Try to re-use an existing connection if possible.
         if let Some(tx) = self.connections.get(&address) {
-            if tx.send(data.clone()).await.is_ok() {
+            if tx.send((data.clone(), priority)).await.is_ok() {
                 return;
             }
         }
 
         // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
-        if tx.send(data).await.is_ok() {
+        let tx = self.spawn_connection(address);
+        if tx.send((data, priority)).await.is_ok() {
             self.connections.insert(address, tx);
         }
     }
@@ -90,45 +197,175 @@ struct Connection {
     /// The destination address.
     address: SocketAddr,
     /// Channel from which the connection receives its commands.
-    receiver: Receiver<Bytes>,
+    receiver: Receiver<(Bytes, Priority)>,
+    /// The messages waiting to be sent (bounded and by priority).
+    buffer: PriorityQueue<Bytes>,
+    /// The maximum size of the messages we send.
+    max_frame_size: usize,
+    /// The compression we offer to the peer.
+    compression: Compression,
+    /// The delay after which we probe the connection when it goes quiet.
+    keep_alive: Duration,
+    /// The delay after which we close the connection if we sent nothing over it.
+    idle_timeout: Duration,
+    /// The metrics of the connections.
+    metrics: NetworkMetrics,
+    /// How we reach the peer.
+    context: NetworkContext,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    #[allow(clippy::too_many_arguments)]
+    fn spawn(
+        address: SocketAddr,
+        receiver: Receiver<(Bytes, Priority)>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        compression: Compression,
+        keep_alive: Duration,
+        idle_timeout: Duration,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
+    ) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self {
+                address,
+                receiver,
+                buffer: PriorityQueue::new(queue),
+                max_frame_size,
+                compression,
+                keep_alive,
+                idle_timeout,
+                metrics,
+                context,
+            }
+            .run()
+            .await;
         });
     }
 
+    /// Adds a message to the buffer, dropping a message if the buffer is full.
+    fn enqueue(&mut self, data: Bytes, priority: Priority) {
+        if self.buffer.push(priority, data).is_some() {
+            debug!("Queue of {} is full: dropping a message", self.address);
+            self.metrics.dropped_messages.inc();
+        }
+    }
+
+    /// Moves the messages waiting in the channel to the buffer (as long as the buffer accepts them),
+    /// so that we send the messages of higher priority first.
+    fn drain_channel(&mut self) {
+        while self.buffer.accepts() {
+            match self.receiver.try_recv() {
+                Ok((data, priority)) => self.enqueue(data, priority),
+                Err(_) => break,
+            }
+        }
+    }
+
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
-        // Try to connect to the peer.
-        let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
-            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
+        // Try to connect to the peer, queuing the messages we get meanwhile.
+        let address = self.address;
+        let context = self.context.clone();
+        let connect = async move {
+            let resolved = context.resolver().resolve(address).await;
+            context.open(address, resolved).await
+        };
+        tokio::pin!(connect);
+        let mut transport = loop {
+            tokio::select! {
+                result = &mut connect => match result {
+                    // Also accept replies as large as our peers accept by default.
+                    Ok(stream) => break Framed::new(stream, codec(self.max_frame_size.max(MAX_FRAME_SIZE))),
+                    Err(e) => {
+                        warn!(
+                            "{}",
+                            NetworkError::FailedToConnect(self.address, /* retry */ 0, e)
+                        );
+                        return;
+                    }
+                },
+                Some((data, priority)) = self.receiver.recv(), if self.buffer.accepts() => {
+                    self.enqueue(data, priority);
+                }
+            }
+        };
+        info!("Outgoing connection established with {}", self.address);
+
+        // Agree on the version of the protocol.
+        let features = self.context.features();
+        let (version, nonce) = match handshake(&mut transport, self.address, features).await {
+            Ok(x) => x,
             Err(e) => {
-                warn!(
-                    "{}",
-                    NetworkError::FailedToConnect(self.address, /* retry */ 0, e)
-                );
+                warn!("{}", e);
                 return;
             }
         };
-        info!("Outgoing connection established with {}", self.address);
+        debug!(
+            "Speaking protocol version {} with {}",
+            version, self.address
+        );
+        let compression =
+            match negotiate_compression(&mut transport, self.address, version, self.compression)
+                .await
+            {
+                Ok(compression) => compression,
+                Err(e) => {
+                    warn!("{}", e);
+                    return;
+                }
+            };
+        debug!(
+            "Compressing messages to {} with {:?}",
+            self.address, compression
+        );
+        let (mut writer, mut reader) = transport.split();
+        let peer_metrics = self.metrics.peer(&self.address);
+        let mut sequencer = Sequencer::new(nonce);
+
+        // Older peers do not answer keep-alive probes.
+        let probe = version >= KEEP_ALIVE_VERSION;
+        let timer = sleep(self.keep_alive);
+        tokio::pin!(timer);
+        let mut probing = false;
+
+        // Close the connection once we have nothing to send over it for a while.
+        let idle = sleep(self.idle_timeout);
+        tokio::pin!(idle);
 
         // Transmit messages once we have established a connection.
         loop {
+            // Send the messages we queued, highest priority first (picking up the messages of higher
+            // priority we get meanwhile).
+            self.drain_channel();
+            while let Some((_, data)) = self.buffer.pop() {
+                let frame = sequencer.tag(encode(compression, &data));
+                let size = frame.len() as u64;
+                if let Err(e) = writer.send(frame).await {
+                    warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
+                    return;
+                }
+                peer_metrics.bytes_sent.inc_by(size);
+                idle.as_mut().reset(Instant::now() + self.idle_timeout);
+                self.drain_channel();
+            }
+
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
-                Some(data) = self.receiver.recv() => {
-                    if let Err(e) = writer.send(data).await {
-                        warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
-                        return;
-                    }
-                },
+                Some((data, priority)) = self.receiver.recv() => self.enqueue(data, priority),
                 response = reader.next() => {
                     match response {
-                        Some(Ok(_)) => {
+                        Some(Ok(bytes)) => {
                             // Sink the reply.
+                            peer_metrics.bytes_received.inc_by(bytes.len() as u64);
+                            if bytes[..] == FRAME_TOO_LARGE[..] {
+                                warn!("{}", NetworkError::FrameRejected(self.address));
+                            }
+
+                            // The peer is alive.
+                            probing = false;
+                            timer.as_mut().reset(Instant::now() + self.keep_alive);
                         },
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
@@ -137,6 +374,42 @@ impl Connection {
                         }
                     }
                 },
+                () = &mut timer, if probe => {
+                    // The peer did not reply to our previous probe (nor to anything else).
+                    if probing {
+                        warn!("{}", NetworkError::KeepAliveTimeout(self.address));
+                        self.metrics.keep_alive_timeouts.inc();
+                        return;
+                    }
+
+                    // Probe the connection.
+                    if let Err(e) = writer.send(Bytes::from_static(PING)).await {
+                        warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
+                        return;
+                    }
+                    peer_metrics.bytes_sent.inc_by(PING.len() as u64);
+                    probing = true;
+                    timer.as_mut().reset(Instant::now() + self.keep_alive);
+                },
+                () = &mut idle => {
+                    debug!("{}", NetworkError::IdleConnection(self.address));
+                    self.metrics.idle_evictions.inc();
+
+                    // Stop accepting messages (the sender then opens a new connection), and send
+                    // those we got meanwhile.
+                    self.receiver.close();
+                    while let Ok((data, priority)) = self.receiver.try_recv() {
+                        self.enqueue(data, priority);
+                    }
+                    while let Some((_, data)) = self.buffer.pop() {
+                        let frame = sequencer.tag(encode(compression, &data));
+                        if let Err(e) = writer.send(frame).await {
+                            warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
+                            return;
+                        }
+                    }
+                    return;
+                },
             }
         }
     }
@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{accept_handshake, Features};
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
@@ -10,8 +11,11 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 pub fn listener(address: SocketAddr, expected: String) -> JoinHandle<()> {
     tokio::spawn(async move {
         let listener = TcpListener::bind(&address).await.unwrap();
-        let (socket, _) = listener.accept().await.unwrap();
-        let transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let (socket, peer) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        accept_handshake(&mut transport, peer, Features::NONE)
+            .await
+            .unwrap();
         let (mut writer, mut reader) = transport.split();
         match reader.next().await {
             Some(Ok(received)) => {
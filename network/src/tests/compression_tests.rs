@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn message(size: usize) -> Bytes {
+    Bytes::from(b"Hello, world! ".repeat(size / 14 + 1)[..size].to_vec())
+}
+
+#[test]
+fn encode_and_decode() {
+    for compression in [Compression::Snappy, Compression::Zstd] {
+        let data = message(10_000);
+        let encoded = encode(compression, &data);
+        assert_eq!(encoded[0], compression.tag());
+        assert!(encoded.len() < data.len());
+        assert_eq!(decode(encoded.clone(), data.len()).unwrap(), data);
+    }
+}
+
+#[test]
+fn small_messages_are_not_compressed() {
+    let data = message(MIN_COMPRESSED_SIZE - 1);
+    let encoded = encode(Compression::Zstd, &data);
+    assert_eq!(encoded[0], Compression::None.tag());
+    let decoded = decode(encoded.clone(), data.len()).unwrap();
+    assert_eq!(decoded, data);
+
+    // The messages sent as they are share the buffer of their frame.
+    assert_eq!(decoded.as_ptr(), encoded[1..].as_ptr());
+
+    // Connections that do not compress send their messages as they are.
+    assert_eq!(encode(Compression::None, &data), data);
+}
+
+#[test]
+fn reject_oversized_message() {
+    for compression in [Compression::Snappy, Compression::Zstd] {
+        let encoded = encode(compression, &message(10_000));
+        assert!(matches!(
+            decode(encoded.clone(), 9_999),
+            Err(DecodeError::TooLarge(10_000, 9_999))
+        ));
+    }
+}
+
+#[test]
+fn reject_invalid_message() {
+    assert!(matches!(
+        decode(Bytes::new(), 100),
+        Err(DecodeError::Invalid(_))
+    ));
+    assert!(matches!(
+        decode(Bytes::from_static(&[7, 1, 2]), 100),
+        Err(DecodeError::Invalid(_))
+    ));
+    assert!(matches!(
+        decode(Bytes::from_static(&[2, 1, 2]), 100),
+        Err(DecodeError::Invalid(_))
+    ));
+}
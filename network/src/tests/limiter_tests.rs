@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crypto::generate_keypair;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+#[tokio::test]
+async fn throttle_each_node() {
+    let limiter = InboundLimiter::new(2);
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, _) = generate_keypair(&mut rng);
+    let (other, _) = generate_keypair(&mut rng);
+
+    // Exhaust the quota of the node, over two connections.
+    let mut first = limiter.quota(Some(name));
+    let mut second = limiter.quota(Some(name));
+    assert!(!first.acquire().await);
+    assert!(!second.acquire().await);
+    assert!(first.try_acquire().is_some());
+
+    // Ensure the other nodes are not affected.
+    assert!(!limiter.quota(Some(other)).acquire().await);
+
+    // Ensure the node gets a new frame once its bucket refilled.
+    let now = Instant::now();
+    assert!(second.acquire().await);
+    assert!(now.elapsed() >= Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn throttle_each_anonymous_connection() {
+    let limiter = InboundLimiter::new(1);
+
+    // The connections that authenticated no node each get their own quota.
+    let mut first = limiter.quota(None);
+    assert!(!first.acquire().await);
+    assert!(first.try_acquire().is_some());
+    assert!(!limiter.quota(None).acquire().await);
+}
+
+#[test]
+fn bucket_delay() {
+    let mut bucket = TokenBucket::new(4);
+    assert_eq!(bucket.delay(), Duration::ZERO);
+    for _ in 0..4 {
+        assert!(bucket.try_acquire());
+    }
+    assert!(!bucket.try_acquire());
+    assert!(bucket.delay() > Duration::ZERO);
+    assert!(bucket.delay() <= Duration::from_millis(250));
+}
+
+#[test]
+fn bucket_without_limit() {
+    // The largest rates do not overflow.
+    let mut bucket = TokenBucket::new(u64::MAX);
+    assert!(bucket.is_full());
+    assert!(bucket.try_acquire());
+}
@@ -0,0 +1,79 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::receiver::Writer;
+use crate::{MessageHandler, NetworkContext, Receiver, ReliableSender};
+use std::error::Error;
+use tokio::time::timeout;
+
+#[derive(Clone)]
+struct AckHandler {
+    deliver: mpsc::Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for AckHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+/// Runs a receiver bound to the port of the address in a new in-memory network, and returns a
+/// sender connecting through the network.
+fn receiver(address: SocketAddr) -> (MemoryNetwork, ReliableSender, mpsc::Receiver<Bytes>) {
+    let network = MemoryNetwork::new();
+    let context = NetworkContext::new().with_transport(network.clone());
+    let (tx, rx) = mpsc::channel(100);
+    let mut unspecified = address;
+    unspecified.set_ip("0.0.0.0".parse().unwrap());
+    Receiver::spawn_with_context(unspecified, AckHandler { deliver: tx }, context.clone());
+    (network, ReliableSender::new().with_context(context), rx)
+}
+
+#[tokio::test]
+async fn send_in_memory() {
+    // The port is not bound: the message can only go through the in-memory network.
+    let address = "127.0.0.1:6500".parse::<SocketAddr>().unwrap();
+    let (_network, mut sender, mut rx) = receiver(address);
+
+    let message = Bytes::from("Hello, world!");
+    let cancel_handler = sender.send(address, message.clone()).await;
+    assert_eq!(cancel_handler.await.unwrap(), Bytes::from("Ack"));
+    assert_eq!(rx.recv().await, Some(message));
+}
+
+#[tokio::test]
+async fn latency() {
+    let address = "127.0.0.1:6501".parse::<SocketAddr>().unwrap();
+    let (network, mut sender, _rx) = receiver(address);
+    network.set_conditions(LinkConditions {
+        latency: Duration::from_millis(100),
+        loss: 0.0,
+    });
+
+    // The message and its ACK both take the latency of the link.
+    let now = Instant::now();
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(cancel_handler.await.is_ok());
+    assert!(now.elapsed() >= Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn partition() {
+    let address = "127.0.0.1:6502".parse::<SocketAddr>().unwrap();
+    let (network, mut sender, mut rx) = receiver(address);
+    network.partition(vec![address]);
+
+    // Nothing gets through the partition.
+    let message = Bytes::from("Hello, world!");
+    let cancel_handler = sender.send(address, message.clone()).await;
+    assert!(timeout(Duration::from_millis(500), rx.recv())
+        .await
+        .is_err());
+
+    // The message is delivered once the partition heals.
+    network.heal();
+    assert!(cancel_handler.await.is_ok());
+    assert_eq!(rx.recv().await, Some(message));
+}
@@ -0,0 +1,66 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn queue(policy: QueuePolicy) -> PriorityQueue<u32> {
+    PriorityQueue::new(QueueLimits {
+        capacity: 2,
+        policy,
+    })
+}
+
+#[test]
+fn drop_oldest() {
+    let mut queue = queue(QueuePolicy::DropOldest);
+    assert_eq!(queue.push(Priority::Bulk, 1), None);
+    assert_eq!(queue.push(Priority::Bulk, 2), None);
+    assert_eq!(queue.push(Priority::Bulk, 3), Some(1));
+    assert!(queue.accepts());
+    assert_eq!(queue.pop(), Some((Priority::Bulk, 2)));
+    assert_eq!(queue.pop(), Some((Priority::Bulk, 3)));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn drop_newest() {
+    let mut queue = queue(QueuePolicy::DropNewest);
+    assert_eq!(queue.push(Priority::Bulk, 1), None);
+    assert_eq!(queue.push(Priority::Bulk, 2), None);
+    assert_eq!(queue.push(Priority::Bulk, 3), Some(3));
+    assert!(queue.accepts());
+    assert_eq!(queue.pop(), Some((Priority::Bulk, 1)));
+    assert_eq!(queue.pop(), Some((Priority::Bulk, 2)));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn block() {
+    let mut queue = queue(QueuePolicy::Block);
+    assert_eq!(queue.push(Priority::Bulk, 1), None);
+    assert!(queue.accepts());
+    assert_eq!(queue.push(Priority::Bulk, 2), None);
+    assert!(!queue.accepts());
+}
+
+#[test]
+fn pop_by_priority() {
+    let mut queue = PriorityQueue::new(QueueLimits::default());
+    queue.push(Priority::Bulk, 1);
+    queue.push(Priority::Proposal, 2);
+    queue.push(Priority::Control, 3);
+    queue.push(Priority::Sync, 4);
+    queue.push(Priority::Control, 5);
+    let popped: Vec<_> = std::iter::from_fn(|| queue.pop().map(|(_, x)| x)).collect();
+    assert_eq!(popped, vec![3, 5, 2, 4, 1]);
+}
+
+#[test]
+fn drop_lower_priority_first() {
+    let mut queue = queue(QueuePolicy::DropOldest);
+    assert_eq!(queue.push(Priority::Control, 1), None);
+    assert_eq!(queue.push(Priority::Bulk, 2), None);
+
+    // A control message evicts the bulk message, but a bulk message never evicts a control one.
+    assert_eq!(queue.push(Priority::Control, 3), Some(2));
+    assert_eq!(queue.push(Priority::Bulk, 4), Some(4));
+    assert_eq!(queue.len(), 2);
+}
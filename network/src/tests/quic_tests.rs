@@ -0,0 +1,117 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{MessageHandler, NetworkContext, Receiver, ReliableSender, Writer};
+use bytes::Bytes;
+use crypto::generate_keypair;
+use futures::sink::SinkExt as _;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::error::Error;
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::sleep;
+
+/// Reports the node of the streams it receives messages from.
+#[derive(Clone)]
+struct NameHandler {
+    name: Option<PublicKey>,
+    deliver: Sender<Option<PublicKey>>,
+}
+
+#[async_trait]
+impl MessageHandler for NameHandler {
+    async fn dispatch(&self, writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(self.name).await.unwrap();
+        Ok(())
+    }
+
+    fn for_peer(&self, peer: &Peer) -> Self {
+        Self {
+            name: peer.name,
+            ..self.clone()
+        }
+    }
+}
+
+fn keys() -> Vec<(PublicKey, SecretKey)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..3).map(|_| generate_keypair(&mut rng)).collect()
+}
+
+/// Runs a receiver over QUIC with the first key (accepting the second one), and returns the
+/// channel of the nodes it attributes the messages it receives to.
+async fn receiver(address: SocketAddr) -> tokio::sync::mpsc::Receiver<Option<PublicKey>> {
+    let keys = keys();
+    let other = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let committee = vec![(address, keys[0].0), (other, keys[1].0)]
+        .into_iter()
+        .collect();
+    let transport = QuicTransport::new(&keys[0].0, &keys[0].1, committee).unwrap();
+    let (tx, rx) = channel(10);
+    let handler = NameHandler {
+        name: None,
+        deliver: tx,
+    };
+    let context = NetworkContext::new().with_peer_transport(transport);
+    Receiver::spawn_with_context(address, handler, context);
+    sleep(Duration::from_millis(50)).await;
+    rx
+}
+
+/// Returns the transport of the node with the specified key, expecting the specified key at the
+/// specified address.
+fn transport(sender: usize, address: SocketAddr, expected: usize) -> QuicTransport {
+    let keys = keys();
+    let peers = std::iter::once((address, keys[expected].0)).collect();
+    QuicTransport::new(&keys[sender].0, &keys[sender].1, peers).unwrap()
+}
+
+#[tokio::test]
+async fn multiplex_senders_over_one_connection() {
+    // Both senders reach the receiver over the same QUIC connection (on streams of their own), and
+    // the receiver learns the key of the node sending the messages.
+    let address = "127.0.0.1:7057".parse::<SocketAddr>().unwrap();
+    let mut rx = receiver(address).await;
+
+    let transport = transport(1, address, 0);
+    let mut handlers = Vec::new();
+    for _ in 0..2 {
+        let context = NetworkContext::new().with_peer_transport(transport.clone());
+        let mut network = ReliableSender::new().with_context(context);
+        handlers.push(network.send(address, Bytes::from("Hello")).await);
+        assert_eq!(rx.recv().await, Some(Some(keys()[1].0)));
+    }
+    for handler in handlers {
+        assert!(handler.await.is_ok());
+    }
+    assert_eq!(transport.connections.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn reject_unknown_client() {
+    // The receiver rejects the nodes outside the committee.
+    let address = "127.0.0.1:7058".parse::<SocketAddr>().unwrap();
+    let mut rx = receiver(address).await;
+
+    let context = NetworkContext::new().with_peer_transport(transport(2, address, 0));
+    let mut network = ReliableSender::new().with_context(context);
+    let _cancel_handler = network.send(address, Bytes::from("Hello")).await;
+    assert!(timeout(Duration::from_millis(500), rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn reject_unexpected_server() {
+    // The sender refuses to talk to another node than the one listed at the address.
+    let address = "127.0.0.1:7059".parse::<SocketAddr>().unwrap();
+    let mut rx = receiver(address).await;
+
+    let context = NetworkContext::new().with_peer_transport(transport(1, address, 1));
+    let mut network = ReliableSender::new().with_context(context);
+    let _cancel_handler = network.send(address, Bytes::from("Hello")).await;
+    assert!(timeout(Duration::from_millis(500), rx.recv())
+        .await
+        .is_err());
+}
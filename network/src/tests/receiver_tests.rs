@@ -1,6 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use futures::sink::SinkExt as _;
+use crate::sequence::Sequencer;
+use std::collections::BTreeMap;
+use tokio::io::AsyncWriteExt as _;
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Sender;
 use tokio::time::{sleep, Duration};
@@ -46,3 +48,206 @@ async fn receive() {
     let received = message.unwrap();
     assert_eq!(received, sent);
 }
+
+#[cfg(unix)]
+#[tokio::test]
+async fn receive_unix() {
+    // Make the network receiver.
+    let path = std::env::temp_dir().join("narwhal_test_receiver.sock");
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_unix(path.clone(), TestHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(bytes.clone()).await.unwrap();
+
+    // Ensure the message gets passed to the channel.
+    let received = rx.recv().await.unwrap();
+    assert_eq!(received, sent);
+}
+
+/// Only lets in the peers opening their connection with the expected token.
+struct TokenAcceptor(u8);
+
+#[async_trait]
+impl Acceptor for TokenAcceptor {
+    async fn accept(
+        &self,
+        mut socket: TcpStream,
+        _peer: SocketAddr,
+    ) -> io::Result<Box<dyn Connection>> {
+        match socket.read_u8().await? {
+            x if x == self.0 => Ok(Box::new(socket)),
+            _ => Err(io::Error::new(io::ErrorKind::PermissionDenied, "Bad token")),
+        }
+    }
+}
+
+#[tokio::test]
+async fn receive_with_acceptor() {
+    // Make the network receiver.
+    let address = "127.0.0.1:4010".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_with_acceptor(
+        address,
+        TestHandler { deliver: tx },
+        Arc::new(TokenAcceptor(7)),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure the messages of a peer failing the handshake are dropped.
+    let bytes = Bytes::from(bincode::serialize("Go away").unwrap());
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_u8(0).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let _ = transport.send(bytes).await;
+
+    // Ensure the messages of a peer passing the handshake get passed to the channel.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_u8(7).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(bytes).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), sent);
+}
+
+/// Closes the connections of silent peers quickly.
+#[derive(Clone)]
+struct IdleHandler {
+    metrics: NetworkMetrics,
+}
+
+#[async_trait]
+impl MessageHandler for IdleHandler {
+    async fn dispatch(&self, _writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(200)
+    }
+
+    fn metrics(&self) -> Option<NetworkMetrics> {
+        Some(self.metrics.clone())
+    }
+}
+
+#[tokio::test]
+async fn close_idle_connection() {
+    // Make the network receiver.
+    let address = "127.0.0.1:4020".parse::<SocketAddr>().unwrap();
+    let metrics = NetworkMetrics::default();
+    Receiver::spawn(
+        address,
+        IdleHandler {
+            metrics: metrics.clone(),
+        },
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Connect as a client (which may stay quiet) and as a peer (which should probe).
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    crate::version::handshake(&mut peer, address, Features::NONE)
+        .await
+        .unwrap();
+
+    // Ensure the receiver only closes the connection of the silent peer.
+    let closed = timeout(Duration::from_secs(1), peer.next()).await;
+    assert!(matches!(closed, Ok(None)));
+    assert_eq!(metrics.idle_evictions.get(), 1);
+    assert!(timeout(Duration::from_millis(300), client.next())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn reject_replayed_frame() {
+    // Make the network receiver, authenticating its peers.
+    let address = "127.0.0.1:4030".parse::<SocketAddr>().unwrap();
+    let (name, secret) = crypto::generate_keypair(&mut rand::rngs::OsRng);
+    let committee: BTreeMap<_, _> = std::iter::once((address, name)).collect();
+    let context = NetworkContext::new()
+        .with_identity(name, secret)
+        .with_security(crate::Security::Noise, committee)
+        .unwrap();
+    let metrics = NetworkMetrics::default();
+    Receiver::spawn_with_context(
+        address,
+        IdleHandler {
+            metrics: metrics.clone(),
+        },
+        context.clone(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Connect as a peer, numbering our frames from the nonce of the receiver.
+    let stream = context.open(address, Ok(address)).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    let (_, nonce) = crate::version::handshake(&mut peer, address, Features::NONE)
+        .await
+        .unwrap();
+    assert!(nonce.is_some());
+    let mut sequencer = Sequencer::new(nonce);
+
+    // Send a frame twice.
+    let frame = sequencer.tag(Bytes::from("Hello, world!"));
+    peer.send(frame.clone()).await.unwrap();
+    peer.send(frame).await.unwrap();
+
+    // Ensure the receiver rejects the replay and closes the connection.
+    let closed = timeout(Duration::from_millis(150), peer.next()).await;
+    assert!(matches!(closed, Ok(None)));
+    assert_eq!(metrics.replayed_frames.get(), 1);
+}
+
+#[tokio::test]
+async fn skip_numbering_plaintext_frames() {
+    // Make the network receiver, over plaintext connections.
+    let address = "127.0.0.1:4031".parse::<SocketAddr>().unwrap();
+    let metrics = NetworkMetrics::default();
+    Receiver::spawn(address, IdleHandler { metrics });
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure the receiver does not number the frames it could not authenticate.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    let (_, nonce) = crate::version::handshake(&mut peer, address, Features::NONE)
+        .await
+        .unwrap();
+    assert_eq!(nonce, None);
+}
+
+#[tokio::test]
+async fn count_received_bytes() {
+    // Make the network receiver.
+    let address = "127.0.0.1:4040".parse::<SocketAddr>().unwrap();
+    let metrics = NetworkMetrics::default();
+    Receiver::spawn(
+        address,
+        IdleHandler {
+            metrics: metrics.clone(),
+        },
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message as a client.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    let message = Bytes::from("Hello, world!");
+    client.send(message.clone()).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure the receiver accounts for the bytes of the client, by its IP address.
+    let received = metrics
+        .peer_bytes_received
+        .with_label_values(&["127.0.0.1"]);
+    assert_eq!(received.get(), message.len() as u64);
+}
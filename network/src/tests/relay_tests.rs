@@ -0,0 +1,150 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::listener;
+use crate::{MessageHandler, Peer, Receiver, ReliableSender, Writer};
+use async_trait::async_trait;
+use bytes::Bytes;
+use crypto::generate_keypair;
+use futures::sink::SinkExt as _;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::collections::BTreeMap;
+use std::error::Error;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// Reports the peers of the connections it receives messages from.
+#[derive(Clone)]
+struct PeerHandler {
+    peer: String,
+    deliver: Sender<String>,
+}
+
+#[async_trait]
+impl MessageHandler for PeerHandler {
+    async fn dispatch(&self, writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(self.peer.clone()).await.unwrap();
+        Ok(())
+    }
+
+    fn for_peer(&self, peer: &Peer) -> Self {
+        Self {
+            peer: peer.address.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+#[tokio::test]
+async fn relay_outgoing_connection() {
+    // Run a relay serving a validator, allowed to connect to a single destination.
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, secret) = generate_keypair(&mut rng);
+    let relay = "127.0.0.1:7001".parse::<SocketAddr>().unwrap();
+    let destination = "127.0.0.1:7000".parse::<SocketAddr>().unwrap();
+    let forbidden = "127.0.0.1:7002".parse::<SocketAddr>().unwrap();
+    let destinations = std::iter::once(destination).collect();
+    Relay::spawn(
+        relay,
+        name,
+        destinations,
+        Vec::new(),
+        NetworkContext::default(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Connect to both addresses through the relay.
+    let relays = vec![(destination, vec![relay]), (forbidden, vec![relay])];
+    let context = NetworkContext::new()
+        .with_relays(relays.into_iter().collect())
+        .with_identity(name, secret);
+    let message = "Hello, world!";
+    let handle = listener(destination, message.to_string());
+    let _forbidden_handle = listener(forbidden, message.to_string());
+    let mut sender = ReliableSender::new().with_context(context);
+    let delivered = sender.send(destination, Bytes::from(message)).await;
+    let refused = sender.send(forbidden, Bytes::from(message)).await;
+
+    // Ensure the relay only opens the connection to its destination.
+    assert!(delivered.await.is_ok());
+    assert!(handle.await.is_ok());
+    assert!(timeout(Duration::from_millis(500), refused).await.is_err());
+}
+
+#[tokio::test]
+async fn refuse_other_nodes() {
+    // Run a relay serving a validator.
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, _) = generate_keypair(&mut rng);
+    let (other, other_secret) = generate_keypair(&mut rng);
+    let relay = "127.0.0.1:7031".parse::<SocketAddr>().unwrap();
+    let destination = "127.0.0.1:7030".parse::<SocketAddr>().unwrap();
+    let destinations = std::iter::once(destination).collect();
+    Relay::spawn(
+        relay,
+        name,
+        destinations,
+        Vec::new(),
+        NetworkContext::default(),
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Try to connect through the relay as another node (claiming to be the validator or not).
+    let message = "Hello, world!";
+    let _handle = listener(destination, message.to_string());
+    let relays: BTreeMap<_, _> = std::iter::once((destination, vec![relay])).collect();
+    for identity in [(other, other_secret.duplicate()), (name, other_secret)] {
+        let context = NetworkContext::new()
+            .with_relays(relays.clone())
+            .with_identity(identity.0, identity.1);
+        let mut sender = ReliableSender::new().with_context(context);
+        let refused = sender.send(destination, Bytes::from(message)).await;
+
+        // Ensure the relay does not open the connection.
+        assert!(timeout(Duration::from_millis(500), refused).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn forward_incoming_connection() {
+    // Run a relay forwarding its external address to the validator.
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (name, _) = generate_keypair(&mut rng);
+    let internal = "127.0.0.1:7011".parse::<SocketAddr>().unwrap();
+    let external = "127.0.0.1:7012".parse::<SocketAddr>().unwrap();
+    let validator = "127.0.0.1:7010".parse::<SocketAddr>().unwrap();
+    let forwards = vec![(external, validator)];
+    Relay::spawn(
+        internal,
+        name,
+        BTreeSet::new(),
+        forwards,
+        NetworkContext::default(),
+    );
+
+    // Run the validator, which connects to its peers through the relay.
+    let peer = "127.0.0.1:7013".parse::<SocketAddr>().unwrap();
+    let relays = std::iter::once((peer, vec![internal])).collect();
+    let context = NetworkContext::new().with_relays(relays);
+    let (tx, mut rx) = channel(1);
+    let handler = PeerHandler {
+        peer: String::new(),
+        deliver: tx,
+    };
+    Receiver::spawn_with_context(validator, handler, context);
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message to the external address of the relay.
+    let stream = TcpStream::connect(external).await.unwrap();
+    let sender = stream.local_addr().unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(Bytes::from("Hello, world!")).await.unwrap();
+
+    // Ensure the validator gets the message, and learns the address of the sender rather than
+    // that of the relay.
+    let received = rx.recv().await.unwrap();
+    assert_eq!(received, sender.to_string());
+}
@@ -1,7 +1,29 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::listener;
+use crate::queue::QueuePolicy;
+use crate::receiver::Writer;
+use crate::version::{accept_handshake, Features};
+use crate::MessageHandler;
+use async_trait::async_trait;
 use futures::future::try_join_all;
+use std::error::Error;
+use tokio::net::TcpListener;
+use tokio_util::codec::LengthDelimitedCodec;
+
+#[derive(Clone)]
+struct AckHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for AckHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
 
 #[tokio::test]
 async fn send() {
@@ -65,3 +87,314 @@ async fn retry() {
     // Ensure the server received the message (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn keep_alive() {
+    // Run a receiver that acknowledges all messages (and answers our keep-alive probes).
+    let address = "127.0.0.1:5400".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    crate::Receiver::spawn(address, AckHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message, and then let the connection go quiet for a few probes.
+    let metrics = NetworkMetrics::default();
+    let mut sender = ReliableSender::new()
+        .with_keep_alive(Duration::from_millis(50))
+        .with_metrics(metrics.clone());
+    let cancel_handler = sender.send(address, Bytes::from("Hello, world!")).await;
+    assert!(cancel_handler.await.is_ok());
+    assert_eq!(rx.recv().await.unwrap(), "Hello, world!");
+    sleep(Duration::from_millis(300)).await;
+
+    // Ensure we kept the connection alive, and did not hand the probes to the handler.
+    assert_eq!(metrics.connected_peers.get(), 1);
+    assert_eq!(metrics.keep_alive_timeouts.get(), 0);
+    assert_eq!(metrics.pending_messages.get(), 0);
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn half_open_connection() {
+    // Run a TCP server that never replies after the handshake (as if the connection went half-open).
+    let address = "127.0.0.1:5500".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(10);
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        loop {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+            accept_handshake(&mut transport, peer, Features::NONE)
+                .await
+                .unwrap();
+            tx.send(transport).await.unwrap();
+        }
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message.
+    let metrics = NetworkMetrics::default();
+    let mut sender = ReliableSender::new()
+        .with_keep_alive(Duration::from_millis(50))
+        .with_metrics(metrics.clone());
+    let _cancel_handler = sender.send(address, Bytes::from("Hello, world!")).await;
+
+    // Ensure we drop the silent connection and connect again, re-transmitting the message.
+    let _first = rx.recv().await.unwrap();
+    let mut second = rx.recv().await.unwrap();
+    assert_eq!(metrics.keep_alive_timeouts.get(), 1);
+    assert_eq!(metrics.reconnects.get(), 1);
+    assert_eq!(metrics.pending_messages.get(), 1);
+    match second.next().await {
+        Some(Ok(received)) => assert_eq!(received, "Hello, world!"),
+        _ => panic!("Failed to receive network message"),
+    }
+}
+
+#[tokio::test]
+async fn abandon_unreachable_peer() {
+    // Make the network sender and send the message (no listeners are running).
+    let address = "127.0.0.1:5600".parse::<SocketAddr>().unwrap();
+    let message = "Hello, world!";
+    let metrics = NetworkMetrics::default();
+    let policy = RetryPolicy {
+        initial_delay: 10,
+        max_delay: 10,
+        jitter: 0.0,
+        max_attempts: Some(3),
+    };
+    let mut sender = ReliableSender::new()
+        .with_retry_policy(policy)
+        .with_metrics(metrics.clone());
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+
+    // Ensure we learn that the message was abandoned.
+    assert!(cancel_handler.await.is_err());
+    assert_eq!(metrics.abandoned_messages.get(), 1);
+    assert_eq!(metrics.pending_messages.get(), 0);
+
+    // Run a TCP server and send another message: we try to reach the peer again.
+    let handle = listener(address, message.to_string());
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+    assert!(cancel_handler.await.is_ok());
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn drop_oldest_when_full() {
+    // Make the network sender and send a few messages (no listeners are running).
+    let address = "127.0.0.1:5700".parse::<SocketAddr>().unwrap();
+    let metrics = NetworkMetrics::default();
+    let queue = QueueLimits {
+        capacity: 2,
+        policy: QueuePolicy::DropOldest,
+    };
+    let mut sender = ReliableSender::new()
+        .with_queue(queue)
+        .with_metrics(metrics.clone());
+    let mut handlers = Vec::new();
+    for message in ["Hello", "Hello, world", "Hello, world!"] {
+        handlers.push(sender.send(address, Bytes::from(message)).await);
+    }
+
+    // Ensure the oldest message is dropped (and its caller notified).
+    let mut handlers = handlers.into_iter();
+    assert!(handlers.next().unwrap().await.is_err());
+    assert_eq!(metrics.dropped_messages.get(), 1);
+
+    // Run a TCP server: the other messages are eventually delivered.
+    let handle = listener(address, "Hello, world".to_string());
+    assert!(handlers.next().unwrap().await.is_ok());
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn send_higher_priority_first() {
+    // Make the network sender and send a bulk then a control message (no listeners are running).
+    let address = "127.0.0.1:5800".parse::<SocketAddr>().unwrap();
+    let mut sender = ReliableSender::new();
+    let _bulk_handler = sender.send(address, Bytes::from("Hello")).await;
+    let cancel_handler = sender
+        .send_with_priority(address, Bytes::from("Hello, world!"), Priority::Control)
+        .await;
+
+    // Run a TCP server: the control message is sent first.
+    let handle = listener(address, "Hello, world!".to_string());
+    assert!(cancel_handler.await.is_ok());
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn peer_metrics() {
+    // Run a TCP server.
+    let address = "127.0.0.1:5900".parse::<SocketAddr>().unwrap();
+    let message = "Hello, world!";
+    let handle = listener(address, message.to_string());
+
+    // Make the network sender and send the message.
+    let metrics = NetworkMetrics::default();
+    let mut sender = ReliableSender::new().with_metrics(metrics.clone());
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+
+    // Ensure the traffic with the peer is accounted for.
+    let reply = cancel_handler.await.unwrap();
+    assert!(handle.await.is_ok());
+    let label = address.to_string();
+    let peer = [label.as_str()];
+    let bytes_sent = metrics.peer_bytes_sent.with_label_values(&peer).get();
+    let bytes_received = metrics.peer_bytes_received.with_label_values(&peer).get();
+    let round_trip = metrics.peer_round_trip.with_label_values(&peer);
+    assert_eq!(bytes_sent, message.len() as u64);
+    assert_eq!(bytes_received, reply.len() as u64);
+    assert_eq!(round_trip.get_sample_count(), 1);
+}
+
+#[tokio::test]
+async fn skip_down_peer() {
+    // Make the network sender and send a message (no listeners are running).
+    let address = "127.0.0.1:6000".parse::<SocketAddr>().unwrap();
+    let message = "Hello, world!";
+    let metrics = NetworkMetrics::default();
+    let policy = RetryPolicy {
+        initial_delay: 10,
+        max_delay: 10,
+        jitter: 0.0,
+        max_attempts: Some(2),
+    };
+    let mut sender = ReliableSender::new()
+        .with_retry_policy(policy)
+        .with_metrics(metrics.clone());
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+
+    // Ensure the peer is marked down once we abandon the message, and that broadcasts skip it.
+    assert!(cancel_handler.await.is_err());
+    assert_eq!(sender.health(&address), PeerHealth::Down);
+    assert_eq!(metrics.down_peers.get(), 1);
+    let handlers = sender.broadcast(vec![address], Bytes::from(message)).await;
+    assert!(try_join_all(handlers).await.is_err());
+    assert_eq!(metrics.skipped_messages.get(), 1);
+
+    // Run a TCP server: we reach the peer again in the background.
+    let handle = listener(address, message.to_string());
+    while sender.health(&address) != PeerHealth::Healthy {
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(metrics.down_peers.get(), 0);
+    let handlers = sender.broadcast(vec![address], Bytes::from(message)).await;
+    assert!(try_join_all(handlers).await.is_ok());
+    assert!(handle.await.is_ok());
+}
+
+#[derive(Clone)]
+struct SmallFramesHandler;
+
+#[async_trait]
+impl MessageHandler for SmallFramesHandler {
+    async fn dispatch(&self, writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        Ok(())
+    }
+
+    fn max_frame_size(&self) -> usize {
+        32
+    }
+}
+
+#[tokio::test]
+async fn frame_too_large() {
+    // Run a network receiver only accepting small frames.
+    let address = "127.0.0.1:6300".parse::<SocketAddr>().unwrap();
+    crate::Receiver::spawn(address, SmallFramesHandler);
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure we drop the messages larger than our own limit right away.
+    let mut sender = ReliableSender::new().with_max_frame_size(64);
+    let message = "Hello, world! ".repeat(5);
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+    assert!(cancel_handler.await.is_err());
+
+    // Ensure the receiver rejects the messages larger than its limit, but still serves us.
+    let cancel_handler = sender
+        .send(address, Bytes::from("Hello, world! Hello, world! Hello!"))
+        .await;
+    assert!(cancel_handler.await.is_err());
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(cancel_handler.await.is_ok());
+}
+
+/// Acknowledges all messages, and accepts to decompress them.
+#[derive(Clone)]
+struct CompressingHandler(AckHandler);
+
+#[async_trait]
+impl MessageHandler for CompressingHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        self.0.dispatch(writer, message).await
+    }
+
+    fn accepts_compression(&self) -> bool {
+        true
+    }
+}
+
+#[tokio::test]
+async fn compressed_connection() {
+    // Run a network receiver accepting compression, and one declining it.
+    let address = "127.0.0.1:6600".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    crate::Receiver::spawn(address, CompressingHandler(AckHandler { deliver: tx }));
+    let declining = "127.0.0.1:6601".parse::<SocketAddr>().unwrap();
+    let (tx, mut declining_rx) = channel(1);
+    crate::Receiver::spawn(declining, AckHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a large message to both.
+    let metrics = NetworkMetrics::default();
+    let mut sender = ReliableSender::new()
+        .with_compression(Compression::Zstd)
+        .with_metrics(metrics.clone());
+    let message = Bytes::from("Hello, world! ".repeat(1_000));
+    let handlers = sender
+        .broadcast(vec![address, declining], message.clone())
+        .await;
+    assert!(try_join_all(handlers).await.is_ok());
+
+    // Ensure both receive the message, but only the first one over the wire compressed.
+    assert_eq!(rx.recv().await, Some(message.clone()));
+    assert_eq!(declining_rx.recv().await, Some(message.clone()));
+    let bytes_sent = |x: SocketAddr| {
+        let label = x.to_string();
+        metrics.peer_bytes_sent.with_label_values(&[&label]).get()
+    };
+    assert!(bytes_sent(address) < message.len() as u64 / 10);
+    assert_eq!(bytes_sent(declining), message.len() as u64);
+}
+
+#[tokio::test]
+async fn idle_timeout() {
+    // Run a network receiver.
+    let address = "127.0.0.1:6800".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    crate::Receiver::spawn(address, AckHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message, then leave the connection idle.
+    let metrics = NetworkMetrics::default();
+    let mut sender = ReliableSender::new()
+        .with_idle_timeout(Duration::from_millis(200))
+        .with_metrics(metrics.clone());
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+    assert!(cancel_handler.await.is_ok());
+    assert_eq!(rx.recv().await, Some(Bytes::from("Hello")));
+    sleep(Duration::from_millis(400)).await;
+
+    // Ensure the sender closed the connection (without counting it as a failure).
+    assert_eq!(metrics.idle_evictions.get(), 1);
+    assert_eq!(metrics.connected_peers.get(), 0);
+    assert_eq!(sender.health(&address), PeerHealth::Healthy);
+
+    // Ensure the next message re-opens the connection.
+    let cancel_handler = sender.send(address, Bytes::from("World")).await;
+    assert!(cancel_handler.await.is_ok());
+    assert_eq!(rx.recv().await, Some(Bytes::from("World")));
+    assert_eq!(metrics.reconnects.get(), 0);
+}
@@ -0,0 +1,48 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[tokio::test]
+async fn resolve_unknown_address() {
+    let resolver = Resolver::default();
+    let address = "127.0.0.1:6400".parse::<SocketAddr>().unwrap();
+    assert!(!resolver.has_hostname(&address));
+    assert_eq!(resolver.resolve(address).await.unwrap(), address);
+}
+
+#[tokio::test]
+async fn resolve_hostname() {
+    // The peer is identified by a placeholder address.
+    let address = "[100::1]:6401".parse::<SocketAddr>().unwrap();
+    let hostnames = std::iter::once((address, "localhost:6401".to_string())).collect();
+    let resolver = Resolver::new(hostnames);
+    assert!(resolver.has_hostname(&address));
+    let resolved = resolver.resolve(address).await.unwrap();
+    assert!(resolved.ip().is_loopback());
+    assert_eq!(resolved.port(), 6401);
+}
+
+#[tokio::test]
+async fn fail_unresolved_hostname() {
+    // We cannot reach a peer whose hostname never resolved.
+    let address = "[100::2]:6402".parse::<SocketAddr>().unwrap();
+    let hostnames = std::iter::once((address, "unknown.invalid:6402".to_string())).collect();
+    let resolver = Resolver::new(hostnames);
+    assert!(resolver.resolve(address).await.is_err());
+}
+
+#[tokio::test]
+async fn keep_last_resolution() {
+    // We keep using the last address of a hostname we fail to resolve.
+    let address = "[100::3]:6403".parse::<SocketAddr>().unwrap();
+    let hostnames = std::iter::once((address, "unknown.invalid:6403".to_string())).collect();
+    let resolver = Resolver::new(hostnames);
+    let last = "127.0.0.1:6403".parse::<SocketAddr>().unwrap();
+    resolver
+        .hostnames
+        .lock()
+        .unwrap()
+        .get_mut(&address)
+        .unwrap()
+        .resolved = Some(last);
+    assert_eq!(resolver.resolve(address).await.unwrap(), last);
+}
@@ -0,0 +1,105 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::Receiver;
+use futures::future::join_all;
+use tokio::time::sleep;
+
+/// Doubles numbers, slowly for the large ones, and refuses to double zero.
+#[derive(Clone)]
+struct DoubleHandler;
+
+#[async_trait]
+impl RpcHandler for DoubleHandler {
+    type Request = u64;
+    type Response = u64;
+
+    async fn handle(&self, request: u64) -> Result<u64, Box<dyn Error>> {
+        if request == 0 {
+            return Err("Nothing to double".into());
+        }
+        if request >= 1_000 {
+            sleep(Duration::from_millis(300)).await;
+        }
+        Ok(request * 2)
+    }
+}
+
+fn server(address: SocketAddr) {
+    Receiver::spawn(address, RpcServer::new(DoubleHandler));
+}
+
+#[tokio::test]
+async fn call() {
+    let address = "127.0.0.1:6900".parse::<SocketAddr>().unwrap();
+    server(address);
+    sleep(Duration::from_millis(50)).await;
+
+    let mut client: RpcClient<u64, u64> = RpcClient::new(ReliableSender::new());
+    assert_eq!(client.call(address, 21).await.unwrap(), 42);
+
+    // Ensure the peer tells why it cannot serve a request.
+    let result = client.call(address, 0).await;
+    assert!(matches!(result, Err(RpcError::Remote(_, e)) if e == "Nothing to double"));
+}
+
+#[tokio::test]
+async fn concurrent_requests() {
+    let address = "127.0.0.1:6901".parse::<SocketAddr>().unwrap();
+    server(address);
+    sleep(Duration::from_millis(50)).await;
+
+    let mut client: RpcClient<u64, u64> = RpcClient::new(ReliableSender::new());
+    let mut pending = Vec::new();
+    for x in 1..=3 {
+        pending.push(client.send(address, x).await);
+    }
+    let responses = join_all(pending.into_iter().map(|x| x.response())).await;
+    let responses: Vec<_> = responses.into_iter().map(|x| x.unwrap()).collect();
+    assert_eq!(responses, vec![2, 4, 6]);
+}
+
+#[tokio::test]
+async fn timeout() {
+    let address = "127.0.0.1:6902".parse::<SocketAddr>().unwrap();
+    server(address);
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure we give up on a slow request.
+    let mut client: RpcClient<u64, u64> =
+        RpcClient::new(ReliableSender::new()).with_timeout(Duration::from_millis(100));
+    let result = client.call(address, 1_000).await;
+    assert!(matches!(result, Err(RpcError::Timeout(_))));
+
+    // Ensure the late response does not answer the next request.
+    let mut client = client.with_timeout(Duration::from_secs(1));
+    assert_eq!(client.call(address, 21).await.unwrap(), 42);
+}
+
+/// Serves the requests embedded in the messages starting with `R`, and ignores the others.
+#[derive(Clone)]
+struct EmbeddingHandler {
+    server: RpcServer<DoubleHandler>,
+}
+
+#[async_trait]
+impl MessageHandler for EmbeddingHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        match message.first() {
+            Some(b'R') => self.server.dispatch(writer, message.slice(1..)).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn embedded_requests() {
+    let address = "127.0.0.1:6903".parse::<SocketAddr>().unwrap();
+    let server = RpcServer::new(DoubleHandler);
+    Receiver::spawn(address, EmbeddingHandler { server });
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure the handler of the peer serves the requests embedded in its messages.
+    let mut client: RpcClient<u64, u64> = RpcClient::new(ReliableSender::new())
+        .with_wrapper(|request| [&b"R"[..], &request].concat());
+    assert_eq!(client.call(address, 21).await.unwrap(), 42);
+}
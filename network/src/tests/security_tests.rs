@@ -0,0 +1,170 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::{MessageHandler, NetworkContext, Peer, Receiver, ReliableSender, Writer};
+use async_trait::async_trait;
+use bytes::Bytes;
+use crypto::generate_keypair;
+use futures::sink::SinkExt as _;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::error::Error;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::sleep;
+
+/// Reports the node of the connections it receives messages from.
+#[derive(Clone)]
+struct NameHandler {
+    name: Option<PublicKey>,
+    deliver: Sender<Option<PublicKey>>,
+}
+
+#[async_trait]
+impl MessageHandler for NameHandler {
+    async fn dispatch(&self, writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(self.name).await.unwrap();
+        Ok(())
+    }
+
+    fn for_peer(&self, peer: &Peer) -> Self {
+        Self {
+            name: peer.name,
+            ..self.clone()
+        }
+    }
+}
+
+fn keys() -> Vec<(PublicKey, SecretKey)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..3).map(|_| generate_keypair(&mut rng)).collect()
+}
+
+/// Runs a receiver secured by the first key (accepting the second one), sends it a message secured
+/// by the specified key (expecting the specified key at the address of the receiver), and returns
+/// the node the receiver attributes the message to (if it got it).
+async fn exchange(
+    security: Security,
+    address: SocketAddr,
+    sender: usize,
+    expected: usize,
+) -> Option<Option<PublicKey>> {
+    let keys = keys();
+    let other = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let committee: BTreeMap<_, _> = vec![(address, keys[0].0), (other, keys[1].0)]
+        .into_iter()
+        .collect();
+    let (tx, mut rx) = channel(1);
+    let handler = NameHandler {
+        name: None,
+        deliver: tx,
+    };
+    let context = NetworkContext::new()
+        .with_identity(keys[0].0, keys[0].1.duplicate())
+        .with_security(security, committee)
+        .unwrap();
+    Receiver::spawn_with_context(address, handler, context);
+    sleep(Duration::from_millis(50)).await;
+
+    let (name, secret) = &keys[sender];
+    let peers = std::iter::once((address, keys[expected].0)).collect();
+    let context = NetworkContext::new()
+        .with_identity(*name, secret.duplicate())
+        .with_security(security, peers)
+        .unwrap();
+    let mut network = ReliableSender::new().with_context(context);
+    let _cancel_handler = network.send(address, Bytes::from("Hello")).await;
+    timeout(Duration::from_millis(500), rx.recv())
+        .await
+        .ok()
+        .flatten()
+}
+
+#[tokio::test]
+async fn authenticate_over_tls() {
+    // The receiver learns the key of the node sending the message.
+    let address = "127.0.0.1:7050".parse::<SocketAddr>().unwrap();
+    let received = exchange(Security::Tls, address, 1, 0).await;
+    assert_eq!(received, Some(Some(keys()[1].0)));
+}
+
+#[tokio::test]
+async fn reject_unknown_client_over_tls() {
+    // The receiver rejects the nodes outside the committee.
+    let address = "127.0.0.1:7051".parse::<SocketAddr>().unwrap();
+    assert_eq!(exchange(Security::Tls, address, 2, 0).await, None);
+}
+
+#[tokio::test]
+async fn reject_unexpected_server_over_tls() {
+    // The sender refuses to talk to another node than the one listed at the address.
+    let address = "127.0.0.1:7052".parse::<SocketAddr>().unwrap();
+    assert_eq!(exchange(Security::Tls, address, 1, 1).await, None);
+}
+
+#[tokio::test]
+async fn authenticate_over_noise() {
+    // The receiver learns the key of the node sending the message.
+    let address = "127.0.0.1:7053".parse::<SocketAddr>().unwrap();
+    let received = exchange(Security::Noise, address, 1, 0).await;
+    assert_eq!(received, Some(Some(keys()[1].0)));
+}
+
+#[tokio::test]
+async fn reject_unknown_client_over_noise() {
+    // The receiver rejects the nodes outside the committee.
+    let address = "127.0.0.1:7054".parse::<SocketAddr>().unwrap();
+    assert_eq!(exchange(Security::Noise, address, 2, 0).await, None);
+}
+
+#[tokio::test]
+async fn reject_unexpected_server_over_noise() {
+    // The sender refuses to talk to another node than the one listed at the address.
+    let address = "127.0.0.1:7055".parse::<SocketAddr>().unwrap();
+    assert_eq!(exchange(Security::Noise, address, 1, 1).await, None);
+}
+
+#[tokio::test]
+async fn encrypt_large_messages_over_noise() {
+    // Messages larger than a Noise message go through whole.
+    let keys = keys();
+    let address = "127.0.0.1:7056".parse::<SocketAddr>().unwrap();
+    let committee: BTreeMap<_, _> = std::iter::once((address, keys[0].0)).collect();
+    let (tx, mut rx) = channel(1);
+    let context = NetworkContext::new()
+        .with_identity(keys[0].0, keys[0].1.duplicate())
+        .with_security(Security::Noise, committee.clone())
+        .unwrap();
+    Receiver::spawn_with_context(address, SizeHandler { deliver: tx }, context);
+    sleep(Duration::from_millis(50)).await;
+
+    let context = NetworkContext::new()
+        .with_identity(keys[0].0, keys[0].1.duplicate())
+        .with_security(Security::Noise, committee)
+        .unwrap();
+    let mut network = ReliableSender::new().with_context(context);
+    let message = Bytes::from(vec![7u8; 200_000]);
+    let reply = network.send(address, message.clone()).await;
+    assert_eq!(rx.recv().await, Some(message));
+    assert!(reply.await.is_ok());
+}
+
+/// Delivers the messages it receives.
+#[derive(Clone)]
+struct SizeHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for SizeHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        self.deliver.send(message).await.unwrap();
+        let _ = writer.send(Bytes::from("Ack")).await;
+        Ok(())
+    }
+}
+
+#[test]
+fn require_identity() {
+    let context = NetworkContext::new().with_security(Security::Tls, BTreeMap::new());
+    assert!(context.is_err());
+}
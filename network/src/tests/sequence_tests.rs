@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn frame(sequence: u64) -> BytesMut {
+    let mut frame = BytesMut::new();
+    frame.put_u64_le(sequence);
+    frame.put(&b"Hello, world!"[..]);
+    frame
+}
+
+#[test]
+fn number_frames() {
+    let mut sequencer = Sequencer::new(Some(10));
+    let mut window = ReplayWindow::new(10);
+    for _ in 0..3 {
+        let tagged = sequencer.tag(Bytes::from("Hello, world!"));
+        let payload = window.check(BytesMut::from(&tagged[..])).unwrap();
+        assert_eq!(&payload[..], b"Hello, world!");
+    }
+
+    // Frames are sent as they are if the receiver does not number them.
+    let mut sequencer = Sequencer::new(None);
+    assert_eq!(sequencer.tag(Bytes::from("Hello")), Bytes::from("Hello"));
+}
+
+#[test]
+fn reject_replays() {
+    let mut window = ReplayWindow::new(10);
+    assert!(window.check(frame(12)).is_some());
+    assert!(window.check(frame(12)).is_none());
+
+    // Frames within the window are accepted once, out of order.
+    assert!(window.check(frame(11)).is_some());
+    assert!(window.check(frame(11)).is_none());
+
+    // The nonce itself and the frames older than the window are rejected.
+    assert!(window.check(frame(10)).is_none());
+    assert!(window.check(frame(12 + REPLAY_WINDOW)).is_some());
+    assert!(window.check(frame(12)).is_none());
+    assert!(window.check(frame(13)).is_some());
+
+    // Frames too short to hold a sequence number are rejected.
+    assert!(window.check(BytesMut::from(&b"Hello"[..])).is_none());
+}
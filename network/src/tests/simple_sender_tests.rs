@@ -1,7 +1,10 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::listener;
+use crate::version::{accept_handshake, Features};
 use futures::future::try_join_all;
+use tokio::net::TcpListener;
+use tokio_util::codec::LengthDelimitedCodec;
 
 #[tokio::test]
 async fn simple_send() {
@@ -40,3 +43,41 @@ async fn broadcast() {
     // Ensure all servers received the broadcast.
     assert!(try_join_all(handles).await.is_ok());
 }
+
+#[tokio::test]
+async fn keep_alive_and_idle_timeout() {
+    // Run a TCP server answering keep-alive probes.
+    let address = "127.0.0.1:6700".parse::<SocketAddr>().unwrap();
+    let handle = tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, peer) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        accept_handshake(&mut transport, peer, Features::NONE)
+            .await
+            .unwrap();
+        let message = transport.next().await.unwrap().unwrap();
+        assert_eq!(message, "Hello, world!");
+
+        // Count the probes until the sender closes the connection.
+        let mut probes = 0;
+        while let Some(Ok(frame)) = transport.next().await {
+            assert_eq!(frame[..], PING[..]);
+            transport.send(Bytes::from_static(PING)).await.unwrap();
+            probes += 1;
+        }
+        probes
+    });
+
+    // Make the network sender and send the message.
+    let metrics = NetworkMetrics::default();
+    let mut sender = SimpleSender::new()
+        .with_keep_alive(Duration::from_millis(100))
+        .with_idle_timeout(Duration::from_millis(500))
+        .with_metrics(metrics.clone());
+    sender.send(address, Bytes::from("Hello, world!")).await;
+
+    // Ensure the sender probes the connection, then closes it once idle.
+    assert!(handle.await.unwrap() >= 2);
+    assert_eq!(metrics.idle_evictions.get(), 1);
+    assert_eq!(metrics.keep_alive_timeouts.get(), 0);
+}
@@ -0,0 +1,172 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::receiver::{MessageHandler, Receiver, Writer};
+use crate::sequence::Sequencer;
+use crate::NetworkContext;
+use async_trait::async_trait;
+use std::error::Error;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let _ = writer.send(Bytes::from("Ack")).await;
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+#[test]
+fn negotiate_version() {
+    assert_eq!(parse_hello(&hello()), Some(PROTOCOL_VERSION));
+    assert_eq!(parse_hello(b"Hello, world!"), None);
+    assert_eq!(negotiate(PROTOCOL_VERSION + 1), Some(PROTOCOL_VERSION));
+    assert_eq!(negotiate(MIN_PROTOCOL_VERSION), Some(MIN_PROTOCOL_VERSION));
+    assert_eq!(negotiate(MIN_PROTOCOL_VERSION - 1), None);
+}
+
+#[derive(Clone)]
+struct DemandingHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for DemandingHandler {
+    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+
+    fn required_features(&self) -> Features {
+        Features::QUIC
+    }
+}
+
+#[test]
+fn announce_features() {
+    let features = Features::COMPRESSION | Features::GOSSIP;
+    let frame = features_frame(features, Features::GOSSIP);
+    let peer = parse_features_frame(&frame).unwrap();
+    assert_eq!(peer.node_version, NODE_VERSION);
+    assert_eq!(peer.min_version, MIN_PROTOCOL_VERSION);
+    assert_eq!(peer.features, features);
+    assert_eq!(peer.required, Features::GOSSIP);
+    assert_eq!(parse_features_frame(&hello()), None);
+
+    assert!(features.contains(Features::GOSSIP));
+    assert_eq!(Features::GOSSIP.missing(features), Features::COMPRESSION);
+    assert_eq!(features.to_string(), "compression+gossip");
+    assert_eq!(Features::NONE.to_string(), "none");
+}
+
+#[tokio::test]
+async fn handshake_with_receiver() {
+    let address = "127.0.0.1:4100".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(address, TestHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Agree on a version, then send a message.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let (version, nonce) = handshake(&mut transport, address, Features::NONE)
+        .await
+        .unwrap();
+    assert_eq!(version, PROTOCOL_VERSION);
+    // The receiver only numbers the frames of the connections authenticating their peer.
+    assert_eq!(nonce, None);
+    let mut sequencer = Sequencer::new(nonce);
+    let frame = sequencer.tag(Bytes::from("Hello, world!"));
+    transport.send(frame).await.unwrap();
+
+    // Ensure the hello frame is not delivered to the handler.
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello, world!"));
+}
+
+#[tokio::test]
+async fn reject_missing_features() {
+    let address = "127.0.0.1:7020".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(address, DemandingHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Connect without the features the receiver requires.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    match handshake(&mut transport, address, Features::NONE).await {
+        Err(NetworkError::MissingFeatures(_, _, missing)) => assert_eq!(missing, Features::QUIC),
+        _ => panic!("Unexpected handshake result"),
+    }
+
+    // Ensure the receiver closes the connection (after its sequence frame) without delivering
+    // anything.
+    let frame = transport.next().await.unwrap().unwrap();
+    assert!(parse_sequence_frame(&frame).is_some());
+    assert!(transport.next().await.is_none());
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn reject_old_version() {
+    let address = "127.0.0.1:4200".parse::<SocketAddr>().unwrap();
+    let (tx, _rx) = channel(1);
+    Receiver::spawn(address, TestHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Announce a version the receiver no longer speaks.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let old = MIN_PROTOCOL_VERSION - 1;
+    let frame = [&HELLO_MAGIC[..], &old.to_le_bytes()].concat();
+    transport.send(Bytes::from(frame)).await.unwrap();
+
+    // Ensure the receiver closes the connection.
+    assert!(transport.next().await.is_none());
+}
+
+#[tokio::test]
+async fn reject_peers_too_old_for_features() {
+    let address = "127.0.0.1:7040".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(address, DemandingHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Connect as a peer too old to announce its features, and as a client (speaking the first
+    // version).
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut peer = Framed::new(stream, LengthDelimitedCodec::new());
+    let old = FEATURES_VERSION - 1;
+    let frame = [&HELLO_MAGIC[..], &old.to_le_bytes()].concat();
+    peer.send(Bytes::from(frame)).await.unwrap();
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut client = Framed::new(stream, LengthDelimitedCodec::new());
+    client.send(Bytes::from("Hello, world!")).await.unwrap();
+
+    // Ensure the receiver closes both connections without delivering anything.
+    assert!(peer.next().await.is_none());
+    assert!(client.next().await.is_none());
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn announce_features_of_context() {
+    let address = "127.0.0.1:7041".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn(address, DemandingHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message from a node enabling the features the receiver requires.
+    let context = NetworkContext::new().with_features(Features::QUIC);
+    let mut sender = crate::SimpleSender::new().with_context(context);
+    sender.send(address, Bytes::from("Hello, world!")).await;
+
+    // Ensure the receiver gets the message.
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from("Hello, world!"));
+}
@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::receiver::{Connection, Listener};
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+
+/// How the senders and receivers of a node reach their peers: over TCP sockets, over QUIC (see
+/// `QuicTransport`), or over the in-process channels of a `MemoryNetwork` (so that tests can run
+/// nodes without sockets).
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+    /// Opens a connection to the receiver bound to the specified address.
+    async fn connect(&self, address: SocketAddr) -> io::Result<Box<dyn Connection>>;
+
+    /// Opens a connection to the node identified by the specified address (as the committee lists
+    /// it), which resolved to `resolved`. The transports authenticating the nodes override it to
+    /// check that they reach the node listed at the address.
+    async fn connect_node(
+        &self,
+        _address: SocketAddr,
+        resolved: SocketAddr,
+    ) -> io::Result<Box<dyn Connection>> {
+        self.connect(resolved).await
+    }
+
+    /// Binds a receiver to the specified address.
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>>;
+}
+
+/// The default transport, over TCP sockets.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn connect(&self, address: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(TcpStream::connect(address).await?))
+    }
+
+    async fn bind(&self, address: SocketAddr) -> io::Result<Box<dyn Listener>> {
+        Ok(Box::new(TcpListener::bind(address).await?))
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::compression::Compression;
+use crate::error::NetworkError;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::debug;
+use std::convert::TryInto as _;
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::BitOr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/version_tests.rs"]
+pub mod version_tests;
+
+/// The version of the wire protocol.
+pub type ProtocolVersion = u16;
+
+/// The version of the wire protocol we speak. Bump it whenever the encoding of a message changes.
+/// A new version may only append (optional) data at the end of the existing messages: peers
+/// running an older version ignore the trailing bytes of the messages they decode, so that a
+/// committee can be upgraded one node at a time.
+pub const PROTOCOL_VERSION: ProtocolVersion = 6;
+
+/// The oldest version of the wire protocol we still speak.
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = 1;
+
+/// The prefix of the first frame of each connection between nodes, announcing the version of the
+/// protocol of its sender. No message starts with these bytes.
+const HELLO_MAGIC: &[u8; 8] = b"NARWHAL/";
+
+/// The first version of the wire protocol in which receivers answer keep-alive probes.
+pub const KEEP_ALIVE_VERSION: ProtocolVersion = 2;
+
+/// The first version of the wire protocol in which receivers may compress the connection.
+pub const COMPRESSION_VERSION: ProtocolVersion = 3;
+
+/// The first version of the wire protocol in which all senders probe their quiet connections, so
+/// that receivers may close the connections over which the peer sends nothing at all.
+pub const IDLE_TIMEOUT_VERSION: ProtocolVersion = 4;
+
+/// The first version of the wire protocol in which receivers may number the frames of their
+/// connections, so that the frames replayed into a connection are rejected. They only number the
+/// frames of the connections authenticating the peer (see `Security`), which also authenticate the
+/// sequence numbers.
+pub const REPLAY_PROTECTION_VERSION: ProtocolVersion = 5;
+
+/// The first version of the wire protocol in which both ends announce their node version and
+/// features right after the hellos, so that they can tell why they cannot talk to each other.
+pub const FEATURES_VERSION: ProtocolVersion = 6;
+
+/// The version of the node, announced to our peers.
+pub const NODE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The prefix of the frame offering to compress a connection (followed by the compression), and of
+/// the reply of the receiver (followed by the compression it accepts). No message starts with
+/// these bytes.
+pub const COMPRESS: &[u8; 8] = b"NARWHAL~";
+
+/// The prefix of the frame a receiver sends right after its hello (from `REPLAY_PROTECTION_VERSION`),
+/// followed by the nonce from which the sender numbers its frames, or by nothing if the receiver
+/// does not number them. No message starts with these bytes.
+pub const SEQUENCE: &[u8; 8] = b"NARWHAL#";
+
+/// The prefix of the frame both ends send after the hellos (from `FEATURES_VERSION`), followed by
+/// the oldest protocol version of the sender, the features it enables, the features it requires
+/// from its peers and its node version. No message starts with these bytes.
+pub const FEATURES: &[u8; 8] = b"NARWHAL+";
+
+/// The frame probing whether a connection is still alive. Receivers echo it back (in the order of
+/// the other replies) instead of handing it to their message handler.
+pub const PING: &[u8; 8] = b"NARWHAL?";
+
+/// The frame a receiver replies with (instead of an acknowledgement) when its peer sends a frame
+/// larger than it accepts, before closing the connection.
+pub const FRAME_TOO_LARGE: &[u8; 8] = b"NARWHAL!";
+
+/// The default maximum size of a frame (the default of `LengthDelimitedCodec`). Senders drop larger
+/// messages, and receivers reject them with a `FRAME_TOO_LARGE` frame.
+pub const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
+/// The bytes a connection may add to each message: its sequence number (8 B) and the tag of its
+/// compression (1 B). Senders drop the messages that do not fit in a frame along with them.
+pub const FRAME_OVERHEAD: usize = 9;
+
+/// Returns a codec accepting frames up to the specified size.
+pub fn codec(max_frame_size: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(max_frame_size)
+        .new_codec()
+}
+
+/// Returns the frame announcing our protocol version.
+pub fn hello() -> Bytes {
+    Bytes::from([&HELLO_MAGIC[..], &PROTOCOL_VERSION.to_le_bytes()].concat())
+}
+
+/// Returns the protocol version announced by the frame, if it is a hello frame.
+pub fn parse_hello(frame: &[u8]) -> Option<ProtocolVersion> {
+    match frame.strip_prefix(&HELLO_MAGIC[..]) {
+        Some(version) => version.try_into().ok().map(ProtocolVersion::from_le_bytes),
+        None => None,
+    }
+}
+
+/// Returns the frame offering (or accepting) the specified compression.
+pub fn compression_frame(compression: Compression) -> Bytes {
+    Bytes::from([&COMPRESS[..], &[compression.tag()]].concat())
+}
+
+/// Returns the compression offered (or accepted) by the frame, if it is a compression frame.
+pub fn parse_compression_frame(frame: &[u8]) -> Option<Compression> {
+    match frame.strip_prefix(&COMPRESS[..]) {
+        Some([tag]) => Compression::from_tag(*tag),
+        _ => None,
+    }
+}
+
+/// Returns the frame giving the nonce from which the peer numbers its frames (if any).
+pub fn sequence_frame(nonce: Option<u64>) -> Bytes {
+    match nonce {
+        Some(nonce) => Bytes::from([&SEQUENCE[..], &nonce.to_le_bytes()].concat()),
+        None => Bytes::from_static(SEQUENCE),
+    }
+}
+
+/// Returns the nonce given by the frame (if any), if it is a sequence frame.
+pub fn parse_sequence_frame(frame: &[u8]) -> Option<Option<u64>> {
+    match frame.strip_prefix(&SEQUENCE[..]) {
+        Some([]) => Some(None),
+        Some(nonce) => nonce.try_into().ok().map(|x| Some(u64::from_le_bytes(x))),
+        None => None,
+    }
+}
+
+/// The optional features of a node. Some of them only work if the whole committee enables them,
+/// so nodes may require them from their peers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features(u32);
+
+impl Features {
+    pub const NONE: Self = Self(0);
+    /// The node offers to compress its connections.
+    pub const COMPRESSION: Self = Self(1);
+    /// The primary diffuses certificates over a gossip overlay rather than broadcasting them.
+    pub const GOSSIP: Self = Self(1 << 1);
+    /// Reserved for a QUIC transport (no node announces it yet).
+    pub const QUIC: Self = Self(1 << 2);
+
+    const NAMES: [(Self, &'static str); 3] = [
+        (Self::COMPRESSION, "compression"),
+        (Self::GOSSIP, "gossip"),
+        (Self::QUIC, "quic"),
+    ];
+
+    /// Whether all the specified features are enabled.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the specified features that are not enabled.
+    pub fn missing(self, required: Self) -> Self {
+        Self(required.0 & !self.0)
+    }
+}
+
+impl BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<_> = Self::NAMES
+            .iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| *name)
+            .collect();
+        match names.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", names.join("+")),
+        }
+    }
+}
+
+/// What a peer announces in its features frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerFeatures {
+    /// The version of the node of the peer.
+    pub node_version: String,
+    /// The oldest protocol version the peer speaks.
+    pub min_version: ProtocolVersion,
+    /// The features the peer enables.
+    pub features: Features,
+    /// The features the peer requires from us.
+    pub required: Features,
+}
+
+/// Returns the frame announcing our node version and features, and those we require.
+pub fn features_frame(features: Features, required: Features) -> Bytes {
+    Bytes::from(
+        [
+            &FEATURES[..],
+            &MIN_PROTOCOL_VERSION.to_le_bytes(),
+            &features.0.to_le_bytes(),
+            &required.0.to_le_bytes(),
+            NODE_VERSION.as_bytes(),
+        ]
+        .concat(),
+    )
+}
+
+/// Returns what the peer announces in the frame, if it is a features frame.
+pub fn parse_features_frame(frame: &[u8]) -> Option<PeerFeatures> {
+    let frame = frame.strip_prefix(&FEATURES[..])?;
+    if frame.len() < 10 {
+        return None;
+    }
+    Some(PeerFeatures {
+        min_version: ProtocolVersion::from_le_bytes(frame[..2].try_into().unwrap()),
+        features: Features(u32::from_le_bytes(frame[2..6].try_into().unwrap())),
+        required: Features(u32::from_le_bytes(frame[6..10].try_into().unwrap())),
+        node_version: String::from_utf8_lossy(&frame[10..]).into_owned(),
+    })
+}
+
+/// Receives the features frame of a peer, failing if it requires features we do not enable.
+async fn receive_features<T: AsyncRead + AsyncWrite + Unpin>(
+    transport: &mut Framed<T, LengthDelimitedCodec>,
+    address: SocketAddr,
+    features: Features,
+) -> Result<PeerFeatures, NetworkError> {
+    let peer = match transport.next().await {
+        Some(Ok(frame)) => {
+            parse_features_frame(&frame).ok_or(NetworkError::FailedHandshake(address))?
+        }
+        Some(Err(e)) => return Err(NetworkError::FailedToReceiveMessage(address, e)),
+        None => return Err(NetworkError::FailedHandshake(address)),
+    };
+    debug!(
+        "Peer {} runs node version {} (protocol versions {} to {}) with features {}",
+        address, peer.node_version, peer.min_version, PROTOCOL_VERSION, peer.features
+    );
+    let missing = features.missing(peer.required);
+    if missing != Features::NONE {
+        return Err(NetworkError::MissingFeatures(
+            address,
+            peer.node_version,
+            missing,
+        ));
+    }
+    Ok(peer)
+}
+
+/// Returns the version we use to talk with a peer speaking (at most) the specified version, or
+/// `None` if the peer is too old.
+pub fn negotiate(peer: ProtocolVersion) -> Option<ProtocolVersion> {
+    (peer >= MIN_PROTOCOL_VERSION).then(|| peer.min(PROTOCOL_VERSION))
+}
+
+/// Announces our protocol version to the peer we just connected to and waits for its own, then
+/// exchanges our features (failing if the peer requires features we do not enable). Returns the
+/// version negotiated for the connection, and the nonce from which we number our frames (if the
+/// peer numbers them).
+pub async fn handshake<T: AsyncRead + AsyncWrite + Unpin>(
+    transport: &mut Framed<T, LengthDelimitedCodec>,
+    address: SocketAddr,
+    features: Features,
+) -> Result<(ProtocolVersion, Option<u64>), NetworkError> {
+    transport
+        .send(hello())
+        .await
+        .map_err(|e| NetworkError::FailedToSendMessage(address, e))?;
+    let version = match transport.next().await {
+        Some(Ok(frame)) => {
+            let version = parse_hello(&frame).ok_or(NetworkError::FailedHandshake(address))?;
+            negotiate(version).ok_or(NetworkError::IncompatibleVersion(address, version))?
+        }
+        Some(Err(e)) => return Err(NetworkError::FailedToReceiveMessage(address, e)),
+        None => return Err(NetworkError::FailedHandshake(address)),
+    };
+    if version >= FEATURES_VERSION {
+        transport
+            .send(features_frame(features, Features::NONE))
+            .await
+            .map_err(|e| NetworkError::FailedToSendMessage(address, e))?;
+        receive_features(transport, address, features).await?;
+    }
+    if version < REPLAY_PROTECTION_VERSION {
+        return Ok((version, None));
+    }
+    match transport.next().await {
+        Some(Ok(frame)) => match parse_sequence_frame(&frame) {
+            Some(nonce) => Ok((version, nonce)),
+            None => Err(NetworkError::FailedHandshake(address)),
+        },
+        Some(Err(e)) => Err(NetworkError::FailedToReceiveMessage(address, e)),
+        None => Err(NetworkError::FailedHandshake(address)),
+    }
+}
+
+/// Waits for the protocol version of the peer that just connected to us and replies with our own,
+/// then exchanges our features (requiring none). Returns the version negotiated for the
+/// connection. The frames of the peer are not numbered.
+pub async fn accept_handshake<T: AsyncRead + AsyncWrite + Unpin>(
+    transport: &mut Framed<T, LengthDelimitedCodec>,
+    peer: SocketAddr,
+    features: Features,
+) -> Result<ProtocolVersion, NetworkError> {
+    match transport.next().await {
+        Some(Ok(frame)) => {
+            let version = parse_hello(&frame).ok_or(NetworkError::FailedHandshake(peer))?;
+            let negotiated =
+                negotiate(version).ok_or(NetworkError::IncompatibleVersion(peer, version))?;
+            transport
+                .send(hello())
+                .await
+                .map_err(|e| NetworkError::FailedToSendMessage(peer, e))?;
+            if negotiated >= FEATURES_VERSION {
+                transport
+                    .send(features_frame(features, Features::NONE))
+                    .await
+                    .map_err(|e| NetworkError::FailedToSendMessage(peer, e))?;
+            }
+            if negotiated >= REPLAY_PROTECTION_VERSION {
+                transport
+                    .send(sequence_frame(None))
+                    .await
+                    .map_err(|e| NetworkError::FailedToSendMessage(peer, e))?;
+            }
+            if negotiated >= FEATURES_VERSION {
+                receive_features(transport, peer, features).await?;
+            }
+            Ok(negotiated)
+        }
+        Some(Err(e)) => Err(NetworkError::FailedToReceiveMessage(peer, e)),
+        None => Err(NetworkError::FailedHandshake(peer)),
+    }
+}
+
+/// Offers to compress the connection we just agreed on a version with, if the peer speaks a
+/// version supporting it. Returns the compression the peer accepts for our messages.
+pub async fn negotiate_compression<T: AsyncRead + AsyncWrite + Unpin>(
+    transport: &mut Framed<T, LengthDelimitedCodec>,
+    address: SocketAddr,
+    version: ProtocolVersion,
+    compression: Compression,
+) -> Result<Compression, NetworkError> {
+    if compression == Compression::None || version < COMPRESSION_VERSION {
+        return Ok(Compression::None);
+    }
+    transport
+        .send(compression_frame(compression))
+        .await
+        .map_err(|e| NetworkError::FailedToSendMessage(address, e))?;
+    match transport.next().await {
+        Some(Ok(frame)) => match parse_compression_frame(&frame) {
+            Some(x) if x == compression || x == Compression::None => Ok(x),
+            _ => Err(NetworkError::FailedHandshake(address)),
+        },
+        Some(Err(e)) => Err(NetworkError::FailedToReceiveMessage(address, e)),
+        None => Err(NetworkError::FailedHandshake(address)),
+    }
+}
@@ -0,0 +1,14 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use the vendored protobuf compiler so the build does not depend on a system installation.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        // Hand the submitted transactions to the worker without copying them.
+        .bytes([
+            ".narwhal.Transaction.transaction",
+            ".narwhal.Batch.transactions",
+        ])
+        .compile_protos(&["proto/narwhal.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
+use bytes::Bytes;
+use consensus::debug::{DagFormat, DagRequest};
+use futures::sink::SinkExt as _;
+use network::{MessageHandler, Writer};
+use primary::{GcDepthCommand, GcDepthUpdate, ProposerCommand, WorkerCache, WorkerUpdate};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+/// The commands operators can send to a running node.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AdminCommand {
+    /// Change the depth of the garbage collector of the primary and of the consensus.
+    SetGcDepth(GcDepthUpdate),
+    /// Export the DAG held by the consensus (for debugging).
+    ExportDag(DagFormat),
+    /// Get the number of committed certificates not yet delivered to the application layer.
+    OutputLag,
+    /// Add or remove a worker, or change its addresses. This only changes how our primary reaches
+    /// the workers: headers are still checked against the workers of the committee, so a worker
+    /// missing from the committee may not seal batches.
+    UpdateWorkers(WorkerUpdate),
+    /// Pause or resume the creation of headers (the node keeps voting and syncing), for instance
+    /// to drain the node before maintenance.
+    Proposer(ProposerCommand),
+}
+
+/// Defines how the network receiver handles admin commands. `SetGcDepth` is answered with a
+/// serialized `Result<(), String>`, `ExportDag` with the serialized DAG (a `String`), `OutputLag`
+/// with a `u64`, and `UpdateWorkers` and `Proposer` with a serialized `Result<(), String>`.
+#[derive(Clone)]
+pub struct AdminHandler {
+    /// Sends requests to change the depth of the garbage collector of the primary.
+    pub tx_primary_gc_depth: Sender<GcDepthCommand>,
+    /// Sends requests to change the depth of the garbage collector of the consensus.
+    pub tx_consensus_gc_depth: Sender<GcDepthCommand>,
+    /// Sends requests to export the DAG held by the consensus.
+    pub tx_consensus_debug: Sender<DagRequest>,
+    /// The number of committed certificates not yet delivered to the application layer.
+    pub output_lag: Arc<AtomicU64>,
+    /// The addresses of the workers, shared with the primary.
+    pub worker_cache: WorkerCache,
+    /// Sends the commands controlling the creation of headers.
+    pub tx_proposer: Sender<ProposerCommand>,
+}
+
+impl AdminHandler {
+    async fn set_gc_depth(&self, update: GcDepthUpdate) -> Result<(), String> {
+        // Submit the update to the consensus first: it is always ahead of the primary, so the primary
+        // accepts any update that the consensus accepts.
+        for tx_gc_depth in [&self.tx_consensus_gc_depth, &self.tx_primary_gc_depth] {
+            let (sender, receiver) = oneshot::channel();
+            tx_gc_depth
+                .send((update.clone(), sender))
+                .await
+                .expect("Failed to send gc depth update");
+            receiver
+                .await
+                .expect("Failed to receive reply to gc depth update")
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    async fn export_dag(&self, format: DagFormat) -> String {
+        let (sender, receiver) = oneshot::channel();
+        self.tx_consensus_debug
+            .send((format, sender))
+            .await
+            .expect("Failed to send dag export request");
+        receiver
+            .await
+            .expect("Failed to receive reply to dag export request")
+    }
+}
+
+#[async_trait]
+impl MessageHandler for AdminHandler {
+    async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
+        let reply = match bincode::deserialize(&serialized)? {
+            AdminCommand::SetGcDepth(update) => {
+                bincode::serialize(&self.set_gc_depth(update).await)?
+            }
+            AdminCommand::ExportDag(format) => bincode::serialize(&self.export_dag(format).await)?,
+            AdminCommand::OutputLag => {
+                bincode::serialize(&self.output_lag.load(Ordering::Relaxed))?
+            }
+            AdminCommand::UpdateWorkers(update) => {
+                let result = self.worker_cache.update(update).map_err(|e| e.to_string());
+                bincode::serialize(&result)?
+            }
+            AdminCommand::Proposer(command) => {
+                self.tx_proposer
+                    .send(command)
+                    .await
+                    .expect("Failed to send command to the proposer");
+                bincode::serialize(&Ok::<(), String>(()))?
+            }
+        };
+        writer.send(Bytes::from(reply)).await?;
+        Ok(())
+    }
+}
@@ -6,12 +6,14 @@ use clap::{crate_name, crate_version, App, AppSettings};
 use env_logger::Env;
 use futures::future::join_all;
 use futures::sink::SinkExt as _;
-use log::{info, warn};
+use futures::stream::StreamExt as _;
+use log::{debug, info, warn};
 use rand::Rng;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
 use tokio::time::{interval, sleep, Duration, Instant};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use worker::ReceiptStatus;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -103,7 +105,16 @@ impl Client {
         let mut tx = BytesMut::with_capacity(self.size);
         let mut counter = 0;
         let mut r = rand::thread_rng().gen();
-        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        let (mut transport, mut replies) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+
+        // Drain the replies of the node, telling us when it sheds the load.
+        tokio::spawn(async move {
+            while let Some(Ok(reply)) = replies.next().await {
+                if let Ok(status) = bincode::deserialize::<ReceiptStatus>(&reply) {
+                    debug!("Transaction dropped by the node: {:?}", status);
+                }
+            }
+        });
         let interval = interval(Duration::from_millis(BURST_DURATION));
         tokio::pin!(interval);
 
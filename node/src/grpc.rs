@@ -0,0 +1,479 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::CHANNEL_CAPACITY;
+use bytes::Bytes;
+use config::Committee;
+use consensus::{CommitFilter, ConsensusOutput, FilteredOutput, Subscription};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use log::error;
+use primary::{Certificate, Round};
+use std::convert::TryInto;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use store::Store;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::{Stream, StreamExt as _};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+use worker::{BatchFetcher, FetchError, ReceiptStatus, SubmitError, TransactionSubmitter};
+
+#[cfg(test)]
+#[path = "tests/grpc_tests.rs"]
+pub mod grpc_tests;
+
+mod proto {
+    tonic::include_proto!("narwhal");
+}
+
+use proto::batches_server::{Batches, BatchesServer};
+use proto::reader_server::{Reader, ReaderServer};
+use proto::submitter_server::{Submitter, SubmitterServer};
+
+/// The number of committed certificates buffered for each subscriber. Subscribers lagging further
+/// behind are disconnected.
+const SUBSCRIBER_CAPACITY: usize = 1_000;
+
+/// The number of certificates of the history read ahead of the client. The stream only reads
+/// further from the store as the client consumes it.
+const HISTORY_BUFFER: usize = 100;
+
+/// The number of transactions of a submission stream waiting for their receipt.
+const RECEIPTS_BUFFER: usize = 1_000;
+
+/// The store key under which we persist the last committed certificate, so that it survives a
+/// restart of the node.
+const LAST_COMMITTED_KEY: &[u8] = b"grpc_last_committed";
+
+/// Serves read-only gRPC requests on the certificates held by the primary and on the consensus
+/// output, so that indexers and tooling do not need to read the store directly.
+#[derive(Clone)]
+pub struct ReadService {
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage of the primary.
+    store: Store,
+    /// The last committed certificate.
+    last_committed: Arc<Mutex<Option<ConsensusOutput>>>,
+    /// Fans out the consensus output to the gRPC subscribers.
+    tx_subscribers: broadcast::Sender<ConsensusOutput>,
+}
+
+impl ReadService {
+    pub async fn spawn(
+        address: SocketAddr,
+        committee: Committee,
+        store: Store,
+        tx_subscription: Sender<Subscription>,
+    ) {
+        // Subscribe once to the consensus output. The gRPC subscribers get it through a broadcast
+        // channel, so that slow clients cannot hold back the node.
+        let (tx_output, mut rx_output) = channel(CHANNEL_CAPACITY);
+        let subscription = Subscription {
+            filter: CommitFilter::All,
+            tx_output,
+        };
+        tx_subscription
+            .send(subscription)
+            .await
+            .expect("Failed to subscribe to the consensus output");
+
+        let service = Self::new(committee, store).await;
+        let tracker = service.clone();
+        tokio::spawn(async move {
+            while let Some(FilteredOutput { output, .. }) = rx_output.recv().await {
+                tracker.record_commit(&output).await;
+                // There may be no subscriber at the moment.
+                let _ = tracker.tx_subscribers.send(output);
+            }
+        });
+
+        tokio::spawn(async move {
+            if let Err(e) = Server::builder()
+                .add_service(ReaderServer::new(service))
+                .serve(address)
+                .await
+            {
+                error!("gRPC server failed: {}", e);
+            }
+        });
+    }
+
+    async fn new(committee: Committee, mut store: Store) -> Self {
+        // Restore the last committed certificate from before the restart (if any).
+        let last_committed = match store.read(LAST_COMMITTED_KEY.to_vec()).await {
+            Ok(Some(bytes)) => bincode::deserialize(&bytes).ok(),
+            Ok(None) => None,
+            Err(e) => {
+                error!("Failed to read the last committed certificate: {}", e);
+                None
+            }
+        };
+        let (tx_subscribers, _) = broadcast::channel(SUBSCRIBER_CAPACITY);
+        Self {
+            committee,
+            store,
+            last_committed: Arc::new(Mutex::new(last_committed)),
+            tx_subscribers,
+        }
+    }
+
+    /// Records (and persists) the last committed certificate.
+    async fn record_commit(&self, output: &ConsensusOutput) {
+        let bytes = bincode::serialize(output).expect("Failed to serialize consensus output");
+        let mut store = self.store.clone();
+        store.write(LAST_COMMITTED_KEY.to_vec(), bytes).await;
+        *self.last_committed.lock().unwrap() = Some(output.clone());
+    }
+
+    async fn read_certificate(&self, digest: &Digest) -> Result<Option<Certificate>, Status> {
+        let mut store = self.store.clone();
+        match store.read(digest.to_vec()).await {
+            Ok(Some(bytes)) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| Status::internal(e.to_string())),
+            Ok(None) => Ok(None),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    /// Reads the certificates of the given round that we hold.
+    async fn read_round(&self, round: Round) -> Result<Vec<Certificate>, Status> {
+        let mut store = self.store.clone();
+        let mut certificates = Vec::new();
+        for author in self.committee.authorities.keys() {
+            let key = Certificate::round_key(round, author);
+            let digest = match store.read(key).await {
+                Ok(Some(bytes)) => bytes
+                    .as_slice()
+                    .try_into()
+                    .map(Digest)
+                    .map_err(|_| Status::internal("Corrupted round index"))?,
+                Ok(None) => continue,
+                Err(e) => return Err(Status::internal(e.to_string())),
+            };
+            if let Some(certificate) = self.read_certificate(&digest).await? {
+                certificates.push(certificate);
+            }
+        }
+        Ok(certificates)
+    }
+
+    /// Feeds the certificates from the given round to the history stream, round by round, until
+    /// the first round we hold no certificate of past the last committed one (or the client goes
+    /// away). We may miss earlier rounds (eg. the ones garbage collected while catching up), but
+    /// the DAG above the last commit has no gap.
+    async fn feed_history(
+        &self,
+        from: Round,
+        tx_history: Sender<Result<proto::Certificate, Status>>,
+    ) {
+        // The genesis certificates are not stored.
+        for round in from.max(1).. {
+            let certificates = match self.read_round(round).await {
+                Ok(x) if x.is_empty() && round > self.last_committed_round() => return,
+                Ok(x) => x,
+                Err(e) => {
+                    let _ = tx_history.send(Err(e)).await;
+                    return;
+                }
+            };
+            for certificate in &certificates {
+                if tx_history.send(Ok(certificate.into())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The round of the last committed certificate (0 if none).
+    fn last_committed_round(&self) -> Round {
+        self.last_committed
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |x| x.certificate.round())
+    }
+}
+
+impl From<&Certificate> for proto::Certificate {
+    fn from(certificate: &Certificate) -> Self {
+        Self {
+            digest: certificate.digest().to_vec(),
+            round: certificate.round(),
+            author: certificate.origin().0.to_vec(),
+            data: bincode::serialize(certificate).expect("Failed to serialize certificate"),
+            timestamp: certificate.timestamp(),
+        }
+    }
+}
+
+impl From<&ConsensusOutput> for proto::CommittedCertificate {
+    fn from(output: &ConsensusOutput) -> Self {
+        Self {
+            certificate: Some((&output.certificate).into()),
+            index: output.index,
+            anchor: output.anchor.to_vec(),
+        }
+    }
+}
+
+type CertificateStream = Pin<Box<dyn Stream<Item = Result<proto::Certificate, Status>> + Send>>;
+
+type CommittedStream =
+    Pin<Box<dyn Stream<Item = Result<proto::CommittedCertificate, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Reader for ReadService {
+    async fn get_certificate(
+        &self,
+        request: Request<proto::CertificateDigest>,
+    ) -> Result<Response<proto::Certificate>, Status> {
+        let digest = request
+            .into_inner()
+            .digest
+            .as_slice()
+            .try_into()
+            .map(Digest)
+            .map_err(|_| Status::invalid_argument("Invalid certificate digest"))?;
+        match self.read_certificate(&digest).await? {
+            Some(certificate) => Ok(Response::new((&certificate).into())),
+            None => Err(Status::not_found(format!("Unknown certificate {}", digest))),
+        }
+    }
+
+    async fn get_certificates_by_round(
+        &self,
+        request: Request<proto::Round>,
+    ) -> Result<Response<proto::Certificates>, Status> {
+        let round = request.into_inner().round;
+        let certificates = self
+            .read_round(round)
+            .await?
+            .iter()
+            .map(|x| x.into())
+            .collect();
+        Ok(Response::new(proto::Certificates { certificates }))
+    }
+
+    async fn get_last_committed(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<proto::CommittedCertificate>, Status> {
+        match self.last_committed.lock().unwrap().as_ref() {
+            Some(output) => Ok(Response::new(output.into())),
+            None => Err(Status::not_found("No certificate committed yet")),
+        }
+    }
+
+    type SubscribeCertificatesStream = CommittedStream;
+
+    #[allow(clippy::result_large_err)]
+    async fn subscribe_certificates(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<Self::SubscribeCertificatesStream>, Status> {
+        let stream = BroadcastStream::new(self.tx_subscribers.subscribe()).map(|x| match x {
+            Ok(output) => Ok((&output).into()),
+            Err(_) => Err(Status::resource_exhausted("Subscriber lagging behind")),
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    type StreamHistoryStream = CertificateStream;
+
+    async fn stream_history(
+        &self,
+        request: Request<proto::Round>,
+    ) -> Result<Response<Self::StreamHistoryStream>, Status> {
+        let from = request.into_inner().round;
+        let (tx_history, rx_history) = channel(HISTORY_BUFFER);
+        let service = self.clone();
+        tokio::spawn(async move { service.feed_history(from, tx_history).await });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx_history))))
+    }
+}
+
+/// Accepts the transactions of gRPC clients on behalf of a worker, replying with their receipts.
+#[derive(Clone)]
+pub struct SubmitService {
+    /// Submits the transactions to the worker.
+    submitter: TransactionSubmitter,
+}
+
+impl SubmitService {
+    /// Serves the submission of transactions, along with the batches (see `BatchService`).
+    pub fn spawn(address: SocketAddr, submitter: TransactionSubmitter, fetcher: BatchFetcher) {
+        let service = Self { submitter };
+        tokio::spawn(async move {
+            if let Err(e) = Server::builder()
+                .add_service(SubmitterServer::new(service))
+                .add_service(BatchesServer::new(BatchService { fetcher }))
+                .serve(address)
+                .await
+            {
+                error!("gRPC server failed: {}", e);
+            }
+        });
+    }
+
+    /// Submits a transaction on behalf of the client of the specified address, returning a future
+    /// resolving to its receipt.
+    #[allow(clippy::result_large_err)]
+    fn submit(
+        &self,
+        client: Option<SocketAddr>,
+        transaction: Bytes,
+    ) -> Result<impl Future<Output = Result<proto::TransactionReceipt, Status>>, Status> {
+        // The server knows the address of all its (TCP) clients.
+        let client = client.map_or(IpAddr::from([0, 0, 0, 0]), |x| x.ip());
+        let receipt = self
+            .submitter
+            .submit_from(client, transaction)
+            .map_err(|e| match e {
+                SubmitError::Overloaded | SubmitError::RateLimited(_) => {
+                    Status::resource_exhausted(e.to_string())
+                }
+                SubmitError::Oversized(..) | SubmitError::Invalid(_) => {
+                    Status::invalid_argument(e.to_string())
+                }
+                SubmitError::Misrouted(_) => Status::failed_precondition(e.to_string()),
+                SubmitError::Backlogged(_) => Status::unavailable(e.to_string()),
+            })?;
+        Ok(async move {
+            let receipt = receipt.await;
+            match receipt.status {
+                ReceiptStatus::Sealed(batch) => Ok(proto::TransactionReceipt {
+                    digest: receipt.transaction.to_vec(),
+                    batch: batch.to_vec(),
+                }),
+                ReceiptStatus::Duplicate => Err(Status::already_exists(format!(
+                    "Transaction {} recently submitted",
+                    receipt.transaction
+                ))),
+                ReceiptStatus::Rejected(reason) => Err(Status::invalid_argument(reason)),
+                ReceiptStatus::Oversized(size, max) => Err(Status::invalid_argument(format!(
+                    "Transaction of {} B exceeds the maximum size of {} B",
+                    size, max
+                ))),
+                ReceiptStatus::Misrouted(id) => Err(Status::failed_precondition(format!(
+                    "Transaction routed to worker {}",
+                    id
+                ))),
+                ReceiptStatus::Overloaded => Err(Status::resource_exhausted("Worker overloaded")),
+                ReceiptStatus::RetryLater => {
+                    Err(Status::unavailable("Worker backlogged, retry later"))
+                }
+                ReceiptStatus::Replaced => Err(Status::aborted(format!(
+                    "Transaction {} replaced",
+                    receipt.transaction
+                ))),
+                ReceiptStatus::Expired => Err(Status::deadline_exceeded(format!(
+                    "Transaction {} expired",
+                    receipt.transaction
+                ))),
+            }
+        })
+    }
+}
+
+type ReceiptStream = Pin<Box<dyn Stream<Item = Result<proto::TransactionReceipt, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Submitter for SubmitService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::TransactionReceipt>, Status> {
+        let client = request.remote_addr();
+        let receipt = self
+            .submit(client, request.into_inner().transaction)?
+            .await?;
+        Ok(Response::new(receipt))
+    }
+
+    type SubmitTransactionStreamStream = ReceiptStream;
+
+    #[allow(clippy::result_large_err)]
+    async fn submit_transaction_stream(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<Self::SubmitTransactionStreamStream>, Status> {
+        let client = request.remote_addr();
+        let mut inbound = request.into_inner();
+        let (tx_pending, mut rx_pending) = channel(RECEIPTS_BUFFER);
+        let (tx_receipts, rx_receipts) = channel(RECEIPTS_BUFFER);
+
+        // Submit the transactions as they arrive, so that they do not wait for each other's receipt.
+        let service = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = inbound.next().await {
+                let pending = message.and_then(|x| service.submit(client, x.transaction));
+                let failed = pending.is_err();
+                if tx_pending.send(pending).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        // Stream the receipts back in order, until the first failure.
+        tokio::spawn(async move {
+            while let Some(pending) = rx_pending.recv().await {
+                let reply = match pending {
+                    Ok(receipt) => receipt.await,
+                    Err(e) => Err(e),
+                };
+                let failed = reply.is_err();
+                if tx_receipts.send(reply).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx_receipts))))
+    }
+}
+
+/// Serves the transactions of the batches held (or fetched) by a worker.
+pub struct BatchService {
+    /// Reads the batches from the store of the worker, or fetches them from the other workers.
+    fetcher: BatchFetcher,
+}
+
+#[tonic::async_trait]
+impl Batches for BatchService {
+    async fn get_batch(
+        &self,
+        request: Request<proto::BatchRequest>,
+    ) -> Result<Response<proto::Batch>, Status> {
+        let request = request.into_inner();
+        let digest = request
+            .digest
+            .as_slice()
+            .try_into()
+            .map(Digest)
+            .map_err(|_| Status::invalid_argument("Invalid batch digest"))?;
+        let origin = match request.origin.is_empty() {
+            true => None,
+            false => Some(
+                request
+                    .origin
+                    .as_slice()
+                    .try_into()
+                    .map(PublicKey)
+                    .map_err(|_| Status::invalid_argument("Invalid origin"))?,
+            ),
+        };
+        match self.fetcher.fetch(&digest, origin).await {
+            Ok(transactions) => Ok(Response::new(proto::Batch { transactions })),
+            Err(e @ FetchError::Unavailable(_)) | Err(e @ FetchError::Uncommitted(_)) => {
+                Err(Status::not_found(e.to_string()))
+            }
+            Err(e @ FetchError::RateLimited(_)) => Err(Status::resource_exhausted(e.to_string())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+}
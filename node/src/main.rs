@@ -1,15 +1,37 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use anyhow::{Context, Result};
+mod admin;
+mod grpc;
+mod metrics;
+
+use crate::admin::AdminHandler;
+use crate::grpc::{ReadService, SubmitService};
+use crate::metrics::MetricsExporter;
+use anyhow::{bail, Context, Result};
 use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, SubCommand};
 use config::Export as _;
 use config::Import as _;
-use config::{Committee, KeyPair, Parameters, WorkerId};
-use consensus::Consensus;
+use config::{
+    Committee, DiffusionMode, KeyPair, Parameters, PublicIdentity, RelayAddresses,
+    TransportCompression, TransportProtocol, TransportSecurity, WorkerId,
+};
+use consensus::{
+    CommitFilter, Consensus, Dispatcher, FilteredOutput, OutputBuffer, SnapshotDiff, Subscription,
+};
+use crypto::Hash as _;
+use crypto::{KeyShare, RemoteSigner, SignatureService};
 use env_logger::Env;
-use primary::{Certificate, Primary};
+use log::{debug, info, warn};
+use network::Receiver as NetworkReceiver;
+use primary::{Certificate, Equivocation, HeaderProgress, Primary, SystemMessage, WorkerCache};
+use prometheus::Registry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 use store::Store;
-use tokio::sync::mpsc::{channel, Receiver};
-use worker::Worker;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use worker::{AcceptAll, RustlsProvider, Worker};
 
 /// The default channel capacity.
 pub const CHANNEL_CAPACITY: usize = 1_000;
@@ -32,6 +54,17 @@ async fn main() -> Result<()> {
                 .args_from_usage("--committee=<FILE> 'The file containing committee information'")
                 .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
                 .args_from_usage("--store=<PATH> 'The path where to create the data store'")
+                .args_from_usage("--admin=[ADDR] 'The address where to listen to admin commands'")
+                .args_from_usage("--grpc=[ADDR] 'The address where to serve the gRPC API'")
+                .args_from_usage(
+                    "--metrics=[ADDR] 'The address where to serve the Prometheus metrics'",
+                )
+                .args_from_usage(
+                    "--signer=[PATH] 'The Unix socket of an external signer holding the node keys'",
+                )
+                .args_from_usage(
+                    "--key-share=[FILE] 'The file containing the node share of the decryption key'",
+                )
                 .subcommand(SubCommand::with_name("primary").about("Run a single primary"))
                 .subcommand(
                     SubCommand::with_name("worker")
@@ -40,6 +73,20 @@ async fn main() -> Result<()> {
                 )
                 .setting(AppSettings::SubcommandRequiredElseHelp),
         )
+        .subcommand(
+            SubCommand::with_name("relay")
+                .about("Run a relay hiding the IP address of a validator")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--addresses=<FILE> 'The file containing the relay addresses'"),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare the store snapshots of two primaries")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
+                .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
+                .args_from_usage("--first=<PATH> 'The path to the first store snapshot'")
+                .args_from_usage("--second=<PATH> 'The path to the second store snapshot'"),
+        )
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .get_matches();
 
@@ -60,6 +107,8 @@ async fn main() -> Result<()> {
             .export(sub_matches.value_of("filename").unwrap())
             .context("Failed to generate key pair")?,
         ("run", Some(sub_matches)) => run(sub_matches).await?,
+        ("relay", Some(sub_matches)) => relay(sub_matches).await?,
+        ("diff", Some(sub_matches)) => diff(sub_matches).await?,
         _ => unreachable!(),
     }
     Ok(())
@@ -71,9 +120,36 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     let committee_file = matches.value_of("committee").unwrap();
     let parameters_file = matches.value_of("parameters");
     let store_path = matches.value_of("store").unwrap();
+    let admin_address = matches
+        .value_of("admin")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid admin address format")?;
+    let grpc_address = matches
+        .value_of("grpc")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid gRPC address format")?;
+    let metrics_address = matches
+        .value_of("metrics")
+        .map(|x| x.parse::<SocketAddr>())
+        .transpose()
+        .context("Invalid metrics address format")?;
 
-    // Read the committee and node's keypair from file.
-    let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
+    // Read the committee and node's keys from file. With an external signer, the file only needs
+    // to hold the public key.
+    let signer = matches.value_of("signer").map(PathBuf::from);
+    let (name, secret) = match signer {
+        Some(_) => {
+            let identity =
+                PublicIdentity::import(key_file).context("Failed to load the node's public key")?;
+            (identity.name, None)
+        }
+        None => {
+            let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
+            (keypair.name, Some(keypair.secret))
+        }
+    };
     let committee =
         Committee::import(committee_file).context("Failed to load the committee information")?;
 
@@ -85,11 +161,78 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
         None => Parameters::default(),
     };
 
+    // Announce the features we enable to our peers.
+    let mut features = network::Features::NONE;
+    if parameters.transport_compression != TransportCompression::None {
+        features = features | network::Features::COMPRESSION;
+    }
+    if parameters.certificate_diffusion.mode != DiffusionMode::Broadcast {
+        features = features | network::Features::GOSSIP;
+    }
+
+    // Open our connections to the other authorities through our relays (if any), proving our
+    // identity to the relays.
+    let mut relays = BTreeMap::new();
+    if !parameters.relays.is_empty() {
+        for (_, authority) in committee.authorities.iter().filter(|(x, _)| **x != name) {
+            relays.insert(
+                authority.primary.primary_to_primary,
+                parameters.relays.clone(),
+            );
+            for addresses in authority.workers.values() {
+                relays.insert(addresses.worker_to_worker, parameters.relays.clone());
+            }
+        }
+    }
+
+    // Reach the peers whose addresses are hostnames by resolving them when (re)connecting.
+    let hostnames =
+        config::hostnames(committee_file).context("Failed to load the committee information")?;
+    let mut context = network::NetworkContext::new()
+        .with_hostnames(hostnames)
+        .with_relays(relays)
+        .with_features(features);
+    match &secret {
+        Some(secret) => context = context.with_identity(name, secret.duplicate()),
+        None if !parameters.relays.is_empty() => {
+            bail!("The relays need the node's secret key, which the external signer holds")
+        }
+        None if parameters.transport_security != TransportSecurity::Plaintext => {
+            bail!("Transport security needs the node's secret key, which the external signer holds")
+        }
+        None if parameters.transport_protocol == TransportProtocol::Quic => {
+            bail!("QUIC needs the node's secret key, which the external signer holds")
+        }
+        None if parameters.aggregate_votes && committee.bls_key(&name).is_none() => {
+            bail!("Aggregating votes with the external signer needs the node's BLS key in the committee")
+        }
+        None => (),
+    }
+
+    // Secure our connections to the other authorities (if enabled), authenticating each of them
+    // by the key of its authority.
+    let security = match parameters.transport_security {
+        TransportSecurity::Plaintext => network::Security::Plaintext,
+        TransportSecurity::Tls => network::Security::Tls,
+        TransportSecurity::Noise => network::Security::Noise,
+    };
+    let mut context = context
+        .with_security(security, committee.network_keys())
+        .context("Failed to secure the connections")?;
+
+    // Reach the other authorities over QUIC (if enabled), which authenticates them the same way.
+    if let (TransportProtocol::Quic, Some(secret)) = (parameters.transport_protocol, &secret) {
+        let transport = network::QuicTransport::new(&name, secret, committee.network_keys())
+            .context("Failed to set up QUIC")?;
+        context = context.with_peer_transport(transport);
+    }
+
     // Make the data store.
     let store = Store::new(store_path).context("Failed to create a store")?;
 
     // Channels the sequence of certificates.
     let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
+    let (tx_subscription, rx_subscription) = channel(CHANNEL_CAPACITY);
 
     // Check whether to run a primary, a worker, or an entire authority.
     match matches.subcommand() {
@@ -97,21 +240,93 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
         ("primary", _) => {
             let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
             let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
+            let (tx_primary_gc_depth, rx_primary_gc_depth) = channel(CHANNEL_CAPACITY);
+            let (tx_proposer, rx_proposer) = channel(CHANNEL_CAPACITY);
+            let (tx_consensus_gc_depth, rx_consensus_gc_depth) = channel(CHANNEL_CAPACITY);
+            let (tx_consensus_debug, rx_consensus_debug) = channel(CHANNEL_CAPACITY);
+            let (tx_consensus_output, rx_consensus_output) = channel(CHANNEL_CAPACITY);
+            let (tx_equivocations, rx_equivocations) = channel(CHANNEL_CAPACITY);
+            let (tx_system, rx_system) = channel(CHANNEL_CAPACITY);
+            let (tx_progress, rx_progress) = channel(CHANNEL_CAPACITY);
+            let registry = Registry::new();
+            let worker_cache = WorkerCache::new(&committee);
+            let signature_service = match (signer, secret) {
+                (Some(path), _) => {
+                    let bls_key = committee.bls_key(&name);
+                    SignatureService::with_signer(RemoteSigner::new(path, name, bls_key))
+                }
+                (None, Some(secret)) => SignatureService::new(secret),
+                (None, None) => unreachable!(),
+            };
             Primary::spawn(
-                keypair,
+                name,
+                signature_service,
                 committee.clone(),
+                worker_cache.clone(),
                 parameters.clone(),
-                store,
+                store.clone(),
+                &PathBuf::from(format!("{}.votes", store_path)),
                 /* tx_consensus */ tx_new_certificates,
                 /* rx_consensus */ rx_feedback,
+                /* rx_gc_depth */ rx_primary_gc_depth,
+                /* rx_proposer_commands */ rx_proposer,
+                tx_equivocations,
+                tx_progress,
+                rx_system,
+                context,
+                &registry,
             );
+            tokio::spawn(report_equivocations(rx_equivocations, tx_system));
+            tokio::spawn(report_progress(rx_progress));
             Consensus::spawn(
-                committee,
+                committee.clone(),
                 parameters.gc_depth,
+                parameters.leader_schedule,
                 /* rx_primary */ rx_new_certificates,
+                /* rx_gc_depth */ rx_consensus_gc_depth,
+                /* rx_debug */ rx_consensus_debug,
                 /* tx_primary */ tx_feedback,
+                /* tx_output */ tx_consensus_output,
+            );
+
+            // Buffer the consensus' output for the application layer.
+            let output_lag = Arc::new(AtomicU64::new(0));
+            OutputBuffer::spawn(
+                store.clone(),
+                parameters.output_buffer,
+                output_lag.clone(),
+                /* rx_consensus */ rx_consensus_output,
                 tx_output,
             );
+
+            // Spawn the network receiver listening to admin commands.
+            if let Some(address) = admin_address {
+                NetworkReceiver::spawn(
+                    address,
+                    /* handler */
+                    AdminHandler {
+                        tx_primary_gc_depth,
+                        tx_consensus_gc_depth,
+                        tx_consensus_debug,
+                        output_lag,
+                        worker_cache,
+                        tx_proposer,
+                    },
+                );
+                info!("Node listening to admin commands on {}", address);
+            }
+
+            // Spawn the gRPC service serving the certificates and the consensus output.
+            if let Some(address) = grpc_address {
+                ReadService::spawn(address, committee, store, tx_subscription.clone()).await;
+                info!("Node serving gRPC requests on {}", address);
+            }
+
+            // Serve the metrics of the primary to Prometheus.
+            if let Some(address) = metrics_address {
+                MetricsExporter::spawn(address, registry).await;
+                info!("Node serving metrics on {}", address);
+            }
         }
 
         // Spawn a single worker.
@@ -121,21 +336,193 @@ async fn run(matches: &ArgMatches<'_>) -> Result<()> {
                 .unwrap()
                 .parse::<WorkerId>()
                 .context("The worker id must be a positive integer")?;
-            Worker::spawn(keypair.name, id, committee, parameters, store);
+            let registry = Registry::new();
+            let key_share = matches
+                .value_of("key-share")
+                .map(KeyShare::import)
+                .transpose()
+                .context("Failed to load the node's decryption key share")?;
+            let (submitter, fetcher) = Worker::spawn(
+                name,
+                id,
+                committee,
+                parameters,
+                store,
+                Arc::new(AcceptAll),
+                key_share,
+                Some(Arc::new(RustlsProvider)),
+                context,
+                &registry,
+            );
+
+            // Spawn the gRPC service accepting the transactions of the clients.
+            if let Some(address) = grpc_address {
+                SubmitService::spawn(address, submitter, fetcher);
+                info!("Node serving gRPC requests on {}", address);
+            }
+
+            // Serve the metrics of the worker to Prometheus.
+            if let Some(address) = metrics_address {
+                MetricsExporter::spawn(address, registry).await;
+                info!("Node serving metrics on {}", address);
+            }
         }
         _ => unreachable!(),
     }
 
+    // Fan out the consensus' output to the subscribers of the application layer.
+    Dispatcher::spawn(rx_output, rx_subscription);
+
     // Analyze the consensus' output.
-    analyze(rx_output).await;
+    analyze(tx_subscription).await;
 
     // If this expression is reached, the program ends and all other tasks terminate.
     unreachable!();
 }
 
+// Runs a relay hiding the IP address of a validator.
+async fn relay(matches: &ArgMatches<'_>) -> Result<()> {
+    let committee_file = matches.value_of("committee").unwrap();
+    let addresses_file = matches.value_of("addresses").unwrap();
+
+    let committee =
+        Committee::import(committee_file).context("Failed to load the committee information")?;
+    let addresses =
+        RelayAddresses::import(addresses_file).context("Failed to load the relay addresses")?;
+    let mut hostnames =
+        config::hostnames(committee_file).context("Failed to load the committee information")?;
+    hostnames
+        .extend(config::hostnames(addresses_file).context("Failed to load the relay addresses")?);
+
+    // The validator may only reach the other authorities through the relay.
+    let mut destinations = BTreeSet::new();
+    for authority in committee.authorities.values() {
+        destinations.insert(authority.primary.primary_to_primary);
+        for addresses in authority.workers.values() {
+            destinations.insert(addresses.worker_to_worker);
+        }
+    }
+    let forwards = addresses
+        .forwards
+        .iter()
+        .map(|x| (x.listen_address(), x.validator))
+        .collect();
+    network::Relay::spawn(
+        addresses.internal,
+        addresses.name,
+        destinations,
+        forwards,
+        network::NetworkContext::new().with_hostnames(hostnames),
+    );
+
+    // The relay runs until the program is killed.
+    std::future::pending::<()>().await;
+    unreachable!();
+}
+
+// Compares the store snapshots of two primaries.
+async fn diff(matches: &ArgMatches<'_>) -> Result<()> {
+    let committee_file = matches.value_of("committee").unwrap();
+    let parameters_file = matches.value_of("parameters");
+    let first_path = matches.value_of("first").unwrap();
+    let second_path = matches.value_of("second").unwrap();
+
+    let committee =
+        Committee::import(committee_file).context("Failed to load the committee information")?;
+    let parameters = match parameters_file {
+        Some(filename) => {
+            Parameters::import(filename).context("Failed to load the node's parameters")?
+        }
+        None => Parameters::default(),
+    };
+
+    // Replay both snapshots through the consensus and print their differences.
+    let first = load_certificates(first_path).await?;
+    let second = load_certificates(second_path).await?;
+    let diff = SnapshotDiff::compute(
+        committee,
+        parameters.gc_depth,
+        parameters.leader_schedule,
+        first,
+        second,
+    )
+    .context("Failed to replay the snapshots")?;
+    print!("{}", diff);
+    Ok(())
+}
+
+// Loads all certificates held in a store snapshot.
+async fn load_certificates(path: &str) -> Result<Vec<Certificate>> {
+    let mut store = Store::new(path).context("Failed to open the store snapshot")?;
+    let certificates = store
+        .read_all()
+        .await
+        .into_iter()
+        .filter_map(|(key, value)| {
+            // The store also holds headers and payload markers: only keep the certificates (they are
+            // stored under their own digest).
+            bincode::deserialize::<Certificate>(&value)
+                .ok()
+                .filter(|x| x.digest().to_vec() == key)
+        })
+        .collect();
+    Ok(certificates)
+}
+
+/// Receives the evidence of the equivocations detected by the primary, and references it in our
+/// next header so that it gets ordered by the consensus.
+async fn report_equivocations(
+    mut rx_equivocations: Receiver<Equivocation>,
+    tx_system: Sender<SystemMessage>,
+) {
+    while let Some(equivocation) = rx_equivocations.recv().await {
+        // NOTE: Here goes the slashing or monitoring logic. The evidence is self-contained: anyone
+        // knowing the committee can check it with `Equivocation::verify`.
+        let bytes = bincode::serialize(&equivocation).expect("Failed to serialize evidence");
+        warn!(
+            "Authority {} equivocated at round {}: {}",
+            equivocation.author(),
+            equivocation.round(),
+            base64::encode(&bytes)
+        );
+        let message = SystemMessage::Evidence(equivocation.author(), equivocation.digest());
+        tx_system
+            .send(message)
+            .await
+            .expect("Failed to send evidence to the primary");
+    }
+}
+
+/// Receives the progress of the certificate formation of our headers.
+async fn report_progress(mut rx_progress: Receiver<HeaderProgress>) {
+    while let Some(progress) = rx_progress.recv().await {
+        // NOTE: Here goes the monitoring logic.
+        debug!(
+            "Header {} (round {}) collected {} stake in {} ms (certified: {}, missing: {:?})",
+            progress.header,
+            progress.round,
+            progress.stake,
+            progress.elapsed.as_millis(),
+            progress.certified,
+            progress.missing
+        );
+    }
+}
+
 /// Receives an ordered list of certificates and apply any application-specific logic.
-async fn analyze(mut rx_output: Receiver<Certificate>) {
-    while let Some(_certificate) = rx_output.recv().await {
+async fn analyze(tx_subscription: Sender<Subscription>) {
+    // NOTE: Here goes the filter selecting the payloads the application is interested in.
+    let (tx_output, mut rx_output): (_, Receiver<FilteredOutput>) = channel(CHANNEL_CAPACITY);
+    let subscription = Subscription {
+        filter: CommitFilter::All,
+        tx_output,
+    };
+    tx_subscription
+        .send(subscription)
+        .await
+        .expect("Failed to subscribe to the consensus output");
+
+    while let Some(_output) = rx_output.recv().await {
         // NOTE: Here goes the application logic.
     }
 }
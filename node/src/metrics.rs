@@ -0,0 +1,56 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use log::{debug, error};
+use prometheus::{Encoder as _, Registry, TextEncoder};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpListener;
+
+/// Serves the metrics of the registry to Prometheus over HTTP. Every request gets the metrics,
+/// regardless of its path.
+pub struct MetricsExporter;
+
+impl MetricsExporter {
+    pub async fn spawn(address: SocketAddr, registry: Registry) {
+        let listener = match TcpListener::bind(&address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to bind the metrics exporter to {}: {}", address, e);
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, peer) = match listener.accept().await {
+                    Ok(x) => x,
+                    Err(e) => {
+                        debug!("Failed to accept metrics connection: {}", e);
+                        continue;
+                    }
+                };
+                let registry = registry.clone();
+                tokio::spawn(async move {
+                    // We do not parse the request: it is enough to wait for it.
+                    let mut buffer = [0u8; 1024];
+                    if socket.read(&mut buffer).await.is_err() {
+                        return;
+                    }
+                    let mut body = Vec::new();
+                    let encoder = TextEncoder::new();
+                    if let Err(e) = encoder.encode(&registry.gather(), &mut body) {
+                        error!("Failed to encode metrics: {}", e);
+                        return;
+                    }
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        encoder.format_type(),
+                        body.len()
+                    );
+                    let response = [header.into_bytes(), body].concat();
+                    if let Err(e) = socket.write_all(&response).await {
+                        debug!("Failed to send metrics to {}: {}", peer, e);
+                    }
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use config::{Authority, BatchCompression, BatchDissemination, PrimaryAddresses};
+use crypto::{generate_keypair, SecretKey};
+use primary::Header;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::collections::{BTreeSet, HashMap};
+use tempfile::TempDir;
+
+// Fixture
+fn keys() -> Vec<(PublicKey, SecretKey)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..4).map(|_| generate_keypair(&mut rng)).collect()
+}
+
+// Fixture
+fn committee() -> Committee {
+    Committee {
+        epoch: 0,
+        authorities: keys()
+            .iter()
+            .map(|(id, _)| {
+                (
+                    *id,
+                    Authority {
+                        stake: 1,
+                        primary: PrimaryAddresses {
+                            primary_to_primary: "0.0.0.0:0".parse().unwrap(),
+                            worker_to_primary: "0.0.0.0:0".parse().unwrap(),
+                        },
+                        workers: HashMap::default(),
+                        bls_key: None,
+                        bls_proof: None,
+                    },
+                )
+            })
+            .collect(),
+        batch_compression: BatchCompression::default(),
+        batch_dissemination: BatchDissemination::default(),
+        max_batch_size: None,
+        encryption_key: None,
+    }
+}
+
+// Fixture
+fn certificate(origin: PublicKey, round: Round) -> Certificate {
+    Certificate {
+        header: Header {
+            author: origin,
+            round,
+            parents: BTreeSet::new(),
+            ..Header::default()
+        },
+        ..Certificate::default()
+    }
+}
+
+// Fixture
+fn test_store() -> (Store, TempDir) {
+    let directory = TempDir::new().unwrap();
+    let store = Store::new(directory.path().to_str().unwrap()).unwrap();
+    (store, directory)
+}
+
+// Stores the certificates of all authorities for the specified rounds, like the core does.
+async fn store_rounds(store: &mut Store, rounds: &[Round]) -> Vec<Certificate> {
+    let mut certificates = Vec::new();
+    for round in rounds {
+        for (name, _) in keys() {
+            let certificate = certificate(name, *round);
+            let bytes = bincode::serialize(&certificate).unwrap();
+            store.write(certificate.digest().to_vec(), bytes).await;
+            let key = Certificate::round_key(*round, &name);
+            store.write(key, certificate.digest().to_vec()).await;
+            certificates.push(certificate);
+        }
+    }
+    certificates
+}
+
+// Collects the rounds of the certificates streamed from the specified round.
+async fn history(service: &ReadService, from: Round) -> Vec<Round> {
+    let (tx_history, mut rx_history) = channel(HISTORY_BUFFER);
+    let feeder = service.clone();
+    tokio::spawn(async move { feeder.feed_history(from, tx_history).await });
+    let mut rounds = Vec::new();
+    while let Some(certificate) = rx_history.recv().await {
+        rounds.push(certificate.unwrap().round);
+    }
+    rounds
+}
+
+#[tokio::test]
+async fn get_certificates() {
+    let (mut store, _directory) = test_store();
+    let certificates = store_rounds(&mut store, &[1]).await;
+    let service = ReadService::new(committee(), store).await;
+
+    // Read a certificate by digest.
+    let digest = certificates[0].digest();
+    let request = Request::new(proto::CertificateDigest {
+        digest: digest.to_vec(),
+    });
+    let reply = service.get_certificate(request).await.unwrap().into_inner();
+    assert_eq!(reply.digest, digest.to_vec());
+    assert_eq!(reply.round, 1);
+
+    // Read all the certificates of a round.
+    let request = Request::new(proto::Round { round: 1 });
+    let reply = service.get_certificates_by_round(request).await.unwrap();
+    assert_eq!(reply.into_inner().certificates.len(), certificates.len());
+
+    // Ensure unknown certificates are reported as such.
+    let request = Request::new(proto::CertificateDigest {
+        digest: Digest::default().to_vec(),
+    });
+    let status = service.get_certificate(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn get_last_committed() {
+    let (store, _directory) = test_store();
+    let service = ReadService::new(committee(), store.clone()).await;
+
+    let request = Request::new(proto::Empty {});
+    let status = service.get_last_committed(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let (name, _) = keys().pop().unwrap();
+    let output = ConsensusOutput {
+        certificate: certificate(name, 2),
+        index: 5,
+        anchor: Digest::default(),
+        duplicates: BTreeSet::new(),
+    };
+    service.record_commit(&output).await;
+    let request = Request::new(proto::Empty {});
+    let reply = service.get_last_committed(request).await.unwrap();
+    assert_eq!(reply.into_inner().index, 5);
+
+    // Ensure the last committed certificate survives a restart.
+    let service = ReadService::new(committee(), store).await;
+    let request = Request::new(proto::Empty {});
+    let reply = service.get_last_committed(request).await.unwrap();
+    assert_eq!(reply.into_inner().index, 5);
+}
+
+#[tokio::test]
+async fn stream_history() {
+    let (mut store, _directory) = test_store();
+    store_rounds(&mut store, &[1, 2, 3]).await;
+    let service = ReadService::new(committee(), store).await;
+
+    // The stream ends at the first round we do not hold.
+    let expected: Vec<_> = [1, 2, 3].iter().flat_map(|x| vec![*x; 4]).collect();
+    assert_eq!(history(&service, 0).await, expected);
+    assert_eq!(history(&service, 3).await, vec![3; 4]);
+    assert!(history(&service, 4).await.is_empty());
+}
+
+#[tokio::test]
+async fn stream_history_across_missing_rounds() {
+    // We caught up from round 5: we miss the earlier rounds.
+    let (mut store, _directory) = test_store();
+    store_rounds(&mut store, &[1, 2, 5, 6]).await;
+    let service = ReadService::new(committee(), store).await;
+    let (name, _) = keys().pop().unwrap();
+    let output = ConsensusOutput {
+        certificate: certificate(name, 5),
+        index: 0,
+        anchor: Digest::default(),
+        duplicates: BTreeSet::new(),
+    };
+    service.record_commit(&output).await;
+
+    // Ensure the stream goes past the missing rounds, up to the tip of the DAG.
+    let expected: Vec<_> = [1, 2, 5, 6].iter().flat_map(|x| vec![*x; 4]).collect();
+    assert_eq!(history(&service, 1).await, expected);
+}
@@ -1,15 +1,37 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
-use crate::messages::{Certificate, Header, Vote};
+use crate::messages::{AggregateVotes, Certificate, Header, Vote};
+use crate::primary::Round;
 use config::{Committee, Stake};
 use crypto::Hash as _;
-use crypto::{Digest, PublicKey, Signature};
+use crypto::{BlsSignature, Digest, PublicKey, Signature};
+use log::warn;
 use std::collections::HashSet;
+use std::time::Duration;
+
+/// The progress of the certificate formation of one of our headers.
+#[derive(Clone, Debug)]
+pub struct HeaderProgress {
+    /// The digest of the header.
+    pub header: Digest,
+    /// The round of the header.
+    pub round: Round,
+    /// The stake of the authorities that voted for the header so far.
+    pub stake: Stake,
+    /// The authorities that did not vote for the header yet.
+    pub missing: Vec<PublicKey>,
+    /// The time elapsed since we proposed the header.
+    pub elapsed: Duration,
+    /// Whether the votes reached a quorum.
+    pub certified: bool,
+}
 
 /// Aggregates votes for a particular header into a certificate.
 pub struct VotesAggregator {
     weight: Stake,
+    certified: bool,
     votes: Vec<(PublicKey, Signature)>,
+    bls_signatures: Vec<BlsSignature>,
     used: HashSet<PublicKey>,
 }
 
@@ -17,11 +39,45 @@ impl VotesAggregator {
     pub fn new() -> Self {
         Self {
             weight: 0,
+            certified: false,
             votes: Vec::new(),
+            bls_signatures: Vec::new(),
             used: HashSet::new(),
         }
     }
 
+    /// Returns the number of votes received so far.
+    pub fn votes(&self) -> usize {
+        self.votes.len()
+    }
+
+    /// Returns whether the votes reached a quorum.
+    pub fn certified(&self) -> bool {
+        self.certified
+    }
+
+    /// Reports the progress of the certificate formation of the header.
+    pub fn progress(
+        &self,
+        committee: &Committee,
+        header: &Header,
+        elapsed: Duration,
+    ) -> HeaderProgress {
+        HeaderProgress {
+            header: header.id.clone(),
+            round: header.round,
+            stake: self.used.iter().map(|x| committee.stake(x)).sum(),
+            missing: committee
+                .authorities
+                .keys()
+                .filter(|x| !self.used.contains(x))
+                .cloned()
+                .collect(),
+            elapsed,
+            certified: self.certified,
+        }
+    }
+
     pub fn append(
         &mut self,
         vote: Vote,
@@ -34,16 +90,47 @@ impl VotesAggregator {
         ensure!(self.used.insert(author), DagError::AuthorityReuse(author));
 
         self.votes.push((author, vote.signature));
+        self.bls_signatures.extend(vote.bls_signature);
         self.weight += committee.stake(&author);
         if self.weight >= committee.quorum_threshold() {
             self.weight = 0; // Ensures quorum is only reached once.
-            return Ok(Some(Certificate {
-                header: header.clone(),
-                votes: self.votes.clone(),
-            }));
+            self.certified = true;
+            let certificate = match self.aggregate(committee, header) {
+                Some(certificate) => certificate,
+                None => Certificate {
+                    header: header.clone(),
+                    votes: self.votes.clone(),
+                    aggregate: None,
+                },
+            };
+            return Ok(Some(certificate));
         }
         Ok(None)
     }
+
+    /// Aggregates the votes into a single BLS signature, provided every vote carries one. The
+    /// aggregate is verified once here (the BLS signatures of the individual votes are not), so
+    /// we fall back to the individual ed25519 signatures if one of the voters misbehaved.
+    fn aggregate(&self, committee: &Committee, header: &Header) -> Option<Certificate> {
+        if self.bls_signatures.len() != self.votes.len() {
+            return None;
+        }
+        let signers: Vec<_> = self.votes.iter().map(|(name, _)| *name).collect();
+        let signature = BlsSignature::aggregate(&self.bls_signatures).ok()?;
+        let certificate = Certificate {
+            header: header.clone(),
+            votes: Vec::new(),
+            aggregate: Some(AggregateVotes::new(&signers, signature, committee)),
+        };
+        let aggregate = certificate.aggregate.as_ref()?;
+        match aggregate.verify(&certificate.digest(), committee) {
+            Ok(()) => Some(certificate),
+            Err(e) => {
+                warn!("Failed to aggregate the votes of {}: {}", header.id, e);
+                None
+            }
+        }
+    }
 }
 
 /// Aggregate certificates and check if we reach a quorum.
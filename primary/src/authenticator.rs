@@ -0,0 +1,112 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::{DagError, DagResult};
+use crate::messages::now;
+use crate::primary::PrimaryMessage;
+use bytes::Bytes;
+use crypto::{Digest, Signature, SignatureService};
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/authenticator_tests.rs"]
+pub mod authenticator_tests;
+
+/// Separates the signatures of our messages from those of headers and votes.
+const DOMAIN: &[u8] = b"NARWHAL-MESSAGE";
+
+/// How far (in ms) the nonce of a signed message may be from our local time. The replay cache of
+/// the receiver drops the replays of the messages still within this window.
+pub const MAX_NONCE_SKEW: u64 = 30_000;
+
+/// Serializes the messages we send, signing those claiming to come from us (such as certificate
+/// requests) so that our peers can check their origin. Each signature also covers a nonce (the
+/// time of signing, in ms), so that our peers may drop the stale and replayed messages. Cloning the
+/// authenticator shares its nonces.
+#[derive(Clone)]
+pub struct Authenticator {
+    /// Signs our messages, if we authenticate them.
+    signature_service: Option<SignatureService>,
+    /// The last nonce we signed: we never sign the same one twice.
+    nonce: Arc<AtomicU64>,
+}
+
+impl Authenticator {
+    /// Signs the messages claiming to come from us with the specified service.
+    pub fn new(signature_service: SignatureService) -> Self {
+        Self {
+            signature_service: Some(signature_service),
+            nonce: Arc::default(),
+        }
+    }
+
+    /// Sends our messages unsigned.
+    pub fn disabled() -> Self {
+        Self {
+            signature_service: None,
+            nonce: Arc::default(),
+        }
+    }
+
+    /// Serializes a message, wrapping it into a signed envelope if it claims to come from us.
+    pub async fn serialize(&mut self, message: &PrimaryMessage) -> Bytes {
+        let bytes = bincode::serialize(message).expect("Failed to serialize our own message");
+        let signature_service = match &mut self.signature_service {
+            Some(x) if message.origin().is_some() => x,
+            _ => return Bytes::from(bytes),
+        };
+        let nonce = next_nonce(&self.nonce);
+        let signature = signature_service
+            .request_signature(digest(&bytes, nonce))
+            .await;
+        let signed = PrimaryMessage::Signed(bytes, nonce, signature);
+        Bytes::from(bincode::serialize(&signed).expect("Failed to serialize our own message"))
+    }
+}
+
+/// Returns the nonce of the next message we sign (after the specified one): the current time,
+/// unless we already signed it.
+fn next_nonce(last: &AtomicU64) -> u64 {
+    let now = now();
+    let previous = last
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| Some(now.max(x + 1)))
+        .expect("The update always succeeds");
+    now.max(previous + 1)
+}
+
+/// Returns the digest signed by the origin of a serialized message.
+fn digest(serialized: &[u8], nonce: u64) -> Digest {
+    let mut hasher = Sha512::new();
+    hasher.update(DOMAIN);
+    hasher.update(nonce.to_le_bytes());
+    hasher.update(serialized);
+    Digest(hasher.finalize()[..32].try_into().unwrap())
+}
+
+/// Opens a signed envelope without checking its signature (see `verify`), so that the receiver
+/// may charge the message to its quotas before spending a signature verification on it.
+pub fn open(serialized: &[u8]) -> DagResult<PrimaryMessage> {
+    let message: PrimaryMessage = bincode::deserialize(serialized)?;
+    match message.origin() {
+        Some(_) => Ok(message),
+        None => Err(DagError::UnexpectedSignedMessage),
+    }
+}
+
+/// Checks that the origin claimed by an opened envelope signed it, recently enough.
+pub fn verify(
+    message: &PrimaryMessage,
+    serialized: &[u8],
+    nonce: u64,
+    signature: &Signature,
+) -> DagResult<()> {
+    let origin = message.origin().ok_or(DagError::UnexpectedSignedMessage)?;
+    ensure!(
+        now().abs_diff(nonce) <= MAX_NONCE_SKEW,
+        DagError::StaleSignedMessage(nonce)
+    );
+    signature.verify(&digest(serialized, nonce), &origin)?;
+    Ok(())
+}
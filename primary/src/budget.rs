@@ -0,0 +1,69 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::VerificationBudget;
+pub use network::TokenBucket;
+use std::fmt;
+use std::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/budget_tests.rs"]
+pub mod budget_tests;
+
+/// The classes of messages whose signatures are verified by the `Verifier`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageClass {
+    Header,
+    Vote,
+    Certificate,
+    SyncResponse,
+}
+
+impl fmt::Display for MessageClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Self::Header => write!(f, "headers"),
+            Self::Vote => write!(f, "votes"),
+            Self::Certificate => write!(f, "certificates"),
+            Self::SyncResponse => write!(f, "sync responses"),
+        }
+    }
+}
+
+/// Keeps a separate verification budget for each class of messages. This ensures that a peer flooding
+/// us with (possibly invalid) messages of one class cannot delay the verification of the other classes.
+pub struct VerificationLimiter {
+    headers: TokenBucket,
+    votes: TokenBucket,
+    certificates: TokenBucket,
+    sync_responses: TokenBucket,
+}
+
+impl VerificationLimiter {
+    pub fn new(budget: VerificationBudget) -> Self {
+        Self {
+            headers: TokenBucket::new(budget.headers),
+            votes: TokenBucket::new(budget.votes),
+            certificates: TokenBucket::new(budget.certificates),
+            sync_responses: TokenBucket::new(budget.sync_responses),
+        }
+    }
+
+    /// Returns `true` if the budget of the specified class allows one more verification.
+    pub fn try_acquire(&mut self, class: MessageClass) -> bool {
+        match class {
+            MessageClass::Header => self.headers.try_acquire(),
+            MessageClass::Vote => self.votes.try_acquire(),
+            MessageClass::Certificate => self.certificates.try_acquire(),
+            MessageClass::SyncResponse => self.sync_responses.try_acquire(),
+        }
+    }
+
+    /// Returns the time until the budget of the specified class allows one more verification.
+    pub fn delay(&mut self, class: MessageClass) -> Duration {
+        match class {
+            MessageClass::Header => self.headers.delay(),
+            MessageClass::Vote => self.votes.delay(),
+            MessageClass::Certificate => self.certificates.delay(),
+            MessageClass::SyncResponse => self.sync_responses.delay(),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::authenticator::Authenticator;
+use crate::budget::TokenBucket;
+use crate::error::{DagError, DagResult};
+use crate::helper::PageToken;
+use crate::metrics::PrimaryMetrics;
+use crate::primary::{PrimaryMessage, Round};
+use config::{Committee, FetchLimits};
+use crypto::{Digest, PublicKey};
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::stream::StreamExt as _;
+use log::{debug, error};
+use network::{NetworkContext, QueueLimits, SimpleSender};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::Store;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/certificate_fetcher_tests.rs"]
+pub mod certificate_fetcher_tests;
+
+/// The resolution of the timer that retries timed out requests and dispatches the requests that
+/// exceeded the rate limits of our peers.
+const TIMER_RESOLUTION: u64 = 100;
+
+/// A certificate we are fetching from our peers.
+struct Request {
+    /// The round of the header waiting for the certificate (used for cleanup).
+    round: Round,
+    /// The peer we asked first (it certified a header referencing the certificate).
+    preferred: PublicKey,
+    /// The peer we are currently waiting for, along with the time we sent it the request.
+    peer: Option<(PublicKey, Instant)>,
+    /// The last peer that failed to deliver the certificate.
+    failed: Option<PublicKey>,
+    /// Cancels the waiter of the certificate.
+    cancel: Sender<()>,
+}
+
+/// Fetches missing certificates from several peers in parallel. Each request is sent to a single
+/// peer at a time; requests are spread across peers within their rate limits, and re-sent to
+/// another peer if they time out.
+pub struct CertificateFetcher {
+    /// The name of this authority.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// The current consensus round (used for cleanup).
+    consensus_round: Arc<AtomicU64>,
+    /// The depth of the garbage collector (it may change at runtime).
+    gc_depth: Arc<AtomicU64>,
+    /// The delay after which we ask another peer for a certificate.
+    timeout: u64,
+    /// The maximum number of certificates we wait for from each peer.
+    max_in_flight: usize,
+
+    /// Receives the missing certificates from the `HeaderWaiter`, along with the author and round
+    /// of the header referencing them.
+    rx_header_waiter: Receiver<(Vec<Digest>, PublicKey, Round)>,
+    /// Receives the tokens of the next pages of certificates our peers have for us.
+    rx_pages: Receiver<(PageToken, PublicKey)>,
+
+    /// The peers we can fetch certificates from.
+    peers: Vec<PublicKey>,
+    /// The index of the next peer to ask (rotating over `peers`).
+    next_peer: usize,
+    /// Limits the number of certificates we request from each peer per second.
+    rate_limits: HashMap<PublicKey, TokenBucket>,
+    /// The number of certificates we are waiting for from each peer.
+    in_flight: HashMap<PublicKey, usize>,
+    /// The certificates we are fetching.
+    requests: HashMap<Digest, Request>,
+    /// The certificates waiting for a peer with spare capacity.
+    queue: VecDeque<Digest>,
+    /// Network driver allowing to send messages.
+    network: SimpleSender,
+    /// Signs the messages claiming to come from us (if we authenticate messages).
+    authenticator: Authenticator,
+    /// The metrics of the primary.
+    metrics: PrimaryMetrics,
+}
+
+impl CertificateFetcher {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        store: Store,
+        consensus_round: Arc<AtomicU64>,
+        gc_depth: Arc<AtomicU64>,
+        timeout: u64,
+        limits: FetchLimits,
+        rx_header_waiter: Receiver<(Vec<Digest>, PublicKey, Round)>,
+        rx_pages: Receiver<(PageToken, PublicKey)>,
+        authenticator: Authenticator,
+        queue_limits: QueueLimits,
+        max_frame_size: usize,
+        metrics: PrimaryMetrics,
+        context: NetworkContext,
+    ) {
+        let peers: Vec<_> = committee
+            .others_primaries(&name)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let rate_limits = peers
+            .iter()
+            .map(|x| (*x, TokenBucket::new(limits.rate_limit)))
+            .collect();
+
+        tokio::spawn(async move {
+            Self {
+                name,
+                committee,
+                store,
+                consensus_round,
+                gc_depth,
+                timeout,
+                max_in_flight: limits.max_in_flight,
+                rx_header_waiter,
+                rx_pages,
+                peers,
+                next_peer: 0,
+                rate_limits,
+                in_flight: HashMap::new(),
+                requests: HashMap::new(),
+                queue: VecDeque::new(),
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue_limits)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics.network.clone()),
+                authenticator,
+                metrics,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Helper function. It waits for a certificate to be stored and returns its digest.
+    async fn waiter(
+        digest: Digest,
+        mut store: Store,
+        mut handler: Receiver<()>,
+    ) -> DagResult<Option<Digest>> {
+        tokio::select! {
+            result = store.notify_read(digest.to_vec()) => {
+                result.map(|_| Some(digest)).map_err(DagError::from)
+            }
+            _ = handler.recv() => Ok(None),
+        }
+    }
+
+    /// Returns whether we can send one more request to the peer (and consumes its rate limit).
+    fn try_acquire(&mut self, peer: &PublicKey) -> bool {
+        let in_flight = self.in_flight.get(peer).copied().unwrap_or_default();
+        in_flight < self.max_in_flight
+            && self
+                .rate_limits
+                .get_mut(peer)
+                .is_some_and(|x| x.try_acquire())
+    }
+
+    /// Selects the peer to ask for a certificate: the preferred peer if it is a first attempt,
+    /// otherwise the next peer (in turn) that did not just fail us and has spare capacity.
+    fn select_peer(
+        &mut self,
+        preferred: Option<PublicKey>,
+        failed: Option<PublicKey>,
+    ) -> Option<PublicKey> {
+        if let Some(peer) = preferred {
+            if self.try_acquire(&peer) {
+                return Some(peer);
+            }
+        }
+        for _ in 0..self.peers.len() {
+            let peer = self.peers[self.next_peer];
+            self.next_peer = (self.next_peer + 1) % self.peers.len();
+            let rotate = self.peers.len() > 1 && Some(peer) == failed;
+            if !rotate && self.try_acquire(&peer) {
+                return Some(peer);
+            }
+        }
+        None
+    }
+
+    /// Sends the queued requests to the peers with spare capacity.
+    async fn dispatch(&mut self) {
+        let mut batches: HashMap<PublicKey, Vec<Digest>> = HashMap::new();
+        while let Some(digest) = self.queue.pop_front() {
+            let (preferred, failed) = match self.requests.get(&digest) {
+                Some(request) if request.failed.is_none() => (Some(request.preferred), None),
+                Some(request) => (None, request.failed),
+                None => continue, // The request was cleaned up.
+            };
+            let peer = match self.select_peer(preferred, failed) {
+                Some(peer) => peer,
+                None => {
+                    // All our peers are busy: retry later.
+                    self.queue.push_front(digest);
+                    break;
+                }
+            };
+            *self.in_flight.entry(peer).or_default() += 1;
+            if let Some(request) = self.requests.get_mut(&digest) {
+                request.peer = Some((peer, Instant::now()));
+            }
+            batches.entry(peer).or_default().push(digest);
+        }
+
+        for (peer, digests) in batches {
+            debug!("Requesting {} certificates from {}", digests.len(), peer);
+            let address = self
+                .committee
+                .primary(&peer)
+                .expect("Peer is not in the committee")
+                .primary_to_primary;
+            let message = PrimaryMessage::CertificatesRequest(digests, self.name);
+            let bytes = self.authenticator.serialize(&message).await;
+            self.network.send(address, bytes).await;
+        }
+    }
+
+    /// Releases the capacity the request was using on its peer.
+    fn release(&mut self, peer: Option<(PublicKey, Instant)>) {
+        if let Some((peer, _)) = peer {
+            if let Some(in_flight) = self.in_flight.get_mut(&peer) {
+                *in_flight = in_flight.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Main loop listening to the `HeaderWaiter` requests.
+    async fn run(&mut self) {
+        let mut waiting = FuturesUnordered::new();
+
+        let timer = sleep(Duration::from_millis(TIMER_RESOLUTION));
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                Some((digests, author, round)) = self.rx_header_waiter.recv() => {
+                    for digest in digests {
+                        // Ensure we fetch each certificate only once.
+                        if self.requests.contains_key(&digest) {
+                            continue;
+                        }
+                        let (tx_cancel, rx_cancel) = channel(1);
+                        let request = Request {
+                            round,
+                            preferred: author,
+                            peer: None,
+                            failed: None,
+                            cancel: tx_cancel,
+                        };
+                        self.requests.insert(digest.clone(), request);
+                        self.queue.push_back(digest.clone());
+                        waiting.push(Self::waiter(digest, self.store.clone(), rx_cancel));
+                    }
+                    self.dispatch().await;
+                },
+
+                Some((token, peer)) = self.rx_pages.recv() => {
+                    // Request the next page only if we still wait for certificates from this peer.
+                    if self.in_flight.get(&peer).is_some_and(|x| *x > 0) {
+                        if let Ok(address) = self.committee.primary(&peer) {
+                            let message = PrimaryMessage::NextCertificatesRequest(token, self.name);
+                            let bytes = self.authenticator.serialize(&message).await;
+                            self.network.send(address.primary_to_primary, bytes).await;
+                        }
+                    }
+                },
+
+                Some(result) = waiting.next() => match result {
+                    Ok(Some(digest)) => {
+                        if let Some(request) = self.requests.remove(&digest) {
+                            self.release(request.peer);
+                        }
+                        self.dispatch().await;
+                    },
+                    Ok(None) => {
+                        // This request has been canceled.
+                    },
+                    Err(e) => {
+                        error!("{}", e);
+                        panic!("Storage failure: killing node.");
+                    }
+                },
+
+                () = &mut timer => {
+                    // Ask another peer for the certificates that timed out.
+                    let timeout = Duration::from_millis(self.timeout);
+                    let mut expired = Vec::new();
+                    for (digest, request) in &mut self.requests {
+                        if let Some((peer, sent)) = request.peer {
+                            if sent.elapsed() > timeout {
+                                debug!("Request for certificate {} to {} timed out", digest, peer);
+                                request.failed = Some(peer);
+                                expired.push((digest.clone(), request.peer.take()));
+                            }
+                        }
+                    }
+                    for (digest, peer) in expired {
+                        self.release(peer);
+                        self.queue.push_back(digest);
+                    }
+                    self.dispatch().await;
+
+                    // Reschedule the timer.
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
+                }
+            }
+
+            // Cleanup internal state.
+            let round = self.consensus_round.load(Ordering::Relaxed);
+            let gc_depth = self.gc_depth.load(Ordering::Relaxed);
+            if round > gc_depth {
+                let gc_round = round - gc_depth;
+                let stale: Vec<_> = self
+                    .requests
+                    .iter()
+                    .filter(|(_, x)| x.round <= gc_round)
+                    .map(|(digest, _)| digest.clone())
+                    .collect();
+                for digest in stale {
+                    if let Some(request) = self.requests.remove(&digest) {
+                        let _ = request.cancel.send(()).await;
+                        self.release(request.peer);
+                    }
+                }
+                let requests = &self.requests;
+                self.queue.retain(|x| requests.contains_key(x));
+            }
+            self.metrics
+                .sync_requests_in_flight
+                .set(self.requests.len() as i64);
+        }
+    }
+}
@@ -1,35 +1,67 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::messages::Certificate;
+use crate::metrics::PrimaryMetrics;
+use crate::primary::Round;
+use crate::synchronizer::SyncObligations;
+use crypto::Digest;
+use crypto::Hash as _;
 use futures::future::try_join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use log::error;
+use log::{debug, error};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use store::Store;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/certificate_waiter_tests.rs"]
+pub mod certificate_waiter_tests;
 
 /// Waits to receive all the ancestors of a certificate before looping it back to the `Core`
 /// for further processing.
 pub struct CertificateWaiter {
     /// The persistent storage.
     store: Store,
+    /// The current consensus round (used for cleanup).
+    consensus_round: Arc<AtomicU64>,
+    /// The depth of the garbage collector (it may change at runtime).
+    gc_depth: Arc<AtomicU64>,
+    /// The missing dependencies we are waiting for (reserved by the `Synchronizer`).
+    obligations: SyncObligations,
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<Certificate>,
     /// Loops back to the core certificates for which we got all parents.
     tx_core: Sender<Certificate>,
+    /// The metrics of the primary.
+    metrics: PrimaryMetrics,
+    /// The certificates waiting for their ancestors (indexed by digest), along with the handler
+    /// cancelling their wait.
+    pending: HashMap<Digest, (Certificate, Sender<()>)>,
 }
 
 impl CertificateWaiter {
     pub fn spawn(
         store: Store,
+        consensus_round: Arc<AtomicU64>,
+        gc_depth: Arc<AtomicU64>,
+        obligations: SyncObligations,
         rx_synchronizer: Receiver<Certificate>,
         tx_core: Sender<Certificate>,
+        metrics: PrimaryMetrics,
     ) {
         tokio::spawn(async move {
             Self {
                 store,
+                consensus_round,
+                gc_depth,
+                obligations,
                 rx_synchronizer,
                 tx_core,
+                metrics,
+                pending: HashMap::new(),
             }
             .run()
             .await
@@ -41,16 +73,48 @@ impl CertificateWaiter {
     async fn waiter(
         mut missing: Vec<(Vec<u8>, Store)>,
         deliver: Certificate,
-    ) -> DagResult<Certificate> {
+        mut handler: Receiver<()>,
+    ) -> DagResult<Option<Certificate>> {
         let waiting: Vec<_> = missing
             .iter_mut()
             .map(|(x, y)| y.notify_read(x.to_vec()))
             .collect();
+        tokio::select! {
+            result = try_join_all(waiting) => {
+                result.map(|_| Some(deliver)).map_err(DagError::from)
+            }
+            _ = handler.recv() => Ok(None),
+        }
+    }
 
-        try_join_all(waiting)
-            .await
-            .map(|_| deliver)
-            .map_err(DagError::from)
+    /// Stops waiting for the certificates whose ancestors fell behind the garbage collector: the
+    /// `Core` drops certificates older than `gc_round`, so these ancestors never reach the store.
+    /// The certificates of `gc_round` itself are still accepted by the `Core`: we re-sync them at
+    /// the new watermark (the `Synchronizer` does not wait for their ancestors anymore).
+    async fn cleanup(&mut self, gc_round: Round) {
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, (x, _))| x.round() <= gc_round)
+            .map(|(digest, _)| digest.clone())
+            .collect();
+        for digest in expired {
+            let (certificate, handler) = match self.pending.remove(&digest) {
+                Some(x) => x,
+                None => continue,
+            };
+            let _ = handler.send(()).await;
+            self.obligations.release(certificate.header.parents.len());
+            self.metrics.cancelled_waits.inc();
+
+            if certificate.round() == gc_round {
+                debug!("Re-syncing {:?} at gc round {}", certificate, gc_round);
+                self.tx_core
+                    .send(certificate)
+                    .await
+                    .expect("Failed to send certificate");
+            }
+        }
     }
 
     async fn run(&mut self) {
@@ -59,6 +123,13 @@ impl CertificateWaiter {
         loop {
             tokio::select! {
                 Some(certificate) = self.rx_synchronizer.recv() => {
+                    // Ensure we wait only once per certificate.
+                    let digest = certificate.digest();
+                    if self.pending.contains_key(&digest) {
+                        self.obligations.release(certificate.header.parents.len());
+                        continue;
+                    }
+
                     // Add the certificate to the waiter pool. The waiter will return it to us
                     // when all its parents are in the store.
                     let wait_for = certificate
@@ -68,19 +139,36 @@ impl CertificateWaiter {
                         .cloned()
                         .map(|x| (x.to_vec(), self.store.clone()))
                         .collect();
-                    let fut = Self::waiter(wait_for, certificate);
+                    let (tx_cancel, rx_cancel) = channel(1);
+                    self.pending.insert(digest, (certificate.clone(), tx_cancel));
+                    let fut = Self::waiter(wait_for, certificate, rx_cancel);
                     waiting.push(fut);
                 }
                 Some(result) = waiting.next() => match result {
-                    Ok(certificate) => {
+                    Ok(Some(certificate)) => {
+                        self.pending.remove(&certificate.digest());
+                        self.obligations.release(certificate.header.parents.len());
                         self.tx_core.send(certificate).await.expect("Failed to send certificate");
                     },
+                    Ok(None) => {
+                        // This request has been canceled.
+                    },
                     Err(e) => {
                         error!("{}", e);
                         panic!("Storage failure: killing node.");
                     }
                 },
             }
+
+            // Cleanup internal state.
+            let round = self.consensus_round.load(Ordering::Relaxed);
+            let gc_depth = self.gc_depth.load(Ordering::Relaxed);
+            if round > gc_depth {
+                self.cleanup(round - gc_depth).await;
+            }
+            self.metrics
+                .pending_certificates
+                .set(self.pending.len() as i64);
         }
     }
 }
@@ -1,26 +1,43 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::aggregators::{CertificatesAggregator, VotesAggregator};
+use crate::aggregators::{CertificatesAggregator, HeaderProgress, VotesAggregator};
 use crate::error::{DagError, DagResult};
-use crate::messages::{Certificate, Header, Vote};
+use crate::header_waiter::Loopback;
+use crate::messages::{now, Certificate, Equivocation, Header, Vote};
+use crate::metrics::PrimaryMetrics;
 use crate::primary::{PrimaryMessage, Round};
 use crate::synchronizer::Synchronizer;
+use crate::vote_log::VoteLog;
 use async_recursion::async_recursion;
 use bytes::Bytes;
-use config::Committee;
+use config::{Committee, PayloadAvailability, PayloadCheck, TimestampBounds, VoteBatching};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, SignatureService};
+use futures::future::BoxFuture;
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::stream::StreamExt as _;
 use log::{debug, error, warn};
-use network::{CancelHandler, ReliableSender};
+use network::{
+    CancelHandler, Compression, NetworkContext, Priority, QueueLimits, ReliableSender, RetryPolicy,
+};
+use std::cmp::max;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::io;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/core_tests.rs"]
 pub mod core_tests;
 
+/// The prefix of the store keys holding the last round we voted for each authority.
+const LAST_VOTED_PREFIX: &[u8] = b"last_voted";
+
 pub struct Core {
     /// The public key of this primary.
     name: PublicKey,
@@ -34,13 +51,24 @@ pub struct Core {
     signature_service: SignatureService,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
-    /// The depth of the garbage collector.
-    gc_depth: Round,
+    /// The depth of the garbage collector (it may change at runtime).
+    gc_depth: Arc<AtomicU64>,
+    /// The round of our current header, shared with the `Verifier` so that it drops the votes on
+    /// older headers without verifying them.
+    header_round: Arc<AtomicU64>,
+    /// Whether our votes carry a BLS signature (so that they can be aggregated).
+    aggregate_votes: bool,
+    /// How thoroughly we check the payload of headers before voting for them.
+    payload_availability: PayloadAvailability,
+    /// How we bundle our votes into network messages.
+    vote_batching: VoteBatching,
+    /// The accepted skew between the timestamp of the headers and our local time.
+    timestamp_bounds: TimestampBounds,
 
     /// Receiver for dag messages (headers, votes, certificates).
     rx_primaries: Receiver<PrimaryMessage>,
     /// Receives loopback headers from the `HeaderWaiter`.
-    rx_header_waiter: Receiver<Header>,
+    rx_header_waiter: Receiver<Loopback>,
     /// Receives loopback certificates from the `CertificateWaiter`.
     rx_certificate_waiter: Receiver<Certificate>,
     /// Receives our newly created headers from the `Proposer`.
@@ -49,15 +77,35 @@ pub struct Core {
     tx_consensus: Sender<Certificate>,
     /// Send valid a quorum of certificates' ids to the `Proposer` (along with their round).
     tx_proposer: Sender<(Vec<Digest>, Round)>,
+    /// Outputs the evidence of the equivocations we detect.
+    tx_equivocations: Sender<Equivocation>,
+    /// Sends the certificates to the `Gossip` (if certificates are gossiped rather than broadcast).
+    tx_gossip: Option<Sender<Certificate>>,
+    /// Reports the progress of the certificate formation of our headers (if anyone listens).
+    tx_progress: Option<Sender<HeaderProgress>>,
+    /// The metrics of the primary.
+    metrics: PrimaryMetrics,
 
     /// The last garbage collected round.
     gc_round: Round,
     /// The authors of the last voted headers.
     last_voted: HashMap<Round, HashSet<PublicKey>>,
+    /// The first header received from each author at each round (to detect equivocations), along
+    /// with whether we already reported an equivocation of the author at that round.
+    received_headers: HashMap<Round, HashMap<PublicKey, (Header, bool)>>,
+    /// The highest round we voted for each author (persisted in the store).
+    highest_votes: HashMap<PublicKey, Round>,
+    /// The highest round we voted for each author before restarting. We never vote again for
+    /// these authors at or below these rounds.
+    restored_votes: HashMap<PublicKey, Round>,
     /// The set of headers we are currently processing.
     processing: HashMap<Round, HashSet<Digest>>,
+    /// The headers whose payload our workers confirmed they store.
+    confirmed: HashMap<Round, HashSet<Digest>>,
     /// The last header we proposed (for which we are waiting votes).
     current_header: Header,
+    /// The time at which we proposed the last header.
+    proposed_at: Instant,
     /// Aggregates votes into a certificate.
     votes_aggregator: VotesAggregator,
     /// Aggregates certificates to use as parents for new headers.
@@ -66,6 +114,23 @@ pub struct Core {
     network: ReliableSender,
     /// Keeps the cancel handlers of the messages we sent.
     cancel_handlers: HashMap<Round, Vec<CancelHandler>>,
+    /// Our votes waiting to be sent in a single message along with our next votes on the headers of
+    /// the same author (keyed by author).
+    pending_votes: HashMap<PublicKey, Vec<Vote>>,
+    /// The time by which we send the pending votes (if any).
+    votes_deadline: Option<Instant>,
+    /// The write-ahead log of our votes (if any).
+    vote_log: Option<VoteLog>,
+    /// Our votes waiting to be logged before we send them.
+    logging_votes: FuturesUnordered<BoxFuture<'static, Vote>>,
+    /// Whether we only archive the dag (we then never vote).
+    archival: bool,
+    /// The delay after which we raise an alert if our header did not gather a quorum (if any).
+    quorum_alert_delay: Option<u64>,
+    /// The maximum number of batches' digests a header may reference (if any).
+    max_header_payload: Option<usize>,
+    /// Whether we already raised an alert for our current header.
+    alerted: bool,
 }
 
 impl Core {
@@ -77,15 +142,41 @@ impl Core {
         synchronizer: Synchronizer,
         signature_service: SignatureService,
         consensus_round: Arc<AtomicU64>,
-        gc_depth: Round,
+        gc_depth: Arc<AtomicU64>,
+        header_round: Arc<AtomicU64>,
+        aggregate_votes: bool,
+        payload_availability: PayloadAvailability,
+        vote_batching: VoteBatching,
+        timestamp_bounds: TimestampBounds,
+        vote_log: Option<VoteLog>,
+        archival: bool,
+        quorum_alert_delay: Option<u64>,
+        max_header_payload: Option<usize>,
         rx_primaries: Receiver<PrimaryMessage>,
-        rx_header_waiter: Receiver<Header>,
+        rx_header_waiter: Receiver<Loopback>,
         rx_certificate_waiter: Receiver<Certificate>,
         rx_proposer: Receiver<Header>,
         tx_consensus: Sender<Certificate>,
         tx_proposer: Sender<(Vec<Digest>, Round)>,
+        tx_equivocations: Sender<Equivocation>,
+        tx_gossip: Option<Sender<Certificate>>,
+        tx_progress: Option<Sender<HeaderProgress>>,
+        retry_policy: RetryPolicy,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        compression: Compression,
+        metrics: PrimaryMetrics,
+        context: NetworkContext,
     ) {
+        let capacity = 2 * gc_depth.load(Ordering::Relaxed) as usize;
         tokio::spawn(async move {
+            let network = ReliableSender::new()
+                .with_context(context)
+                .with_retry_policy(retry_policy)
+                .with_queue(queue)
+                .with_max_frame_size(max_frame_size)
+                .with_compression(compression)
+                .with_metrics(metrics.network.clone());
             Self {
                 name,
                 committee,
@@ -94,20 +185,42 @@ impl Core {
                 signature_service,
                 consensus_round,
                 gc_depth,
+                header_round,
+                aggregate_votes,
+                payload_availability,
+                vote_batching,
+                timestamp_bounds,
                 rx_primaries,
                 rx_header_waiter,
                 rx_certificate_waiter,
                 rx_proposer,
                 tx_consensus,
                 tx_proposer,
+                tx_equivocations,
+                tx_gossip,
+                tx_progress,
+                metrics,
                 gc_round: 0,
-                last_voted: HashMap::with_capacity(2 * gc_depth as usize),
-                processing: HashMap::with_capacity(2 * gc_depth as usize),
+                last_voted: HashMap::with_capacity(capacity),
+                received_headers: HashMap::with_capacity(capacity),
+                highest_votes: HashMap::new(),
+                restored_votes: HashMap::new(),
+                processing: HashMap::with_capacity(capacity),
+                confirmed: HashMap::with_capacity(capacity),
                 current_header: Header::default(),
+                proposed_at: Instant::now(),
                 votes_aggregator: VotesAggregator::new(),
-                certificates_aggregators: HashMap::with_capacity(2 * gc_depth as usize),
-                network: ReliableSender::new(),
-                cancel_handlers: HashMap::with_capacity(2 * gc_depth as usize),
+                certificates_aggregators: HashMap::with_capacity(capacity),
+                network,
+                cancel_handlers: HashMap::with_capacity(capacity),
+                pending_votes: HashMap::new(),
+                votes_deadline: None,
+                vote_log,
+                logging_votes: FuturesUnordered::new(),
+                archival,
+                quorum_alert_delay,
+                max_header_payload,
+                alerted: false,
             }
             .run()
             .await;
@@ -116,8 +229,15 @@ impl Core {
 
     async fn process_own_header(&mut self, header: Header) -> DagResult<()> {
         // Reset the votes aggregator.
+        if self.current_header.round > 0 {
+            let votes = self.votes_aggregator.votes();
+            self.metrics.votes_per_header.observe(votes as f64);
+        }
         self.current_header = header.clone();
+        self.header_round.store(header.round, Ordering::Relaxed);
+        self.proposed_at = Instant::now();
         self.votes_aggregator = VotesAggregator::new();
+        self.alerted = false;
 
         // Broadcast the new header in a reliable manner.
         let addresses = self
@@ -128,7 +248,10 @@ impl Core {
             .collect();
         let bytes = bincode::serialize(&PrimaryMessage::Header(header.clone()))
             .expect("Failed to serialize our own header");
-        let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
+        let handlers = self
+            .network
+            .broadcast_with_priority(addresses, Bytes::from(bytes), Priority::Proposal)
+            .await;
         self.cancel_handlers
             .entry(header.round)
             .or_insert_with(Vec::new)
@@ -138,9 +261,106 @@ impl Core {
         self.process_header(&header).await
     }
 
+    /// Records the header and returns whether it is the first header its author created for this
+    /// round. If it is not, the author equivocated and we output the evidence (once per author and
+    /// round: further conflicting headers would only make us burn signatures and bandwidth).
+    async fn record_header(&mut self, header: &Header) -> bool {
+        let (first, reported) = match self
+            .received_headers
+            .entry(header.round)
+            .or_default()
+            .entry(header.author)
+        {
+            Entry::Vacant(entry) => {
+                entry.insert((header.clone(), false));
+                return true;
+            }
+            Entry::Occupied(entry) => entry.into_mut(),
+        };
+        if first.id == header.id {
+            return true;
+        }
+        if *reported {
+            return false;
+        }
+        *reported = true;
+
+        let first = first.clone();
+        let equivocation = Equivocation::new(
+            first,
+            header.clone(),
+            &self.name,
+            &mut self.signature_service,
+        )
+        .await;
+        warn!("Detected equivocation {:?}", equivocation);
+        self.tx_equivocations
+            .send(equivocation)
+            .await
+            .expect("Failed to output equivocation");
+        false
+    }
+
+    fn last_voted_key(author: &PublicKey) -> Vec<u8> {
+        [LAST_VOTED_PREFIX, author.as_ref()].concat()
+    }
+
+    /// Loads the rounds we voted for before restarting (if any).
+    async fn restore_votes(&mut self) {
+        let authors: Vec<_> = self.committee.authorities.keys().cloned().collect();
+        for author in authors {
+            let bytes = self
+                .store
+                .read(Self::last_voted_key(&author))
+                .await
+                .expect("Failed to read our voting state");
+            if let Some(bytes) = bytes {
+                let round = bytes
+                    .as_slice()
+                    .try_into()
+                    .map(Round::from_le_bytes)
+                    .expect("Corrupted voting state");
+                self.highest_votes.insert(author, round);
+                self.restored_votes.insert(author, round);
+            }
+        }
+
+        // The log of our votes may be ahead of the store.
+        if let Some(vote_log) = &self.vote_log {
+            for (author, round) in vote_log.restored() {
+                for votes in [&mut self.highest_votes, &mut self.restored_votes] {
+                    let entry = votes.entry(*author).or_default();
+                    *entry = max(*entry, *round);
+                }
+            }
+        }
+    }
+
+    /// Persists our vote for the header. This must happen before sending the vote: the returned
+    /// channel (if any) tells when the vote log holds it.
+    async fn persist_vote(&mut self, header: &Header) -> Option<oneshot::Receiver<io::Result<()>>> {
+        let highest = self.highest_votes.entry(header.author).or_default();
+        if header.round <= *highest {
+            return None;
+        }
+        *highest = header.round;
+        let key = Self::last_voted_key(&header.author);
+        self.store
+            .write(key, header.round.to_le_bytes().to_vec())
+            .await;
+        match &mut self.vote_log {
+            Some(vote_log) => Some(vote_log.append(&header.author, header.round).await),
+            None => None,
+        }
+    }
+
     #[async_recursion]
     async fn process_header(&mut self, header: &Header) -> DagResult<()> {
         debug!("Processing {:?}", header);
+        // Ensure the author did not create another header for this round. We still process
+        // conflicting headers (they may be certified by others) but we only vote for the first one.
+        let first_of_round = self.record_header(header).await;
+
         // Indicate that we are processing this header.
         self.processing
             .entry(header.round)
@@ -156,13 +376,18 @@ impl Core {
             return Ok(());
         }
 
-        // Check the parent certificates. Ensure the parents form a quorum and are all from the previous round.
+        // Check the parent certificates. Ensure the parents form a quorum and are all from the previous round
+        // (and from the same epoch).
         let mut stake = 0;
         for x in parents {
             ensure!(
                 x.round() + 1 == header.round,
                 DagError::MalformedHeader(header.id.clone())
             );
+            ensure!(
+                x.epoch() == header.epoch,
+                DagError::InvalidEpoch(x.digest(), x.epoch())
+            );
             stake += self.committee.stake(&x.origin());
         }
         ensure!(
@@ -170,9 +395,11 @@ impl Core {
             DagError::HeaderRequiresQuorum(header.id.clone())
         );
 
-        // Ensure we have the payload. If we don't, the synchronizer will ask our workers to get it, and then
-        // reschedule processing of this header once we have it.
-        if self.synchronizer.missing_payload(header).await? {
+        // Ensure we have the payload (unless we trust the author). If we don't, the synchronizer will ask our
+        // workers to get it, and then reschedule processing of this header once we have it.
+        if self.payload_availability.check != PayloadCheck::Trust
+            && self.synchronizer.missing_payload(header).await?
+        {
             debug!("Processing of {} suspended: missing payload", header);
             return Ok(());
         }
@@ -182,62 +409,163 @@ impl Core {
         self.store.write(header.id.to_vec(), bytes).await;
 
         // Check if we can vote for this header.
-        if self
+        let restored_vote = self.restored_votes.get(&header.author).copied();
+        let voted = self
             .last_voted
-            .entry(header.round)
-            .or_insert_with(HashSet::new)
-            .insert(header.author)
+            .get(&header.round)
+            .is_some_and(|x| x.contains(&header.author));
+        if !self.archival
+            && first_of_round
+            && restored_vote.is_none_or(|round| header.round > round)
+            && !voted
         {
-            // Make a vote and send it to the header's creator.
-            let vote = Vote::new(header, &self.name, &mut self.signature_service).await;
+            // Under the strictest check, our workers must confirm they store the payload. The
+            // `HeaderWaiter` asks them and loops the header back to us once they do.
+            if self.payload_availability.check == PayloadCheck::Confirmed
+                && header.author != self.name
+                && !self
+                    .confirmed
+                    .get(&header.round)
+                    .is_some_and(|x| x.contains(&header.id))
+            {
+                self.synchronizer.confirm_payload(header).await;
+                debug!("Processing of {} suspended: confirming payload", header);
+                return Ok(());
+            }
+            self.last_voted
+                .entry(header.round)
+                .or_default()
+                .insert(header.author);
+
+            // Make a vote and send it to the header's creator (once our vote log holds it).
+            let logged = self.persist_vote(header).await;
+            let mut vote = Vote::new(header, &self.name, &mut self.signature_service).await;
+            if self.aggregate_votes {
+                vote.sign_bls(&mut self.signature_service).await;
+            }
             debug!("Created {:?}", vote);
-            if vote.origin == self.name {
-                self.process_vote(vote)
-                    .await
-                    .expect("Failed to process our own vote");
-            } else {
-                let address = self
-                    .committee
-                    .primary(&header.author)
-                    .expect("Author of valid header is not in the committee")
-                    .primary_to_primary;
-                let bytes = bincode::serialize(&PrimaryMessage::Vote(vote))
-                    .expect("Failed to serialize our own vote");
-                let handler = self.network.send(address, Bytes::from(bytes)).await;
-                self.cancel_handlers
-                    .entry(header.round)
-                    .or_insert_with(Vec::new)
-                    .push(handler);
+            match logged {
+                Some(logged) => self.logging_votes.push(Box::pin(async move {
+                    logged
+                        .await
+                        .expect("Failed to log our vote")
+                        .expect("Failed to log our vote");
+                    vote
+                })),
+                None => self.send_vote(vote).await,
             }
         }
         Ok(())
     }
 
+    /// Sends our vote to the header's creator.
+    async fn send_vote(&mut self, vote: Vote) {
+        if vote.origin == self.name {
+            self.process_vote(vote)
+                .await
+                .expect("Failed to process our own vote");
+        } else if self.vote_batching.max_votes > 1 {
+            self.batch_vote(vote).await;
+        } else {
+            let address = self
+                .committee
+                .primary(&vote.origin)
+                .expect("Author of valid header is not in the committee")
+                .primary_to_primary;
+            let round = vote.round;
+            let bytes = bincode::serialize(&PrimaryMessage::Vote(vote))
+                .expect("Failed to serialize our own vote");
+            let handler = self
+                .network
+                .send_with_priority(address, Bytes::from(bytes), Priority::Control)
+                .await;
+            self.cancel_handlers
+                .entry(round)
+                .or_insert_with(Vec::new)
+                .push(handler);
+        }
+    }
+
+    /// Buffers a vote until we can send it along with our next votes on the headers of the same
+    /// author (we vote for at most one header per author and round), or until it waited long enough.
+    async fn batch_vote(&mut self, vote: Vote) {
+        let author = vote.origin;
+        let votes = self.pending_votes.entry(author).or_default();
+        votes.push(vote);
+        if votes.len() >= self.vote_batching.max_votes {
+            self.flush_votes(&author).await;
+        } else if self.votes_deadline.is_none() {
+            let delay = Duration::from_millis(self.vote_batching.max_delay);
+            self.votes_deadline = Some(Instant::now() + delay);
+        }
+    }
+
+    /// Sends the buffered votes on the headers of the specified author in a single message.
+    async fn flush_votes(&mut self, author: &PublicKey) {
+        let votes = match self.pending_votes.remove(author) {
+            Some(votes) => votes,
+            None => return,
+        };
+        let address = match self.committee.primary(author) {
+            Ok(x) => x.primary_to_primary,
+            Err(_) => return,
+        };
+        debug!("Sending {} votes to {}", votes.len(), author);
+        let round = votes.iter().map(|x| x.round).max().unwrap_or_default();
+        let bytes =
+            bincode::serialize(&PrimaryMessage::Votes(votes)).expect("Failed to serialize votes");
+        let handler = self
+            .network
+            .send_with_priority(address, Bytes::from(bytes), Priority::Control)
+            .await;
+        self.cancel_handlers
+            .entry(round)
+            .or_insert_with(Vec::new)
+            .push(handler);
+    }
+
+    /// Sends all the buffered votes, once the oldest one waited long enough.
+    async fn flush_all_votes(&mut self) {
+        self.votes_deadline = None;
+        let authors: Vec<_> = self.pending_votes.keys().cloned().collect();
+        for author in authors {
+            self.flush_votes(&author).await;
+        }
+    }
+
     #[async_recursion]
     async fn process_vote(&mut self, vote: Vote) -> DagResult<()> {
         debug!("Processing {:?}", vote);
 
         // Add it to the votes' aggregator and try to make a new certificate.
-        if let Some(certificate) =
+        let certificate =
             self.votes_aggregator
-                .append(vote, &self.committee, &self.current_header)?
-        {
+                .append(vote, &self.committee, &self.current_header)?;
+        self.report_progress();
+        if let Some(certificate) = certificate {
             debug!("Assembled {:?}", certificate);
+            let latency = self.proposed_at.elapsed().as_secs_f64();
+            self.metrics.certificate_latency.observe(latency);
 
-            // Broadcast the certificate.
-            let addresses = self
-                .committee
-                .others_primaries(&self.name)
-                .iter()
-                .map(|(_, x)| x.primary_to_primary)
-                .collect();
-            let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate.clone()))
-                .expect("Failed to serialize our own certificate");
-            let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
-            self.cancel_handlers
-                .entry(certificate.round())
-                .or_insert_with(Vec::new)
-                .extend(handlers);
+            // Broadcast the certificate (unless the gossip diffuses it).
+            if self.tx_gossip.is_none() {
+                let addresses = self
+                    .committee
+                    .others_primaries(&self.name)
+                    .iter()
+                    .map(|(_, x)| x.primary_to_primary)
+                    .collect();
+                let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate.clone()))
+                    .expect("Failed to serialize our own certificate");
+                let handlers = self
+                    .network
+                    .broadcast_with_priority(addresses, Bytes::from(bytes), Priority::Control)
+                    .await;
+                self.cancel_handlers
+                    .entry(certificate.round())
+                    .or_insert_with(Vec::new)
+                    .extend(handlers);
+            }
 
             // Process the new certificate.
             self.process_certificate(certificate)
@@ -266,7 +594,11 @@ impl Core {
 
         // Ensure we have all the ancestors of this certificate yet. If we don't, the synchronizer will gather
         // them and trigger re-processing of this certificate.
-        if !self.synchronizer.deliver_certificate(&certificate).await? {
+        if !self
+            .synchronizer
+            .deliver_certificate(&certificate, self.gc_round)
+            .await?
+        {
             debug!(
                 "Processing of {:?} suspended: missing ancestors",
                 certificate
@@ -274,9 +606,19 @@ impl Core {
             return Ok(());
         }
 
-        // Store the certificate.
+        // Store the certificate and index it by round.
         let bytes = bincode::serialize(&certificate).expect("Failed to serialize certificate");
         self.store.write(certificate.digest().to_vec(), bytes).await;
+        let key = Certificate::round_key(certificate.round(), &certificate.origin());
+        self.store.write(key, certificate.digest().to_vec()).await;
+
+        // Let the gossip diffuse the certificate to our peers.
+        if let Some(tx_gossip) = &self.tx_gossip {
+            tx_gossip
+                .send(certificate.clone())
+                .await
+                .expect("Failed to send certificate to the gossip");
+        }
 
         // Check if we have enough certificates to enter a new dag round and propose a header.
         if let Some(parents) = self
@@ -303,20 +645,72 @@ impl Core {
         Ok(())
     }
 
+    /// Reports the progress of the certificate formation of our current header. Progress reports
+    /// are dropped (rather than holding back the core) if nobody consumes them.
+    fn report_progress(&self) {
+        if let Some(tx_progress) = &self.tx_progress {
+            let progress = self.votes_aggregator.progress(
+                &self.committee,
+                &self.current_header,
+                self.proposed_at.elapsed(),
+            );
+            let _ = tx_progress.try_send(progress);
+        }
+    }
+
+    /// Raises an alert if our current header did not gather a quorum of votes in time.
+    fn check_quorum_alert(&mut self) {
+        let delay = match self.quorum_alert_delay {
+            Some(delay) => Duration::from_millis(delay),
+            None => return,
+        };
+        if self.current_header.round == 0
+            || self.alerted
+            || self.votes_aggregator.certified()
+            || self.proposed_at.elapsed() < delay
+        {
+            return;
+        }
+        self.alerted = true;
+        self.metrics.headers_without_quorum.inc();
+        let progress = self.votes_aggregator.progress(
+            &self.committee,
+            &self.current_header,
+            self.proposed_at.elapsed(),
+        );
+        warn!(
+            "Header {} did not reach a quorum within {} ms: {} stake collected, no vote from {:?}",
+            progress.header,
+            delay.as_millis(),
+            progress.stake,
+            progress.missing
+        );
+    }
+
+    /// Checks a header of another primary. Its signature is already verified by the `Verifier`.
     fn sanitize_header(&mut self, header: &Header) -> DagResult<()> {
         ensure!(
             self.gc_round <= header.round,
             DagError::TooOld(header.id.clone(), header.round)
         );
 
-        // Verify the header's signature.
-        header.verify(&self.committee)?;
+        // Ensure the header was created around the current time.
+        header.check_timestamp(now(), &self.timestamp_bounds)?;
+
+        // Ensure the payload is not larger than the one we would propose.
+        if let Some(max) = self.max_header_payload {
+            ensure!(
+                header.payload.len() <= max,
+                DagError::PayloadTooLarge(header.id.clone(), header.payload.len())
+            );
+        }
 
         // TODO [issue #3]: Prevent bad nodes from sending junk headers with high round numbers.
 
         Ok(())
     }
 
+    /// Checks a vote of another primary. Its signature is already verified by the `Verifier`.
     fn sanitize_vote(&mut self, vote: &Vote) -> DagResult<()> {
         ensure!(
             self.current_header.round <= vote.round,
@@ -330,54 +724,109 @@ impl Core {
                 && vote.round == self.current_header.round,
             DagError::UnexpectedVote(vote.id.clone())
         );
-
-        // Verify the vote.
-        vote.verify(&self.committee).map_err(DagError::from)
+        Ok(())
     }
 
+    /// Checks a certificate of another primary. Its signatures are already verified by the `Verifier`.
     fn sanitize_certificate(&mut self, certificate: &Certificate) -> DagResult<()> {
         ensure!(
             self.gc_round <= certificate.round(),
             DagError::TooOld(certificate.digest(), certificate.round())
         );
+        Ok(())
+    }
+
+    /// Handles a message from another primary.
+    async fn handle_message(&mut self, message: PrimaryMessage) -> DagResult<()> {
+        match message {
+            PrimaryMessage::Header(header) => {
+                self.sanitize_header(&header)?;
+                self.process_header(&header).await
+            }
+            PrimaryMessage::Vote(vote) => {
+                self.sanitize_vote(&vote)?;
+                self.process_vote(vote).await
+            }
+            PrimaryMessage::Votes(votes) => {
+                // Only the votes on our current header concern us.
+                let id = self.current_header.id.clone();
+                for vote in votes.into_iter().filter(|x| x.id == id) {
+                    let result = match self.sanitize_vote(&vote) {
+                        Ok(()) => self.process_vote(vote).await,
+                        error => error,
+                    };
+                    Self::report(result);
+                }
+                Ok(())
+            }
+            PrimaryMessage::Certificate(certificate) => {
+                self.sanitize_certificate(&certificate)?;
+                self.process_certificate(certificate).await
+            }
+            _ => panic!("Unexpected core message"),
+        }
+    }
 
-        // Verify the certificate (and the embedded header).
-        certificate.verify(&self.committee).map_err(DagError::from)
+    /// Logs the outcome of handling a message.
+    fn report(result: DagResult<()>) {
+        match result {
+            Ok(()) => (),
+            Err(DagError::StoreError(e)) => {
+                error!("{}", e);
+                panic!("Storage failure: killing node.");
+            }
+            Err(e @ DagError::TooOld(..)) => debug!("{}", e),
+            Err(e @ DagError::VerificationBudgetExceeded(..)) => debug!("{}", e),
+            Err(e @ DagError::TooManySyncObligations(..)) => debug!("{}", e),
+            Err(e) => warn!("{}", e),
+        }
     }
 
     // Main loop listening to incoming messages.
     pub async fn run(&mut self) {
+        self.restore_votes().await;
+
+        // Sends our buffered votes (only armed when batching votes).
+        let votes_timer = sleep(Duration::default());
+        tokio::pin!(votes_timer);
+
+        // Raises the quorum alert of our current header (only armed when alerts are enabled).
+        let alert_delay = self.quorum_alert_delay.map(Duration::from_millis);
+        let alert_timer = sleep(Duration::default());
+        tokio::pin!(alert_timer);
+        let mut alert_armed = false;
+
         loop {
+            if let Some(deadline) = self.votes_deadline {
+                if votes_timer.deadline() != deadline {
+                    votes_timer.as_mut().reset(deadline);
+                }
+            }
+            if let Some(delay) = alert_delay {
+                let deadline = self.proposed_at + delay;
+                if alert_timer.deadline() != deadline {
+                    alert_timer.as_mut().reset(deadline);
+                    alert_armed = true;
+                }
+            }
+
             let result = tokio::select! {
                 // We receive here messages from other primaries.
-                Some(message) = self.rx_primaries.recv() => {
-                    match message {
-                        PrimaryMessage::Header(header) => {
-                            match self.sanitize_header(&header) {
-                                Ok(()) => self.process_header(&header).await,
-                                error => error
-                            }
-
-                        },
-                        PrimaryMessage::Vote(vote) => {
-                            match self.sanitize_vote(&vote) {
-                                Ok(()) => self.process_vote(vote).await,
-                                error => error
-                            }
-                        },
-                        PrimaryMessage::Certificate(certificate) => {
-                            match self.sanitize_certificate(&certificate) {
-                                Ok(()) =>  self.process_certificate(certificate).await,
-                                error => error
-                            }
-                        },
-                        _ => panic!("Unexpected core message")
-                    }
-                },
+                Some(message) = self.rx_primaries.recv() => self.handle_message(message).await,
 
                 // We receive here loopback headers from the `HeaderWaiter`. Those are headers for which we interrupted
-                // execution (we were missing some of their dependencies) and we are now ready to resume processing.
-                Some(header) = self.rx_header_waiter.recv() => self.process_header(&header).await,
+                // execution (we were missing some of their dependencies, or waited for our workers to confirm their
+                // payload) and we are now ready to resume processing.
+                Some(loopback) = self.rx_header_waiter.recv() => match loopback {
+                    Loopback::Ready(header) => self.process_header(&header).await,
+                    Loopback::Confirmed(header) => {
+                        self.confirmed
+                            .entry(header.round)
+                            .or_default()
+                            .insert(header.id.clone());
+                        self.process_header(&header).await
+                    }
+                },
 
                 // We receive here loopback certificates from the `CertificateWaiter`. Those are certificates for which
                 // we interrupted execution (we were missing some of their ancestors) and we are now ready to resume
@@ -386,23 +835,38 @@ impl Core {
 
                 // We also receive here our new headers created by the `Proposer`.
                 Some(header) = self.rx_proposer.recv() => self.process_own_header(header).await,
-            };
-            match result {
-                Ok(()) => (),
-                Err(DagError::StoreError(e)) => {
-                    error!("{}", e);
-                    panic!("Storage failure: killing node.");
+
+                // Send our votes once our vote log holds them.
+                Some(vote) = self.logging_votes.next() => {
+                    self.send_vote(vote).await;
+                    Ok(())
+                },
+
+                // Send the votes that waited long enough for others to be bundled with.
+                () = &mut votes_timer, if self.votes_deadline.is_some() => {
+                    self.flush_all_votes().await;
+                    Ok(())
+                },
+
+                // Check whether our current header gathered a quorum in time.
+                () = &mut alert_timer, if alert_armed => {
+                    alert_armed = false;
+                    self.check_quorum_alert();
+                    Ok(())
                 }
-                Err(e @ DagError::TooOld(..)) => debug!("{}", e),
-                Err(e) => warn!("{}", e),
-            }
+            };
+            Self::report(result);
 
             // Cleanup internal state.
             let round = self.consensus_round.load(Ordering::Relaxed);
-            if round > self.gc_depth {
-                let gc_round = round - self.gc_depth;
+            let gc_depth = self.gc_depth.load(Ordering::Relaxed);
+            if round > gc_depth {
+                // The gc round never moves backwards, even if the depth of the garbage collector increases.
+                let gc_round = max(round - gc_depth, self.gc_round);
                 self.last_voted.retain(|k, _| k >= &gc_round);
+                self.received_headers.retain(|k, _| k >= &gc_round);
                 self.processing.retain(|k, _| k >= &gc_round);
+                self.confirmed.retain(|k, _| k >= &gc_round);
                 self.certificates_aggregators.retain(|k, _| k >= &gc_round);
                 self.cancel_handlers.retain(|k, _| k >= &gc_round);
                 self.gc_round = gc_round;
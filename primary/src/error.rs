@@ -1,5 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::budget::MessageClass;
 use crate::primary::Round;
+use config::{Epoch, WorkerId};
 use crypto::{CryptoError, Digest, PublicKey};
 use store::StoreError;
 use thiserror::Error;
@@ -48,6 +50,15 @@ pub enum DagError {
     #[error("Received unexpected vote fo header {0}")]
     UnexpectedVote(Digest),
 
+    #[error("Malformed aggregated votes in certificate {0}")]
+    MalformedAggregate(Digest),
+
+    #[error("Authority {0} has no BLS key")]
+    MissingBlsKey(PublicKey),
+
+    #[error("Invalid equivocation evidence against {0}")]
+    InvalidEquivocation(PublicKey),
+
     #[error("Received certificate without a quorum")]
     CertificateRequiresQuorum,
 
@@ -56,4 +67,46 @@ pub enum DagError {
 
     #[error("Message {0} (round {1}) too old")]
     TooOld(Digest, Round),
+
+    #[error("Verification budget for {0} exceeded: dropping message {1}")]
+    VerificationBudgetExceeded(MessageClass, Digest),
+
+    #[error("Too many pending sync requests: dropping message {0}")]
+    TooManySyncObligations(Digest),
+
+    #[error("Our workers did not confirm the payload of header {0}")]
+    PayloadUnavailable(Digest),
+
+    #[error("Message {0} is from epoch {1}, not ours")]
+    InvalidEpoch(Digest, Epoch),
+
+    #[error("Header {0} has a timestamp ({1}) too far from our local time")]
+    InvalidTimestamp(Digest, u64),
+
+    #[error("Header {0} references {1} batches, more than allowed")]
+    PayloadTooLarge(Digest, usize),
+
+    #[error("Signed message claims no origin")]
+    UnexpectedSignedMessage,
+
+    #[error("Signed message has a nonce ({0}) too far from our local time")]
+    StaleSignedMessage(u64),
+}
+
+#[derive(Debug, Error)]
+pub enum GcDepthError {
+    #[error("The depth of the garbage collector must be positive")]
+    InvalidDepth,
+
+    #[error("Activation round {0} is not after the last committed round {1}")]
+    StaleActivation(Round, Round),
+}
+
+#[derive(Debug, Error)]
+pub enum WorkerCacheError {
+    #[error("Authority {0} is not in the committee")]
+    UnknownAuthority(PublicKey),
+
+    #[error("Authority {0} has no worker {1}")]
+    UnknownWorker(PublicKey, WorkerId),
 }
@@ -1,71 +1,196 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::GcDepthError;
 use crate::messages::Certificate;
-use crate::primary::PrimaryWorkerMessage;
+use crate::primary::{PrimaryWorkerMessage, Round};
+use crate::worker_cache::WorkerCache;
 use bytes::Bytes;
-use config::Committee;
-use crypto::PublicKey;
-use network::SimpleSender;
-use std::net::SocketAddr;
+use config::WorkerId;
+use crypto::{Digest, PublicKey};
+use log::{info, warn};
+use network::{NetworkContext, NetworkMetrics, QueueLimits, SimpleSender};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+#[path = "tests/garbage_collector_tests.rs"]
+pub mod garbage_collector_tests;
+
+/// A request to change the depth of the garbage collector at runtime. The new depth only takes effect
+/// once the consensus commits a round greater or equal to `activation_round`. Since the depth influences
+/// the commit sequence, all authorities must switch at the same activation round: the consensus switches
+/// right after committing the first leader of that round or higher (whatever the leaders it commits
+/// along with it), while the other tasks of the primary only prune their memory and switch with the
+/// next committed round they observe.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GcDepthUpdate {
+    /// The new depth of the garbage collector (denominated in number of rounds).
+    pub depth: Round,
+    /// The committed round from which the new depth applies.
+    pub activation_round: Round,
+}
+
+impl GcDepthUpdate {
+    /// Ensure the update is well formed and can still be applied at the specified activation round.
+    pub fn validate(&self, last_committed_round: Round) -> Result<(), GcDepthError> {
+        if self.depth == 0 {
+            return Err(GcDepthError::InvalidDepth);
+        }
+        if self.activation_round <= last_committed_round {
+            return Err(GcDepthError::StaleActivation(
+                self.activation_round,
+                last_committed_round,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A `GcDepthUpdate` along with the channel where to report whether it was accepted.
+pub type GcDepthCommand = (GcDepthUpdate, oneshot::Sender<Result<(), GcDepthError>>);
 
 /// Receives the highest round reached by consensus and update it for all tasks.
+///
+/// Garbage collection only drops the in-memory state of the tasks: certificates and headers are
+/// never deleted from the store, which thus holds the full history of the dag. Archival primaries
+/// do not garbage collect at all, so that they keep syncing the oldest parts of the dag.
 pub struct GarbageCollector {
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
+    /// The current depth of the garbage collector (shared with the other tasks).
+    gc_depth: Arc<AtomicU64>,
     /// Receives the ordered certificates from consensus.
     rx_consensus: Receiver<Certificate>,
-    /// The network addresses of our workers.
-    addresses: Vec<SocketAddr>,
+    /// Receives requests to change the depth of the garbage collector.
+    rx_gc_depth: Receiver<GcDepthCommand>,
+    /// Notifies the `Proposer` of the author and round of each committed certificate.
+    tx_proposer: Sender<(PublicKey, Round)>,
+    /// The name of this authority.
+    name: PublicKey,
+    /// The addresses of the workers.
+    worker_cache: WorkerCache,
+    /// Whether we only archive the dag (garbage collection is then disabled).
+    archival: bool,
     /// A network sender to notify our workers of cleanup events.
     network: SimpleSender,
 }
 
 impl GarbageCollector {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        name: &PublicKey,
-        committee: &Committee,
+        name: PublicKey,
+        worker_cache: WorkerCache,
+        archival: bool,
         consensus_round: Arc<AtomicU64>,
+        gc_depth: Arc<AtomicU64>,
         rx_consensus: Receiver<Certificate>,
+        rx_gc_depth: Receiver<GcDepthCommand>,
+        tx_proposer: Sender<(PublicKey, Round)>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
     ) {
-        let addresses = committee
-            .our_workers(name)
-            .expect("Our public key or worker id is not in the committee")
-            .iter()
-            .map(|x| x.primary_to_worker)
-            .collect();
-
         tokio::spawn(async move {
             Self {
                 consensus_round,
+                gc_depth,
                 rx_consensus,
-                addresses,
-                network: SimpleSender::new(),
+                rx_gc_depth,
+                tx_proposer,
+                name,
+                worker_cache,
+                archival,
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics),
             }
             .run()
             .await;
         });
     }
 
+    /// Sends the digests of the batches of a committed certificate to the workers storing them.
+    async fn notify_workers(&mut self, certificate: &Certificate) {
+        let mut digests: BTreeMap<WorkerId, Vec<Digest>> = BTreeMap::new();
+        for (digest, worker_id) in &certificate.header.payload {
+            digests.entry(*worker_id).or_default().push(digest.clone());
+        }
+        for (worker_id, digests) in digests {
+            let address = match self.worker_cache.worker(&self.name, &worker_id) {
+                Ok(x) => x.primary_to_worker,
+                Err(e) => {
+                    warn!("Failed to notify worker of committed batches: {}", e);
+                    continue;
+                }
+            };
+            let message = PrimaryWorkerMessage::Committed(digests, certificate.round());
+            let bytes = bincode::serialize(&message).expect("Failed to serialize our own message");
+            self.network.send(address, Bytes::from(bytes)).await;
+        }
+    }
+
     async fn run(&mut self) {
         let mut last_committed_round = 0;
-        while let Some(certificate) = self.rx_consensus.recv().await {
-            // TODO [issue #9]: Re-include batch digests that have not been sequenced into our next block.
+        let mut pending_update: Option<GcDepthUpdate> = None;
+        loop {
+            tokio::select! {
+                Some(certificate) = self.rx_consensus.recv() => {
+                    // Let the `Proposer` re-include the batches' digests of our headers that will never
+                    // be committed.
+                    let round = certificate.round();
+                    self.tx_proposer
+                        .send((certificate.origin(), round))
+                        .await
+                        .expect("Failed to notify the proposer of a commit");
+
+                    // Tell our workers which batches got committed, so that they decrypt their
+                    // encrypted transactions and eventually delete them.
+                    self.notify_workers(&certificate).await;
+
+                    if round > last_committed_round && !self.archival {
+                        last_committed_round = round;
 
-            let round = certificate.round();
-            if round > last_committed_round {
-                last_committed_round = round;
+                        // Apply the pending change of depth (if any) once we reach its activation round.
+                        if let Some(update) = pending_update.take() {
+                            if round >= update.activation_round {
+                                info!("Garbage collection depth set to {} rounds", update.depth);
+                                self.gc_depth.store(update.depth, Ordering::Relaxed);
+                            } else {
+                                pending_update = Some(update);
+                            }
+                        }
 
-                // Trigger cleanup on the primary.
-                self.consensus_round.store(round, Ordering::Relaxed);
+                        // Trigger cleanup on the primary.
+                        self.consensus_round.store(round, Ordering::Relaxed);
 
-                // Trigger cleanup on the workers..
-                let bytes = bincode::serialize(&PrimaryWorkerMessage::Cleanup(round))
-                    .expect("Failed to serialize our own message");
-                self.network
-                    .broadcast(self.addresses.clone(), Bytes::from(bytes))
-                    .await;
+                        // Trigger cleanup on the workers..
+                        let addresses = self
+                            .worker_cache
+                            .our_workers(&self.name)
+                            .iter()
+                            .map(|x| x.primary_to_worker)
+                            .collect();
+                        let bytes = bincode::serialize(&PrimaryWorkerMessage::Cleanup(round))
+                            .expect("Failed to serialize our own message");
+                        self.network
+                            .broadcast(addresses, Bytes::from(bytes))
+                            .await;
+                    }
+                },
+                Some((update, reply)) = self.rx_gc_depth.recv() => {
+                    let result = update.validate(last_committed_round);
+                    if result.is_ok() {
+                        pending_update = Some(update);
+                    }
+                    let _ = reply.send(result);
+                },
+                else => break,
             }
         }
     }
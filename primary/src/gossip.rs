@@ -0,0 +1,230 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::authenticator::Authenticator;
+use crate::mesh::Mesh;
+use crate::messages::Certificate;
+use crate::primary::{PrimaryMessage, Round};
+use bytes::Bytes;
+use config::{CertificateDiffusion, Committee, DiffusionMode};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use log::{debug, error};
+use network::{NetworkContext, NetworkMetrics, QueueLimits, SimpleSender};
+use rand::seq::SliceRandom as _;
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use store::Store;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/gossip_tests.rs"]
+pub mod gossip_tests;
+
+/// Diffuses the certificates through an epidemic gossip rather than an all-to-all broadcast: each
+/// new certificate is pushed to a few random peers, and we periodically advertise the digests of
+/// our recent certificates to a random peer so that it pulls (from the `Helper`) those it misses.
+/// In large committees, the peers may instead form a deterministic `Mesh`: each certificate is then
+/// relayed along the mesh up to `rounds` hops from its origin, and we only advertise our recent
+/// certificates to our mesh neighbours.
+pub struct Gossip {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// The number of random peers to which we push each new certificate.
+    fanout: usize,
+    /// The delay between two advertisements of our recent certificates.
+    pull_interval: u64,
+    /// The number of (most recent) rounds whose certificates we advertise.
+    pull_depth: u64,
+    /// The number of hops (from their origin) over which certificates are relayed along the mesh.
+    rounds: usize,
+
+    /// Receives the certificates processed by the `Core`.
+    rx_core: Receiver<Certificate>,
+    /// Receives the digests advertised by our peers.
+    rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+
+    /// The addresses of the peers we advertise our recent certificates to: all other primaries,
+    /// or our neighbours in the mesh.
+    peers: Vec<SocketAddr>,
+    /// The overlay along which we relay certificates, if any (otherwise we push to random peers).
+    mesh: Option<Mesh>,
+    /// The certificates of the most recent rounds we diffused (indexed by round).
+    recent: BTreeMap<Round, Vec<Digest>>,
+    /// The digests of the certificates held in `recent`.
+    seen: HashSet<Digest>,
+    /// The highest round of the certificates we diffused.
+    highest_round: Round,
+    /// A network sender to push certificates and advertisements.
+    network: SimpleSender,
+    /// Signs the messages claiming to come from us (if we authenticate messages).
+    authenticator: Authenticator,
+}
+
+impl Gossip {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        store: Store,
+        diffusion: CertificateDiffusion,
+        rx_core: Receiver<Certificate>,
+        rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+        authenticator: Authenticator,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
+    ) {
+        let mesh = (diffusion.mode == DiffusionMode::Mesh)
+            .then(|| Mesh::new(&committee, &name, diffusion.fanout));
+        let peers = match &mesh {
+            Some(mesh) => mesh
+                .neighbours()
+                .iter()
+                .filter_map(|x| committee.primary(x).ok())
+                .map(|x| x.primary_to_primary)
+                .collect(),
+            None => committee
+                .others_primaries(&name)
+                .into_iter()
+                .map(|(_, x)| x.primary_to_primary)
+                .collect(),
+        };
+
+        tokio::spawn(async move {
+            Self {
+                name,
+                committee,
+                store,
+                fanout: diffusion.fanout,
+                pull_interval: diffusion.pull_interval,
+                pull_depth: diffusion.pull_depth,
+                rounds: diffusion.rounds,
+                rx_core,
+                rx_primaries,
+                peers,
+                mesh,
+                recent: BTreeMap::new(),
+                seen: HashSet::new(),
+                highest_round: 0,
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics),
+                authenticator,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Pushes a certificate we did not diffuse yet to a few random peers, or relays it along the mesh.
+    async fn push(&mut self, certificate: Certificate) {
+        let round = certificate.round();
+        if round + self.pull_depth <= self.highest_round || !self.seen.insert(certificate.digest())
+        {
+            return;
+        }
+        self.recent
+            .entry(round)
+            .or_default()
+            .push(certificate.digest());
+
+        // Only keep the most recent rounds.
+        if round > self.highest_round {
+            self.highest_round = round;
+            let oldest = (round + 1).saturating_sub(self.pull_depth);
+            let recent = self.recent.split_off(&oldest);
+            for digest in std::mem::replace(&mut self.recent, recent)
+                .into_values()
+                .flatten()
+            {
+                self.seen.remove(&digest);
+            }
+        }
+
+        let addresses = match &self.mesh {
+            Some(mesh) => mesh
+                .relays(&certificate.origin(), self.rounds)
+                .iter()
+                .filter_map(|x| self.committee.primary(x).ok())
+                .map(|x| x.primary_to_primary)
+                .collect(),
+            None => self
+                .peers
+                .choose_multiple(&mut rand::thread_rng(), self.fanout)
+                .cloned()
+                .collect(),
+        };
+        let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
+            .expect("Failed to serialize certificate");
+        self.network.broadcast(addresses, Bytes::from(bytes)).await;
+    }
+
+    /// Advertises the digests of our recent certificates to a random peer (of the mesh, if any).
+    async fn advertise(&mut self) {
+        if self.recent.is_empty() {
+            return;
+        }
+        let address = match self.peers.choose(&mut rand::thread_rng()) {
+            Some(address) => *address,
+            None => return,
+        };
+        let digests = self.recent.values().flatten().cloned().collect();
+        let message = PrimaryMessage::CertificateDigests(digests, self.name);
+        let bytes = self.authenticator.serialize(&message).await;
+        self.network.send(address, bytes).await;
+    }
+
+    /// Requests the advertised certificates we miss from the peer that advertised them.
+    async fn pull(&mut self, digests: Vec<Digest>, origin: PublicKey) {
+        let mut missing = Vec::new();
+        for digest in digests {
+            if self.seen.contains(&digest) {
+                continue;
+            }
+            match self.store.read(digest.to_vec()).await {
+                Ok(Some(_)) => (),
+                Ok(None) => missing.push(digest),
+                Err(e) => error!("{}", e),
+            }
+        }
+        if missing.is_empty() {
+            return;
+        }
+
+        let address = match self.committee.primary(&origin) {
+            Ok(x) => x.primary_to_primary,
+            Err(e) => {
+                debug!("Unexpected certificates advertisement: {}", e);
+                return;
+            }
+        };
+        debug!("Pulling {} certificates from {}", missing.len(), origin);
+        let message = PrimaryMessage::CertificatesRequest(missing, self.name);
+        let bytes = self.authenticator.serialize(&message).await;
+        self.network.send(address, bytes).await;
+    }
+
+    async fn run(&mut self) {
+        let timer = sleep(Duration::from_millis(self.pull_interval));
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                Some(certificate) = self.rx_core.recv() => self.push(certificate).await,
+                Some((digests, origin)) = self.rx_primaries.recv() => self.pull(digests, origin).await,
+                () = &mut timer => {
+                    self.advertise().await;
+                    let deadline = Instant::now() + Duration::from_millis(self.pull_interval);
+                    timer.as_mut().reset(deadline);
+                }
+            }
+        }
+    }
+}
@@ -1,25 +1,34 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::messages::Header;
-use crate::primary::{PrimaryMessage, PrimaryWorkerMessage, Round};
+use crate::metrics::PrimaryMetrics;
+use crate::primary::{PrimaryWorkerMessage, Round};
+use crate::synchronizer::SyncObligations;
+use crate::worker_cache::WorkerCache;
 use bytes::Bytes;
-use config::{Committee, WorkerId};
+use config::{Committee, SyncRetries, WorkerId};
 use crypto::{Digest, PublicKey};
-use futures::future::try_join_all;
+use futures::future::{join_all, try_join_all};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use log::{debug, error};
-use network::SimpleSender;
-use std::collections::HashMap;
+use log::{debug, error, warn};
+use network::{
+    NetworkContext, PendingResponse, QueueLimits, ReliableSender, RpcClient, SimpleSender,
+};
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
 use store::Store;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::time::{sleep, Duration, Instant};
 
-/// The resolution of the timer that checks whether we received replies to our sync requests, and triggers
-/// new sync requests if we didn't.
+#[cfg(test)]
+#[path = "tests/header_waiter_tests.rs"]
+pub mod header_waiter_tests;
+
+/// The resolution of the timer that checks whether the dependencies of the pending headers arrived, and
+/// re-issues their sync requests if they didn't.
 const TIMER_RESOLUTION: u64 = 1_000;
 
 /// The commands that can be sent to the `Waiter`.
@@ -27,6 +36,38 @@ const TIMER_RESOLUTION: u64 = 1_000;
 pub enum WaiterMessage {
     SyncBatches(HashMap<Digest, WorkerId>, Header),
     SyncParents(Vec<Digest>, Header),
+    /// Asks our workers to confirm they store the payload of the header.
+    ConfirmPayload(Header),
+}
+
+/// The headers looped back to the `Core`.
+#[derive(Debug)]
+pub enum Loopback {
+    /// A header for which we got all parents and batches.
+    Ready(Header),
+    /// A header whose payload our workers confirmed they store.
+    Confirmed(Header),
+}
+
+/// The missing dependencies of a pending header.
+#[derive(Clone)]
+enum Missing {
+    Batches(HashMap<Digest, WorkerId>),
+    Parents(Vec<Digest>),
+}
+
+/// The sync requests issued for a pending header.
+struct SyncAttempts {
+    /// The dependencies to request.
+    missing: Missing,
+    /// The author of the header (the first peer we sync with).
+    author: PublicKey,
+    /// The round of the header.
+    round: Round,
+    /// The number of times we re-issued the sync requests.
+    retries: u32,
+    /// When to re-issue the sync requests.
+    deadline: Instant,
 }
 
 /// Waits for missing parent certificates and batches' digests.
@@ -35,33 +76,47 @@ pub struct HeaderWaiter {
     name: PublicKey,
     /// The committee information.
     committee: Committee,
+    /// The addresses of the workers.
+    worker_cache: WorkerCache,
     /// The persistent storage.
     store: Store,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
-    /// The depth of the garbage collector.
-    gc_depth: Round,
-    /// The delay to wait before re-trying sync requests.
+    /// The delay to wait before re-trying sync requests (doubled after every retry).
     sync_retry_delay: u64,
-    /// Determine with how many nodes to sync when re-trying to send sync-request.
-    sync_retry_nodes: usize,
+    /// Bounds the retries of the sync requests of each header.
+    sync_retries: SyncRetries,
+    /// The depth of the garbage collector (it may change at runtime).
+    gc_depth: Arc<AtomicU64>,
+    /// The missing dependencies we are waiting for (reserved by the `Synchronizer`).
+    obligations: SyncObligations,
 
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<WaiterMessage>,
-    /// Loops back to the core headers for which we got all parents and batches.
-    tx_core: Sender<Header>,
+    /// Loops back to the core headers for which we got all parents and batches, or whose payload
+    /// our workers confirmed.
+    tx_core: Sender<Loopback>,
+    /// Sends the missing parents to the `CertificateFetcher` (along with the peer to ask first and
+    /// the round of the header referencing them).
+    tx_fetcher: Sender<(Vec<Digest>, PublicKey, Round)>,
+    /// The metrics of the primary.
+    metrics: PrimaryMetrics,
 
     /// Network driver allowing to send messages.
     network: SimpleSender,
-    /// Keeps the digests of the all certificates for which we sent a sync request,
-    /// along with a timestamp (`u128`) indicating when we sent the request.
-    parent_requests: HashMap<Digest, (Round, u128)>,
-    /// Keeps the digests of the all tx batches for which we sent a sync request,
-    /// similarly to `header_requests`.
+    /// Sends the confirmation requests to our workers (and gets their replies), giving them the
+    /// confirmation timeout to reply.
+    confirmations: RpcClient<Vec<Digest>, Vec<Digest>>,
+    /// Keeps the digests of the all tx batches for which we sent a sync request.
     batch_requests: HashMap<Digest, Round>,
     /// List of digests (either certificates, headers or tx batch) that are waiting
     /// to be processed. Their processing will resume when we get all their dependencies.
-    pending: HashMap<Digest, (Round, Sender<()>)>,
+    /// It also keeps the number of missing dependencies of each of them.
+    pending: HashMap<Digest, (Round, usize, Sender<()>)>,
+    /// The sync requests of the pending headers, re-issued until their dependencies arrive.
+    attempts: HashMap<Digest, SyncAttempts>,
+    /// The headers whose payload our workers are currently confirming.
+    confirming: HashSet<Digest>,
 }
 
 impl HeaderWaiter {
@@ -69,29 +124,58 @@ impl HeaderWaiter {
     pub fn spawn(
         name: PublicKey,
         committee: Committee,
+        worker_cache: WorkerCache,
         store: Store,
         consensus_round: Arc<AtomicU64>,
-        gc_depth: Round,
+        gc_depth: Arc<AtomicU64>,
         sync_retry_delay: u64,
-        sync_retry_nodes: usize,
+        sync_retries: SyncRetries,
+        obligations: SyncObligations,
+        confirm_timeout: u64,
         rx_synchronizer: Receiver<WaiterMessage>,
-        tx_core: Sender<Header>,
+        tx_core: Sender<Loopback>,
+        tx_fetcher: Sender<(Vec<Digest>, PublicKey, Round)>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: PrimaryMetrics,
+        context: NetworkContext,
     ) {
         tokio::spawn(async move {
             Self {
                 name,
                 committee,
+                worker_cache,
                 store,
                 consensus_round,
-                gc_depth,
                 sync_retry_delay,
-                sync_retry_nodes,
+                sync_retries,
+                gc_depth,
+                obligations,
                 rx_synchronizer,
                 tx_core,
-                network: SimpleSender::new(),
-                parent_requests: HashMap::new(),
+                tx_fetcher,
+                network: SimpleSender::new()
+                    .with_context(context.clone())
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics.network.clone()),
+                confirmations: RpcClient::new(
+                    ReliableSender::new()
+                        .with_context(context)
+                        .with_queue(queue)
+                        .with_max_frame_size(max_frame_size)
+                        .with_metrics(metrics.network.clone()),
+                )
+                .with_timeout(Duration::from_millis(confirm_timeout))
+                .with_wrapper(|request| {
+                    bincode::serialize(&PrimaryWorkerMessage::Confirm(request))
+                        .expect("Failed to serialize payload confirmation request")
+                }),
+                metrics,
                 batch_requests: HashMap::new(),
                 pending: HashMap::new(),
+                attempts: HashMap::new(),
+                confirming: HashSet::new(),
             }
             .run()
             .await;
@@ -117,9 +201,116 @@ impl HeaderWaiter {
         }
     }
 
+    /// Helper function. It waits for our workers to confirm they store the payload of the header
+    /// (each reply lists the batches the worker misses). Returns the header along with whether all
+    /// workers confirmed in time.
+    async fn confirmer(
+        responses: Vec<PendingResponse<Vec<Digest>>>,
+        header: Header,
+    ) -> (Header, bool) {
+        let replies = join_all(responses.into_iter().map(|x| x.response())).await;
+        let confirmed = replies.into_iter().all(|reply| match reply {
+            Ok(missing) => missing.is_empty(),
+            Err(e) => {
+                debug!("{}", e);
+                false
+            }
+        });
+        (header, confirmed)
+    }
+
+    /// Asks our workers to confirm they store the payload of the header. Returns `None` if one of
+    /// the workers holding the payload is unknown.
+    async fn confirm(&mut self, header: &Header) -> Option<Vec<PendingResponse<Vec<Digest>>>> {
+        let mut batches: HashMap<WorkerId, Vec<Digest>> = HashMap::new();
+        for (digest, worker_id) in &header.payload {
+            batches.entry(*worker_id).or_default().push(digest.clone());
+        }
+
+        let mut responses = Vec::new();
+        for (worker_id, digests) in batches {
+            let address = self
+                .worker_cache
+                .worker(&self.name, &worker_id)
+                .ok()?
+                .primary_to_worker;
+            responses.push(self.confirmations.send(address, digests).await);
+        }
+        Some(responses)
+    }
+
+    /// The delay before re-issuing sync requests that were already retried `retries` times.
+    fn backoff(&self, retries: u32) -> Duration {
+        let delay = self.sync_retry_delay.saturating_mul(1 << min(retries, 16));
+        Duration::from_millis(min(delay, self.sync_retries.max_delay))
+    }
+
+    /// The peer to sync with at the specified retry: the author of the header first, then every
+    /// other authority in turn.
+    fn target(&self, author: &PublicKey, retries: u32) -> PublicKey {
+        let mut peers = vec![*author];
+        peers.extend(
+            self.committee
+                .authorities
+                .keys()
+                .filter(|x| *x != author && *x != &self.name),
+        );
+        peers[retries as usize % peers.len()]
+    }
+
+    /// Requests the missing dependencies from the target peer.
+    async fn request(&mut self, missing: Missing, target: PublicKey, round: Round) {
+        match missing {
+            Missing::Batches(missing) => {
+                // Our workers fetch the batches from the workers of the target.
+                let mut requires_sync: HashMap<WorkerId, Vec<Digest>> = HashMap::new();
+                for (digest, worker_id) in missing {
+                    requires_sync.entry(worker_id).or_default().push(digest);
+                }
+                for (worker_id, digests) in requires_sync {
+                    // Our worker may have been removed since we received the header.
+                    let address = match self.worker_cache.worker(&self.name, &worker_id) {
+                        Ok(x) => x.primary_to_worker,
+                        Err(e) => {
+                            warn!("Cannot sync batches: {}", e);
+                            continue;
+                        }
+                    };
+                    let message = PrimaryWorkerMessage::Synchronize(digests, target);
+                    let bytes = bincode::serialize(&message)
+                        .expect("Failed to serialize batch sync request");
+                    self.network.send(address, Bytes::from(bytes)).await;
+                }
+            }
+            Missing::Parents(missing) => {
+                // The `CertificateFetcher` deduplicates the requests and starts by asking the target.
+                self.tx_fetcher
+                    .send((missing, target, round))
+                    .await
+                    .expect("Failed to send missing parents to the fetcher");
+            }
+        }
+    }
+
+    /// Stops waiting for the dependencies of a header.
+    async fn cancel(&mut self, header_id: &Digest) {
+        if let Some((_, obligations, handler)) = self.pending.remove(header_id) {
+            let _ = handler.send(()).await;
+            self.obligations.release(obligations);
+        }
+        if let Some(attempts) = self.attempts.remove(header_id) {
+            if let Missing::Batches(missing) = attempts.missing {
+                for digest in missing.keys() {
+                    self.batch_requests.remove(digest);
+                }
+            }
+        }
+    }
+
     /// Main loop listening to the `Synchronizer` messages.
     async fn run(&mut self) {
         let mut waiting = FuturesUnordered::new();
+        let mut confirmations = FuturesUnordered::new();
 
         let timer = sleep(Duration::from_millis(TIMER_RESOLUTION));
         tokio::pin!(timer);
@@ -136,6 +327,7 @@ impl HeaderWaiter {
 
                             // Ensure we sync only once per header.
                             if self.pending.contains_key(&header_id) {
+                                self.obligations.release(missing.len());
                                 continue;
                             }
 
@@ -149,28 +341,29 @@ impl HeaderWaiter {
                                 })
                                 .collect();
                             let (tx_cancel, rx_cancel) = channel(1);
-                            self.pending.insert(header_id, (round, tx_cancel));
+                            self.pending.insert(header_id.clone(), (round, missing.len(), tx_cancel));
                             let fut = Self::waiter(wait_for, header, rx_cancel);
                             waiting.push(fut);
 
+                            // Keep track of the sync request to retry it if needed.
+                            let attempts = SyncAttempts {
+                                missing: Missing::Batches(missing.clone()),
+                                author,
+                                round,
+                                retries: 0,
+                                deadline: Instant::now() + self.backoff(0),
+                            };
+                            self.attempts.insert(header_id, attempts);
+
                             // Ensure we didn't already send a sync request for these parents.
                             let mut requires_sync = HashMap::new();
                             for (digest, worker_id) in missing.into_iter() {
                                 self.batch_requests.entry(digest.clone()).or_insert_with(|| {
-                                    requires_sync.entry(worker_id).or_insert_with(Vec::new).push(digest);
+                                    requires_sync.insert(digest, worker_id);
                                     round
                                 });
                             }
-                            for (worker_id, digests) in requires_sync {
-                                let address = self.committee
-                                    .worker(&author, &worker_id)
-                                    .expect("Author of valid header is not in the committee")
-                                    .primary_to_worker;
-                                let message = PrimaryWorkerMessage::Synchronize(digests, author);
-                                let bytes = bincode::serialize(&message)
-                                    .expect("Failed to serialize batch sync request");
-                                self.network.send(address, Bytes::from(bytes)).await;
-                            }
+                            self.request(Missing::Batches(requires_sync), author, round).await;
                         }
 
                         WaiterMessage::SyncParents(missing, header) => {
@@ -181,6 +374,7 @@ impl HeaderWaiter {
 
                             // Ensure we sync only once per header.
                             if self.pending.contains_key(&header_id) {
+                                self.obligations.release(missing.len());
                                 continue;
                             }
 
@@ -192,47 +386,64 @@ impl HeaderWaiter {
                                 .map(|x| (x.to_vec(), self.store.clone()))
                                 .collect();
                             let (tx_cancel, rx_cancel) = channel(1);
-                            self.pending.insert(header_id, (round, tx_cancel));
+                            self.pending.insert(header_id.clone(), (round, missing.len(), tx_cancel));
                             let fut = Self::waiter(wait_for, header, rx_cancel);
                             waiting.push(fut);
 
-                            // Ensure we didn't already sent a sync request for these parents.
-                            // Optimistically send the sync request to the node that created the certificate.
-                            // If this fails (after a timeout), we broadcast the sync request.
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .expect("Failed to measure time")
-                                .as_millis();
-                            let mut requires_sync = Vec::new();
-                            for missing in missing {
-                                self.parent_requests.entry(missing.clone()).or_insert_with(|| {
-                                    requires_sync.push(missing);
-                                    (round, now)
-                                });
+                            // Keep track of the sync request to retry it if needed.
+                            let attempts = SyncAttempts {
+                                missing: Missing::Parents(missing.clone()),
+                                author,
+                                round,
+                                retries: 0,
+                                deadline: Instant::now() + self.backoff(0),
+                            };
+                            self.attempts.insert(header_id, attempts);
+                            self.request(Missing::Parents(missing), author, round).await;
+                        }
+
+                        WaiterMessage::ConfirmPayload(header) => {
+                            debug!("Confirming the payload of {}", header);
+
+                            // Ensure we ask our workers only once per header.
+                            if !self.confirming.insert(header.id.clone()) {
+                                continue;
                             }
-                            if !requires_sync.is_empty() {
-                                let address = self.committee
-                                    .primary(&author)
-                                    .expect("Author of valid header not in the committee")
-                                    .primary_to_primary;
-                                let message = PrimaryMessage::CertificatesRequest(requires_sync, self.name);
-                                let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
-                                self.network.send(address, Bytes::from(bytes)).await;
+                            match self.confirm(&header).await {
+                                Some(responses) => {
+                                    confirmations.push(Self::confirmer(responses, header));
+                                }
+                                None => {
+                                    self.confirming.remove(&header.id);
+                                    warn!("{}", DagError::PayloadUnavailable(header.id));
+                                }
                             }
                         }
                     }
                 },
 
+                Some((header, confirmed)) = confirmations.next() => {
+                    self.confirming.remove(&header.id);
+                    if confirmed {
+                        self.tx_core
+                            .send(Loopback::Confirmed(header))
+                            .await
+                            .expect("Failed to send header");
+                    } else {
+                        warn!("{}", DagError::PayloadUnavailable(header.id));
+                    }
+                },
+
                 Some(result) = waiting.next() => match result {
                     Ok(Some(header)) => {
-                        let _ = self.pending.remove(&header.id);
+                        if let Some((_, obligations, _)) = self.pending.remove(&header.id) {
+                            self.obligations.release(obligations);
+                        }
+                        self.attempts.remove(&header.id);
                         for x in header.payload.keys() {
                             let _ = self.batch_requests.remove(x);
                         }
-                        for x in &header.parents {
-                            let _ = self.parent_requests.remove(x);
-                        }
-                        self.tx_core.send(header).await.expect("Failed to send header");
+                        self.tx_core.send(Loopback::Ready(header)).await.expect("Failed to send header");
                     },
                     Ok(None) => {
                         // This request has been canceled.
@@ -244,30 +455,40 @@ impl HeaderWaiter {
                 },
 
                 () = &mut timer => {
-                    // We optimistically sent sync requests to a single node. If this timer triggers,
-                    // it means we were wrong to trust it. We are done waiting for a reply and we now
-                    // broadcast the request to all nodes.
-                    let now = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Failed to measure time")
-                        .as_millis();
-
-                    let mut retry = Vec::new();
-                    for (digest, (_, timestamp)) in &self.parent_requests {
-                        if timestamp + (self.sync_retry_delay as u128) < now {
-                            debug!("Requesting sync for certificate {} (retry)", digest);
-                            retry.push(digest.clone());
-                        }
-                    }
-
-                    let addresses = self.committee
-                        .others_primaries(&self.name)
+                    // Re-issue the sync requests that did not get a reply in time to the next peer,
+                    // and give up on the headers whose dependencies seem unavailable.
+                    let now = Instant::now();
+                    let expired: Vec<_> = self.attempts
                         .iter()
-                        .map(|(_, x)| x.primary_to_primary)
+                        .filter(|(_, x)| x.deadline <= now)
+                        .map(|(id, _)| id.clone())
                         .collect();
-                    let message = PrimaryMessage::CertificatesRequest(retry, self.name);
-                    let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
-                    self.network.lucky_broadcast(addresses, Bytes::from(bytes), self.sync_retry_nodes).await;
+                    for header_id in expired {
+                        let attempts = match self.attempts.get(&header_id) {
+                            Some(attempts) => attempts,
+                            None => continue,
+                        };
+                        if attempts.retries >= self.sync_retries.max_retries {
+                            warn!(
+                                "Giving up on header {}: its dependencies are still missing after {} retries",
+                                header_id,
+                                attempts.retries
+                            );
+                            self.cancel(&header_id).await;
+                            continue;
+                        }
+
+                        let retries = attempts.retries + 1;
+                        let (missing, author, round) = (attempts.missing.clone(), attempts.author, attempts.round);
+                        let target = self.target(&author, retries);
+                        let deadline = now + self.backoff(retries);
+                        if let Some(attempts) = self.attempts.get_mut(&header_id) {
+                            attempts.retries = retries;
+                            attempts.deadline = deadline;
+                        }
+                        debug!("Requesting sync for header {} from {} (retry {})", header_id, target, retries);
+                        self.request(missing, target, round).await;
+                    }
 
                     // Reschedule the timer.
                     timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
@@ -276,18 +497,22 @@ impl HeaderWaiter {
 
             // Cleanup internal state.
             let round = self.consensus_round.load(Ordering::Relaxed);
-            if round > self.gc_depth {
-                let mut gc_round = round - self.gc_depth;
+            let gc_depth = self.gc_depth.load(Ordering::Relaxed);
+            if round > gc_depth {
+                let mut gc_round = round - gc_depth;
 
-                for (r, handler) in self.pending.values() {
+                for (r, obligations, handler) in self.pending.values() {
                     if r <= &gc_round {
                         let _ = handler.send(()).await;
+                        self.obligations.release(*obligations);
+                        self.metrics.cancelled_waits.inc();
                     }
                 }
-                self.pending.retain(|_, (r, _)| r > &mut gc_round);
+                self.pending.retain(|_, (r, _, _)| r > &mut gc_round);
+                self.attempts.retain(|_, x| x.round > gc_round);
                 self.batch_requests.retain(|_, r| r > &mut gc_round);
-                self.parent_requests.retain(|_, (r, _)| r > &mut gc_round);
             }
+            self.metrics.pending_headers.set(self.pending.len() as i64);
         }
     }
 }
@@ -1,69 +1,202 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::authenticator::Authenticator;
+use crate::budget::TokenBucket;
 use crate::primary::PrimaryMessage;
 use bytes::Bytes;
-use config::Committee;
+use config::{Committee, HelperLimits};
 use crypto::{Digest, PublicKey};
-use log::{error, warn};
-use network::SimpleSender;
+use log::{debug, error, warn};
+use network::{NetworkContext, NetworkMetrics, Priority, QueueLimits, SimpleSender};
+use std::collections::{HashMap, VecDeque};
 use store::Store;
 use tokio::sync::mpsc::Receiver;
 
+#[cfg(test)]
+#[path = "tests/helper_tests.rs"]
+pub mod helper_tests;
+
+/// Identifies the remaining pages of a certificates request.
+pub type PageToken = u64;
+
+/// The requests served by the `Helper`.
+#[derive(Debug)]
+pub enum HelperRequest {
+    /// A peer requests a set of certificates.
+    Certificates(Vec<Digest>),
+    /// A peer requests the next page of its last certificates request.
+    NextPage(PageToken),
+}
+
+/// The certificates of a request that we did not serve yet.
+struct Cursor {
+    /// The token the peer must present to get the next page.
+    token: PageToken,
+    /// The digests of the certificates left to serve.
+    remaining: VecDeque<Digest>,
+}
+
 /// A task dedicated to help other authorities by replying to their certificates requests.
 pub struct Helper {
+    /// The public key of this authority.
+    name: PublicKey,
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
     store: Store,
+    /// The maximum number of certificates served to each peer per second.
+    rate_limit: u64,
+    /// The maximum number of certificates served per request.
+    page_size: usize,
     /// Input channel to receive certificates requests.
-    rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+    rx_primaries: Receiver<(HelperRequest, PublicKey)>,
     /// A network sender to reply to the sync requests.
     network: SimpleSender,
+    /// Signs the messages claiming to come from us (if we authenticate messages).
+    authenticator: Authenticator,
+    /// Limits the number of certificates we serve to each peer per second.
+    rate_limits: HashMap<PublicKey, TokenBucket>,
+    /// The unserved part of the requests of each peer.
+    cursors: HashMap<PublicKey, Cursor>,
+    /// The token of the last paginated request.
+    next_token: PageToken,
 }
 
 impl Helper {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
+        name: PublicKey,
         committee: Committee,
         store: Store,
-        rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+        limits: HelperLimits,
+        rx_primaries: Receiver<(HelperRequest, PublicKey)>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        authenticator: Authenticator,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
     ) {
+        let rate_limits = committee
+            .authorities
+            .keys()
+            .map(|x| (*x, TokenBucket::new(limits.rate_limit)))
+            .collect();
+
         tokio::spawn(async move {
             Self {
+                name,
                 committee,
                 store,
+                rate_limit: limits.rate_limit,
+                page_size: limits.page_size,
                 rx_primaries,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics),
+                authenticator,
+                rate_limits,
+                cursors: HashMap::new(),
+                next_token: 0,
             }
             .run()
             .await;
         });
     }
 
-    async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_primaries.recv().await {
-            // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
+    /// Serves the next page of the request of the peer, within its quota. If more certificates
+    /// remain, the peer gets a token to request the next page.
+    async fn serve(&mut self, origin: PublicKey) {
+        // get the requestors address.
+        let address = match self.committee.primary(&origin) {
+            Ok(x) => x.primary_to_primary,
+            Err(e) => {
+                warn!("Unexpected certificate request: {}", e);
+                return;
+            }
+        };
 
-            // get the requestors address.
-            let address = match self.committee.primary(&origin) {
-                Ok(x) => x.primary_to_primary,
-                Err(e) => {
-                    warn!("Unexpected certificate request: {}", e);
-                    continue;
-                }
+        // Reply to the request (the best we can).
+        let mut served = 0;
+        while served < self.page_size {
+            let cursor = match self.cursors.get_mut(&origin) {
+                Some(cursor) => cursor,
+                None => break,
+            };
+            let digest = match cursor.remaining.pop_front() {
+                Some(digest) => digest,
+                None => break,
             };
+            if !self
+                .rate_limits
+                .get_mut(&origin)
+                .is_some_and(|x| x.try_acquire())
+            {
+                // The peer exceeded its quota: keep the rest of its request for when it asks again.
+                debug!(
+                    "Rate limit of {} exceeded: suspending its certificates request",
+                    origin
+                );
+                cursor.remaining.push_front(digest);
+                break;
+            }
+            served += 1;
 
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => {
-                        // TODO: Remove this deserialization-serialization in the critical path.
-                        let certificate = bincode::deserialize(&data)
-                            .expect("Failed to deserialize our own certificate");
-                        let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
-                            .expect("Failed to serialize our own certificate");
-                        self.network.send(address, Bytes::from(bytes)).await;
+            match self.store.read(digest.to_vec()).await {
+                Ok(Some(data)) => {
+                    // TODO: Remove this deserialization-serialization in the critical path.
+                    let certificate = bincode::deserialize(&data)
+                        .expect("Failed to deserialize our own certificate");
+                    let bytes = bincode::serialize(&PrimaryMessage::SyncedCertificate(certificate))
+                        .expect("Failed to serialize our own certificate");
+                    self.network
+                        .send_with_priority(address, Bytes::from(bytes), Priority::Sync)
+                        .await;
+                }
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
+            }
+        }
+
+        // Send the token of the next page (if any). If we could not serve anything, the peer resumes
+        // its request by asking again.
+        match self.cursors.get(&origin) {
+            Some(cursor) if !cursor.remaining.is_empty() && served == 0 => (),
+            Some(cursor) if !cursor.remaining.is_empty() => {
+                let message = PrimaryMessage::CertificatesPage(cursor.token, self.name);
+                let bytes = self.authenticator.serialize(&message).await;
+                self.network
+                    .send_with_priority(address, bytes, Priority::Sync)
+                    .await;
+            }
+            _ => {
+                self.cursors.remove(&origin);
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        while let Some((request, origin)) = self.rx_primaries.recv().await {
+            match request {
+                HelperRequest::Certificates(digests) => {
+                    // Queue the request after the unserved part of the previous ones. We hold at
+                    // most one second worth of quota for each peer; it asks again for the others.
+                    let token = &mut self.next_token;
+                    let cursor = self.cursors.entry(origin).or_insert_with(|| {
+                        *token += 1;
+                        Cursor {
+                            token: *token,
+                            remaining: VecDeque::new(),
+                        }
+                    });
+                    let room = (self.rate_limit as usize).saturating_sub(cursor.remaining.len());
+                    cursor.remaining.extend(digests.into_iter().take(room));
+                    self.serve(origin).await;
+                }
+                HelperRequest::NextPage(token) => {
+                    if self.cursors.get(&origin).is_some_and(|x| x.token == token) {
+                        self.serve(origin).await;
                     }
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
                 }
             }
         }
@@ -2,20 +2,38 @@
 #[macro_use]
 mod error;
 mod aggregators;
+mod authenticator;
+mod budget;
+mod certificate_fetcher;
 mod certificate_waiter;
 mod core;
 mod garbage_collector;
+mod gossip;
 mod header_waiter;
 mod helper;
+mod mesh;
 mod messages;
+mod metrics;
 mod payload_receiver;
 mod primary;
 mod proposer;
+mod quotas;
+mod replay_cache;
 mod synchronizer;
+mod verifier;
+mod vote_log;
+mod worker_cache;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::messages::{Certificate, Header};
+pub use crate::aggregators::HeaderProgress;
+pub use crate::budget::TokenBucket;
+pub use crate::error::{DagError, GcDepthError, WorkerCacheError};
+pub use crate::garbage_collector::{GcDepthCommand, GcDepthUpdate};
+pub use crate::messages::{Certificate, Equivocation, Header, SystemMessage, MAX_SYSTEM_MESSAGES};
+pub use crate::metrics::PrimaryMetrics;
 pub use crate::primary::{Primary, PrimaryWorkerMessage, Round, WorkerPrimaryMessage};
+pub use crate::proposer::ProposerCommand;
+pub use crate::worker_cache::{WorkerCache, WorkerUpdate};
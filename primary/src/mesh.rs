@@ -0,0 +1,96 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::Committee;
+use crypto::PublicKey;
+use std::collections::VecDeque;
+
+#[cfg(test)]
+#[path = "tests/mesh_tests.rs"]
+pub mod mesh_tests;
+
+/// A deterministic partial mesh over the committee, so that every primary derives the same overlay
+/// without coordination. The primaries are placed on a ring (in the order of the committee) and
+/// each is linked to the primaries at distance 1, 2, 4, ... on both sides, which keeps the diameter
+/// of the mesh logarithmic in the size of the committee.
+pub struct Mesh {
+    /// The primaries of the committee, in order.
+    names: Vec<PublicKey>,
+    /// Our position on the ring.
+    index: usize,
+    /// The positions of our neighbours relative to ours (the same for every primary).
+    offsets: Vec<usize>,
+    /// The number of hops from any primary to the primary at each relative position.
+    distances: Vec<usize>,
+}
+
+impl Mesh {
+    /// Builds the mesh seen by the specified primary, linking each primary to at least `degree`
+    /// others (or to all others in small committees).
+    pub fn new(committee: &Committee, name: &PublicKey, degree: usize) -> Self {
+        let names: Vec<_> = committee.authorities.keys().cloned().collect();
+        let index = names.iter().position(|x| x == name).unwrap_or_default();
+        let size = names.len();
+
+        let mut offsets = Vec::new();
+        let mut step = 1;
+        while step < size && offsets.len() < degree {
+            for offset in [step, size - step] {
+                if !offsets.contains(&offset) {
+                    offsets.push(offset);
+                }
+            }
+            step *= 2;
+        }
+
+        // The mesh is the same around every primary, so one traversal gives all distances.
+        let mut distances = vec![usize::MAX; size];
+        let mut queue = VecDeque::new();
+        if size > 0 {
+            distances[0] = 0;
+            queue.push_back(0);
+        }
+        while let Some(position) = queue.pop_front() {
+            for offset in &offsets {
+                let next = (position + offset) % size;
+                if distances[next] == usize::MAX {
+                    distances[next] = distances[position] + 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        Self {
+            names,
+            index,
+            offsets,
+            distances,
+        }
+    }
+
+    /// Returns our neighbours in the mesh.
+    pub fn neighbours(&self) -> Vec<PublicKey> {
+        self.offsets
+            .iter()
+            .map(|offset| self.names[(self.index + offset) % self.names.len()])
+            .collect()
+    }
+
+    /// Returns the neighbours to which we relay the certificates of the specified primary: those one
+    /// hop farther from it than us, as long as they are within `rounds` hops of it. The primaries
+    /// farther away pull the certificates instead.
+    pub fn relays(&self, origin: &PublicKey, rounds: usize) -> Vec<PublicKey> {
+        let size = self.names.len();
+        let (position, distance) = match self.names.iter().position(|x| x == origin) {
+            Some(x) => (x, self.distances[(self.index + size - x) % size]),
+            None => return Vec::new(),
+        };
+        if distance >= rounds {
+            return Vec::new();
+        }
+        self.offsets
+            .iter()
+            .map(|offset| (self.index + offset) % size)
+            .filter(|x| self.distances[(x + size - position) % size] == distance + 1)
+            .map(|x| self.names[x])
+            .collect()
+    }
+}
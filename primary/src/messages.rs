@@ -1,20 +1,58 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
-use crypto::{Digest, Hash, PublicKey, Signature, SignatureService};
+use config::{Committee, Epoch, TimestampBounds, WorkerId};
+use crypto::{BlsSignature, Digest, Hash, PublicKey, Signature, SignatureService};
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::convert::TryInto;
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+#[path = "tests/messages_tests.rs"]
+pub mod messages_tests;
+
+/// Returns the current time in ms since the UNIX epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Failed to measure time")
+        .as_millis() as u64
+}
+
+/// The prefix of the store keys indexing the certificates by round.
+const ROUND_INDEX_PREFIX: &[u8] = b"round_index";
+
+/// The maximum number of system messages a header may carry.
+pub const MAX_SYSTEM_MESSAGES: usize = 16;
+
+/// A small payload injected by the primary itself (rather than by its workers) into its headers.
+/// System messages are ordered by the consensus along with the batches of the header, giving
+/// reconfiguration and slashing an in-band channel.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum SystemMessage {
+    /// Signals that the authority is ready to move to the given epoch.
+    EpochChange(Epoch),
+    /// Signals that the authority is alive.
+    Heartbeat,
+    /// References evidence (eg. of an equivocation) against an authority by its digest.
+    Evidence(PublicKey, Digest),
+}
 
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Header {
     pub author: PublicKey,
+    /// The epoch of the committee under which the header was created.
+    pub epoch: Epoch,
     pub round: Round,
+    /// The creation time of the header, in ms since the UNIX epoch (according to its author).
+    pub timestamp: u64,
     pub payload: BTreeMap<Digest, WorkerId>,
+    /// The system messages of the author, delivered with the payload by the consensus.
+    pub system: Vec<SystemMessage>,
     pub parents: BTreeSet<Digest>,
     pub id: Digest,
     pub signature: Signature,
@@ -23,15 +61,20 @@ pub struct Header {
 impl Header {
     pub async fn new(
         author: PublicKey,
+        epoch: Epoch,
         round: Round,
         payload: BTreeMap<Digest, WorkerId>,
+        system: Vec<SystemMessage>,
         parents: BTreeSet<Digest>,
         signature_service: &mut SignatureService,
     ) -> Self {
         let header = Self {
             author,
+            epoch,
             round,
+            timestamp: now(),
             payload,
+            system,
             parents,
             id: Digest::default(),
             signature: Signature::default(),
@@ -45,25 +88,105 @@ impl Header {
         }
     }
 
+    /// The authority that created the header.
+    pub fn author(&self) -> PublicKey {
+        self.author
+    }
+
+    /// The epoch of the committee under which the header was created.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// The round of the header.
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
+    /// The creation time of the header, in ms since the UNIX epoch (according to its author).
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// The digests of the batches referenced by the header, along with the id of the worker
+    /// holding each of them.
+    pub fn payload(&self) -> &BTreeMap<Digest, WorkerId> {
+        &self.payload
+    }
+
+    /// The system messages of the author.
+    pub fn system(&self) -> &[SystemMessage] {
+        &self.system
+    }
+
+    /// The digests of the parent certificates of the header.
+    pub fn parents(&self) -> &BTreeSet<Digest> {
+        &self.parents
+    }
+
+    /// The digest of the header.
+    pub fn id(&self) -> &Digest {
+        &self.id
+    }
+
+    /// The author's signature over the header id.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
     pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        self.check(committee)?;
+
+        // Check the signature.
+        self.signature
+            .verify(&self.id, &self.author)
+            .map_err(DagError::from)
+    }
+
+    /// Ensures the timestamp of the header is within the accepted skew from our local time `now`
+    /// (in ms since the UNIX epoch).
+    pub fn check_timestamp(&self, now: u64, bounds: &TimestampBounds) -> DagResult<()> {
+        let ahead = bounds
+            .max_future_skew
+            .is_some_and(|skew| self.timestamp > now.saturating_add(skew));
+        let behind = bounds
+            .max_past_skew
+            .is_some_and(|skew| self.timestamp < now.saturating_sub(skew));
+        ensure!(
+            !ahead && !behind,
+            DagError::InvalidTimestamp(self.id.clone(), self.timestamp)
+        );
+        Ok(())
+    }
+
+    /// Checks everything but the signature.
+    fn check(&self, committee: &Committee) -> DagResult<()> {
         // Ensure the header id is well formed.
         ensure!(self.digest() == self.id, DagError::InvalidHeaderId);
 
+        // Ensure the header is from the epoch of our committee.
+        ensure!(
+            self.epoch == committee.epoch,
+            DagError::InvalidEpoch(self.id.clone(), self.epoch)
+        );
+
         // Ensure the authority has voting rights.
         let voting_rights = committee.stake(&self.author);
         ensure!(voting_rights > 0, DagError::UnknownAuthority(self.author));
 
+        // Ensure the system messages are within bounds.
+        ensure!(
+            self.system.len() <= MAX_SYSTEM_MESSAGES,
+            DagError::MalformedHeader(self.id.clone())
+        );
+
         // Ensure all worker ids are correct.
         for worker_id in self.payload.values() {
             committee
-                .worker(&self.author, &worker_id)
+                .worker(&self.author, worker_id)
                 .map_err(|_| DagError::MalformedHeader(self.id.clone()))?;
         }
-
-        // Check the signature.
-        self.signature
-            .verify(&self.id, &self.author)
-            .map_err(DagError::from)
+        Ok(())
     }
 }
 
@@ -71,11 +194,16 @@ impl Hash for Header {
     fn digest(&self) -> Digest {
         let mut hasher = Sha512::new();
         hasher.update(&self.author);
+        hasher.update(self.epoch.to_le_bytes());
         hasher.update(self.round.to_le_bytes());
+        hasher.update(self.timestamp.to_le_bytes());
         for (x, y) in &self.payload {
             hasher.update(x);
             hasher.update(y.to_le_bytes());
         }
+        for x in &self.system {
+            hasher.update(bincode::serialize(x).expect("Failed to serialize system message"));
+        }
         for x in &self.parents {
             hasher.update(x);
         }
@@ -109,6 +237,8 @@ pub struct Vote {
     pub origin: PublicKey,
     pub author: PublicKey,
     pub signature: Signature,
+    /// Optional BLS signature over the vote digest, allowing the votes to be aggregated.
+    pub bls_signature: Option<BlsSignature>,
 }
 
 impl Vote {
@@ -123,11 +253,18 @@ impl Vote {
             origin: header.author,
             author: *author,
             signature: Signature::default(),
+            bls_signature: None,
         };
         let signature = signature_service.request_signature(vote.digest()).await;
         Self { signature, ..vote }
     }
 
+    /// Adds a BLS signature to the vote so that it can be aggregated into a compact certificate.
+    pub async fn sign_bls(&mut self, signature_service: &mut SignatureService) {
+        let signature = signature_service.request_bls_signature(self.digest()).await;
+        self.bls_signature = Some(signature);
+    }
+
     pub fn verify(&self, committee: &Committee) -> DagResult<()> {
         // Ensure the authority has voting rights.
         ensure!(
@@ -165,23 +302,182 @@ impl fmt::Debug for Vote {
     }
 }
 
+/// Evidence that an authority created two different headers for the same round. Both headers
+/// carry the signature of their author, and the evidence is signed by the authority reporting it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Equivocation {
+    pub first: Header,
+    pub second: Header,
+    pub reporter: PublicKey,
+    pub signature: Signature,
+}
+
+impl Equivocation {
+    pub async fn new(
+        first: Header,
+        second: Header,
+        reporter: &PublicKey,
+        signature_service: &mut SignatureService,
+    ) -> Self {
+        let equivocation = Self {
+            first,
+            second,
+            reporter: *reporter,
+            signature: Signature::default(),
+        };
+        let signature = signature_service
+            .request_signature(equivocation.digest())
+            .await;
+        Self {
+            signature,
+            ..equivocation
+        }
+    }
+
+    /// The authority that equivocated.
+    pub fn author(&self) -> PublicKey {
+        self.first.author
+    }
+
+    /// The round of the conflicting headers.
+    pub fn round(&self) -> Round {
+        self.first.round
+    }
+
+    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        // Ensure the headers actually conflict.
+        ensure!(
+            self.first.author == self.second.author
+                && self.first.round == self.second.round
+                && self.first.id != self.second.id,
+            DagError::InvalidEquivocation(self.author())
+        );
+
+        // Check both headers and the signature of the reporter.
+        self.first.verify(committee)?;
+        self.second.verify(committee)?;
+        ensure!(
+            committee.stake(&self.reporter) > 0,
+            DagError::UnknownAuthority(self.reporter)
+        );
+        self.signature
+            .verify(&self.digest(), &self.reporter)
+            .map_err(DagError::from)
+    }
+}
+
+impl Hash for Equivocation {
+    fn digest(&self) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(&self.first.id);
+        hasher.update(&self.second.id);
+        hasher.update(self.reporter);
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+}
+
+impl fmt::Debug for Equivocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}: E{}({}, {}, {})",
+            self.digest(),
+            self.round(),
+            self.author(),
+            self.first.id,
+            self.second.id
+        )
+    }
+}
+
+/// The votes of a certificate aggregated into a single BLS signature.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AggregateVotes {
+    /// Bitmap of the voters, indexed by the position of their public key in the committee.
+    pub signers: Vec<u8>,
+    /// The aggregated BLS signature of the voters over the certificate digest.
+    pub signature: BlsSignature,
+}
+
+impl AggregateVotes {
+    pub fn new(signers: &[PublicKey], signature: BlsSignature, committee: &Committee) -> Self {
+        let mut bitmap = vec![0u8; committee.size().div_ceil(8)];
+        for (i, name) in committee.authorities.keys().enumerate() {
+            if signers.contains(name) {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        Self {
+            signers: bitmap,
+            signature,
+        }
+    }
+
+    /// Returns the voters, or `None` if the bitmap does not match the committee.
+    pub fn signers(&self, committee: &Committee) -> Option<Vec<PublicKey>> {
+        if self.signers.len() != committee.size().div_ceil(8) {
+            return None;
+        }
+        let signers: Vec<_> = committee
+            .authorities
+            .keys()
+            .enumerate()
+            .filter(|(i, _)| self.signers[i / 8] & (1 << (i % 8)) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+
+        // Ensure no bit is set past the last authority.
+        let bits: u32 = self.signers.iter().map(|x| x.count_ones()).sum();
+        (bits as usize == signers.len()).then_some(signers)
+    }
+
+    /// Verifies the aggregated signature of the voters over the certificate digest.
+    pub fn verify(&self, digest: &Digest, committee: &Committee) -> DagResult<()> {
+        let signers = self
+            .signers(committee)
+            .ok_or_else(|| DagError::MalformedAggregate(digest.clone()))?;
+        let keys = signers
+            .iter()
+            .map(|name| {
+                committee
+                    .bls_key(name)
+                    .ok_or(DagError::MissingBlsKey(*name))
+            })
+            .collect::<DagResult<Vec<_>>>()?;
+        self.signature
+            .verify_aggregate(digest, &keys)
+            .map_err(DagError::from)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Certificate {
     pub header: Header,
     pub votes: Vec<(PublicKey, Signature)>,
+    /// The votes aggregated into a single signature. When set, `votes` is empty.
+    pub aggregate: Option<AggregateVotes>,
 }
 
 impl Certificate {
+    /// The genesis of the epoch of the committee. The id of the genesis headers commits to the
+    /// epoch, so that the dag of each epoch starts from distinct parents.
     pub fn genesis(committee: &Committee) -> Vec<Self> {
         committee
             .authorities
             .keys()
-            .map(|name| Self {
-                header: Header {
+            .map(|name| {
+                let header = Header {
                     author: *name,
+                    epoch: committee.epoch,
                     ..Header::default()
-                },
-                ..Self::default()
+                };
+                Self {
+                    header: Header {
+                        id: header.digest(),
+                        ..header
+                    },
+                    ..Self::default()
+                }
             })
             .collect()
     }
@@ -195,32 +491,136 @@ impl Certificate {
         // Check the embedded header.
         self.header.verify(committee)?;
 
+        // Check the votes.
+        self.check_quorum(committee)?;
+        match &self.aggregate {
+            Some(aggregate) => aggregate.verify(&self.digest(), committee),
+            None => Signature::verify_batch(&self.digest(), &self.votes).map_err(DagError::from),
+        }
+    }
+
+    /// Checks everything but the signatures (of the header and of the votes).
+    pub fn check(&self, committee: &Committee) -> DagResult<()> {
+        // Genesis certificates are always valid.
+        if Self::genesis(committee).contains(self) {
+            return Ok(());
+        }
+        self.header.check(committee)?;
+        self.check_quorum(committee)
+    }
+
+    /// Verifies the signatures of a batch of certificates (and of their headers) at once. The
+    /// certificates must already be checked (see `check`).
+    pub fn verify_signatures(certificates: &[Self], committee: &Committee) -> DagResult<()> {
+        let genesis = Self::genesis(committee);
+        let certificates: Vec<_> = certificates
+            .iter()
+            .filter(|x| !genesis.contains(x))
+            .map(|x| (x.digest(), x))
+            .collect();
+
+        let headers = certificates
+            .iter()
+            .map(|(_, x)| (&x.header.id, &x.header.author, &x.header.signature));
+        let votes = certificates.iter().flat_map(|(digest, x)| {
+            x.votes
+                .iter()
+                .map(move |(name, signature)| (digest, name, signature))
+        });
+        Signature::verify_many(headers.chain(votes))?;
+
+        // Aggregated votes cover a single digest, so they are checked one certificate at a time.
+        for (digest, certificate) in &certificates {
+            if let Some(aggregate) = &certificate.aggregate {
+                aggregate.verify(digest, committee)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The authorities whose votes form the quorum of the certificate.
+    pub fn signers(&self, committee: &Committee) -> DagResult<Vec<PublicKey>> {
+        match &self.aggregate {
+            Some(aggregate) => {
+                ensure!(
+                    self.votes.is_empty(),
+                    DagError::MalformedAggregate(self.digest())
+                );
+                aggregate
+                    .signers(committee)
+                    .ok_or_else(|| DagError::MalformedAggregate(self.digest()))
+            }
+            None => Ok(self.votes.iter().map(|(name, _)| *name).collect()),
+        }
+    }
+
+    /// Ensures the votes form a quorum of distinct authorities.
+    fn check_quorum(&self, committee: &Committee) -> DagResult<()> {
         // Ensure the certificate has a quorum.
         let mut weight = 0;
         let mut used = HashSet::new();
-        for (name, _) in self.votes.iter() {
-            ensure!(!used.contains(name), DagError::AuthorityReuse(*name));
-            let voting_rights = committee.stake(name);
-            ensure!(voting_rights > 0, DagError::UnknownAuthority(*name));
-            used.insert(*name);
+        for name in self.signers(committee)? {
+            ensure!(!used.contains(&name), DagError::AuthorityReuse(name));
+            let voting_rights = committee.stake(&name);
+            ensure!(voting_rights > 0, DagError::UnknownAuthority(name));
+            used.insert(name);
             weight += voting_rights;
         }
         ensure!(
             weight >= committee.quorum_threshold(),
             DagError::CertificateRequiresQuorum
         );
-
-        // Check the signatures.
-        Signature::verify_batch(&self.digest(), &self.votes).map_err(DagError::from)
+        Ok(())
     }
 
     pub fn round(&self) -> Round {
         self.header.round
     }
 
+    /// The epoch of the certified header.
+    pub fn epoch(&self) -> Epoch {
+        self.header.epoch
+    }
+
     pub fn origin(&self) -> PublicKey {
         self.header.author
     }
+
+    /// The creation time of the certified header, in ms since the UNIX epoch.
+    pub fn timestamp(&self) -> u64 {
+        self.header.timestamp
+    }
+
+    /// The store key under which we index the digest of the certificate of `origin` at `round`.
+    pub fn round_key(round: Round, origin: &PublicKey) -> Vec<u8> {
+        [ROUND_INDEX_PREFIX, &round.to_le_bytes(), origin.as_ref()].concat()
+    }
+
+    /// The header certified by the certificate.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The digests of the parent certificates.
+    pub fn parents(&self) -> &BTreeSet<Digest> {
+        &self.header.parents
+    }
+
+    /// The digests of the certified batches, along with the id of the worker holding each of them.
+    pub fn payload(&self) -> &BTreeMap<Digest, WorkerId> {
+        &self.header.payload
+    }
+
+    /// The certified system messages.
+    pub fn system(&self) -> &[SystemMessage] {
+        &self.header.system
+    }
+
+    /// The votes (authority and signature over the certificate digest) forming the quorum. It is
+    /// empty when the votes are aggregated (see `signers`).
+    pub fn votes(&self) -> &[(PublicKey, Signature)] {
+        &self.votes
+    }
 }
 
 impl Hash for Certificate {
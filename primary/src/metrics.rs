@@ -0,0 +1,110 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use network::NetworkMetrics;
+use prometheus::{
+    register_histogram_with_registry, register_int_counter_with_registry,
+    register_int_gauge_with_registry, Histogram, IntCounter, IntGauge, Registry,
+};
+
+/// The Prometheus metrics of the primary. Cloning the metrics shares them.
+#[derive(Clone)]
+pub struct PrimaryMetrics {
+    /// The time between the creation of our header and the assembly of its certificate.
+    pub certificate_latency: Histogram,
+    /// The number of votes we received on each of our headers.
+    pub votes_per_header: Histogram,
+    /// The number of headers waiting for missing dependencies in the `HeaderWaiter`.
+    pub pending_headers: IntGauge,
+    /// The number of certificates waiting for missing ancestors in the `CertificateWaiter`.
+    pub pending_certificates: IntGauge,
+    /// The number of headers and certificates we stopped waiting for because the garbage
+    /// collector passed them.
+    pub cancelled_waits: IntCounter,
+    /// The number of certificates the `CertificateFetcher` is fetching from our peers.
+    pub sync_requests_in_flight: IntGauge,
+    /// The number of messages dropped because their sender exceeded its quotas (or is banned).
+    pub throttled_messages: IntCounter,
+    /// The number of authorities currently banned for exceeding their quotas.
+    pub banned_peers: IntGauge,
+    /// The number of headers, votes, and certificates dropped because we recently received them.
+    pub replayed_messages: IntCounter,
+    /// The number of our headers that did not reach a quorum of votes within the alert delay.
+    pub headers_without_quorum: IntCounter,
+    /// The state of the connections we keep with the other primaries.
+    pub network: NetworkMetrics,
+}
+
+impl PrimaryMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            certificate_latency: register_histogram_with_registry!(
+                "primary_certificate_latency_seconds",
+                "Time between the creation of our header and the assembly of its certificate",
+                registry
+            )
+            .expect("Failed to register metric"),
+            votes_per_header: register_histogram_with_registry!(
+                "primary_votes_per_header",
+                "Number of votes received on each of our headers",
+                vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0],
+                registry
+            )
+            .expect("Failed to register metric"),
+            pending_headers: register_int_gauge_with_registry!(
+                "primary_pending_headers",
+                "Number of headers waiting for missing dependencies",
+                registry
+            )
+            .expect("Failed to register metric"),
+            pending_certificates: register_int_gauge_with_registry!(
+                "primary_pending_certificates",
+                "Number of certificates waiting for missing ancestors",
+                registry
+            )
+            .expect("Failed to register metric"),
+            cancelled_waits: register_int_counter_with_registry!(
+                "primary_cancelled_waits",
+                "Number of headers and certificates no longer waited for after garbage collection",
+                registry
+            )
+            .expect("Failed to register metric"),
+            sync_requests_in_flight: register_int_gauge_with_registry!(
+                "primary_sync_requests_in_flight",
+                "Number of certificates being fetched from our peers",
+                registry
+            )
+            .expect("Failed to register metric"),
+            throttled_messages: register_int_counter_with_registry!(
+                "primary_throttled_messages",
+                "Number of messages dropped because their sender exceeded its quotas",
+                registry
+            )
+            .expect("Failed to register metric"),
+            banned_peers: register_int_gauge_with_registry!(
+                "primary_banned_peers",
+                "Number of authorities banned for exceeding their quotas",
+                registry
+            )
+            .expect("Failed to register metric"),
+            replayed_messages: register_int_counter_with_registry!(
+                "primary_replayed_messages",
+                "Number of messages dropped because we recently received them",
+                registry
+            )
+            .expect("Failed to register metric"),
+            headers_without_quorum: register_int_counter_with_registry!(
+                "primary_headers_without_quorum",
+                "Number of our headers that did not reach a quorum of votes within the alert delay",
+                registry
+            )
+            .expect("Failed to register metric"),
+            network: NetworkMetrics::new(registry),
+        }
+    }
+}
+
+impl Default for PrimaryMetrics {
+    /// Metrics registered in a registry of their own (they are not exported).
+    fn default() -> Self {
+        Self::new(&Registry::new())
+    }
+}
@@ -1,25 +1,42 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::aggregators::HeaderProgress;
+use crate::authenticator::{open, verify, Authenticator};
+use crate::certificate_fetcher::CertificateFetcher;
 use crate::certificate_waiter::CertificateWaiter;
 use crate::core::Core;
 use crate::error::DagError;
-use crate::garbage_collector::GarbageCollector;
+use crate::garbage_collector::{GarbageCollector, GcDepthCommand};
+use crate::gossip::Gossip;
 use crate::header_waiter::HeaderWaiter;
-use crate::helper::Helper;
-use crate::messages::{Certificate, Header, Vote};
+use crate::helper::{Helper, HelperRequest, PageToken};
+use crate::messages::{Certificate, Equivocation, Header, SystemMessage, Vote};
+use crate::metrics::PrimaryMetrics;
 use crate::payload_receiver::PayloadReceiver;
-use crate::proposer::Proposer;
-use crate::synchronizer::Synchronizer;
+use crate::proposer::{Proposer, ProposerCommand};
+use crate::quotas::{PeerLimiter, QuotaClass};
+use crate::replay_cache::{ReplayCache, DEFAULT_REPLAY_CACHE_SIZE};
+use crate::synchronizer::{SyncObligations, Synchronizer};
+use crate::verifier::Verifier;
+use crate::vote_log::VoteLog;
+use crate::worker_cache::WorkerCache;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, KeyPair, Parameters, WorkerId};
-use crypto::{Digest, PublicKey, SignatureService};
+use config::{
+    Committee, DiffusionMode, Parameters, PeerQuotas, SendQueue, TransportCompression, WorkerId,
+};
+use crypto::{Digest, PublicKey, Signature, SignatureService};
 use futures::sink::SinkExt as _;
-use log::info;
-use network::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use log::{debug, info, warn};
+use network::{
+    Compression, Features, InboundLimiter, MessageHandler, NetworkContext, NetworkMetrics, Peer,
+    QueueLimits, QueuePolicy, Receiver as NetworkReceiver, RetryPolicy, Writer,
+};
+use prometheus::Registry;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::path::Path;
 use std::sync::atomic::AtomicU64;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use store::Store;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
@@ -33,8 +50,36 @@ pub type Round = u64;
 pub enum PrimaryMessage {
     Header(Header),
     Vote(Vote),
+    /// Votes of the same round on the headers of different authors. Each author only processes
+    /// the vote on its own header.
+    Votes(Vec<Vote>),
     Certificate(Certificate),
     CertificatesRequest(Vec<Digest>, /* requestor */ PublicKey),
+    /// The responder has more certificates to serve: the token allows to request the next page.
+    CertificatesPage(PageToken, /* responder */ PublicKey),
+    NextCertificatesRequest(PageToken, /* requestor */ PublicKey),
+    /// The digests of the recent certificates of the sender (when certificates are gossiped).
+    CertificateDigests(Vec<Digest>, /* origin */ PublicKey),
+    /// A serialized message signed by the origin it claims, along with a nonce (see
+    /// `Authenticator`).
+    Signed(Vec<u8>, /* nonce */ u64, Signature),
+    /// A certificate served by the `Helper` in reply to our request. It is handled as any other
+    /// certificate, but it is exempt from the replay cache (we may need a certificate again to
+    /// catch up after dropping it) and charged to its own verification budget.
+    SyncedCertificate(Certificate),
+}
+
+impl PrimaryMessage {
+    /// Returns the authority a message claims to come from, if it carries no signature of its own.
+    pub fn origin(&self) -> Option<PublicKey> {
+        match self {
+            PrimaryMessage::CertificatesRequest(_, origin)
+            | PrimaryMessage::NextCertificatesRequest(_, origin)
+            | PrimaryMessage::CertificatesPage(_, origin)
+            | PrimaryMessage::CertificateDigests(_, origin) => Some(*origin),
+            _ => None,
+        }
+    }
 }
 
 /// The messages sent by the primary to its workers.
@@ -44,6 +89,12 @@ pub enum PrimaryWorkerMessage {
     Synchronize(Vec<Digest>, /* target */ PublicKey),
     /// The primary indicates a round update.
     Cleanup(Round),
+    /// The primary asks the worker to confirm it stores the batches: an embedded `RpcClient`
+    /// request of their digests, to which the worker replies with the digests of the batches it
+    /// does not have.
+    Confirm(/* request */ Vec<u8>),
+    /// The primary indicates that the consensus committed the target batches at the specified round.
+    Committed(Vec<Digest>, Round),
 }
 
 /// The messages sent by the workers to their primary.
@@ -58,13 +109,24 @@ pub enum WorkerPrimaryMessage {
 pub struct Primary;
 
 impl Primary {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        keypair: KeyPair,
+        name: PublicKey,
+        signature_service: SignatureService,
         committee: Committee,
+        worker_cache: WorkerCache,
         parameters: Parameters,
         store: Store,
+        vote_log: &Path,
         tx_consensus: Sender<Certificate>,
         rx_consensus: Receiver<Certificate>,
+        rx_gc_depth: Receiver<GcDepthCommand>,
+        rx_proposer_commands: Receiver<ProposerCommand>,
+        tx_equivocations: Sender<Equivocation>,
+        tx_progress: Sender<HeaderProgress>,
+        rx_system: Receiver<SystemMessage>,
+        context: NetworkContext,
+        registry: &Registry,
     ) {
         let (tx_others_digests, rx_others_digests) = channel(CHANNEL_CAPACITY);
         let (tx_our_digests, rx_our_digests) = channel(CHANNEL_CAPACITY);
@@ -75,32 +137,73 @@ impl Primary {
         let (tx_headers_loopback, rx_headers_loopback) = channel(CHANNEL_CAPACITY);
         let (tx_certificates_loopback, rx_certificates_loopback) = channel(CHANNEL_CAPACITY);
         let (tx_primary_messages, rx_primary_messages) = channel(CHANNEL_CAPACITY);
+        let (tx_verified_messages, rx_verified_messages) = channel(CHANNEL_CAPACITY);
         let (tx_cert_requests, rx_cert_requests) = channel(CHANNEL_CAPACITY);
+        let (tx_fetcher, rx_fetcher) = channel(CHANNEL_CAPACITY);
+        let (tx_pages, rx_pages) = channel(CHANNEL_CAPACITY);
+        let (tx_committed, rx_committed) = channel(CHANNEL_CAPACITY);
+        let (tx_gossip, rx_gossip) = channel(CHANNEL_CAPACITY);
+        let (tx_advertisements, rx_advertisements) = channel(CHANNEL_CAPACITY);
 
         // Write the parameters to the logs.
         parameters.log();
 
-        // Parse the public and secret key of this authority.
-        let name = keypair.name;
-        let secret = keypair.secret;
+        // Sign the messages claiming to come from us, so that our peers can check their origin.
+        let authenticator = match parameters.authenticate_messages {
+            true => Authenticator::new(signature_service.clone()),
+            false => Authenticator::disabled(),
+        };
+
+        // Register the metrics of the primary.
+        let metrics = PrimaryMetrics::new(registry);
 
         // Atomic variable use to synchronizer all tasks with the latest consensus round. This is only
         // used for cleanup. The only tasks that write into this variable is `GarbageCollector`.
         let consensus_round = Arc::new(AtomicU64::new(0));
 
+        // The depth of the garbage collector. It can be changed at runtime, but only `GarbageCollector` writes
+        // into this variable.
+        let gc_depth = Arc::new(AtomicU64::new(parameters.gc_depth));
+
         // Spawn the network receiver listening to messages from the other primaries.
-        let mut address = committee
-            .primary(&name)
+        let address = committee
+            .listen_primary(&name, &parameters)
             .expect("Our public key or worker id is not in the committee")
             .primary_to_primary;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+        NetworkReceiver::spawn_with_context(
             address,
             /* handler */
             PrimaryReceiverHandler {
                 tx_primary_messages,
                 tx_cert_requests,
+                tx_pages,
+                tx_advertisements,
+                limiter: Arc::new(Mutex::new(PeerLimiter::new(
+                    &committee,
+                    parameters.peer_quotas,
+                    metrics.clone(),
+                ))),
+                committee: committee.clone(),
+                peer_quotas: parameters.peer_quotas,
+                metrics: metrics.clone(),
+                peer: None,
+                replays: Arc::new(Mutex::new(ReplayCache::new(
+                    parameters
+                        .replay_cache_size
+                        .unwrap_or(DEFAULT_REPLAY_CACHE_SIZE),
+                    metrics.clone(),
+                ))),
+                max_frame_size: parameters.frame_limits.max_inbound,
+                accepts_compression: parameters.transport_compression != TransportCompression::None,
+                network_metrics: metrics.network.clone(),
+                authenticate_messages: parameters.authenticate_messages,
+                required_features: match parameters.certificate_diffusion.mode {
+                    DiffusionMode::Broadcast => Features::NONE,
+                    _ => Features::GOSSIP,
+                },
+                inbound_limiter: InboundLimiter::new(parameters.peer_quotas.frames),
             },
+            context.clone(),
         );
         info!(
             "Primary {} listening to primary messages on {}",
@@ -108,18 +211,22 @@ impl Primary {
         );
 
         // Spawn the network receiver listening to messages from our workers.
-        let mut address = committee
-            .primary(&name)
+        let address = committee
+            .listen_primary(&name, &parameters)
             .expect("Our public key or worker id is not in the committee")
             .worker_to_primary;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+        NetworkReceiver::spawn_with_context(
             address,
             /* handler */
             WorkerReceiverHandler {
+                name,
+                worker_cache: worker_cache.clone(),
                 tx_our_digests,
                 tx_others_digests,
+                max_frame_size: parameters.frame_limits.max_inbound,
+                network_metrics: metrics.network.clone(),
             },
+            context.clone(),
         );
         info!(
             "Primary {} listening to workers messages on {}",
@@ -127,18 +234,42 @@ impl Primary {
         );
 
         // The `Synchronizer` provides auxiliary methods helping to `Core` to sync.
+        let header_obligations = SyncObligations::new(parameters.sync_limits.header_waiter);
+        let certificate_obligations =
+            SyncObligations::new(parameters.sync_limits.certificate_waiter);
         let synchronizer = Synchronizer::new(
             name,
             &committee,
             store.clone(),
             /* tx_header_waiter */ tx_sync_headers,
             /* tx_certificate_waiter */ tx_sync_certificates,
+            header_obligations.clone(),
+            certificate_obligations.clone(),
         );
 
-        // The `SignatureService` is used to require signatures on specific digests.
-        let signature_service = SignatureService::new(secret);
+        // The `Verifier` verifies the signatures of the headers, votes, and certificates from the other
+        // primaries in parallel, and hands them to the `Core` in order.
+        let parallelism = parameters
+            .verification_parallelism
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()));
+        let header_round = Arc::new(AtomicU64::new(0));
+        Verifier::spawn(
+            name,
+            committee.clone(),
+            header_round.clone(),
+            parameters.verification_budget,
+            parallelism,
+            /* rx_primaries */ rx_primary_messages,
+            /* tx_core */ tx_verified_messages,
+        );
 
         // The `Core` receives and handles headers, votes, and certificates from the other primaries.
+        let retry_policy = RetryPolicy {
+            initial_delay: parameters.delivery_retries.initial_delay,
+            max_delay: parameters.delivery_retries.max_delay,
+            jitter: parameters.delivery_retries.jitter,
+            max_attempts: parameters.delivery_retries.max_attempts,
+        };
         Core::spawn(
             name,
             committee.clone(),
@@ -146,17 +277,67 @@ impl Primary {
             synchronizer,
             signature_service.clone(),
             consensus_round.clone(),
-            parameters.gc_depth,
-            /* rx_primaries */ rx_primary_messages,
+            gc_depth.clone(),
+            header_round,
+            parameters.aggregate_votes,
+            parameters.payload_availability,
+            parameters.vote_batching,
+            parameters.timestamp_bounds,
+            Some(
+                VoteLog::open(vote_log, parameters.vote_log).expect("Failed to open the vote log"),
+            ),
+            parameters.archival,
+            parameters.quorum_alert_delay,
+            parameters.max_header_payload,
+            /* rx_primaries */ rx_verified_messages,
             /* rx_header_waiter */ rx_headers_loopback,
             /* rx_certificate_waiter */ rx_certificates_loopback,
             /* rx_proposer */ rx_headers,
             tx_consensus,
             /* tx_proposer */ tx_parents,
+            tx_equivocations,
+            (parameters.certificate_diffusion.mode != DiffusionMode::Broadcast)
+                .then_some(tx_gossip),
+            Some(tx_progress),
+            retry_policy,
+            queue_limits(parameters.send_queues.consensus),
+            parameters.frame_limits.max_outbound,
+            transport_compression(parameters.transport_compression),
+            metrics.clone(),
+            context.clone(),
+        );
+
+        // The `Gossip` diffuses the certificates to a few peers (if enabled) and pulls the
+        // certificates advertised by our peers that we miss.
+        Gossip::spawn(
+            name,
+            committee.clone(),
+            store.clone(),
+            parameters.certificate_diffusion,
+            /* rx_core */ rx_gossip,
+            /* rx_primaries */ rx_advertisements,
+            authenticator.clone(),
+            queue_limits(parameters.send_queues.consensus),
+            parameters.frame_limits.max_outbound,
+            metrics.network.clone(),
+            context.clone(),
         );
 
         // Keeps track of the latest consensus round and allows other tasks to clean up their their internal state
-        GarbageCollector::spawn(&name, &committee, consensus_round.clone(), rx_consensus);
+        GarbageCollector::spawn(
+            name,
+            worker_cache.clone(),
+            parameters.archival,
+            consensus_round.clone(),
+            gc_depth.clone(),
+            rx_consensus,
+            rx_gc_depth,
+            /* tx_proposer */ tx_committed,
+            queue_limits(parameters.send_queues.internal),
+            parameters.frame_limits.max_outbound,
+            metrics.network.clone(),
+            context.clone(),
+        );
 
         // Receives batch digests from other workers. They are only used to validate headers.
         PayloadReceiver::spawn(store.clone(), /* rx_workers */ rx_others_digests);
@@ -167,21 +348,52 @@ impl Primary {
         HeaderWaiter::spawn(
             name,
             committee.clone(),
+            worker_cache,
             store.clone(),
-            consensus_round,
-            parameters.gc_depth,
+            consensus_round.clone(),
+            gc_depth.clone(),
             parameters.sync_retry_delay,
-            parameters.sync_retry_nodes,
+            parameters.sync_retries,
+            header_obligations,
+            parameters.payload_availability.timeout,
             /* rx_synchronizer */ rx_sync_headers,
             /* tx_core */ tx_headers_loopback,
+            tx_fetcher,
+            queue_limits(parameters.send_queues.requests),
+            parameters.frame_limits.max_outbound,
+            metrics.clone(),
+            context.clone(),
+        );
+
+        // The `CertificateFetcher` fetches the parents missed by the `HeaderWaiter` from several
+        // peers in parallel.
+        CertificateFetcher::spawn(
+            name,
+            committee.clone(),
+            store.clone(),
+            consensus_round.clone(),
+            gc_depth.clone(),
+            /* timeout */ parameters.sync_retry_delay,
+            parameters.fetch_limits,
+            /* rx_header_waiter */ rx_fetcher,
+            rx_pages,
+            authenticator.clone(),
+            queue_limits(parameters.send_queues.requests),
+            parameters.frame_limits.max_outbound,
+            metrics.clone(),
+            context.clone(),
         );
 
         // The `CertificateWaiter` waits to receive all the ancestors of a certificate before looping it back to the
         // `Core` for further processing.
         CertificateWaiter::spawn(
             store.clone(),
+            consensus_round,
+            gc_depth.clone(),
+            certificate_obligations,
             /* rx_synchronizer */ rx_sync_certificates,
             /* tx_core */ tx_certificates_loopback,
+            metrics.clone(),
         );
 
         // When the `Core` collects enough parent certificates, the `Proposer` generates a new header with new batch
@@ -189,16 +401,39 @@ impl Primary {
         Proposer::spawn(
             name,
             &committee,
+            store.clone(),
             signature_service,
             parameters.header_size,
+            parameters.min_header_size,
+            parameters.max_header_payload,
             parameters.max_header_delay,
+            parameters.payload_balancing,
+            parameters.max_commit_lag,
+            parameters.lazy_header_delay,
+            parameters.leader_schedule,
+            parameters.archival,
+            gc_depth,
             /* rx_core */ rx_parents,
             /* rx_workers */ rx_our_digests,
+            rx_system,
+            rx_committed,
+            /* rx_commands */ rx_proposer_commands,
             /* tx_core */ tx_headers,
         );
 
         // The `Helper` is dedicated to reply to certificates requests from other primaries.
-        Helper::spawn(committee.clone(), store, rx_cert_requests);
+        Helper::spawn(
+            name,
+            committee.clone(),
+            store,
+            parameters.helper_limits,
+            rx_cert_requests,
+            queue_limits(parameters.send_queues.sync),
+            parameters.frame_limits.max_outbound,
+            authenticator,
+            metrics.network.clone(),
+            context,
+        );
 
         // NOTE: This log entry is used to compute performance.
         info!(
@@ -217,7 +452,47 @@ impl Primary {
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_primary_messages: Sender<PrimaryMessage>,
-    tx_cert_requests: Sender<(Vec<Digest>, PublicKey)>,
+    tx_cert_requests: Sender<(HelperRequest, PublicKey)>,
+    tx_pages: Sender<(PageToken, PublicKey)>,
+    tx_advertisements: Sender<(Vec<Digest>, PublicKey)>,
+    /// The quotas of the authorities (those of the connection if it authenticated no authority).
+    limiter: Arc<Mutex<PeerLimiter>>,
+    committee: Committee,
+    peer_quotas: PeerQuotas,
+    metrics: PrimaryMetrics,
+    /// The authority the connection authenticated (if any).
+    peer: Option<PublicKey>,
+    replays: Arc<Mutex<ReplayCache>>,
+    max_frame_size: usize,
+    /// Whether we accept the offers of the other primaries to compress their messages.
+    accepts_compression: bool,
+    network_metrics: NetworkMetrics,
+    /// Whether we drop the unsigned messages claiming an origin.
+    authenticate_messages: bool,
+    /// The features the other primaries must enable (gossiping certificates only works if they
+    /// all relay them).
+    required_features: Features,
+    /// Limits the rate at which each of the other primaries sends us frames.
+    inbound_limiter: InboundLimiter,
+}
+
+impl PrimaryReceiverHandler {
+    /// Returns the authority a message claims to come from and the class of its quota (if any).
+    fn quota(message: &PrimaryMessage) -> Option<(PublicKey, QuotaClass)> {
+        match message {
+            PrimaryMessage::Header(header) => Some((header.author, QuotaClass::Header)),
+            PrimaryMessage::Vote(vote) => Some((vote.author, QuotaClass::Vote)),
+            PrimaryMessage::Votes(votes) => votes.first().map(|x| (x.author, QuotaClass::Vote)),
+            PrimaryMessage::CertificatesRequest(_, peer)
+            | PrimaryMessage::NextCertificatesRequest(_, peer)
+            | PrimaryMessage::CertificatesPage(_, peer)
+            | PrimaryMessage::CertificateDigests(_, peer) => Some((*peer, QuotaClass::SyncRequest)),
+            // Certificates are relayed: they do not identify their sender.
+            PrimaryMessage::Certificate(_) | PrimaryMessage::SyncedCertificate(_) => None,
+            // Signed messages are opened before being charged.
+            PrimaryMessage::Signed(..) => None,
+        }
+    }
 }
 
 #[async_trait]
@@ -227,12 +502,70 @@ impl MessageHandler for PrimaryReceiverHandler {
         let _ = writer.send(Bytes::from("Ack")).await;
 
         // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
+        let message = bincode::deserialize(&serialized).map_err(DagError::SerializationError)?;
+
+        // Open the signed messages, and require a signature from the messages claiming an origin
+        // if we authenticate messages.
+        let (message, envelope) = match message {
+            PrimaryMessage::Signed(bytes, nonce, signature) => {
+                (open(&bytes)?, Some((bytes, nonce, signature)))
+            }
+            message if self.authenticate_messages && message.origin().is_some() => {
+                debug!("Dropping unsigned message from {:?}", message.origin());
+                return Ok(());
+            }
+            message => (message, None),
+        };
+
+        // Drop the message if its sender exceeded its quota: we charge the authority the connection
+        // authenticated (or the one claimed by the message, to the quotas of the connection).
+        if let Some((claimed, class)) = Self::quota(&message) {
+            let peer = self.peer.unwrap_or(claimed);
+            if !self.limiter.lock().unwrap().try_acquire(&peer, class) {
+                debug!("Dropping {} from {}: quota exceeded", class, peer);
+                return Ok(());
+            }
+        }
+
+        // Check the signature of the signed messages (once charged to the quota of their origin).
+        if let Some((bytes, nonce, signature)) = &envelope {
+            verify(&message, bytes, *nonce, signature)?;
+        }
+
+        // Drop the headers, votes, certificates, and signed messages we recently received.
+        let replayable = envelope.is_some()
+            || matches!(
+                message,
+                PrimaryMessage::Header(_)
+                    | PrimaryMessage::Vote(_)
+                    | PrimaryMessage::Votes(_)
+                    | PrimaryMessage::Certificate(_)
+            );
+        if replayable && !self.replays.lock().unwrap().check(&serialized) {
+            debug!("Dropping replayed message");
+            return Ok(());
+        }
+        match message {
             PrimaryMessage::CertificatesRequest(missing, requestor) => self
                 .tx_cert_requests
-                .send((missing, requestor))
+                .send((HelperRequest::Certificates(missing), requestor))
                 .await
                 .expect("Failed to send primary message"),
+            PrimaryMessage::NextCertificatesRequest(token, requestor) => self
+                .tx_cert_requests
+                .send((HelperRequest::NextPage(token), requestor))
+                .await
+                .expect("Failed to send primary message"),
+            PrimaryMessage::CertificatesPage(token, responder) => self
+                .tx_pages
+                .send((token, responder))
+                .await
+                .expect("Failed to send page token"),
+            PrimaryMessage::CertificateDigests(digests, origin) => self
+                .tx_advertisements
+                .send((digests, origin))
+                .await
+                .expect("Failed to send certificates advertisement"),
             request => self
                 .tx_primary_messages
                 .send(request)
@@ -241,13 +574,53 @@ impl MessageHandler for PrimaryReceiverHandler {
         }
         Ok(())
     }
+
+    fn for_peer(&self, peer: &Peer) -> Self {
+        let limiter = match peer.name {
+            Some(_) => self.limiter.clone(),
+            None => Arc::new(Mutex::new(PeerLimiter::for_connection(
+                &self.committee,
+                self.peer_quotas,
+                self.metrics.clone(),
+            ))),
+        };
+        Self {
+            limiter,
+            peer: peer.name,
+            ..self.clone()
+        }
+    }
+
+    fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    fn accepts_compression(&self) -> bool {
+        self.accepts_compression
+    }
+
+    fn metrics(&self) -> Option<NetworkMetrics> {
+        Some(self.network_metrics.clone())
+    }
+
+    fn required_features(&self) -> Features {
+        self.required_features
+    }
+
+    fn inbound_limiter(&self) -> Option<InboundLimiter> {
+        Some(self.inbound_limiter.clone())
+    }
 }
 
 /// Defines how the network receiver handles incoming workers messages.
 #[derive(Clone)]
 struct WorkerReceiverHandler {
+    name: PublicKey,
+    worker_cache: WorkerCache,
     tx_our_digests: Sender<(Digest, WorkerId)>,
     tx_others_digests: Sender<(Digest, WorkerId)>,
+    max_frame_size: usize,
+    network_metrics: NetworkMetrics,
 }
 
 #[async_trait]
@@ -259,11 +632,17 @@ impl MessageHandler for WorkerReceiverHandler {
     ) -> Result<(), Box<dyn Error>> {
         // Deserialize and parse the message.
         match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
-            WorkerPrimaryMessage::OurBatch(digest, worker_id) => self
-                .tx_our_digests
-                .send((digest, worker_id))
-                .await
-                .expect("Failed to send workers' digests"),
+            WorkerPrimaryMessage::OurBatch(digest, worker_id) => {
+                // Only include the batches of our current workers in our headers.
+                if let Err(e) = self.worker_cache.worker(&self.name, &worker_id) {
+                    warn!("Dropping batch {}: {}", digest, e);
+                    return Ok(());
+                }
+                self.tx_our_digests
+                    .send((digest, worker_id))
+                    .await
+                    .expect("Failed to send workers' digests")
+            }
             WorkerPrimaryMessage::OthersBatch(digest, worker_id) => self
                 .tx_others_digests
                 .send((digest, worker_id))
@@ -272,4 +651,34 @@ impl MessageHandler for WorkerReceiverHandler {
         }
         Ok(())
     }
+
+    fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    fn metrics(&self) -> Option<NetworkMetrics> {
+        Some(self.network_metrics.clone())
+    }
+}
+
+/// Returns the compression the primaries offer to (and accept from) each other.
+fn transport_compression(compression: TransportCompression) -> Compression {
+    match compression {
+        TransportCompression::None => Compression::None,
+        TransportCompression::Snappy => Compression::Snappy,
+        TransportCompression::Zstd => Compression::Zstd,
+    }
+}
+
+/// Returns the limits of the send queues of a class of messages.
+fn queue_limits(queue: SendQueue) -> QueueLimits {
+    let policy = match queue.policy {
+        config::QueuePolicy::DropOldest => QueuePolicy::DropOldest,
+        config::QueuePolicy::DropNewest => QueuePolicy::DropNewest,
+        config::QueuePolicy::Block => QueuePolicy::Block,
+    };
+    QueueLimits {
+        capacity: queue.capacity,
+        policy,
+    }
 }
@@ -1,12 +1,18 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::messages::{Certificate, Header};
+use crate::messages::{Certificate, Header, SystemMessage, MAX_SYSTEM_MESSAGES};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
+use config::{
+    scheduled_leader, Committee, Epoch, LeaderSchedule, PayloadBalancing, PayloadPolicy, WorkerId,
+};
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, SignatureService};
-use log::debug;
-#[cfg(feature = "benchmark")]
-use log::info;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::time::{sleep, Duration, Instant};
 
@@ -14,21 +20,63 @@ use tokio::time::{sleep, Duration, Instant};
 #[path = "tests/proposer_tests.rs"]
 pub mod proposer_tests;
 
+/// The store key of the last header we proposed.
+pub const LAST_PROPOSED_KEY: &[u8] = b"last_proposed_header";
+
+/// The commands operators can send to the `Proposer`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ProposerCommand {
+    /// Stop creating headers. We keep voting for the headers of the other authorities and
+    /// syncing with them, and we keep buffering the digests of our workers.
+    Pause,
+    /// Resume creating headers.
+    Resume,
+}
+
+/// The batches' digests and system messages of one of our headers.
+type UncommittedPayload = (Vec<(Digest, WorkerId)>, Vec<SystemMessage>);
+
 /// The proposer creates new headers and send them to the core for broadcasting and further processing.
 pub struct Proposer {
     /// The public key of this primary.
     name: PublicKey,
+    /// The epoch of the committee.
+    epoch: Epoch,
+    /// The persistent storage (holding the last header we proposed).
+    store: Store,
     /// Service to sign headers.
     signature_service: SignatureService,
     /// The size of the headers' payload.
     header_size: usize,
+    /// The minimum payload for which we propose as soon as we have the parents (if any).
+    min_header_size: Option<usize>,
+    /// The maximum number of digests in a header (if any).
+    max_header_payload: Option<usize>,
     /// The maximum delay to wait for batches' digests.
     max_header_delay: u64,
+    /// How we pick the digests of the next header when they do not all fit.
+    payload_balancing: PayloadBalancing,
+    /// The maximum number of rounds we may get ahead of the last committed round (if any).
+    max_commit_lag: Option<u64>,
+    /// The delay before proposing a header without payload when we are not the leader (if any).
+    lazy_header_delay: Option<u64>,
+    /// One cycle of the leader schedule of the consensus.
+    leaders: Vec<PublicKey>,
+    /// Whether we only archive the dag (we then never propose).
+    archival: bool,
+    /// The depth of the garbage collector (it may change at runtime).
+    gc_depth: Arc<AtomicU64>,
 
     /// Receives the parents to include in the next header (along with their round number).
     rx_core: Receiver<(Vec<Digest>, Round)>,
     /// Receives the batches' digests from our workers.
     rx_workers: Receiver<(Digest, WorkerId)>,
+    /// Receives the system messages to include in our headers.
+    rx_system: Receiver<SystemMessage>,
+    /// Receives the author and round of the certificates committed by the consensus.
+    rx_committed: Receiver<(PublicKey, Round)>,
+    /// Receives the commands of the operator.
+    rx_commands: Receiver<ProposerCommand>,
     /// Sends newly created headers to the `Core`.
     tx_core: Sender<Header>,
 
@@ -36,10 +84,22 @@ pub struct Proposer {
     round: Round,
     /// Holds the certificates' ids waiting to be included in the next header.
     last_parents: Vec<Digest>,
-    /// Holds the batches' digests waiting to be included in the next header.
-    digests: Vec<(Digest, WorkerId)>,
+    /// Holds the batches' digests waiting to be included in the next header (along with the time
+    /// we received them).
+    digests: Vec<(Digest, WorkerId, Instant)>,
+    /// The batches' digests we buffered or referenced in our recent headers (along with the round
+    /// at which we received or referenced them). We never reference a batch twice within the gc window.
+    referenced: HashMap<Digest, Round>,
     /// Keeps track of the size (in bytes) of batches' digests that we received so far.
     payload_size: usize,
+    /// Holds the system messages waiting to be included in the next headers.
+    system: Vec<SystemMessage>,
+    /// The highest round committed by the consensus.
+    committed_round: Round,
+    /// The payload and system messages of our headers that are not committed yet (indexed by round).
+    uncommitted: BTreeMap<Round, UncommittedPayload>,
+    /// Whether the operator paused the creation of headers.
+    paused: bool,
 }
 
 impl Proposer {
@@ -47,49 +107,188 @@ impl Proposer {
     pub fn spawn(
         name: PublicKey,
         committee: &Committee,
+        store: Store,
         signature_service: SignatureService,
         header_size: usize,
+        min_header_size: Option<usize>,
+        max_header_payload: Option<usize>,
         max_header_delay: u64,
+        payload_balancing: PayloadBalancing,
+        max_commit_lag: Option<u64>,
+        lazy_header_delay: Option<u64>,
+        leader_schedule: LeaderSchedule,
+        archival: bool,
+        gc_depth: Arc<AtomicU64>,
         rx_core: Receiver<(Vec<Digest>, Round)>,
         rx_workers: Receiver<(Digest, WorkerId)>,
+        rx_system: Receiver<SystemMessage>,
+        rx_committed: Receiver<(PublicKey, Round)>,
+        rx_commands: Receiver<ProposerCommand>,
         tx_core: Sender<Header>,
     ) {
         let genesis = Certificate::genesis(committee)
             .iter()
             .map(|x| x.digest())
             .collect();
+        let leaders = committee.leader_schedule(leader_schedule);
+        let epoch = committee.epoch;
 
         tokio::spawn(async move {
             Self {
                 name,
+                epoch,
+                store,
                 signature_service,
                 header_size,
+                min_header_size,
+                max_header_payload,
                 max_header_delay,
+                payload_balancing,
+                max_commit_lag,
+                lazy_header_delay,
+                leaders,
+                archival,
+                gc_depth,
                 rx_core,
                 rx_workers,
+                rx_system,
+                rx_committed,
+                rx_commands,
                 tx_core,
                 round: 1,
                 last_parents: genesis,
                 digests: Vec::with_capacity(2 * header_size),
+                referenced: HashMap::new(),
                 payload_size: 0,
+                system: Vec::new(),
+                committed_round: 0,
+                uncommitted: BTreeMap::new(),
+                paused: false,
             }
             .run()
             .await;
         });
     }
 
+    /// Whether we are the leader of the given round. The consensus only elects leaders for even
+    /// rounds.
+    fn is_leader(&self, round: Round) -> bool {
+        round.is_multiple_of(2) && scheduled_leader(&self.leaders, round) == self.name
+    }
+
+    /// Returns the delay we wait before proposing our next header (without enough payload).
+    fn header_delay(&self) -> u64 {
+        match self.lazy_header_delay {
+            Some(delay)
+                if self.digests.is_empty()
+                    && self.system.is_empty()
+                    && !self.is_leader(self.round) =>
+            {
+                delay
+            }
+            _ => self.max_header_delay,
+        }
+    }
+
+    /// Returns the indices of the buffered digests, in the order we should include them.
+    fn preference_order(&self) -> Vec<usize> {
+        if self.payload_balancing.policy == PayloadPolicy::Fifo {
+            return (0..self.digests.len()).collect();
+        }
+
+        // Digests waiting for too long go first (oldest first).
+        let max_age = Duration::from_millis(self.payload_balancing.max_batch_age);
+        let mut order: Vec<_> = (0..self.digests.len())
+            .filter(|i| self.digests[*i].2.elapsed() >= max_age)
+            .collect();
+
+        // Then, repeatedly take the oldest digest of the worker with the largest backlog.
+        let mut backlogs: HashMap<WorkerId, VecDeque<usize>> = HashMap::new();
+        for (i, (_, worker_id, received)) in self.digests.iter().enumerate() {
+            if received.elapsed() < max_age {
+                backlogs.entry(*worker_id).or_default().push_back(i);
+            }
+        }
+        while let Some(queue) = backlogs
+            .values_mut()
+            .filter(|x| !x.is_empty())
+            .max_by_key(|x| (x.len(), std::cmp::Reverse(x[0])))
+        {
+            order.extend(queue.pop_front());
+        }
+        order
+    }
+
     async fn make_header(&mut self) {
+        // Take the digests fitting in the header (at least one); the others wait for the next header.
+        let mut size = 0;
+        let mut selected = Vec::new();
+        for i in self.preference_order() {
+            let digest = &self.digests[i].0;
+            let full = size + digest.size() > self.header_size
+                || self
+                    .max_header_payload
+                    .is_some_and(|max| selected.len() >= max);
+            if !selected.is_empty() && full {
+                break;
+            }
+            size += digest.size();
+            selected.push(i);
+        }
+        self.payload_size -= size;
+
+        // Keep the other digests (in the order we received them).
+        selected.sort_unstable();
+        let mut payload = Vec::with_capacity(selected.len());
+        let mut remaining = Vec::with_capacity(self.digests.len() - selected.len());
+        let mut selected = selected.into_iter().peekable();
+        for (i, (digest, worker_id, received)) in self.digests.drain(..).enumerate() {
+            if selected.next_if_eq(&i).is_some() {
+                payload.push((digest, worker_id));
+            } else {
+                remaining.push((digest, worker_id, received));
+            }
+        }
+        self.digests = remaining;
+        let system = self
+            .system
+            .drain(..self.system.len().min(MAX_SYSTEM_MESSAGES))
+            .collect();
+
         // Make a new header.
         let header = Header::new(
             self.name,
+            self.epoch,
             self.round,
-            self.digests.drain(..).collect(),
+            payload.into_iter().collect(),
+            system,
             self.last_parents.drain(..).collect(),
             &mut self.signature_service,
         )
         .await;
         debug!("Created {:?}", header);
 
+        // Remember the batches we referenced, so that we never reference them again in this window.
+        for digest in header.payload.keys() {
+            self.referenced.insert(digest.clone(), header.round);
+        }
+
+        // Remember the payload until the header is committed (or may no longer be).
+        if !header.payload.is_empty() || !header.system.is_empty() {
+            let payload = header
+                .payload
+                .iter()
+                .map(|(x, y)| (x.clone(), *y))
+                .collect();
+            self.uncommitted
+                .insert(header.round, (payload, header.system.clone()));
+        }
+
+        // Persist the header before broadcasting it, so we never propose another header for this
+        // round after a restart.
+        let bytes = bincode::serialize(&header).expect("Failed to serialize our own header");
+        self.store.write(LAST_PROPOSED_KEY.to_vec(), bytes).await;
+
         #[cfg(feature = "benchmark")]
         for digest in header.payload.keys() {
             // NOTE: This log entry is used to compute performance.
@@ -103,11 +302,73 @@ impl Proposer {
             .expect("Failed to send header");
     }
 
+    /// Returns whether we got too far ahead of the last committed round. We never stall before the dag
+    /// holds the rounds needed to commit the next leader (its round, the round voting for it, and the
+    /// round triggering its commit), as only our headers let the consensus catch up.
+    fn stalled(&self) -> bool {
+        let max_commit_lag = match self.max_commit_lag {
+            Some(x) => x,
+            None => return false,
+        };
+        let next_leader = (self.committed_round / 2 + 1) * 2;
+        self.round > max(self.committed_round + max_commit_lag, next_leader + 3)
+    }
+
+    /// Handles the commit of a certificate. Once the consensus commits round `r`, our headers
+    /// older than `r - gc_depth` will never be committed: we re-include their payload in our next
+    /// header so that the transactions of the clients are not dropped.
+    fn process_commit(&mut self, author: PublicKey, round: Round) {
+        if author == self.name {
+            self.uncommitted.remove(&round);
+        }
+        if round <= self.committed_round {
+            return;
+        }
+        self.committed_round = round;
+
+        let gc_round = round.saturating_sub(self.gc_depth.load(Ordering::Relaxed));
+        self.referenced.retain(|_, r| *r >= gc_round);
+        let pending = self.uncommitted.split_off(&gc_round);
+        let orphaned = std::mem::replace(&mut self.uncommitted, pending);
+        for (r, (payload, system)) in orphaned {
+            debug!(
+                "Re-including {} batches' digests and {} system messages of round {}",
+                payload.len(),
+                system.len(),
+                r
+            );
+            for (digest, worker_id) in payload {
+                self.referenced.insert(digest.clone(), self.round);
+                self.payload_size += digest.size();
+                self.digests.push((digest, worker_id, Instant::now()));
+            }
+            self.system.extend(system);
+        }
+    }
+
+    /// Resumes after the last header we proposed before a restart (if any): we then wait for the
+    /// parents of its round before proposing again.
+    async fn restore(&mut self) {
+        let bytes = self
+            .store
+            .read(LAST_PROPOSED_KEY.to_vec())
+            .await
+            .expect("Failed to read our last header");
+        if let Some(bytes) = bytes {
+            let header: Header =
+                bincode::deserialize(&bytes).expect("Failed to deserialize our last header");
+            self.round = header.round;
+            self.last_parents.clear();
+        }
+    }
+
     // Main loop listening to incoming messages.
     pub async fn run(&mut self) {
+        self.restore().await;
         debug!("Dag starting at round {}", self.round);
 
-        let timer = sleep(Duration::from_millis(self.max_header_delay));
+        let mut last_header = Instant::now();
+        let timer = sleep(Duration::from_millis(self.header_delay()));
         tokio::pin!(timer);
 
         loop {
@@ -115,17 +376,34 @@ impl Proposer {
             // conditions is met:
             // 1. We have a quorum of certificates from the previous round and enough batches' digests;
             // 2. We have a quorum of certificates from the previous round and the specified maximum
-            // inter-header delay has passed.
+            // inter-header delay has passed (or the lazy delay, if we have no payload and are not
+            // the leader of the round);
+            // 3. We have a quorum of certificates from the previous round and the minimum payload (if set).
+            // In any case, we wait for the consensus if it lags too many rounds behind (if set), and we
+            // do not propose while the operator paused us (or if we only archive the dag).
             let enough_parents = !self.last_parents.is_empty();
+            let stalled = self.stalled();
             let enough_digests = self.payload_size >= self.header_size;
-            let timer_expired = timer.is_elapsed();
-            if (timer_expired || enough_digests) && enough_parents {
+            let deadline = last_header + Duration::from_millis(self.header_delay());
+            if timer.deadline() != deadline {
+                timer.as_mut().reset(deadline);
+            }
+            let timer_expired = Instant::now() >= deadline;
+            let min_digests = self
+                .min_header_size
+                .is_some_and(|min| self.payload_size >= min);
+            if (timer_expired || enough_digests || min_digests)
+                && enough_parents
+                && !stalled
+                && !self.paused
+                && !self.archival
+            {
                 // Make a new header.
                 self.make_header().await;
-                self.payload_size = 0;
 
                 // Reschedule the timer.
-                let deadline = Instant::now() + Duration::from_millis(self.max_header_delay);
+                last_header = Instant::now();
+                let deadline = last_header + Duration::from_millis(self.header_delay());
                 timer.as_mut().reset(deadline);
             }
 
@@ -143,8 +421,23 @@ impl Proposer {
                     self.last_parents = parents;
                 }
                 Some((digest, worker_id)) = self.rx_workers.recv() => {
+                    if self.referenced.contains_key(&digest) {
+                        debug!("Ignoring batch {} already referenced by a recent header", digest);
+                        continue;
+                    }
+                    self.referenced.insert(digest.clone(), self.round);
                     self.payload_size += digest.size();
-                    self.digests.push((digest, worker_id));
+                    self.digests.push((digest, worker_id, Instant::now()));
+                }
+                Some(message) = self.rx_system.recv() => {
+                    self.system.push(message);
+                }
+                Some((author, round)) = self.rx_committed.recv() => {
+                    self.process_commit(author, round);
+                }
+                Some(command) = self.rx_commands.recv() => {
+                    self.paused = matches!(command, ProposerCommand::Pause);
+                    info!("Header creation {}", if self.paused { "paused" } else { "resumed" });
                 }
                 () = &mut timer => {
                     // Nothing to do.
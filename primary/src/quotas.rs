@@ -0,0 +1,118 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::budget::TokenBucket;
+use crate::metrics::PrimaryMetrics;
+use config::{Committee, PeerQuotas};
+use crypto::PublicKey;
+use log::warn;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/quotas_tests.rs"]
+pub mod quotas_tests;
+
+/// The classes of inbound messages subject to a per-authority quota.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QuotaClass {
+    Header,
+    Vote,
+    SyncRequest,
+}
+
+impl fmt::Display for QuotaClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Self::Header => write!(f, "headers"),
+            Self::Vote => write!(f, "votes"),
+            Self::SyncRequest => write!(f, "sync requests"),
+        }
+    }
+}
+
+/// Enforces the inbound quotas of each authority, so that a single faulty peer cannot flood our
+/// channels and starve the honest traffic. Authorities exceeding one of their quotas are banned
+/// (all their messages are dropped) for a while. The receiver charges the authority the connection
+/// authenticated to the shared limiter; the connections authenticating no authority get a limiter
+/// of their own (see `for_connection`), charging the authority claimed by the messages (only
+/// authenticated later on), so that a peer forging the identity of another authority only
+/// exhausts the quotas of its own connection.
+pub struct PeerLimiter {
+    /// The time during which we drop all messages of a banned authority.
+    ban_duration: Duration,
+    /// Whether we report the banned authorities in the metrics (the limiters of single
+    /// connections do not).
+    report_bans: bool,
+    /// The quota of each authority for each class of messages.
+    buckets: HashMap<(PublicKey, QuotaClass), TokenBucket>,
+    /// The authorities currently banned, along with the end of their ban.
+    banned: HashMap<PublicKey, Instant>,
+    /// The metrics of the primary.
+    metrics: PrimaryMetrics,
+}
+
+impl PeerLimiter {
+    pub fn new(committee: &Committee, quotas: PeerQuotas, metrics: PrimaryMetrics) -> Self {
+        let mut buckets = HashMap::new();
+        for name in committee.authorities.keys() {
+            buckets.insert(
+                (*name, QuotaClass::Header),
+                TokenBucket::new(quotas.headers),
+            );
+            buckets.insert((*name, QuotaClass::Vote), TokenBucket::new(quotas.votes));
+            buckets.insert(
+                (*name, QuotaClass::SyncRequest),
+                TokenBucket::new(quotas.sync_requests),
+            );
+        }
+        Self {
+            ban_duration: Duration::from_millis(quotas.ban_duration),
+            report_bans: true,
+            buckets,
+            banned: HashMap::new(),
+            metrics,
+        }
+    }
+
+    /// Returns the limiter of a single connection authenticating no authority.
+    pub fn for_connection(
+        committee: &Committee,
+        quotas: PeerQuotas,
+        metrics: PrimaryMetrics,
+    ) -> Self {
+        Self {
+            report_bans: false,
+            ..Self::new(committee, quotas, metrics)
+        }
+    }
+
+    /// Returns `true` if we accept one more message of the specified class from the authority.
+    /// Messages claiming to come from unknown authorities are never accepted.
+    pub fn try_acquire(&mut self, peer: &PublicKey, class: QuotaClass) -> bool {
+        let now = Instant::now();
+        self.banned.retain(|_, until| *until > now);
+
+        let accepted = !self.banned.contains_key(peer)
+            && match self
+                .buckets
+                .get_mut(&(*peer, class))
+                .map(|x| x.try_acquire())
+            {
+                Some(true) => true,
+                Some(false) => {
+                    warn!("{} exceeded its quota of {}: banning it", peer, class);
+                    self.banned.insert(*peer, now + self.ban_duration);
+                    false
+                }
+                None => false,
+            };
+
+        if !accepted {
+            self.metrics.throttled_messages.inc();
+        }
+        if self.report_bans {
+            self.metrics.banned_peers.set(self.banned.len() as i64);
+        }
+        accepted
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::metrics::PrimaryMetrics;
+use crypto::Digest;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto as _;
+
+#[cfg(test)]
+#[path = "tests/replay_cache_tests.rs"]
+pub mod replay_cache_tests;
+
+/// The default number of messages remembered by the `ReplayCache`.
+pub const DEFAULT_REPLAY_CACHE_SIZE: usize = 10_000;
+
+/// Remembers the (serialized) headers, votes, and certificates we recently received, so that the
+/// replays and re-broadcasts of a message are dropped before reaching the `Core`. Messages are
+/// identified by the hash of their bytes (rather than the digest they claim) so that a forged
+/// message cannot shadow a valid one. When full, the cache forgets the least recently seen message.
+/// It catches the replays over new connections, which the replay window of the network receiver
+/// (only covering the frames of a single connection) lets through; both count the replays. The
+/// certificates served in reply to our sync requests are not checked, since we may request a
+/// certificate we dropped earlier.
+pub struct ReplayCache {
+    /// The maximum number of messages we remember.
+    capacity: usize,
+    /// The messages we remember, along with the last time we saw them.
+    seen: HashMap<Digest, u64>,
+    /// The messages we remember, ordered by the last time we saw them.
+    recency: BTreeMap<u64, Digest>,
+    /// A logical clock, ticking at every message.
+    clock: u64,
+    /// The metrics of the primary.
+    metrics: PrimaryMetrics,
+}
+
+impl ReplayCache {
+    pub fn new(capacity: usize, metrics: PrimaryMetrics) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            seen: HashMap::with_capacity(capacity),
+            recency: BTreeMap::new(),
+            clock: 0,
+            metrics,
+        }
+    }
+
+    /// Returns `true` if we did not see the message recently.
+    pub fn check(&mut self, message: &[u8]) -> bool {
+        let digest = Digest(Sha512::digest(message)[..32].try_into().unwrap());
+        self.clock += 1;
+
+        if let Some(last_seen) = self.seen.insert(digest.clone(), self.clock) {
+            self.recency.remove(&last_seen);
+            self.recency.insert(self.clock, digest);
+            self.metrics.replayed_messages.inc();
+            return false;
+        }
+
+        self.recency.insert(self.clock, digest);
+        if self.seen.len() > self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
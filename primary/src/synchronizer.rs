@@ -1,14 +1,56 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::error::DagResult;
+use crate::error::{DagError, DagResult};
 use crate::header_waiter::WaiterMessage;
 use crate::messages::{Certificate, Header};
+use crate::primary::Round;
 use config::Committee;
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::Sender;
 
+#[cfg(test)]
+#[path = "tests/synchronizer_tests.rs"]
+pub mod synchronizer_tests;
+
+/// Counts the missing dependencies (parents or batches) a waiter is waiting for. It is shared between
+/// the `Synchronizer` (that reserves room before issuing sync requests) and the waiter (that releases it
+/// once the dependencies are delivered or cleaned up).
+#[derive(Clone)]
+pub struct SyncObligations {
+    /// The number of missing dependencies we are currently waiting for.
+    pending: Arc<AtomicUsize>,
+    /// The maximum number of missing dependencies we are willing to wait for.
+    limit: usize,
+}
+
+impl SyncObligations {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            pending: Arc::new(AtomicUsize::new(0)),
+            limit,
+        }
+    }
+
+    /// Reserves room for `n` more obligations. Returns `false` (and reserves nothing) if that would
+    /// exceed the limit.
+    pub fn try_acquire(&self, n: usize) -> bool {
+        self.pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
+                Some(x + n).filter(|x| *x <= self.limit)
+            })
+            .is_ok()
+    }
+
+    /// Releases `n` obligations.
+    pub fn release(&self, n: usize) {
+        self.pending.fetch_sub(n, Ordering::SeqCst);
+    }
+}
+
 /// The `Synchronizer` checks if we have all batches and parents referenced by a header. If we don't, it sends
 /// a command to the `Waiter` to request the missing data.
 pub struct Synchronizer {
@@ -20,6 +62,10 @@ pub struct Synchronizer {
     tx_header_waiter: Sender<WaiterMessage>,
     /// Send commands to the `CertificateWaiter`.
     tx_certificate_waiter: Sender<Certificate>,
+    /// The missing dependencies the `HeaderWaiter` is waiting for.
+    header_obligations: SyncObligations,
+    /// The missing dependencies the `CertificateWaiter` is waiting for.
+    certificate_obligations: SyncObligations,
     /// The genesis and its digests.
     genesis: Vec<(Digest, Certificate)>,
 }
@@ -31,12 +77,16 @@ impl Synchronizer {
         store: Store,
         tx_header_waiter: Sender<WaiterMessage>,
         tx_certificate_waiter: Sender<Certificate>,
+        header_obligations: SyncObligations,
+        certificate_obligations: SyncObligations,
     ) -> Self {
         Self {
             name,
             store,
             tx_header_waiter,
             tx_certificate_waiter,
+            header_obligations,
+            certificate_obligations,
             genesis: Certificate::genesis(committee)
                 .into_iter()
                 .map(|x| (x.digest(), x))
@@ -76,6 +126,10 @@ impl Synchronizer {
             return Ok(false);
         }
 
+        ensure!(
+            self.header_obligations.try_acquire(missing.len()),
+            DagError::TooManySyncObligations(header.id.clone())
+        );
         self.tx_header_waiter
             .send(WaiterMessage::SyncBatches(missing, header.clone()))
             .await
@@ -83,6 +137,15 @@ impl Synchronizer {
         Ok(true)
     }
 
+    /// Asks our workers (through the `HeaderWaiter`) to confirm they store the payload of the header.
+    /// The header is looped back to the core once they do.
+    pub async fn confirm_payload(&mut self, header: &Header) {
+        self.tx_header_waiter
+            .send(WaiterMessage::ConfirmPayload(header.clone()))
+            .await
+            .expect("Failed to send payload confirmation request");
+    }
+
     /// Returns the parents of a header if we have them all. If at least one parent is missing,
     /// we return an empty vector, synchronize with other nodes, and re-schedule processing
     /// of the header for when we will have all the parents.
@@ -110,6 +173,10 @@ impl Synchronizer {
             return Ok(parents);
         }
 
+        ensure!(
+            self.header_obligations.try_acquire(missing.len()),
+            DagError::TooManySyncObligations(header.id.clone())
+        );
         self.tx_header_waiter
             .send(WaiterMessage::SyncParents(missing, header.clone()))
             .await
@@ -118,14 +185,28 @@ impl Synchronizer {
     }
 
     /// Check whether we have all the ancestors of the certificate. If we don't, send the certificate to
-    /// the `CertificateWaiter` which will trigger re-processing once we have all the missing data.
-    pub async fn deliver_certificate(&mut self, certificate: &Certificate) -> DagResult<bool> {
+    /// the `CertificateWaiter` which will trigger re-processing once we have all the missing data. The
+    /// parents of the certificates of `gc_round` (or older) are garbage collected: we do not wait for them.
+    pub async fn deliver_certificate(
+        &mut self,
+        certificate: &Certificate,
+        gc_round: Round,
+    ) -> DagResult<bool> {
+        if certificate.round() <= gc_round {
+            return Ok(true);
+        }
+
         for digest in &certificate.header.parents {
             if self.genesis.iter().any(|(x, _)| x == digest) {
                 continue;
             }
 
             if self.store.read(digest.to_vec()).await?.is_none() {
+                ensure!(
+                    self.certificate_obligations
+                        .try_acquire(certificate.header.parents.len()),
+                    DagError::TooManySyncObligations(certificate.digest())
+                );
                 self.tx_certificate_waiter
                     .send(certificate.clone())
                     .await
@@ -0,0 +1,96 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{header, keys};
+
+#[tokio::test]
+async fn sign_claimed_origin() {
+    let (name, secret) = keys().pop().unwrap();
+    let mut authenticator = Authenticator::new(SignatureService::new(secret));
+
+    // Sign a request claiming to come from us.
+    let message = PrimaryMessage::CertificatesRequest(vec![Digest([1; 32])], name);
+    let bytes = authenticator.serialize(&message).await;
+
+    // Ensure the request opens with our signature.
+    match bincode::deserialize(&bytes).unwrap() {
+        PrimaryMessage::Signed(bytes, nonce, signature) => {
+            let message = open(&bytes).unwrap();
+            assert!(verify(&message, &bytes, nonce, &signature).is_ok());
+            match message {
+                PrimaryMessage::CertificatesRequest(digests, origin) => {
+                    assert_eq!(digests, vec![Digest([1; 32])]);
+                    assert_eq!(origin, name);
+                }
+                x => panic!("Unexpected message: {:?}", x),
+            }
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn reject_forged_origin() {
+    let mut keys = keys();
+    let (_, secret) = keys.pop().unwrap();
+    let (victim, _) = keys.pop().unwrap();
+    let mut authenticator = Authenticator::new(SignatureService::new(secret));
+
+    // Sign a request claiming to come from another authority.
+    let message = PrimaryMessage::CertificateDigests(vec![Digest([1; 32])], victim);
+    let bytes = authenticator.serialize(&message).await;
+
+    // Ensure the signature does not authenticate the claimed origin.
+    match bincode::deserialize(&bytes).unwrap() {
+        PrimaryMessage::Signed(bytes, nonce, signature) => {
+            let message = open(&bytes).unwrap();
+            assert!(matches!(
+                verify(&message, &bytes, nonce, &signature),
+                Err(DagError::InvalidSignature(_))
+            ));
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn skip_self_signed_messages() {
+    let (_, secret) = keys().pop().unwrap();
+    let mut authenticator = Authenticator::new(SignatureService::new(secret));
+
+    // Headers carry the signature of their author: they are sent as they are.
+    let message = PrimaryMessage::Header(header());
+    let bytes = authenticator.serialize(&message).await;
+    match bincode::deserialize(&bytes).unwrap() {
+        PrimaryMessage::Header(x) => assert_eq!(x, header()),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn reject_stale_nonce() {
+    let (name, secret) = keys().pop().unwrap();
+    let mut authenticator = Authenticator::new(SignatureService::new(secret));
+    let message = PrimaryMessage::CertificatesRequest(vec![Digest([1; 32])], name);
+
+    // The nonces of our messages never repeat.
+    let first = authenticator.serialize(&message).await;
+    let second = authenticator.serialize(&message).await;
+    assert_ne!(first, second);
+
+    // Ensure the signature does not cover another nonce, and that old nonces are rejected.
+    match bincode::deserialize(&first).unwrap() {
+        PrimaryMessage::Signed(bytes, nonce, signature) => {
+            let message = open(&bytes).unwrap();
+            assert!(matches!(
+                verify(&message, &bytes, nonce + 1, &signature),
+                Err(DagError::InvalidSignature(_))
+            ));
+            let stale = nonce - MAX_NONCE_SKEW - 1;
+            assert!(matches!(
+                verify(&message, &bytes, stale, &signature),
+                Err(DagError::StaleSignedMessage(_))
+            ));
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
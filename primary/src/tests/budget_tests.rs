@@ -0,0 +1,41 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn budget_exhausted() {
+    let budget = VerificationBudget {
+        headers: 2,
+        votes: 2,
+        certificates: 2,
+        sync_responses: 2,
+    };
+    let mut limiter = VerificationLimiter::new(budget);
+
+    // Exhaust the budget of the headers.
+    assert!(limiter.try_acquire(MessageClass::Header));
+    assert!(limiter.try_acquire(MessageClass::Header));
+    assert!(!limiter.try_acquire(MessageClass::Header));
+
+    // Ensure the other classes are not affected.
+    assert!(limiter.try_acquire(MessageClass::Vote));
+    assert!(limiter.try_acquire(MessageClass::Certificate));
+    assert!(limiter.try_acquire(MessageClass::SyncResponse));
+}
+
+#[test]
+fn budget_refill() {
+    let budget = VerificationBudget {
+        headers: 1_000,
+        votes: 1,
+        certificates: 1,
+        sync_responses: 1,
+    };
+    let mut limiter = VerificationLimiter::new(budget);
+
+    // Exhaust the budget of the headers.
+    while limiter.try_acquire(MessageClass::Header) {}
+
+    // Ensure the budget is refilled over time.
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert!(limiter.try_acquire(MessageClass::Header));
+}
@@ -0,0 +1,123 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, listener};
+use futures::future::{select, try_join_all};
+use network::{QueueLimits, MAX_FRAME_SIZE};
+use std::collections::BTreeSet;
+use std::fs;
+
+fn requested(bytes: &[u8]) -> Vec<Digest> {
+    match bincode::deserialize(bytes).unwrap() {
+        PrimaryMessage::CertificatesRequest(digests, _) => digests,
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn fetch_in_parallel() {
+    let mut keys = keys();
+    let (name, _) = keys.remove(0);
+    let committee = committee_with_base_port(15_000);
+
+    // Create a new test store.
+    let path = ".db_test_fetch_in_parallel";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a listener for each of our peers.
+    let handles: Vec<_> = keys
+        .iter()
+        .map(|(peer, _)| listener(committee.primary(peer).unwrap().primary_to_primary))
+        .collect();
+
+    // Spawn the fetcher, allowing a single pending certificate per peer.
+    let (tx_header_waiter, rx_header_waiter) = channel(1);
+    let (_tx_pages, rx_pages) = channel(1);
+    CertificateFetcher::spawn(
+        name,
+        committee,
+        store,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* timeout */ 10_000,
+        FetchLimits {
+            rate_limit: 1_000,
+            max_in_flight: 1,
+        },
+        rx_header_waiter,
+        rx_pages,
+        Authenticator::disabled(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Request three certificates (twice, to ensure the requests are deduplicated).
+    let digests: Vec<_> = (0..3).map(|i| Digest([i; 32])).collect();
+    let author = keys[0].0;
+    for _ in 0..2 {
+        tx_header_waiter
+            .send((digests.clone(), author, 1))
+            .await
+            .unwrap();
+    }
+
+    // Ensure each peer is asked for a different certificate.
+    let received = try_join_all(handles).await.unwrap();
+    let requested: BTreeSet<_> = received.iter().flat_map(|x| requested(x)).collect();
+    assert_eq!(requested, digests.into_iter().collect());
+}
+
+#[tokio::test]
+async fn fetch_rotates_on_timeout() {
+    let mut keys = keys();
+    let (name, _) = keys.remove(0);
+    let committee = committee_with_base_port(15_500);
+
+    // Create a new test store.
+    let path = ".db_test_fetch_rotates_on_timeout";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a listener for each of our peers.
+    let mut handles: Vec<_> = keys
+        .iter()
+        .map(|(peer, _)| listener(committee.primary(peer).unwrap().primary_to_primary))
+        .collect();
+
+    // Spawn the fetcher.
+    let (tx_header_waiter, rx_header_waiter) = channel(1);
+    let (_tx_pages, rx_pages) = channel(1);
+    CertificateFetcher::spawn(
+        name,
+        committee,
+        store,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* timeout */ 200,
+        FetchLimits::default(),
+        rx_header_waiter,
+        rx_pages,
+        Authenticator::disabled(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Request a certificate: the fetcher starts with the author of the header.
+    let digest = Digest([1; 32]);
+    tx_header_waiter
+        .send((vec![digest.clone()], keys[0].0, 1))
+        .await
+        .unwrap();
+    let received = handles.remove(0).await.unwrap();
+    assert_eq!(requested(&received), vec![digest.clone()]);
+
+    // Ensure the request is sent to another peer once it times out.
+    let (received, _) = select(handles.remove(0), handles.remove(0))
+        .await
+        .factor_first();
+    assert_eq!(requested(&received.unwrap()), vec![digest]);
+}
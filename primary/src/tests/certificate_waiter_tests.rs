@@ -0,0 +1,64 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header};
+use crate::messages::Header;
+use std::fs;
+use tokio::time::{timeout, Duration};
+
+#[tokio::test]
+async fn cancel_after_gc() {
+    let (tx_synchronizer, rx_synchronizer) = channel(3);
+    let (tx_core, mut rx_core) = channel(3);
+
+    // Create a new test store.
+    let path = ".db_test_cancel_after_gc";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the certificate waiter.
+    let consensus_round = Arc::new(AtomicU64::new(0));
+    let obligations = SyncObligations::new(3);
+    let metrics = PrimaryMetrics::default();
+    CertificateWaiter::spawn(
+        store,
+        consensus_round.clone(),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        obligations.clone(),
+        rx_synchronizer,
+        tx_core,
+        metrics.clone(),
+    );
+
+    // Make the waiter wait for the missing parents of certificates of rounds 1, 2 and 5.
+    let certificates: Vec<_> = [1, 2, 5]
+        .iter()
+        .map(|round| {
+            certificate(&Header {
+                round: *round,
+                parents: [Digest([*round as u8; 32])].iter().cloned().collect(),
+                ..header()
+            })
+        })
+        .collect();
+    for certificate in certificates.iter().take(2) {
+        assert!(obligations.try_acquire(1));
+        tx_synchronizer.send(certificate.clone()).await.unwrap();
+    }
+
+    // Move the garbage collector to round 2, and wake up the waiter.
+    consensus_round.store(52, Ordering::Relaxed);
+    assert!(obligations.try_acquire(1));
+    tx_synchronizer.send(certificates[2].clone()).await.unwrap();
+
+    // Ensure the certificate of the gc round is re-synced, and the older one is dropped.
+    let received = rx_core.recv().await.unwrap();
+    assert_eq!(received.digest(), certificates[1].digest());
+    assert!(timeout(Duration::from_millis(200), rx_core.recv())
+        .await
+        .is_err());
+    assert_eq!(metrics.cancelled_waits.get(), 2);
+
+    // Ensure their obligations are released.
+    assert!(obligations.try_acquire(2));
+    assert!(!obligations.try_acquire(1));
+}
@@ -1,11 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::messages::{Certificate, Header, Vote};
 use bytes::Bytes;
-use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
+use config::{
+    Authority, BatchCompression, BatchDissemination, Committee, PrimaryAddresses, WorkerAddresses,
+};
 use crypto::Hash as _;
-use crypto::{generate_keypair, PublicKey, SecretKey, Signature};
+use crypto::{generate_keypair, BlsSecretKey, PublicKey, SecretKey, Signature};
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
+use network::{accept_handshake, Features};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use std::net::SocketAddr;
@@ -34,10 +37,11 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn committee() -> Committee {
     Committee {
+        epoch: 0,
         authorities: keys()
             .iter()
             .enumerate()
-            .map(|(i, (id, _))| {
+            .map(|(i, (id, secret))| {
                 let primary = PrimaryAddresses {
                     primary_to_primary: format!("127.0.0.1:{}", 100 + i).parse().unwrap(),
                     worker_to_primary: format!("127.0.0.1:{}", 200 + i).parse().unwrap(),
@@ -48,6 +52,8 @@ pub fn committee() -> Committee {
                         primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
                         transactions: format!("127.0.0.1:{}", 400 + i).parse().unwrap(),
                         worker_to_worker: format!("127.0.0.1:{}", 500 + i).parse().unwrap(),
+                        receipts: None,
+                        ingress: Vec::new(),
                     },
                 )]
                 .iter()
@@ -59,10 +65,16 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        bls_key: Some(BlsSecretKey::derive(secret).public()),
+                        bls_proof: Some(BlsSecretKey::derive(secret).prove_possession()),
                     },
                 )
             })
             .collect(),
+        batch_compression: BatchCompression::default(),
+        batch_dissemination: BatchDissemination::default(),
+        max_batch_size: None,
+        encryption_key: None,
     }
 }
 
@@ -145,6 +157,7 @@ pub fn votes(header: &Header) -> Vec<Vote> {
                 origin: header.author,
                 author,
                 signature: Signature::default(),
+                bls_signature: None,
             };
             Vote {
                 signature: Signature::new(&vote.digest(), &secret),
@@ -162,6 +175,7 @@ pub fn certificate(header: &Header) -> Certificate {
             .into_iter()
             .map(|x| (x.author, x.signature))
             .collect(),
+        aggregate: None,
     }
 }
 
@@ -169,8 +183,11 @@ pub fn certificate(header: &Header) -> Certificate {
 pub fn listener(address: SocketAddr) -> JoinHandle<Bytes> {
     tokio::spawn(async move {
         let listener = TcpListener::bind(&address).await.unwrap();
-        let (socket, _) = listener.accept().await.unwrap();
-        let transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let (socket, peer) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        accept_handshake(&mut transport, peer, Features::NONE)
+            .await
+            .unwrap();
         let (mut writer, mut reader) = transport.split();
         match reader.next().await {
             Some(Ok(received)) => {
@@ -181,3 +198,22 @@ pub fn listener(address: SocketAddr) -> JoinHandle<Bytes> {
         }
     })
 }
+
+// Fixture
+pub fn listener_many(address: SocketAddr, n: usize) -> JoinHandle<Vec<Bytes>> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (socket, peer) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        accept_handshake(&mut transport, peer, Features::NONE)
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        while received.len() < n {
+            let message = transport.next().await.unwrap().unwrap();
+            transport.send(Bytes::from("Ack")).await.unwrap();
+            received.push(message.freeze());
+        }
+        received
+    })
+}
@@ -3,7 +3,12 @@ use super::*;
 use crate::common::{
     certificate, committee, committee_with_base_port, header, headers, keys, listener, votes,
 };
+use crate::header_waiter::WaiterMessage;
+use crate::synchronizer::SyncObligations;
+use crypto::Signature;
 use futures::future::try_join_all;
+use network::MAX_FRAME_SIZE;
+use std::collections::BTreeSet;
 use std::fs;
 use tokio::sync::mpsc::channel;
 
@@ -24,6 +29,7 @@ async fn process_header() {
     let (_tx_headers, rx_headers) = channel(1);
     let (tx_consensus, _rx_consensus) = channel(1);
     let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
 
     // Create a new test store.
     let path = ".db_test_process_header";
@@ -47,23 +53,43 @@ async fn process_header() {
         store.clone(),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
     );
 
     // Spawn the core.
     Core::spawn(
         name,
-        committee,
+        committee.clone(),
         store.clone(),
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
     );
 
     // Send a header to the core.
@@ -79,6 +105,11 @@ async fn process_header() {
         x => panic!("Unexpected message: {:?}", x),
     }
 
+    // Ensure our vote is persisted.
+    let key = Core::last_voted_key(&header().author);
+    let stored = store.read(key).await.unwrap();
+    assert_eq!(stored, Some(1u64.to_le_bytes().to_vec()));
+
     // Ensure the header is correctly stored.
     let stored = store
         .read(header().id.to_vec())
@@ -88,6 +119,124 @@ async fn process_header() {
     assert_eq!(stored, Some(header()));
 }
 
+#[tokio::test]
+async fn process_header_confirmed_payload() {
+    let mut keys = keys();
+    let (author, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(16_500);
+
+    let (tx_sync_headers, mut rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_process_header_confirmed_payload";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make a header with a payload that our worker 0 reported.
+    let digest = Digest([1; 32]);
+    let header = Header {
+        payload: [(digest.clone(), 0)].iter().cloned().collect(),
+        ..header()
+    };
+    let header = Header {
+        id: header.digest(),
+        signature: Signature::new(&header.digest(), &author_secret),
+        ..header
+    };
+    let key = [digest.as_ref(), &0u32.to_le_bytes()].concat();
+    store.write(key, Vec::default()).await;
+
+    // Spawn a listener to receive the vote.
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core.
+    let expected = Vote::new(&header, &name, &mut signature_service).await;
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability {
+            check: PayloadCheck::Confirmed,
+            timeout: 1_000,
+        },
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send the header to the core.
+    tx_primary_messages
+        .send(PrimaryMessage::Header(header.clone()))
+        .await
+        .unwrap();
+
+    // Ensure the core asks the header waiter to confirm the payload with our workers.
+    match rx_sync_headers.recv().await {
+        Some(WaiterMessage::ConfirmPayload(x)) => assert_eq!(x.id, header.id),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+
+    // Loop the header back once our workers confirmed its payload.
+    tx_headers_loopback
+        .send(Loopback::Confirmed(header))
+        .await
+        .unwrap();
+
+    // Ensure the core voted for the header.
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::Vote(x) => assert_eq!(x, expected),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
 #[tokio::test]
 async fn process_header_missing_parent() {
     let (name, secret) = keys().pop().unwrap();
@@ -101,6 +250,7 @@ async fn process_header_missing_parent() {
     let (_tx_headers, rx_headers) = channel(1);
     let (tx_consensus, _rx_consensus) = channel(1);
     let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
 
     // Create a new test store.
     let path = ".db_test_process_header_missing_parent";
@@ -114,6 +264,8 @@ async fn process_header_missing_parent() {
         store.clone(),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
     );
 
     // Spawn the core.
@@ -124,13 +276,31 @@ async fn process_header_missing_parent() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
     );
 
     // Send a header to the core.
@@ -148,6 +318,218 @@ async fn process_header_missing_parent() {
     assert!(store.read(id.to_vec()).await.unwrap().is_none());
 }
 
+#[tokio::test]
+async fn process_equivocating_header() {
+    let mut keys = keys();
+    let (author, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(14_000);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, mut rx_equivocations) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_process_equivocating_header";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a listener to receive any vote.
+    let address = committee.primary(&author).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store,
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send a first header (suspended on a missing parent), then a conflicting one.
+    let first = Header {
+        parents: [Digest::default()].iter().cloned().collect(),
+        ..header()
+    };
+    let first = Header {
+        id: first.digest(),
+        signature: Signature::new(&first.digest(), &author_secret),
+        ..first
+    };
+    for header in [first.clone(), header()] {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core outputs verifiable evidence of the equivocation.
+    let equivocation = rx_equivocations.recv().await.unwrap();
+    assert_eq!(equivocation.author(), author);
+    assert_eq!(equivocation.first.id, first.id);
+    assert_eq!(equivocation.second.id, header().id);
+    assert_eq!(equivocation.reporter, name);
+    assert!(equivocation.verify(&committee).is_ok());
+
+    // Send a third conflicting header.
+    let third = Header {
+        timestamp: 1,
+        ..header()
+    };
+    let third = Header {
+        id: third.digest(),
+        signature: Signature::new(&third.digest(), &author_secret),
+        ..third
+    };
+    tx_primary_messages
+        .send(PrimaryMessage::Header(third))
+        .await
+        .unwrap();
+
+    // Ensure the core does not vote for the other headers, nor reports the author again.
+    let vote = tokio::time::timeout(std::time::Duration::from_millis(200), handle).await;
+    assert!(vote.is_err());
+    assert!(rx_equivocations.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn process_header_after_restart() {
+    let mut keys = keys();
+    let _ = keys.pop().unwrap(); // Skip the header' author.
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(14_500);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+
+    // Create a new test store recording that we already voted for the author at round 1.
+    let path = ".db_test_process_header_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let key = Core::last_voted_key(&header().author);
+    store.write(key, 1u64.to_le_bytes().to_vec()).await;
+
+    // Spawn a listener to receive any vote.
+    let address = committee
+        .primary(&header().author)
+        .unwrap()
+        .primary_to_primary;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send a header to the core.
+    tx_primary_messages
+        .send(PrimaryMessage::Header(header()))
+        .await
+        .unwrap();
+
+    // Ensure the core stores the header but does not vote for it a second time.
+    let vote = tokio::time::timeout(std::time::Duration::from_millis(200), handle).await;
+    assert!(vote.is_err());
+    let stored = store.read(header().id.to_vec()).await.unwrap();
+    assert!(stored.is_some());
+}
+
 #[tokio::test]
 async fn process_header_missing_payload() {
     let (name, secret) = keys().pop().unwrap();
@@ -161,6 +543,7 @@ async fn process_header_missing_payload() {
     let (_tx_headers, rx_headers) = channel(1);
     let (tx_consensus, _rx_consensus) = channel(1);
     let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
 
     // Create a new test store.
     let path = ".db_test_process_header_missing_payload";
@@ -174,6 +557,8 @@ async fn process_header_missing_payload() {
         store.clone(),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
     );
 
     // Spawn the core.
@@ -184,13 +569,31 @@ async fn process_header_missing_payload() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
     );
 
     // Send a header to the core.
@@ -223,6 +626,7 @@ async fn process_votes() {
     let (_tx_headers, rx_headers) = channel(1);
     let (tx_consensus, _rx_consensus) = channel(1);
     let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
 
     // Create a new test store.
     let path = ".db_test_process_vote";
@@ -236,6 +640,8 @@ async fn process_votes() {
         store.clone(),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
     );
 
     // Spawn the core.
@@ -246,13 +652,31 @@ async fn process_votes() {
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
     );
 
     // Make the certificate we expect to receive.
@@ -283,79 +707,710 @@ async fn process_votes() {
 }
 
 #[tokio::test]
-async fn process_certificates() {
+async fn process_batched_votes() {
     let (name, secret) = keys().pop().unwrap();
     let signature_service = SignatureService::new(secret);
 
+    let committee = committee_with_base_port(18_000);
+
     let (tx_sync_headers, _rx_sync_headers) = channel(1);
     let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
-    let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
     let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
     let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
     let (_tx_headers, rx_headers) = channel(1);
-    let (tx_consensus, mut rx_consensus) = channel(3);
-    let (tx_parents, mut rx_parents) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
 
     // Create a new test store.
-    let path = ".db_test_process_certificates";
+    let path = ".db_test_process_batched_votes";
     let _ = fs::remove_dir_all(path);
-    let mut store = Store::new(path).unwrap();
+    let store = Store::new(path).unwrap();
 
     // Make a synchronizer for the core.
     let synchronizer = Synchronizer::new(
         name,
-        &committee(),
+        &committee,
         store.clone(),
         /* tx_header_waiter */ tx_sync_headers,
         /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
     );
 
     // Spawn the core.
     Core::spawn(
         name,
-        committee(),
+        committee.clone(),
         store.clone(),
         synchronizer,
         signature_service,
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
-        /* gc_depth */ 50,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
         /* rx_primaries */ rx_primary_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
         tx_consensus,
         /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
     );
 
-    // Send enough certificates to the core.
-    let certificates: Vec<_> = headers()
+    // Make the certificate we expect to receive.
+    let expected = certificate(&Header::default());
+
+    // Spawn all listeners to receive our newly formed certificate.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
         .iter()
-        .take(3)
-        .map(|header| certificate(header))
+        .map(|(_, address)| listener(address.primary_to_primary))
         .collect();
 
-    for x in certificates.clone() {
+    // Send each vote bundled with a vote on the header of another authority.
+    for vote in votes(&Header::default()) {
+        let other = votes(&headers()[0]).pop().unwrap();
         tx_primary_messages
-            .send(PrimaryMessage::Certificate(x))
+            .send(PrimaryMessage::Votes(vec![other, vote]))
             .await
             .unwrap();
     }
 
-    // Ensure the core sends the parents of the certificates to the proposer.
-    let received = rx_parents.recv().await.unwrap();
-    let parents = certificates.iter().map(|x| x.digest()).collect();
-    assert_eq!(received, (parents, 1));
-
-    // Ensure the core sends the certificates to the consensus.
-    for x in certificates.clone() {
-        let received = rx_consensus.recv().await.unwrap();
-        assert_eq!(received, x);
+    // Ensure all listeners got the certificate.
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Certificate(x) => assert_eq!(x, expected),
+            x => panic!("Unexpected message: {:?}", x),
+        }
     }
+}
 
-    // Ensure the certificates are stored.
-    for x in &certificates {
-        let stored = store.read(x.digest().to_vec()).await.unwrap();
-        let serialized = bincode::serialize(x).unwrap();
-        assert_eq!(stored, Some(serialized));
+#[tokio::test]
+async fn process_certificates() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(3);
+    let (tx_parents, mut rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_process_certificates";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send enough certificates to the core.
+    let certificates: Vec<_> = headers()
+        .iter()
+        .take(3)
+        .map(|header| certificate(header))
+        .collect();
+
+    for x in certificates.clone() {
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(x))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core sends the parents of the certificates to the proposer.
+    let received = rx_parents.recv().await.unwrap();
+    let parents = certificates.iter().map(|x| x.digest()).collect();
+    assert_eq!(received, (parents, 1));
+
+    // Ensure the core sends the certificates to the consensus.
+    for x in certificates.clone() {
+        let received = rx_consensus.recv().await.unwrap();
+        assert_eq!(received, x);
+    }
+
+    // Ensure the certificates are stored and indexed by round.
+    for x in &certificates {
+        let stored = store.read(x.digest().to_vec()).await.unwrap();
+        let serialized = bincode::serialize(x).unwrap();
+        assert_eq!(stored, Some(serialized));
+
+        let key = Certificate::round_key(x.round(), &x.origin());
+        let indexed = store.read(key).await.unwrap();
+        assert_eq!(indexed, Some(x.digest().to_vec()));
+    }
+}
+
+#[tokio::test]
+async fn archive_header() {
+    let mut keys = keys();
+    let _ = keys.pop().unwrap(); // Skip the header' author.
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(18_300);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_archive_header";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn an archival core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ true,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send a header to the core.
+    tx_primary_messages
+        .send(PrimaryMessage::Header(header()))
+        .await
+        .unwrap();
+
+    // Ensure the header is stored.
+    let stored = store.notify_read(header().id.to_vec()).await.unwrap();
+    assert_eq!(bincode::deserialize::<Header>(&stored).unwrap(), header());
+
+    // Ensure we did not vote for it.
+    let key = Core::last_voted_key(&header().author);
+    assert_eq!(store.read(key).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn report_header_progress() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(18_400);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+    let (tx_progress, mut rx_progress) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_report_header_progress";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        Some(tx_progress),
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send a quorum of votes to the core.
+    let votes = votes(&Header::default());
+    let quorum = committee.quorum_threshold() as usize;
+    for vote in votes.iter().take(quorum).cloned() {
+        tx_primary_messages
+            .send(PrimaryMessage::Vote(vote))
+            .await
+            .unwrap();
+    }
+
+    // Ensure we get a progress report for every vote.
+    for i in 1..=quorum {
+        let progress = rx_progress.recv().await.unwrap();
+        assert_eq!(progress.stake as usize, i);
+        assert_eq!(progress.missing.len(), votes.len() - i);
+        assert_eq!(progress.certified, i == quorum);
+    }
+}
+
+#[tokio::test]
+async fn vote_for_duplicate_batch() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(18_500);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+
+    // Create a new test store holding the batch.
+    let path = ".db_test_vote_for_duplicate_batch";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let digest = Digest([1; 32]);
+    let key = [digest.as_ref(), &0u32.to_le_bytes()].concat();
+    store.write(key, Vec::default()).await;
+
+    // Make two headers of different authors referencing the same batch (and a third header
+    // without payload).
+    let mut headers = headers();
+    headers[0].payload = [(digest.clone(), 0)].iter().cloned().collect();
+    headers[1].payload = [(digest, 0)].iter().cloned().collect();
+
+    // Spawn listeners to receive our votes.
+    let handles: Vec<_> = headers
+        .iter()
+        .take(3)
+        .map(|x| listener(committee.primary(&x.author).unwrap().primary_to_primary))
+        .collect();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send the headers to the core.
+    for header in headers.iter().take(3).cloned() {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header))
+            .await
+            .unwrap();
+    }
+
+    // Ensure we vote for all headers: the duplicate is only skipped once committed (by the consensus).
+    for handle in handles {
+        match bincode::deserialize(&handle.await.unwrap()).unwrap() {
+            PrimaryMessage::Vote(_) => (),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+
+    // Ensure both headers referencing the batch are stored.
+    assert!(store.read(headers[0].id.to_vec()).await.unwrap().is_some());
+    assert!(store.read(headers[1].id.to_vec()).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn reject_oversized_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(26_500);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+
+    // Create a new test store holding the batches.
+    let path = ".db_test_reject_oversized_payload";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let digests = [Digest([1; 32]), Digest([2; 32])];
+    for digest in &digests {
+        let key = [digest.as_ref(), &0u32.to_le_bytes()].concat();
+        store.write(key, Vec::default()).await;
+    }
+
+    // Make a header referencing more batches than allowed (and another header without payload).
+    let mut headers = headers();
+    headers[0].payload = digests.iter().map(|x| (x.clone(), 0)).collect();
+
+    // Spawn a listener to receive our vote.
+    let address = committee.primary(&headers[1].author).unwrap();
+    let handle = listener(address.primary_to_primary);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core, accepting a single digest per header.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching::default(),
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ Some(1),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send the headers to the core.
+    for header in headers.iter().take(2).cloned() {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header))
+            .await
+            .unwrap();
+    }
+
+    // Ensure we only vote for the second header.
+    match bincode::deserialize(&handle.await.unwrap()).unwrap() {
+        PrimaryMessage::Vote(x) => assert_eq!(x.id, headers[1].id),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+    assert!(store.read(headers[0].id.to_vec()).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn batch_votes_per_author() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(27_500);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+    let (tx_equivocations, _rx_equivocations) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_batch_votes_per_author";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make the headers of rounds 1 and 2 of a first author, and the header of round 1 of a second
+    // author. We hold the certificates of round 1.
+    let mut parents = BTreeSet::new();
+    for header in headers() {
+        let certificate = certificate(&header);
+        let bytes = bincode::serialize(&certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        parents.insert(certificate.digest());
+    }
+    let (first, first_secret) = keys().into_iter().find(|(x, _)| *x != name).unwrap();
+    let second = headers()
+        .into_iter()
+        .find(|x| x.author != name && x.author != first)
+        .unwrap();
+    let header = Header {
+        author: first,
+        round: 2,
+        parents,
+        ..Header::default()
+    };
+    let next = Header {
+        id: header.digest(),
+        signature: Signature::new(&header.digest(), &first_secret),
+        ..header
+    };
+    let previous = headers().into_iter().find(|x| x.author == first).unwrap();
+    let headers = vec![previous.clone(), second.clone(), next.clone()];
+
+    // Spawn listeners to receive our votes.
+    let first_handle = listener(committee.primary(&first).unwrap().primary_to_primary);
+    let second_handle = listener(
+        committee
+            .primary(&second.author)
+            .unwrap()
+            .primary_to_primary,
+    );
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1_000),
+        /* certificate_obligations */ SyncObligations::new(1_000),
+    );
+
+    // Spawn the core, bundling our votes two by two.
+    Core::spawn(
+        name,
+        committee.clone(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        /* aggregate_votes */ false,
+        PayloadAvailability::default(),
+        VoteBatching {
+            max_votes: 2,
+            max_delay: 1_000,
+        },
+        TimestampBounds::default(),
+        /* vote_log */ None,
+        /* archival */ false,
+        /* quorum_alert_delay */ None,
+        /* max_header_payload */ None,
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+        tx_equivocations,
+        /* tx_gossip */ None,
+        /* tx_progress */ None,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send the headers to the core.
+    for header in headers {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the first author receives our votes on both its headers in a single message.
+    match bincode::deserialize(&first_handle.await.unwrap()).unwrap() {
+        PrimaryMessage::Votes(x) => {
+            let ids: Vec<_> = x.into_iter().map(|x| x.id).collect();
+            assert_eq!(ids, vec![previous.id, next.id]);
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+
+    // Ensure the second author receives its single vote once it waited long enough.
+    match bincode::deserialize(&second_handle.await.unwrap()).unwrap() {
+        PrimaryMessage::Votes(x) => {
+            assert_eq!(x.len(), 1);
+            assert_eq!(x[0].id, second.id);
+        }
+        x => panic!("Unexpected message: {:?}", x),
     }
 }
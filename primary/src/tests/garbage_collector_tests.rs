@@ -0,0 +1,80 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, header, keys};
+use crate::messages::Header;
+use network::{NetworkMetrics, QueueLimits, MAX_FRAME_SIZE};
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn update_gc_depth() {
+    let (name, _) = keys().pop().unwrap();
+    let consensus_round = Arc::new(AtomicU64::new(0));
+    let gc_depth = Arc::new(AtomicU64::new(50));
+
+    let (tx_consensus, rx_consensus) = channel(1);
+    let (tx_gc_depth, rx_gc_depth) = channel(1);
+    let (tx_committed, _rx_committed) = channel(10);
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        name,
+        WorkerCache::new(&committee()),
+        /* archival */ false,
+        consensus_round.clone(),
+        gc_depth.clone(),
+        rx_consensus,
+        rx_gc_depth,
+        /* tx_proposer */ tx_committed,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Commit round 1.
+    let certificate = Certificate {
+        header: header(),
+        ..Certificate::default()
+    };
+    tx_consensus.send(certificate).await.unwrap();
+    while consensus_round.load(Ordering::Relaxed) != 1 {
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    // Ensure we reject updates activating at a round we already committed.
+    let update = GcDepthUpdate {
+        depth: 100,
+        activation_round: 1,
+    };
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_gc_depth.send((update, tx_reply)).await.unwrap();
+    assert!(matches!(
+        rx_reply.await.unwrap(),
+        Err(GcDepthError::StaleActivation(1, 1))
+    ));
+
+    // Request a new depth starting from round 2.
+    let update = GcDepthUpdate {
+        depth: 100,
+        activation_round: 2,
+    };
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_gc_depth.send((update, tx_reply)).await.unwrap();
+    assert!(rx_reply.await.unwrap().is_ok());
+    assert_eq!(gc_depth.load(Ordering::Relaxed), 50);
+
+    // Commit round 2 and ensure the new depth is applied.
+    let certificate = Certificate {
+        header: Header {
+            round: 2,
+            ..header()
+        },
+        ..Certificate::default()
+    };
+    tx_consensus.send(certificate).await.unwrap();
+    while consensus_round.load(Ordering::Relaxed) != 2 {
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(gc_depth.load(Ordering::Relaxed), 100);
+}
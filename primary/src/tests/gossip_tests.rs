@@ -0,0 +1,167 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, header, keys, listener};
+use config::DiffusionMode;
+use futures::future::try_join_all;
+use network::{NetworkMetrics, QueueLimits, MAX_FRAME_SIZE};
+use std::fs;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn push_certificate() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(18_100);
+
+    // Create a new test store.
+    let path = ".db_test_push_certificate";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the gossip, pushing to all our peers.
+    let (tx_core, rx_core) = channel(1);
+    let (_tx_primaries, rx_primaries) = channel(1);
+    let diffusion = CertificateDiffusion {
+        mode: DiffusionMode::Gossip,
+        fanout: committee.size() - 1,
+        ..CertificateDiffusion::default()
+    };
+    Gossip::spawn(
+        name,
+        committee.clone(),
+        store,
+        diffusion,
+        rx_core,
+        rx_primaries,
+        Authenticator::disabled(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn all listeners to receive the certificate.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, address)| listener(address.primary_to_primary))
+        .collect();
+
+    // Send a certificate to the gossip.
+    let expected = certificate(&header());
+    tx_core.send(expected.clone()).await.unwrap();
+
+    // Ensure all listeners got the certificate.
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Certificate(x) => assert_eq!(x, expected),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+}
+
+#[tokio::test]
+async fn pull_missing_certificates() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (peer, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(18_200);
+
+    // Create a new test store holding one certificate.
+    let path = ".db_test_pull_missing_certificates";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let stored = certificate(&header());
+    let bytes = bincode::serialize(&stored).unwrap();
+    store.write(stored.digest().to_vec(), bytes).await;
+
+    // Spawn the gossip.
+    let (_tx_core, rx_core) = channel(1);
+    let (tx_primaries, rx_primaries) = channel(1);
+    Gossip::spawn(
+        name,
+        committee.clone(),
+        store,
+        CertificateDiffusion::default(),
+        rx_core,
+        rx_primaries,
+        Authenticator::disabled(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn a listener for the peer advertising its certificates.
+    let address = committee.primary(&peer).unwrap().primary_to_primary;
+    let handle = listener(address);
+
+    // Receive an advertisement of a certificate we hold and one we miss.
+    let missing = Digest([1; 32]);
+    tx_primaries
+        .send((vec![stored.digest(), missing.clone()], peer))
+        .await
+        .unwrap();
+
+    // Ensure we only request the certificate we miss.
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::CertificatesRequest(x, requestor) => {
+            assert_eq!(x, vec![missing]);
+            assert_eq!(requestor, name);
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn relay_along_mesh() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(25_000);
+
+    // Create a new test store.
+    let path = ".db_test_relay_along_mesh";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the gossip over a mesh linking each primary to two others.
+    let (tx_core, rx_core) = channel(1);
+    let (_tx_primaries, rx_primaries) = channel(1);
+    let diffusion = CertificateDiffusion {
+        mode: DiffusionMode::Mesh,
+        fanout: 2,
+        ..CertificateDiffusion::default()
+    };
+    Gossip::spawn(
+        name,
+        committee.clone(),
+        store,
+        diffusion,
+        rx_core,
+        rx_primaries,
+        Authenticator::disabled(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn listeners for our mesh neighbours.
+    let handles: Vec<_> = Mesh::new(&committee, &name, 2)
+        .neighbours()
+        .iter()
+        .map(|x| listener(committee.primary(x).unwrap().primary_to_primary))
+        .collect();
+    assert_eq!(handles.len(), 2);
+
+    // Send one of our certificates to the gossip.
+    let expected = certificate(&header());
+    tx_core.send(expected.clone()).await.unwrap();
+
+    // Ensure our neighbours got the certificate.
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Certificate(x) => assert_eq!(x, expected),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+}
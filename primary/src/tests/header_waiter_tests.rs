@@ -0,0 +1,181 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, header, keys, listener_many};
+use async_trait::async_trait;
+use network::{
+    MessageHandler, QueueLimits, Receiver as NetworkReceiver, RpcHandler, RpcServer, Writer,
+    MAX_FRAME_SIZE,
+};
+use std::error::Error;
+use std::fs;
+
+#[tokio::test]
+async fn retry_then_give_up() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(16_000);
+    let header = header();
+
+    let (tx_synchronizer, rx_synchronizer) = channel(1);
+    let (tx_core, _rx_core) = channel(1);
+    let (tx_fetcher, _rx_fetcher) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_retry_then_give_up";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a listener impersonating our worker.
+    let address = committee.worker(&name, &0).unwrap().primary_to_worker;
+    let handle = listener_many(address, 2);
+
+    // Spawn the header waiter, retrying once.
+    let obligations = SyncObligations::new(1);
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        WorkerCache::new(&committee),
+        store,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* sync_retry_delay */ 100,
+        SyncRetries {
+            max_retries: 1,
+            max_delay: 1_000,
+        },
+        obligations.clone(),
+        /* confirm_timeout */ 1_000,
+        rx_synchronizer,
+        tx_core,
+        tx_fetcher,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Ask the header waiter to sync a missing batch (reserving the obligation as the
+    // `Synchronizer` does).
+    let digest = Digest([1; 32]);
+    assert!(obligations.try_acquire(1));
+    let missing = [(digest.clone(), 0)].iter().cloned().collect();
+    let message = WaiterMessage::SyncBatches(missing, header.clone());
+    tx_synchronizer.send(message).await.unwrap();
+
+    // Ensure our worker is asked to sync with the author first, then with another peer.
+    let received = handle.await.unwrap();
+    let targets: Vec<_> = received
+        .iter()
+        .map(|x| match bincode::deserialize(x).unwrap() {
+            PrimaryWorkerMessage::Synchronize(digests, target) => {
+                assert_eq!(digests, vec![digest.clone()]);
+                target
+            }
+            x => panic!("Unexpected message: {:?}", x),
+        })
+        .collect();
+    assert_eq!(targets[0], header.author);
+    assert_ne!(targets[1], header.author);
+    assert_ne!(targets[1], name);
+
+    // Ensure the header waiter eventually gives up and releases the obligation.
+    let mut released = false;
+    for _ in 0..50 {
+        if obligations.try_acquire(1) {
+            released = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(released);
+}
+
+/// Impersonates our worker, confirming it stores all the batches of the requests of our primary
+/// (and forwarding their digests).
+#[derive(Clone)]
+struct ConfirmingWorker {
+    tx_requests: Sender<Vec<Digest>>,
+}
+
+#[async_trait]
+impl RpcHandler for ConfirmingWorker {
+    type Request = Vec<Digest>;
+    type Response = Vec<Digest>;
+
+    async fn handle(&self, digests: Vec<Digest>) -> Result<Vec<Digest>, Box<dyn Error>> {
+        self.tx_requests.send(digests).await?;
+        Ok(Vec::new())
+    }
+}
+
+#[async_trait]
+impl MessageHandler for ConfirmingWorker {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        match bincode::deserialize(&message)? {
+            PrimaryWorkerMessage::Confirm(request) => {
+                let server = RpcServer::new(self.clone());
+                server.dispatch(writer, Bytes::from(request)).await
+            }
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+}
+
+#[tokio::test]
+async fn confirm_payload() {
+    let (name, _) = keys().remove(0);
+    let committee = committee_with_base_port(26_000);
+    let digest = Digest([1; 32]);
+    let header = Header {
+        payload: [(digest.clone(), 0)].iter().cloned().collect(),
+        ..header()
+    };
+
+    let (tx_synchronizer, rx_synchronizer) = channel(1);
+    let (tx_core, mut rx_core) = channel(1);
+    let (tx_fetcher, _rx_fetcher) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_confirm_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn our worker, confirming it stores the batch.
+    let address = committee.worker(&name, &0).unwrap().primary_to_worker;
+    let (tx_requests, mut rx_requests) = channel(1);
+    NetworkReceiver::spawn(address, ConfirmingWorker { tx_requests });
+    sleep(Duration::from_millis(50)).await;
+
+    // Spawn the header waiter.
+    HeaderWaiter::spawn(
+        name,
+        committee.clone(),
+        WorkerCache::new(&committee),
+        store,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* sync_retry_delay */ 100,
+        SyncRetries::default(),
+        SyncObligations::new(1),
+        /* confirm_timeout */ 1_000,
+        rx_synchronizer,
+        tx_core,
+        tx_fetcher,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        PrimaryMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Ask the header waiter to confirm the payload of the header.
+    let message = WaiterMessage::ConfirmPayload(header.clone());
+    tx_synchronizer.send(message).await.unwrap();
+
+    // Ensure our worker is asked to confirm the batch.
+    assert_eq!(rx_requests.recv().await, Some(vec![digest]));
+
+    // Ensure the header is looped back to the core once confirmed.
+    match rx_core.recv().await {
+        Some(Loopback::Confirmed(x)) => assert_eq!(x.id, header.id),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
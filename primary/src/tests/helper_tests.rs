@@ -0,0 +1,200 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, headers, keys, listener_many};
+use crate::messages::Certificate;
+use crypto::Hash as _;
+use network::MAX_FRAME_SIZE;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn serve_by_pages() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (requestor, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(17_000);
+
+    // Create a new test store holding a few certificates.
+    let path = ".db_test_serve_by_pages";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificates: Vec<_> = headers().iter().take(3).map(certificate).collect();
+    for x in &certificates {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+    }
+    let digests: Vec<_> = certificates.iter().map(|x| x.digest()).collect();
+
+    // Spawn the helper, serving two certificates per page.
+    let (tx_primaries, rx_primaries) = channel(1);
+    Helper::spawn(
+        name,
+        committee.clone(),
+        store,
+        HelperLimits {
+            rate_limit: 1_000,
+            page_size: 2,
+        },
+        rx_primaries,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Authenticator::disabled(),
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn a listener for the requestor.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener_many(address, 4);
+
+    // Request all certificates, then the next page.
+    tx_primaries
+        .send((HelperRequest::Certificates(digests.clone()), requestor))
+        .await
+        .unwrap();
+    tx_primaries
+        .send((HelperRequest::NextPage(1), requestor))
+        .await
+        .unwrap();
+
+    // Ensure we get the first page, its token, and then the last certificate.
+    let mut received = Vec::new();
+    for bytes in handle.await.unwrap() {
+        match bincode::deserialize(&bytes).unwrap() {
+            PrimaryMessage::SyncedCertificate(x) => received.push(x.digest()),
+            PrimaryMessage::CertificatesPage(token, responder) => {
+                assert_eq!(received.len(), 2);
+                assert_eq!(token, 1);
+                assert_eq!(responder, name);
+            }
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+    assert_eq!(received, digests);
+}
+
+#[tokio::test]
+async fn serve_within_quota() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (requestor, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(17_500);
+
+    // Create a new test store holding a few certificates.
+    let path = ".db_test_serve_within_quota";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificates: Vec<Certificate> = headers().iter().take(3).map(certificate).collect();
+    for x in &certificates {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+    }
+    let digests: Vec<_> = certificates.iter().map(|x| x.digest()).collect();
+
+    // Spawn the helper, serving a single certificate per second to each peer.
+    let (tx_primaries, rx_primaries) = channel(1);
+    Helper::spawn(
+        name,
+        committee.clone(),
+        store,
+        HelperLimits {
+            rate_limit: 1,
+            page_size: 100,
+        },
+        rx_primaries,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Authenticator::disabled(),
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn a listener for the requestor.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener_many(address, 1);
+
+    // Request all certificates.
+    tx_primaries
+        .send((HelperRequest::Certificates(digests.clone()), requestor))
+        .await
+        .unwrap();
+
+    // Ensure we only get the first certificate (without a token for the others).
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received[0]).unwrap() {
+        PrimaryMessage::SyncedCertificate(x) => assert_eq!(x.digest(), digests[0]),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn resume_after_quota() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (requestor, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(27_000);
+
+    // Create a new test store holding a few certificates.
+    let path = ".db_test_resume_after_quota";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificates: Vec<Certificate> = headers().iter().take(3).map(certificate).collect();
+    for x in &certificates {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+    }
+    let digests: Vec<_> = certificates.iter().map(|x| x.digest()).collect();
+
+    // Spawn the helper, serving two certificates per second to each peer.
+    let (tx_primaries, rx_primaries) = channel(1);
+    Helper::spawn(
+        name,
+        committee.clone(),
+        store,
+        HelperLimits {
+            rate_limit: 2,
+            page_size: 100,
+        },
+        rx_primaries,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Authenticator::disabled(),
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn a listener for the requestor.
+    let address = committee.primary(&requestor).unwrap().primary_to_primary;
+    let handle = listener_many(address, 3);
+
+    // Exhaust the quota of the requestor, then request the last certificate.
+    tx_primaries
+        .send((
+            HelperRequest::Certificates(digests[..2].to_vec()),
+            requestor,
+        ))
+        .await
+        .unwrap();
+    tx_primaries
+        .send((
+            HelperRequest::Certificates(digests[2..].to_vec()),
+            requestor,
+        ))
+        .await
+        .unwrap();
+
+    // Ensure the last certificate is served once the requestor asks again with a fresh quota.
+    sleep(Duration::from_millis(1_100)).await;
+    tx_primaries
+        .send((HelperRequest::Certificates(Vec::new()), requestor))
+        .await
+        .unwrap();
+    let received = handle.await.unwrap();
+    for (bytes, digest) in received.iter().zip(&digests) {
+        match bincode::deserialize(bytes).unwrap() {
+            PrimaryMessage::SyncedCertificate(x) => assert_eq!(&x.digest(), digest),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+}
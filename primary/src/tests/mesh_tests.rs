@@ -0,0 +1,79 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::committee;
+use crypto::generate_keypair;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::collections::HashSet;
+
+// Fixture
+fn large_committee(size: usize) -> Committee {
+    let mut committee = committee();
+    let authority = committee.authorities.values().next().unwrap().clone();
+    let mut rng = StdRng::from_seed([1; 32]);
+    committee.authorities = (0..size)
+        .map(|_| (generate_keypair(&mut rng).0, authority.clone()))
+        .collect();
+    committee
+}
+
+/// Returns the primaries that receive the certificates of `origin` when relayed along the mesh.
+fn diffuse(
+    committee: &Committee,
+    origin: &PublicKey,
+    degree: usize,
+    rounds: usize,
+) -> HashSet<PublicKey> {
+    let mut reached = HashSet::new();
+    let mut pending = vec![*origin];
+    while let Some(name) = pending.pop() {
+        for next in Mesh::new(committee, &name, degree).relays(origin, rounds) {
+            if reached.insert(next) {
+                pending.push(next);
+            }
+        }
+    }
+    reached
+}
+
+#[test]
+fn symmetric_mesh() {
+    let committee = large_committee(100);
+    for name in committee.authorities.keys() {
+        let neighbours = Mesh::new(&committee, name, 6).neighbours();
+        assert!(neighbours.len() >= 6);
+        assert!(!neighbours.contains(name));
+        for neighbour in &neighbours {
+            assert!(Mesh::new(&committee, neighbour, 6)
+                .neighbours()
+                .contains(name));
+        }
+    }
+}
+
+#[test]
+fn relay_to_all() {
+    let committee = large_committee(100);
+    let origin = *committee.authorities.keys().nth(42).unwrap();
+
+    // Relaying over enough hops reaches the whole committee.
+    let reached = diffuse(&committee, &origin, 6, committee.size());
+    assert_eq!(reached.len(), committee.size() - 1);
+    assert!(!reached.contains(&origin));
+}
+
+#[test]
+fn relay_within_rounds() {
+    let committee = large_committee(100);
+    let origin = *committee.authorities.keys().next().unwrap();
+
+    // With a single hop, only the neighbours of the origin receive its certificates.
+    let neighbours: HashSet<_> = Mesh::new(&committee, &origin, 6)
+        .neighbours()
+        .into_iter()
+        .collect();
+    assert_eq!(diffuse(&committee, &origin, 6, 1), neighbours);
+
+    // The others pull them.
+    assert!(diffuse(&committee, &origin, 6, 2).len() < committee.size() - 1);
+}
@@ -0,0 +1,161 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::aggregators::VotesAggregator;
+use crate::common::{certificate, committee, header, headers, keys, votes};
+use crypto::BlsSecretKey;
+
+#[test]
+fn accessors() {
+    let header = header();
+    assert_eq!(header.author(), header.author);
+    assert_eq!(header.epoch(), 0);
+    assert_eq!(header.round(), 1);
+    assert_eq!(header.timestamp(), header.timestamp);
+    assert_eq!(header.parents(), &header.parents);
+    assert_eq!(header.id(), &header.digest());
+
+    let certificate = certificate(&header);
+    assert_eq!(certificate.header().id(), header.id());
+    assert_eq!(certificate.parents(), header.parents());
+    assert!(certificate.payload().is_empty());
+    assert_eq!(certificate.votes().len(), certificate.votes.len());
+}
+
+#[test]
+fn stable_header_serialization() {
+    // Downstream consumers rely on this encoding: any change must be treated as a breaking change.
+    let header = Header {
+        round: 1,
+        ..Header::default()
+    };
+    let author = header.author.encode_base64();
+    let expected = [
+        &(author.len() as u64).to_le_bytes()[..],
+        author.as_bytes(),
+        &0u64.to_le_bytes(), // epoch
+        &1u64.to_le_bytes(), // round
+        &0u64.to_le_bytes(), // timestamp
+        &0u64.to_le_bytes(), // payload length
+        &0u64.to_le_bytes(), // system messages length
+        &0u64.to_le_bytes(), // parents length
+        &[0u8; 32],          // id
+        &[0u8; 64],          // signature
+    ]
+    .concat();
+    assert_eq!(bincode::serialize(&header).unwrap(), expected);
+
+    let decoded: Header = bincode::deserialize(&expected).unwrap();
+    assert_eq!(decoded.round(), 1);
+}
+
+#[test]
+fn check_timestamp() {
+    let bounds = TimestampBounds {
+        max_future_skew: Some(1_000),
+        max_past_skew: Some(1_000),
+    };
+    let header = Header {
+        timestamp: 10_000,
+        ..header()
+    };
+    assert!(header.check_timestamp(10_500, &bounds).is_ok());
+    assert!(header.check_timestamp(9_500, &bounds).is_ok());
+
+    // Ensure headers too far ahead or behind our local time are rejected.
+    assert!(header.check_timestamp(8_000, &bounds).is_err());
+    assert!(header.check_timestamp(12_000, &bounds).is_err());
+
+    // Ensure unbounded skews accept any timestamp.
+    let bounds = TimestampBounds {
+        max_future_skew: None,
+        max_past_skew: None,
+    };
+    assert!(header.check_timestamp(0, &bounds).is_ok());
+}
+
+#[test]
+fn reject_other_epoch() {
+    let committee = committee();
+    let next = Committee {
+        epoch: 1,
+        ..committee.clone()
+    };
+
+    // Ensure each epoch has its own genesis.
+    let genesis = Certificate::genesis(&next);
+    assert!(Certificate::genesis(&committee)
+        .iter()
+        .all(|x| !genesis.contains(x)));
+    assert!(genesis.iter().all(|x| x.verify(&next).is_ok()));
+    assert!(genesis.iter().all(|x| x.verify(&committee).is_err()));
+
+    // Ensure certificates of the previous epoch are rejected.
+    let certificate = certificate(&header());
+    assert!(certificate.verify(&committee).is_ok());
+    match certificate.verify(&next) {
+        Err(DagError::InvalidEpoch(id, 0)) => assert_eq!(id, certificate.header.id),
+        _ => panic!("Unexpected result"),
+    }
+}
+
+#[test]
+fn verify_certificates_signatures() {
+    let committee = committee();
+    let mut certificates: Vec<_> = headers().iter().map(certificate).collect();
+    assert!(certificates.iter().all(|x| x.check(&committee).is_ok()));
+    assert!(Certificate::verify_signatures(&certificates, &committee).is_ok());
+
+    // Corrupt one vote and ensure the batch is rejected.
+    certificates[1].votes[0].1 = Signature::default();
+    assert!(Certificate::verify_signatures(&certificates, &committee).is_err());
+    assert!(certificates[0].verify(&committee).is_ok());
+    assert!(certificates[1].verify(&committee).is_err());
+}
+
+#[test]
+fn aggregate_votes() {
+    let committee = committee();
+    let header = header();
+    let votes: Vec<_> = votes(&header)
+        .into_iter()
+        .zip(keys().iter())
+        .map(|(vote, (_, secret))| {
+            let signature = BlsSignature::new(&vote.digest(), &BlsSecretKey::derive(secret));
+            Vote {
+                bls_signature: Some(signature),
+                ..vote
+            }
+        })
+        .collect();
+
+    // Aggregate a quorum of votes into a compact certificate.
+    let mut aggregator = VotesAggregator::new();
+    let mut certificates: Vec<_> = votes
+        .iter()
+        .take(3)
+        .filter_map(|x| aggregator.append(x.clone(), &committee, &header).unwrap())
+        .collect();
+    let mut certificate = certificates.pop().unwrap();
+    assert!(certificate.votes().is_empty());
+    assert_eq!(certificate.signers(&committee).unwrap().len(), 3);
+    assert!(certificate.verify(&committee).is_ok());
+    assert!(Certificate::verify_signatures(&[certificate.clone()], &committee).is_ok());
+
+    // Removing a signer breaks the quorum.
+    certificate.aggregate.as_mut().unwrap().signers[0] &= 0b0000_0011;
+    assert!(certificate.verify(&committee).is_err());
+
+    // A bad BLS signature makes the aggregator fall back to the individual signatures.
+    let mut votes = votes;
+    votes[0].bls_signature = votes[1].bls_signature.clone();
+    let mut aggregator = VotesAggregator::new();
+    let mut certificates: Vec<_> = votes
+        .into_iter()
+        .take(3)
+        .filter_map(|x| aggregator.append(x, &committee, &header).unwrap())
+        .collect();
+    let certificate = certificates.pop().unwrap();
+    assert!(certificate.aggregate.is_none());
+    assert_eq!(certificate.votes().len(), 3);
+    assert!(certificate.verify(&committee).is_ok());
+}
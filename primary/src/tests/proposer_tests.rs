@@ -1,26 +1,51 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{committee, keys};
+use crate::common::{committee, header, keys};
+use config::{LeaderSchedule, PayloadBalancing, PayloadPolicy};
+use std::fs;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn propose_empty() {
     let (name, secret) = keys().pop().unwrap();
     let signature_service = SignatureService::new(secret);
 
+    // Create a new test store.
+    let path = ".db_test_propose_empty";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
     let (_tx_parents, rx_parents) = channel(1);
     let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
     let (tx_headers, mut rx_headers) = channel(1);
 
     // Spawn the proposer.
     Proposer::spawn(
         name,
         &committee(),
+        store,
         signature_service,
         /* header_size */ 1_000,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
         /* max_header_delay */ 20,
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
         /* tx_core */ tx_headers,
     );
 
@@ -36,19 +61,39 @@ async fn propose_payload() {
     let (name, secret) = keys().pop().unwrap();
     let signature_service = SignatureService::new(secret);
 
+    // Create a new test store.
+    let path = ".db_test_propose_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
     let (_tx_parents, rx_parents) = channel(1);
     let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
     let (tx_headers, mut rx_headers) = channel(1);
 
     // Spawn the proposer.
     Proposer::spawn(
         name,
         &committee(),
+        store,
         signature_service,
         /* header_size */ 32,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
         /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
         /* tx_core */ tx_headers,
     );
 
@@ -66,3 +111,656 @@ async fn propose_payload() {
     assert_eq!(header.payload.get(&digest), Some(&worker_id));
     assert!(header.verify(&committee()).is_ok());
 }
+
+#[tokio::test]
+async fn propose_after_restart() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store holding the last header we proposed before restarting.
+    let path = ".db_test_propose_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let last = Header {
+        round: 3,
+        ..header()
+    };
+    let bytes = bincode::serialize(&last).unwrap();
+    store.write(LAST_PROPOSED_KEY.to_vec(), bytes).await;
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 1_000,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 20,
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // The proposer ignores parents up to the round of its last header.
+    let parents = vec![Digest::default()];
+    tx_parents.send((parents.clone(), 2)).await.unwrap();
+    tx_parents.send((parents, 3)).await.unwrap();
+
+    // Ensure the proposer resumes after its last header.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 4);
+}
+
+#[tokio::test]
+async fn repropose_orphaned_payload() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (other, _) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_repropose_orphaned_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 32,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(1)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Make a header of round 1 with a digest of our workers.
+    let digest = Digest(name.0);
+    let worker_id = 0;
+    tx_our_digests
+        .send((digest.clone(), worker_id))
+        .await
+        .unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // The consensus commits round 3 without our header: it will never be committed.
+    tx_committed.send((other, 3)).await.unwrap();
+
+    // Ensure the proposer re-includes the digest in its next header.
+    tx_parents.send((vec![Digest::default()], 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!(header.payload.get(&digest), Some(&worker_id));
+}
+
+#[tokio::test]
+async fn propose_min_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_propose_min_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (_tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 1_000,
+        /* min_header_size */ Some(32),
+        /* max_header_payload */ None,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Send the minimum payload.
+    let digest = Digest(name.0);
+    tx_our_digests.send((digest.clone(), 0)).await.unwrap();
+
+    // Ensure the proposer does not wait for a full header.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+    assert_eq!(header.payload.len(), 1);
+}
+
+#[tokio::test]
+async fn propose_bounded_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_propose_bounded_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 32,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Make a first header.
+    tx_our_digests.send((Digest([0; 32]), 0)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Buffer more digests than fit in a header while waiting for the parents.
+    tx_our_digests.send((Digest([1; 32]), 0)).await.unwrap();
+    tx_our_digests.send((Digest([2; 32]), 0)).await.unwrap();
+
+    // Ensure each of the next headers carries a single digest.
+    for round in 1..3 {
+        tx_parents
+            .send((vec![Digest::default()], round))
+            .await
+            .unwrap();
+        let header = rx_headers.recv().await.unwrap();
+        assert_eq!(header.round, round + 1);
+        assert_eq!(header.payload.len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn propose_max_header_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_propose_max_header_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 96,
+        /* min_header_size */ None,
+        /* max_header_payload */ Some(2),
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Send enough digests for a full header.
+    for i in 0..3 {
+        tx_our_digests.send((Digest([i; 32]), 0)).await.unwrap();
+    }
+
+    // Ensure the header only references two digests.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+    assert_eq!(header.payload.len(), 2);
+
+    // Ensure the last digest is carried to the next header.
+    for i in 3..5 {
+        tx_our_digests.send((Digest([i; 32]), 0)).await.unwrap();
+    }
+    tx_parents.send((vec![Digest::default()], 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!(header.payload.len(), 2);
+    assert_eq!(header.payload.get(&Digest([2; 32])), Some(&0));
+}
+
+#[tokio::test]
+async fn propose_balanced_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_propose_balanced_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    let payload_balancing = PayloadBalancing {
+        policy: PayloadPolicy::Balanced,
+        max_batch_age: 1_000_000, // Ensure it is not triggered.
+    };
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 64,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        payload_balancing,
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Make a first header.
+    for i in 0..2 {
+        tx_our_digests.send((Digest([i; 32]), 0)).await.unwrap();
+    }
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Buffer one digest of worker 0, then a backlog of digests of worker 1.
+    tx_our_digests.send((Digest([2; 32]), 0)).await.unwrap();
+    for i in 3..7 {
+        tx_our_digests.send((Digest([i; 32]), 1)).await.unwrap();
+    }
+    tx_parents.send((vec![Digest::default()], 1)).await.unwrap();
+
+    // Ensure the next header favors the worker with the largest backlog.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!(header.payload.len(), 2);
+    assert!(!header.payload.contains_key(&Digest([2; 32])));
+    assert!(header.payload.values().all(|x| *x == 1));
+}
+
+#[tokio::test]
+async fn stall_on_commit_lag() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let (other, _) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_stall_on_commit_lag";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 1_000,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 20,
+        PayloadBalancing::default(),
+        /* max_commit_lag */ Some(1),
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Make a first header.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Ensure the proposer creates the rounds needed to commit the first leader (round 2), despite
+    // the commit lag.
+    for round in 1..5 {
+        tx_parents
+            .send((vec![Digest::default()], round))
+            .await
+            .unwrap();
+        let header = rx_headers.recv().await.unwrap();
+        assert_eq!(header.round, round + 1);
+    }
+
+    // Ensure the proposer does not move further ahead of the last committed round.
+    tx_parents.send((vec![Digest::default()], 5)).await.unwrap();
+    let result = timeout(Duration::from_millis(200), rx_headers.recv()).await;
+    assert!(result.is_err());
+
+    // Ensure the proposer resumes once the consensus catches up.
+    tx_committed.send((other, 2)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 6);
+}
+
+#[tokio::test]
+async fn lazy_headers() {
+    // Pick the leader of round 2 (the second slot of the schedule).
+    let mut keys = keys();
+    keys.sort_by_key(|(name, _)| *name);
+    let (name, secret) = keys.swap_remove(1);
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_lazy_headers";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 1_000,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 20,
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ Some(1_000_000), // Ensure it is not triggered.
+        LeaderSchedule::RoundRobin,
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Ensure the proposer does not make an empty header for round 1 (we are not its leader).
+    let result = timeout(Duration::from_millis(200), rx_headers.recv()).await;
+    assert!(result.is_err());
+
+    // Ensure it proposes as soon as it has some payload.
+    let digest = Digest([1; 32]);
+    tx_our_digests.send((digest.clone(), 0)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+    assert_eq!(header.payload.get(&digest), Some(&0));
+
+    // Ensure it makes an empty header for round 2 (we are its leader).
+    tx_parents.send((vec![Digest::default()], 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert!(header.payload.is_empty());
+}
+
+#[tokio::test]
+async fn propose_system_messages() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_propose_system_messages";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (_tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 1_000,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 20,
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ Some(1_000_000), // Ensure it is not triggered.
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Ensure the proposer does not stay lazy with pending system messages, and includes them in
+    // its next header.
+    tx_system.send(SystemMessage::Heartbeat).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+    assert!(header.payload.is_empty());
+    assert_eq!(header.system(), &[SystemMessage::Heartbeat]);
+    assert!(header.verify(&committee()).is_ok());
+}
+
+#[tokio::test]
+async fn pause_and_resume() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_pause_and_resume";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (_tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Pause the proposer before it gets the chance to propose.
+    tx_commands.send(ProposerCommand::Pause).await.unwrap();
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 1_000,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 20,
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Ensure the proposer does not propose while paused.
+    let result = timeout(Duration::from_millis(200), rx_headers.recv()).await;
+    assert!(result.is_err());
+
+    // Ensure it proposes once resumed.
+    tx_commands.send(ProposerCommand::Resume).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+}
+
+#[tokio::test]
+async fn ignore_duplicate_digests() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    // Create a new test store.
+    let path = ".db_test_ignore_duplicate_digests";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (_tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_system, rx_system) = channel(1);
+    let (_tx_committed, rx_committed) = channel(1);
+    let (_tx_commands, rx_commands) = channel(1);
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        store,
+        signature_service,
+        /* header_size */ 64,
+        /* min_header_size */ None,
+        /* max_header_payload */ None,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        PayloadBalancing::default(),
+        /* max_commit_lag */ None,
+        /* lazy_header_delay */ None,
+        LeaderSchedule::default(),
+        /* archival */ false,
+        /* gc_depth */ Arc::new(AtomicU64::new(50)),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        rx_system,
+        rx_committed,
+        rx_commands,
+        /* tx_core */ tx_headers,
+    );
+
+    // Send the same digest twice, then another digest.
+    let digests = [Digest([1; 32]), Digest([1; 32]), Digest([2; 32])];
+    for digest in &digests {
+        tx_our_digests.send((digest.clone(), 0)).await.unwrap();
+    }
+
+    // Ensure the duplicate does not count towards the header size: the header only fills up
+    // with the second distinct digest.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.payload.len(), 2);
+    assert!(header.payload.contains_key(&digests[0]));
+    assert!(header.payload.contains_key(&digests[2]));
+}
@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+
+#[test]
+fn ban_after_quota_exceeded() {
+    let quotas = PeerQuotas {
+        headers: 2,
+        votes: 2,
+        sync_requests: 2,
+        ban_duration: 50,
+        ..PeerQuotas::default()
+    };
+    let mut limiter = PeerLimiter::new(&committee(), quotas, PrimaryMetrics::default());
+    let mut keys = keys();
+    let (peer, _) = keys.pop().unwrap();
+    let (other, _) = keys.pop().unwrap();
+
+    // Exhaust the quota of headers of the peer.
+    assert!(limiter.try_acquire(&peer, QuotaClass::Header));
+    assert!(limiter.try_acquire(&peer, QuotaClass::Header));
+    assert!(!limiter.try_acquire(&peer, QuotaClass::Header));
+
+    // Ensure the peer is banned for all classes of messages, but not the others.
+    assert!(!limiter.try_acquire(&peer, QuotaClass::Vote));
+    assert!(limiter.try_acquire(&other, QuotaClass::Header));
+
+    // Ensure the ban expires.
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(limiter.try_acquire(&peer, QuotaClass::Vote));
+}
+
+#[test]
+fn reject_unknown_peer() {
+    let mut limiter = PeerLimiter::new(
+        &committee(),
+        PeerQuotas::default(),
+        PrimaryMetrics::default(),
+    );
+    let unknown = PublicKey::default();
+    assert!(!limiter.try_acquire(&unknown, QuotaClass::Vote));
+}
+
+#[test]
+fn connection_limiter_spares_shared_quotas() {
+    let quotas = PeerQuotas {
+        headers: 1,
+        ..PeerQuotas::default()
+    };
+    let metrics = PrimaryMetrics::default();
+    let mut shared = PeerLimiter::new(&committee(), quotas, metrics.clone());
+    let mut connection = PeerLimiter::for_connection(&committee(), quotas, metrics);
+    let (victim, _) = keys().pop().unwrap();
+
+    // A connection forging the identity of an authority only gets its own connection banned.
+    assert!(connection.try_acquire(&victim, QuotaClass::Header));
+    assert!(!connection.try_acquire(&victim, QuotaClass::Header));
+    assert!(shared.try_acquire(&victim, QuotaClass::Header));
+}
@@ -0,0 +1,15 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn drop_replays() {
+    let mut cache = ReplayCache::new(2, PrimaryMetrics::default());
+    assert!(cache.check(b"a"));
+    assert!(cache.check(b"b"));
+    assert!(!cache.check(b"a"));
+
+    // Seeing `c` evicts `b`, the least recently seen message.
+    assert!(cache.check(b"c"));
+    assert!(!cache.check(b"a"));
+    assert!(cache.check(b"b"));
+}
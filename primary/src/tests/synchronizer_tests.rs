@@ -0,0 +1,58 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, header, keys};
+use std::fs;
+use tokio::sync::mpsc::channel;
+
+#[test]
+fn sync_obligations() {
+    let obligations = SyncObligations::new(3);
+    assert!(obligations.try_acquire(2));
+    assert!(!obligations.try_acquire(2));
+    obligations.release(2);
+    assert!(obligations.try_acquire(3));
+}
+
+#[tokio::test]
+async fn refuse_sync_when_full() {
+    let (name, _) = keys().pop().unwrap();
+    let (tx_sync_headers, mut rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_refuse_sync_when_full";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer willing to wait for a single missing parent.
+    let mut synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store,
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+        /* header_obligations */ SyncObligations::new(1),
+        /* certificate_obligations */ SyncObligations::new(1),
+    );
+
+    // A header with two missing parents exceeds the limit.
+    let full = Header {
+        parents: [Digest([1; 32]), Digest([2; 32])].iter().cloned().collect(),
+        ..header()
+    };
+    assert!(matches!(
+        synchronizer.get_parents(&full).await,
+        Err(DagError::TooManySyncObligations(..))
+    ));
+
+    // A header with a single missing parent is sent to the header waiter.
+    let single = Header {
+        parents: [Digest([1; 32])].iter().cloned().collect(),
+        ..header()
+    };
+    assert!(synchronizer.get_parents(&single).await.unwrap().is_empty());
+    match rx_sync_headers.recv().await {
+        Some(WaiterMessage::SyncParents(missing, _)) => assert_eq!(missing, vec![Digest([1; 32])]),
+        _ => panic!("Unexpected waiter message"),
+    }
+}
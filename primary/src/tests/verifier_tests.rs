@@ -0,0 +1,190 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header, headers, keys, votes};
+use tokio::sync::mpsc::channel;
+use tokio::time::{timeout, Duration};
+
+#[tokio::test]
+async fn verify_in_order() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+    Verifier::spawn(
+        /* name */ header().author,
+        committee(),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        VerificationBudget::default(),
+        /* parallelism */ 4,
+        rx_primaries,
+        tx_core,
+    );
+
+    // Send a mix of valid and invalid messages.
+    let header = header();
+    let mut invalid = votes(&header).pop().unwrap();
+    invalid.round += 1;
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+    let messages = vec![
+        PrimaryMessage::Header(header.clone()),
+        PrimaryMessage::Vote(invalid),
+        PrimaryMessage::Certificate(certificates[0].clone()),
+        PrimaryMessage::Votes(votes(&header)),
+        PrimaryMessage::Certificate(certificates[1].clone()),
+    ];
+    for message in messages {
+        tx_primaries.send(message).await.unwrap();
+    }
+
+    // Ensure the valid messages reach the core in the order we received them.
+    match rx_core.recv().await {
+        Some(PrimaryMessage::Header(x)) => assert_eq!(x.id, header.id),
+        _ => panic!("Unexpected message"),
+    }
+    match rx_core.recv().await {
+        Some(PrimaryMessage::Certificate(x)) => assert_eq!(x, certificates[0]),
+        _ => panic!("Unexpected message"),
+    }
+    match rx_core.recv().await {
+        Some(PrimaryMessage::Votes(x)) => assert_eq!(x.len(), 4),
+        _ => panic!("Unexpected message"),
+    }
+    match rx_core.recv().await {
+        Some(PrimaryMessage::Certificate(x)) => assert_eq!(x, certificates[1]),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn defer_votes_over_budget() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+    let budget = VerificationBudget {
+        votes: 2,
+        ..VerificationBudget::default()
+    };
+    Verifier::spawn(
+        /* name */ header().author,
+        committee(),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        budget,
+        /* parallelism */ 4,
+        rx_primaries,
+        tx_core,
+    );
+
+    // Send more votes than the budget allows per second.
+    let header = header();
+    tx_primaries
+        .send(PrimaryMessage::Votes(votes(&header)))
+        .await
+        .unwrap();
+
+    // Ensure all votes eventually reach the core.
+    let mut received = 0;
+    while received < 4 {
+        match rx_core.recv().await {
+            Some(PrimaryMessage::Vote(_)) => received += 1,
+            Some(PrimaryMessage::Votes(x)) => received += x.len(),
+            _ => panic!("Unexpected message"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn drop_unexpected_votes_before_verifying() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+    let budget = VerificationBudget {
+        votes: 4,
+        ..VerificationBudget::default()
+    };
+    let header = header();
+    Verifier::spawn(
+        header.author,
+        committee(),
+        /* header_round */ Arc::new(AtomicU64::new(header.round + 1)),
+        budget,
+        /* parallelism */ 4,
+        rx_primaries,
+        tx_core,
+    );
+
+    // Send votes on an older header of ours, then on a header of another authority.
+    tx_primaries
+        .send(PrimaryMessage::Votes(votes(&header)))
+        .await
+        .unwrap();
+    let (other, _) = keys().remove(0);
+    let theirs = Header {
+        author: other,
+        round: header.round + 1,
+        ..header.clone()
+    };
+    tx_primaries
+        .send(PrimaryMessage::Votes(votes(&theirs)))
+        .await
+        .unwrap();
+
+    // Send votes on our current header.
+    let ours = Header {
+        round: header.round + 1,
+        ..header
+    };
+    tx_primaries
+        .send(PrimaryMessage::Votes(votes(&ours)))
+        .await
+        .unwrap();
+
+    // Ensure only the votes on our current header reach the core, without waiting for the budget
+    // (the other votes did not use it up).
+    match timeout(Duration::from_millis(500), rx_core.recv()).await {
+        Ok(Some(PrimaryMessage::Votes(x))) => {
+            assert_eq!(x.len(), 4);
+            assert!(x
+                .iter()
+                .all(|x| x.round == ours.round && x.origin == ours.author));
+        }
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn sync_responses_have_their_own_budget() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+    let budget = VerificationBudget {
+        certificates: 1,
+        ..VerificationBudget::default()
+    };
+    Verifier::spawn(
+        /* name */ header().author,
+        committee(),
+        /* header_round */ Arc::new(AtomicU64::new(0)),
+        budget,
+        /* parallelism */ 4,
+        rx_primaries,
+        tx_core,
+    );
+
+    // Exhaust the budget of the relayed certificates, then send the certificates we synced.
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+    for certificate in &certificates[..2] {
+        let message = PrimaryMessage::Certificate(certificate.clone());
+        tx_primaries.send(message).await.unwrap();
+    }
+    for certificate in &certificates[2..] {
+        let message = PrimaryMessage::SyncedCertificate(certificate.clone());
+        tx_primaries.send(message).await.unwrap();
+    }
+
+    // Ensure the synced certificates reach the core (as regular certificates) despite the exhausted
+    // budget of the relayed ones.
+    let mut received = Vec::new();
+    while received.len() < certificates.len() - 1 {
+        match timeout(Duration::from_millis(500), rx_core.recv()).await {
+            Ok(Some(PrimaryMessage::Certificate(x))) => received.push(x),
+            _ => panic!("Unexpected message"),
+        }
+    }
+    assert_eq!(received[0], certificates[0]);
+    assert_eq!(received[1..], certificates[2..]);
+}
@@ -0,0 +1,80 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::keys;
+use futures::future::join_all;
+
+/// Logs a vote and waits until the log holds it.
+async fn log_vote(log: &mut VoteLog, author: &PublicKey, round: Round) {
+    log.append(author, round).await.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn restore_votes() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("votes");
+    let (a, _) = keys().pop().unwrap();
+    let (b, _) = keys().remove(0);
+
+    // Log a few votes.
+    let mut log = VoteLog::open(&path, VoteLogParameters::default()).unwrap();
+    log_vote(&mut log, &a, 1).await;
+    log_vote(&mut log, &a, 3).await;
+    log_vote(&mut log, &b, 2).await;
+    log_vote(&mut log, &a, 2).await; // Lower than our last vote, not logged.
+    drop(log);
+
+    // Reopen the log.
+    let log = VoteLog::open(&path, VoteLogParameters::default()).unwrap();
+    let expected: HashMap<_, _> = vec![(a, 3), (b, 2)].into_iter().collect();
+    assert_eq!(log.restored(), &expected);
+    assert_eq!(fs::metadata(&path).unwrap().len(), (3 * RECORD_SIZE) as u64);
+}
+
+#[tokio::test]
+async fn discard_torn_record() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("votes");
+    let (a, _) = keys().pop().unwrap();
+
+    let mut log = VoteLog::open(&path, VoteLogParameters::default()).unwrap();
+    log_vote(&mut log, &a, 1).await;
+    drop(log);
+
+    // Simulate a crash while appending a record.
+    let record = encode(&a, 5);
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&record[..RECORD_SIZE / 2]).unwrap();
+    drop(file);
+
+    // The torn record is discarded and new votes are appended after the last complete record.
+    let mut log = VoteLog::open(&path, VoteLogParameters::default()).unwrap();
+    assert_eq!(log.restored().get(&a), Some(&1));
+    log_vote(&mut log, &a, 2).await;
+    drop(log);
+
+    let log = VoteLog::open(&path, VoteLogParameters::default()).unwrap();
+    assert_eq!(log.restored().get(&a), Some(&2));
+}
+
+#[tokio::test]
+async fn log_concurrent_votes() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("votes");
+    let keys = keys();
+
+    // Log the votes of all authorities without waiting for each of them to be logged.
+    let mut log = VoteLog::open(&path, VoteLogParameters::default()).unwrap();
+    let mut logged = Vec::new();
+    for (author, _) in &keys {
+        logged.push(log.append(author, 1).await);
+    }
+    for result in join_all(logged).await {
+        result.unwrap().unwrap();
+    }
+    drop(log);
+
+    // Ensure the log holds all of them.
+    let log = VoteLog::open(&path, VoteLogParameters::default()).unwrap();
+    let expected: HashMap<_, _> = keys.into_iter().map(|(x, _)| (x, 1)).collect();
+    assert_eq!(log.restored(), &expected);
+}
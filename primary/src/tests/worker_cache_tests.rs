@@ -0,0 +1,40 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys};
+use crypto::generate_keypair;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+#[test]
+fn add_and_remove_workers() {
+    let (name, _) = keys().pop().unwrap();
+    let cache = WorkerCache::new(&committee());
+    assert_eq!(cache.our_workers(&name).len(), 1);
+    assert!(cache.worker(&name, &1).is_err());
+
+    // Add a worker.
+    let addresses = WorkerAddresses {
+        transactions: "127.0.0.1:20000".parse().unwrap(),
+        worker_to_worker: "127.0.0.1:20001".parse().unwrap(),
+        primary_to_worker: "127.0.0.1:20002".parse().unwrap(),
+        receipts: None,
+        ingress: Vec::new(),
+    };
+    let update = WorkerUpdate::Add(name, 1, addresses.clone());
+    assert!(cache.clone().update(update).is_ok());
+    assert_eq!(cache.worker(&name, &1).unwrap(), addresses);
+    assert_eq!(cache.our_workers(&name).len(), 2);
+
+    // Remove it.
+    assert!(cache.update(WorkerUpdate::Remove(name, 1)).is_ok());
+    assert!(cache.worker(&name, &1).is_err());
+    assert!(cache.update(WorkerUpdate::Remove(name, 1)).is_err());
+}
+
+#[test]
+fn reject_unknown_authority() {
+    let mut rng = StdRng::from_seed([1; 32]);
+    let (unknown, _) = generate_keypair(&mut rng);
+    let cache = WorkerCache::new(&committee());
+    assert!(cache.update(WorkerUpdate::Remove(unknown, 0)).is_err());
+}
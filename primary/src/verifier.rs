@@ -0,0 +1,291 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::budget::{MessageClass, VerificationLimiter};
+use crate::error::{DagError, DagResult};
+use crate::messages::{Certificate, Header, Vote};
+use crate::primary::PrimaryMessage;
+use config::{Committee, VerificationBudget};
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use futures::stream::FuturesOrdered;
+use futures::stream::StreamExt as _;
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::{spawn_blocking, JoinHandle};
+use tokio::time::sleep;
+
+#[cfg(test)]
+#[path = "tests/verifier_tests.rs"]
+pub mod verifier_tests;
+
+/// The maximum number of certificates whose signatures are verified in a single batch.
+const MAX_CERTIFICATES_BATCH: usize = 100;
+
+/// The maximum number of votes waiting for the verification budget. Votes beyond it are dropped.
+const MAX_DEFERRED_VOTES: usize = 10_000;
+
+/// A unit of verification work, handled by a blocking thread.
+enum Job {
+    Header(Header),
+    Votes(Vec<Vote>),
+    Certificates(Vec<Certificate>),
+}
+
+/// Verifies the signatures (and the structure) of the headers, votes, and certificates of the other
+/// primaries on the blocking thread pool, so that the `Core` does not spend its time verifying
+/// signatures. The verified messages are handed to the `Core` in the order we received them.
+pub struct Verifier {
+    /// The public key of this authority.
+    name: PublicKey,
+    /// The committee information.
+    committee: Arc<Committee>,
+    /// The round of our current header (set by the `Core`): we only verify the votes on our
+    /// headers of this round or later.
+    header_round: Arc<AtomicU64>,
+    /// Limits the number of signature verifications we perform for each class of messages.
+    limiter: VerificationLimiter,
+    /// The maximum number of jobs verified in parallel.
+    parallelism: usize,
+    /// Receives the messages of the other primaries.
+    rx_primaries: Receiver<PrimaryMessage>,
+    /// Sends the verified messages to the `Core`.
+    tx_core: Sender<PrimaryMessage>,
+    /// A message interrupting a batch of certificates, verified right after the batch.
+    interrupt: Option<PrimaryMessage>,
+    /// The votes exceeding the verification budget, verified (in order) as soon as it allows. Votes
+    /// are not dropped when over budget since losing them may prevent a header from being certified.
+    deferred: VecDeque<Vote>,
+}
+
+impl Verifier {
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        header_round: Arc<AtomicU64>,
+        budget: VerificationBudget,
+        parallelism: usize,
+        rx_primaries: Receiver<PrimaryMessage>,
+        tx_core: Sender<PrimaryMessage>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                name,
+                committee: Arc::new(committee),
+                header_round,
+                limiter: VerificationLimiter::new(budget),
+                parallelism: parallelism.max(1),
+                rx_primaries,
+                tx_core,
+                interrupt: None,
+                deferred: VecDeque::new(),
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Returns whether the budget of the specified class allows to verify one more message.
+    fn within_budget(&mut self, class: MessageClass, digest: impl FnOnce() -> Digest) -> bool {
+        if self.limiter.try_acquire(class) {
+            return true;
+        }
+        debug!("{}", DagError::VerificationBudgetExceeded(class, digest()));
+        false
+    }
+
+    /// Checks that a vote may be on our current header (as the `Core` does), before spending a
+    /// signature verification on it.
+    fn expected_vote(&self, vote: &Vote) -> DagResult<()> {
+        ensure!(
+            self.header_round.load(Ordering::Relaxed) <= vote.round,
+            DagError::TooOld(vote.digest(), vote.round)
+        );
+        ensure!(
+            vote.origin == self.name && self.committee.stake(&vote.author) > 0,
+            DagError::UnexpectedVote(vote.id.clone())
+        );
+        Ok(())
+    }
+
+    /// Queues votes behind the ones already waiting for the verification budget, dropping those
+    /// that cannot be on our current header.
+    fn defer_votes(&mut self, votes: Vec<Vote>) {
+        for vote in votes {
+            if let Err(e) = self.expected_vote(&vote) {
+                debug!("{}", e);
+                continue;
+            }
+            if self.deferred.len() >= MAX_DEFERRED_VOTES {
+                debug!(
+                    "{}",
+                    DagError::VerificationBudgetExceeded(MessageClass::Vote, vote.digest())
+                );
+                continue;
+            }
+            self.deferred.push_back(vote);
+        }
+    }
+
+    /// Turns the oldest deferred votes allowed by the verification budget into a job.
+    fn take_votes(&mut self) -> Option<Job> {
+        let mut votes = Vec::new();
+        while !self.deferred.is_empty() && self.limiter.try_acquire(MessageClass::Vote) {
+            votes.push(self.deferred.pop_front().unwrap());
+        }
+        (!votes.is_empty()).then_some(Job::Votes(votes))
+    }
+
+    /// Turns a message into a verification job, charging it to the verification budget. Certificates
+    /// are batched with the certificates already waiting in our inbox, so that we verify all their
+    /// signatures at once; a message of another type interrupts the batch. The certificates we
+    /// received in reply to our sync requests are charged to their own budget.
+    fn make_job(&mut self, message: PrimaryMessage) -> Option<Job> {
+        match message {
+            PrimaryMessage::Header(header) => self
+                .within_budget(MessageClass::Header, || header.id.clone())
+                .then_some(Job::Header(header)),
+            PrimaryMessage::Vote(vote) => {
+                self.defer_votes(vec![vote]);
+                self.take_votes()
+            }
+            PrimaryMessage::Votes(votes) => {
+                self.defer_votes(votes);
+                self.take_votes()
+            }
+            message @ (PrimaryMessage::Certificate(_) | PrimaryMessage::SyncedCertificate(_)) => {
+                let mut batch = vec![message];
+                while batch.len() < MAX_CERTIFICATES_BATCH {
+                    match self.rx_primaries.try_recv() {
+                        Ok(
+                            message @ (PrimaryMessage::Certificate(_)
+                            | PrimaryMessage::SyncedCertificate(_)),
+                        ) => batch.push(message),
+                        Ok(message) => {
+                            self.interrupt = Some(message);
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                let batch: Vec<_> = batch
+                    .into_iter()
+                    .filter_map(|message| {
+                        let (class, certificate) = match message {
+                            PrimaryMessage::SyncedCertificate(x) => (MessageClass::SyncResponse, x),
+                            PrimaryMessage::Certificate(x) => (MessageClass::Certificate, x),
+                            _ => unreachable!(),
+                        };
+                        self.within_budget(class, || certificate.digest())
+                            .then_some(certificate)
+                    })
+                    .collect();
+                (!batch.is_empty()).then_some(Job::Certificates(batch))
+            }
+            _ => panic!("Unexpected verifier message"),
+        }
+    }
+
+    /// Verifies a job, returning the messages that passed verification.
+    fn verify(committee: &Committee, job: Job) -> Vec<PrimaryMessage> {
+        match job {
+            Job::Header(header) => match header.verify(committee) {
+                Ok(()) => vec![PrimaryMessage::Header(header)],
+                error => {
+                    Self::report(error);
+                    Vec::new()
+                }
+            },
+            Job::Votes(votes) => {
+                let mut votes: Vec<_> = votes
+                    .into_iter()
+                    .filter(|x| match x.verify(committee) {
+                        Ok(()) => true,
+                        error => {
+                            Self::report(error);
+                            false
+                        }
+                    })
+                    .collect();
+                match votes.len() {
+                    0 => Vec::new(),
+                    1 => vec![PrimaryMessage::Vote(votes.pop().unwrap())],
+                    _ => vec![PrimaryMessage::Votes(votes)],
+                }
+            }
+            Job::Certificates(certificates) => {
+                let mut checked: Vec<_> = certificates
+                    .into_iter()
+                    .filter(|x| match x.check(committee) {
+                        Ok(()) => true,
+                        error => {
+                            Self::report(error);
+                            false
+                        }
+                    })
+                    .collect();
+
+                // Verify all signatures at once. If the batch is invalid, verify the certificates
+                // one by one to only drop the invalid ones.
+                if Certificate::verify_signatures(&checked, committee).is_err() {
+                    checked.retain(|x| match x.verify(committee) {
+                        Ok(()) => true,
+                        error => {
+                            Self::report(error);
+                            false
+                        }
+                    });
+                }
+                checked
+                    .into_iter()
+                    .map(PrimaryMessage::Certificate)
+                    .collect()
+            }
+        }
+    }
+
+    /// Logs the messages failing verification.
+    fn report(result: DagResult<()>) {
+        if let Err(e) = result {
+            warn!("{}", e);
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut pending: FuturesOrdered<JoinHandle<Vec<PrimaryMessage>>> = FuturesOrdered::new();
+
+        loop {
+            let idle = pending.len() < self.parallelism;
+            let deferred = !self.deferred.is_empty();
+            tokio::select! {
+                Some(message) = self.rx_primaries.recv(), if idle => {
+                    let mut next = Some(message);
+                    while let Some(message) = next {
+                        if let Some(job) = self.make_job(message) {
+                            let committee = self.committee.clone();
+                            pending.push_back(spawn_blocking(move || Self::verify(&committee, job)));
+                        }
+                        next = self.interrupt.take();
+                    }
+                },
+                // Verify the deferred votes once the budget allows.
+                () = sleep(self.limiter.delay(MessageClass::Vote)), if idle && deferred => {
+                    if let Some(job) = self.take_votes() {
+                        let committee = self.committee.clone();
+                        pending.push_back(spawn_blocking(move || Self::verify(&committee, job)));
+                    }
+                },
+                Some(result) = pending.next() => {
+                    let verified = result.expect("Failed to verify messages");
+                    for message in verified {
+                        self.tx_core
+                            .send(message)
+                            .await
+                            .expect("Failed to send verified message to the core");
+                    }
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::primary::Round;
+use config::{FsyncPolicy, VoteLogParameters};
+use crypto::PublicKey;
+use std::collections::HashMap;
+use std::convert::TryInto as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read as _, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task::spawn_blocking;
+use tokio::time::{sleep, Duration};
+
+#[cfg(test)]
+#[path = "tests/vote_log_tests.rs"]
+pub mod vote_log_tests;
+
+/// The size of a record of the log: the public key of the header's author followed by its round.
+const RECORD_SIZE: usize = 32 + 8;
+
+/// The number of records after which we compact the log (keeping a single record per author).
+const COMPACTION_THRESHOLD: usize = 100_000;
+
+/// The maximum number of votes waiting to be written to the log.
+const PENDING_CAPACITY: usize = 1_000;
+
+/// A vote waiting to be written to the log, along with the channel notifying that it is logged.
+type PendingVote = (PublicKey, Round, oneshot::Sender<io::Result<()>>);
+
+/// A write-ahead log of our votes. We append the author and round of each header before sending
+/// our vote for it so that, after a crash, we never vote twice for the same author and round. A
+/// task writes the votes to the log, writing (and syncing) at once all the votes that arrived
+/// while it synced the previous ones.
+pub struct VoteLog {
+    /// The highest round we voted for, for each author.
+    highest: HashMap<PublicKey, Round>,
+    /// Sends the votes to the task writing them.
+    tx_votes: Sender<PendingVote>,
+}
+
+impl VoteLog {
+    /// Opens the log (creating it if needed) and replays the votes it holds. A torn record at the
+    /// end of the log (the process crashed while appending it) is discarded.
+    pub fn open(path: &Path, parameters: VoteLogParameters) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut highest = HashMap::new();
+        let records = bytes.len() / RECORD_SIZE;
+        for record in bytes.chunks_exact(RECORD_SIZE) {
+            let (author, round) = decode(record);
+            let entry = highest.entry(author).or_default();
+            *entry = round.max(*entry);
+        }
+        file.set_len((records * RECORD_SIZE) as u64)?;
+
+        let file = Arc::new(Mutex::new(file));
+        let dirty = Arc::new(AtomicBool::new(false));
+        if parameters.fsync == FsyncPolicy::Periodic {
+            spawn_syncer(&file, dirty.clone(), parameters.fsync_interval);
+        }
+
+        let (tx_votes, rx_votes) = channel(PENDING_CAPACITY);
+        tokio::spawn(
+            LogWriter {
+                path: path.to_path_buf(),
+                file,
+                fsync: parameters.fsync,
+                dirty,
+                highest: highest.clone(),
+                records,
+                rx_votes,
+            }
+            .run(),
+        );
+        Ok(Self { highest, tx_votes })
+    }
+
+    /// The highest round we voted for, for each author.
+    pub fn restored(&self) -> &HashMap<PublicKey, Round> {
+        &self.highest
+    }
+
+    /// Logs our vote for the header of `author` at `round`. The returned channel tells when the
+    /// vote is logged (and, depending on the fsync policy, synced to disk): we may only send the
+    /// vote then.
+    pub async fn append(
+        &mut self,
+        author: &PublicKey,
+        round: Round,
+    ) -> oneshot::Receiver<io::Result<()>> {
+        let (sender, receiver) = oneshot::channel();
+        let highest = self.highest.entry(*author).or_default();
+        if round <= *highest {
+            let _ = sender.send(Ok(()));
+            return receiver;
+        }
+        *highest = round;
+        self.tx_votes
+            .send((*author, round, sender))
+            .await
+            .expect("Failed to send vote to the vote log");
+        receiver
+    }
+}
+
+fn encode(author: &PublicKey, round: Round) -> Vec<u8> {
+    [&author.0[..], &round.to_le_bytes()].concat()
+}
+
+fn decode(record: &[u8]) -> (PublicKey, Round) {
+    let author = PublicKey(record[..32].try_into().unwrap());
+    let round = Round::from_le_bytes(record[32..].try_into().unwrap());
+    (author, round)
+}
+
+/// Periodically syncs the log to disk (until the log is dropped).
+fn spawn_syncer(file: &Arc<Mutex<File>>, dirty: Arc<AtomicBool>, interval: u64) {
+    let file = Arc::downgrade(file);
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_millis(interval)).await;
+            let file = match file.upgrade() {
+                Some(file) => file,
+                None => return,
+            };
+            if dirty.swap(false, Ordering::SeqCst) {
+                spawn_blocking(move || file.lock().unwrap().sync_data())
+                    .await
+                    .expect("Failed to sync the vote log")
+                    .expect("Failed to sync the vote log");
+            }
+        }
+    });
+}
+
+/// Writes the votes to the log, off the loop of the `Core`.
+struct LogWriter {
+    /// The path of the log file.
+    path: PathBuf,
+    /// The log file (opened in append mode).
+    file: Arc<Mutex<File>>,
+    /// When to sync the log to disk.
+    fsync: FsyncPolicy,
+    /// Whether the log holds records that are not synced yet (under the periodic policy).
+    dirty: Arc<AtomicBool>,
+    /// The highest round we logged, for each author.
+    highest: HashMap<PublicKey, Round>,
+    /// The number of records in the log file.
+    records: usize,
+    /// Receives the votes to log.
+    rx_votes: Receiver<PendingVote>,
+}
+
+impl LogWriter {
+    async fn run(mut self) {
+        while let Some(vote) = self.rx_votes.recv().await {
+            // Write at once all the votes waiting.
+            let mut votes = vec![vote];
+            while let Ok(vote) = self.rx_votes.try_recv() {
+                votes.push(vote);
+            }
+            let mut bytes = Vec::with_capacity(votes.len() * RECORD_SIZE);
+            let mut notifiers = Vec::with_capacity(votes.len());
+            for (author, round, notifier) in votes {
+                let highest = self.highest.entry(author).or_default();
+                *highest = round.max(*highest);
+                bytes.extend(encode(&author, round));
+                notifiers.push(notifier);
+            }
+
+            let result = match self.records >= COMPACTION_THRESHOLD {
+                true => self.compact().await,
+                false => {
+                    self.records += notifiers.len();
+                    self.write(bytes).await
+                }
+            };
+            for notifier in notifiers {
+                let result = match &result {
+                    Ok(()) => Ok(()),
+                    Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                };
+                let _ = notifier.send(result);
+            }
+        }
+    }
+
+    /// Appends records to the log, syncing them if the fsync policy requires it.
+    async fn write(&mut self, bytes: Vec<u8>) -> io::Result<()> {
+        let file = self.file.clone();
+        let sync = self.fsync == FsyncPolicy::EveryVote;
+        spawn_blocking(move || {
+            let mut file = file.lock().unwrap();
+            file.write_all(&bytes)?;
+            if sync {
+                file.sync_data()?;
+            }
+            Ok::<_, io::Error>(())
+        })
+        .await
+        .expect("Failed to append to the vote log")?;
+
+        if self.fsync == FsyncPolicy::Periodic {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Rewrites the log with a single record per author. The new log is synced before replacing
+    /// the old one, and the directory is synced after, so that a crash during compaction loses no
+    /// vote.
+    async fn compact(&mut self) -> io::Result<()> {
+        let bytes: Vec<_> = self
+            .highest
+            .iter()
+            .flat_map(|(author, round)| encode(author, *round))
+            .collect();
+        self.records = self.highest.len();
+
+        let path = self.path.clone();
+        let file = self.file.clone();
+        spawn_blocking(move || {
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&bytes)?;
+            tmp.sync_all()?;
+            fs::rename(&tmp_path, &path)?;
+            let directory = match path.parent() {
+                Some(x) if !x.as_os_str().is_empty() => x,
+                _ => Path::new("."),
+            };
+            File::open(directory)?.sync_all()?;
+            *file.lock().unwrap() = OpenOptions::new().append(true).open(&path)?;
+            Ok(())
+        })
+        .await
+        .expect("Failed to compact the vote log")
+    }
+}
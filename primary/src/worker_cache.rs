@@ -0,0 +1,92 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::WorkerCacheError;
+use config::{Committee, WorkerAddresses, WorkerId};
+use crypto::PublicKey;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+#[cfg(test)]
+#[path = "tests/worker_cache_tests.rs"]
+pub mod worker_cache_tests;
+
+/// A change to the set of workers of an authority, submitted by the operator at runtime.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum WorkerUpdate {
+    /// Add a worker (or change its addresses).
+    Add(PublicKey, WorkerId, WorkerAddresses),
+    /// Remove a worker.
+    Remove(PublicKey, WorkerId),
+}
+
+/// The addresses of the workers of each authority. It starts from the workers listed in the
+/// committee and can be updated while the primary runs, so that workers can be added or removed
+/// without restarting the nodes. Cloning the cache shares it.
+#[derive(Clone)]
+pub struct WorkerCache {
+    workers: Arc<RwLock<HashMap<PublicKey, BTreeMap<WorkerId, WorkerAddresses>>>>,
+}
+
+impl WorkerCache {
+    pub fn new(committee: &Committee) -> Self {
+        let workers = committee
+            .authorities
+            .iter()
+            .map(|(name, authority)| (*name, authority.workers.clone().into_iter().collect()))
+            .collect();
+        Self {
+            workers: Arc::new(RwLock::new(workers)),
+        }
+    }
+
+    /// Returns the addresses of a specific worker (`id`) of a specific authority (`to`).
+    pub fn worker(
+        &self,
+        to: &PublicKey,
+        id: &WorkerId,
+    ) -> Result<WorkerAddresses, WorkerCacheError> {
+        self.workers
+            .read()
+            .unwrap()
+            .get(to)
+            .ok_or(WorkerCacheError::UnknownAuthority(*to))?
+            .get(id)
+            .cloned()
+            .ok_or(WorkerCacheError::UnknownWorker(*to, *id))
+    }
+
+    /// Returns the addresses of all our workers.
+    pub fn our_workers(&self, myself: &PublicKey) -> Vec<WorkerAddresses> {
+        self.workers
+            .read()
+            .unwrap()
+            .get(myself)
+            .map(|x| x.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Applies a change to the set of workers. Only the workers of the authorities of the committee
+    /// can be changed.
+    pub fn update(&self, update: WorkerUpdate) -> Result<(), WorkerCacheError> {
+        let mut workers = self.workers.write().unwrap();
+        match update {
+            WorkerUpdate::Add(name, id, addresses) => {
+                workers
+                    .get_mut(&name)
+                    .ok_or(WorkerCacheError::UnknownAuthority(name))?
+                    .insert(id, addresses);
+                info!("Added worker {} of {}", id, name);
+            }
+            WorkerUpdate::Remove(name, id) => {
+                workers
+                    .get_mut(&name)
+                    .ok_or(WorkerCacheError::UnknownAuthority(name))?
+                    .remove(&id)
+                    .ok_or(WorkerCacheError::UnknownWorker(name, id))?;
+                info!("Removed worker {} of {}", id, name);
+            }
+        }
+        Ok(())
+    }
+}
@@ -13,10 +13,53 @@ type StoreResult<T> = Result<T, StoreError>;
 type Key = Vec<u8>;
 type Value = Vec<u8>;
 
+/// The minimum number of awaited keys at which we look for the readers that gave up waiting.
+const MIN_SWEEP_SIZE: usize = 1_000;
+
+/// The readers waiting for keys to be written (see `Store::notify_read`). A reader gives up by
+/// dropping its future: we forget those readers whenever the number of awaited keys doubles, so
+/// that the obligations remain proportional to the readers still waiting.
+struct Obligations {
+    senders: HashMap<Key, VecDeque<oneshot::Sender<StoreResult<Value>>>>,
+    /// The number of awaited keys at which we look for the readers that gave up.
+    sweep_size: usize,
+}
+
+impl Obligations {
+    fn new() -> Self {
+        Self {
+            senders: HashMap::new(),
+            sweep_size: MIN_SWEEP_SIZE,
+        }
+    }
+
+    /// Registers a reader waiting for the specified key.
+    fn add(&mut self, key: Key, sender: oneshot::Sender<StoreResult<Value>>) {
+        let senders = self.senders.entry(key).or_default();
+        senders.retain(|x| !x.is_closed());
+        senders.push_back(sender);
+        if self.senders.len() >= self.sweep_size {
+            self.senders.retain(|_, senders| {
+                senders.retain(|x| !x.is_closed());
+                !senders.is_empty()
+            });
+            self.sweep_size = MIN_SWEEP_SIZE.max(2 * self.senders.len());
+        }
+    }
+
+    /// Returns the readers waiting for the specified key.
+    fn take(&mut self, key: &[u8]) -> Option<VecDeque<oneshot::Sender<StoreResult<Value>>>> {
+        self.senders.remove(key)
+    }
+}
+
 pub enum StoreCommand {
     Write(Key, Value),
+    Delete(Key, oneshot::Sender<StoreResult<()>>),
     Read(Key, oneshot::Sender<StoreResult<Option<Value>>>),
     NotifyRead(Key, oneshot::Sender<StoreResult<Value>>),
+    ReadAll(oneshot::Sender<Vec<(Key, Value)>>),
+    Size(oneshot::Sender<StoreResult<u64>>),
 }
 
 #[derive(Clone)]
@@ -27,19 +70,23 @@ pub struct Store {
 impl Store {
     pub fn new(path: &str) -> StoreResult<Self> {
         let db = rocksdb::DB::open_default(path)?;
-        let mut obligations = HashMap::<_, VecDeque<oneshot::Sender<_>>>::new();
+        let mut obligations = Obligations::new();
         let (tx, mut rx) = channel(100);
         tokio::spawn(async move {
             while let Some(command) = rx.recv().await {
                 match command {
                     StoreCommand::Write(key, value) => {
                         let _ = db.put(&key, &value);
-                        if let Some(mut senders) = obligations.remove(&key) {
+                        if let Some(mut senders) = obligations.take(&key) {
                             while let Some(s) = senders.pop_front() {
                                 let _ = s.send(Ok(value.clone()));
                             }
                         }
                     }
+                    StoreCommand::Delete(key, sender) => {
+                        let response = db.delete(&key);
+                        let _ = sender.send(response);
+                    }
                     StoreCommand::Read(key, sender) => {
                         let response = db.get(&key);
                         let _ = sender.send(response);
@@ -47,15 +94,25 @@ impl Store {
                     StoreCommand::NotifyRead(key, sender) => {
                         let response = db.get(&key);
                         match response {
-                            Ok(None) => obligations
-                                .entry(key)
-                                .or_insert_with(VecDeque::new)
-                                .push_back(sender),
+                            Ok(None) => obligations.add(key, sender),
                             _ => {
                                 let _ = sender.send(response.map(|x| x.unwrap()));
                             }
                         }
                     }
+                    StoreCommand::ReadAll(sender) => {
+                        let response = db
+                            .iterator(rocksdb::IteratorMode::Start)
+                            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                            .collect();
+                        let _ = sender.send(response);
+                    }
+                    StoreCommand::Size(sender) => {
+                        let response = db
+                            .property_int_value("rocksdb.estimate-live-data-size")
+                            .map(|x| x.unwrap_or_default());
+                        let _ = sender.send(response);
+                    }
                 }
             }
         });
@@ -68,6 +125,16 @@ impl Store {
         }
     }
 
+    pub async fn delete(&mut self, key: Key) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::Delete(key, sender)).await {
+            panic!("Failed to send Delete command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to Delete command from store")
+    }
+
     pub async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
@@ -91,4 +158,25 @@ impl Store {
             .await
             .expect("Failed to receive reply to NotifyRead command from store")
     }
+
+    pub async fn read_all(&mut self) -> Vec<(Key, Value)> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::ReadAll(sender)).await {
+            panic!("Failed to send ReadAll command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to ReadAll command from store")
+    }
+
+    /// Returns an estimate of the size of the data held by the store (in bytes).
+    pub async fn size(&mut self) -> StoreResult<u64> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::Size(sender)).await {
+            panic!("Failed to send Size command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to Size command from store")
+    }
 }
@@ -31,6 +31,25 @@ async fn read_write_value() {
     assert_eq!(read_value.unwrap(), value);
 }
 
+#[tokio::test]
+async fn delete_value() {
+    // Create new store.
+    let path = ".db_test_delete_value";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write value to the store and delete it.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+    store.write(key.clone(), value).await;
+    assert!(store.delete(key.clone()).await.is_ok());
+
+    // Ensure the value is gone.
+    let result = store.read(key).await;
+    assert!(result.is_ok());
+    assert!(result.unwrap().is_none());
+}
+
 #[tokio::test]
 async fn read_unknown_key() {
     // Create new store.
@@ -72,3 +91,62 @@ async fn read_notify() {
     store.write(key, value).await;
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn read_all() {
+    // Create new store.
+    let path = ".db_test_read_all";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write two values to the store.
+    let entries = vec![
+        (vec![0u8, 1u8, 2u8, 3u8], vec![4u8, 5u8, 6u8, 7u8]),
+        (vec![8u8, 9u8, 10u8, 11u8], vec![12u8, 13u8, 14u8, 15u8]),
+    ];
+    for (key, value) in &entries {
+        store.write(key.clone(), value.clone()).await;
+    }
+
+    // Read all values back (in key order).
+    assert_eq!(store.read_all().await, entries);
+}
+
+#[tokio::test]
+async fn read_size() {
+    // Create new store.
+    let path = ".db_test_read_size";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write value to the store.
+    store.write(vec![0u8, 1u8], vec![2u8, 3u8]).await;
+
+    // Ensure we can estimate the size of the store.
+    assert!(store.size().await.is_ok());
+}
+
+#[test]
+fn forget_abandoned_readers() {
+    let mut obligations = Obligations::new();
+
+    // Readers waiting for many keys give up (dropping their receiver).
+    for i in 0..MIN_SWEEP_SIZE as u64 - 1 {
+        let (sender, _) = oneshot::channel();
+        obligations.add(i.to_le_bytes().to_vec(), sender);
+    }
+    assert_eq!(obligations.senders.len(), MIN_SWEEP_SIZE - 1);
+
+    // Ensure we forget them once the number of awaited keys reaches the threshold, but not the
+    // reader still waiting.
+    let (sender, mut receiver) = oneshot::channel();
+    obligations.add(b"key".to_vec(), sender);
+    assert_eq!(obligations.senders.len(), 1);
+    let _ = obligations
+        .take(b"key")
+        .unwrap()
+        .pop_front()
+        .unwrap()
+        .send(Ok(vec![1]));
+    assert_eq!(receiver.try_recv().unwrap().unwrap(), vec![1]);
+}
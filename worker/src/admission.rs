@@ -0,0 +1,101 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::collections::{BTreeSet, HashMap};
+use thiserror::Error;
+
+#[cfg(test)]
+#[path = "tests/admission_tests.rs"]
+pub mod admission_tests;
+
+/// The number of senders above which we forget the senders without pending transactions.
+const MAX_TRACKED_SENDERS: usize = 100_000;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AdmissionError {
+    #[error("Nonce {0} skips ahead of the next expected nonce {1}")]
+    NonceGap(u64, u64),
+
+    #[error("Nonce {0} is stale (the next expected nonce is {1})")]
+    StaleNonce(u64, u64),
+
+    #[error("Sender already has {0} pending transactions")]
+    TooManyPending(usize),
+}
+
+/// What we know of a sender.
+#[derive(Default)]
+struct Account {
+    /// The next nonce we expect from the sender: one past the nonces we admitted without gaps
+    /// (rolled back when we drop one of them).
+    next: u64,
+    /// The nonces of the transactions of the sender waiting to be batched.
+    pending: BTreeSet<u64>,
+}
+
+/// Admits the transactions of each sender in nonce order, so that a single sender cannot flood
+/// our batches with transactions that will never execute (or hold too many of our pending
+/// slots). The first transaction we see from a sender may carry any nonce.
+pub struct SenderAdmission {
+    /// The maximum number of pending transactions of each sender.
+    max_pending: usize,
+    /// The senders we know of.
+    accounts: HashMap<Vec<u8>, Account>,
+}
+
+impl SenderAdmission {
+    pub fn new(max_pending: usize) -> Self {
+        Self {
+            max_pending,
+            accounts: HashMap::new(),
+        }
+    }
+
+    /// Checks whether we may admit a transaction of the sender with the specified nonce. Stale
+    /// nonces are only accepted when the transaction replaces a pending transaction.
+    pub fn check(
+        &self,
+        sender: &[u8],
+        nonce: u64,
+        replacement: bool,
+    ) -> Result<(), AdmissionError> {
+        let account = match self.accounts.get(sender) {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+        if nonce > account.next {
+            return Err(AdmissionError::NonceGap(nonce, account.next));
+        }
+        if nonce < account.next && !replacement {
+            return Err(AdmissionError::StaleNonce(nonce, account.next));
+        }
+        if nonce == account.next && account.pending.len() >= self.max_pending {
+            return Err(AdmissionError::TooManyPending(account.pending.len()));
+        }
+        Ok(())
+    }
+
+    /// Records the admission of a (checked) transaction.
+    pub fn admit(&mut self, sender: &[u8], nonce: u64) {
+        if self.accounts.len() >= MAX_TRACKED_SENDERS && !self.accounts.contains_key(sender) {
+            self.accounts
+                .retain(|_, account| !account.pending.is_empty());
+        }
+        let account = self.accounts.entry(sender.to_vec()).or_default();
+        account.pending.insert(nonce);
+        account.next = account.next.max(nonce + 1);
+        // Skip the nonces still pending after a resubmission filled the gap before them.
+        while account.pending.contains(&account.next) {
+            account.next += 1;
+        }
+    }
+
+    /// Records that a transaction of the sender no longer waits to be batched. If we dropped it
+    /// (rather than batched it), the sender may submit its nonce again: we expect it next.
+    pub fn release(&mut self, sender: &[u8], nonce: u64, batched: bool) {
+        if let Some(account) = self.accounts.get_mut(sender) {
+            account.pending.remove(&nonce);
+            if !batched {
+                account.next = account.next.min(nonce);
+            }
+        }
+    }
+}
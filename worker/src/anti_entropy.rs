@@ -0,0 +1,182 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::erasure::shard_key;
+use crate::metrics::WorkerMetrics;
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{AntiEntropyParameters, Committee, WorkerId};
+use crypto::{Digest, PublicKey};
+use log::{debug, error};
+use network::{NetworkContext, QueueLimits, SimpleSender};
+use std::collections::{HashMap, VecDeque};
+use store::Store;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/anti_entropy_tests.rs"]
+pub mod anti_entropy_tests;
+
+/// The maximum number of digests we advertise at once (and look up in a summary we receive).
+const MAX_SUMMARY_SIZE: usize = 1_000;
+
+/// Repairs the gaps left by dropped broadcasts, independently of the sync driven by our primary.
+/// We periodically advertise the digests of the batches we recently sealed to the other workers,
+/// and pull the batches advertised by the other workers that we do not hold.
+pub struct AntiEntropy {
+    /// The public key of this authority.
+    name: PublicKey,
+    /// The id of this worker.
+    id: WorkerId,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// The delay between two advertisements of our batches.
+    interval: Duration,
+    /// How long we keep advertising a batch we sealed.
+    window: Duration,
+    /// Receives the digests of the batches we seal.
+    rx_sealed: Receiver<Digest>,
+    /// Receives the summaries of the other workers (and the authority of their sender).
+    rx_summary: Receiver<(Vec<Digest>, PublicKey)>,
+    /// A network sender to send our summaries and batch requests.
+    network: SimpleSender,
+    /// The batches we recently sealed, in the order we sealed them.
+    recent: VecDeque<(Instant, Digest)>,
+    /// The missing batches we requested, and when.
+    requested: HashMap<Digest, Instant>,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
+}
+
+impl AntiEntropy {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        store: Store,
+        parameters: AntiEntropyParameters,
+        rx_sealed: Receiver<Digest>,
+        rx_summary: Receiver<(Vec<Digest>, PublicKey)>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: WorkerMetrics,
+        context: NetworkContext,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                name,
+                id,
+                committee,
+                store,
+                interval: Duration::from_millis(parameters.interval),
+                window: Duration::from_millis(parameters.window),
+                rx_sealed,
+                rx_summary,
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics.network.clone()),
+                recent: VecDeque::new(),
+                requested: HashMap::new(),
+                metrics,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Sends the digests of the batches we sealed during the window to the other workers.
+    async fn advertise(&mut self) {
+        let now = Instant::now();
+        while let Some((time, _)) = self.recent.front() {
+            if now.duration_since(*time) < self.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.is_empty() {
+            return;
+        }
+
+        let digests = self.recent.iter().map(|(_, x)| x.clone()).collect();
+        let message = WorkerMessage::BatchSummary(digests, self.name);
+        let bytes = bincode::serialize(&message).expect("Failed to serialize batch summary");
+        let addresses = self
+            .committee
+            .others_workers(&self.name, &self.id)
+            .iter()
+            .map(|(_, x)| x.worker_to_worker)
+            .collect();
+        self.network.broadcast(addresses, Bytes::from(bytes)).await;
+    }
+
+    /// Requests the batches of the summary we do not hold from the worker that sent it. We do not
+    /// request a batch again before the next advertisement, to give the worker time to reply.
+    async fn pull(&mut self, mut digests: Vec<Digest>, origin: PublicKey) {
+        let address = match self.committee.worker(&origin, &self.id) {
+            Ok(x) => x.worker_to_worker,
+            Err(e) => {
+                debug!("Unexpected batch summary: {}", e);
+                return;
+            }
+        };
+
+        let now = Instant::now();
+        let interval = self.interval;
+        self.requested
+            .retain(|_, time| now.duration_since(*time) < interval);
+
+        digests.truncate(MAX_SUMMARY_SIZE);
+        let mut missing = Vec::new();
+        for digest in digests {
+            if self.requested.contains_key(&digest) {
+                continue;
+            }
+            // Holding our shard of the batch is enough.
+            match (
+                self.store.read(digest.to_vec()).await,
+                self.store.read(shard_key(&digest)).await,
+            ) {
+                (Ok(None), Ok(None)) => missing.push(digest),
+                (Err(e), _) | (_, Err(e)) => error!("{}", e),
+                _ => (),
+            }
+        }
+        if missing.is_empty() {
+            return;
+        }
+
+        debug!("Pulling {} missing batches from {}", missing.len(), origin);
+        self.metrics.anti_entropy_pulls.inc_by(missing.len() as u64);
+        for digest in &missing {
+            self.requested.insert(digest.clone(), now);
+        }
+        let message = WorkerMessage::BatchRequest(missing, self.name);
+        let bytes = bincode::serialize(&message).expect("Failed to serialize batch request");
+        self.network.send(address, Bytes::from(bytes)).await;
+    }
+
+    async fn run(&mut self) {
+        let timer = sleep(self.interval);
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                Some(digest) = self.rx_sealed.recv() => {
+                    if self.recent.len() >= MAX_SUMMARY_SIZE {
+                        self.recent.pop_front();
+                    }
+                    self.recent.push_back((Instant::now(), digest));
+                },
+                Some((digests, origin)) = self.rx_summary.recv() => self.pull(digests, origin).await,
+                () = &mut timer => {
+                    self.advertise().await;
+                    timer.as_mut().reset(Instant::now() + self.interval);
+                }
+            }
+        }
+    }
+}
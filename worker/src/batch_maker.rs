@@ -1,95 +1,324 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_sizer::BatchSizer;
+use crate::compression::compress;
+use crate::erasure::ShardLayout;
+use crate::mempool::Mempool;
+use crate::metrics::WorkerMetrics;
 use crate::quorum_waiter::QuorumWaiterMessage;
-use crate::worker::WorkerMessage;
+use crate::worker::{ReceiptStatus, WorkerMessage};
 use bytes::Bytes;
-#[cfg(feature = "benchmark")]
-use crypto::Digest;
-use crypto::PublicKey;
-#[cfg(feature = "benchmark")]
+use config::{BatchCompression, BatchDissemination, Committee, TransactionDedup};
+use crypto::{Digest, PublicKey};
 use ed25519_dalek::{Digest as _, Sha512};
 #[cfg(feature = "benchmark")]
 use log::info;
-use network::ReliableSender;
-#[cfg(feature = "benchmark")]
+use network::{Compression, NetworkContext, QueueLimits, ReliableSender, RetryPolicy};
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto as _;
 use std::net::SocketAddr;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/batch_maker_tests.rs"]
 pub mod batch_maker_tests;
 
-pub type Transaction = Vec<u8>;
+/// The maximum number of transactions we move from our inbox into the mempool in a row.
+const MAX_DRAINED: usize = 1_000;
+
+/// The size of a serialized `WorkerMessage::Batch` message without transactions (the tag of the
+/// message and the length of the batch).
+const SERIALIZED_BATCH_HEADER: usize = 12;
+
+/// The size bincode adds to each serialized transaction (its length).
+const SERIALIZED_TRANSACTION_HEADER: usize = 8;
+
+pub type Transaction = Bytes;
 pub type Batch = Vec<Transaction>;
 
+/// What we send the other workers of each batch we seal.
+pub enum Broadcast {
+    /// The whole batch, to every other worker.
+    Batch,
+    /// A single Reed-Solomon coded shard of the batch, to each other worker.
+    Shards(ShardLayout),
+    /// Only the digest of the batch: the other workers pull the batch when they need it.
+    Digest,
+}
+
+impl Broadcast {
+    pub fn new(committee: &Committee) -> Self {
+        match committee.batch_dissemination {
+            BatchDissemination::Replicated => Self::Batch,
+            BatchDissemination::ErasureCoded => {
+                ShardLayout::new(committee).map_or(Self::Batch, Self::Shards)
+            }
+            BatchDissemination::Pull => Self::Digest,
+        }
+    }
+}
+
+/// Returns the size of a serialized `WorkerMessage::Batch` message holding the specified number of
+/// transactions, of the specified total size.
+pub fn serialized_batch_size(transactions: usize, bytes: usize) -> usize {
+    SERIALIZED_BATCH_HEADER + transactions * SERIALIZED_TRANSACTION_HEADER + bytes
+}
+
+/// Notifies a client of the fate of its transaction.
+pub type ReceiptSender = oneshot::Sender<ReceiptStatus>;
+
+/// Remembers the transactions submitted during the last time window, so that the retries of
+/// clients do not end up twice in our batches. Transactions are identified by their hash. We forget
+/// the transactions the mempool drops, so that their retries are not mistaken for duplicates.
+pub struct DedupCache {
+    /// The maximum number of transactions we remember.
+    capacity: usize,
+    /// The time during which we remember a transaction.
+    window: Duration,
+    /// The transactions we remember, along with their entry number in `order`.
+    seen: HashMap<Digest, u64>,
+    /// The transactions we remember (along with the time of their first submission and their entry
+    /// number), oldest first. It may still hold the transactions we forgot (see `forget`).
+    order: VecDeque<(Instant, u64, Digest)>,
+    /// The entry number of the next transaction we remember.
+    next: u64,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
+}
+
+impl DedupCache {
+    pub fn new(parameters: TransactionDedup, metrics: WorkerMetrics) -> Self {
+        Self {
+            capacity: parameters.capacity,
+            window: Duration::from_millis(parameters.window),
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            next: 0,
+            metrics,
+        }
+    }
+
+    /// Forgets the oldest transaction of `order` (unless we already forgot it).
+    fn pop_oldest(&mut self) {
+        if let Some((_, number, digest)) = self.order.pop_front() {
+            if self.seen.get(&digest) == Some(&number) {
+                self.seen.remove(&digest);
+            }
+        }
+    }
+
+    /// Returns `true` if the transaction was not submitted during the last time window.
+    pub fn check(&mut self, transaction: &[u8]) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        self.metrics.dedup_lookups.inc();
+
+        // Forget the transactions submitted before the window.
+        let now = Instant::now();
+        while let Some((time, _, _)) = self.order.front() {
+            if now.duration_since(*time) < self.window {
+                break;
+            }
+            self.pop_oldest();
+        }
+
+        let digest = Digest(Sha512::digest(transaction)[..32].try_into().unwrap());
+        if self.seen.contains_key(&digest) {
+            self.metrics.dedup_hits.inc();
+            return false;
+        }
+
+        if self.order.len() >= self.capacity {
+            self.pop_oldest();
+        }
+        self.seen.insert(digest.clone(), self.next);
+        self.order.push_back((now, self.next, digest));
+        self.next += 1;
+        true
+    }
+
+    /// Forgets a transaction we did not batch (such as a transaction evicted from the mempool), so
+    /// that its client may submit it again.
+    pub fn forget(&mut self, transaction: &[u8]) {
+        if self.capacity > 0 {
+            let digest = Digest(Sha512::digest(transaction)[..32].try_into().unwrap());
+            self.seen.remove(&digest);
+        }
+    }
+}
+
 /// Assemble clients transactions into batches.
 pub struct BatchMaker {
-    /// The preferred batch size (in bytes).
-    batch_size: usize,
-    /// The maximum delay after which to seal the batch (in ms).
-    max_batch_delay: u64,
+    /// Decides the preferred size and the max delay of our batches.
+    sizer: BatchSizer,
     /// Channel to receive transactions from the network.
-    rx_transaction: Receiver<Transaction>,
+    rx_transaction: Receiver<(Transaction, Option<ReceiptSender>)>,
+    /// Receives the time the other workers took to acknowledge our batches.
+    rx_latency: Receiver<Duration>,
     /// Output channel to deliver sealed batches to the `QuorumWaiter`.
     tx_message: Sender<QuorumWaiterMessage>,
     /// The network addresses of the other workers that share our worker id.
     workers_addresses: Vec<(PublicKey, SocketAddr)>,
+    /// Drops the transactions clients submit more than once.
+    dedup: DedupCache,
+    /// How we compress our batches.
+    compression: BatchCompression,
+    /// The maximum size of a serialized batch.
+    max_serialized_size: usize,
+    /// What we send the other workers of our batches.
+    broadcast: Broadcast,
+    /// The transactions waiting to be batched.
+    mempool: Mempool,
     /// Holds the current batch.
     current_batch: Batch,
     /// Holds the size of the current batch (in bytes).
     current_batch_size: usize,
+    /// The clients waiting for the receipt of a transaction of the current batch.
+    waiters: Vec<ReceiptSender>,
     /// A network sender to broadcast the batches to the other workers.
     network: ReliableSender,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
 }
 
 impl BatchMaker {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        batch_size: usize,
-        max_batch_delay: u64,
-        rx_transaction: Receiver<Transaction>,
+        sizer: BatchSizer,
+        rx_transaction: Receiver<(Transaction, Option<ReceiptSender>)>,
+        rx_latency: Receiver<Duration>,
         tx_message: Sender<QuorumWaiterMessage>,
         workers_addresses: Vec<(PublicKey, SocketAddr)>,
+        mempool: Mempool,
+        dedup: TransactionDedup,
+        compression: BatchCompression,
+        max_serialized_size: usize,
+        broadcast: Broadcast,
+        retry_policy: RetryPolicy,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        transport_compression: Compression,
+        metrics: WorkerMetrics,
+        context: NetworkContext,
     ) {
         tokio::spawn(async move {
+            let batch_size = sizer.batch_size();
             Self {
-                batch_size,
-                max_batch_delay,
+                sizer,
                 rx_transaction,
+                rx_latency,
                 tx_message,
                 workers_addresses,
+                dedup: DedupCache::new(dedup, metrics.clone()),
+                compression,
+                max_serialized_size,
+                broadcast,
+                mempool,
                 current_batch: Batch::with_capacity(batch_size * 2),
                 current_batch_size: 0,
-                network: ReliableSender::new(),
+                waiters: Vec::new(),
+                network: ReliableSender::new()
+                    .with_context(context)
+                    .with_retry_policy(retry_policy)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_compression(transport_compression)
+                    .with_metrics(metrics.network.clone()),
+                metrics,
             }
             .run()
             .await;
         });
     }
 
+    /// Queues a transaction in the mempool (unless it is a duplicate).
+    fn enqueue(&mut self, transaction: Transaction, waiter: Option<ReceiptSender>) {
+        if !self.dedup.check(&transaction) {
+            if let Some(waiter) = waiter {
+                let _ = waiter.send(ReceiptStatus::Duplicate);
+            }
+            return;
+        }
+        self.sizer.record_arrival(transaction.len());
+        self.mempool.insert(transaction, waiter);
+        self.forget_dropped();
+    }
+
+    /// Forgets the transactions the mempool dropped, so that their clients may submit them again.
+    fn forget_dropped(&mut self) {
+        for transaction in self.mempool.drain_dropped() {
+            self.dedup.forget(&transaction);
+        }
+    }
+
+    /// Moves the pending transactions into the current batch, highest priority first, until the
+    /// batch reaches its preferred size (or cannot fit the next transaction). Returns whether the
+    /// batch holds any transaction (the pending transactions may all have expired).
+    fn fill(&mut self) -> bool {
+        let batch_size = self.sizer.batch_size();
+        while self.current_batch_size < batch_size {
+            let next = match self.mempool.peek_size() {
+                Some(x) => x,
+                None => break,
+            };
+            let size =
+                serialized_batch_size(self.current_batch.len() + 1, self.current_batch_size + next);
+            if !self.current_batch.is_empty() && size > self.max_serialized_size {
+                break;
+            }
+            match self.mempool.pop() {
+                Some((transaction, waiter)) => {
+                    self.current_batch_size += transaction.len();
+                    self.current_batch.push(transaction);
+                    self.waiters.extend(waiter);
+                }
+                None => break,
+            }
+        }
+        self.forget_dropped();
+        !self.current_batch.is_empty()
+    }
+
     /// Main loop receiving incoming transactions and creating batches.
     async fn run(&mut self) {
-        let timer = sleep(Duration::from_millis(self.max_batch_delay));
+        let timer = sleep(self.sizer.max_batch_delay());
         tokio::pin!(timer);
 
         loop {
             tokio::select! {
                 // Assemble client transactions into batches of preset size.
-                Some(transaction) = self.rx_transaction.recv() => {
-                    self.current_batch_size += transaction.len();
-                    self.current_batch.push(transaction);
-                    if self.current_batch_size >= self.batch_size {
-                        self.seal().await;
-                        timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                Some((transaction, waiter)) = self.rx_transaction.recv() => {
+                    self.enqueue(transaction, waiter);
+
+                    // Under load, move our inbox into the mempool so that transactions of higher
+                    // priority overtake the others and the mempool (rather than the inbox) decides
+                    // which transactions to drop.
+                    for _ in 0..MAX_DRAINED {
+                        match self.rx_transaction.try_recv() {
+                            Ok((transaction, waiter)) => self.enqueue(transaction, waiter),
+                            Err(_) => break,
+                        }
+                    }
+
+                    while self.mempool.size() >= self.sizer.batch_size() {
+                        if self.fill() {
+                            self.seal().await;
+                        }
+                        timer.as_mut().reset(Instant::now() + self.sizer.max_batch_delay());
                     }
                 },
 
+                // Adapt our batches to the time the other workers take to acknowledge them.
+                Some(latency) = self.rx_latency.recv() => self.sizer.record_latency(latency),
+
                 // If the timer triggers, seal the batch even if it contains few transactions.
                 () = &mut timer => {
-                    if !self.current_batch.is_empty() {
+                    if self.fill() {
                         self.seal().await;
                     }
-                    timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                    timer.as_mut().reset(Instant::now() + self.sizer.max_batch_delay());
                 }
             }
 
@@ -100,6 +329,11 @@ impl BatchMaker {
 
     /// Seal and broadcast the current batch.
     async fn seal(&mut self) {
+        let start = Instant::now();
+        self.metrics
+            .batch_size
+            .observe(self.current_batch_size as f64);
+
         #[cfg(feature = "benchmark")]
         let size = self.current_batch_size;
 
@@ -112,21 +346,43 @@ impl BatchMaker {
             .filter_map(|tx| tx[1..9].try_into().ok())
             .collect();
 
-        // Serialize the batch.
+        // Serialize (and compress) the batch.
         self.current_batch_size = 0;
         let batch: Vec<_> = self.current_batch.drain(..).collect();
-        let message = WorkerMessage::Batch(batch);
-        let serialized = bincode::serialize(&message).expect("Failed to serialize our own batch");
+        let message = match self.compression {
+            BatchCompression::None => WorkerMessage::Batch(batch),
+            compression => {
+                let bytes = bincode::serialize(&batch).expect("Failed to serialize our own batch");
+                let compressed = compress(compression, &bytes);
+                // Incompressible batches would only grow (possibly past the maximum size).
+                match compressed.len() < bytes.len() {
+                    true => WorkerMessage::CompressedBatch(compression, compressed),
+                    false => WorkerMessage::Batch(batch),
+                }
+            }
+        };
+        let serialized =
+            Bytes::from(bincode::serialize(&message).expect("Failed to serialize our own batch"));
+
+        // Hash the batch (coding it into shards first, if we disseminate shards: the digest then
+        // commits to them).
+        let (digest, shards) = match &self.broadcast {
+            Broadcast::Shards(layout) => layout.shards(&serialized),
+            _ => (
+                Digest(Sha512::digest(&serialized)[..32].try_into().unwrap()),
+                Vec::new(),
+            ),
+        };
+
+        // Tell the clients waiting for a receipt which batch holds their transaction.
+        if !self.waiters.is_empty() {
+            for waiter in self.waiters.drain(..) {
+                let _ = waiter.send(ReceiptStatus::Sealed(digest.clone()));
+            }
+        }
 
         #[cfg(feature = "benchmark")]
         {
-            // NOTE: This is one extra hash that is only needed to print the following log entries.
-            let digest = Digest(
-                Sha512::digest(&serialized).as_slice()[..32]
-                    .try_into()
-                    .unwrap(),
-            );
-
             for id in tx_ids {
                 // NOTE: This log entry is used to compute performance.
                 info!(
@@ -140,14 +396,41 @@ impl BatchMaker {
             info!("Batch {:?} contains {} B", digest, size);
         }
 
-        // Broadcast the batch through the network.
-        let (names, addresses): (Vec<_>, _) = self.workers_addresses.iter().cloned().unzip();
-        let bytes = Bytes::from(serialized.clone());
-        let handlers = self.network.broadcast(addresses, bytes).await;
+        // Broadcast the batch through the network (or a single shard of it to each worker, or only
+        // its digest).
+        let (names, addresses): (Vec<_>, Vec<_>) = self.workers_addresses.iter().cloned().unzip();
+        let handlers = match &self.broadcast {
+            Broadcast::Batch => self.network.broadcast(addresses, serialized.clone()).await,
+            Broadcast::Digest => {
+                let message = WorkerMessage::BatchAnnouncement(digest.clone());
+                let bytes =
+                    bincode::serialize(&message).expect("Failed to serialize our announcement");
+                self.network.broadcast(addresses, Bytes::from(bytes)).await
+            }
+            Broadcast::Shards(layout) => {
+                let mut shards: Vec<_> = shards.into_iter().map(Some).collect();
+                let mut handlers = Vec::new();
+                for (name, address) in names.iter().zip(addresses) {
+                    let index = layout.index(name).expect("Unknown worker");
+                    let shard = shards[index].take().expect("Duplicate worker");
+                    let message = WorkerMessage::BatchShard(digest.clone(), shard);
+                    let bytes =
+                        bincode::serialize(&message).expect("Failed to serialize our own shard");
+                    handlers.push(self.network.send(address, Bytes::from(bytes)).await);
+                }
+                handlers
+            }
+        };
+        self.metrics
+            .batch_seal_latency
+            .observe(start.elapsed().as_secs_f64());
 
-        // Send the batch through the deliver channel for further processing.
+        // Send the batch through the deliver channel for further processing. It is part of our
+        // backlog until the `QuorumWaiter` gathers enough acknowledgements.
+        self.metrics.batch_backlog.inc();
         self.tx_message
             .send(QuorumWaiterMessage {
+                digest,
                 batch: serialized,
                 handlers: names.into_iter().zip(handlers.into_iter()).collect(),
             })
@@ -0,0 +1,111 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::AdaptiveBatching;
+use tokio::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/batch_sizer_tests.rs"]
+pub mod batch_sizer_tests;
+
+/// The number of additive steps taking the batches from their smallest to their largest size.
+const INCREASE_STEPS: u64 = 20;
+
+/// The period over which we measure the rate at which transactions arrive (in ms).
+const RATE_WINDOW: u64 = 1_000;
+
+/// Decides the preferred size and the max delay of our batches. Unless adaptive, these are the
+/// `batch_size` and `max_batch_delay` parameters. Otherwise, both grow by a fixed step whenever the
+/// other workers take longer than the target latency to acknowledge a batch (so that we send them
+/// fewer, larger batches), and halve whenever they are faster (additive increase, multiplicative
+/// decrease). We also never wait for more bytes than our clients submit within the batch delay, so
+/// that the batches stay small when idle.
+pub struct BatchSizer {
+    /// The largest preferred batch size (in bytes).
+    max_size: usize,
+    /// The largest batch delay (in ms).
+    max_delay: u64,
+    /// The bounds and the target latency of adaptive batching (if enabled).
+    adaptive: Option<AdaptiveBatching>,
+    /// The current preferred batch size (in bytes).
+    size: usize,
+    /// The current batch delay (in ms).
+    delay: u64,
+    /// The rate at which transactions arrived during the last window (in bytes per ms).
+    rate: f64,
+    /// The number of bytes that arrived since the start of the current window.
+    arrived: usize,
+    /// The start of the current window.
+    since: Instant,
+}
+
+impl BatchSizer {
+    pub fn new(
+        batch_size: usize,
+        max_batch_delay: u64,
+        adaptive: Option<AdaptiveBatching>,
+    ) -> Self {
+        // Adaptive batching starts from the smallest batches, as if idle.
+        let (size, delay) = match &adaptive {
+            Some(x) => (
+                x.min_batch_size.min(batch_size),
+                x.min_batch_delay.min(max_batch_delay),
+            ),
+            None => (batch_size, max_batch_delay),
+        };
+        Self {
+            max_size: batch_size,
+            max_delay: max_batch_delay,
+            adaptive,
+            size,
+            delay,
+            rate: 0.0,
+            arrived: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Returns the preferred size of the next batch (in bytes).
+    pub fn batch_size(&self) -> usize {
+        match &self.adaptive {
+            Some(x) => {
+                let expected = (self.rate * self.delay as f64) as usize;
+                self.size.min(expected.max(x.min_batch_size))
+            }
+            None => self.size,
+        }
+    }
+
+    /// Returns the delay after which we seal the next batch.
+    pub fn max_batch_delay(&self) -> Duration {
+        Duration::from_millis(self.delay)
+    }
+
+    /// Records the arrival of a transaction of the specified size.
+    pub fn record_arrival(&mut self, bytes: usize) {
+        self.arrived += bytes;
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_millis(RATE_WINDOW) {
+            self.rate = self.arrived as f64 / (elapsed.as_secs_f64() * 1_000.0);
+            self.arrived = 0;
+            self.since = Instant::now();
+        }
+    }
+
+    /// Records the time the other workers took to acknowledge one of our batches.
+    pub fn record_latency(&mut self, latency: Duration) {
+        let adaptive = match &self.adaptive {
+            Some(x) => x,
+            None => return,
+        };
+        let min_size = adaptive.min_batch_size.min(self.max_size);
+        let min_delay = adaptive.min_batch_delay.min(self.max_delay);
+        if latency > Duration::from_millis(adaptive.target_latency) {
+            let size_step = ((self.max_size - min_size) as u64 / INCREASE_STEPS).max(1);
+            let delay_step = ((self.max_delay - min_delay) / INCREASE_STEPS).max(1);
+            self.size = (self.size + size_step as usize).min(self.max_size);
+            self.delay = (self.delay + delay_step).min(self.max_delay);
+        } else {
+            self.size = (self.size / 2).max(min_size);
+            self.delay = (self.delay / 2).max(min_delay);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Batch;
+use config::BatchCompression;
+use std::io;
+
+#[cfg(test)]
+#[path = "tests/compression_tests.rs"]
+pub mod compression_tests;
+
+/// The maximum size of a decompressed batch. Larger batches are rejected, so that a bad worker
+/// cannot exhaust our memory with a small compressed batch.
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// The zstd compression level (0 selects the default level of the library).
+const ZSTD_LEVEL: i32 = 0;
+
+/// Compresses a serialized batch.
+pub fn compress(compression: BatchCompression, bytes: &[u8]) -> Vec<u8> {
+    match compression {
+        BatchCompression::None => bytes.to_vec(),
+        BatchCompression::Snappy => snap::raw::Encoder::new()
+            .compress_vec(bytes)
+            .expect("Failed to compress our own batch"),
+        BatchCompression::Zstd => {
+            zstd::bulk::compress(bytes, ZSTD_LEVEL).expect("Failed to compress our own batch")
+        }
+    }
+}
+
+/// Decompresses a serialized batch.
+pub fn decompress(compression: BatchCompression, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    match compression {
+        BatchCompression::None => Ok(bytes.to_vec()),
+        BatchCompression::Snappy => {
+            let size = snap::raw::decompress_len(bytes).map_err(invalid)?;
+            if size > MAX_DECOMPRESSED_SIZE {
+                return Err(invalid(snap::Error::TooBig {
+                    given: size as u64,
+                    max: MAX_DECOMPRESSED_SIZE as u64,
+                }));
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(bytes)
+                .map_err(invalid)
+        }
+        BatchCompression::Zstd => zstd::bulk::decompress(bytes, MAX_DECOMPRESSED_SIZE),
+    }
+}
+
+/// Decompresses and deserializes a batch.
+pub fn decompress_batch(compression: BatchCompression, bytes: &[u8]) -> io::Result<Batch> {
+    let bytes = decompress(compression, bytes)?;
+    bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
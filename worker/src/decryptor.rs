@@ -0,0 +1,346 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::{Batch, Transaction};
+use crate::encryption::decode_encrypted;
+use crate::fetcher::{BatchFetcher, FetchError};
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{Committee, WorkerId};
+use crypto::{Ciphertext, DecryptionShare, Digest, KeyShare, PublicKey, ThresholdPublicKey};
+use ed25519_dalek::{Digest as _, Sha512};
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::stream::StreamExt as _;
+use log::{debug, error, warn};
+use network::{NetworkContext, NetworkMetrics, QueueLimits, SimpleSender};
+use rand::rngs::OsRng;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto as _;
+use store::Store;
+use tokio::sync::mpsc::Receiver;
+
+#[cfg(test)]
+#[path = "tests/decryptor_tests.rs"]
+pub mod decryptor_tests;
+
+/// The key of the committed batches we did not decrypt yet.
+const DECRYPTOR_KEY: &[u8] = b"decryptor";
+
+/// The maximum number of ciphertexts (not committed yet on our side) we buffer decryption shares
+/// for. The other workers may send us their shares before our primary notifies us of the commit.
+const MAX_EARLY_CIPHERTEXTS: usize = 10_000;
+
+/// Returns the key under which we store the decrypted batch of the specified digest.
+pub fn decrypted_key(digest: &Digest) -> Vec<u8> {
+    [&digest.0[..], b"/decrypted"].concat()
+}
+
+/// A transaction of a committed batch holding encrypted transactions.
+enum Slot {
+    /// A plaintext transaction, or a decrypted one.
+    Plain(Transaction),
+    /// An encrypted transaction, by the digest of its ciphertext, waiting for decryption.
+    Encrypted(Digest),
+    /// An encrypted transaction the committee cannot decrypt (its ciphertext is malformed).
+    Dropped,
+}
+
+/// Decrypts the transactions encrypted to the committee once their batch is committed, so that
+/// their content remains hidden until the consensus orders them. For each encrypted transaction of
+/// a committed batch, we send our decryption share to the other workers (of the same id), gather
+/// the shares they send us, and decrypt the transaction as soon as we hold `threshold` valid
+/// shares. We then store the batch with its transactions decrypted (see `decrypted_key`), which
+/// the `BatchFetcher` serves to the execution layer instead of the batch holding the ciphertexts.
+pub struct Decryptor {
+    /// The public key of this authority.
+    name: PublicKey,
+    /// The id of this worker.
+    id: WorkerId,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// The public key of the committee, to check the decryption shares.
+    key: ThresholdPublicKey,
+    /// Our share of the decryption key. Without it, we only decrypt from the shares of the others.
+    key_share: Option<KeyShare>,
+    /// Reads the committed batches (and fetches those we miss).
+    fetcher: BatchFetcher,
+    /// Receives the digests of the committed batches from our primary.
+    rx_committed: Receiver<Vec<Digest>>,
+    /// Receives the decryption shares of the other workers, by digest of ciphertext.
+    rx_shares: Receiver<Vec<(Digest, DecryptionShare)>>,
+    /// A network sender to send our decryption shares.
+    network: SimpleSender,
+    /// The committed batches holding ciphertexts we did not decrypt yet.
+    batches: HashMap<Digest, Vec<Slot>>,
+    /// The ciphertexts of the committed batches we did not decrypt yet (along with these batches).
+    ciphertexts: HashMap<Digest, (Ciphertext, Vec<Digest>)>,
+    /// The decryption shares we hold, by digest of ciphertext.
+    shares: HashMap<Digest, Vec<DecryptionShare>>,
+    /// The ciphertexts we received shares for before they were committed, in order of arrival.
+    early: VecDeque<Digest>,
+}
+
+impl Decryptor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        store: Store,
+        key_share: Option<KeyShare>,
+        fetcher: BatchFetcher,
+        rx_committed: Receiver<Vec<Digest>>,
+        rx_shares: Receiver<Vec<(Digest, DecryptionShare)>>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
+    ) {
+        let key = match &committee.encryption_key {
+            Some(key) => key.clone(),
+            None => return,
+        };
+        tokio::spawn(async move {
+            let mut decryptor = Self {
+                name,
+                id,
+                committee,
+                store,
+                key,
+                key_share,
+                fetcher,
+                rx_committed,
+                rx_shares,
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics),
+                batches: HashMap::new(),
+                ciphertexts: HashMap::new(),
+                shares: HashMap::new(),
+                early: VecDeque::new(),
+            };
+            decryptor.run().await;
+        });
+    }
+
+    /// Returns the committed batches we did not decrypt before the last restart.
+    async fn restore(&mut self) -> Vec<Digest> {
+        match self.store.read(DECRYPTOR_KEY.to_vec()).await {
+            Ok(Some(bytes)) => bincode::deserialize(&bytes).expect("Failed to deserialize digests"),
+            Ok(None) => Vec::new(),
+            Err(e) => panic!("Storage failure: {}", e),
+        }
+    }
+
+    /// Records the committed batches we still need to decrypt after a restart.
+    async fn persist(&mut self, loading: &HashSet<Digest>) {
+        let digests: Vec<_> = self.batches.keys().chain(loading.iter()).collect();
+        let bytes = bincode::serialize(&digests).expect("Failed to serialize digests");
+        self.store.write(DECRYPTOR_KEY.to_vec(), bytes).await;
+    }
+
+    /// Reads a committed batch, and returns it if it holds encrypted transactions we did not
+    /// decrypt yet.
+    async fn load(
+        fetcher: BatchFetcher,
+        mut store: Store,
+        digest: Digest,
+    ) -> (Digest, Result<Option<Batch>, FetchError>) {
+        let result = match store.read(decrypted_key(&digest)).await {
+            Ok(Some(_)) => Ok(None),
+            Ok(None) => fetcher.fetch_sealed(&digest, None).await.map(Some),
+            Err(e) => Err(e.into()),
+        };
+        (digest, result)
+    }
+
+    /// Registers the ciphertexts of a committed batch, and sends our decryption shares of them to
+    /// the other workers.
+    async fn process_batch(&mut self, digest: Digest, batch: Batch) {
+        let mut slots = Vec::with_capacity(batch.len());
+        let mut pending = Vec::new();
+        let mut ours = Vec::new();
+        for transaction in batch {
+            let ciphertext = match decode_encrypted(&transaction) {
+                Ok(Some(x)) => x,
+                // The fetcher filtered out the malformed envelopes.
+                _ => {
+                    slots.push(Slot::Plain(transaction));
+                    continue;
+                }
+            };
+            let id = Digest(Sha512::digest(&transaction)[..32].try_into().unwrap());
+            slots.push(Slot::Encrypted(id.clone()));
+            pending.push(id.clone());
+            if let Some((_, batches)) = self.ciphertexts.get_mut(&id) {
+                batches.push(digest.clone());
+                continue;
+            }
+
+            if let Some(key_share) = &self.key_share {
+                match key_share.decrypt(&ciphertext, &mut OsRng) {
+                    Ok(share) => ours.push((id.clone(), share)),
+                    Err(e) => warn!("Failed to compute decryption share: {}", e),
+                }
+            }
+            self.ciphertexts
+                .insert(id, (ciphertext, vec![digest.clone()]));
+        }
+        if pending.is_empty() {
+            return;
+        }
+        debug!("Decrypting committed batch {}", digest);
+        self.batches.insert(digest, slots);
+
+        // Send our shares to the other workers before adding them to ours (they may complete them).
+        if !ours.is_empty() {
+            let message = WorkerMessage::DecryptionShares(ours.clone());
+            let bytes = bincode::serialize(&message).expect("Failed to serialize our shares");
+            let addresses = self
+                .committee
+                .others_workers(&self.name, &self.id)
+                .iter()
+                .map(|(_, x)| x.worker_to_worker)
+                .collect();
+            self.network.broadcast(addresses, Bytes::from(bytes)).await;
+        }
+        self.add_shares(ours).await;
+
+        // We may already hold the shares of the other workers for some of the ciphertexts.
+        for id in pending {
+            self.try_decrypt(&id).await;
+        }
+    }
+
+    /// Adds decryption shares: we check the shares of the committed ciphertexts right away, and
+    /// buffer those of the other ciphertexts until they are committed.
+    async fn add_shares(&mut self, shares: Vec<(Digest, DecryptionShare)>) {
+        for (id, share) in shares {
+            let held = self.shares.entry(id.clone()).or_default();
+            if held.contains(&share) {
+                continue;
+            }
+            match self.ciphertexts.get(&id) {
+                Some((ciphertext, _)) => {
+                    if self.key.verify_share(ciphertext, &share).is_err() {
+                        warn!("Invalid decryption share {} of {}", share.index, id);
+                        continue;
+                    }
+                    held.push(share);
+                    self.try_decrypt(&id).await;
+                }
+                None => {
+                    // Bound the shares we buffer: the honest workers send one share per ciphertext.
+                    if held.is_empty() {
+                        self.early.push_back(id.clone());
+                    }
+                    if held.len() < 2 * self.committee.size() {
+                        held.push(share);
+                    }
+                    while self.early.len() > MAX_EARLY_CIPHERTEXTS {
+                        if let Some(id) = self.early.pop_front() {
+                            if !self.ciphertexts.contains_key(&id) {
+                                self.shares.remove(&id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decrypts a committed ciphertext if we hold enough valid shares, and stores the batches it
+    /// completes.
+    async fn try_decrypt(&mut self, id: &Digest) {
+        let ciphertext = match self.ciphertexts.get(id) {
+            Some((ciphertext, _)) => ciphertext,
+            None => return,
+        };
+        let key = &self.key;
+        let held = self.shares.entry(id.clone()).or_default();
+        held.retain(|x| key.verify_share(ciphertext, x).is_ok());
+        let indices: HashSet<_> = held.iter().map(|x| x.index).collect();
+        if indices.len() < self.key.threshold {
+            return;
+        }
+
+        // With enough valid shares, the decryption only fails if the ciphertext is malformed: all
+        // honest workers drop it alike.
+        let plaintext = self.key.combine(ciphertext, held).ok();
+        if plaintext.is_none() {
+            warn!("Dropping undecryptable transaction {}", id);
+        }
+        self.shares.remove(id);
+        let (_, batches) = self.ciphertexts.remove(id).unwrap();
+        for digest in batches {
+            let slots = match self.batches.get_mut(&digest) {
+                Some(x) => x,
+                None => continue,
+            };
+            for slot in slots.iter_mut() {
+                if matches!(slot, Slot::Encrypted(x) if x == id) {
+                    *slot = match &plaintext {
+                        Some(x) => Slot::Plain(Bytes::from(x.clone())),
+                        None => Slot::Dropped,
+                    };
+                }
+            }
+            if slots.iter().all(|x| !matches!(x, Slot::Encrypted(_))) {
+                let slots = self.batches.remove(&digest).unwrap();
+                let batch: Batch = slots
+                    .into_iter()
+                    .filter_map(|x| match x {
+                        Slot::Plain(transaction) => Some(transaction),
+                        _ => None,
+                    })
+                    .collect();
+                debug!("Decrypted committed batch {}", digest);
+                let bytes = bincode::serialize(&batch).expect("Failed to serialize batch");
+                self.store.write(decrypted_key(&digest), bytes).await;
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        let mut loading = HashSet::new();
+        let mut waiting = FuturesUnordered::new();
+        for digest in self.restore().await {
+            loading.insert(digest.clone());
+            waiting.push(Self::load(self.fetcher.clone(), self.store.clone(), digest));
+        }
+
+        loop {
+            tokio::select! {
+                Some(digests) = self.rx_committed.recv() => {
+                    for digest in digests {
+                        if self.batches.contains_key(&digest) || !loading.insert(digest.clone()) {
+                            continue;
+                        }
+                        waiting.push(Self::load(self.fetcher.clone(), self.store.clone(), digest));
+                    }
+                    self.persist(&loading).await;
+                },
+
+                Some(shares) = self.rx_shares.recv() => {
+                    let pending = self.batches.len();
+                    self.add_shares(shares).await;
+                    if self.batches.len() != pending {
+                        self.persist(&loading).await;
+                    }
+                },
+
+                Some((digest, result)) = waiting.next() => {
+                    loading.remove(&digest);
+                    match result {
+                        Ok(Some(batch)) => self.process_batch(digest, batch).await,
+                        Ok(None) => (),
+                        Err(e) => error!("Failed to decrypt committed batch: {}", e),
+                    }
+                    self.persist(&loading).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Transaction;
+use crate::validator::{TransactionValidator, ValidationError};
+use bytes::Bytes;
+use crypto::Ciphertext;
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/encryption_tests.rs"]
+pub mod encryption_tests;
+
+/// The prefix of the transactions encrypted to the committee.
+const ENCRYPTED_MAGIC: &[u8; 8] = b"NWENC/v1";
+
+/// Wraps a transaction encrypted to the committee (see `Committee::encryption_key`) so that the
+/// workers recognize it.
+pub fn encrypted_transaction(ciphertext: &Ciphertext) -> Transaction {
+    let mut transaction = ENCRYPTED_MAGIC.to_vec();
+    bincode::serialize_into(&mut transaction, ciphertext).expect("Failed to serialize ciphertext");
+    Bytes::from(transaction)
+}
+
+/// Returns the ciphertext of an encrypted transaction, or `None` if the transaction is plaintext.
+/// Fails if the transaction carries the prefix of encrypted transactions but no ciphertext.
+pub fn decode_encrypted(transaction: &[u8]) -> Result<Option<Ciphertext>, ValidationError> {
+    match transaction.strip_prefix(ENCRYPTED_MAGIC) {
+        Some(bytes) => Ok(Some(bincode::deserialize(bytes)?)),
+        None => Ok(None),
+    }
+}
+
+/// Accepts the transactions encrypted to the committee besides the (plaintext) transactions of the
+/// inner validator. We cannot see the content of an encrypted transaction before the committee
+/// decrypts it, so we only check that its sender knows the randomness of the encryption (which
+/// prevents replaying a mauled copy of the ciphertext of another client), and it has no replacement
+/// key, expiry, sender nonce or routing key.
+pub struct EncryptionValidator {
+    inner: Arc<dyn TransactionValidator>,
+}
+
+impl EncryptionValidator {
+    pub fn new(inner: Arc<dyn TransactionValidator>) -> Self {
+        Self { inner }
+    }
+}
+
+impl TransactionValidator for EncryptionValidator {
+    fn validate(&self, transaction: &[u8]) -> Result<(), ValidationError> {
+        match decode_encrypted(transaction)? {
+            Some(ciphertext) => ciphertext
+                .verify()
+                .map_err(|_| "Invalid proof of encryption".into()),
+            None => self.inner.validate(transaction),
+        }
+    }
+
+    fn replacement_key(&self, transaction: &[u8]) -> Option<Vec<u8>> {
+        match transaction.starts_with(ENCRYPTED_MAGIC) {
+            true => None,
+            false => self.inner.replacement_key(transaction),
+        }
+    }
+
+    fn expiry(&self, transaction: &[u8]) -> Option<u64> {
+        match transaction.starts_with(ENCRYPTED_MAGIC) {
+            true => None,
+            false => self.inner.expiry(transaction),
+        }
+    }
+
+    fn sender_nonce(&self, transaction: &[u8]) -> Option<(Vec<u8>, u64)> {
+        match transaction.starts_with(ENCRYPTED_MAGIC) {
+            true => None,
+            false => self.inner.sender_nonce(transaction),
+        }
+    }
+
+    fn routing_key(&self, transaction: &[u8]) -> Option<Vec<u8>> {
+        match transaction.starts_with(ENCRYPTED_MAGIC) {
+            true => None,
+            false => self.inner.routing_key(transaction),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::merkle::{merkle_root, MerkleProof};
+use config::{BatchDissemination, Committee};
+use crypto::{Digest, PublicKey};
+use ed25519_dalek::{Digest as _, Sha512};
+use log::debug;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto as _;
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/erasure_tests.rs"]
+pub mod erasure_tests;
+
+/// The maximum number of bytes of shards we hold while reconstructing batches. Beyond, we drop the
+/// shards of the oldest batches, so that bad workers cannot exhaust our memory.
+pub const MAX_PENDING_SIZE: usize = 64 * 1024 * 1024;
+
+/// Returns the key under which a worker stores its shard of the batch of the specified digest.
+pub fn shard_key(digest: &Digest) -> Vec<u8> {
+    [&digest.0[..], b"/shard"].concat()
+}
+
+/// Returns the Merkle leaf of a shard: the shard prefixed by its index, so that a shard cannot
+/// pass for the shard of another index.
+fn leaf(index: u32, data: &[u8]) -> Vec<u8> {
+    [&index.to_le_bytes()[..], data].concat()
+}
+
+/// Returns the digest of a coded batch: it commits to the Merkle root of the shards of the batch
+/// and to the length of the serialized batch.
+fn commitment(root: &Digest, length: u64) -> Digest {
+    let mut hasher = Sha512::new();
+    hasher.update(root);
+    hasher.update(length.to_le_bytes());
+    Digest(hasher.finalize()[..32].try_into().unwrap())
+}
+
+/// A Reed-Solomon coded shard of a serialized batch, along with the proof that it is the shard of
+/// its index committed to by the digest of the batch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    /// The index of the shard.
+    pub index: u32,
+    /// The length of the serialized batch.
+    pub length: u64,
+    /// The coded bytes.
+    pub data: Vec<u8>,
+    /// The Merkle root of the shards of the batch.
+    pub root: Digest,
+    /// The proof that the shard is included in the Merkle tree of the shards.
+    pub proof: MerkleProof,
+}
+
+impl Shard {
+    /// Checks that the shard is the one of its index committed to by the specified batch digest.
+    pub fn verify(&self, digest: &Digest) -> bool {
+        &commitment(&self.root, self.length) == digest
+            && self.proof.verify(&self.root, &leaf(self.index, &self.data))
+    }
+}
+
+/// How the batches are split into Reed-Solomon coded shards. There is one shard per authority of the
+/// committee (the worker of each authority holds the shard of the rank of its key), and any f+1
+/// shards reconstruct the batch. The digest of a coded batch commits to the Merkle root of its
+/// shards (see `digest`), so that each worker can check its shard on its own.
+#[derive(Clone)]
+pub struct ShardLayout {
+    /// The index of the shard of each authority.
+    indices: HashMap<PublicKey, usize>,
+    /// The number of shards needed to reconstruct a batch.
+    data_shards: usize,
+    /// The Reed-Solomon codec.
+    codec: Arc<ReedSolomon>,
+}
+
+impl ShardLayout {
+    /// Returns the layout of the committee, or `None` if the committee replicates its batches (or
+    /// is too small to benefit from coding).
+    pub fn new(committee: &Committee) -> Option<Self> {
+        if committee.batch_dissemination != BatchDissemination::ErasureCoded {
+            return None;
+        }
+        let total = committee.size();
+        let data_shards = total.div_ceil(3);
+        let codec = ReedSolomon::new(data_shards, total - data_shards).ok()?;
+        Some(Self {
+            indices: committee
+                .authorities
+                .keys()
+                .enumerate()
+                .map(|(i, name)| (*name, i))
+                .collect(),
+            data_shards,
+            codec: Arc::new(codec),
+        })
+    }
+
+    /// The index of the shard held by the specified authority.
+    pub fn index(&self, name: &PublicKey) -> Option<usize> {
+        self.indices.get(name).cloned()
+    }
+
+    /// Splits a serialized batch into shards (indexed like the authorities).
+    pub fn encode(&self, serialized: &[u8]) -> Vec<Vec<u8>> {
+        let shard_size = serialized.len().div_ceil(self.data_shards).max(1);
+        let mut shards: Vec<_> = serialized
+            .chunks(shard_size)
+            .map(|x| x.to_vec())
+            .chain(std::iter::repeat(Vec::new()))
+            .take(self.indices.len())
+            .collect();
+        for shard in &mut shards {
+            shard.resize(shard_size, 0);
+        }
+        self.codec
+            .encode(&mut shards)
+            .expect("Failed to encode our own batch");
+        shards
+    }
+
+    /// Splits a serialized batch into shards (indexed like the authorities) along with their proofs,
+    /// and returns them with the digest of the batch.
+    pub fn shards(&self, serialized: &[u8]) -> (Digest, Vec<Shard>) {
+        let data = self.encode(serialized);
+        let leaves: Vec<_> = data
+            .iter()
+            .enumerate()
+            .map(|(i, x)| leaf(i as u32, x))
+            .collect();
+        let root = merkle_root(&leaves);
+        let length = serialized.len() as u64;
+        let shards = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, data)| Shard {
+                index: i as u32,
+                length,
+                data,
+                root: root.clone(),
+                proof: MerkleProof::new(&leaves, i).expect("The shard is in the tree"),
+            })
+            .collect();
+        (commitment(&root, length), shards)
+    }
+
+    /// Returns the digest of a serialized batch: the commitment to the Merkle root of its shards.
+    pub fn digest(&self, serialized: &[u8]) -> Digest {
+        let leaves: Vec<_> = self
+            .encode(serialized)
+            .iter()
+            .enumerate()
+            .map(|(i, x)| leaf(i as u32, x))
+            .collect();
+        commitment(&merkle_root(&leaves), serialized.len() as u64)
+    }
+
+    /// Reconstructs a serialized batch of the specified length from (at least f+1 of) its shards.
+    pub fn decode(&self, mut shards: Vec<Option<Vec<u8>>>, length: usize) -> Option<Vec<u8>> {
+        self.codec.reconstruct_data(&mut shards).ok()?;
+        let mut serialized: Vec<_> = shards
+            .into_iter()
+            .take(self.data_shards)
+            .flat_map(|x| x.unwrap_or_default())
+            .collect();
+        (serialized.len() >= length).then(|| {
+            serialized.truncate(length);
+            serialized
+        })
+    }
+}
+
+/// The shards of a batch we are reconstructing.
+struct PartialBatch {
+    /// The length of the serialized batch.
+    length: usize,
+    /// The shards we received (indexed like the authorities).
+    shards: Vec<Option<Vec<u8>>>,
+    /// The number of shards we received.
+    received: usize,
+}
+
+/// Gathers the shards that other workers send us in reply to our batch requests, and reconstructs
+/// the batches. We only accept the shards proven against the digest of their batch. A bad author
+/// may still commit to shards that do not decode consistently: if the reconstructed batch does not
+/// encode back into its digest, we drop its shards (the synchronizer requests them again later).
+pub struct ShardCollector {
+    /// How the batches are split into shards.
+    layout: ShardLayout,
+    /// The batches being reconstructed, indexed by digest.
+    pending: HashMap<Digest, PartialBatch>,
+    /// The digests of the batches being reconstructed, oldest first.
+    order: VecDeque<Digest>,
+    /// The number of bytes held in `pending`.
+    size: usize,
+}
+
+impl ShardCollector {
+    pub fn new(layout: ShardLayout) -> Self {
+        Self {
+            layout,
+            pending: HashMap::new(),
+            order: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    /// Drops the shards of a batch.
+    fn drop_batch(&mut self, digest: &Digest) {
+        if let Some(partial) = self.pending.remove(digest) {
+            self.size -= partial
+                .shards
+                .iter()
+                .flatten()
+                .map(|x| x.len())
+                .sum::<usize>();
+            self.order.retain(|x| x != digest);
+        }
+    }
+
+    /// Drops the oldest batches (but the specified one) until `size` more bytes fit.
+    fn make_room(&mut self, digest: &Digest, size: usize) {
+        while self.size + size > MAX_PENDING_SIZE {
+            match self.order.iter().find(|x| *x != digest).cloned() {
+                Some(oldest) => self.drop_batch(&oldest),
+                None => return,
+            }
+        }
+    }
+
+    /// Adds a shard of a batch, returning the serialized batch once we can reconstruct it.
+    pub fn add(&mut self, digest: Digest, shard: Shard) -> Option<Vec<u8>> {
+        let (index, length) = (shard.index as usize, shard.length as usize);
+        if !shard.verify(&digest) {
+            debug!("Dropping invalid shard {} of batch {}", index, digest);
+            return None;
+        }
+        let shard = shard.data;
+        let total = self.layout.indices.len();
+        self.make_room(&digest, shard.len());
+        if index >= total || self.size + shard.len() > MAX_PENDING_SIZE {
+            debug!("Dropping shard {} of batch {}", index, digest);
+            return None;
+        }
+        if !self.pending.contains_key(&digest) {
+            let partial = PartialBatch {
+                length,
+                shards: vec![None; total],
+                received: 0,
+            };
+            self.pending.insert(digest.clone(), partial);
+            self.order.push_back(digest.clone());
+        }
+        let partial = self.pending.get_mut(&digest).unwrap();
+        if partial.length != length || partial.shards[index].is_some() {
+            return None;
+        }
+        self.size += shard.len();
+        partial.shards[index] = Some(shard);
+        partial.received += 1;
+        if partial.received < self.layout.data_shards {
+            return None;
+        }
+
+        let shards = partial.shards.clone();
+        self.drop_batch(&digest);
+        let serialized = self.layout.decode(shards, length)?;
+        if self.layout.digest(&serialized) != digest {
+            debug!("Failed to reconstruct batch {}: bad shards", digest);
+            return None;
+        }
+        Some(serialized)
+    }
+}
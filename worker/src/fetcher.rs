@@ -0,0 +1,254 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Batch;
+use crate::compression::decompress_batch;
+use crate::decryptor::decrypted_key;
+use crate::encryption::decode_encrypted;
+use crate::erasure::shard_key;
+use crate::validator::TransactionValidator;
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{BatchDissemination, Committee, WorkerId};
+use crypto::{Digest, PublicKey};
+use log::debug;
+use network::{NetworkContext, NetworkMetrics, QueueLimits, SimpleSender, TokenBucket};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use store::{Store, StoreError};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tokio::time::{timeout, Duration};
+
+#[cfg(test)]
+#[path = "tests/fetcher_tests.rs"]
+pub mod fetcher_tests;
+
+/// The number of times we request a missing batch from the other workers before giving up.
+const MAX_FETCH_ATTEMPTS: usize = 3;
+
+/// The maximum number of missing batches per second we request from the other workers.
+const MAX_REMOTE_FETCH_RATE: u64 = 100;
+
+/// The number of recently committed batches we remember (as those we may request from the other
+/// workers).
+const MAX_COMMITTED_BATCHES: usize = 100_000;
+
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("Batch {0} is unavailable")]
+    Unavailable(Digest),
+
+    #[error("Batch {0} is malformed: {1}")]
+    Malformed(Digest, String),
+
+    #[error("Batch {0} holds transactions the committee did not decrypt yet")]
+    Encrypted(Digest),
+
+    #[error("Batch {0} is not committed")]
+    Uncommitted(Digest),
+
+    #[error("Too many missing batches requested: try batch {0} again later")]
+    RateLimited(Digest),
+
+    #[error(transparent)]
+    StoreError(#[from] StoreError),
+}
+
+/// Fetches the transactions of a batch (for instance, for the execution layer consuming the digests
+/// output by the consensus). We read the batch from our store, and request it from the other
+/// workers if we miss it. The workers store the batches of the others even if the transaction
+/// validator rejects some of their transactions (the batches may be certified): those are filtered
+/// out here, at execution time. If the committee encrypts transactions, we serve the batches with
+/// their transactions decrypted (by the `Decryptor`, once the batch is committed).
+///
+/// Anyone may ask for any digest, so we only request the missing batches we know were certified
+/// (the recently committed batches, and those we hold a shard of), at a bounded rate.
+#[derive(Clone)]
+pub struct BatchFetcher {
+    /// The public key of this authority.
+    name: PublicKey,
+    /// The id of this worker.
+    id: WorkerId,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// How long we wait for a reply before requesting the batch again (in ms).
+    retry_delay: u64,
+    /// The number of other workers we request the batch from, when its origin does not reply.
+    retry_nodes: usize,
+    /// A network sender to request the missing batches.
+    network: Arc<Mutex<SimpleSender>>,
+    /// Filters out the invalid transactions of the batches (if any).
+    validator: Option<Arc<dyn TransactionValidator>>,
+    /// The batches recently committed (reported by our primary).
+    committed: Arc<std::sync::Mutex<CommittedBatches>>,
+    /// Bounds the rate at which we request missing batches.
+    requests: Arc<std::sync::Mutex<TokenBucket>>,
+}
+
+/// The digests of the batches recently committed, oldest first.
+#[derive(Default)]
+struct CommittedBatches {
+    digests: HashSet<Digest>,
+    order: VecDeque<Digest>,
+}
+
+impl BatchFetcher {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        store: Store,
+        retry_delay: u64,
+        retry_nodes: usize,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
+    ) -> Self {
+        Self {
+            name,
+            id,
+            committee,
+            store,
+            retry_delay,
+            retry_nodes,
+            network: Arc::new(Mutex::new(
+                SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics),
+            )),
+            validator: None,
+            committed: Arc::default(),
+            requests: Arc::new(std::sync::Mutex::new(TokenBucket::new(
+                MAX_REMOTE_FETCH_RATE,
+            ))),
+        }
+    }
+
+    /// Filters out the transactions of the fetched batches rejected by the specified validator.
+    pub fn with_validator(mut self, validator: Arc<dyn TransactionValidator>) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Records the digests of committed batches, which we may then request from the other workers.
+    pub(crate) fn record_committed(&self, digests: &[Digest]) {
+        let mut committed = self.committed.lock().unwrap();
+        for digest in digests {
+            if committed.digests.insert(digest.clone()) {
+                committed.order.push_back(digest.clone());
+            }
+        }
+        while committed.order.len() > MAX_COMMITTED_BATCHES {
+            if let Some(digest) = committed.order.pop_front() {
+                committed.digests.remove(&digest);
+            }
+        }
+    }
+
+    /// Returns the transactions of the batch of the specified digest. We first request a missing
+    /// batch from the worker of its origin (if known), then from other workers. Fails if the batch
+    /// holds encrypted transactions the committee did not decrypt yet.
+    pub async fn fetch(
+        &self,
+        digest: &Digest,
+        origin: Option<PublicKey>,
+    ) -> Result<Batch, FetchError> {
+        let batch = self.fetch_sealed(digest, origin).await?;
+        if self.committee.encryption_key.is_none()
+            || !batch
+                .iter()
+                .any(|x| matches!(decode_encrypted(x), Ok(Some(_))))
+        {
+            return Ok(batch);
+        }
+        let mut store = self.store.clone();
+        match store.read(decrypted_key(digest)).await? {
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map_err(|e| FetchError::Malformed(digest.clone(), e.to_string())),
+            None => Err(FetchError::Encrypted(digest.clone())),
+        }
+    }
+
+    /// Returns the transactions of the batch of the specified digest, as sealed by its origin (that
+    /// is, with its encrypted transactions).
+    pub(crate) async fn fetch_sealed(
+        &self,
+        digest: &Digest,
+        origin: Option<PublicKey>,
+    ) -> Result<Batch, FetchError> {
+        let mut store = self.store.clone();
+        if let Some(serialized) = store.read(digest.to_vec()).await? {
+            return Self::decode(digest, &serialized).map(|x| self.filter(x));
+        }
+
+        // Only request the batches we know were certified.
+        let committed = self.committed.lock().unwrap().digests.contains(digest);
+        if !committed && store.read(shard_key(digest)).await?.is_none() {
+            return Err(FetchError::Uncommitted(digest.clone()));
+        }
+        if !self.requests.lock().unwrap().try_acquire() {
+            return Err(FetchError::RateLimited(digest.clone()));
+        }
+
+        // Giving up on a (timed out) read cancels it.
+        for attempt in 0..MAX_FETCH_ATTEMPTS {
+            debug!("Fetching missing batch {} (attempt {})", digest, attempt);
+            self.request(digest, origin.filter(|_| attempt == 0)).await;
+            let wait = Duration::from_millis(self.retry_delay);
+            if let Ok(result) = timeout(wait, store.notify_read(digest.to_vec())).await {
+                return Self::decode(digest, &result?).map(|x| self.filter(x));
+            }
+        }
+        Err(FetchError::Unavailable(digest.clone()))
+    }
+
+    /// Requests a batch from the worker of its origin, or from other workers.
+    async fn request(&self, digest: &Digest, origin: Option<PublicKey>) {
+        let message = WorkerMessage::BatchRequest(vec![digest.clone()], self.name);
+        let bytes = Bytes::from(bincode::serialize(&message).expect("Failed to serialize request"));
+        let origin = origin.and_then(|x| self.committee.worker(&x, &self.id).ok());
+        let mut network = self.network.lock().await;
+        match origin {
+            Some(address) => network.send(address.worker_to_worker, bytes).await,
+            None => {
+                let addresses = self
+                    .committee
+                    .others_workers(&self.name, &self.id)
+                    .iter()
+                    .map(|(_, x)| x.worker_to_worker)
+                    .collect();
+                // Reconstructing a batch from its shards takes the replies of f+1 workers.
+                let nodes = match self.committee.batch_dissemination {
+                    BatchDissemination::ErasureCoded => usize::MAX,
+                    _ => self.retry_nodes,
+                };
+                network.lucky_broadcast(addresses, bytes, nodes).await;
+            }
+        }
+    }
+
+    /// Drops the transactions of a batch rejected by the validator (if any).
+    fn filter(&self, mut batch: Batch) -> Batch {
+        if let Some(validator) = &self.validator {
+            batch.retain(|x| validator.validate(x).is_ok());
+        }
+        batch
+    }
+
+    /// Returns the transactions of a serialized batch message.
+    fn decode(digest: &Digest, serialized: &[u8]) -> Result<Batch, FetchError> {
+        let malformed = |e: String| FetchError::Malformed(digest.clone(), e);
+        match bincode::deserialize(serialized).map_err(|e| malformed(e.to_string()))? {
+            WorkerMessage::Batch(batch) => Ok(batch),
+            WorkerMessage::CompressedBatch(compression, bytes) => {
+                decompress_batch(compression, &bytes).map_err(|e| malformed(e.to_string()))
+            }
+            _ => Err(malformed("Not a batch".to_string())),
+        }
+    }
+}
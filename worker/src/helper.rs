@@ -1,9 +1,13 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::erasure::shard_key;
+use crate::worker::WorkerMessage;
 use bytes::Bytes;
-use config::{Committee, WorkerId};
+use config::{BatchHelperLimits, Committee, WorkerId};
 use crypto::{Digest, PublicKey};
-use log::{error, warn};
-use network::SimpleSender;
+use log::{debug, error, warn};
+use network::{Compression, NetworkContext, NetworkMetrics, Priority, QueueLimits, SimpleSender};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
 
@@ -11,7 +15,68 @@ use tokio::sync::mpsc::Receiver;
 #[path = "tests/helper_tests.rs"]
 pub mod helper_tests;
 
-/// A task dedicated to help other authorities by replying to their batch requests.
+/// The maximum number of batch requests we serve concurrently. Further requests are dropped (the
+/// requestors ask again later).
+const MAX_PENDING_REQUESTS: usize = 1_000;
+
+/// A batch request being served.
+struct PendingRequest {
+    /// The digests of the requested batches we did not serve yet.
+    digests: VecDeque<Digest>,
+    /// The address of the requestor.
+    address: SocketAddr,
+    /// The number of bytes we sent in reply so far.
+    sent: usize,
+}
+
+/// The batches we recently served, up to a total size (evicting the oldest first).
+struct BatchCache {
+    /// The maximum total size of the cached batches (in bytes).
+    capacity: usize,
+    /// The total size of the cached batches (in bytes).
+    size: usize,
+    /// The cached batches.
+    batches: HashMap<Digest, Bytes>,
+    /// The digests of the cached batches, from the oldest.
+    order: VecDeque<Digest>,
+}
+
+impl BatchCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            batches: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, digest: &Digest) -> Option<Bytes> {
+        self.batches.get(digest).cloned()
+    }
+
+    fn insert(&mut self, digest: Digest, data: Bytes) {
+        if data.len() > self.capacity || self.batches.contains_key(&digest) {
+            return;
+        }
+        while self.size + data.len() > self.capacity {
+            let oldest = match self.order.pop_front() {
+                Some(x) => x,
+                None => break,
+            };
+            if let Some(evicted) = self.batches.remove(&oldest) {
+                self.size -= evicted.len();
+            }
+        }
+        self.size += data.len();
+        self.order.push_back(digest.clone());
+        self.batches.insert(digest, data);
+    }
+}
+
+/// A task dedicated to help other authorities by replying to their batch requests. Requests are
+/// served page by page (interleaving the pages of the pending requests) and large batches are
+/// streamed in chunks, so that a large request cannot blow up the memory of either side.
 pub struct Helper {
     /// The id of this worker.
     id: WorkerId,
@@ -19,51 +84,151 @@ pub struct Helper {
     committee: Committee,
     /// The persistent storage.
     store: Store,
+    /// Limits the batches we send in reply to each request.
+    limits: BatchHelperLimits,
     /// Input channel to receive batch requests.
     rx_request: Receiver<(Vec<Digest>, PublicKey)>,
     /// A network sender to send the batches to the other workers.
     network: SimpleSender,
+    /// The requests being served, in the order we serve their next page.
+    pending: VecDeque<PendingRequest>,
+    /// The batches we recently served.
+    cache: BatchCache,
 }
 
 impl Helper {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         id: WorkerId,
         committee: Committee,
         store: Store,
+        limits: BatchHelperLimits,
         rx_request: Receiver<(Vec<Digest>, PublicKey)>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        transport_compression: Compression,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
     ) {
         tokio::spawn(async move {
             Self {
                 id,
                 committee,
                 store,
+                limits,
                 rx_request,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_compression(transport_compression)
+                    .with_metrics(metrics),
+                pending: VecDeque::new(),
+                cache: BatchCache::new(limits.cache_size),
             }
             .run()
             .await;
         });
     }
 
-    async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_request.recv().await {
-            // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
+    /// Queues a batch request.
+    fn enqueue(&mut self, digests: Vec<Digest>, origin: PublicKey) {
+        // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
 
-            // get the requestors address.
-            let address = match self.committee.worker(&origin, &self.id) {
-                Ok(x) => x.worker_to_worker,
-                Err(e) => {
-                    warn!("Unexpected batch request: {}", e);
-                    continue;
+        // get the requestors address.
+        let address = match self.committee.worker(&origin, &self.id) {
+            Ok(x) => x.worker_to_worker,
+            Err(e) => {
+                warn!("Unexpected batch request: {}", e);
+                return;
+            }
+        };
+        if self.pending.len() >= MAX_PENDING_REQUESTS {
+            debug!(
+                "Dropping batch request of {}: too many pending requests",
+                origin
+            );
+            return;
+        }
+        self.pending.push_back(PendingRequest {
+            digests: digests.into(),
+            address,
+            sent: 0,
+        });
+    }
+
+    /// Sends a batch, split into chunks if it is large.
+    async fn send_batch(&mut self, address: SocketAddr, digest: Digest, data: Bytes) {
+        let chunk_size = self.limits.chunk_size.max(1);
+        if data.len() <= chunk_size {
+            self.network
+                .send_with_priority(address, data, Priority::Sync)
+                .await;
+            return;
+        }
+        let total = data.len().div_ceil(chunk_size) as u32;
+        for (index, chunk) in data.chunks(chunk_size).enumerate() {
+            let message =
+                WorkerMessage::BatchChunk(digest.clone(), index as u32, total, chunk.to_vec());
+            let bytes = bincode::serialize(&message).expect("Failed to serialize our own message");
+            self.network
+                .send_with_priority(address, Bytes::from(bytes), Priority::Sync)
+                .await;
+        }
+    }
+
+    /// Serves the next page of a request (the best we can).
+    async fn serve_page(&mut self, request: &mut PendingRequest) {
+        for _ in 0..self.limits.page_size.max(1) {
+            let digest = match request.digests.pop_front() {
+                Some(x) => x,
+                None => return,
+            };
+            // We may only hold our shard of the batch.
+            let data = match self.cache.get(&digest) {
+                Some(data) => Ok(Some(data)),
+                None => match self.store.read(digest.to_vec()).await {
+                    Ok(None) => self.store.read(shard_key(&digest)).await,
+                    result => result,
                 }
+                .map(|x| x.map(Bytes::from)),
             };
+            match data {
+                Ok(Some(data)) => {
+                    // Always serve the first batch, however large.
+                    if request.sent > 0 && request.sent + data.len() > self.limits.max_reply_size {
+                        debug!("Reply size cap reached: dropping the rest of the batch request");
+                        request.digests.clear();
+                        return;
+                    }
+                    request.sent += data.len();
+                    self.cache.insert(digest.clone(), data.clone());
+                    self.send_batch(request.address, digest, data).await;
+                }
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+
+    async fn run(&mut self) {
+        loop {
+            // Only wait for new requests when we have nothing left to serve.
+            if self.pending.is_empty() {
+                match self.rx_request.recv().await {
+                    Some((digests, origin)) => self.enqueue(digests, origin),
+                    None => return,
+                }
+            }
+            while let Ok((digests, origin)) = self.rx_request.try_recv() {
+                self.enqueue(digests, origin);
+            }
 
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => self.network.send(address, Bytes::from(data)).await,
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
+            // Serve a page of the oldest request, and move the request to the back of the queue.
+            if let Some(mut request) = self.pending.pop_front() {
+                self.serve_page(&mut request).await;
+                if !request.digests.is_empty() {
+                    self.pending.push_back(request);
                 }
             }
         }
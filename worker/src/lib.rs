@@ -1,14 +1,41 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod admission;
+mod anti_entropy;
 mod batch_maker;
+mod batch_sizer;
+mod compression;
+mod decryptor;
+mod encryption;
+mod erasure;
+mod fetcher;
 mod helper;
+mod limiter;
+mod mempool;
+mod merkle;
+mod metrics;
 mod primary_connector;
 mod processor;
+mod pruner;
 mod quorum_waiter;
+mod reassembler;
+mod routing;
+mod submitter;
 mod synchronizer;
+mod tls;
+mod validator;
+mod verifier;
 mod worker;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::worker::Worker;
+pub use crate::encryption::{decode_encrypted, encrypted_transaction, EncryptionValidator};
+pub use crate::fetcher::{BatchFetcher, FetchError};
+pub use crate::merkle::{merkle_key, merkle_root, MerkleProof};
+pub use crate::metrics::WorkerMetrics;
+pub use crate::routing::route;
+pub use crate::submitter::{SubmitError, TransactionSubmitter};
+pub use crate::tls::{RustlsProvider, TlsProvider};
+pub use crate::validator::{AcceptAll, TransactionValidator, ValidationError};
+pub use crate::worker::{Receipt, ReceiptStatus, Worker};
@@ -0,0 +1,89 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use primary::TokenBucket;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+#[path = "tests/limiter_tests.rs"]
+pub mod limiter_tests;
+
+/// The number of clients above which we forget the clients we did not hear from recently.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// The quota of a client, along with whether we heard from it since it was last considered for
+/// eviction.
+struct Client {
+    bucket: TokenBucket,
+    recent: bool,
+}
+
+/// The clients we track, along with the order in which we consider them for eviction.
+#[derive(Default)]
+struct Clients {
+    buckets: HashMap<Option<IpAddr>, Client>,
+    order: VecDeque<Option<IpAddr>>,
+}
+
+impl Clients {
+    /// Forgets the oldest client we did not hear from since it was last considered for eviction
+    /// (the others get a second chance). Each second chance follows a transaction of its client,
+    /// so eviction takes constant time on average.
+    fn evict(&mut self) {
+        while let Some(oldest) = self.order.pop_front() {
+            match self.buckets.get_mut(&oldest) {
+                Some(client) if client.recent => {
+                    client.recent = false;
+                    self.order.push_back(oldest);
+                }
+                _ => {
+                    self.buckets.remove(&oldest);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Limits the rate at which each client submits transactions, so that a single client cannot
+/// monopolize the worker. Clients are identified by their address; the clients submitting over
+/// the Unix domain socket (`None`) share a single limit. Cloning the limiter shares it.
+#[derive(Clone)]
+pub struct ClientLimiter {
+    /// The maximum number of transactions per second accepted from each client.
+    rate: u64,
+    /// The quota of each client we recently heard from.
+    clients: Arc<Mutex<Clients>>,
+}
+
+impl ClientLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            clients: Arc::default(),
+        }
+    }
+
+    /// Returns `true` if we accept one more transaction from the client.
+    pub fn try_acquire(&self, client: Option<IpAddr>) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(tracked) = clients.buckets.get_mut(&client) {
+            tracked.recent = true;
+            return tracked.bucket.try_acquire();
+        }
+        if clients.buckets.len() >= MAX_TRACKED_CLIENTS {
+            clients.evict();
+        }
+        let mut bucket = TokenBucket::new(self.rate);
+        let accepted = bucket.try_acquire();
+        clients.order.push_back(client);
+        clients.buckets.insert(
+            client,
+            Client {
+                bucket,
+                recent: false,
+            },
+        );
+        accepted
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::admission::SenderAdmission;
+use crate::batch_maker::{ReceiptSender, Transaction};
+use crate::metrics::WorkerMetrics;
+use crate::validator::TransactionValidator;
+use crate::worker::ReceiptStatus;
+use config::{EvictionPolicy, MempoolParameters};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+#[path = "tests/mempool_tests.rs"]
+pub mod mempool_tests;
+
+/// A transaction waiting to be batched.
+struct Pending {
+    transaction: Transaction,
+    waiter: Option<ReceiptSender>,
+    /// The replacement key of the transaction (if any).
+    key: Option<Vec<u8>>,
+    /// The time after which we drop the transaction (if any).
+    expiry: Option<Instant>,
+    /// The sender and nonce of the transaction (if the transaction validator tells them).
+    account: Option<(Vec<u8>, u64)>,
+}
+
+impl Pending {
+    /// Tells the client (if it waits for a receipt) what became of its transaction.
+    fn notify(self, status: ReceiptStatus) {
+        if let Some(waiter) = self.waiter {
+            let _ = waiter.send(status);
+        }
+    }
+}
+
+/// Holds the transactions waiting to be batched, by priority (the first byte of each transaction,
+/// capped to the highest lane). The mempool is bounded: when full, it evicts transactions as per
+/// its eviction policy rather than holding back all clients alike. A transaction replaces the
+/// pending transaction of the same replacement key (as defined by the transaction validator).
+pub struct Mempool {
+    /// The maximum size of the pending transactions (in bytes).
+    capacity: usize,
+    /// Which transactions we drop when full.
+    eviction: EvictionPolicy,
+    /// The time after which we drop the transactions not batched yet (if any).
+    ttl: Option<Duration>,
+    /// Defines the replacement keys (as well as the expiry, sender, and nonce) of the transactions.
+    validator: Arc<dyn TransactionValidator>,
+    /// Admits the transactions of each sender in nonce order.
+    admission: SenderAdmission,
+    /// The pending transactions of each lane, by arrival order. Higher lanes go first.
+    lanes: Vec<BTreeMap<u64, Pending>>,
+    /// The lane and arrival number of the pending transactions with a replacement key.
+    keys: HashMap<Vec<u8>, (usize, u64)>,
+    /// The arrival number of the next transaction.
+    next: u64,
+    /// The size of the pending transactions (in bytes).
+    size: usize,
+    /// The transactions we dropped (evicted, expired, or rejected) since the last call to
+    /// `drain_dropped`.
+    dropped: Vec<Transaction>,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
+}
+
+impl Mempool {
+    pub fn new(
+        lanes: usize,
+        parameters: MempoolParameters,
+        validator: Arc<dyn TransactionValidator>,
+        metrics: WorkerMetrics,
+    ) -> Self {
+        Self {
+            capacity: parameters.capacity,
+            eviction: parameters.eviction,
+            ttl: parameters.ttl.map(Duration::from_millis),
+            validator,
+            admission: SenderAdmission::new(parameters.max_pending_per_sender),
+            lanes: (0..lanes.max(1)).map(|_| BTreeMap::new()).collect(),
+            keys: HashMap::new(),
+            next: 0,
+            size: 0,
+            dropped: Vec::new(),
+            metrics,
+        }
+    }
+
+    /// The size of the pending transactions (in bytes).
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Drops a transaction we will not batch, telling its client why.
+    fn drop_pending(&mut self, pending: Pending, status: ReceiptStatus) {
+        self.dropped.push(pending.transaction.clone());
+        pending.notify(status);
+    }
+
+    /// Returns the transactions we dropped (evicted, expired, or rejected) since the last call, so
+    /// that their clients may submit them again.
+    pub fn drain_dropped(&mut self) -> std::vec::Drain<'_, Transaction> {
+        self.dropped.drain(..)
+    }
+
+    /// Removes a pending transaction, to batch it or to drop it.
+    fn remove(&mut self, lane: usize, number: u64, batched: bool) -> Option<Pending> {
+        let pending = self.lanes[lane].remove(&number)?;
+        self.size -= pending.transaction.len();
+        if let Some(key) = &pending.key {
+            self.keys.remove(key);
+        }
+        if let Some((sender, nonce)) = &pending.account {
+            self.admission.release(sender, *nonce, batched);
+        }
+        Some(pending)
+    }
+
+    /// Selects the transaction to evict to make room for a transaction of the specified lane, or
+    /// `None` if the transaction itself should be dropped.
+    fn victim(&self, lane: usize) -> Option<(usize, u64)> {
+        let oldest = |i: usize| self.lanes[i].keys().next().map(|x| (i, *x));
+        match self.eviction {
+            EvictionPolicy::Oldest => (0..self.lanes.len())
+                .filter_map(oldest)
+                .min_by_key(|(_, number)| *number),
+            EvictionPolicy::LowestPriority => (0..=lane).find_map(oldest),
+        }
+    }
+
+    /// Returns the time after which we drop a transaction arriving now (if any): the end of its
+    /// TTL or the expiry attached by its client, whichever comes first.
+    fn expiry(&self, transaction: &[u8]) -> Option<Instant> {
+        let now = Instant::now();
+        let attached = self.validator.expiry(transaction).map(|expiry| {
+            let wall = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Failed to measure time")
+                .as_millis() as u64;
+            now + Duration::from_millis(expiry.saturating_sub(wall))
+        });
+        let ttl = self.ttl.map(|x| now + x);
+        match (attached, ttl) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        }
+    }
+
+    /// Queues a transaction, evicting other transactions if the mempool is full.
+    pub fn insert(&mut self, transaction: Transaction, waiter: Option<ReceiptSender>) {
+        let lane = match self.lanes.len() {
+            1 => 0,
+            n => transaction.first().map_or(0, |x| (*x as usize).min(n - 1)),
+        };
+        let key = self.validator.replacement_key(&transaction);
+        let replaced = key.as_ref().and_then(|x| self.keys.get(x)).copied();
+
+        // Admit the transactions of each sender in nonce order.
+        let account = self.validator.sender_nonce(&transaction);
+        if let Some((sender, nonce)) = &account {
+            if let Err(e) = self.admission.check(sender, *nonce, replaced.is_some()) {
+                self.metrics.rejected_transactions.inc();
+                self.dropped.push(transaction);
+                if let Some(waiter) = waiter {
+                    let _ = waiter.send(ReceiptStatus::Rejected(e.to_string()));
+                }
+                return;
+            }
+        }
+
+        let pending = Pending {
+            key,
+            expiry: self.expiry(&transaction),
+            account: account.clone(),
+            transaction,
+            waiter,
+        };
+
+        // Replace the pending transaction of the same key (if any).
+        if let Some((lane, number)) = replaced {
+            if let Some(replaced) = self.remove(lane, number, /* batched */ false) {
+                self.metrics.replaced_transactions.inc();
+                replaced.notify(ReceiptStatus::Replaced);
+            }
+        }
+
+        // Make room for the transaction (or drop it).
+        if pending.transaction.len() > self.capacity {
+            self.metrics.evicted_transactions.inc();
+            return self.drop_pending(pending, ReceiptStatus::Overloaded);
+        }
+        while self.size + pending.transaction.len() > self.capacity {
+            self.metrics.evicted_transactions.inc();
+            let victim = self.victim(lane);
+            match victim.and_then(|(i, x)| self.remove(i, x, /* batched */ false)) {
+                Some(evicted) => self.drop_pending(evicted, ReceiptStatus::Overloaded),
+                None => return self.drop_pending(pending, ReceiptStatus::Overloaded),
+            }
+        }
+
+        if let Some((sender, nonce)) = &account {
+            self.admission.admit(sender, *nonce);
+        }
+        let number = self.next;
+        self.next += 1;
+        if let Some(key) = &pending.key {
+            self.keys.insert(key.clone(), (lane, number));
+        }
+        self.size += pending.transaction.len();
+        self.lanes[lane].insert(number, pending);
+    }
+
+    /// Returns the size of the transaction `pop` returns next (unless it expired).
+    pub fn peek_size(&self) -> Option<usize> {
+        self.lanes
+            .iter()
+            .rev()
+            .find_map(|lane| lane.values().next())
+            .map(|x| x.transaction.len())
+    }
+
+    /// Removes the next transaction to batch: the oldest transaction of the highest lane. The
+    /// expired transactions we come across are dropped.
+    pub fn pop(&mut self) -> Option<(Transaction, Option<ReceiptSender>)> {
+        let now = Instant::now();
+        loop {
+            let (lane, number) = (0..self.lanes.len())
+                .rev()
+                .find_map(|i| self.lanes[i].keys().next().map(|x| (i, *x)))?;
+            let expired = self.lanes[lane][&number]
+                .expiry
+                .is_some_and(|expiry| expiry <= now);
+            let pending = self.remove(lane, number, /* batched */ !expired)?;
+            if !expired {
+                return Some((pending.transaction, pending.waiter));
+            }
+            self.metrics.expired_transactions.inc();
+            self.drop_pending(pending, ReceiptStatus::Expired);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::Digest;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+#[cfg(test)]
+#[path = "tests/merkle_tests.rs"]
+pub mod merkle_tests;
+
+/// Returns the key under which we store the Merkle root of the batch of the specified digest.
+pub fn merkle_key(digest: &Digest) -> Vec<u8> {
+    [&digest.0[..], b"/merkle"].concat()
+}
+
+/// Hashes the leaves (prefixed by 0) and the inner nodes (prefixed by 1) of the tree apart, so that
+/// an inner node cannot pass for a transaction.
+fn hash(prefix: u8, parts: &[&[u8]]) -> Digest {
+    let mut hasher = Sha512::new();
+    hasher.update([prefix]);
+    parts.iter().for_each(|x| hasher.update(x));
+    Digest(hasher.finalize()[..32].try_into().unwrap())
+}
+
+fn leaf(transaction: &[u8]) -> Digest {
+    hash(0, &[transaction])
+}
+
+fn node(left: &Digest, right: &Digest) -> Digest {
+    hash(1, &[&left.0, &right.0])
+}
+
+/// Returns the levels of the Merkle tree over the leaves (the transactions of a batch, or its
+/// shards), from the leaves to the root. The last node of a level with an odd number of nodes is
+/// promoted to the next level as is.
+fn levels<T: AsRef<[u8]>>(leaves: &[T]) -> Vec<Vec<Digest>> {
+    let mut levels = vec![leaves.iter().map(|x| leaf(x.as_ref())).collect::<Vec<_>>()];
+    while levels.last().unwrap().len() > 1 {
+        let level = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node(left, right),
+                [last] => last.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(level);
+    }
+    levels
+}
+
+/// Returns the Merkle root over the transactions of a batch, or over any other leaves (the default
+/// digest if there are none).
+pub fn merkle_root<T: AsRef<[u8]>>(leaves: &[T]) -> Digest {
+    levels(leaves)
+        .pop()
+        .and_then(|mut level| level.pop())
+        .unwrap_or_default()
+}
+
+/// Proves that a transaction (or any other leaf) is included in a batch of known Merkle root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// The siblings of the path from the transaction to the root, and whether each of them is the
+    /// left node of its pair.
+    pub siblings: Vec<(Digest, bool)>,
+}
+
+impl MerkleProof {
+    /// Returns the proof that the transaction at the specified index is included in the batch (or
+    /// `None` if the batch has no such transaction).
+    pub fn new<T: AsRef<[u8]>>(leaves: &[T], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+        let mut siblings = Vec::new();
+        let mut index = index;
+        for level in levels(leaves).iter().filter(|x| x.len() > 1) {
+            let sibling = index ^ 1;
+            if let Some(digest) = level.get(sibling) {
+                siblings.push((digest.clone(), sibling < index));
+            }
+            index /= 2;
+        }
+        Some(Self { siblings })
+    }
+
+    /// Checks that the transaction is included in the batch of the specified Merkle root.
+    pub fn verify(&self, root: &Digest, transaction: &[u8]) -> bool {
+        let computed = self
+            .siblings
+            .iter()
+            .fold(leaf(transaction), |digest, (sibling, left)| match left {
+                true => node(sibling, &digest),
+                false => node(&digest, sibling),
+            });
+        &computed == root
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use network::NetworkMetrics;
+use prometheus::{
+    register_histogram_with_registry, register_int_counter_with_registry,
+    register_int_gauge_with_registry, Histogram, IntCounter, IntGauge, Registry,
+};
+
+/// The Prometheus metrics of the worker. Cloning the metrics shares them.
+#[derive(Clone)]
+pub struct WorkerMetrics {
+    /// The number of client transactions we received (before validation).
+    pub received_transactions: IntCounter,
+    /// The size of the batches we seal (in bytes).
+    pub batch_size: Histogram,
+    /// The time it takes to seal a batch: serialize (and compress or encode) and broadcast it.
+    pub batch_seal_latency: Histogram,
+    /// The number of missing batches the `Synchronizer` waits for.
+    pub pending_sync_requests: IntGauge,
+    /// An estimate of the size of the data held by the store (in bytes).
+    pub store_size: IntGauge,
+    /// The number of client transactions looked up in the dedup cache.
+    pub dedup_lookups: IntCounter,
+    /// The number of client transactions dropped because the dedup cache holds them.
+    pub dedup_hits: IntCounter,
+    /// The number of client transactions rejected by the transaction validator.
+    pub rejected_transactions: IntCounter,
+    /// The number of batches of other workers holding transactions rejected by the transaction
+    /// validator (they are stored anyway).
+    pub rejected_batches: IntCounter,
+    /// The time between the broadcast of our batch and the acknowledgement of enough workers.
+    pub dissemination_latency: Histogram,
+    /// The number of times we re-broadcast a batch to the workers that did not acknowledge it.
+    pub batch_rebroadcasts: IntCounter,
+    /// The number of client transactions dropped because their client exceeded its rate limit.
+    pub rate_limited_transactions: IntCounter,
+    /// The number of client transactions evicted from (or not admitted into) the full mempool.
+    pub evicted_transactions: IntCounter,
+    /// The number of client transactions replaced by a transaction of the same replacement key.
+    pub replaced_transactions: IntCounter,
+    /// The number of client transactions dropped because they expired before being batched.
+    pub expired_transactions: IntCounter,
+    /// The number of batches advertised by the other workers that we missed and pulled.
+    pub anti_entropy_pulls: IntCounter,
+    /// The number of client transactions rejected because they are routed to another worker.
+    pub misrouted_transactions: IntCounter,
+    /// The number of batches we sealed that did not gather enough acknowledgements yet.
+    pub batch_backlog: IntGauge,
+    /// The batch backlog at which we ask clients to retry later (0 if unbounded).
+    pub batch_backlog_watermark: IntGauge,
+    /// The number of client transactions refused because the batch backlog is above its watermark.
+    pub backlogged_transactions: IntCounter,
+    /// The number of times the network gave up on delivering one of our batches to a worker.
+    pub abandoned_batch_deliveries: IntCounter,
+    /// The state of the connections we keep with the other workers.
+    pub network: NetworkMetrics,
+}
+
+impl WorkerMetrics {
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            received_transactions: register_int_counter_with_registry!(
+                "worker_received_transactions",
+                "Number of client transactions received",
+                registry
+            )
+            .expect("Failed to register metric"),
+            batch_size: register_histogram_with_registry!(
+                "worker_batch_size_bytes",
+                "Size of the batches we seal",
+                vec![
+                    1_000.0,
+                    10_000.0,
+                    50_000.0,
+                    100_000.0,
+                    250_000.0,
+                    500_000.0,
+                    1_000_000.0,
+                    5_000_000.0
+                ],
+                registry
+            )
+            .expect("Failed to register metric"),
+            batch_seal_latency: register_histogram_with_registry!(
+                "worker_batch_seal_latency_seconds",
+                "Time to serialize and broadcast a batch",
+                registry
+            )
+            .expect("Failed to register metric"),
+            pending_sync_requests: register_int_gauge_with_registry!(
+                "worker_pending_sync_requests",
+                "Number of missing batches the synchronizer waits for",
+                registry
+            )
+            .expect("Failed to register metric"),
+            store_size: register_int_gauge_with_registry!(
+                "worker_store_size_bytes",
+                "Estimate of the size of the data held by the store",
+                registry
+            )
+            .expect("Failed to register metric"),
+            dedup_lookups: register_int_counter_with_registry!(
+                "worker_dedup_lookups",
+                "Number of client transactions looked up in the dedup cache",
+                registry
+            )
+            .expect("Failed to register metric"),
+            dedup_hits: register_int_counter_with_registry!(
+                "worker_dedup_hits",
+                "Number of client transactions dropped as duplicates",
+                registry
+            )
+            .expect("Failed to register metric"),
+            rejected_transactions: register_int_counter_with_registry!(
+                "worker_rejected_transactions",
+                "Number of client transactions rejected by the transaction validator",
+                registry
+            )
+            .expect("Failed to register metric"),
+            rejected_batches: register_int_counter_with_registry!(
+                "worker_rejected_batches",
+                "Number of batches of other workers holding transactions rejected by the transaction validator",
+                registry
+            )
+            .expect("Failed to register metric"),
+            dissemination_latency: register_histogram_with_registry!(
+                "worker_dissemination_latency_seconds",
+                "Time between the broadcast of our batch and the acknowledgement of enough workers",
+                registry
+            )
+            .expect("Failed to register metric"),
+            batch_rebroadcasts: register_int_counter_with_registry!(
+                "worker_batch_rebroadcasts",
+                "Number of batches re-broadcast to the workers that did not acknowledge them",
+                registry
+            )
+            .expect("Failed to register metric"),
+            rate_limited_transactions: register_int_counter_with_registry!(
+                "worker_rate_limited_transactions",
+                "Number of client transactions dropped because their client exceeded its rate limit",
+                registry
+            )
+            .expect("Failed to register metric"),
+            evicted_transactions: register_int_counter_with_registry!(
+                "worker_evicted_transactions",
+                "Number of client transactions evicted from the full mempool",
+                registry
+            )
+            .expect("Failed to register metric"),
+            replaced_transactions: register_int_counter_with_registry!(
+                "worker_replaced_transactions",
+                "Number of client transactions replaced by a transaction of the same key",
+                registry
+            )
+            .expect("Failed to register metric"),
+            expired_transactions: register_int_counter_with_registry!(
+                "worker_expired_transactions",
+                "Number of client transactions dropped because they expired before being batched",
+                registry
+            )
+            .expect("Failed to register metric"),
+            anti_entropy_pulls: register_int_counter_with_registry!(
+                "worker_anti_entropy_pulls",
+                "Number of batches advertised by the other workers that we missed and pulled",
+                registry
+            )
+            .expect("Failed to register metric"),
+            misrouted_transactions: register_int_counter_with_registry!(
+                "worker_misrouted_transactions",
+                "Number of client transactions rejected because they are routed to another worker",
+                registry
+            )
+            .expect("Failed to register metric"),
+            batch_backlog: register_int_gauge_with_registry!(
+                "worker_batch_backlog",
+                "Number of sealed batches waiting for enough acknowledgements",
+                registry
+            )
+            .expect("Failed to register metric"),
+            batch_backlog_watermark: register_int_gauge_with_registry!(
+                "worker_batch_backlog_watermark",
+                "Batch backlog at which clients are asked to retry later",
+                registry
+            )
+            .expect("Failed to register metric"),
+            backlogged_transactions: register_int_counter_with_registry!(
+                "worker_backlogged_transactions",
+                "Number of client transactions refused because the batch backlog is too large",
+                registry
+            )
+            .expect("Failed to register metric"),
+            abandoned_batch_deliveries: register_int_counter_with_registry!(
+                "worker_abandoned_batch_deliveries",
+                "Number of times the network gave up on delivering one of our batches to a worker",
+                registry
+            )
+            .expect("Failed to register metric"),
+            network: NetworkMetrics::new(registry),
+        }
+    }
+}
+
+impl Default for WorkerMetrics {
+    /// Metrics registered in a registry of their own (they are not exported).
+    fn default() -> Self {
+        Self::new(&Registry::new())
+    }
+}
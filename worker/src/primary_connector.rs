@@ -1,7 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::worker::SerializedBatchDigestMessage;
 use bytes::Bytes;
-use network::SimpleSender;
+use network::{NetworkContext, NetworkMetrics, QueueLimits, SimpleSender};
 use std::net::SocketAddr;
 use tokio::sync::mpsc::Receiver;
 
@@ -16,12 +16,23 @@ pub struct PrimaryConnector {
 }
 
 impl PrimaryConnector {
-    pub fn spawn(primary_address: SocketAddr, rx_digest: Receiver<SerializedBatchDigestMessage>) {
+    pub fn spawn(
+        primary_address: SocketAddr,
+        rx_digest: Receiver<SerializedBatchDigestMessage>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: NetworkMetrics,
+        context: NetworkContext,
+    ) {
         tokio::spawn(async move {
             Self {
                 primary_address,
                 rx_digest,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics),
             }
             .run()
             .await;
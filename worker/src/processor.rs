@@ -1,9 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::worker::SerializedBatchDigestMessage;
+use crate::compression::decompress_batch;
+use crate::erasure::ShardLayout;
+use crate::merkle::{merkle_key, merkle_root};
+use crate::worker::{SerializedBatchDigestMessage, WorkerMessage};
+use bytes::Bytes;
 use config::WorkerId;
 use crypto::Digest;
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
+use log::warn;
 use primary::WorkerPrimaryMessage;
 use std::convert::TryInto;
 use store::Store;
@@ -14,12 +19,25 @@ use tokio::sync::mpsc::{Receiver, Sender};
 pub mod processor_tests;
 
 /// Indicates a serialized `WorkerMessage::Batch` message.
-pub type SerializedBatchMessage = Vec<u8>;
+pub type SerializedBatchMessage = Bytes;
 
-/// Hashes and stores batches, it then outputs the batch's digest.
+/// Returns the Merkle root over the transactions of a serialized batch message.
+fn batch_root(serialized: &[u8]) -> Option<Digest> {
+    match bincode::deserialize(serialized).ok()? {
+        WorkerMessage::Batch(batch) => Some(merkle_root(&batch)),
+        WorkerMessage::CompressedBatch(compression, bytes) => decompress_batch(compression, &bytes)
+            .ok()
+            .map(|batch| merkle_root(&batch)),
+        _ => None,
+    }
+}
+
+/// Hashes and stores batches (along with the Merkle root over their transactions), it then outputs
+/// the batch's digest.
 pub struct Processor;
 
 impl Processor {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         // Our worker's id.
         id: WorkerId,
@@ -31,14 +49,46 @@ impl Processor {
         tx_digest: Sender<SerializedBatchDigestMessage>,
         // Whether we are processing our own batches or the batches of other nodes.
         own_digest: bool,
+        // The maximum size of a serialized batch.
+        max_batch_size: usize,
+        // Output channel to report the digests of the stored batches (if any).
+        tx_stored: Option<Sender<Digest>>,
+        // The layout of the shards of the batches, if the digests commit to them.
+        layout: Option<ShardLayout>,
     ) {
         tokio::spawn(async move {
             while let Some(batch) = rx_batch.recv().await {
+                if batch.len() > max_batch_size {
+                    warn!(
+                        "Dropping batch of {} B exceeding the maximum size of {} B",
+                        batch.len(),
+                        max_batch_size
+                    );
+                    continue;
+                }
+
                 // Hash the batch.
-                let digest = Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap());
+                let digest = match &layout {
+                    Some(layout) => layout.digest(&batch),
+                    None => Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap()),
+                };
+
+                // Commit to the transactions of the batch, so that we can later prove their inclusion.
+                if let Some(root) = batch_root(&batch) {
+                    store.write(merkle_key(&digest), root.to_vec()).await;
+                }
+
+                // Store the batch. This copies the batch, unless we hold its only reference (the
+                // network usually still holds our own batches, for the workers yet to acknowledge
+                // them).
+                store.write(digest.to_vec(), Vec::from(batch)).await;
 
-                // Store the batch.
-                store.write(digest.to_vec(), batch).await;
+                if let Some(tx_stored) = &tx_stored {
+                    tx_stored
+                        .send(digest.clone())
+                        .await
+                        .expect("Failed to send digest");
+                }
 
                 // Deliver the batch's digest.
                 let message = match own_digest {
@@ -0,0 +1,129 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::decryptor::decrypted_key;
+use crate::erasure::shard_key;
+use crate::merkle::merkle_key;
+use crate::worker::Round;
+use crypto::Digest;
+use log::{debug, error};
+use std::collections::BTreeMap;
+use store::{Store, StoreError};
+use tokio::sync::mpsc::Receiver;
+
+#[cfg(test)]
+#[path = "tests/pruner_tests.rs"]
+pub mod pruner_tests;
+
+/// The key of the range of rounds of the committed batches we still keep.
+const PRUNER_KEY: &[u8] = b"pruner";
+
+/// The key of the committed batches of a round.
+fn committed_key(round: Round) -> Vec<u8> {
+    [PRUNER_KEY, b"/", &round.to_be_bytes()].concat()
+}
+
+/// Deletes from the store the batches committed by the consensus, once they fall out of the
+/// retention window. Without it, the storage of the worker grows forever. The committed batches
+/// waiting for deletion are persisted (one key per round), so that they are still deleted after a
+/// restart.
+pub struct Pruner {
+    /// The persistent storage.
+    store: Store,
+    /// The number of rounds we keep the committed batches.
+    retention: Round,
+    /// Receives the digests of the committed batches (along with their commit round) from our primary.
+    rx_committed: Receiver<(Vec<Digest>, Round)>,
+    /// The committed batches we still keep, indexed by commit round.
+    committed: BTreeMap<Round, Vec<Digest>>,
+    /// The highest round committed so far.
+    last_committed_round: Round,
+}
+
+impl Pruner {
+    pub fn spawn(store: Store, retention: Round, rx_committed: Receiver<(Vec<Digest>, Round)>) {
+        tokio::spawn(async move {
+            let mut pruner = Self {
+                store,
+                retention,
+                rx_committed,
+                committed: BTreeMap::new(),
+                last_committed_round: 0,
+            };
+            pruner.restore().await;
+            pruner.run().await;
+        });
+    }
+
+    /// Loads the committed batches we did not delete before the last restart.
+    async fn restore(&mut self) {
+        let (first, last): (Round, Round) = match self.store.read(PRUNER_KEY.to_vec()).await {
+            Ok(Some(bytes)) => bincode::deserialize(&bytes).expect("Failed to deserialize rounds"),
+            Ok(None) => return,
+            Err(e) => panic!("Storage failure: {}", e),
+        };
+        for round in first..=last {
+            match self.store.read(committed_key(round)).await {
+                Ok(Some(bytes)) => {
+                    let digests =
+                        bincode::deserialize(&bytes).expect("Failed to deserialize digests");
+                    self.committed.insert(round, digests);
+                }
+                Ok(None) => (),
+                Err(e) => panic!("Storage failure: {}", e),
+            }
+        }
+        self.last_committed_round = last;
+        debug!(
+            "Restored the committed batches of rounds {} to {}",
+            first, last
+        );
+    }
+
+    /// Deletes a committed batch (and what we store alongside it).
+    async fn delete(&mut self, digest: &Digest) -> Result<(), StoreError> {
+        debug!("Deleting committed batch {}", digest);
+        self.store.delete(digest.to_vec()).await?;
+        self.store.delete(shard_key(digest)).await?;
+        self.store.delete(decrypted_key(digest)).await?;
+        self.store.delete(merkle_key(digest)).await
+    }
+
+    /// Deletes the batches committed before the retention window. If the store fails, we keep the
+    /// remaining batches and try again at the next commit.
+    async fn prune(&mut self) -> Result<(), StoreError> {
+        let limit = self.last_committed_round.saturating_sub(self.retention);
+        while let Some(entry) = self.committed.first_entry() {
+            if *entry.key() > limit {
+                break;
+            }
+            let (round, digests) = entry.remove_entry();
+            for (i, digest) in digests.iter().enumerate() {
+                if let Err(e) = self.delete(digest).await {
+                    self.committed.insert(round, digests[i..].to_vec());
+                    return Err(e);
+                }
+            }
+            self.store.delete(committed_key(round)).await?;
+        }
+        Ok(())
+    }
+
+    async fn run(&mut self) {
+        while let Some((digests, round)) = self.rx_committed.recv().await {
+            let batches = self.committed.entry(round).or_default();
+            batches.extend(digests);
+            let bytes = bincode::serialize(batches).expect("Failed to serialize digests");
+            self.store.write(committed_key(round), bytes).await;
+            self.last_committed_round = round.max(self.last_committed_round);
+
+            if let Err(e) = self.prune().await {
+                error!("Failed to delete committed batches: {}", e);
+            }
+
+            // Record the rounds we still need to prune after a restart.
+            let first = self.committed.keys().next().copied().unwrap_or(round);
+            let rounds = (first, self.last_committed_round);
+            let bytes = bincode::serialize(&rounds).expect("Failed to serialize rounds");
+            self.store.write(PRUNER_KEY.to_vec(), bytes).await;
+        }
+    }
+}
@@ -1,87 +1,398 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Broadcast;
+use crate::metrics::WorkerMetrics;
 use crate::processor::SerializedBatchMessage;
-use config::{Committee, Stake};
-use crypto::PublicKey;
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{AckThreshold, Committee, QuorumWaiterParameters, Stake, WorkerId};
+use crypto::{Digest, PublicKey};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use network::CancelHandler;
+use log::{debug, error, warn};
+use network::{
+    CancelHandler, Compression, NetworkContext, QueueLimits, ReliableSender, RetryPolicy,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/quorum_waiter_tests.rs"]
 pub mod quorum_waiter_tests;
 
+/// The key under which we store the range of sequence numbers of the batches that did not gather
+/// enough acknowledgements yet.
+const UNACKED_RANGE_KEY: &[u8] = b"unacked_batches";
+
+/// Returns the key under which we store a batch that did not gather enough acknowledgements yet.
+fn unacked_key(sequence: u64) -> Vec<u8> {
+    [&b"unacked/"[..], &sequence.to_be_bytes()[..]].concat()
+}
+
+/// Returns the key under which we store the acknowledgements of a batch.
+fn acks_key(sequence: u64) -> Vec<u8> {
+    [&b"unacked_acks/"[..], &sequence.to_be_bytes()[..]].concat()
+}
+
+/// A batch we sealed that did not gather enough acknowledgements yet. We persist it once (and its
+/// acknowledgements under their own key, as they arrive), so that we re-broadcast it if we crash
+/// before reaching a quorum.
+#[derive(Serialize, Deserialize)]
+pub struct UnackedBatch {
+    /// The digest of the batch.
+    pub digest: Digest,
+    /// A serialized `WorkerMessage::Batch` message.
+    pub batch: SerializedBatchMessage,
+    /// The authorities whose worker we sent the batch to.
+    pub recipients: Vec<PublicKey>,
+    /// A bitmap of the recipients that acknowledged the batch (see `acks_key`).
+    #[serde(skip)]
+    pub acks: Vec<u8>,
+}
+
+impl UnackedBatch {
+    pub fn new(digest: Digest, batch: SerializedBatchMessage, recipients: Vec<PublicKey>) -> Self {
+        let acks = vec![0; recipients.len().div_ceil(8)];
+        Self {
+            digest,
+            batch,
+            recipients,
+            acks,
+        }
+    }
+
+    /// Records the acknowledgement of a recipient.
+    pub fn acknowledge(&mut self, name: &PublicKey) {
+        if let Some(i) = self.recipients.iter().position(|x| x == name) {
+            self.acks[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    /// Returns the recipients that acknowledged the batch.
+    pub fn acknowledged(&self) -> HashSet<PublicKey> {
+        self.recipients
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.acks[i / 8] & (1 << (i % 8)) != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct QuorumWaiterMessage {
+    /// The digest of the batch.
+    pub digest: Digest,
     /// A serialized `WorkerMessage::Batch` message.
     pub batch: SerializedBatchMessage,
     /// The cancel handlers to receive the acknowledgements of our broadcast.
     pub handlers: Vec<(PublicKey, CancelHandler)>,
 }
 
-/// The QuorumWaiter waits for 2f authorities to acknowledge reception of a batch.
+/// The QuorumWaiter waits for enough authorities (2f by default) to acknowledge reception of a
+/// batch, re-broadcasting the batch to the others when they take too long (or after a crash). We
+/// re-broadcast the way the batch maker broadcasts: the batch, its shards or its digest.
 pub struct QuorumWaiter {
     /// The committee information.
     committee: Committee,
+    /// Our worker id.
+    id: WorkerId,
     /// The stake of this authority.
     stake: Stake,
+    /// The stake that must acknowledge a batch (counting our own).
+    threshold: Stake,
+    /// The delay after which we re-broadcast a batch to the workers that did not acknowledge it.
+    rebroadcast_timeout: Duration,
+    /// What we send the other workers of each batch.
+    broadcast: Broadcast,
     /// Input Channel to receive commands.
     rx_message: Receiver<QuorumWaiterMessage>,
     /// Channel to deliver batches for which we have enough acknowledgements.
     tx_batch: Sender<SerializedBatchMessage>,
+    /// Reports the time the other workers took to acknowledge our batches to the `BatchMaker`.
+    tx_latency: Sender<Duration>,
+    /// A network sender to re-broadcast the batches.
+    network: ReliableSender,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
+    /// The persistent storage, holding the batches that did not gather enough acknowledgements.
+    store: Store,
+    /// The sequence number of the next batch we persist. We wait for the acknowledgements of one
+    /// batch at a time, so the batches persisted before it are those we did not deliver yet.
+    next_sequence: u64,
 }
 
 impl QuorumWaiter {
     /// Spawn a new QuorumWaiter.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         committee: Committee,
+        id: WorkerId,
         stake: Stake,
+        parameters: QuorumWaiterParameters,
         rx_message: Receiver<QuorumWaiterMessage>,
-        tx_batch: Sender<Vec<u8>>,
+        tx_batch: Sender<SerializedBatchMessage>,
+        tx_latency: Sender<Duration>,
+        store: Store,
+        retry_policy: RetryPolicy,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        transport_compression: Compression,
+        metrics: WorkerMetrics,
+        context: NetworkContext,
     ) {
         tokio::spawn(async move {
+            let threshold = match parameters.threshold {
+                AckThreshold::Validity => committee.validity_threshold(),
+                AckThreshold::Quorum => committee.quorum_threshold(),
+            };
+            let broadcast = Broadcast::new(&committee);
             Self {
                 committee,
+                id,
                 stake,
+                threshold,
+                rebroadcast_timeout: Duration::from_millis(parameters.rebroadcast_timeout),
+                broadcast,
                 rx_message,
                 tx_batch,
+                tx_latency,
+                network: ReliableSender::new()
+                    .with_context(context)
+                    .with_retry_policy(retry_policy)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_compression(transport_compression)
+                    .with_metrics(metrics.network.clone()),
+                metrics,
+                store,
+                next_sequence: 0,
             }
             .run()
             .await;
         });
     }
 
-    /// Helper function. It waits for a future to complete and then delivers a value.
-    async fn waiter(wait_for: CancelHandler, deliver: Stake) -> Stake {
-        let _ = wait_for.await;
-        deliver
+    /// Helper function. It waits for the acknowledgement of a worker and then delivers its name, or
+    /// an error holding its name if the network gave up on reaching the worker.
+    async fn waiter(wait_for: CancelHandler, deliver: PublicKey) -> Result<PublicKey, PublicKey> {
+        match wait_for.await {
+            Ok(_) => Ok(deliver),
+            Err(_) => Err(deliver),
+        }
     }
 
-    /// Main loop.
-    async fn run(&mut self) {
-        while let Some(QuorumWaiterMessage { batch, handlers }) = self.rx_message.recv().await {
-            let mut wait_for_quorum: FuturesUnordered<_> = handlers
-                .into_iter()
-                .map(|(name, handler)| {
-                    let stake = self.committee.stake(&name);
-                    Self::waiter(handler, stake)
-                })
-                .collect();
+    /// Re-broadcasts the batch to the specified workers, returning the new cancel handlers. Dropping
+    /// the previous handlers cancels the previous transmissions.
+    async fn rebroadcast(
+        &mut self,
+        unacked: &UnackedBatch,
+        names: Vec<PublicKey>,
+    ) -> Vec<(PublicKey, CancelHandler)> {
+        let (names, addresses): (Vec<_>, _) = names
+            .into_iter()
+            .filter_map(|name| {
+                self.committee
+                    .worker(&name, &self.id)
+                    .ok()
+                    .map(|x| (name, x.worker_to_worker))
+            })
+            .unzip();
+        let handlers = match &self.broadcast {
+            Broadcast::Batch => {
+                self.network
+                    .broadcast(addresses, unacked.batch.clone())
+                    .await
+            }
+            Broadcast::Digest => {
+                let message = WorkerMessage::BatchAnnouncement(unacked.digest.clone());
+                let bytes =
+                    bincode::serialize(&message).expect("Failed to serialize our announcement");
+                self.network.broadcast(addresses, Bytes::from(bytes)).await
+            }
+            Broadcast::Shards(layout) => {
+                let (_, shards) = layout.shards(&unacked.batch);
+                let mut shards: Vec<_> = shards.into_iter().map(Some).collect();
+                let mut handlers = Vec::new();
+                for (name, address) in names.iter().zip(addresses) {
+                    let index = layout.index(name).expect("Unknown worker");
+                    let shard = shards[index].take().expect("Duplicate worker");
+                    let message = WorkerMessage::BatchShard(unacked.digest.clone(), shard);
+                    let bytes =
+                        bincode::serialize(&message).expect("Failed to serialize our own shard");
+                    handlers.push(self.network.send(address, Bytes::from(bytes)).await);
+                }
+                handlers
+            }
+        };
+        names.into_iter().zip(handlers).collect()
+    }
+
+    /// Persists the range of sequence numbers of the batches we did not deliver yet.
+    async fn persist_range(&mut self, first: u64) {
+        let range = (first, self.next_sequence);
+        let bytes = bincode::serialize(&range).expect("Failed to serialize batch range");
+        self.store.write(UNACKED_RANGE_KEY.to_vec(), bytes).await;
+    }
+
+    /// Persists a batch we sealed (before waiting for its acknowledgements), returning its sequence
+    /// number.
+    async fn persist(&mut self, unacked: &UnackedBatch) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        let bytes = bincode::serialize(unacked).expect("Failed to serialize unacknowledged batch");
+        self.store.write(unacked_key(sequence), bytes).await;
+        self.persist_range(sequence).await;
+        sequence
+    }
+
+    /// Loads the batches we sealed before crashing, that did not gather enough acknowledgements.
+    async fn recover(&mut self) -> Vec<(u64, UnackedBatch)> {
+        let (first, next): (u64, u64) = match self.store.read(UNACKED_RANGE_KEY.to_vec()).await {
+            Ok(Some(bytes)) => bincode::deserialize(&bytes).unwrap_or_default(),
+            Ok(None) => return Vec::new(),
+            Err(e) => {
+                error!("Failed to load unacknowledged batches: {}", e);
+                return Vec::new();
+            }
+        };
+        self.next_sequence = next;
+        let mut recovered = Vec::new();
+        for sequence in first..next {
+            let mut unacked: UnackedBatch = match self.store.read(unacked_key(sequence)).await {
+                Ok(Some(bytes)) => match bincode::deserialize(&bytes) {
+                    Ok(unacked) => unacked,
+                    Err(e) => {
+                        error!("Failed to load unacknowledged batch {}: {}", sequence, e);
+                        continue;
+                    }
+                },
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            };
+            let size = unacked.recipients.len().div_ceil(8);
+            unacked.acks = match self.store.read(acks_key(sequence)).await {
+                Ok(Some(acks)) if acks.len() == size => acks,
+                _ => vec![0; size],
+            };
+            recovered.push((sequence, unacked));
+        }
+        recovered
+    }
+
+    /// Waits for enough nodes to send back an Ack. Then we consider the batch delivered and we send
+    /// its digest to the primary (that will include it into the dag). This should reduce the amount
+    /// of synching.
+    async fn wait_for_quorum(
+        &mut self,
+        sequence: u64,
+        mut unacked: UnackedBatch,
+        handlers: Vec<(PublicKey, CancelHandler)>,
+    ) {
+        let now = Instant::now();
+        let digest = unacked.digest.clone();
+
+        let mut wait_for_quorum: FuturesUnordered<_> = handlers
+            .into_iter()
+            .map(|(name, handler)| Self::waiter(handler, name))
+            .collect();
 
-            // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
-            // delivered and we send its digest to the primary (that will include it into
-            // the dag). This should reduce the amount of synching.
-            let mut total_stake = self.stake;
-            while let Some(stake) = wait_for_quorum.next().await {
-                total_stake += stake;
-                if total_stake >= self.committee.quorum_threshold() {
-                    self.tx_batch
-                        .send(batch)
+        let timer = sleep(self.rebroadcast_timeout);
+        tokio::pin!(timer);
+
+        let mut total_stake = self.stake
+            + unacked
+                .acknowledged()
+                .iter()
+                .map(|x| self.committee.stake(x))
+                .sum::<Stake>();
+        while total_stake < self.threshold {
+            tokio::select! {
+                Some(result) = wait_for_quorum.next() => match result {
+                    Ok(name) => {
+                        total_stake += self.committee.stake(&name);
+                        unacked.acknowledge(&name);
+                        self.store.write(acks_key(sequence), unacked.acks.clone()).await;
+                    }
+                    // We re-broadcast the batch to the worker on the next timeout.
+                    Err(name) => {
+                        warn!("Gave up on delivering batch {} to {}", digest, name);
+                        self.metrics.abandoned_batch_deliveries.inc();
+                    }
+                },
+                () = &mut timer => {
+                    let acknowledged = unacked.acknowledged();
+                    let pending: Vec<_> = unacked
+                        .recipients
+                        .iter()
+                        .filter(|x| !acknowledged.contains(*x))
+                        .cloned()
+                        .collect();
+                    debug!("Re-broadcasting batch to {} workers", pending.len());
+                    self.metrics.batch_rebroadcasts.inc();
+                    wait_for_quorum = self
+                        .rebroadcast(&unacked, pending)
                         .await
-                        .expect("Failed to deliver batch");
-                    break;
+                        .into_iter()
+                        .map(|(name, handler)| Self::waiter(handler, name))
+                        .collect();
+                    timer.as_mut().reset(Instant::now() + self.rebroadcast_timeout);
                 }
             }
         }
+
+        self.metrics
+            .dissemination_latency
+            .observe(now.elapsed().as_secs_f64());
+        // The `BatchMaker` only needs a sample of the latencies, never wait for it.
+        let _ = self.tx_latency.try_send(now.elapsed());
+        self.tx_batch
+            .send(unacked.batch)
+            .await
+            .expect("Failed to deliver batch");
+
+        // We deliver the batches in sequence, so all batches before this one are delivered too.
+        self.persist_range(sequence + 1).await;
+        for key in [unacked_key(sequence), acks_key(sequence)] {
+            if let Err(e) = self.store.delete(key).await {
+                error!("Failed to delete acknowledged batch {}: {}", digest, e);
+            }
+        }
+    }
+
+    /// Main loop.
+    async fn run(&mut self) {
+        // Re-broadcast the batches we sealed before crashing, to the workers that did not
+        // acknowledge them.
+        for (sequence, unacked) in self.recover().await {
+            let acknowledged = unacked.acknowledged();
+            let pending: Vec<_> = unacked
+                .recipients
+                .iter()
+                .filter(|x| !acknowledged.contains(*x))
+                .cloned()
+                .collect();
+            debug!("Re-broadcasting batch {} after restart", unacked.digest);
+            let handlers = self.rebroadcast(&unacked, pending).await;
+            self.wait_for_quorum(sequence, unacked, handlers).await;
+        }
+
+        while let Some(QuorumWaiterMessage {
+            digest,
+            batch,
+            handlers,
+        }) = self.rx_message.recv().await
+        {
+            let recipients = handlers.iter().map(|(name, _)| *name).collect();
+            let unacked = UnackedBatch::new(digest, batch, recipients);
+            let sequence = self.persist(&unacked).await;
+            self.wait_for_quorum(sequence, unacked, handlers).await;
+            self.metrics.batch_backlog.dec();
+        }
     }
 }
@@ -0,0 +1,110 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::Digest;
+use log::debug;
+use std::collections::{HashMap, VecDeque};
+
+#[cfg(test)]
+#[path = "tests/reassembler_tests.rs"]
+pub mod reassembler_tests;
+
+/// The maximum number of bytes of partially received batches we hold. Beyond, we drop the oldest
+/// partial batches, so that bad workers cannot exhaust our memory.
+pub const MAX_PENDING_SIZE: usize = 64 * 1024 * 1024;
+
+/// A batch of which we received the first chunks.
+struct PartialBatch {
+    /// The total number of chunks of the batch.
+    total: u32,
+    /// The number of chunks we received.
+    received: u32,
+    /// The concatenation of the chunks we received.
+    bytes: Vec<u8>,
+}
+
+/// The connection streaming a batch and the digest of the batch.
+type Key = (String, Digest);
+
+/// Reassembles the batches that other workers stream in chunks. The chunks of a batch travel over
+/// a single connection and thus arrive in order: a chunk arriving out of order drops its batch
+/// (the synchronizer requests it again later). Several workers may stream us the same batch (when
+/// we request it again), so the batches are reassembled separately for each connection.
+#[derive(Default)]
+pub struct BatchReassembler {
+    /// The batches being reassembled, indexed by connection and digest.
+    pending: HashMap<Key, PartialBatch>,
+    /// The batches being reassembled, oldest first.
+    order: VecDeque<Key>,
+    /// The number of bytes held in `pending`.
+    size: usize,
+}
+
+impl BatchReassembler {
+    /// Drops a partially received batch.
+    fn drop_batch(&mut self, key: &Key) {
+        if let Some(partial) = self.pending.remove(key) {
+            self.size -= partial.bytes.len();
+            self.order.retain(|x| x != key);
+        }
+    }
+
+    /// Drops the oldest partial batches (but the specified one) until `size` more bytes fit.
+    fn make_room(&mut self, key: &Key, size: usize) {
+        while self.size + size > MAX_PENDING_SIZE {
+            match self.order.iter().find(|x| *x != key).cloned() {
+                Some(oldest) => self.drop_batch(&oldest),
+                None => return,
+            }
+        }
+    }
+
+    /// Adds a chunk of a batch streamed over the specified connection, returning the serialized
+    /// batch once we received all its chunks.
+    pub fn add(
+        &mut self,
+        connection: &str,
+        digest: Digest,
+        index: u32,
+        total: u32,
+        chunk: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        let key = (connection.to_string(), digest);
+        if index == 0 {
+            self.drop_batch(&key);
+            self.pending.insert(
+                key.clone(),
+                PartialBatch {
+                    total,
+                    received: 0,
+                    bytes: Vec::new(),
+                },
+            );
+            self.order.push_back(key.clone());
+        }
+        self.make_room(&key, chunk.len());
+
+        let in_order = matches!(
+            self.pending.get(&key),
+            Some(x) if x.received == index && x.total == total && index < total
+        );
+        if !in_order || self.size + chunk.len() > MAX_PENDING_SIZE {
+            debug!(
+                "Dropping chunk {}/{} of batch {} from {}",
+                index, total, key.1, key.0
+            );
+            self.drop_batch(&key);
+            return None;
+        }
+
+        self.size += chunk.len();
+        let partial = self.pending.get_mut(&key).unwrap();
+        partial.bytes.extend(chunk);
+        partial.received += 1;
+        if partial.received < partial.total {
+            return None;
+        }
+        let partial = self.pending.remove(&key).unwrap();
+        self.size -= partial.bytes.len();
+        self.order.retain(|x| x != &key);
+        Some(partial.bytes)
+    }
+}
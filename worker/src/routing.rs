@@ -0,0 +1,49 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::validator::TransactionValidator;
+use config::WorkerId;
+use ed25519_dalek::{Digest as _, Sha512};
+use std::convert::TryInto as _;
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/routing_tests.rs"]
+pub mod routing_tests;
+
+/// Returns the id of the worker (of each authority) batching the transactions of the specified
+/// routing key, given the number of workers of the authorities (numbered from 0). Clients follow
+/// the same rule to submit their transactions to the right worker, so that the transactions
+/// touching the same state land in the same batches.
+pub fn route(key: &[u8], workers: usize) -> WorkerId {
+    let digest = Sha512::digest(key);
+    let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    (hash % workers.max(1) as u64) as WorkerId
+}
+
+/// Checks that the transactions we receive are routed to us.
+#[derive(Clone)]
+pub struct Router {
+    /// Our worker id.
+    id: WorkerId,
+    /// The number of workers of our authority.
+    workers: usize,
+    /// Tells the routing key of the transactions.
+    validator: Arc<dyn TransactionValidator>,
+}
+
+impl Router {
+    pub fn new(id: WorkerId, workers: usize, validator: Arc<dyn TransactionValidator>) -> Self {
+        Self {
+            id,
+            workers,
+            validator,
+        }
+    }
+
+    /// Returns the id of the worker the transaction is routed to, unless it is us (or the
+    /// transaction has no routing key).
+    pub fn misrouted(&self, transaction: &[u8]) -> Option<WorkerId> {
+        let key = self.validator.routing_key(transaction)?;
+        let id = route(&key, self.workers);
+        (id != self.id).then_some(id)
+    }
+}
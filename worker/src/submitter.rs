@@ -0,0 +1,206 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::{ReceiptSender, Transaction};
+use crate::limiter::ClientLimiter;
+use crate::metrics::WorkerMetrics;
+use crate::routing::Router;
+use crate::validator::TransactionValidator;
+use crate::worker::{Receipt, ReceiptStatus};
+use bytes::Bytes;
+use config::WorkerId;
+use crypto::Digest;
+use ed25519_dalek::{Digest as _, Sha512};
+use std::convert::TryInto as _;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+#[path = "tests/submitter_tests.rs"]
+pub mod submitter_tests;
+
+#[derive(Debug, Error)]
+pub enum SubmitError {
+    #[error("Transaction of {0} B exceeds the maximum size of {1} B")]
+    Oversized(usize, usize),
+
+    #[error("Invalid transaction: {0}")]
+    Invalid(String),
+
+    #[error("Worker overloaded")]
+    Overloaded,
+
+    #[error("Client {0} exceeded its rate limit")]
+    RateLimited(String),
+
+    #[error("Transaction routed to worker {0}")]
+    Misrouted(WorkerId),
+
+    #[error("Worker backlogged with {0} unacknowledged batches, retry later")]
+    Backlogged(usize),
+}
+
+/// Submits transactions to the worker on behalf of an external service (such as a gRPC endpoint).
+/// Unlike the TCP ingress, which waits for room in the worker, submissions fail as soon as the
+/// worker is overloaded so that clients can back off.
+#[derive(Clone)]
+pub struct TransactionSubmitter {
+    /// Sends the transactions to the `BatchMaker`.
+    tx_batch_maker: Sender<(Transaction, Option<ReceiptSender>)>,
+    /// Checks the transactions before we batch them (unless the `TransactionVerifier` does).
+    validator: Option<Arc<dyn TransactionValidator>>,
+    /// The maximum size of a transaction (if any).
+    max_transaction_size: Option<usize>,
+    /// Limits the rate at which each client submits transactions (if set).
+    limiter: Option<ClientLimiter>,
+    /// Rejects the transactions routed to other workers (if enforced).
+    router: Option<Router>,
+    /// The batch backlog above which we ask clients to retry later (if any).
+    max_batch_backlog: Option<usize>,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
+}
+
+impl TransactionSubmitter {
+    pub(crate) fn new(
+        tx_batch_maker: Sender<(Transaction, Option<ReceiptSender>)>,
+        validator: Option<Arc<dyn TransactionValidator>>,
+        max_transaction_size: Option<usize>,
+        client_rate_limit: Option<u64>,
+        metrics: WorkerMetrics,
+    ) -> Self {
+        Self {
+            tx_batch_maker,
+            validator,
+            max_transaction_size,
+            limiter: client_rate_limit.map(ClientLimiter::new),
+            router: None,
+            max_batch_backlog: None,
+            metrics,
+        }
+    }
+
+    /// Returns a submitter rejecting the transactions routed to other workers.
+    pub(crate) fn with_router(self, router: Router) -> Self {
+        Self {
+            router: Some(router),
+            ..self
+        }
+    }
+
+    /// Returns a submitter refusing transactions while the specified number of our batches (or more)
+    /// wait for the acknowledgements of the other workers.
+    pub(crate) fn with_max_batch_backlog(self, max_batch_backlog: usize) -> Self {
+        self.metrics
+            .batch_backlog_watermark
+            .set(max_batch_backlog as i64);
+        Self {
+            max_batch_backlog: Some(max_batch_backlog),
+            ..self
+        }
+    }
+
+    /// Returns a submitter enforcing the specified rate limit (if any), independently of ours.
+    pub(crate) fn with_rate_limit(&self, client_rate_limit: Option<u64>) -> Self {
+        Self {
+            limiter: client_rate_limit.map(ClientLimiter::new),
+            ..self.clone()
+        }
+    }
+
+    /// Charges a transaction to the rate limit of its client, identified by its address (or `None`
+    /// for the clients of the Unix domain socket).
+    pub(crate) fn admit(&self, client: Option<IpAddr>) -> Result<(), SubmitError> {
+        match &self.limiter {
+            Some(limiter) if !limiter.try_acquire(client) => {
+                self.metrics.rate_limited_transactions.inc();
+                let client = client.map_or_else(|| "unix".to_string(), |x| x.to_string());
+                Err(SubmitError::RateLimited(client))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the size of a transaction and runs the transaction validator (if any). We first shed
+    /// the load if our batch backlog is too large, before spending any work on the transaction.
+    pub(crate) fn check(&self, transaction: &[u8]) -> Result<(), SubmitError> {
+        self.metrics.received_transactions.inc();
+        if let Some(max) = self.max_batch_backlog {
+            let backlog = self.metrics.batch_backlog.get().max(0) as usize;
+            if backlog >= max {
+                self.metrics.backlogged_transactions.inc();
+                return Err(SubmitError::Backlogged(backlog));
+            }
+        }
+        if let Some(max) = self.max_transaction_size {
+            if transaction.len() > max {
+                return Err(SubmitError::Oversized(transaction.len(), max));
+            }
+        }
+        if let Some(id) = self.router.as_ref().and_then(|x| x.misrouted(transaction)) {
+            self.metrics.misrouted_transactions.inc();
+            return Err(SubmitError::Misrouted(id));
+        }
+        if let Some(validator) = &self.validator {
+            if let Err(e) = validator.validate(transaction) {
+                self.metrics.rejected_transactions.inc();
+                return Err(SubmitError::Invalid(e.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a checked transaction to the `BatchMaker`, waiting for room in its inbox.
+    pub(crate) async fn send(&self, transaction: Transaction, waiter: Option<ReceiptSender>) {
+        self.tx_batch_maker
+            .send((transaction, waiter))
+            .await
+            .expect("Failed to send transaction");
+    }
+
+    /// Submits a transaction. The returned future resolves to the receipt of the transaction once
+    /// it is sealed into a batch (or dropped as a duplicate).
+    pub fn submit(
+        &self,
+        transaction: Transaction,
+    ) -> Result<impl Future<Output = Receipt>, SubmitError> {
+        self.check(&transaction)?;
+        self.enqueue(transaction)
+    }
+
+    /// Submits a transaction on behalf of the client of the specified address, charging it to the
+    /// rate limit of the client.
+    pub fn submit_from(
+        &self,
+        client: IpAddr,
+        transaction: Transaction,
+    ) -> Result<impl Future<Output = Receipt>, SubmitError> {
+        self.admit(Some(client))?;
+        self.check(&transaction)?;
+        self.enqueue(transaction)
+    }
+
+    fn enqueue(
+        &self,
+        transaction: Transaction,
+    ) -> Result<impl Future<Output = Receipt>, SubmitError> {
+        let digest = Digest(Sha512::digest(&transaction)[..32].try_into().unwrap());
+        // The transaction may be a slice of a larger request buffer: copy it so that the mempool
+        // does not pin the whole buffer (and its byte accounting holds).
+        let transaction = Bytes::copy_from_slice(&transaction);
+        let (sender, receiver) = oneshot::channel();
+        match self.tx_batch_maker.try_send((transaction, Some(sender))) {
+            Ok(()) => Ok(async move {
+                Receipt {
+                    transaction: digest,
+                    status: receiver.await.unwrap_or(ReceiptStatus::RetryLater),
+                }
+            }),
+            Err(TrySendError::Full(_)) => Err(SubmitError::Overloaded),
+            Err(TrySendError::Closed(_)) => panic!("Failed to send transaction"),
+        }
+    }
+}
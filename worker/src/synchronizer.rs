@@ -1,12 +1,13 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::metrics::WorkerMetrics;
 use crate::worker::{Round, WorkerMessage};
 use bytes::Bytes;
-use config::{Committee, WorkerId};
+use config::{BatchDissemination, Committee, WorkerId};
 use crypto::{Digest, PublicKey};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use log::{debug, error};
-use network::SimpleSender;
+use log::{debug, error, warn};
+use network::{NetworkContext, QueueLimits, SimpleSender};
 use primary::PrimaryWorkerMessage;
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -38,6 +39,8 @@ pub struct Synchronizer {
     /// Determine with how many nodes to sync when re-trying to send sync-requests. These nodes
     /// are picked at random from the committee.
     sync_retry_nodes: usize,
+    /// The maximum number of missing batches we wait for at any time.
+    sync_limit: usize,
     /// Input channel to receive the commands from the primary.
     rx_message: Receiver<PrimaryWorkerMessage>,
     /// A network sender to send requests to the other workers.
@@ -48,6 +51,8 @@ pub struct Synchronizer {
     /// processing will resume when we get the missing batches in the store or we no longer need them.
     /// It also keeps the round number and a timestamp (`u128`) of each request we sent.
     pending: HashMap<Digest, (Round, Sender<()>, u128)>,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
 }
 
 impl Synchronizer {
@@ -60,7 +65,12 @@ impl Synchronizer {
         gc_depth: Round,
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
+        sync_limit: usize,
         rx_message: Receiver<PrimaryWorkerMessage>,
+        queue: QueueLimits,
+        max_frame_size: usize,
+        metrics: WorkerMetrics,
+        context: NetworkContext,
     ) {
         tokio::spawn(async move {
             Self {
@@ -71,10 +81,16 @@ impl Synchronizer {
                 gc_depth,
                 sync_retry_delay,
                 sync_retry_nodes,
+                sync_limit,
                 rx_message,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_context(context)
+                    .with_queue(queue)
+                    .with_max_frame_size(max_frame_size)
+                    .with_metrics(metrics.network.clone()),
                 round: Round::default(),
                 pending: HashMap::new(),
+                metrics,
             }
             .run()
             .await;
@@ -116,11 +132,20 @@ impl Synchronizer {
 
                         let mut missing = Vec::new();
                         for digest in digests {
-                            // Ensure we do not send twice the same sync request.
+                            // Ensure we do not wait twice for the same batch. If the primary asks again
+                            // (it did not get the batch in time), we request it from the new target.
                             if self.pending.contains_key(&digest) {
+                                missing.push(digest);
                                 continue;
                             }
 
+                            // Bound the number of batches we wait for. The primary will ask us again
+                            // if it still needs the remaining ones.
+                            if self.pending.len() >= self.sync_limit {
+                                warn!("Too many pending sync requests: not requesting batch {}", digest);
+                                break;
+                            }
+
                             // Check if we received the batch in the meantime.
                             match self.store.read(digest.to_vec()).await {
                                 Ok(None) => {
@@ -174,6 +199,9 @@ impl Synchronizer {
                         }
                         self.pending.retain(|_, (r, _, _)| r > &mut gc_round);
                     }
+                    PrimaryWorkerMessage::Confirm(_) | PrimaryWorkerMessage::Committed(..) => {
+                        // The network receiver handles these messages directly.
+                    }
                 },
 
                 // Stream out the futures of the `FuturesUnordered` that completed.
@@ -212,8 +240,15 @@ impl Synchronizer {
                             .collect();
                         let message = WorkerMessage::BatchRequest(retry, self.name);
                         let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+                        // Reconstructing a batch from its shards takes the replies of f+1 workers.
+                        let nodes = match self.committee.batch_dissemination {
+                            BatchDissemination::Replicated | BatchDissemination::Pull => {
+                                self.sync_retry_nodes
+                            }
+                            BatchDissemination::ErasureCoded => usize::MAX,
+                        };
                         self.network
-                            .lucky_broadcast(addresses, Bytes::from(serialized), self.sync_retry_nodes)
+                            .lucky_broadcast(addresses, Bytes::from(serialized), nodes)
                             .await;
                     }
 
@@ -221,6 +256,9 @@ impl Synchronizer {
                     timer.as_mut().reset(Instant::now() + Duration::from_millis(TIMER_RESOLUTION));
                 },
             }
+            self.metrics
+                .pending_sync_requests
+                .set(self.pending.len() as i64);
         }
     }
 }
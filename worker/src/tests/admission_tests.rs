@@ -0,0 +1,69 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn admit_in_nonce_order() {
+    let mut admission = SenderAdmission::new(10);
+    let sender = b"alice";
+
+    // The first nonce of a sender may be anything.
+    assert_eq!(admission.check(sender, 5, false), Ok(()));
+    admission.admit(sender, 5);
+
+    // Ensure the next nonces must follow without gaps.
+    assert_eq!(
+        admission.check(sender, 7, false),
+        Err(AdmissionError::NonceGap(7, 6))
+    );
+    assert_eq!(admission.check(sender, 6, false), Ok(()));
+    admission.admit(sender, 6);
+
+    // Ensure stale nonces are only accepted as replacements.
+    assert_eq!(
+        admission.check(sender, 5, false),
+        Err(AdmissionError::StaleNonce(5, 7))
+    );
+    assert_eq!(admission.check(sender, 5, true), Ok(()));
+
+    // Ensure the other senders are not affected.
+    assert_eq!(admission.check(b"bob", 0, false), Ok(()));
+}
+
+#[test]
+fn limit_pending_transactions() {
+    let mut admission = SenderAdmission::new(2);
+    let sender = b"alice";
+    admission.admit(sender, 0);
+    admission.admit(sender, 1);
+
+    // Ensure the sender cannot hold more pending transactions than its limit.
+    assert_eq!(
+        admission.check(sender, 2, false),
+        Err(AdmissionError::TooManyPending(2))
+    );
+
+    // Ensure the sender may submit again once its transactions are batched.
+    admission.release(sender, 0, /* batched */ true);
+    assert_eq!(admission.check(sender, 2, false), Ok(()));
+}
+
+#[test]
+fn resubmit_dropped_nonce() {
+    let mut admission = SenderAdmission::new(10);
+    let sender = b"alice";
+    for nonce in 0..3 {
+        admission.admit(sender, nonce);
+    }
+
+    // Ensure we expect the nonce of a dropped transaction next.
+    admission.release(sender, 1, /* batched */ false);
+    assert_eq!(
+        admission.check(sender, 3, false),
+        Err(AdmissionError::NonceGap(3, 1))
+    );
+    assert_eq!(admission.check(sender, 1, false), Ok(()));
+
+    // Ensure the resubmission fills the gap before the nonces still pending.
+    admission.admit(sender, 1);
+    assert_eq!(admission.check(sender, 3, false), Ok(()));
+}
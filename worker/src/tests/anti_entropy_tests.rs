@@ -0,0 +1,97 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch_digest, committee_with_base_port, keys, listener, test_store};
+use network::MAX_FRAME_SIZE;
+use tokio::sync::mpsc::channel;
+
+fn parameters() -> AntiEntropyParameters {
+    AntiEntropyParameters {
+        interval: 100,
+        window: 60_000,
+    }
+}
+
+#[tokio::test]
+async fn advertise_recent_batches() {
+    let (tx_sealed, rx_sealed) = channel(1);
+    let (_tx_summary, rx_summary) = channel(1);
+
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(23_000);
+
+    // Spawn an `AntiEntropy` instance.
+    let (store, _directory) = test_store();
+    AntiEntropy::spawn(
+        name,
+        id,
+        committee.clone(),
+        store,
+        parameters(),
+        rx_sealed,
+        rx_summary,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn a listener to receive our summary.
+    let (target, _) = keys.pop().unwrap();
+    let address = committee.worker(&target, &id).unwrap().worker_to_worker;
+    let message = WorkerMessage::BatchSummary(vec![batch_digest()], name);
+    let serialized = bincode::serialize(&message).unwrap();
+    let handle = listener(address, Some(Bytes::from(serialized)));
+
+    // Seal a batch.
+    tx_sealed.send(batch_digest()).await.unwrap();
+
+    // Ensure the other workers learn of the batch.
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn pull_missing_batches() {
+    let (_tx_sealed, rx_sealed) = channel(1);
+    let (tx_summary, rx_summary) = channel(1);
+
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(23_100);
+
+    // Spawn an `AntiEntropy` instance holding one of the advertised batches.
+    let (mut store, _directory) = test_store();
+    let held = Digest([1; 32]);
+    store.write(held.to_vec(), vec![0; 10]).await;
+    AntiEntropy::spawn(
+        name,
+        id,
+        committee.clone(),
+        store,
+        parameters(),
+        rx_sealed,
+        rx_summary,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn a listener to receive our batch request.
+    let (origin, _) = keys.pop().unwrap();
+    let address = committee.worker(&origin, &id).unwrap().worker_to_worker;
+    let message = WorkerMessage::BatchRequest(vec![batch_digest()], name);
+    let serialized = bincode::serialize(&message).unwrap();
+    let handle = listener(address, Some(Bytes::from(serialized)));
+
+    // Receive a summary of another worker.
+    tx_summary
+        .send((vec![held, batch_digest()], origin))
+        .await
+        .unwrap();
+
+    // Ensure we only request the batch we miss.
+    assert!(handle.await.is_ok());
+}
@@ -1,8 +1,26 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::transaction;
+use crate::batch_sizer::BatchSizer;
+use crate::common::{
+    batch_digest, committee_with_base_port, keys, listener, serialized_batch, transaction,
+};
+use crate::compression::decompress_batch;
+use crate::validator::AcceptAll;
+use config::{BatchDissemination, Committee, MempoolParameters};
+use futures::future::try_join_all;
+use network::{Compression, MAX_FRAME_SIZE};
+use std::sync::Arc;
 use tokio::sync::mpsc::channel;
 
+fn mempool(lanes: usize) -> Mempool {
+    Mempool::new(
+        lanes,
+        MempoolParameters::default(),
+        Arc::new(AcceptAll),
+        WorkerMetrics::default(),
+    )
+}
+
 #[tokio::test]
 async fn make_batch() {
     let (tx_transaction, rx_transaction) = channel(1);
@@ -11,20 +29,38 @@ async fn make_batch() {
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
-        /* max_batch_size */ 200,
-        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        // Ensure the timer is not triggered.
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 1_000_000, None,
+        ),
         rx_transaction,
+        /* rx_latency */ channel(1).1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        /* mempool */ mempool(1),
+        /* dedup */
+        TransactionDedup {
+            capacity: 0, // The batches hold the same transaction several times.
+            ..TransactionDedup::default()
+        },
+        BatchCompression::None,
+        /* max_serialized_size */ MAX_FRAME_SIZE,
+        Broadcast::Batch,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
     );
 
     // Send enough transactions to seal a batch.
-    tx_transaction.send(transaction()).await.unwrap();
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send((transaction(), None)).await.unwrap();
+    tx_transaction.send((transaction(), None)).await.unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction(), transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
     match bincode::deserialize(&batch).unwrap() {
         WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
         _ => panic!("Unexpected message"),
@@ -39,21 +75,390 @@ async fn batch_timeout() {
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
-        /* max_batch_size */ 200,
-        /* max_batch_delay */ 50, // Ensure the timer is triggered.
+        // Ensure the timer is triggered.
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 50, None,
+        ),
         rx_transaction,
+        /* rx_latency */ channel(1).1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        /* mempool */ mempool(1),
+        /* dedup */
+        TransactionDedup {
+            capacity: 0, // The batches hold the same transaction several times.
+            ..TransactionDedup::default()
+        },
+        BatchCompression::None,
+        /* max_serialized_size */ MAX_FRAME_SIZE,
+        Broadcast::Batch,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
     );
 
     // Do not send enough transactions to seal a batch..
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send((transaction(), None)).await.unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn drop_duplicate_transactions() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+    let metrics = WorkerMetrics::default();
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        // Ensure the timer is not triggered.
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 1_000_000, None,
+        ),
+        rx_transaction,
+        /* rx_latency */ channel(1).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* mempool */ mempool(1),
+        TransactionDedup::default(),
+        BatchCompression::None,
+        /* max_serialized_size */ MAX_FRAME_SIZE,
+        Broadcast::Batch,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        metrics.clone(),
+        NetworkContext::default(),
+    );
+
+    // Submit the same transaction twice, then another transaction.
+    let other = Bytes::from(vec![1; 100]);
+    tx_transaction.send((transaction(), None)).await.unwrap();
+    tx_transaction.send((transaction(), None)).await.unwrap();
+    tx_transaction.send((other.clone(), None)).await.unwrap();
+
+    // Ensure the batch only holds the transaction once.
+    let expected_batch = vec![transaction(), other];
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
     match bincode::deserialize(&batch).unwrap() {
         WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
         _ => panic!("Unexpected message"),
     }
+    assert_eq!(metrics.dedup_lookups.get(), 3);
+    assert_eq!(metrics.dedup_hits.get(), 1);
+}
+
+#[test]
+fn forget_oldest_transactions() {
+    let parameters = TransactionDedup {
+        capacity: 1,
+        window: 1_000_000,
+    };
+    let mut cache = DedupCache::new(parameters, WorkerMetrics::default());
+
+    // A full cache forgets its oldest transaction.
+    assert!(cache.check(&transaction()));
+    assert!(!cache.check(&transaction()));
+    assert!(cache.check(&[1; 100]));
+    assert!(cache.check(&transaction()));
+}
+
+#[test]
+fn forget_dropped_transactions() {
+    let parameters = TransactionDedup {
+        capacity: 2,
+        window: 1_000_000,
+    };
+    let mut cache = DedupCache::new(parameters, WorkerMetrics::default());
+
+    // A forgotten transaction is not a duplicate.
+    assert!(cache.check(&transaction()));
+    cache.forget(&transaction());
+    assert!(cache.check(&transaction()));
+    assert!(!cache.check(&transaction()));
+
+    // Ensure the stale entry of the forgotten transaction does not make us forget it again.
+    assert!(cache.check(&[1; 100]));
+    assert!(!cache.check(&transaction()));
+}
+
+#[tokio::test]
+async fn make_compressed_batch() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        // Ensure the timer is not triggered.
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 1_000_000, None,
+        ),
+        rx_transaction,
+        /* rx_latency */ channel(1).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* mempool */ mempool(1),
+        TransactionDedup::default(),
+        BatchCompression::Snappy,
+        /* max_serialized_size */ MAX_FRAME_SIZE,
+        Broadcast::Batch,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send enough transactions to seal a batch.
+    let other = Bytes::from(vec![1; 100]);
+    tx_transaction.send((transaction(), None)).await.unwrap();
+    tx_transaction.send((other.clone(), None)).await.unwrap();
+
+    // Ensure the batch is compressed.
+    let expected_batch = vec![transaction(), other];
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::CompressedBatch(compression, bytes) => {
+            assert_eq!(compression, BatchCompression::Snappy);
+            assert!(bytes.len() < 200);
+            let batch = decompress_batch(compression, &bytes).unwrap();
+            assert_eq!(batch, expected_batch);
+        }
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn prioritize_transactions() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        // Ensure the timer is not triggered.
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 1_000_000, None,
+        ),
+        rx_transaction,
+        /* rx_latency */ channel(1).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* mempool */ mempool(2),
+        TransactionDedup::default(),
+        BatchCompression::None,
+        /* max_serialized_size */ MAX_FRAME_SIZE,
+        Broadcast::Batch,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send two batches worth of transactions at once, the high-priority ones last.
+    let low = |i| Bytes::from([vec![0, i], vec![0; 98]].concat());
+    let high = |i| Bytes::from([vec![1, i], vec![0; 98]].concat());
+    for transaction in [low(0), low(1), high(0), high(1)] {
+        tx_transaction.send((transaction, None)).await.unwrap();
+    }
+
+    // Ensure the high-priority transactions are batched first.
+    for expected_batch in [vec![high(0), high(1)], vec![low(0), low(1)]] {
+        let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+        match bincode::deserialize(&batch).unwrap() {
+            WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+            _ => panic!("Unexpected message"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn disseminate_shards() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = Committee {
+        batch_dissemination: BatchDissemination::ErasureCoded,
+        ..committee_with_base_port(21_000)
+    };
+    let layout = ShardLayout::new(&committee).unwrap();
+
+    // Spawn a listener for each other worker, expecting its own shard of the batch.
+    let (digest, shards) = layout.shards(&serialized_batch());
+    let mut addresses = Vec::new();
+    let mut listener_handles = Vec::new();
+    for (name, address) in committee.others_workers(&myself, /* id */ &0) {
+        let index = layout.index(&name).unwrap();
+        let message = WorkerMessage::BatchShard(digest.clone(), shards[index].clone());
+        let expected = Bytes::from(bincode::serialize(&message).unwrap());
+        listener_handles.push(listener(address.worker_to_worker, Some(expected)));
+        addresses.push((name, address.worker_to_worker));
+    }
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        // Ensure the timer is not triggered.
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 1_000_000, None,
+        ),
+        rx_transaction,
+        /* rx_latency */ channel(1).1,
+        tx_message,
+        /* workers_addresses */ addresses,
+        /* mempool */ mempool(1),
+        /* dedup */
+        TransactionDedup {
+            capacity: 0, // The batches hold the same transaction several times.
+            ..TransactionDedup::default()
+        },
+        BatchCompression::None,
+        /* max_serialized_size */ MAX_FRAME_SIZE,
+        Broadcast::Shards(layout),
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send enough transactions to seal a batch.
+    tx_transaction.send((transaction(), None)).await.unwrap();
+    tx_transaction.send((transaction(), None)).await.unwrap();
+
+    // Ensure we keep the whole batch and each worker receives its shard.
+    let QuorumWaiterMessage {
+        digest: sealed,
+        batch,
+        handlers,
+    } = rx_message.recv().await.unwrap();
+    assert_eq!(sealed, digest);
+    assert_eq!(batch, serialized_batch());
+    assert_eq!(handlers.len(), 3);
+    assert!(try_join_all(listener_handles).await.is_ok());
+}
+
+#[tokio::test]
+async fn limit_serialized_size() {
+    let (tx_transaction, rx_transaction) = channel(3);
+    let (tx_message, mut rx_message) = channel(2);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance whose batches only fit a single transaction.
+    BatchMaker::spawn(
+        // Ensure the timer is not triggered.
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 1_000_000, None,
+        ),
+        rx_transaction,
+        /* rx_latency */ channel(1).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        /* mempool */ mempool(1),
+        TransactionDedup {
+            capacity: 0,
+            ..TransactionDedup::default()
+        },
+        BatchCompression::None,
+        /* max_serialized_size */ serialized_batch_size(1, transaction().len()),
+        Broadcast::Batch,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send enough transactions to reach the preferred batch size twice.
+    for _ in 0..3 {
+        tx_transaction.send((transaction(), None)).await.unwrap();
+    }
+
+    // Ensure the transactions are sealed into separate batches of the expected size.
+    for _ in 0..2 {
+        let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+        assert_eq!(batch.len(), serialized_batch_size(1, transaction().len()));
+        match bincode::deserialize(&batch).unwrap() {
+            WorkerMessage::Batch(batch) => assert_eq!(batch, vec![transaction()]),
+            _ => panic!("Unexpected message"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn announce_batches() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = Committee {
+        batch_dissemination: BatchDissemination::Pull,
+        ..committee_with_base_port(23_200)
+    };
+
+    // Spawn a listener for each other worker, expecting the digest of the batch only.
+    let message = WorkerMessage::BatchAnnouncement(batch_digest());
+    let expected = Bytes::from(bincode::serialize(&message).unwrap());
+    let mut addresses = Vec::new();
+    let mut listener_handles = Vec::new();
+    for (name, address) in committee.others_workers(&myself, /* id */ &0) {
+        let address = address.worker_to_worker;
+        addresses.push((name, address));
+        listener_handles.push(listener(address, Some(expected.clone())));
+    }
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        BatchSizer::new(
+            /* max_batch_size */ 200, /* max_batch_delay */ 1_000_000, None,
+        ),
+        rx_transaction,
+        /* rx_latency */ channel(1).1,
+        tx_message,
+        addresses,
+        /* mempool */ mempool(1),
+        /* dedup */
+        TransactionDedup {
+            capacity: 0, // The batches hold the same transaction several times.
+            ..TransactionDedup::default()
+        },
+        BatchCompression::None,
+        /* max_serialized_size */ MAX_FRAME_SIZE,
+        Broadcast::new(&committee),
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send enough transactions to seal a batch.
+    tx_transaction.send((transaction(), None)).await.unwrap();
+    tx_transaction.send((transaction(), None)).await.unwrap();
+
+    // Ensure we keep the whole batch and the other workers only learn its digest.
+    let QuorumWaiterMessage {
+        batch, handlers, ..
+    } = rx_message.recv().await.unwrap();
+    assert_eq!(batch, Bytes::from(serialized_batch()));
+    assert!(try_join_all(handlers.into_iter().map(|(_, x)| x))
+        .await
+        .is_ok());
+    assert!(try_join_all(listener_handles).await.is_ok());
 }
@@ -0,0 +1,65 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn adaptive() -> AdaptiveBatching {
+    AdaptiveBatching {
+        min_batch_size: 100,
+        min_batch_delay: 10,
+        target_latency: 500,
+    }
+}
+
+#[test]
+fn fixed_batches() {
+    let mut sizer = BatchSizer::new(1_000, 100, None);
+    sizer.record_latency(Duration::from_secs(10));
+    assert_eq!(sizer.batch_size(), 1_000);
+    assert_eq!(sizer.max_batch_delay(), Duration::from_millis(100));
+}
+
+#[test]
+fn adapt_to_latency() {
+    let mut sizer = BatchSizer::new(2_100, 210, Some(adaptive()));
+    sizer.rate = f64::MAX; // Ensure the arrival rate does not bound the batch size.
+    assert_eq!(sizer.batch_size(), 100);
+    assert_eq!(sizer.max_batch_delay(), Duration::from_millis(10));
+
+    // Ensure the batches grow additively (up to their maximum) when the latency is high.
+    sizer.record_latency(Duration::from_millis(600));
+    assert_eq!(sizer.batch_size(), 200);
+    assert_eq!(sizer.max_batch_delay(), Duration::from_millis(20));
+    for _ in 0..100 {
+        sizer.record_latency(Duration::from_millis(600));
+    }
+    assert_eq!(sizer.batch_size(), 2_100);
+    assert_eq!(sizer.max_batch_delay(), Duration::from_millis(210));
+
+    // Ensure the batches shrink multiplicatively (down to their minimum) when the latency is low.
+    sizer.record_latency(Duration::from_millis(100));
+    assert_eq!(sizer.batch_size(), 1_050);
+    assert_eq!(sizer.max_batch_delay(), Duration::from_millis(105));
+    for _ in 0..10 {
+        sizer.record_latency(Duration::from_millis(100));
+    }
+    assert_eq!(sizer.batch_size(), 100);
+    assert_eq!(sizer.max_batch_delay(), Duration::from_millis(10));
+}
+
+#[test]
+fn adapt_to_arrival_rate() {
+    let mut sizer = BatchSizer::new(100_000, 100, Some(adaptive()));
+    for _ in 0..10 {
+        sizer.record_latency(Duration::from_secs(1));
+    }
+
+    // Ensure we do not wait for more bytes than arrive within the batch delay.
+    sizer.record_arrival(5_000);
+    std::thread::sleep(Duration::from_millis(RATE_WINDOW));
+    sizer.record_arrival(5_000);
+    let size = sizer.batch_size();
+    assert!(
+        size > 100 && size <= 1_000,
+        "Unexpected batch size {}",
+        size
+    );
+}
@@ -2,20 +2,32 @@
 use crate::batch_maker::{Batch, Transaction};
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
-use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
+use config::{
+    Authority, BatchCompression, BatchDissemination, Committee, PrimaryAddresses, WorkerAddresses,
+};
 use crypto::{generate_keypair, Digest, PublicKey, SecretKey};
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
+use network::{accept_handshake, Features};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use std::convert::TryInto as _;
 use std::net::SocketAddr;
+use store::Store;
+use tempfile::TempDir;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+// Fixture: a store in a new temporary directory (deleted along with the returned handle).
+pub fn test_store() -> (Store, TempDir) {
+    let directory = tempfile::tempdir().unwrap();
+    let store = Store::new(directory.path().to_str().unwrap()).unwrap();
+    (store, directory)
+}
+
 // Fixture
 pub fn keys() -> Vec<(PublicKey, SecretKey)> {
     let mut rng = StdRng::from_seed([0; 32]);
@@ -25,6 +37,7 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
 // Fixture
 pub fn committee() -> Committee {
     Committee {
+        epoch: 0,
         authorities: keys()
             .iter()
             .enumerate()
@@ -39,6 +52,8 @@ pub fn committee() -> Committee {
                         primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
                         transactions: format!("127.0.0.1:{}", 400 + i).parse().unwrap(),
                         worker_to_worker: format!("127.0.0.1:{}", 500 + i).parse().unwrap(),
+                        receipts: Some(format!("127.0.0.1:{}", 600 + i).parse().unwrap()),
+                        ingress: Vec::new(),
                     },
                 )]
                 .iter()
@@ -50,10 +65,16 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        bls_key: None,
+                        bls_proof: None,
                     },
                 )
             })
             .collect(),
+        batch_compression: BatchCompression::default(),
+        batch_dissemination: BatchDissemination::default(),
+        max_batch_size: None,
+        encryption_key: None,
     }
 }
 
@@ -78,6 +99,11 @@ pub fn committee_with_base_port(base_port: u16) -> Committee {
 
             let port = worker.worker_to_worker.port();
             worker.worker_to_worker.set_port(base_port + port);
+
+            if let Some(receipts) = &mut worker.receipts {
+                let port = receipts.port();
+                receipts.set_port(base_port + port);
+            }
         }
     }
     committee
@@ -85,7 +111,7 @@ pub fn committee_with_base_port(base_port: u16) -> Committee {
 
 // Fixture
 pub fn transaction() -> Transaction {
-    vec![0; 100]
+    Bytes::from(vec![0; 100])
 }
 
 // Fixture
@@ -102,7 +128,7 @@ pub fn serialized_batch() -> Vec<u8> {
 // Fixture
 pub fn batch_digest() -> Digest {
     Digest(
-        Sha512::digest(&serialized_batch()).as_slice()[..32]
+        Sha512::digest(serialized_batch()).as_slice()[..32]
             .try_into()
             .unwrap(),
     )
@@ -112,8 +138,11 @@ pub fn batch_digest() -> Digest {
 pub fn listener(address: SocketAddr, expected: Option<Bytes>) -> JoinHandle<()> {
     tokio::spawn(async move {
         let listener = TcpListener::bind(&address).await.unwrap();
-        let (socket, _) = listener.accept().await.unwrap();
-        let transport = Framed::new(socket, LengthDelimitedCodec::new());
+        let (socket, peer) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        accept_handshake(&mut transport, peer, Features::NONE)
+            .await
+            .unwrap();
         let (mut writer, mut reader) = transport.split();
         match reader.next().await {
             Some(Ok(received)) => {
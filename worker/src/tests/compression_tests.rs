@@ -0,0 +1,26 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::batch;
+
+#[test]
+fn compress_and_decompress() {
+    let bytes = bincode::serialize(&batch()).unwrap();
+    for compression in [
+        BatchCompression::None,
+        BatchCompression::Snappy,
+        BatchCompression::Zstd,
+    ] {
+        let compressed = compress(compression, &bytes);
+        assert_eq!(decompress(compression, &compressed).unwrap(), bytes);
+        assert_eq!(decompress_batch(compression, &compressed).unwrap(), batch());
+    }
+}
+
+#[test]
+fn reject_oversized_batch() {
+    let bytes = vec![0; MAX_DECOMPRESSED_SIZE + 1];
+    for compression in [BatchCompression::Snappy, BatchCompression::Zstd] {
+        let compressed = compress(compression, &bytes);
+        assert!(decompress(compression, &compressed).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, test_store, transaction};
+use crate::encryption::{encrypted_transaction, EncryptionValidator};
+use crate::validator::AcceptAll;
+use crypto::deal;
+use network::MAX_FRAME_SIZE;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::sync::Arc;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn decrypt_after_commit() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (key, key_shares) = deal(3, 4, &mut rng);
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let mut committee = committee_with_base_port(24_500);
+    committee.encryption_key = Some(key.clone());
+
+    // Store a batch holding an encrypted transaction.
+    let ciphertext = Ciphertext::new(&key, b"Hello, world!", &mut rng).unwrap();
+    let encrypted = encrypted_transaction(&ciphertext);
+    let serialized = bincode::serialize(&WorkerMessage::Batch(vec![
+        encrypted.clone(),
+        transaction(),
+    ]))
+    .unwrap();
+    let digest = Digest(Sha512::digest(&serialized)[..32].try_into().unwrap());
+    let (mut store, _directory) = test_store();
+    store.write(digest.to_vec(), serialized).await;
+
+    let fetcher = BatchFetcher::new(
+        name,
+        id,
+        committee.clone(),
+        store.clone(),
+        100,
+        3,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    )
+    .with_validator(Arc::new(EncryptionValidator::new(Arc::new(AcceptAll))));
+
+    // Ensure the batch is not revealed before it is committed.
+    assert!(matches!(
+        fetcher.fetch(&digest, None).await,
+        Err(FetchError::Encrypted(_))
+    ));
+
+    let (tx_committed, rx_committed) = channel(1);
+    let (tx_shares, rx_shares) = channel(1);
+    Decryptor::spawn(
+        name,
+        id,
+        committee,
+        store.clone(),
+        Some(key_shares[3].clone()),
+        fetcher.clone(),
+        rx_committed,
+        rx_shares,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Send a share before the commit (it is buffered), and another one after it: with ours, they
+    // reach the threshold.
+    let transaction_id = Digest(Sha512::digest(&encrypted)[..32].try_into().unwrap());
+    let share = key_shares[0].decrypt(&ciphertext, &mut rng).unwrap();
+    tx_shares
+        .send(vec![(transaction_id.clone(), share)])
+        .await
+        .unwrap();
+    tx_committed.send(vec![digest.clone()]).await.unwrap();
+    let share = key_shares[1].decrypt(&ciphertext, &mut rng).unwrap();
+    tx_shares.send(vec![(transaction_id, share)]).await.unwrap();
+
+    // Ensure the batch is decrypted once committed.
+    let bytes = store.notify_read(decrypted_key(&digest)).await.unwrap();
+    let expected = vec![Bytes::from_static(b"Hello, world!"), transaction()];
+    assert_eq!(bincode::deserialize::<Batch>(&bytes).unwrap(), expected);
+    assert_eq!(fetcher.fetch(&digest, None).await.unwrap(), expected);
+}
@@ -0,0 +1,40 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crypto::deal;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+
+/// Rejects every plaintext transaction, and keys them by their first byte.
+struct RejectPlaintext;
+
+impl TransactionValidator for RejectPlaintext {
+    fn validate(&self, _transaction: &[u8]) -> Result<(), ValidationError> {
+        Err("Plaintext".into())
+    }
+
+    fn routing_key(&self, transaction: &[u8]) -> Option<Vec<u8>> {
+        transaction.first().map(|x| vec![*x])
+    }
+}
+
+#[test]
+fn accept_encrypted_transactions() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (key, _) = deal(3, 4, &mut rng);
+    let ciphertext = Ciphertext::new(&key, b"Hello, world!", &mut rng).unwrap();
+    let transaction = encrypted_transaction(&ciphertext);
+    assert_eq!(decode_encrypted(&transaction).unwrap(), Some(ciphertext));
+
+    // Encrypted transactions bypass the inner validator, plaintext ones do not.
+    let validator = EncryptionValidator::new(Arc::new(RejectPlaintext));
+    assert!(validator.validate(&transaction).is_ok());
+    assert!(validator.routing_key(&transaction).is_none());
+    assert!(validator.validate(b"Hello, world!").is_err());
+    assert_eq!(validator.routing_key(b"Hello, world!"), Some(vec![b'H']));
+
+    // Ensure we reject mauled ciphertexts and malformed envelopes.
+    let mut mauled = transaction.to_vec();
+    *mauled.last_mut().unwrap() ^= 1;
+    assert!(validator.validate(&mauled).is_err());
+    assert!(validator.validate(ENCRYPTED_MAGIC).is_err());
+}
@@ -0,0 +1,93 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch_digest, committee, serialized_batch};
+
+fn layout() -> ShardLayout {
+    let committee = Committee {
+        batch_dissemination: BatchDissemination::ErasureCoded,
+        ..committee()
+    };
+    ShardLayout::new(&committee).unwrap()
+}
+
+#[test]
+fn replicated_committee() {
+    assert!(ShardLayout::new(&committee()).is_none());
+}
+
+#[test]
+fn reconstruct_from_any_shards() {
+    let layout = layout();
+    let serialized = serialized_batch();
+    let (digest, shards) = layout.shards(&serialized);
+    assert_eq!(shards.len(), 4);
+    assert_eq!(digest, layout.digest(&serialized));
+
+    // Ensure any f+1 shards reconstruct the batch.
+    for (i, j) in [(0, 1), (0, 3), (2, 3)] {
+        let mut collector = ShardCollector::new(layout.clone());
+        assert!(collector.add(digest.clone(), shards[i].clone()).is_none());
+        let reconstructed = collector.add(digest.clone(), shards[j].clone());
+        assert_eq!(reconstructed, Some(serialized.clone()));
+    }
+}
+
+#[test]
+fn reject_forged_shards() {
+    let layout = layout();
+    let (digest, shards) = layout.shards(&serialized_batch());
+
+    // A shard with altered bytes does not match its proof.
+    let mut altered = shards[1].clone();
+    altered.data[0] ^= 1;
+    assert!(!altered.verify(&digest));
+
+    // Nor does a shard claiming the index of another.
+    let mut moved = shards[1].clone();
+    moved.index = 2;
+    assert!(!moved.verify(&digest));
+
+    // Nor a shard of another batch.
+    assert!(!shards[1].verify(&batch_digest()));
+
+    // Ensure the collector drops them before buffering them.
+    let mut collector = ShardCollector::new(layout);
+    for shard in [altered, moved] {
+        assert!(collector.add(digest.clone(), shard).is_none());
+    }
+    assert!(collector.pending.is_empty());
+    assert_eq!(collector.size, 0);
+}
+
+#[test]
+fn reject_inconsistent_shards() {
+    let layout = layout();
+    let serialized = serialized_batch();
+
+    // Commit to shards that do not decode consistently (as a bad author would).
+    let mut data = layout.encode(&serialized);
+    data[1][0] ^= 1;
+    let leaves: Vec<_> = data
+        .iter()
+        .enumerate()
+        .map(|(i, x)| leaf(i as u32, x))
+        .collect();
+    let root = merkle_root(&leaves);
+    let length = serialized.len() as u64;
+    let digest = commitment(&root, length);
+    let shard = |i: usize| Shard {
+        index: i as u32,
+        length,
+        data: data[i].clone(),
+        root: root.clone(),
+        proof: MerkleProof::new(&leaves, i).unwrap(),
+    };
+
+    // Each shard is proven, but the reconstructed batch does not encode back into its digest.
+    let mut collector = ShardCollector::new(layout);
+    assert!(shard(1).verify(&digest));
+    assert!(collector.add(digest.clone(), shard(0)).is_none());
+    assert!(collector.add(digest, shard(1)).is_none());
+    assert!(collector.pending.is_empty());
+    assert_eq!(collector.size, 0);
+}
@@ -0,0 +1,99 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{
+    batch_digest, committee_with_base_port, keys, listener, serialized_batch, test_store,
+    transaction,
+};
+use network::MAX_FRAME_SIZE;
+
+#[tokio::test]
+async fn fetch_local_batch() {
+    let (name, _) = keys().pop().unwrap();
+    let (mut store, _directory) = test_store();
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+    let fetcher = BatchFetcher::new(
+        name,
+        0,
+        committee_with_base_port(23_300),
+        store,
+        100,
+        3,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Ensure we read the batch from the store.
+    let batch = fetcher.fetch(&batch_digest(), None).await.unwrap();
+    assert_eq!(batch, vec![transaction(), transaction()]);
+}
+
+#[tokio::test]
+async fn fetch_missing_batch() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (origin, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(23_400);
+    let (mut store, _directory) = test_store();
+    let fetcher = BatchFetcher::new(
+        name,
+        id,
+        committee.clone(),
+        store.clone(),
+        1_000,
+        3,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Our primary reports the batch as committed.
+    fetcher.record_committed(&[batch_digest()]);
+
+    // Spawn a listener at the worker of the origin, expecting our request.
+    let address = committee.worker(&origin, &id).unwrap().worker_to_worker;
+    let message = WorkerMessage::BatchRequest(vec![batch_digest()], name);
+    let expected = Bytes::from(bincode::serialize(&message).unwrap());
+    let handle = listener(address, Some(expected));
+
+    // Store the batch once the origin receives our request (as if it replied).
+    tokio::spawn(async move {
+        assert!(handle.await.is_ok());
+        store
+            .write(batch_digest().to_vec(), serialized_batch())
+            .await;
+    });
+
+    // Ensure we fetch the batch from its origin.
+    let batch = fetcher.fetch(&batch_digest(), Some(origin)).await.unwrap();
+    assert_eq!(batch, vec![transaction(), transaction()]);
+}
+
+#[tokio::test]
+async fn refuse_uncommitted_batch() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (origin, _) = keys.pop().unwrap();
+    let (store, _directory) = test_store();
+    let fetcher = BatchFetcher::new(
+        name,
+        0,
+        committee_with_base_port(24_200),
+        store,
+        100,
+        3,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Ensure we do not request a batch that is not committed.
+    let result = fetcher.fetch(&batch_digest(), Some(origin)).await;
+    assert!(matches!(result, Err(FetchError::Uncommitted(_))));
+}
@@ -1,8 +1,13 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener, serialized_batch};
+use crate::reassembler::BatchReassembler;
+use futures::stream::StreamExt as _;
+use network::{accept_handshake, Compression, Features, MAX_FRAME_SIZE};
 use std::fs;
+use tokio::net::TcpListener;
 use tokio::sync::mpsc::channel;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn batch_reply() {
@@ -22,7 +27,18 @@ async fn batch_reply() {
         .await;
 
     // Spawn an `Helper` instance.
-    Helper::spawn(id, committee.clone(), store, rx_request);
+    Helper::spawn(
+        id,
+        committee.clone(),
+        store,
+        BatchHelperLimits::default(),
+        rx_request,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
 
     // Spawn a listener to receive the batch reply.
     let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
@@ -36,3 +52,88 @@ async fn batch_reply() {
     // Ensure the requestor received the batch (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn chunked_batch_reply() {
+    let (tx_request, rx_request) = channel(1);
+    let (requestor, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(20_000);
+
+    // Create a new test store.
+    let path = ".db_test_chunked_batch_reply";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Add a batch to the store.
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+
+    // Spawn an `Helper` instance streaming batches in small chunks.
+    let limits = BatchHelperLimits {
+        chunk_size: 100,
+        ..BatchHelperLimits::default()
+    };
+    Helper::spawn(
+        id,
+        committee.clone(),
+        store,
+        limits,
+        rx_request,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        NetworkMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Listen to the batch reply.
+    let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
+    let listener = TcpListener::bind(&address).await.unwrap();
+
+    // Send a batch request.
+    let digests = vec![batch_digest()];
+    tx_request.send((digests, requestor)).await.unwrap();
+
+    // Ensure the requestor receives the batch in several chunks.
+    let (socket, peer) = listener.accept().await.unwrap();
+    let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+    accept_handshake(&mut transport, peer, Features::NONE)
+        .await
+        .unwrap();
+    let mut reassembler = BatchReassembler::default();
+    let mut chunks = 0;
+    let reassembled = loop {
+        let frame = transport.next().await.unwrap().unwrap();
+        match bincode::deserialize(&frame).unwrap() {
+            WorkerMessage::BatchChunk(digest, index, total, chunk) => {
+                chunks += 1;
+                if let Some(bytes) = reassembler.add("helper", digest, index, total, chunk) {
+                    break bytes;
+                }
+            }
+            _ => panic!("Unexpected message"),
+        }
+    };
+    assert!(chunks > 1);
+    assert_eq!(reassembled, serialized_batch());
+}
+
+#[test]
+fn cache_recent_batches() {
+    let mut cache = BatchCache::new(10);
+    let digest = |i| Digest([i; 32]);
+
+    // Ensure we evict the oldest batches to make room, and never cache oversized batches.
+    cache.insert(digest(0), Bytes::from(vec![0; 4]));
+    cache.insert(digest(1), Bytes::from(vec![1; 4]));
+    cache.insert(digest(2), Bytes::from(vec![2; 11]));
+    assert!(cache.get(&digest(0)).is_some());
+    assert!(cache.get(&digest(2)).is_none());
+    cache.insert(digest(3), Bytes::from(vec![3; 4]));
+    assert!(cache.get(&digest(0)).is_none());
+    assert_eq!(cache.get(&digest(1)), Some(Bytes::from(vec![1; 4])));
+    assert_eq!(cache.get(&digest(3)), Some(Bytes::from(vec![3; 4])));
+    assert_eq!(cache.size, 8);
+}
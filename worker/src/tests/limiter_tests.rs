@@ -0,0 +1,39 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[test]
+fn limit_each_client() {
+    let limiter = ClientLimiter::new(2);
+    let client = Some("127.0.0.1".parse().unwrap());
+
+    // Exhaust the quota of the client.
+    assert!(limiter.try_acquire(client));
+    assert!(limiter.try_acquire(client));
+    assert!(!limiter.try_acquire(client));
+
+    // Ensure the other clients are not affected.
+    assert!(limiter.try_acquire(Some("127.0.0.2".parse().unwrap())));
+    assert!(limiter.try_acquire(None));
+}
+
+#[test]
+fn forget_idle_clients() {
+    let limiter = ClientLimiter::new(1);
+    let limited = Some("127.0.0.1".parse().unwrap());
+    assert!(limiter.try_acquire(limited));
+    for i in 1..MAX_TRACKED_CLIENTS as u32 {
+        assert!(limiter.try_acquire(Some(IpAddr::from((i + 1).to_be_bytes()))));
+    }
+    assert!(!limiter.try_acquire(limited));
+
+    // Ensure we forget the oldest idle client but keep limiting the client we heard from.
+    assert!(limiter.try_acquire(None));
+    let clients = limiter.clients.lock().unwrap();
+    assert_eq!(clients.buckets.len(), MAX_TRACKED_CLIENTS);
+    assert!(clients.buckets.contains_key(&limited));
+    assert!(!clients
+        .buckets
+        .contains_key(&Some(IpAddr::from(2u32.to_be_bytes()))));
+    drop(clients);
+    assert!(!limiter.try_acquire(limited));
+}
@@ -0,0 +1,125 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::validator::{AcceptAll, ValidationError};
+use tokio::sync::oneshot;
+
+/// Transactions replace each other when their second byte is the same.
+struct KeyedBySecondByte;
+
+impl TransactionValidator for KeyedBySecondByte {
+    fn validate(&self, _transaction: &[u8]) -> Result<(), ValidationError> {
+        Ok(())
+    }
+
+    fn replacement_key(&self, transaction: &[u8]) -> Option<Vec<u8>> {
+        transaction.get(1).map(|x| vec![*x])
+    }
+}
+
+fn mempool(
+    lanes: usize,
+    capacity: usize,
+    eviction: EvictionPolicy,
+    validator: Arc<dyn TransactionValidator>,
+) -> Mempool {
+    let parameters = MempoolParameters {
+        capacity,
+        eviction,
+        ..MempoolParameters::default()
+    };
+    Mempool::new(lanes, parameters, validator, WorkerMetrics::default())
+}
+
+#[test]
+fn evict_oldest() {
+    let mut mempool = mempool(2, 6, EvictionPolicy::Oldest, Arc::new(AcceptAll));
+    let (sender, mut receiver) = oneshot::channel();
+    mempool.insert(vec![1, 1].into(), Some(sender));
+    mempool.insert(vec![0, 2].into(), None);
+    mempool.insert(vec![0, 3].into(), None);
+
+    // Ensure the oldest transaction makes room for the new one (regardless of its priority).
+    mempool.insert(vec![0, 4].into(), None);
+    assert_eq!(receiver.try_recv(), Ok(ReceiptStatus::Overloaded));
+    assert_eq!(mempool.size(), 6);
+    let dropped: Vec<_> = mempool.drain_dropped().collect();
+    assert_eq!(dropped, vec![Transaction::from(vec![1, 1])]);
+    assert_eq!(mempool.pop().map(|x| x.0.to_vec()), Some(vec![0, 2]));
+}
+
+#[test]
+fn evict_lowest_priority() {
+    let mut mempool = mempool(2, 4, EvictionPolicy::LowestPriority, Arc::new(AcceptAll));
+    mempool.insert(vec![1, 1].into(), None);
+    mempool.insert(vec![0, 2].into(), None);
+
+    // Ensure the transactions of lower priority make room for the new one.
+    mempool.insert(vec![1, 3].into(), None);
+    assert_eq!(mempool.pop().map(|x| x.0.to_vec()), Some(vec![1, 1]));
+    mempool.insert(vec![1, 4].into(), None);
+
+    // Ensure new transactions of lower priority are dropped.
+    let (sender, mut receiver) = oneshot::channel();
+    mempool.insert(vec![0, 5].into(), Some(sender));
+    assert_eq!(receiver.try_recv(), Ok(ReceiptStatus::Overloaded));
+    assert_eq!(mempool.pop().map(|x| x.0.to_vec()), Some(vec![1, 3]));
+    assert_eq!(mempool.pop().map(|x| x.0.to_vec()), Some(vec![1, 4]));
+    assert_eq!(mempool.size(), 0);
+}
+
+#[test]
+fn replace_by_key() {
+    let mut mempool = mempool(1, 100, EvictionPolicy::Oldest, Arc::new(KeyedBySecondByte));
+    let (sender, mut receiver) = oneshot::channel();
+    mempool.insert(vec![0, 1, 0].into(), Some(sender));
+    mempool.insert(vec![0, 2].into(), None);
+
+    // Ensure the new transaction replaces the pending transaction of the same key.
+    mempool.insert(vec![0, 1, 1].into(), None);
+    assert_eq!(receiver.try_recv(), Ok(ReceiptStatus::Replaced));
+    assert_eq!(mempool.size(), 5);
+    assert_eq!(mempool.pop().map(|x| x.0.to_vec()), Some(vec![0, 2]));
+    assert_eq!(mempool.pop().map(|x| x.0.to_vec()), Some(vec![0, 1, 1]));
+}
+
+/// Transactions starting with 1 expired at the Unix epoch.
+struct ExpiredOnes;
+
+impl TransactionValidator for ExpiredOnes {
+    fn validate(&self, _transaction: &[u8]) -> Result<(), ValidationError> {
+        Ok(())
+    }
+
+    fn expiry(&self, transaction: &[u8]) -> Option<u64> {
+        (transaction.first() == Some(&1)).then_some(0)
+    }
+}
+
+#[tokio::test]
+async fn drop_expired() {
+    let parameters = MempoolParameters {
+        ttl: Some(50),
+        ..MempoolParameters::default()
+    };
+    let mut mempool = Mempool::new(
+        1,
+        parameters,
+        Arc::new(ExpiredOnes),
+        WorkerMetrics::default(),
+    );
+
+    // Ensure we drop the transactions whose client-attached expiry passed.
+    let (sender, mut receiver) = oneshot::channel();
+    mempool.insert(vec![1].into(), Some(sender));
+    mempool.insert(vec![0].into(), None);
+    assert_eq!(mempool.pop().map(|x| x.0.to_vec()), Some(vec![0]));
+    assert_eq!(receiver.try_recv(), Ok(ReceiptStatus::Expired));
+    let dropped: Vec<_> = mempool.drain_dropped().collect();
+    assert_eq!(dropped, vec![Transaction::from(vec![1])]);
+
+    // Ensure we drop the transactions that outlived their TTL.
+    mempool.insert(vec![0].into(), None);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(mempool.pop().is_none());
+    assert_eq!(mempool.size(), 0);
+}
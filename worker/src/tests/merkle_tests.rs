@@ -0,0 +1,39 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::batch_maker::Transaction;
+use bytes::Bytes;
+
+fn transactions(count: u8) -> Vec<Transaction> {
+    (0..count).map(|i| Bytes::from(vec![i; 10])).collect()
+}
+
+#[test]
+fn prove_inclusion() {
+    // Ensure every transaction of batches of any size (including odd ones) can be proven.
+    for count in 1..10 {
+        let batch = transactions(count);
+        let root = merkle_root(&batch);
+        for (i, transaction) in batch.iter().enumerate() {
+            let proof = MerkleProof::new(&batch, i).unwrap();
+            assert!(proof.verify(&root, transaction));
+        }
+        assert!(MerkleProof::new(&batch, count as usize).is_none());
+    }
+}
+
+#[test]
+fn reject_wrong_proof() {
+    let batch = transactions(5);
+    let root = merkle_root(&batch);
+
+    // Ensure a proof does not hold for another transaction or another batch.
+    let proof = MerkleProof::new(&batch, 2).unwrap();
+    assert!(!proof.verify(&root, &batch[3]));
+    assert!(!proof.verify(&merkle_root(&transactions(6)), &batch[2]));
+
+    // Ensure an inner node of the tree does not pass for a transaction.
+    let mut proof = MerkleProof::new(&batch, 0).unwrap();
+    let (sibling, _) = proof.siblings.remove(0);
+    let inner = [leaf(&batch[0]).0, sibling.0].concat();
+    assert!(!proof.verify(&root, &inner));
+}
@@ -1,6 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::batch;
+use crate::merkle::MerkleProof;
 use crate::worker::WorkerMessage;
 use std::fs;
 use tokio::sync::mpsc::channel;
@@ -23,12 +24,18 @@ async fn hash_and_store() {
         rx_batch,
         tx_digest,
         /* own_batch */ true,
+        /* max_batch_size */ 1_000_000,
+        /* tx_stored */ None,
+        /* layout */ None,
     );
 
     // Send a batch to the `Processor`.
     let message = WorkerMessage::Batch(batch());
     let serialized = bincode::serialize(&message).unwrap();
-    tx_batch.send(serialized.clone()).await.unwrap();
+    tx_batch
+        .send(Bytes::from(serialized.clone()))
+        .await
+        .unwrap();
 
     // Ensure the `Processor` outputs the batch's digest.
     let output = rx_digest.recv().await.unwrap();
@@ -44,4 +51,10 @@ async fn hash_and_store() {
     let stored_batch = store.read(digest.to_vec()).await.unwrap();
     assert!(stored_batch.is_some(), "The batch is not in the store");
     assert_eq!(stored_batch.unwrap(), serialized);
+
+    // Ensure the `Processor` stored the Merkle root over the batch's transactions.
+    let root = store.read(merkle_key(&digest)).await.unwrap().unwrap();
+    let root = Digest(root.try_into().unwrap());
+    let proof = MerkleProof::new(&batch(), 0).unwrap();
+    assert!(proof.verify(&root, &batch()[0]));
 }
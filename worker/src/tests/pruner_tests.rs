@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::serialized_batch;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn delete_committed_batches() {
+    let (tx_committed, rx_committed) = channel(1);
+
+    // Create a new test store holding two batches.
+    let path = ".db_test_delete_committed_batches";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let old = Digest([0; 32]);
+    let recent = Digest([1; 32]);
+    store.write(old.to_vec(), serialized_batch()).await;
+    store.write(recent.to_vec(), serialized_batch()).await;
+
+    // Spawn a `Pruner` keeping the batches committed during the last 2 rounds.
+    Pruner::spawn(store.clone(), /* retention */ 2, rx_committed);
+
+    // Commit the batches at rounds 1 and 2.
+    tx_committed.send((vec![old.clone()], 1)).await.unwrap();
+    tx_committed.send((vec![recent.clone()], 2)).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert!(store.read(old.to_vec()).await.unwrap().is_some());
+
+    // Commit round 3: only the batch committed at round 1 falls out of the retention window.
+    tx_committed.send((Vec::new(), 3)).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert!(store.read(old.to_vec()).await.unwrap().is_none());
+    assert!(store.read(recent.to_vec()).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn delete_after_restart() {
+    // Create a new test store holding a batch.
+    let path = ".db_test_delete_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let digest = Digest([0; 32]);
+    store.write(digest.to_vec(), serialized_batch()).await;
+
+    // Commit the batch at round 1, then stop the `Pruner`.
+    let (tx_committed, rx_committed) = channel(1);
+    Pruner::spawn(store.clone(), /* retention */ 2, rx_committed);
+    tx_committed.send((vec![digest.clone()], 1)).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    drop(tx_committed);
+
+    // Ensure the restarted `Pruner` still deletes the batch once it falls out of the window.
+    let (tx_committed, rx_committed) = channel(1);
+    Pruner::spawn(store.clone(), /* retention */ 2, rx_committed);
+    tx_committed.send((Vec::new(), 3)).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert!(store.read(digest.to_vec()).await.unwrap().is_none());
+}
@@ -1,11 +1,15 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch, committee_with_base_port, keys, listener};
+use crate::common::{batch, batch_digest, committee_with_base_port, keys, listener, test_store};
+use crate::erasure::ShardLayout;
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
+use config::{AckThreshold, BatchDissemination, QuorumWaiterParameters};
 use futures::future::try_join_all;
-use network::ReliableSender;
+use network::{Compression, ReliableSender, MAX_FRAME_SIZE};
 use tokio::sync::mpsc::channel;
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration};
 
 #[tokio::test]
 async fn wait_for_quorum() {
@@ -15,7 +19,23 @@ async fn wait_for_quorum() {
     let committee = committee_with_base_port(7_000);
 
     // Spawn a `QuorumWaiter` instance.
-    QuorumWaiter::spawn(committee.clone(), /* stake */ 1, rx_message, tx_batch);
+    let (store, _directory) = test_store();
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* id */ 0,
+        /* stake */ 1,
+        QuorumWaiterParameters::default(),
+        rx_message,
+        tx_batch,
+        /* tx_latency */ channel(1).0,
+        store,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
 
     // Make a batch.
     let message = WorkerMessage::Batch(batch());
@@ -40,7 +60,8 @@ async fn wait_for_quorum() {
 
     // Forward the batch along with the handlers to the `QuorumWaiter`.
     let message = QuorumWaiterMessage {
-        batch: serialized.clone(),
+        digest: batch_digest(),
+        batch: Bytes::from(serialized.clone()),
         handlers: names.into_iter().zip(handlers.into_iter()).collect(),
     };
     tx_message.send(message).await.unwrap();
@@ -52,3 +73,319 @@ async fn wait_for_quorum() {
     // Ensure the other listeners correctly received the batch.
     assert!(try_join_all(listener_handles).await.is_ok());
 }
+
+#[tokio::test]
+async fn rebroadcast_after_timeout() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(9_400);
+    let metrics = WorkerMetrics::default();
+
+    // Spawn a `QuorumWaiter` instance re-broadcasting quickly.
+    let parameters = QuorumWaiterParameters {
+        threshold: AckThreshold::Quorum,
+        rebroadcast_timeout: 100,
+    };
+    let (store, _directory) = test_store();
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* id */ 0,
+        /* stake */ 1,
+        parameters,
+        rx_message,
+        tx_batch,
+        /* tx_latency */ channel(1).0,
+        store,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        metrics.clone(),
+        NetworkContext::default(),
+    );
+
+    // Make a batch.
+    let message = WorkerMessage::Batch(batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let expected = Bytes::from(serialized.clone());
+
+    // Spawn listeners acknowledging the re-broadcast batch.
+    let mut names = Vec::new();
+    let mut listener_handles = Vec::new();
+    for (name, address) in committee.others_workers(&myself, /* id */ &0) {
+        let handle = listener(address.worker_to_worker, Some(expected.clone()));
+        names.push(name);
+        listener_handles.push(handle);
+    }
+
+    // Forward the batch along with handlers that never resolve, as if our first broadcast got lost.
+    let (senders, handlers): (Vec<_>, Vec<_>) = names.iter().map(|_| oneshot::channel()).unzip();
+    let message = QuorumWaiterMessage {
+        digest: batch_digest(),
+        batch: Bytes::from(serialized.clone()),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the `QuorumWaiter` re-broadcasts the batch and gathers enough acknowledgements.
+    let output = rx_batch.recv().await.unwrap();
+    assert_eq!(output, serialized);
+    assert_eq!(metrics.batch_rebroadcasts.get(), 1);
+    assert_eq!(metrics.dissemination_latency.get_sample_count(), 1);
+    drop(senders);
+}
+
+#[tokio::test]
+async fn rebroadcast_announcement() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let mut committee = committee_with_base_port(28_000);
+    committee.batch_dissemination = BatchDissemination::Pull;
+
+    // Spawn a `QuorumWaiter` instance re-broadcasting quickly.
+    let parameters = QuorumWaiterParameters {
+        threshold: AckThreshold::Quorum,
+        rebroadcast_timeout: 100,
+    };
+    let (store, _directory) = test_store();
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* id */ 0,
+        /* stake */ 1,
+        parameters,
+        rx_message,
+        tx_batch,
+        /* tx_latency */ channel(1).0,
+        store,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn listeners expecting the announcement of the batch (not the batch itself).
+    let announcement = WorkerMessage::BatchAnnouncement(batch_digest());
+    let expected = Bytes::from(bincode::serialize(&announcement).unwrap());
+    let mut names = Vec::new();
+    let mut listener_handles = Vec::new();
+    for (name, address) in committee.others_workers(&myself, /* id */ &0) {
+        let handle = listener(address.worker_to_worker, Some(expected.clone()));
+        names.push(name);
+        listener_handles.push(handle);
+    }
+
+    // Forward the batch along with handlers that never resolve, as if our first broadcast got lost.
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch())).unwrap();
+    let (senders, handlers): (Vec<_>, Vec<_>) = names.iter().map(|_| oneshot::channel()).unzip();
+    let message = QuorumWaiterMessage {
+        digest: batch_digest(),
+        batch: Bytes::from(serialized.clone()),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the `QuorumWaiter` re-broadcasts the announcement and gathers enough acknowledgements.
+    let output = rx_batch.recv().await.unwrap();
+    assert_eq!(output, serialized);
+    assert!(try_join_all(listener_handles).await.is_ok());
+    drop(senders);
+}
+
+#[tokio::test]
+async fn rebroadcast_shards() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let mut committee = committee_with_base_port(28_600);
+    committee.batch_dissemination = BatchDissemination::ErasureCoded;
+
+    // Spawn a `QuorumWaiter` instance re-broadcasting quickly.
+    let parameters = QuorumWaiterParameters {
+        threshold: AckThreshold::Quorum,
+        rebroadcast_timeout: 100,
+    };
+    let (store, _directory) = test_store();
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* id */ 0,
+        /* stake */ 1,
+        parameters,
+        rx_message,
+        tx_batch,
+        /* tx_latency */ channel(1).0,
+        store,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn listeners expecting their own shard of the batch.
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch())).unwrap();
+    let layout = ShardLayout::new(&committee).unwrap();
+    let (digest, shards) = layout.shards(&serialized);
+    let mut names = Vec::new();
+    let mut listener_handles = Vec::new();
+    for (name, address) in committee.others_workers(&myself, /* id */ &0) {
+        let shard = shards[layout.index(&name).unwrap()].clone();
+        let message = WorkerMessage::BatchShard(digest.clone(), shard);
+        let expected = Bytes::from(bincode::serialize(&message).unwrap());
+        let handle = listener(address.worker_to_worker, Some(expected));
+        names.push(name);
+        listener_handles.push(handle);
+    }
+
+    // Forward the batch along with handlers that never resolve, as if our first broadcast got lost.
+    let (senders, handlers): (Vec<_>, Vec<_>) = names.iter().map(|_| oneshot::channel()).unzip();
+    let message = QuorumWaiterMessage {
+        digest,
+        batch: Bytes::from(serialized.clone()),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the `QuorumWaiter` re-broadcasts the shards and gathers enough acknowledgements.
+    let output = rx_batch.recv().await.unwrap();
+    assert_eq!(output, serialized);
+    assert!(try_join_all(listener_handles).await.is_ok());
+    drop(senders);
+}
+
+#[tokio::test]
+async fn recover_unacked_batch() {
+    let (_tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(21_500);
+
+    // Make a batch.
+    let message = WorkerMessage::Batch(batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    let expected = Bytes::from(serialized.clone());
+
+    // Persist the batch as if we crashed after a single worker acknowledged it.
+    let (mut store, _directory) = test_store();
+    let others = committee.others_workers(&myself, /* id */ &0);
+    let recipients: Vec<_> = others.iter().map(|(name, _)| *name).collect();
+    let mut unacked =
+        UnackedBatch::new(batch_digest(), Bytes::from(serialized.clone()), recipients);
+    unacked.acknowledge(&others[0].0);
+    let sequence = 5;
+    store
+        .write(unacked_key(sequence), bincode::serialize(&unacked).unwrap())
+        .await;
+    store.write(acks_key(sequence), unacked.acks.clone()).await;
+    store
+        .write(
+            UNACKED_RANGE_KEY.to_vec(),
+            bincode::serialize(&(sequence, sequence + 1)).unwrap(),
+        )
+        .await;
+
+    // Spawn listeners for the workers that did not acknowledge the batch.
+    let listener_handles: Vec<_> = others[1..]
+        .iter()
+        .map(|(_, address)| listener(address.worker_to_worker, Some(expected.clone())))
+        .collect();
+
+    // Spawn a `QuorumWaiter` instance.
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* id */ 0,
+        /* stake */ 1,
+        QuorumWaiterParameters::default(),
+        rx_message,
+        tx_batch,
+        /* tx_latency */ channel(1).0,
+        store.clone(),
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Ensure the `QuorumWaiter` re-broadcasts the batch and gathers enough acknowledgements.
+    let output = rx_batch.recv().await.unwrap();
+    assert_eq!(output, serialized);
+    assert!(try_join_all(listener_handles).await.is_ok());
+
+    // Ensure the batch is no longer persisted.
+    sleep(Duration::from_millis(50)).await;
+    assert!(store.read(unacked_key(sequence)).await.unwrap().is_none());
+    assert!(store.read(acks_key(sequence)).await.unwrap().is_none());
+    let range = store
+        .read(UNACKED_RANGE_KEY.to_vec())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        bincode::deserialize::<(u64, u64)>(&range).unwrap(),
+        (sequence + 1, sequence + 1)
+    );
+}
+
+#[tokio::test]
+async fn abandoned_delivery() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(7_300);
+    let metrics = WorkerMetrics::default();
+
+    // Spawn a `QuorumWaiter` instance.
+    let (store, _directory) = test_store();
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* id */ 0,
+        /* stake */ 1,
+        QuorumWaiterParameters::default(),
+        rx_message,
+        tx_batch,
+        /* tx_latency */ channel(1).0,
+        store,
+        RetryPolicy::default(),
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        Compression::None,
+        metrics.clone(),
+        NetworkContext::default(),
+    );
+
+    // Forward a batch to the `QuorumWaiter`, along with the handlers of its delivery.
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch())).unwrap();
+    let (mut senders, handlers): (Vec<_>, Vec<_>) = committee
+        .others_workers(&myself, /* id */ &0)
+        .into_iter()
+        .map(|(name, _)| {
+            let (sender, receiver) = oneshot::channel();
+            (sender, (name, receiver))
+        })
+        .unzip();
+    let message = QuorumWaiterMessage {
+        digest: batch_digest(),
+        batch: Bytes::from(serialized.clone()),
+        handlers,
+    };
+    tx_message.send(message).await.unwrap();
+
+    // The network gives up on one worker (which does not count as an acknowledgement).
+    drop(senders.remove(0));
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(metrics.abandoned_batch_deliveries.get(), 1);
+    assert!(rx_batch.try_recv().is_err());
+
+    // The other workers acknowledge the batch.
+    for sender in senders {
+        let _ = sender.send(Bytes::from("Ack"));
+    }
+    let output = rx_batch.recv().await.unwrap();
+    assert_eq!(output, serialized);
+}
@@ -0,0 +1,87 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch_digest, serialized_batch};
+
+#[test]
+fn reassemble_chunks() {
+    let mut reassembler = BatchReassembler::default();
+    let serialized = serialized_batch();
+    let chunks: Vec<_> = serialized.chunks(100).map(|x| x.to_vec()).collect();
+    let total = chunks.len() as u32;
+
+    // The batch is only output once we received all its chunks.
+    let mut output = None;
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        assert!(output.is_none());
+        output = reassembler.add("peer", batch_digest(), i as u32, total, chunk);
+    }
+    assert_eq!(output, Some(serialized));
+    assert_eq!(reassembler.size, 0);
+}
+
+#[test]
+fn drop_chunks_out_of_order() {
+    let mut reassembler = BatchReassembler::default();
+    let serialized = serialized_batch();
+    let chunks: Vec<_> = serialized.chunks(100).map(|x| x.to_vec()).collect();
+    let total = chunks.len() as u32;
+    assert!(total > 2);
+
+    // Skip the second chunk: the batch is dropped.
+    assert!(reassembler
+        .add("peer", batch_digest(), 0, total, chunks[0].clone())
+        .is_none());
+    assert!(reassembler
+        .add("peer", batch_digest(), 2, total, chunks[2].clone())
+        .is_none());
+    assert!(reassembler.pending.is_empty());
+    assert_eq!(reassembler.size, 0);
+}
+
+#[test]
+fn evict_oldest_batches() {
+    let mut reassembler = BatchReassembler::default();
+    let chunk = vec![0u8; MAX_PENDING_SIZE / 2];
+
+    // Start reassembling two batches filling our memory.
+    assert!(reassembler
+        .add("peer", Digest([0; 32]), 0, 2, chunk.clone())
+        .is_none());
+    assert!(reassembler
+        .add("peer", Digest([1; 32]), 0, 2, chunk.clone())
+        .is_none());
+
+    // Ensure a third batch evicts the oldest one.
+    assert!(reassembler
+        .add("peer", Digest([2; 32]), 0, 2, chunk.clone())
+        .is_none());
+    assert!(!reassembler
+        .pending
+        .contains_key(&("peer".to_string(), Digest([0; 32]))));
+    assert!(reassembler
+        .pending
+        .contains_key(&("peer".to_string(), Digest([1; 32]))));
+    assert!(reassembler
+        .pending
+        .contains_key(&("peer".to_string(), Digest([2; 32]))));
+    assert_eq!(reassembler.size, MAX_PENDING_SIZE);
+}
+
+#[test]
+fn reassemble_per_connection() {
+    let mut reassembler = BatchReassembler::default();
+    let serialized = serialized_batch();
+    let chunks: Vec<_> = serialized.chunks(100).map(|x| x.to_vec()).collect();
+    let total = chunks.len() as u32;
+
+    // Two workers stream us the same batch at the same time: their chunks do not interleave.
+    let mut outputs = Vec::new();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        for peer in ["first", "second"] {
+            let output = reassembler.add(peer, batch_digest(), i as u32, total, chunk.clone());
+            outputs.extend(output);
+        }
+    }
+    assert_eq!(outputs, vec![serialized.clone(), serialized]);
+    assert_eq!(reassembler.size, 0);
+}
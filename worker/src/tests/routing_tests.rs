@@ -0,0 +1,38 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::validator::ValidationError;
+
+/// The routing key of a transaction is its first byte.
+struct KeyedByFirstByte;
+
+impl TransactionValidator for KeyedByFirstByte {
+    fn validate(&self, _transaction: &[u8]) -> Result<(), ValidationError> {
+        Ok(())
+    }
+
+    fn routing_key(&self, transaction: &[u8]) -> Option<Vec<u8>> {
+        transaction.first().map(|x| vec![*x])
+    }
+}
+
+#[test]
+fn route_deterministically() {
+    // Ensure every worker is in use and the same key always lands on the same worker.
+    let routes: Vec<_> = (0..100u8).map(|i| route(&[i], 4)).collect();
+    assert!((0..4).all(|id| routes.contains(&id)));
+    assert_eq!(
+        routes,
+        (0..100u8).map(|i| route(&[i], 4)).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn detect_misrouted_transactions() {
+    let key = (0..100u8).find(|i| route(&[*i], 4) == 2).unwrap();
+    let router = Router::new(2, 4, Arc::new(KeyedByFirstByte));
+    assert_eq!(router.misrouted(&[key, 1, 2]), None);
+    assert_eq!(router.misrouted(&[]), None);
+
+    let other = Router::new(1, 4, Arc::new(KeyedByFirstByte));
+    assert_eq!(other.misrouted(&[key, 1, 2]), Some(2));
+}
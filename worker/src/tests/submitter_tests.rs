@@ -0,0 +1,109 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+use crate::validator::AcceptAll;
+use crate::worker::ReceiptStatus;
+use bytes::Bytes;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn submit_transactions() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(1);
+    let submitter = TransactionSubmitter::new(
+        tx_batch_maker,
+        Some(Arc::new(AcceptAll)),
+        /* max_transaction_size */ Some(100),
+        /* client_rate_limit */ None,
+        WorkerMetrics::default(),
+    );
+
+    // Ensure oversized transactions are refused.
+    let result = submitter.submit(Bytes::from(vec![0; 101]));
+    assert!(matches!(result, Err(SubmitError::Oversized(101, 100))));
+
+    // Ensure submissions fail when the batch maker does not keep up.
+    let receipt = submitter.submit(transaction()).unwrap();
+    let result = submitter.submit(transaction());
+    assert!(matches!(result, Err(SubmitError::Overloaded)));
+
+    // Ensure the client receives the receipt of its transaction.
+    let (received, waiter) = rx_batch_maker.recv().await.unwrap();
+    assert_eq!(received, transaction());
+    waiter.unwrap().send(ReceiptStatus::Duplicate).unwrap();
+    assert_eq!(receipt.await.status, ReceiptStatus::Duplicate);
+
+    // Ensure the client is asked to retry if the worker drops its transaction without a receipt.
+    let receipt = submitter.submit(transaction()).unwrap();
+    drop(rx_batch_maker.recv().await.unwrap());
+    assert_eq!(receipt.await.status, ReceiptStatus::RetryLater);
+}
+
+#[tokio::test]
+async fn rate_limit_clients() {
+    let (tx_batch_maker, _rx_batch_maker) = channel(10);
+    let submitter = TransactionSubmitter::new(
+        tx_batch_maker,
+        Some(Arc::new(AcceptAll)),
+        /* max_transaction_size */ None,
+        /* client_rate_limit */ Some(1),
+        WorkerMetrics::default(),
+    );
+    let client = "127.0.0.1".parse().unwrap();
+
+    // Ensure the client cannot exceed its rate limit.
+    assert!(submitter.submit_from(client, transaction()).is_ok());
+    let result = submitter.submit_from(client, transaction());
+    assert!(matches!(result, Err(SubmitError::RateLimited(_))));
+
+    // Ensure the other clients are not affected.
+    let other = "127.0.0.2".parse().unwrap();
+    assert!(submitter.submit_from(other, transaction()).is_ok());
+}
+
+#[tokio::test]
+async fn shed_load_when_backlogged() {
+    let (tx_batch_maker, _rx_batch_maker) = channel(10);
+    let metrics = WorkerMetrics::default();
+    let submitter = TransactionSubmitter::new(
+        tx_batch_maker,
+        Some(Arc::new(AcceptAll)),
+        /* max_transaction_size */ None,
+        /* client_rate_limit */ None,
+        metrics.clone(),
+    )
+    .with_max_batch_backlog(2);
+    assert_eq!(metrics.batch_backlog_watermark.get(), 2);
+
+    // Ensure clients are asked to retry later once our backlog reaches its watermark.
+    metrics.batch_backlog.set(1);
+    assert!(submitter.submit(transaction()).is_ok());
+    metrics.batch_backlog.set(2);
+    let result = submitter.submit(transaction());
+    assert!(matches!(result, Err(SubmitError::Backlogged(2))));
+    assert_eq!(metrics.backlogged_transactions.get(), 1);
+
+    // Ensure we accept transactions again once the backlog drains.
+    metrics.batch_backlog.set(1);
+    assert!(submitter.submit(transaction()).is_ok());
+}
+
+#[tokio::test]
+async fn copy_transaction_slices() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(1);
+    let submitter = TransactionSubmitter::new(
+        tx_batch_maker,
+        None,
+        /* max_transaction_size */ None,
+        /* client_rate_limit */ None,
+        WorkerMetrics::default(),
+    );
+
+    // Submit a transaction sliced out of a larger request buffer.
+    let buffer = Bytes::from(vec![1; 1_000]);
+    let _receipt = submitter.submit(buffer.slice(..100)).unwrap();
+
+    // Ensure the batch maker receives a copy that does not pin the buffer.
+    let (received, _) = rx_batch_maker.recv().await.unwrap();
+    assert_eq!(received, buffer.slice(..100));
+    assert_ne!(received.as_ptr(), buffer.as_ptr());
+}
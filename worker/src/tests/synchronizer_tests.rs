@@ -1,6 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener};
+use network::MAX_FRAME_SIZE;
 use std::fs;
 use tokio::sync::mpsc::channel;
 
@@ -27,7 +28,12 @@ async fn synchronize() {
         /* gc_depth */ 50, // Not used in this test.
         /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
         /* sync_retry_nodes */ 3, // Not used in this test.
+        /* sync_limit */ 1_000,
         rx_message,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
     );
 
     // Spawn a listener to receive our batch requests.
@@ -45,3 +51,102 @@ async fn synchronize() {
     // Ensure the target receives the sync request.
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn synchronize_over_limit() {
+    let (tx_message, rx_message) = channel(1);
+
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(9_100);
+
+    // Create a new test store.
+    let path = ".db_test_synchronize_over_limit";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Synchronizer` instance willing to wait for a single batch.
+    Synchronizer::spawn(
+        name,
+        id,
+        committee.clone(),
+        store.clone(),
+        /* gc_depth */ 50, // Not used in this test.
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        /* sync_retry_nodes */ 3, // Not used in this test.
+        /* sync_limit */ 1,
+        rx_message,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Spawn a listener expecting a request for the first batch only.
+    let (target, _) = keys.pop().unwrap();
+    let address = committee.worker(&target, &id).unwrap().worker_to_worker;
+    let message = WorkerMessage::BatchRequest(vec![batch_digest()], name);
+    let serialized = bincode::serialize(&message).unwrap();
+    let handle = listener(address, Some(Bytes::from(serialized)));
+
+    // Ask to sync two batches.
+    let missing = vec![batch_digest(), Digest([1; 32])];
+    let message = PrimaryWorkerMessage::Synchronize(missing, target);
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the target only receives a request for the first batch.
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn synchronize_again() {
+    let (tx_message, rx_message) = channel(1);
+
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(9_200);
+
+    // Create a new test store.
+    let path = ".db_test_synchronize_again";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Synchronizer` instance.
+    Synchronizer::spawn(
+        name,
+        id,
+        committee.clone(),
+        store.clone(),
+        /* gc_depth */ 50, // Not used in this test.
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        /* sync_retry_nodes */ 3, // Not used in this test.
+        /* sync_limit */ 1_000,
+        rx_message,
+        QueueLimits::default(),
+        MAX_FRAME_SIZE,
+        WorkerMetrics::default(),
+        NetworkContext::default(),
+    );
+
+    // Ask to sync a batch with a first target (that never replies).
+    let missing = vec![batch_digest()];
+    let (first, _) = keys.pop().unwrap();
+    let message = PrimaryWorkerMessage::Synchronize(missing.clone(), first);
+    tx_message.send(message).await.unwrap();
+
+    // Spawn a listener for the second target.
+    let (second, _) = keys.pop().unwrap();
+    let address = committee.worker(&second, &id).unwrap().worker_to_worker;
+    let message = WorkerMessage::BatchRequest(missing.clone(), name);
+    let serialized = bincode::serialize(&message).unwrap();
+    let handle = listener(address, Some(Bytes::from(serialized)));
+
+    // Ask again to sync the same batch, this time with the second target.
+    let message = PrimaryWorkerMessage::Synchronize(missing, second);
+    tx_message.send(message).await.unwrap();
+
+    // Ensure the second target receives the sync request.
+    assert!(handle.await.is_ok());
+}
@@ -0,0 +1,96 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use std::convert::TryFrom as _;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsConnector;
+
+/// Returns the path of a fixture (the certificate of `localhost`, signed by a test CA).
+fn fixture(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "src/tests/tls", name]
+        .iter()
+        .collect()
+}
+
+fn parameters(client_ca: bool) -> TlsParameters {
+    TlsParameters {
+        certificate: fixture("cert.pem"),
+        private_key: fixture("key.pem"),
+        client_ca: client_ca.then(|| fixture("ca.pem")),
+    }
+}
+
+/// Makes a client trusting the test CA, presenting the certificate of the fixtures (if specified).
+fn connector(with_certificate: bool) -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(load_certificates(&fixture("ca.pem")).unwrap());
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match with_certificate {
+        true => builder
+            .with_client_auth_cert(
+                load_certificates(&fixture("cert.pem")).unwrap(),
+                PrivateKeyDer::from_pem_file(fixture("key.pem")).unwrap(),
+            )
+            .unwrap(),
+        false => builder.with_no_client_auth(),
+    };
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Connects a client to the acceptor and returns what the acceptor receives (if the handshake
+/// succeeds).
+async fn exchange(
+    acceptor: Arc<dyn Acceptor>,
+    connector: TlsConnector,
+    port: u16,
+) -> io::Result<Vec<u8>> {
+    let address: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    tokio::spawn(async move {
+        let socket = TcpStream::connect(address).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        if let Ok(mut stream) = connector.connect(name, socket).await {
+            let _ = stream.write_all(b"Hello, world!").await;
+            let _ = stream.shutdown().await;
+        }
+    });
+    let (socket, peer) = listener.accept().await.unwrap();
+    let mut connection = acceptor.accept(socket, peer).await?;
+    let mut received = Vec::new();
+    connection.read_to_end(&mut received).await?;
+    Ok(received)
+}
+
+#[tokio::test]
+async fn accept_tls_clients() {
+    let acceptor = RustlsProvider.acceptor(&parameters(false)).unwrap();
+    let received = exchange(acceptor, connector(false), 29_000).await.unwrap();
+    assert_eq!(received, b"Hello, world!");
+}
+
+#[tokio::test]
+async fn require_client_certificates() {
+    // Ensure we reject the clients presenting no certificate.
+    let acceptor = RustlsProvider.acceptor(&parameters(true)).unwrap();
+    assert!(exchange(acceptor.clone(), connector(false), 29_001)
+        .await
+        .is_err());
+
+    // Ensure we accept the clients presenting a certificate of the CA.
+    let received = exchange(acceptor, connector(true), 29_002).await.unwrap();
+    assert_eq!(received, b"Hello, world!");
+}
+
+#[test]
+fn reject_missing_certificate() {
+    let mut parameters = parameters(false);
+    parameters.certificate = fixture("missing.pem");
+    assert!(RustlsProvider.acceptor(&parameters).is_err());
+}
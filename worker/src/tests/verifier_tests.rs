@@ -0,0 +1,46 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+use crate::validator::ValidationError;
+use bytes::Bytes;
+use tokio::sync::mpsc::channel;
+
+/// Rejects the transactions starting with 1.
+struct RejectOnes;
+
+impl TransactionValidator for RejectOnes {
+    fn validate(&self, transaction: &[u8]) -> Result<(), ValidationError> {
+        match transaction.first() {
+            Some(1) => Err("Starts with 1".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn verify_transactions() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    TransactionVerifier::spawn(
+        Arc::new(RejectOnes),
+        /* threads */ 2,
+        rx_transaction,
+        tx_batch_maker,
+        WorkerMetrics::default(),
+    );
+
+    // Send an invalid transaction between two valid ones.
+    let (sender, receiver) = oneshot::channel();
+    let invalid = Bytes::from(vec![1; 100]);
+    let other = Bytes::from(vec![2; 100]);
+    tx_transaction.send((transaction(), None)).await.unwrap();
+    tx_transaction.send((invalid, Some(sender))).await.unwrap();
+    tx_transaction.send((other.clone(), None)).await.unwrap();
+
+    // Ensure the valid transactions reach the batch maker in order.
+    assert_eq!(rx_batch_maker.recv().await.unwrap().0, transaction());
+    assert_eq!(rx_batch_maker.recv().await.unwrap().0, other);
+
+    // Ensure the client of the invalid transaction is told why we rejected it.
+    assert!(matches!(receiver.await, Ok(ReceiptStatus::Rejected(_))));
+}
@@ -1,9 +1,17 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener, transaction};
-use network::SimpleSender;
+use crate::validator::{AcceptAll, ValidationError};
+use config::{IngressAddress, TransactionDedup};
+use futures::stream::StreamExt as _;
+use network::{
+    LinkConditions, MemoryNetwork, NetworkContext, ReliableSender, RpcClient, SimpleSender,
+};
 use primary::WorkerPrimaryMessage;
 use std::fs;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn handle_clients_transactions() {
@@ -12,6 +20,10 @@ async fn handle_clients_transactions() {
     let committee = committee_with_base_port(11_000);
     let parameters = Parameters {
         batch_size: 200, // Two transactions.
+        transaction_dedup: TransactionDedup {
+            capacity: 0, // The batch holds the same transaction twice.
+            ..TransactionDedup::default()
+        },
         ..Parameters::default()
     };
 
@@ -21,7 +33,18 @@ async fn handle_clients_transactions() {
     let store = Store::new(path).unwrap();
 
     // Spawn a `Worker` instance.
-    Worker::spawn(name, id, committee.clone(), parameters, store);
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        Arc::new(AcceptAll),
+        /* key_share */ None,
+        /* tls */ None,
+        NetworkContext::default(),
+        &Registry::new(),
+    );
 
     // Spawn a network listener to receive our batch's digest.
     let primary_address = committee.primary(&name).unwrap().worker_to_primary;
@@ -37,9 +60,305 @@ async fn handle_clients_transactions() {
     // Send enough transactions to create a batch.
     let mut network = SimpleSender::new();
     let address = committee.worker(&name, &id).unwrap().transactions;
-    network.send(address, Bytes::from(transaction())).await;
-    network.send(address, Bytes::from(transaction())).await;
+    network.send(address, transaction()).await;
+    network.send(address, transaction()).await;
 
     // Ensure the primary received the batch's digest (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+/// Returns a client sending payload confirmation requests, as our primary does.
+fn confirmation_client(network: ReliableSender) -> RpcClient<Vec<Digest>, Vec<Digest>> {
+    RpcClient::new(network).with_wrapper(|request| {
+        bincode::serialize(&PrimaryWorkerMessage::Confirm(request)).unwrap()
+    })
+}
+
+#[tokio::test]
+async fn confirm_batches() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(11_500);
+
+    // Create a new test store holding a single batch.
+    let path = ".db_test_confirm_batches";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store.write(batch_digest().to_vec(), Vec::default()).await;
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        Arc::new(AcceptAll),
+        /* key_share */ None,
+        /* tls */ None,
+        NetworkContext::default(),
+        &Registry::new(),
+    );
+
+    // Ask the worker to confirm it stores two batches.
+    let missing = Digest([1; 32]);
+    let address = committee.worker(&name, &id).unwrap().primary_to_worker;
+    let mut client = confirmation_client(ReliableSender::new());
+    let request = vec![batch_digest(), missing.clone()];
+    let received = client.call(address, request).await.unwrap();
+
+    // Ensure the worker replies with the batch it does not have.
+    assert_eq!(received, vec![missing]);
+}
+
+#[tokio::test]
+async fn confirm_batches_in_memory() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(24_000);
+
+    // Run the worker over a lossless in-memory network.
+    let addresses = committee.worker(&name, &id).unwrap();
+    let network = MemoryNetwork::new();
+    let context = NetworkContext::new().with_transport(network.clone());
+    network.set_conditions(LinkConditions {
+        latency: Duration::from_millis(10),
+        loss: 0.0,
+    });
+
+    // Create a new test store holding a single batch.
+    let path = ".db_test_confirm_batches_in_memory";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store.write(batch_digest().to_vec(), Vec::default()).await;
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        Arc::new(AcceptAll),
+        /* key_share */ None,
+        /* tls */ None,
+        context.clone(),
+        &Registry::new(),
+    );
+
+    // Ask the worker to confirm it stores two batches.
+    let missing = Digest([1; 32]);
+    let mut client = confirmation_client(ReliableSender::new().with_context(context));
+    let request = vec![batch_digest(), missing.clone()];
+    let received = client
+        .call(addresses.primary_to_worker, request)
+        .await
+        .unwrap();
+
+    // Ensure the worker replies through the in-memory network.
+    assert_eq!(received, vec![missing]);
+}
+
+/// Rejects the transactions starting with 1.
+struct RejectOnes;
+
+impl TransactionValidator for RejectOnes {
+    fn validate(&self, transaction: &[u8]) -> Result<(), ValidationError> {
+        match transaction.first() {
+            Some(1) => Err("Transaction starting with 1".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn store_invalid_batch() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(19_000);
+
+    // Create a new test store.
+    let path = ".db_test_store_invalid_batch";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    let (_, fetcher) = Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        Arc::new(RejectOnes),
+        /* key_share */ None,
+        /* tls */ None,
+        NetworkContext::default(),
+        &Registry::new(),
+    );
+
+    // Spawn a network listener to receive the digest of the batch.
+    let batch = vec![Bytes::from(vec![1; 100]), transaction()];
+    let serialized = bincode::serialize(&WorkerMessage::Batch(batch)).unwrap();
+    let digest = Digest(Sha512::digest(&serialized)[..32].try_into().unwrap());
+    let primary_address = committee.primary(&name).unwrap().worker_to_primary;
+    let expected =
+        bincode::serialize(&WorkerPrimaryMessage::OthersBatch(digest.clone(), id)).unwrap();
+    let handle = listener(primary_address, Some(Bytes::from(expected)));
+
+    // Send a batch holding an invalid transaction.
+    let address = committee.worker(&name, &id).unwrap().worker_to_worker;
+    let mut network = ReliableSender::new();
+    let handler = network.send(address, Bytes::from(serialized)).await;
+    handler.await.unwrap();
+
+    // Ensure the batch is stored anyway, and that its invalid transaction is filtered out when
+    // fetched for execution.
+    assert!(handle.await.is_ok());
+    let batch = fetcher.fetch(&digest, None).await.unwrap();
+    assert_eq!(batch, vec![transaction()]);
+}
+
+#[tokio::test]
+async fn submit_with_receipts() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(19_500);
+
+    // Create a new test store.
+    let path = ".db_test_submit_with_receipts";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        Arc::new(RejectOnes),
+        /* key_share */ None,
+        /* tls */ None,
+        NetworkContext::default(),
+        &Registry::new(),
+    );
+
+    // Submit a transaction twice, along with an invalid transaction.
+    let invalid = Bytes::from(vec![1; 100]);
+    let transactions = vec![transaction(), transaction(), invalid.clone()];
+    let serialized = bincode::serialize(&transactions).unwrap();
+    let address = committee.worker(&name, &id).unwrap().receipts.unwrap();
+    let mut network = ReliableSender::new();
+    let handler = network.send(address, Bytes::from(serialized)).await;
+
+    // Ensure we receive the receipts once the batch is sealed (by the timer).
+    let digest = |x: &[u8]| Digest(Sha512::digest(x)[..32].try_into().unwrap());
+    let batch = digest(&bincode::serialize(&WorkerMessage::Batch(vec![transaction()])).unwrap());
+    let reply = handler.await.unwrap();
+    let receipts: Vec<Receipt> = bincode::deserialize(&reply).unwrap();
+    assert_eq!(receipts.len(), 3);
+    assert_eq!(receipts[0].transaction, digest(&transaction()));
+    assert_eq!(receipts[0].status, ReceiptStatus::Sealed(batch));
+    assert_eq!(receipts[1].status, ReceiptStatus::Duplicate);
+    assert_eq!(receipts[2].transaction, digest(&invalid));
+    assert!(matches!(receipts[2].status, ReceiptStatus::Rejected(_)));
+}
+
+#[tokio::test]
+async fn additional_ingress() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let mut committee = committee_with_base_port(22_000);
+    let address: SocketAddr = "127.0.0.1:22700".parse().unwrap();
+    let ingress = IngressAddress {
+        address,
+        rate_limit: Some(1),
+        receipts: true,
+        tls: None,
+    };
+    committee
+        .authorities
+        .get_mut(&name)
+        .unwrap()
+        .workers
+        .get_mut(&id)
+        .unwrap()
+        .ingress
+        .push(ingress);
+
+    // Create a new test store.
+    let path = ".db_test_additional_ingress";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        Parameters::default(),
+        store,
+        Arc::new(AcceptAll),
+        /* key_share */ None,
+        /* tls */ None,
+        NetworkContext::default(),
+        &Registry::new(),
+    );
+
+    // Submit two transactions through the additional ingress address.
+    let other = Bytes::from(vec![1; 100]);
+    let transactions = vec![transaction(), other];
+    let serialized = bincode::serialize(&transactions).unwrap();
+    let mut network = ReliableSender::new();
+    let handler = network.send(address, Bytes::from(serialized)).await;
+
+    // Ensure the ingress address enforces its own rate limit.
+    let reply = handler.await.unwrap();
+    let receipts: Vec<Receipt> = bincode::deserialize(&reply).unwrap();
+    assert_eq!(receipts.len(), 2);
+    assert!(matches!(receipts[0].status, ReceiptStatus::Sealed(_)));
+    assert_eq!(receipts[1].status, ReceiptStatus::Overloaded);
+}
+
+#[tokio::test]
+async fn reply_when_overloaded() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(23_500);
+    let parameters = Parameters {
+        client_rate_limit: Some(1),
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_reply_when_overloaded";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    Worker::spawn(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        Arc::new(AcceptAll),
+        /* key_share */ None,
+        /* tls */ None,
+        NetworkContext::default(),
+        &Registry::new(),
+    );
+    tokio::task::yield_now().await;
+
+    // Send two transactions without receipts, exceeding our rate limit.
+    let address = committee.worker(&name, &id).unwrap().transactions;
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(transaction()).await.unwrap();
+    transport.send(transaction()).await.unwrap();
+
+    // Ensure the worker only replies to the transaction it sheds.
+    let reply = transport.next().await.unwrap().unwrap();
+    let status: ReceiptStatus = bincode::deserialize(&reply).unwrap();
+    assert_eq!(status, ReceiptStatus::Overloaded);
+}
@@ -0,0 +1,111 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use async_trait::async_trait;
+use config::TlsParameters;
+use network::{Acceptor, Connection};
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject as _;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_rustls::TlsAcceptor;
+
+#[cfg(test)]
+#[path = "tests/tls_tests.rs"]
+pub mod tls_tests;
+
+/// How long a client has to complete the TLS handshake (in ms), so that idle connections do not
+/// hold on to our tasks.
+const HANDSHAKE_TIMEOUT: u64 = 10_000;
+
+/// Terminates TLS on the ingress addresses configured to (see `IngressAddress::tls`). Supplied by
+/// the application embedding the worker, which picks the TLS library (see `RustlsProvider`).
+pub trait TlsProvider: Send + Sync + 'static {
+    /// Loads the certificate and key of an endpoint, returning the acceptor running the TLS
+    /// handshake of its connections (and checking the certificates of the clients, if required).
+    fn acceptor(&self, parameters: &TlsParameters) -> io::Result<Arc<dyn Acceptor>>;
+}
+
+/// Terminates TLS with rustls (TLS 1.2 and 1.3), loading the certificates and keys from PEM files.
+pub struct RustlsProvider;
+
+impl TlsProvider for RustlsProvider {
+    fn acceptor(&self, parameters: &TlsParameters) -> io::Result<Arc<dyn Acceptor>> {
+        let invalid = |e: rustls::Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let certificates = load_certificates(&parameters.certificate)?;
+        let key = PrivateKeyDer::from_pem_file(&parameters.private_key).map_err(pem_error)?;
+
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?;
+        let builder = match &parameters.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in load_certificates(path)? {
+                    roots.add(certificate).map_err(invalid)?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certificates, key)
+            .map_err(invalid)?;
+        Ok(Arc::new(RustlsAcceptor(TlsAcceptor::from(Arc::new(
+            config,
+        )))))
+    }
+}
+
+/// Loads the certificates of a PEM file.
+fn load_certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .map_err(pem_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(pem_error)?;
+    match certificates.is_empty() {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No certificate in {}", path.display()),
+        )),
+        false => Ok(certificates),
+    }
+}
+
+fn pem_error(e: rustls::pki_types::pem::Error) -> io::Error {
+    match e {
+        rustls::pki_types::pem::Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+/// Runs the TLS handshake of the connections of the clients.
+struct RustlsAcceptor(TlsAcceptor);
+
+#[async_trait]
+impl Acceptor for RustlsAcceptor {
+    async fn accept(
+        &self,
+        socket: TcpStream,
+        _peer: SocketAddr,
+    ) -> io::Result<Box<dyn Connection>> {
+        let handshake = self.0.accept(socket);
+        match timeout(Duration::from_millis(HANDSHAKE_TIMEOUT), handshake).await {
+            Ok(stream) => Ok(Box::new(stream?)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "TLS handshake timed out",
+            )),
+        }
+    }
+}
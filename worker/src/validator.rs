@@ -0,0 +1,56 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Batch;
+use std::error::Error;
+
+/// The reason a transaction is invalid.
+pub type ValidationError = Box<dyn Error + Send + Sync>;
+
+/// Checks the transactions before the worker includes them in a batch, so that malformed (or
+/// unpayable) transactions are rejected at the edge of the mempool rather than after being
+/// committed, and filters out the invalid transactions of the batches of other workers when they
+/// are fetched for execution. Supplied by the application embedding the worker.
+pub trait TransactionValidator: Send + Sync + 'static {
+    /// Checks a transaction submitted by a client.
+    fn validate(&self, transaction: &[u8]) -> Result<(), ValidationError>;
+
+    /// Checks a batch received from another worker. The batch is stored even if one of its
+    /// transactions is invalid (it may be certified).
+    fn validate_batch(&self, batch: &Batch) -> Result<(), ValidationError> {
+        batch.iter().try_for_each(|x| self.validate(x))
+    }
+
+    /// Returns the replacement key of a transaction (if any). A transaction replaces the
+    /// transaction of the same key waiting to be batched, if any (for instance, a transaction of
+    /// the same sender and nonce offering a higher fee).
+    fn replacement_key(&self, _transaction: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Returns the expiry a client attached to its transaction (if any), as a Unix timestamp in
+    /// ms. The worker drops the transaction if it is not batched by then.
+    fn expiry(&self, _transaction: &[u8]) -> Option<u64> {
+        None
+    }
+
+    /// Returns the sender and nonce of a transaction (if any). The worker then admits the
+    /// transactions of each sender in nonce order (without gaps) and limits the number of
+    /// transactions each sender has waiting to be batched.
+    fn sender_nonce(&self, _transaction: &[u8]) -> Option<(Vec<u8>, u64)> {
+        None
+    }
+
+    /// Returns the routing key of a transaction (if any), deciding which worker batches it (see
+    /// `route`). The transactions touching the same state should share their routing key.
+    fn routing_key(&self, _transaction: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Accepts every transaction.
+pub struct AcceptAll;
+
+impl TransactionValidator for AcceptAll {
+    fn validate(&self, _transaction: &[u8]) -> Result<(), ValidationError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::{ReceiptSender, Transaction};
+use crate::metrics::WorkerMetrics;
+use crate::validator::TransactionValidator;
+use crate::worker::ReceiptStatus;
+use log::debug;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+#[path = "tests/verifier_tests.rs"]
+pub mod verifier_tests;
+
+/// The maximum number of transactions verified together.
+const MAX_CHUNK_SIZE: usize = 1_000;
+
+/// Runs the transaction validator on a dedicated pool of threads, verifying the transactions we
+/// receive in parallel (checking the signatures of client transactions is typically expensive).
+/// The valid transactions are handed to the `BatchMaker` in the order we received them.
+pub struct TransactionVerifier {
+    /// Checks the transactions before we batch them.
+    validator: Arc<dyn TransactionValidator>,
+    /// The threads verifying the transactions.
+    pool: Arc<ThreadPool>,
+    /// Receives the transactions of the clients.
+    rx_transaction: Receiver<(Transaction, Option<ReceiptSender>)>,
+    /// Sends the valid transactions to the `BatchMaker`.
+    tx_batch_maker: Sender<(Transaction, Option<ReceiptSender>)>,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
+}
+
+impl TransactionVerifier {
+    pub fn spawn(
+        validator: Arc<dyn TransactionValidator>,
+        threads: usize,
+        rx_transaction: Receiver<(Transaction, Option<ReceiptSender>)>,
+        tx_batch_maker: Sender<(Transaction, Option<ReceiptSender>)>,
+        metrics: WorkerMetrics,
+    ) {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("tx-verifier-{}", i))
+            .build()
+            .expect("Failed to create the transaction verification threads");
+        tokio::spawn(async move {
+            Self {
+                validator,
+                pool: Arc::new(pool),
+                rx_transaction,
+                tx_batch_maker,
+                metrics,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        while let Some(transaction) = self.rx_transaction.recv().await {
+            // Verify all the transactions waiting in our inbox at once.
+            let mut chunk = vec![transaction];
+            while chunk.len() < MAX_CHUNK_SIZE {
+                match self.rx_transaction.try_recv() {
+                    Ok(transaction) => chunk.push(transaction),
+                    Err(_) => break,
+                }
+            }
+            let transactions: Vec<_> = chunk.iter().map(|(x, _)| x.clone()).collect();
+
+            let (sender, receiver) = oneshot::channel();
+            let validator = self.validator.clone();
+            self.pool.spawn(move || {
+                let results: Vec<_> = transactions
+                    .par_iter()
+                    .map(|x| validator.validate(x))
+                    .collect();
+                let _ = sender.send(results);
+            });
+            let results = receiver.await.expect("Failed to verify transactions");
+
+            for ((transaction, waiter), result) in chunk.into_iter().zip(results) {
+                match result {
+                    Ok(()) => self
+                        .tx_batch_maker
+                        .send((transaction, waiter))
+                        .await
+                        .expect("Failed to send transaction"),
+                    Err(e) => {
+                        debug!("Rejected transaction: {}", e);
+                        self.metrics.rejected_transactions.inc();
+                        if let Some(waiter) = waiter {
+                            let _ = waiter.send(ReceiptStatus::Rejected(e.to_string()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
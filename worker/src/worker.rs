@@ -1,22 +1,52 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::batch_maker::{Batch, BatchMaker, Transaction};
+use crate::anti_entropy::AntiEntropy;
+use crate::batch_maker::{serialized_batch_size, Batch, BatchMaker, Broadcast, Transaction};
+use crate::batch_sizer::BatchSizer;
+use crate::compression::decompress_batch;
+use crate::decryptor::Decryptor;
+use crate::encryption::EncryptionValidator;
+use crate::erasure::{shard_key, Shard, ShardCollector, ShardLayout};
+use crate::fetcher::BatchFetcher;
 use crate::helper::Helper;
+use crate::mempool::Mempool;
+use crate::metrics::WorkerMetrics;
 use crate::primary_connector::PrimaryConnector;
 use crate::processor::{Processor, SerializedBatchMessage};
+use crate::pruner::Pruner;
 use crate::quorum_waiter::QuorumWaiter;
+use crate::reassembler::BatchReassembler;
+use crate::routing::Router;
+use crate::submitter::{SubmitError, TransactionSubmitter};
 use crate::synchronizer::Synchronizer;
+use crate::tls::TlsProvider;
+use crate::validator::TransactionValidator;
+use crate::verifier::TransactionVerifier;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, Parameters, WorkerId};
-use crypto::{Digest, PublicKey};
+use config::{
+    BatchCompression, BatchDissemination, Committee, Parameters, SendQueue, TransportCompression,
+    WorkerId,
+};
+use crypto::{DecryptionShare, Digest, KeyShare, PublicKey};
+use ed25519_dalek::{Digest as _, Sha512};
 use futures::sink::SinkExt as _;
-use log::{error, info, warn};
-use network::{MessageHandler, Receiver, Writer};
-use primary::PrimaryWorkerMessage;
+use log::{debug, error, info, warn};
+use network::{
+    Compression, InboundLimiter, MessageHandler, NetworkContext, NetworkMetrics, Peer, QueueLimits,
+    QueuePolicy, Receiver, RetryPolicy, RpcHandler, RpcServer, Writer, FRAME_OVERHEAD,
+};
+use primary::{PrimaryWorkerMessage, WorkerPrimaryMessage};
+use prometheus::Registry;
 use serde::{Deserialize, Serialize};
+use std::convert::TryInto as _;
 use std::error::Error;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use store::Store;
 use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration};
 
 #[cfg(test)]
 #[path = "tests/worker_tests.rs"]
@@ -25,6 +55,9 @@ pub mod worker_tests;
 /// The default channel capacity for each channel of the worker.
 pub const CHANNEL_CAPACITY: usize = 1_000;
 
+/// The interval at which we report the size of the store (in ms).
+const STORE_SIZE_INTERVAL: u64 = 10_000;
+
 /// The primary round number.
 // TODO: Move to the primary.
 pub type Round = u64;
@@ -37,6 +70,64 @@ pub type SerializedBatchDigestMessage = Vec<u8>;
 pub enum WorkerMessage {
     Batch(Batch),
     BatchRequest(Vec<Digest>, /* origin */ PublicKey),
+    /// A chunk of a large batch served in reply to a batch request. The concatenation of the chunks
+    /// is the serialized `Batch` (or `CompressedBatch`) message of the specified digest.
+    BatchChunk(Digest, /* index */ u32, /* total */ u32, Vec<u8>),
+    /// A Reed-Solomon coded shard of the serialized `Batch` (or `CompressedBatch`) message of the
+    /// specified digest (see `ShardLayout`).
+    BatchShard(Digest, Shard),
+    /// The digests of the batches the origin sealed recently, so that the workers that missed
+    /// their broadcast request them.
+    BatchSummary(Vec<Digest>, /* origin */ PublicKey),
+    /// The digest of a batch the sender sealed, broadcast instead of the batch under pull
+    /// dissemination.
+    BatchAnnouncement(Digest),
+    /// A serialized batch, compressed with the specified algorithm.
+    CompressedBatch(BatchCompression, Vec<u8>),
+    /// The decryption shares of the sender for the encrypted transactions of a committed batch, by
+    /// digest of the (encrypted) transaction.
+    DecryptionShares(Vec<(Digest, DecryptionShare)>),
+}
+
+/// What became of a transaction submitted with a receipt. The clients submitting transactions
+/// without receipts only receive a serialized status when we shed the load (`Overloaded` or
+/// `RetryLater`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    /// The transaction is sealed into the batch of the specified digest.
+    Sealed(Digest),
+    /// The transaction was recently submitted: it is dropped (the original is batched).
+    Duplicate,
+    /// The transaction validator rejected the transaction, for the specified reason.
+    Rejected(String),
+    /// The client exceeded its rate limit, or the transaction was evicted from the full mempool:
+    /// the transaction is dropped (the client should back off).
+    Overloaded,
+    /// A later transaction of the same replacement key replaced the transaction before it was
+    /// batched.
+    Replaced,
+    /// The transaction expired before it was batched.
+    Expired,
+    /// The transaction (of the specified size) exceeds the maximum size: it is dropped.
+    Oversized(usize, usize),
+    /// The transaction is routed to the worker of the specified id: it is dropped (the client
+    /// should submit it to that worker).
+    Misrouted(WorkerId),
+    /// Too many of our batches wait for the acknowledgements of the other workers, or the worker
+    /// lost track of the transaction (e.g. while shutting down): the transaction may be dropped
+    /// (the client should retry later).
+    RetryLater,
+}
+
+/// The reply to a transaction submitted with a receipt. Clients submitting transactions to the
+/// receipts address send a serialized `Vec<Transaction>` and receive, once they are all sealed (or
+/// dropped), a serialized `Vec<Receipt>` (in the same order).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    /// The digest of the transaction.
+    pub transaction: Digest,
+    /// What became of the transaction.
+    pub status: ReceiptStatus,
 }
 
 pub struct Worker {
@@ -50,16 +141,36 @@ pub struct Worker {
     parameters: Parameters,
     /// The persistent storage.
     store: Store,
+    /// Checks the transactions before we batch or store them.
+    validator: Arc<dyn TransactionValidator>,
+    /// Terminates TLS on the ingress addresses configured to (if any).
+    tls: Option<Arc<dyn TlsProvider>>,
+    /// How we reach the other workers and our primary.
+    context: NetworkContext,
+    /// The metrics of the worker.
+    metrics: WorkerMetrics,
 }
 
 impl Worker {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: PublicKey,
         id: WorkerId,
         committee: Committee,
         parameters: Parameters,
         store: Store,
-    ) {
+        validator: Arc<dyn TransactionValidator>,
+        key_share: Option<KeyShare>,
+        tls: Option<Arc<dyn TlsProvider>>,
+        context: NetworkContext,
+        registry: &Registry,
+    ) -> (TransactionSubmitter, BatchFetcher) {
+        // Accept the transactions encrypted to the committee, if it has an encryption key.
+        let validator: Arc<dyn TransactionValidator> = match committee.encryption_key {
+            Some(_) => Arc::new(EncryptionValidator::new(validator)),
+            None => validator,
+        };
+
         // Define a worker instance.
         let worker = Self {
             name,
@@ -67,13 +178,55 @@ impl Worker {
             committee,
             parameters,
             store,
+            validator,
+            tls,
+            context,
+            metrics: WorkerMetrics::new(registry),
         };
 
         // Spawn all worker tasks.
         let (tx_primary, rx_primary) = channel(CHANNEL_CAPACITY);
-        worker.handle_primary_messages();
-        worker.handle_clients_transactions(tx_primary.clone());
-        worker.handle_workers_messages(tx_primary);
+        let (tx_sealed, rx_sealed) = channel(CHANNEL_CAPACITY);
+        let (tx_summary, rx_summary) = channel(CHANNEL_CAPACITY);
+        let (tx_decryptor, rx_decryptor) = channel(CHANNEL_CAPACITY);
+        let (tx_shares, rx_shares) = channel(CHANNEL_CAPACITY);
+        let encrypted = worker.committee.encryption_key.is_some();
+
+        // Lets the execution layer fetch the transactions of the batches output by the consensus.
+        let fetcher = BatchFetcher::new(
+            worker.name,
+            worker.id,
+            worker.committee.clone(),
+            worker.store.clone(),
+            worker.parameters.sync_retry_delay,
+            worker.parameters.sync_retry_nodes,
+            queue_limits(worker.parameters.send_queues.requests),
+            worker.parameters.frame_limits.max_outbound,
+            worker.metrics.network.clone(),
+            worker.context.clone(),
+        )
+        .with_validator(worker.validator.clone());
+
+        worker.handle_primary_messages(fetcher.clone(), encrypted.then_some(tx_decryptor));
+        let submitter = worker.handle_clients_transactions(tx_primary.clone(), tx_sealed);
+        worker.handle_workers_messages(tx_primary, tx_summary, encrypted.then_some(tx_shares));
+        Self::report_store_size(worker.store.clone(), worker.metrics.clone());
+
+        // The `AntiEntropy` advertises our recent batches to the other workers, and pulls the
+        // batches they advertise that we missed.
+        AntiEntropy::spawn(
+            worker.name,
+            worker.id,
+            worker.committee.clone(),
+            worker.store.clone(),
+            worker.parameters.anti_entropy,
+            rx_sealed,
+            rx_summary,
+            queue_limits(worker.parameters.send_queues.requests),
+            worker.parameters.frame_limits.max_outbound,
+            worker.metrics.clone(),
+            worker.context.clone(),
+        );
 
         // The `PrimaryConnector` allows the worker to send messages to its primary.
         PrimaryConnector::spawn(
@@ -83,6 +236,10 @@ impl Worker {
                 .expect("Our public key is not in the committee")
                 .worker_to_primary,
             rx_primary,
+            queue_limits(worker.parameters.send_queues.internal),
+            worker.parameters.frame_limits.max_outbound,
+            worker.metrics.network.clone(),
+            worker.context.clone(),
         );
 
         // NOTE: This log entry is used to compute performance.
@@ -96,23 +253,66 @@ impl Worker {
                 .transactions
                 .ip()
         );
+
+        // The `Decryptor` decrypts the encrypted transactions of the committed batches (if the
+        // committee encrypts transactions).
+        Decryptor::spawn(
+            worker.name,
+            worker.id,
+            worker.committee.clone(),
+            worker.store.clone(),
+            key_share,
+            fetcher.clone(),
+            /* rx_committed */ rx_decryptor,
+            rx_shares,
+            queue_limits(worker.parameters.send_queues.requests),
+            worker.parameters.frame_limits.max_outbound,
+            worker.metrics.network.clone(),
+            worker.context.clone(),
+        );
+        (submitter, fetcher)
     }
 
     /// Spawn all tasks responsible to handle messages from our primary.
-    fn handle_primary_messages(&self) {
+    fn handle_primary_messages(
+        &self,
+        fetcher: BatchFetcher,
+        tx_decryptor: Option<Sender<Vec<Digest>>>,
+    ) {
         let (tx_synchronizer, rx_synchronizer) = channel(CHANNEL_CAPACITY);
 
         // Receive incoming messages from our primary.
-        let mut address = self
+        let address = self
             .committee
-            .worker(&self.name, &self.id)
+            .listen_worker(&self.name, &self.id, &self.parameters)
             .expect("Our public key or worker id is not in the committee")
             .primary_to_worker;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+        // The `Pruner` deletes the batches committed long enough ago (if enabled, and unless we
+        // archive everything).
+        let retention = self.parameters.batch_retention;
+        let tx_pruner = retention
+            .filter(|_| !self.parameters.archival)
+            .map(|retention| {
+                let (tx_pruner, rx_pruner) = channel(CHANNEL_CAPACITY);
+                Pruner::spawn(self.store.clone(), retention, rx_pruner);
+                tx_pruner
+            });
+
+        Receiver::spawn_with_context(
             address,
             /* handler */
-            PrimaryReceiverHandler { tx_synchronizer },
+            PrimaryReceiverHandler {
+                tx_synchronizer,
+                tx_pruner,
+                tx_decryptor,
+                fetcher,
+                confirmations: RpcServer::new(PayloadConfirmer {
+                    store: self.store.clone(),
+                }),
+                max_frame_size: self.parameters.frame_limits.max_inbound,
+                network_metrics: self.metrics.network.clone(),
+            },
+            self.context.clone(),
         );
 
         // The `Synchronizer` is responsible to keep the worker in sync with the others. It handles the commands
@@ -125,7 +325,12 @@ impl Worker {
             self.parameters.gc_depth,
             self.parameters.sync_retry_delay,
             self.parameters.sync_retry_nodes,
+            self.parameters.sync_limits.worker_synchronizer,
             /* rx_message */ rx_synchronizer,
+            queue_limits(self.parameters.send_queues.requests),
+            self.parameters.frame_limits.max_outbound,
+            self.metrics.clone(),
+            self.context.clone(),
         );
 
         info!(
@@ -134,31 +339,183 @@ impl Worker {
         );
     }
 
-    /// Spawn all tasks responsible to handle clients transactions.
-    fn handle_clients_transactions(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
+    /// Periodically reports the size of the store.
+    fn report_store_size(mut store: Store, metrics: WorkerMetrics) {
+        tokio::spawn(async move {
+            loop {
+                match store.size().await {
+                    Ok(size) => metrics.store_size.set(size as i64),
+                    Err(e) => warn!("Failed to estimate the size of the store: {}", e),
+                }
+                sleep(Duration::from_millis(STORE_SIZE_INTERVAL)).await;
+            }
+        });
+    }
+
+    /// Returns the maximum size of our serialized batches (leaving room for the overhead of the
+    /// connections in our frames, and within the limit of the committee).
+    fn max_serialized_batch_size(&self) -> usize {
+        let max_frame_size = self.parameters.frame_limits.max_outbound;
+        self.parameters
+            .max_serialized_batch_size
+            .unwrap_or(max_frame_size)
+            .min(max_frame_size.saturating_sub(FRAME_OVERHEAD))
+            .min(self.committee.max_batch_size.unwrap_or(usize::MAX))
+    }
+
+    /// Spawn all tasks responsible to handle clients transactions. Returns a handle to submit
+    /// transactions through other means than our network ingress.
+    fn handle_clients_transactions(
+        &self,
+        tx_primary: Sender<SerializedBatchDigestMessage>,
+        tx_sealed: Sender<Digest>,
+    ) -> TransactionSubmitter {
         let (tx_batch_maker, rx_batch_maker) = channel(CHANNEL_CAPACITY);
         let (tx_quorum_waiter, rx_quorum_waiter) = channel(CHANNEL_CAPACITY);
+        let (tx_latency, rx_latency) = channel(CHANNEL_CAPACITY);
         let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
 
         // We first receive clients' transactions from the network.
-        let mut address = self
+        let address = self
             .committee
-            .worker(&self.name, &self.id)
+            .listen_worker(&self.name, &self.id, &self.parameters)
             .expect("Our public key or worker id is not in the committee")
             .transactions;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
-            address,
-            /* handler */ TxReceiverHandler { tx_batch_maker },
+
+        // Verify the transactions in parallel chunks if configured to, instead of one at a time
+        // as we receive them.
+        let (tx_submitter, validator) = match self.parameters.validation_threads {
+            Some(threads) => {
+                let (tx_verifier, rx_verifier) = channel(CHANNEL_CAPACITY);
+                TransactionVerifier::spawn(
+                    self.validator.clone(),
+                    threads,
+                    rx_verifier,
+                    tx_batch_maker,
+                    self.metrics.clone(),
+                );
+                (tx_verifier, None)
+            }
+            None => (tx_batch_maker, Some(self.validator.clone())),
+        };
+        // We reject the transactions that would not fit in a batch on their own.
+        let max_batch_size = self.max_serialized_batch_size();
+        let max_transaction_size = max_batch_size.saturating_sub(serialized_batch_size(1, 0));
+        let submitter = TransactionSubmitter::new(
+            tx_submitter,
+            validator,
+            Some(
+                self.parameters
+                    .max_transaction_size
+                    .map_or(max_transaction_size, |x| x.min(max_transaction_size)),
+            ),
+            self.parameters.client_rate_limit,
+            self.metrics.clone(),
         );
+        let submitter = match self.parameters.enforce_routing {
+            true => {
+                let workers = self
+                    .committee
+                    .our_workers(&self.name)
+                    .expect("Our public key is not in the committee")
+                    .len();
+                submitter.with_router(Router::new(self.id, workers, self.validator.clone()))
+            }
+            false => submitter,
+        };
+        let submitter = match self.parameters.max_batch_backlog {
+            Some(max) => submitter.with_max_batch_backlog(max),
+            None => submitter,
+        };
+        let handler = TxReceiverHandler {
+            submitter: submitter.clone(),
+            receipts: false,
+            client: None,
+            max_frame_size: self.parameters.frame_limits.max_inbound,
+        };
+        Receiver::spawn_with_context(address, handler.clone(), self.context.for_clients());
+
+        // Clients running on the same host may submit their transactions over a Unix domain socket.
+        let socket_path = self.parameters.transactions_socket(&self.name, self.id);
+        #[cfg(unix)]
+        if let Some(path) = &socket_path {
+            Receiver::spawn_unix(path.clone(), handler.clone());
+        }
+        #[cfg(not(unix))]
+        let socket_path = socket_path.and_then(|_| {
+            warn!("Unix domain sockets are not supported on this platform");
+            None::<std::path::PathBuf>
+        });
+
+        // Clients needing to know which batch holds their transactions submit them to the
+        // receipts address (if any).
+        let receipts_address = self
+            .committee
+            .listen_worker(&self.name, &self.id, &self.parameters)
+            .expect("Our public key or worker id is not in the committee")
+            .receipts
+            .inspect(|address| {
+                Receiver::spawn_with_context(
+                    *address,
+                    TxReceiverHandler {
+                        receipts: true,
+                        ..handler.clone()
+                    },
+                    self.context.for_clients(),
+                );
+            });
+
+        // Each additional ingress address enforces its own rate limits.
+        let ingress: Vec<_> = self
+            .committee
+            .listen_worker(&self.name, &self.id, &self.parameters)
+            .expect("Our public key or worker id is not in the committee")
+            .ingress
+            .into_iter()
+            .map(|ingress| {
+                let address = ingress.address;
+                let rate_limit = ingress.rate_limit.or(self.parameters.client_rate_limit);
+                let handler = TxReceiverHandler {
+                    submitter: submitter.with_rate_limit(rate_limit),
+                    receipts: ingress.receipts,
+                    client: None,
+                    max_frame_size: self.parameters.frame_limits.max_inbound,
+                };
+                match &ingress.tls {
+                    Some(parameters) => {
+                        let acceptor = self
+                            .tls
+                            .as_ref()
+                            .expect("No TLS provider to serve the TLS ingress addresses")
+                            .acceptor(parameters)
+                            .expect("Failed to load the TLS certificate or key");
+                        Receiver::spawn_with_acceptor(address, handler, acceptor);
+                    }
+                    None => {
+                        Receiver::spawn_with_context(address, handler, self.context.for_clients())
+                    }
+                }
+                address
+            })
+            .collect();
 
         // The transactions are sent to the `BatchMaker` that assembles them into batches. It then broadcasts
         // (in a reliable manner) the batches to all other workers that share the same `id` as us. Finally, it
         // gathers the 'cancel handlers' of the messages and send them to the `QuorumWaiter`.
+        let retry_policy = RetryPolicy {
+            initial_delay: self.parameters.delivery_retries.initial_delay,
+            max_delay: self.parameters.delivery_retries.max_delay,
+            jitter: self.parameters.delivery_retries.jitter,
+            max_attempts: self.parameters.delivery_retries.max_attempts,
+        };
         BatchMaker::spawn(
-            self.parameters.batch_size,
-            self.parameters.max_batch_delay,
+            BatchSizer::new(
+                self.parameters.batch_size,
+                self.parameters.max_batch_delay,
+                self.parameters.adaptive_batching,
+            ),
             /* rx_transaction */ rx_batch_maker,
+            rx_latency,
             /* tx_message */ tx_quorum_waiter,
             /* workers_addresses */
             self.committee
@@ -166,15 +523,41 @@ impl Worker {
                 .iter()
                 .map(|(name, addresses)| (*name, addresses.worker_to_worker))
                 .collect(),
+            Mempool::new(
+                self.parameters.priority_lanes.unwrap_or(1),
+                self.parameters.mempool,
+                self.validator.clone(),
+                self.metrics.clone(),
+            ),
+            self.parameters.transaction_dedup,
+            self.committee.batch_compression,
+            max_batch_size,
+            Broadcast::new(&self.committee),
+            retry_policy,
+            queue_limits(self.parameters.send_queues.batches),
+            self.parameters.frame_limits.max_outbound,
+            self.batch_transport_compression(),
+            self.metrics.clone(),
+            self.context.clone(),
         );
 
-        // The `QuorumWaiter` waits for 2f authorities to acknowledge reception of the batch. It then forwards
-        // the batch to the `Processor`.
+        // The `QuorumWaiter` waits for 2f authorities (by default) to acknowledge reception of the batch. It then
+        // forwards the batch to the `Processor`.
         QuorumWaiter::spawn(
             self.committee.clone(),
+            self.id,
             /* stake */ self.committee.stake(&self.name),
+            self.parameters.quorum_waiter,
             /* rx_message */ rx_quorum_waiter,
             /* tx_batch */ tx_processor,
+            tx_latency,
+            self.store.clone(),
+            retry_policy,
+            queue_limits(self.parameters.send_queues.batches),
+            self.parameters.frame_limits.max_outbound,
+            self.batch_transport_compression(),
+            self.metrics.clone(),
+            self.context.clone(),
         );
 
         // The `Processor` hashes and stores the batch. It then forwards the batch's digest to the `PrimaryConnector`
@@ -185,33 +568,90 @@ impl Worker {
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ true,
+            max_batch_size,
+            // Advertising our batches would have the other workers pull them all.
+            match self.committee.batch_dissemination {
+                BatchDissemination::Pull => None,
+                _ => Some(tx_sealed),
+            },
+            ShardLayout::new(&self.committee),
         );
 
         info!(
             "Worker {} listening to client transactions on {}",
             self.id, address
         );
+        if let Some(address) = receipts_address {
+            info!(
+                "Worker {} listening to client transactions with receipts on {}",
+                self.id, address
+            );
+        }
+        for address in ingress {
+            info!(
+                "Worker {} listening to client transactions on {}",
+                self.id, address
+            );
+        }
+        if let Some(path) = socket_path {
+            info!(
+                "Worker {} listening to client transactions on {}",
+                self.id,
+                path.display()
+            );
+        }
+        submitter
     }
 
     /// Spawn all tasks responsible to handle messages from other workers.
-    fn handle_workers_messages(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
+    fn handle_workers_messages(
+        &self,
+        tx_primary: Sender<SerializedBatchDigestMessage>,
+        tx_anti_entropy: Sender<(Vec<Digest>, PublicKey)>,
+        tx_decryptor: Option<Sender<Vec<(Digest, DecryptionShare)>>>,
+    ) {
         let (tx_helper, rx_helper) = channel(CHANNEL_CAPACITY);
         let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
 
         // Receive incoming messages from other workers.
-        let mut address = self
+        let address = self
             .committee
-            .worker(&self.name, &self.id)
+            .listen_worker(&self.name, &self.id, &self.parameters)
             .expect("Our public key or worker id is not in the committee")
             .worker_to_worker;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+        Receiver::spawn_with_context(
             address,
             /* handler */
             WorkerReceiverHandler {
                 tx_helper,
                 tx_processor,
+                tx_anti_entropy,
+                tx_decryptor,
+                validator: self.validator.clone(),
+                metrics: self.metrics.clone(),
+                reassembler: Arc::new(Mutex::new(BatchReassembler::default())),
+                shards: ShardLayout::new(&self.committee).map(|layout| {
+                    let own = layout
+                        .index(&self.name)
+                        .expect("Our public key is not in the committee");
+                    (own, Arc::new(Mutex::new(ShardCollector::new(layout))))
+                }),
+                id: self.id,
+                store: self.store.clone(),
+                tx_primary: tx_primary.clone(),
+                max_frame_size: self.parameters.frame_limits.max_inbound,
+                accepts_compression: self.parameters.transport_compression
+                    != TransportCompression::None,
+                committee: self
+                    .parameters
+                    .authenticate_messages
+                    .then(|| self.committee.clone()),
+                peer: None,
+                connection: String::new(),
+                peer_name: None,
+                inbound_limiter: InboundLimiter::new(self.parameters.peer_quotas.frames),
             },
+            self.context.clone(),
         );
 
         // The `Helper` is dedicated to reply to batch requests from other workers.
@@ -219,7 +659,13 @@ impl Worker {
             self.id,
             self.committee.clone(),
             self.store.clone(),
+            self.parameters.batch_helper,
             /* rx_request */ rx_helper,
+            queue_limits(self.parameters.send_queues.sync),
+            self.parameters.frame_limits.max_outbound,
+            self.batch_transport_compression(),
+            self.metrics.network.clone(),
+            self.context.clone(),
         );
 
         // This `Processor` hashes and stores the batches we receive from the other workers. It then forwards the
@@ -230,6 +676,9 @@ impl Worker {
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ false,
+            self.committee.max_batch_size.unwrap_or(usize::MAX),
+            /* tx_stored */ None,
+            ShardLayout::new(&self.committee),
         );
 
         info!(
@@ -237,27 +686,124 @@ impl Worker {
             self.id, address
         );
     }
+
+    /// Returns the compression we offer on the connections carrying our batches: none if the
+    /// committee compresses the batches already (compressing them twice only costs CPU).
+    fn batch_transport_compression(&self) -> Compression {
+        match self.committee.batch_compression {
+            BatchCompression::None => transport_compression(self.parameters.transport_compression),
+            _ => Compression::None,
+        }
+    }
 }
 
 /// Defines how the network receiver handles incoming transactions.
 #[derive(Clone)]
 struct TxReceiverHandler {
-    tx_batch_maker: Sender<Transaction>,
+    submitter: TransactionSubmitter,
+    /// Whether clients expect a receipt for their transactions.
+    receipts: bool,
+    /// The address of the client of the connection (`None` over the Unix domain socket).
+    client: Option<IpAddr>,
+    /// The maximum size of the frames we accept.
+    max_frame_size: usize,
+}
+
+impl TxReceiverHandler {
+    /// Replies to a request holding several transactions with their receipts, once they are all
+    /// sealed into batches (or dropped).
+    async fn dispatch_with_receipts(
+        &self,
+        writer: &mut Writer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        let transactions: Vec<Transaction> = bincode::deserialize(&message)?;
+
+        let mut pending = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            let digest = Digest(Sha512::digest(&transaction)[..32].try_into().unwrap());
+            let (sender, receiver) = oneshot::channel();
+            let admitted = self.submitter.admit(self.client);
+            match admitted.and_then(|()| self.submitter.check(&transaction)) {
+                Ok(()) => self.submitter.send(transaction, Some(sender)).await,
+                Err(SubmitError::RateLimited(_)) => {
+                    let _ = sender.send(ReceiptStatus::Overloaded);
+                }
+                Err(SubmitError::Oversized(size, max)) => {
+                    let _ = sender.send(ReceiptStatus::Oversized(size, max));
+                }
+                Err(SubmitError::Misrouted(id)) => {
+                    let _ = sender.send(ReceiptStatus::Misrouted(id));
+                }
+                Err(SubmitError::Backlogged(_)) => {
+                    let _ = sender.send(ReceiptStatus::RetryLater);
+                }
+                Err(e) => {
+                    let _ = sender.send(ReceiptStatus::Rejected(e.to_string()));
+                }
+            }
+            pending.push((digest, receiver));
+        }
+
+        let mut receipts = Vec::with_capacity(pending.len());
+        for (transaction, receiver) in pending {
+            let status = receiver.await.unwrap_or(ReceiptStatus::RetryLater);
+            receipts.push(Receipt {
+                transaction,
+                status,
+            });
+        }
+        let bytes = bincode::serialize(&receipts).expect("Failed to serialize receipts");
+        writer.send(Bytes::from(bytes)).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
-        // Send the transaction to the batch maker.
-        self.tx_batch_maker
-            .send(message.to_vec())
-            .await
-            .expect("Failed to send transaction");
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        if self.receipts {
+            return self.dispatch_with_receipts(writer, message).await;
+        }
+
+        // Drop the transactions of the clients exceeding their rate limit as well as the invalid
+        // (or oversized) transactions. These clients do not expect receipts: we only reply (with a
+        // serialized `ReceiptStatus`) when we shed the load, so that they back off.
+        let admitted = self.submitter.admit(self.client);
+        if let Err(e) = admitted.and_then(|()| self.submitter.check(&message)) {
+            debug!("Rejected transaction: {}", e);
+            let status = match e {
+                SubmitError::RateLimited(_) => ReceiptStatus::Overloaded,
+                SubmitError::Backlogged(_) => ReceiptStatus::RetryLater,
+                _ => return Ok(()),
+            };
+            let bytes = bincode::serialize(&status).expect("Failed to serialize receipt status");
+            writer.send(Bytes::from(bytes)).await?;
+            return Ok(());
+        }
+
+        // Send the transaction to the batch maker. The frame is a slice of the receive buffer of
+        // the connection: we copy it so that the mempool does not pin the whole buffer (and its
+        // byte accounting holds).
+        self.submitter
+            .send(Bytes::copy_from_slice(&message), None)
+            .await;
 
         // Give the change to schedule other tasks.
         tokio::task::yield_now().await;
         Ok(())
     }
+
+    fn for_peer(&self, peer: &Peer) -> Self {
+        Self {
+            client: peer.address.parse::<SocketAddr>().ok().map(|x| x.ip()),
+            ..self.clone()
+        }
+    }
+
+    fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
 }
 
 /// Defines how the network receiver handles incoming workers messages.
@@ -265,6 +811,119 @@ impl MessageHandler for TxReceiverHandler {
 struct WorkerReceiverHandler {
     tx_helper: Sender<(Vec<Digest>, PublicKey)>,
     tx_processor: Sender<SerializedBatchMessage>,
+    tx_anti_entropy: Sender<(Vec<Digest>, PublicKey)>,
+    /// Receives the decryption shares of the other workers (if the committee encrypts transactions).
+    tx_decryptor: Option<Sender<Vec<(Digest, DecryptionShare)>>>,
+    validator: Arc<dyn TransactionValidator>,
+    metrics: WorkerMetrics,
+    reassembler: Arc<Mutex<BatchReassembler>>,
+    /// The index of our shard and the collector of the shards we requested (if the committee
+    /// disseminates coded shards).
+    shards: Option<(usize, Arc<Mutex<ShardCollector>>)>,
+    id: WorkerId,
+    store: Store,
+    tx_primary: Sender<SerializedBatchDigestMessage>,
+    max_frame_size: usize,
+    /// Whether we accept to decompress the connections of the other workers.
+    accepts_compression: bool,
+    /// The committee information, to check the origin claimed by the messages of the other workers
+    /// (if we authenticate messages).
+    committee: Option<Committee>,
+    /// The address of the peer of the connection (`None` if it is not an IP address).
+    peer: Option<IpAddr>,
+    /// The address of the peer of the connection, as described by the receiver (identifying the
+    /// connection streaming us a batch).
+    connection: String,
+    /// The authority of the peer of the connection, if the connection authenticated it (see
+    /// `TransportSecurity`).
+    peer_name: Option<PublicKey>,
+    /// Limits the rate at which each of the other workers sends us frames.
+    inbound_limiter: InboundLimiter,
+}
+
+impl WorkerReceiverHandler {
+    /// Whether the peer of the connection may send messages on behalf of the specified authority:
+    /// the connection must have authenticated the authority (if we authenticate messages).
+    fn authenticate(&self, origin: &PublicKey) -> bool {
+        match &self.committee {
+            Some(committee) => {
+                self.peer_name.as_ref() == Some(origin)
+                    && committee.worker(origin, &self.id).is_ok()
+            }
+            None => true,
+        }
+    }
+
+    /// Processes a shard of a batch: we store the shard other workers send us to hold, and we
+    /// gather the shards they send us in reply to our batch requests to reconstruct the batch. We
+    /// only store (and acknowledge) the shards proven against the digest of their batch.
+    async fn process_shard(&self, serialized: Bytes, digest: Digest, shard: Shard) {
+        let (own, collector) = match &self.shards {
+            Some(x) => x,
+            None => {
+                warn!("Unexpected batch shard");
+                return;
+            }
+        };
+        if shard.index as usize == *own {
+            if !shard.verify(&digest) {
+                warn!("Invalid shard {} of batch {}", shard.index, digest);
+                return;
+            }
+            let mut store = self.store.clone();
+            store.write(shard_key(&digest), serialized.to_vec()).await;
+            let message = bincode::serialize(&WorkerPrimaryMessage::OthersBatch(digest, self.id))
+                .expect("Failed to serialize our own worker-primary message");
+            self.tx_primary
+                .send(message)
+                .await
+                .expect("Failed to send digest");
+            return;
+        }
+        let reconstructed = collector.lock().unwrap().add(digest, shard);
+        if let Some(bytes) = reconstructed {
+            match bincode::deserialize(&bytes) {
+                Ok(message) => self.process_message(message, Bytes::from(bytes)).await,
+                Err(e) => warn!("Serialization error: {}", e),
+            }
+        }
+    }
+
+    /// Processes a (whole) batch of another worker.
+    async fn process_message(&self, message: WorkerMessage, serialized: Bytes) {
+        match message {
+            WorkerMessage::Batch(batch) => self.process_batch(Ok(batch), serialized).await,
+            // We accept the batches of any compression algorithm (the one of the committee may have
+            // changed), as long as they decompress into a valid batch.
+            WorkerMessage::CompressedBatch(compression, bytes) => {
+                let batch = decompress_batch(compression, &bytes);
+                self.process_batch(batch, serialized).await
+            }
+            _ => warn!("Unexpected worker message"),
+        }
+    }
+
+    /// Checks a batch of another worker and sends it (serialized) to the processor. We store the
+    /// batches holding invalid transactions anyway: they may be certified, and the validator may
+    /// be stateful (and thus disagree with the other workers). The `BatchFetcher` filters out their
+    /// invalid transactions at execution time.
+    async fn process_batch(&self, batch: io::Result<Batch>, serialized: Bytes) {
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(e) => {
+                warn!("Invalid batch: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.validator.validate_batch(&batch) {
+            warn!("Batch holding invalid transactions: {}", e);
+            self.metrics.rejected_batches.inc();
+        }
+        self.tx_processor
+            .send(serialized)
+            .await
+            .expect("Failed to send batch");
+    }
 }
 
 #[async_trait]
@@ -275,38 +934,126 @@ impl MessageHandler for WorkerReceiverHandler {
 
         // Deserialize and parse the message.
         match bincode::deserialize(&serialized) {
-            Ok(WorkerMessage::Batch(..)) => self
-                .tx_processor
-                .send(serialized.to_vec())
-                .await
-                .expect("Failed to send batch"),
+            Ok(WorkerMessage::BatchRequest(_, origin) | WorkerMessage::BatchSummary(_, origin))
+                if !self.authenticate(&origin) =>
+            {
+                warn!(
+                    "Dropping message from {:?} claiming to come from {}",
+                    self.peer, origin
+                )
+            }
             Ok(WorkerMessage::BatchRequest(missing, requestor)) => self
                 .tx_helper
                 .send((missing, requestor))
                 .await
                 .expect("Failed to send batch request"),
+            Ok(WorkerMessage::BatchSummary(digests, origin)) => self
+                .tx_anti_entropy
+                .send((digests, origin))
+                .await
+                .expect("Failed to send batch summary"),
+            // The other workers pull the batches they announce when our primary needs them.
+            Ok(WorkerMessage::BatchAnnouncement(digest)) => {
+                debug!("Received announcement of batch {}", digest)
+            }
+            Ok(WorkerMessage::BatchChunk(digest, index, total, chunk)) => {
+                let reassembled = self.reassembler.lock().unwrap().add(
+                    &self.connection,
+                    digest,
+                    index,
+                    total,
+                    chunk,
+                );
+                if let Some(bytes) = reassembled {
+                    match bincode::deserialize(&bytes) {
+                        Ok(message) => self.process_message(message, Bytes::from(bytes)).await,
+                        Err(e) => warn!("Serialization error: {}", e),
+                    }
+                }
+            }
+            Ok(WorkerMessage::BatchShard(digest, shard)) => {
+                self.process_shard(serialized, digest, shard).await
+            }
+            Ok(WorkerMessage::DecryptionShares(shares)) => match &self.tx_decryptor {
+                Some(tx_decryptor) => tx_decryptor
+                    .send(shares)
+                    .await
+                    .expect("Failed to send decryption shares"),
+                None => warn!("Unexpected decryption shares"),
+            },
+            Ok(message) => self.process_message(message, serialized).await,
             Err(e) => warn!("Serialization error: {}", e),
         }
         Ok(())
     }
+
+    fn for_peer(&self, peer: &Peer) -> Self {
+        Self {
+            peer: peer.address.parse::<SocketAddr>().ok().map(|x| x.ip()),
+            connection: peer.address.clone(),
+            peer_name: peer.name,
+            ..self.clone()
+        }
+    }
+
+    fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    fn accepts_compression(&self) -> bool {
+        self.accepts_compression
+    }
+
+    fn metrics(&self) -> Option<NetworkMetrics> {
+        Some(self.metrics.network.clone())
+    }
+
+    fn inbound_limiter(&self) -> Option<InboundLimiter> {
+        Some(self.inbound_limiter.clone())
+    }
 }
 
 /// Defines how the network receiver handles incoming primary messages.
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_synchronizer: Sender<PrimaryWorkerMessage>,
+    tx_pruner: Option<Sender<(Vec<Digest>, Round)>>,
+    /// Receives the digests of the committed batches (if the committee encrypts transactions).
+    tx_decryptor: Option<Sender<Vec<Digest>>>,
+    /// Fetches the committed batches for the execution layer.
+    fetcher: BatchFetcher,
+    /// Serves the payload confirmation requests of our primary.
+    confirmations: RpcServer<PayloadConfirmer>,
+    max_frame_size: usize,
+    network_metrics: NetworkMetrics,
 }
 
 #[async_trait]
 impl MessageHandler for PrimaryReceiverHandler {
-    async fn dispatch(
-        &self,
-        _writer: &mut Writer,
-        serialized: Bytes,
-    ) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
         // Deserialize the message and send it to the synchronizer.
         match bincode::deserialize(&serialized) {
             Err(e) => error!("Failed to deserialize primary message: {}", e),
+            Ok(PrimaryWorkerMessage::Confirm(request)) => {
+                self.confirmations
+                    .dispatch(writer, Bytes::from(request))
+                    .await?
+            }
+            Ok(PrimaryWorkerMessage::Committed(digests, round)) => {
+                self.fetcher.record_committed(&digests);
+                if let Some(tx_decryptor) = &self.tx_decryptor {
+                    tx_decryptor
+                        .send(digests.clone())
+                        .await
+                        .expect("Failed to send committed batches to the decryptor");
+                }
+                if let Some(tx_pruner) = &self.tx_pruner {
+                    tx_pruner
+                        .send((digests, round))
+                        .await
+                        .expect("Failed to send committed batches to the pruner");
+                }
+            }
             Ok(message) => self
                 .tx_synchronizer
                 .send(message)
@@ -315,4 +1062,60 @@ impl MessageHandler for PrimaryReceiverHandler {
         }
         Ok(())
     }
+
+    fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    fn metrics(&self) -> Option<NetworkMetrics> {
+        Some(self.network_metrics.clone())
+    }
+}
+
+/// Tells our primary which of the batches it asks about we do not store.
+#[derive(Clone)]
+struct PayloadConfirmer {
+    store: Store,
+}
+
+#[async_trait]
+impl RpcHandler for PayloadConfirmer {
+    type Request = Vec<Digest>;
+    type Response = Vec<Digest>;
+
+    async fn handle(&self, digests: Vec<Digest>) -> Result<Vec<Digest>, Box<dyn Error>> {
+        let mut store = self.store.clone();
+        let mut missing = Vec::new();
+        for digest in digests {
+            // Holding our shard of the batch is enough.
+            if store.read(digest.to_vec()).await?.is_none()
+                && store.read(shard_key(&digest)).await?.is_none()
+            {
+                missing.push(digest);
+            }
+        }
+        Ok(missing)
+    }
+}
+
+/// Returns the compression the workers offer to (and accept from) each other.
+fn transport_compression(compression: TransportCompression) -> Compression {
+    match compression {
+        TransportCompression::None => Compression::None,
+        TransportCompression::Snappy => Compression::Snappy,
+        TransportCompression::Zstd => Compression::Zstd,
+    }
+}
+
+/// Returns the limits of the send queues of a class of messages.
+fn queue_limits(queue: SendQueue) -> QueueLimits {
+    let policy = match queue.policy {
+        config::QueuePolicy::DropOldest => QueuePolicy::DropOldest,
+        config::QueuePolicy::DropNewest => QueuePolicy::DropNewest,
+        config::QueuePolicy::Block => QueuePolicy::Block,
+    };
+    QueueLimits {
+        capacity: queue.capacity,
+        policy,
+    }
 }